@@ -36,7 +36,7 @@ if ($choice -eq "1") {
 
 # 3. Run the Compiler
 Write-Host "Compiling $SourcePath..." -ForegroundColor Yellow
-& "target\release\brain.exe" $SourcePath
+& "target\release\brain.exe" $SourcePath --keep-ir
 
 if ($LASTEXITCODE -ne 0) {
     Write-Host "Brain compiler failed." -ForegroundColor Red
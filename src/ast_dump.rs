@@ -0,0 +1,535 @@
+use crate::diagnostic::json_string;
+use crate::parser::{AstNode, Attribute, BinOp, MatchArm, Pattern, Span, UnOp};
+
+/// Output style for `--dump-ast`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Pretty,
+    Json,
+}
+
+pub fn dump(ast: &AstNode, format: DumpFormat) -> String {
+    let tree = Tree::from_node(ast);
+    match format {
+        DumpFormat::Pretty => {
+            let mut out = String::new();
+            tree.write_pretty(&mut out, 0, None);
+            out
+        }
+        DumpFormat::Json => {
+            let mut out = tree.to_json();
+            out.push('\n');
+            out
+        }
+    }
+}
+
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Null,
+}
+
+enum Slot {
+    One(Box<Tree>),
+    Many(Vec<Tree>),
+}
+
+/// Format-neutral view of a node: a kind, scalar attributes, and named
+/// child slots. Both renderers walk this instead of matching `AstNode`.
+struct Tree {
+    kind: &'static str,
+    attrs: Vec<(&'static str, Value)>,
+    children: Vec<(&'static str, Slot)>,
+}
+
+impl Tree {
+    fn new(kind: &'static str) -> Self {
+        Tree {
+            kind,
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn attr(mut self, key: &'static str, value: Value) -> Self {
+        self.attrs.push((key, value));
+        self
+    }
+
+    fn string(self, key: &'static str, value: &str) -> Self {
+        self.attr(key, Value::Str(value.to_string()))
+    }
+
+    fn opt_string(self, key: &'static str, value: &Option<String>) -> Self {
+        match value {
+            Some(v) => self.string(key, v),
+            None => self.attr(key, Value::Null),
+        }
+    }
+
+    fn flag(self, key: &'static str, value: bool) -> Self {
+        self.attr(key, Value::Bool(value))
+    }
+
+    /// `#[...]` names, left out when there are none.
+    fn attributes(self, attributes: &[Attribute]) -> Self {
+        if attributes.is_empty() {
+            return self;
+        }
+        let written: Vec<String> = attributes
+            .iter()
+            .map(|a| {
+                if a.args.is_empty() {
+                    a.name.clone()
+                } else {
+                    format!("{}({})", a.name, a.args.join(", "))
+                }
+            })
+            .collect();
+        self.string("attributes", &written.join(", "))
+    }
+
+    fn span(self, span: Span) -> Self {
+        self.attr("line", Value::Int(span.line as i64))
+            .attr("column", Value::Int(span.column as i64))
+    }
+
+    fn child(mut self, key: &'static str, node: &AstNode) -> Self {
+        self.children
+            .push((key, Slot::One(Box::new(Tree::from_node(node)))));
+        self
+    }
+
+    fn opt_child(self, key: &'static str, node: &Option<Box<AstNode>>) -> Self {
+        match node {
+            Some(n) => self.child(key, n),
+            None => self,
+        }
+    }
+
+    fn list(mut self, key: &'static str, items: Vec<Tree>) -> Self {
+        self.children.push((key, Slot::Many(items)));
+        self
+    }
+
+    fn nodes(self, key: &'static str, nodes: &[AstNode]) -> Self {
+        self.list(key, nodes.iter().map(Tree::from_node).collect())
+    }
+
+    /// `node`'s tree, with where it starts in the source.
+    fn from_node(node: &AstNode) -> Tree {
+        Tree::node_tree(node).span(node.span())
+    }
+
+    fn node_tree(node: &AstNode) -> Tree {
+        match node {
+            AstNode::Program(nodes, _) => Tree::new("Program").nodes("items", nodes),
+            AstNode::Import { names, path, .. } => Tree::new("Import")
+                .string("path", path)
+                .string("names", &names.join(", ")),
+            AstNode::LetBinding {
+                mutable,
+                name,
+                type_annotation,
+                value,
+                is_exported,
+                attributes,
+                ..
+            } => Tree::new("Let")
+                .string("name", name)
+                .flag("mutable", *mutable)
+                .opt_string("type", type_annotation)
+                .flag("exported", *is_exported)
+                .attributes(attributes)
+                .child("value", value),
+            AstNode::Assignment { name, value, .. } => Tree::new("Assign")
+                .string("name", name)
+                .child("value", value),
+            AstNode::FunctionDef {
+                name,
+                params,
+                return_type,
+                body,
+                is_exported,
+                is_unsafe,
+                attributes,
+                ..
+            } => Tree::new("Function")
+                .string("name", name)
+                .opt_string("return_type", return_type)
+                .flag("exported", *is_exported)
+                .flag("unsafe", *is_unsafe)
+                .attributes(attributes)
+                .list(
+                    "params",
+                    params
+                        .iter()
+                        .map(|p| {
+                            Tree::new("Param")
+                                .string("name", &p.name)
+                                .string("type", &p.param_type)
+                                .flag("reference", p.is_reference)
+                                .flag("mutable", p.is_mutable)
+                        })
+                        .collect(),
+                )
+                .child("body", body),
+            AstNode::ExternFn {
+                name,
+                params,
+                variadic,
+                return_type,
+                is_exported,
+                ..
+            } => Tree::new("Extern")
+                .string("name", name)
+                .opt_string("return_type", return_type)
+                .flag("exported", *is_exported)
+                .flag("variadic", *variadic)
+                .list(
+                    "params",
+                    params
+                        .iter()
+                        .map(|p| {
+                            Tree::new("Param")
+                                .string("name", &p.name)
+                                .string("type", &p.param_type)
+                        })
+                        .collect(),
+                ),
+            AstNode::StructDef {
+                name,
+                fields,
+                is_exported,
+                attributes,
+                ..
+            } => Tree::new("Struct")
+                .string("name", name)
+                .flag("exported", *is_exported)
+                .attributes(attributes)
+                .list(
+                    "fields",
+                    fields
+                        .iter()
+                        .map(|f| {
+                            Tree::new("Field")
+                                .string("name", &f.name)
+                                .string("type", &f.field_type)
+                                .opt_child("default", &f.default)
+                        })
+                        .collect(),
+                ),
+            AstNode::StructInit {
+                name, fields, base, ..
+            } => Tree::new("StructInit")
+                .string("name", name)
+                .list(
+                    "fields",
+                    fields
+                        .iter()
+                        .map(|(field, value)| {
+                            Tree::new("FieldInit")
+                                .string("name", field)
+                                .child("value", value)
+                        })
+                        .collect(),
+                )
+                .opt_child("base", base),
+            AstNode::EnumDef {
+                name,
+                variants,
+                is_exported,
+                attributes,
+                ..
+            } => Tree::new("Enum")
+                .string("name", name)
+                .flag("exported", *is_exported)
+                .attributes(attributes)
+                .list(
+                    "variants",
+                    variants
+                        .iter()
+                        .map(|v| {
+                            Tree::new("Variant").string("name", &v.name).opt_string(
+                                "payload",
+                                &(!v.fields.is_empty()).then(|| v.fields.join(", ")),
+                            )
+                        })
+                        .collect(),
+                ),
+            AstNode::EnumValue {
+                enum_name,
+                variant,
+                values,
+                ..
+            } => Tree::new("EnumValue")
+                .string("enum", enum_name)
+                .string("variant", variant)
+                .nodes("values", values),
+            AstNode::ArrayLit(elems, _) => Tree::new("ArrayLit").nodes("elements", elems),
+            AstNode::ArrayType {
+                element_type, size, ..
+            } => Tree::new("ArrayType")
+                .string("element_type", element_type)
+                .attr("size", Value::Int(*size as i64)),
+            AstNode::Index { array, index, .. } => Tree::new("Index")
+                .child("array", array)
+                .child("index", index),
+            AstNode::Slice {
+                value, start, end, ..
+            } => Tree::new("Slice")
+                .child("value", value)
+                .opt_child("start", start)
+                .opt_child("end", end),
+            AstNode::ArrayAssignment {
+                array,
+                index,
+                value,
+                ..
+            } => Tree::new("IndexAssign")
+                .string("array", array)
+                .child("index", index)
+                .child("value", value),
+            AstNode::MemberAssignment {
+                object,
+                field,
+                value,
+                ..
+            } => Tree::new("MemberAssign")
+                .string("field", field)
+                .child("object", object)
+                .child("value", value),
+            AstNode::BinaryOp {
+                op, left, right, ..
+            } => Tree::new("Binary")
+                .string("op", binop_str(op))
+                .child("left", left)
+                .child("right", right),
+            AstNode::UnaryOp { op, operand, .. } => Tree::new("Unary")
+                .string(
+                    "op",
+                    match op {
+                        UnOp::Not => "!",
+                        UnOp::Negate => "-",
+                    },
+                )
+                .child("operand", operand),
+            AstNode::Cast { value, target, .. } => Tree::new("Cast")
+                .string("type", target)
+                .child("value", value),
+            AstNode::Number(n, _) => Tree::new("Number").attr("value", Value::Int(*n)),
+            AstNode::Boolean(b, _) => Tree::new("Bool").flag("value", *b),
+            AstNode::Null(_) => Tree::new("Null"),
+            AstNode::Character(c, _) => Tree::new("Char").string("value", &c.to_string()),
+            AstNode::StringLit(s, _) => Tree::new("String").string("value", s),
+            AstNode::BytesLit(bytes, _) => {
+                Tree::new("Bytes").attr("len", Value::Int(bytes.len() as i64))
+            }
+            AstNode::SizeOf(ty, _) => Tree::new("SizeOf").string("type", ty),
+            AstNode::Identifier { name, .. } => Tree::new("Identifier").string("name", name),
+            AstNode::Reference(inner, _) => Tree::new("Reference").child("value", inner),
+            AstNode::Call { name, args, .. } => {
+                Tree::new("Call").string("name", name).nodes("args", args)
+            }
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => Tree::new("MethodCall")
+                .string("method", method)
+                .child("object", object)
+                .nodes("args", args),
+            AstNode::MemberAccess { object, field, .. } => Tree::new("Member")
+                .string("field", field)
+                .child("object", object),
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => Tree::new("If")
+                .child("condition", condition)
+                .child("then", then_block)
+                .opt_child("else", else_block),
+            AstNode::While {
+                condition, body, ..
+            } => Tree::new("While")
+                .child("condition", condition)
+                .child("body", body),
+            AstNode::For {
+                variable,
+                index,
+                iterator,
+                body,
+                ..
+            } => Tree::new("For")
+                .string("variable", variable)
+                .opt_string("index", index)
+                .child("iterator", iterator)
+                .child("body", body),
+            AstNode::Match { value, arms, .. } => Tree::new("Match")
+                .child("value", value)
+                .list("arms", arms.iter().map(arm_tree).collect()),
+            AstNode::Return(value, _) => Tree::new("Return").opt_child("value", value),
+            AstNode::Break(_) => Tree::new("Break"),
+            AstNode::Continue(_) => Tree::new("Continue"),
+            AstNode::Block(nodes, _) => Tree::new("Block").nodes("statements", nodes),
+            AstNode::ExpressionStatement(expr, _) => Tree::new("ExprStmt").child("expr", expr),
+            AstNode::When {
+                conditions,
+                body,
+                else_body,
+                ..
+            } => {
+                let conditions: Vec<String> = conditions
+                    .iter()
+                    .map(|(key, value, _)| format!("{} = {:?}", key, value))
+                    .collect();
+                Tree::new("When")
+                    .string("conditions", &conditions.join(", "))
+                    .nodes("body", body)
+                    .nodes("else", else_body)
+            }
+        }
+    }
+
+    /// Indented tree, one node per line; list slots with several entries get
+    /// their own `name:` header line.
+    fn write_pretty(&self, out: &mut String, depth: usize, label: Option<&str>) {
+        out.push_str(&"  ".repeat(depth));
+        if let Some(label) = label {
+            out.push_str(label);
+            out.push_str(": ");
+        }
+        out.push_str(self.kind);
+        for (key, value) in &self.attrs {
+            match value {
+                Value::Str(s) => out.push_str(&format!(" {}={:?}", key, s)),
+                Value::Int(n) => out.push_str(&format!(" {}={}", key, n)),
+                Value::Bool(true) => out.push_str(&format!(" {}", key)),
+                Value::Bool(false) | Value::Null => {}
+            }
+        }
+        out.push('\n');
+
+        for (key, slot) in &self.children {
+            match slot {
+                Slot::One(child) => child.write_pretty(out, depth + 1, Some(key)),
+                Slot::Many(items) if items.is_empty() => {}
+                Slot::Many(items) => {
+                    out.push_str(&"  ".repeat(depth + 1));
+                    out.push_str(key);
+                    out.push_str(":\n");
+                    for item in items {
+                        item.write_pretty(out, depth + 2, None);
+                    }
+                }
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"kind\":{}", json_string(self.kind))];
+        for (key, value) in &self.attrs {
+            let v = match value {
+                Value::Str(s) => json_string(s),
+                Value::Int(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                Value::Null => "null".to_string(),
+            };
+            fields.push(format!("{}:{}", json_string(key), v));
+        }
+        for (key, slot) in &self.children {
+            let v = match slot {
+                Slot::One(child) => child.to_json(),
+                Slot::Many(items) => format!(
+                    "[{}]",
+                    items
+                        .iter()
+                        .map(Tree::to_json)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            };
+            fields.push(format!("{}:{}", json_string(key), v));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn arm_tree(arm: &MatchArm) -> Tree {
+    let mut tree = Tree::new("Arm");
+    tree.children
+        .push(("pattern", Slot::One(Box::new(pattern_tree(&arm.pattern)))));
+    tree.child("body", &arm.body)
+}
+
+fn pattern_tree(pattern: &Pattern) -> Tree {
+    match pattern {
+        Pattern::Identifier(name) => Tree::new("BindingPattern").string("name", name),
+        Pattern::EnumPattern {
+            enum_name,
+            variant,
+            payload,
+        } => {
+            let mut tree = Tree::new("EnumPattern")
+                .string("enum", enum_name)
+                .string("variant", variant);
+            tree.children.push((
+                "payload",
+                Slot::Many(payload.iter().map(pattern_tree).collect()),
+            ));
+            tree
+        }
+        Pattern::StructPattern {
+            struct_name,
+            fields,
+            rest,
+        } => Tree::new("StructPattern")
+            .string("struct", struct_name)
+            .list(
+                "fields",
+                fields
+                    .iter()
+                    .map(|(field, pattern)| {
+                        let mut tree = Tree::new("FieldPattern").string("field", field);
+                        tree.children
+                            .push(("pattern", Slot::One(Box::new(pattern_tree(pattern)))));
+                        tree
+                    })
+                    .collect(),
+            )
+            .flag("rest", *rest),
+        Pattern::NumberPattern(n) => Tree::new("NumberPattern").attr("value", Value::Int(*n)),
+        Pattern::StringPattern(s) => Tree::new("StringPattern").string("value", s),
+        Pattern::PrefixPattern { prefix, rest } => Tree::new("PrefixPattern")
+            .string("prefix", prefix)
+            .string("rest", rest),
+        Pattern::SuffixPattern { rest, suffix } => Tree::new("SuffixPattern")
+            .string("rest", rest)
+            .string("suffix", suffix),
+        Pattern::Wildcard => Tree::new("WildcardPattern"),
+    }
+}
+
+fn binop_str(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Equal => "==",
+        BinOp::NotEqual => "!=",
+        BinOp::LessThan => "<",
+        BinOp::LessEqual => "<=",
+        BinOp::GreaterThan => ">",
+        BinOp::GreaterEqual => ">=",
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+        BinOp::DotDot => "..",
+    }
+}
@@ -1,6 +1,88 @@
-use crate::parser::{AstNode, BinOp, Parameter, Pattern};
+use crate::ir::{FunctionBuilder, Inst};
+use crate::monomorphize::Monomorphizer;
+use crate::parser::{
+    AstNode, BinOp, Location, MatchArm, Parameter, Pattern, Span, StringOwnership, is_value_struct,
+};
+use crate::semantic::global_init_order;
 use std::collections::HashMap;
 
+/// Settings that change the generated code, chosen on the command line.
+#[derive(Clone, Default)]
+pub struct CodegenOptions {
+    /// `--profile`: count calls and time every function, report at exit.
+    pub profile: bool,
+    /// `--profile-alloc`: count heap allocations, live and peak bytes, per
+    /// allocation site, report at exit.
+    pub profile_alloc: bool,
+    /// `--detect-leaks`: keep track of every live heap block and the
+    /// allocation site it came from, report the ones left at exit.
+    pub detect_leaks: bool,
+    /// `--runtime`: Brain's own runtime or the C library's.
+    pub runtime: Runtime,
+    /// `--sanitize`: build for AddressSanitizer, on the C runtime, which
+    /// then reports memory errors (and faults) itself.
+    pub sanitize: bool,
+    /// `--no-checks`: leave out runtime checks (division by zero, Vec
+    /// indexing).
+    pub no_checks: bool,
+    /// `--stack-size`: how far the main thread's stack may grow, in bytes.
+    /// Linux raises its limit at startup; elsewhere the linker reserves it.
+    pub stack_size: Option<u64>,
+    /// What dead-code elimination keeps; `--lib` swaps `main` for the
+    /// exports.
+    pub roots: Roots,
+}
+
+/// What a program runs on besides its own code.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Runtime {
+    /// Brain's allocator, entry point and raw system calls, linked
+    /// statically with no C library.
+    #[default]
+    Freestanding,
+    /// The C library's `malloc`, `realloc` and `free`, its startup code
+    /// calling `main`, and its `exit`; linked dynamically.
+    Libc,
+}
+
+/// Where dead-code elimination starts: a function or top-level binding is
+/// generated only when one of these reaches it.
+#[derive(Clone)]
+pub struct Roots {
+    /// `fn main`, along with the entry point that calls it.
+    pub main: bool,
+    /// Whatever the compiled files declare `export`. Exports of the modules
+    /// they import are not roots; those are only kept if used.
+    pub exports: bool,
+    /// Items carrying any of these attributes: `#[used]`, and the
+    /// `#[no_mangle]`, `#[startup]` and `#[shutdown]` functions, which
+    /// are called from outside the program or by its entry point.
+    pub attributes: Vec<String>,
+}
+
+impl Roots {
+    /// A library has no `main`; what it exports is what its users call.
+    pub fn library() -> Self {
+        Roots {
+            main: false,
+            exports: true,
+            ..Roots::default()
+        }
+    }
+}
+
+impl Default for Roots {
+    fn default() -> Self {
+        Roots {
+            main: true,
+            exports: false,
+            attributes: ["used", "no_mangle", "startup", "shutdown"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
 pub struct CodeGenerator {
     output: String,
     struct_decls: Vec<String>,
@@ -9,28 +91,257 @@ pub struct CodeGenerator {
     label_counter: usize,
     string_literals: Vec<(String, String)>,
     string_literal_map: HashMap<String, String>, // dedup: value -> id
+    /// Array literals made only of constants, emitted as private globals.
+    array_literals: Vec<(String, Vec<i64>)>,
+    array_literal_map: HashMap<Vec<i64>, String>, // dedup: elements -> id
+    /// `include_bytes` contents, emitted as private globals.
+    byte_literals: Vec<(String, Vec<u8>)>,
+    /// Enums whose variant names are needed at run time, each emitted as an
+    /// `@.enum.<Name>` table of string constants indexed by tag.
+    enum_name_tables: Vec<String>,
+    /// Structs `debug` prints, each given a `@brn_debug.<Name>` formatter
+    /// once everything else is generated.
+    debug_structs: Vec<String>,
+    /// Structs compared with `==` or `!=`, each given a `@brn_eq.<Name>`
+    /// function the same way.
+    eq_structs: Vec<String>,
+    /// Structs given to `hash`, each given a `@brn_hash.<Name>` function.
+    hash_structs: Vec<String>,
     current_function_vars: HashMap<String, VarMetadata>,
     loop_stack: Vec<LoopLabels>,
     enum_types: HashMap<String, Vec<String>>,
+    /// Enum name → the types each variant holds, in variant order.
+    enum_fields: HashMap<String, Vec<Vec<String>>>,
     struct_types: HashMap<String, Vec<(String, String)>>,
-    block_terminated: bool,
+    /// Struct name → the fields declared with `= value` defaults.
+    struct_defaults: HashMap<String, HashMap<String, AstNode>>,
+    /// The function being generated, if any; instructions go here rather
+    /// than straight into `output`.
+    function: Option<FunctionBuilder>,
     current_function_name: String,
     current_function_return_type: String,
     function_signatures: HashMap<String, String>,
+    /// Declared parameter types of every user function, which its calls
+    /// convert `int` and `char` arguments to.
+    param_types: HashMap<String, Vec<String>>,
+    /// Declared `Vec<...>`, `Bytes`, `*T` and enum return types, which
+    /// `function_signatures` (LLVM types) can't tell apart from `string`, an
+    /// owned struct or another enum.
+    vec_return_types: HashMap<String, String>,
+    /// Functions declared to return a reference (`-> &string`): what they
+    /// return is borrowed, not the caller's to free.
+    borrowed_returns: std::collections::HashSet<String>,
     pure_functions: std::collections::HashSet<String>,
+    /// Pure functions that are known to return: no loops, no recursion.
+    terminating_functions: std::collections::HashSet<String>,
     non_escaping: std::collections::HashSet<String>,
     current_binding: Option<String>,
+    /// Set while generating a `return` value: enum values built there must
+    /// outlive the function's stack frame.
+    returning: bool,
     is_unsafe_fn: bool,
     guard_vars: std::collections::HashSet<String>,
+    /// `impl` methods (`Type.method`) → whether `self` is taken by reference.
+    methods: HashMap<String, bool>,
+    /// Slots of struct and string variables whose value now belongs to
+    /// something else — a `*T` or another binding; block exit leaves them
+    /// alone.
+    moved_vars: std::collections::HashSet<String>,
+    options: CodegenOptions,
+    /// Function name → slot in the `--profile` counter tables.
+    profile_ids: HashMap<String, usize>,
+    /// `--profile-alloc` site names, indexed by site ID.
+    alloc_sites: Vec<String>,
+    /// Top-level `let` bindings, which every function sees as variables.
+    globals: Vec<(String, VarMetadata)>,
+    /// Generic function templates and the instances requested of them.
+    instances: Monomorphizer,
+    /// `extern fn` declarations, by name.
+    externs: HashMap<String, ExternSig>,
+    /// `#[no_mangle]` functions, defined under their own name.
+    no_mangle: std::collections::HashSet<String>,
+    /// What the compiled files export, the roots `Roots::exports` adds.
+    exports: Vec<String>,
+    /// Whether the program has top-level statements, which
+    /// `@brn.init_modules` runs before `main`.
+    has_module_init: bool,
+    /// `#[startup]` functions, run in the order they are declared before
+    /// `main`; `#[shutdown]` ones run in reverse order once it returns.
+    startup_hooks: Vec<String>,
+    shutdown_hooks: Vec<String>,
+    /// `#[inline]` and `#[noinline]` functions → the LLVM attribute they get.
+    inline_hints: HashMap<String, &'static str>,
+    /// Nodes of the loops being generated that are hoisted or strength
+    /// reduced, by address.
+    loop_values: HashMap<*const AstNode, LoopValue>,
+    /// Induction variable steps (`i = i + k`), by address: the running
+    /// products to advance after each, and by how much.
+    induction_steps: HashMap<*const AstNode, Steps>,
 }
 
 #[derive(Clone)]
 struct VarMetadata {
     llvm_name: String,
     var_type: String,
+    /// The variable owns its value and frees it when its block ends; for a
+    /// string, see `StringOwnership`.
     is_heap: bool,
     array_size: Option<usize>,
-    is_string_literal: bool,
+}
+
+/// An `extern fn`: its LLVM parameter and return types, whether more
+/// arguments may follow the parameters, and what calls go through — the
+/// symbol itself, or a cast of it when the runtime already declares that
+/// symbol with another type.
+struct ExternSig {
+    params: Vec<String>,
+    variadic: bool,
+    return_type: String,
+    callee: String,
+}
+
+/// What generating a node produced: the register (or constant) holding it,
+/// its LLVM type, and the Brain type it was generated as.
+#[derive(Clone, Debug)]
+struct Value {
+    reg: String,
+    llvm_ty: String,
+    brain_ty: String,
+}
+
+/// What a node inside a loop is generated as instead of itself.
+#[derive(Clone)]
+enum LoopValue {
+    /// Loop-invariant: the register it was computed into before the loop.
+    Hoisted(String),
+    /// `i * c` for an induction variable `i`: a slot kept equal to it,
+    /// advanced along with `i`.
+    Running(String),
+}
+
+/// Running products to advance: each slot, and by how much.
+type Steps = Vec<(String, i64)>;
+
+/// What `leave_loop` restores, and the running products a counted `for`
+/// advances (by their slot and step) each time round.
+struct LoopScope {
+    saved_values: Vec<(*const AstNode, Option<LoopValue>)>,
+    saved_steps: Vec<(*const AstNode, Option<Steps>)>,
+    counter_steps: Steps,
+}
+
+/// What a loop can do to the variables around it: the names it binds
+/// itself, shadowing outer ones, and the names it may change. For each
+/// changed name, `steps` has its `i = i + k` assignments, or `None` once it
+/// is changed some other way.
+#[derive(Default)]
+struct LoopFacts<'a> {
+    bound: std::collections::HashSet<&'a str>,
+    changed: std::collections::HashSet<&'a str>,
+    steps: HashMap<&'a str, Option<Vec<(&'a AstNode, i64)>>>,
+}
+
+impl<'a> LoopFacts<'a> {
+    /// Methods that only read the value they are called on.
+    const READERS: &'static [&'static str] = &["len", "char_count", "char_at", "get"];
+
+    fn analyze(parts: &[&'a AstNode], vars: &HashMap<String, VarMetadata>) -> Self {
+        let mut facts = LoopFacts::default();
+        for part in parts {
+            facts.visit(part, vars);
+        }
+        facts
+    }
+
+    fn visit(&mut self, node: &'a AstNode, vars: &HashMap<String, VarMetadata>) {
+        match node {
+            AstNode::Assignment { name, value, .. } => {
+                self.changed.insert(name);
+                let step = Self::constant_step(name, value);
+                let steps = self.steps.entry(name).or_insert_with(|| Some(Vec::new()));
+                match (steps.as_mut(), step) {
+                    (Some(steps), Some(k)) => steps.push((node, k)),
+                    _ => *steps = None,
+                }
+            }
+            AstNode::ArrayAssignment { array, .. } => self.change(array),
+            AstNode::MemberAssignment { object, .. } | AstNode::Reference(object, _) => {
+                if let Some(name) = Self::root(object) {
+                    self.change(name);
+                }
+            }
+            AstNode::MethodCall { object, method, .. } => {
+                if !Self::READERS.contains(&method.as_str())
+                    && let Some(name) = Self::root(object)
+                {
+                    self.change(name);
+                }
+            }
+            AstNode::LetBinding { name, .. } => {
+                self.bound.insert(name);
+            }
+            AstNode::For {
+                variable, index, ..
+            } => {
+                self.bound.insert(variable);
+                self.bound.extend(index.as_deref());
+            }
+            AstNode::Match { arms, .. } => {
+                for arm in arms {
+                    self.bound.extend(arm.pattern.bindings());
+                }
+            }
+            _ => {}
+        }
+        // Anything but a plain value passed to a call may be changed by it
+        if let AstNode::Call { args, .. } | AstNode::MethodCall { args, .. } = node {
+            for arg in args {
+                if let AstNode::Identifier { name, .. } = arg
+                    && !vars
+                        .get(name)
+                        .is_some_and(|m| matches!(m.var_type.as_str(), "int" | "bool" | "char"))
+                {
+                    self.change(name);
+                }
+            }
+        }
+        for child in node.children() {
+            self.visit(child, vars);
+        }
+    }
+
+    fn change(&mut self, name: &'a str) {
+        self.changed.insert(name);
+        self.steps.insert(name, None);
+    }
+
+    /// The variable a place expression (`v`, `v.field`, `v[i]`) is part of.
+    fn root(node: &AstNode) -> Option<&str> {
+        match node {
+            AstNode::Identifier { name, .. } => Some(name),
+            AstNode::MemberAccess { object, .. } | AstNode::Index { array: object, .. } => {
+                Self::root(object)
+            }
+            _ => None,
+        }
+    }
+
+    /// `k` when `value` is `name + k`, `k + name` or `name - k`.
+    fn constant_step(name: &str, value: &AstNode) -> Option<i64> {
+        let AstNode::BinaryOp {
+            op, left, right, ..
+        } = value
+        else {
+            return None;
+        };
+        let is_name = |n: &AstNode| matches!(n, AstNode::Identifier { name: n, .. } if n == name);
+        match (op, left.as_ref(), right.as_ref()) {
+            (BinOp::Add, l, AstNode::Number(k, _)) if is_name(l) => Some(*k),
+            (BinOp::Add, AstNode::Number(k, _), r) if is_name(r) => Some(*k),
+            (BinOp::Sub, l, AstNode::Number(k, _)) if is_name(l) => k.checked_neg(),
+            _ => None,
+        }
+    }
 }
 
 struct LoopLabels {
@@ -38,6 +349,17 @@ struct LoopLabels {
     break_label: String,
 }
 
+/// One function body's part in purity inference: the functions it calls
+/// (`.m` for a method call `x.m()`) and whether it loops.
+#[derive(Default)]
+struct PurityFacts {
+    callees: Vec<String>,
+    has_loop: bool,
+    /// Whether it indexes something, which can panic when bounds are
+    /// checked.
+    indexes: bool,
+}
+
 fn get_target_triple() -> &'static str {
     if cfg!(target_os = "windows") {
         "x86_64-pc-windows-msvc"
@@ -78,11 +400,11 @@ impl EscapeAnalysis {
 
     fn visit(&mut self, node: &AstNode) {
         match node {
-            AstNode::Return(Some(val)) => {
+            AstNode::Return(Some(val), _) => {
                 self.mark_escaping(val);
                 self.visit(val);
             }
-            AstNode::Call { name, args } => {
+            AstNode::Call { name, args, .. } => {
                 let safe_builtins = matches!(
                     name.as_str(),
                     "print"
@@ -94,18 +416,70 @@ impl EscapeAnalysis {
                         | "print_char"
                         | "println_char"
                         | "write_file"
+                        | "append_file"
                         | "read_file"
+                        | "read_bytes"
+                        | "open_lines"
+                        | "path_join"
+                        | "path_basename"
+                        | "path_extension"
+                        | "path_parent"
+                        | "next_line"
+                        | "has_next_line"
+                        | "write_bytes"
+                        | "mkdir"
+                        | "read_dir"
+                        | "remove_dir"
+                        | "run"
+                        | "run_capture"
+                        | "sleep"
+                        | "sleep_ms"
+                        | "now"
                         | "read_input"
                         | "vec_len"
                         | "vec_get"
                         | "vec_push"
                         | "vec_set"
+                        | "vec_pop"
+                        | "vec_insert"
+                        | "vec_remove"
+                        | "vec_clear"
+                        | "vec_with_capacity"
+                        | "vec_reserve"
+                        | "bytes_new"
+                        | "bytes_len"
+                        | "bytes_get"
+                        | "bytes_set"
+                        | "bytes_slice"
+                        | "bytes_from_string"
+                        | "bytes_to_string"
+                        | "to_uppercase"
+                        | "to_lowercase"
+                        | "sb_new"
+                        | "sb_append"
+                        | "sb_to_string"
                         | "int_to_string"
+                        | "to_string"
+                        | "enum_name"
+                        | "debug"
+                        | "hash"
+                        | "bool_to_string"
+                        | "compare"
+                        | "char_to_int"
+                        | "int_to_char"
                         | "len"
+                        | "char_count"
+                        | "assert"
                 );
+                // A Vec keeps the element it is given
+                if matches!(name.as_str(), "vec_push" | "vec_insert" | "vec_set")
+                    && let Some(element) = args.last()
+                {
+                    self.mark_escaping(element);
+                }
                 for arg in args {
                     match arg {
-                        AstNode::Reference(_) => {}
+                        AstNode::Reference(_, _) => {}
                         _ if !safe_builtins => {
                             let t = Self::rough_type(arg);
                             if Self::is_heap_type(&t) {
@@ -117,9 +491,31 @@ impl EscapeAnalysis {
                     self.visit(arg);
                 }
             }
-            AstNode::LetBinding { value, .. } => self.visit(value),
-            AstNode::Assignment { value, .. } => self.visit(value),
-            AstNode::Block(stmts) | AstNode::Program(stmts) => {
+            AstNode::LetBinding { value, .. } => {
+                // `let q = p;` hands p's value to q, which may live on elsewhere;
+                // `let q = &p;` lets q reach p's memory
+                if matches!(
+                    value.as_ref(),
+                    AstNode::Identifier { .. } | AstNode::Reference(..)
+                ) {
+                    self.mark_escaping(value);
+                }
+                self.visit(value);
+            }
+            // A value stored into another variable, a struct field or an
+            // element can outlive the block that created it, e.g. a node
+            // linked into a list. A variable assigned to stays off the stack
+            // too, since it frees the value it replaces.
+            AstNode::Assignment { value, .. }
+            | AstNode::MemberAssignment { value, .. }
+            | AstNode::ArrayAssignment { value, .. } => {
+                self.mark_escaping(value);
+                if let AstNode::Assignment { name, .. } = node {
+                    self.escaping.insert(name.clone());
+                }
+                self.visit(value);
+            }
+            AstNode::Block(stmts, _) | AstNode::Program(stmts, _) => {
                 for s in stmts {
                     self.visit(s);
                 }
@@ -128,6 +524,7 @@ impl EscapeAnalysis {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
                 self.visit(condition);
                 self.visit(then_block);
@@ -135,7 +532,9 @@ impl EscapeAnalysis {
                     self.visit(e);
                 }
             }
-            AstNode::While { condition, body } => {
+            AstNode::While {
+                condition, body, ..
+            } => {
                 self.visit(condition);
                 self.visit(body);
             }
@@ -143,56 +542,91 @@ impl EscapeAnalysis {
                 self.visit(iterator);
                 self.visit(body);
             }
-            AstNode::BinaryOp { left, right, .. } => {
+            AstNode::BinaryOp {
+                left, right, op, ..
+            } => {
+                // `a + b` on strings frees the variables it consumes, which
+                // must be on the heap for that
+                if matches!(op, BinOp::Add) {
+                    self.mark_escaping(left);
+                    self.mark_escaping(right);
+                }
                 self.visit(left);
                 self.visit(right);
             }
-            AstNode::UnaryOp { operand, .. } => self.visit(operand),
-            AstNode::ExpressionStatement(e) => self.visit(e),
-            AstNode::Match { value, arms } => {
+            AstNode::UnaryOp { operand, .. } | AstNode::Cast { value: operand, .. } => {
+                self.visit(operand)
+            }
+            AstNode::ExpressionStatement(e, _) => self.visit(e),
+            AstNode::Match { value, arms, .. } => {
                 self.visit(value);
                 for arm in arms {
                     self.visit(&arm.body);
                 }
             }
-            AstNode::ArrayLit(elems) => {
+            AstNode::ArrayLit(elems, _) => {
                 for e in elems {
+                    self.mark_escaping(e);
                     self.visit(e);
                 }
             }
-            AstNode::StructInit { fields, .. } => {
+            AstNode::EnumValue { values, .. } => {
+                for v in values {
+                    self.mark_escaping(v);
+                    self.visit(v);
+                }
+            }
+            AstNode::StructInit { fields, base, .. } => {
                 for (_, v) in fields {
+                    self.mark_escaping(v);
                     self.visit(v);
                 }
+                if let Some(b) = base {
+                    self.visit(b);
+                }
             }
-            AstNode::Index { array, index } => {
+            AstNode::Index { array, index, .. } => {
                 self.visit(array);
                 self.visit(index);
             }
-            AstNode::Reference(e) => self.visit(e),
+            AstNode::Slice {
+                value, start, end, ..
+            } => {
+                self.visit(value);
+                for bound in [start, end].into_iter().flatten() {
+                    self.visit(bound);
+                }
+            }
+            AstNode::Reference(e, _) => self.visit(e),
             AstNode::MemberAccess { object, .. } => self.visit(object),
             AstNode::MethodCall { object, args, .. } => {
                 self.visit(object);
+                // `v.push(s)` and the like keep what they are given
                 for a in args {
+                    if !matches!(a, AstNode::Reference(..)) {
+                        self.mark_escaping(a);
+                    }
                     self.visit(a);
                 }
             }
-            AstNode::Return(None)
-            | AstNode::Break
-            | AstNode::Continue
+            AstNode::Return(None, _)
+            | AstNode::Break(_)
+            | AstNode::Continue(_)
             | AstNode::Identifier { .. }
-            | AstNode::Number(_)
-            | AstNode::Boolean(_)
-            | AstNode::StringLit(_)
-            | AstNode::Character(_)
-            | AstNode::ArrayAssignment { .. }
-            | AstNode::MemberAssignment { .. }
+            | AstNode::Number(_, _)
+            | AstNode::Boolean(_, _)
+            | AstNode::Null(_)
+            | AstNode::StringLit(_, _)
+            | AstNode::BytesLit(_, _)
+            | AstNode::Character(_, _)
+            | AstNode::SizeOf(_, _)
             | AstNode::FunctionDef { .. }
             | AstNode::StructDef { .. }
             | AstNode::EnumDef { .. }
-            | AstNode::EnumValue { .. }
             | AstNode::ArrayType { .. }
-            | AstNode::Import { .. } => {}
+            | AstNode::Import { .. }
+            | AstNode::When { .. }
+            | AstNode::ExternFn { .. } => {}
         }
     }
 
@@ -201,14 +635,23 @@ impl EscapeAnalysis {
             AstNode::Identifier { name, .. } => {
                 self.escaping.insert(name.clone());
             }
-            AstNode::Reference(inner) => self.mark_escaping(inner),
+            AstNode::Reference(inner, _) => {
+                // `&p.field` and `&v[i]` point into `p` and `v`
+                let mut root = inner.as_ref();
+                while let AstNode::MemberAccess { object: inner, .. }
+                | AstNode::Index { array: inner, .. } = root
+                {
+                    root = inner;
+                }
+                self.mark_escaping(root)
+            }
             _ => {}
         }
     }
 
     fn rough_type(node: &AstNode) -> String {
         match node {
-            AstNode::StringLit(_) => "string".to_string(),
+            AstNode::StringLit(_, _) => "string".to_string(),
             AstNode::Identifier { .. } => "unknown".to_string(),
             AstNode::BinaryOp { left, .. } => Self::rough_type(left),
             _ => String::new(),
@@ -220,6 +663,12 @@ impl EscapeAnalysis {
     }
 }
 
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CodeGenerator {
     pub fn new() -> Self {
         CodeGenerator {
@@ -230,25 +679,71 @@ impl CodeGenerator {
             label_counter: 0,
             string_literals: Vec::new(),
             string_literal_map: HashMap::new(),
+            array_literals: Vec::new(),
+            byte_literals: Vec::new(),
+            enum_name_tables: Vec::new(),
+            debug_structs: Vec::new(),
+            eq_structs: Vec::new(),
+            hash_structs: Vec::new(),
+            array_literal_map: HashMap::new(),
             current_function_vars: HashMap::new(),
             loop_stack: Vec::new(),
             enum_types: HashMap::new(),
+            enum_fields: HashMap::new(),
             struct_types: HashMap::new(),
-            block_terminated: false,
+            struct_defaults: HashMap::new(),
+            function: None,
             current_function_name: String::new(),
             current_function_return_type: String::new(),
             function_signatures: HashMap::new(),
+            param_types: HashMap::new(),
+            vec_return_types: HashMap::new(),
+            borrowed_returns: std::collections::HashSet::new(),
             pure_functions: std::collections::HashSet::new(),
+            no_mangle: std::collections::HashSet::new(),
+            exports: Vec::new(),
+            has_module_init: false,
+            startup_hooks: Vec::new(),
+            shutdown_hooks: Vec::new(),
+            inline_hints: HashMap::new(),
+            terminating_functions: std::collections::HashSet::new(),
             non_escaping: std::collections::HashSet::new(),
             current_binding: None,
+            returning: false,
             is_unsafe_fn: false,
             guard_vars: std::collections::HashSet::new(),
+            methods: HashMap::new(),
+            moved_vars: std::collections::HashSet::new(),
+            options: CodegenOptions::default(),
+            profile_ids: HashMap::new(),
+            alloc_sites: Vec::new(),
+            globals: Vec::new(),
+            externs: HashMap::new(),
+            loop_values: HashMap::new(),
+            induction_steps: HashMap::new(),
+            instances: Monomorphizer::new(),
+        }
+    }
+
+    pub fn with_options(options: CodegenOptions) -> Self {
+        CodeGenerator {
+            options,
+            ..Self::new()
         }
     }
 
+    /// Name the compiled files' exports, which are kept alive when the
+    /// options make them roots.
+    pub fn with_exports(mut self, exports: Vec<String>) -> Self {
+        self.exports = exports;
+        self
+    }
+
     pub fn generate(&mut self, ast: &AstNode) -> String {
         // Single pre-pass: collect structs, enums, fn signatures, purity — was 4 separate loops
-        if let AstNode::Program(nodes) = ast {
+        let mut purity_facts = HashMap::new();
+        let mut user_functions = std::collections::HashSet::new();
+        if let AstNode::Program(nodes, _) = ast {
             for node in nodes {
                 match node {
                     AstNode::StructDef { name, fields, .. } => {
@@ -257,11 +752,39 @@ impl CodeGenerator {
                             .map(|f| (f.name.clone(), f.field_type.clone()))
                             .collect();
                         self.struct_types.insert(name.clone(), field_info);
+                        let defaults: HashMap<String, AstNode> = fields
+                            .iter()
+                            .filter_map(|f| Some((f.name.clone(), (**f.default.as_ref()?).clone())))
+                            .collect();
+                        if !defaults.is_empty() {
+                            self.struct_defaults.insert(name.clone(), defaults);
+                        }
                     }
                     AstNode::EnumDef { name, variants, .. } => {
                         let variant_names: Vec<String> =
                             variants.iter().map(|v| v.name.clone()).collect();
                         self.enum_types.insert(name.clone(), variant_names);
+                        let fields = variants.iter().map(|v| v.fields.clone()).collect();
+                        self.enum_fields.insert(name.clone(), fields);
+                    }
+                    AstNode::ExternFn {
+                        name,
+                        params,
+                        variadic,
+                        return_type,
+                        ..
+                    } => {
+                        self.register_signature(name, return_type);
+                        let sig = ExternSig {
+                            params: params
+                                .iter()
+                                .map(|p| self.type_to_llvm(&p.param_type))
+                                .collect(),
+                            variadic: *variadic,
+                            return_type: self.function_signatures[name].clone(),
+                            callee: format!("@{}", name),
+                        };
+                        self.externs.insert(name.clone(), sig);
                     }
                     AstNode::FunctionDef {
                         name,
@@ -270,16 +793,31 @@ impl CodeGenerator {
                         return_type,
                         ..
                     } => {
-                        let ret_llvm = if name == "main" {
-                            "i32".to_string()
-                        } else if let Some(rt) = return_type {
-                            self.type_to_llvm(rt)
-                        } else {
-                            "void".to_string()
-                        };
-                        self.function_signatures.insert(name.clone(), ret_llvm);
-                        if Self::infer_purity(params, body) {
-                            self.pure_functions.insert(name.clone());
+                        self.register_signature(name, return_type);
+                        self.register_params(name, params);
+                        user_functions.insert(name.clone());
+                        if node.has_attribute("no_mangle") {
+                            self.no_mangle.insert(name.clone());
+                        }
+                        if node.has_attribute("startup") {
+                            self.startup_hooks.push(name.clone());
+                        }
+                        if node.has_attribute("shutdown") {
+                            self.shutdown_hooks.insert(0, name.clone());
+                        }
+                        if node.has_attribute("inline") {
+                            self.inline_hints.insert(name.clone(), " alwaysinline");
+                        } else if node.has_attribute("noinline") {
+                            self.inline_hints.insert(name.clone(), " noinline");
+                        }
+                        if let Some(facts) = Self::infer_purity(params, body, return_type) {
+                            purity_facts.insert(name.clone(), facts);
+                        }
+                        if name.contains('.')
+                            && let Some(receiver) = params.first()
+                        {
+                            let (by_ref, _, _) = Self::strip_ref_prefix(&receiver.param_type);
+                            self.methods.insert(name.clone(), by_ref);
                         }
                     }
                     _ => {}
@@ -287,15 +825,31 @@ impl CodeGenerator {
             }
         }
 
-        let reachable = if let AstNode::Program(nodes) = ast {
-            Self::collect_reachable(nodes)
+        self.infer_call_graph_purity(&purity_facts, &user_functions);
+
+        let value_structs = self
+            .struct_types
+            .keys()
+            .filter(|name| self.is_value_struct(name))
+            .cloned()
+            .collect();
+        let enums = self.enum_types.keys().cloned().collect();
+        self.instances.set_types(value_structs, enums);
+
+        let reachable = if let AstNode::Program(nodes, _) = ast {
+            self.collect_reachable(nodes)
         } else {
             std::collections::HashSet::new()
         };
 
-        for (struct_name, fields) in &self.struct_types.clone() {
-            let field_types: Vec<String> =
-                fields.iter().map(|(_, ft)| self.type_to_llvm(ft)).collect();
+        // Sorted, so that the same program always gives the same IR
+        let mut structs: Vec<_> = self.struct_types.clone().into_iter().collect();
+        structs.sort();
+        for (struct_name, fields) in &structs {
+            let field_types: Vec<String> = fields
+                .iter()
+                .map(|(_, ft)| self.value_type_to_llvm(ft))
+                .collect();
             self.struct_decls.push(format!(
                 "%{} = type {{ {} }}",
                 struct_name,
@@ -304,8 +858,39 @@ impl CodeGenerator {
         }
 
         self.emit_header();
+        self.declare_externs();
+
+        if self.options.profile
+            && let AstNode::Program(nodes, _) = ast
+        {
+            for node in nodes {
+                if let AstNode::FunctionDef { name, .. } = node
+                    && reachable.contains(name.as_str())
+                {
+                    let id = self.profile_ids.len();
+                    self.profile_ids.insert(name.clone(), id);
+                }
+            }
+        }
+
+        if let AstNode::Program(nodes, _) = ast {
+            let globals: Vec<&AstNode> = global_init_order(nodes)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|node| {
+                    matches!(node, AstNode::LetBinding { name, .. } if reachable.contains(name.as_str()))
+                })
+                .collect();
+            if !globals.is_empty() {
+                self.gen_global_init(&globals);
+            }
+            let statements: Vec<&AstNode> = nodes.iter().filter(|n| !n.is_declaration()).collect();
+            if !statements.is_empty() {
+                self.gen_module_init(&statements);
+            }
+        }
 
-        if let AstNode::Program(nodes) = ast {
+        if let AstNode::Program(nodes, _) = ast {
             for node in nodes {
                 match node {
                     AstNode::FunctionDef { name, .. } => {
@@ -313,11 +898,8 @@ impl CodeGenerator {
                             self.gen_node(node);
                         }
                     }
-                    AstNode::LetBinding { name, .. } => {
-                        if reachable.contains(name.as_str()) {
-                            self.gen_node(node);
-                        }
-                    }
+                    AstNode::LetBinding { .. } => {}
+                    _ if !node.is_declaration() => {}
                     _ => {
                         self.gen_node(node);
                     }
@@ -325,22 +907,105 @@ impl CodeGenerator {
             }
         }
 
+        // Generic instances requested by the code above, and any they
+        // request in turn
+        while let Some(instance) = self.instances.next_instance() {
+            if let AstNode::FunctionDef {
+                name,
+                params,
+                return_type,
+                ..
+            } = &instance
+            {
+                self.register_signature(name, return_type);
+                self.register_params(name, params);
+            }
+            self.gen_node(&instance);
+        }
+        self.emit_debug_formatters();
+        self.emit_eq_functions();
+        self.emit_hash_functions();
+
+        if self.options.profile {
+            self.emit_profile_runtime();
+        }
+        if self.options.profile_alloc {
+            self.emit_alloc_profile_runtime();
+        }
+        if self.options.detect_leaks {
+            self.emit_leak_detector_runtime();
+        }
+
+        if !self.options.roots.main {
+            self.emit_library_hooks();
+        }
+
+        let main_takes_args = match ast {
+            AstNode::Program(nodes, _) if self.options.roots.main => {
+                nodes.iter().find_map(|n| match n {
+                    AstNode::FunctionDef { name, params, .. } if name == "main" => {
+                        Some(!params.is_empty())
+                    }
+                    _ => None,
+                })
+            }
+            _ => None,
+        };
+        if let Some(takes_args) = main_takes_args {
+            if cfg!(target_os = "linux") && self.options.runtime == Runtime::Freestanding {
+                self.emit_linux_entry(takes_args);
+            } else if self.main_inits_runtime() {
+                self.emit_runtime_init();
+            }
+        }
+
+        if !self.options.roots.main {
+            self.internalize_runtime();
+        } else if self.options.runtime == Runtime::Libc {
+            self.internalize_libc_names();
+        }
+
         self.emit_footer();
         self.build_output()
     }
 
-    fn collect_reachable(nodes: &[AstNode]) -> std::collections::HashSet<String> {
+    fn collect_reachable(&self, nodes: &[AstNode]) -> std::collections::HashSet<String> {
+        let roots = &self.options.roots;
         let mut reachable = std::collections::HashSet::new();
-        let mut queue = vec!["main".to_string()];
+        let mut queue = Vec::new();
+        if roots.main {
+            queue.push("main".to_string());
+        }
+        if roots.exports {
+            queue.extend(self.exports.iter().cloned());
+        }
+        // Top-level statements always run, and so reach what they use
+        for statement in nodes.iter().filter(|n| !n.is_declaration()) {
+            Self::collect_calls(statement, &mut queue);
+        }
+        // Attribute-marked items are kept whether or not anything reaches them
+        queue.extend(
+            nodes
+                .iter()
+                .filter(|n| roots.attributes.iter().any(|a| n.has_attribute(a)))
+                .filter_map(|n| match n {
+                    AstNode::FunctionDef { name, .. } | AstNode::LetBinding { name, .. } => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                }),
+        );
 
+        // A top-level binding is reached when something reachable reads it,
+        // and then reaches what its initializer uses
         let fn_bodies: std::collections::HashMap<&str, &AstNode> = nodes
             .iter()
-            .filter_map(|n| {
-                if let AstNode::FunctionDef { name, body, .. } = n {
-                    Some((name.as_str(), body.as_ref()))
-                } else {
-                    None
-                }
+            .filter_map(|n| match n {
+                AstNode::FunctionDef { name, body, .. } => Some((name.as_str(), body.as_ref())),
+                AstNode::LetBinding { name, value, .. } => Some((name.as_str(), value.as_ref())),
+                // A struct literal reaches what the field defaults use
+                AstNode::StructDef { name, .. } => Some((name.as_str(), n)),
+                _ => None,
             })
             .collect();
 
@@ -348,6 +1013,14 @@ impl CodeGenerator {
             if reachable.contains(&current) {
                 continue;
             }
+            if current.starts_with('.') {
+                queue.extend(
+                    fn_bodies
+                        .keys()
+                        .filter(|name| name.ends_with(current.as_str()))
+                        .map(|name| name.to_string()),
+                );
+            }
             reachable.insert(current.clone());
             if let Some(body) = fn_bodies.get(current.as_str()) {
                 Self::collect_calls(body, &mut queue);
@@ -357,31 +1030,47 @@ impl CodeGenerator {
         reachable
     }
 
+    /// The functions `node` calls, and the names it reads, which may be
+    /// top-level bindings.
     fn collect_calls(node: &AstNode, queue: &mut Vec<String>) {
         match node {
-            AstNode::Call { name, args } => {
+            AstNode::Identifier { name, .. } => queue.push(name.clone()),
+            AstNode::Call { name, args, .. } => {
                 queue.push(name.clone());
                 for arg in args {
                     Self::collect_calls(arg, queue);
                 }
             }
-            AstNode::Block(stmts) | AstNode::Program(stmts) => {
+            AstNode::Block(stmts, _) | AstNode::Program(stmts, _) => {
                 for s in stmts {
                     Self::collect_calls(s, queue);
                 }
             }
             AstNode::FunctionDef { body, .. } => Self::collect_calls(body, queue),
             AstNode::LetBinding { value, .. } => Self::collect_calls(value, queue),
-            AstNode::Assignment { value, .. } => Self::collect_calls(value, queue),
-            AstNode::ArrayAssignment { index, value, .. } => {
+            AstNode::Assignment { name, value, .. } => {
+                queue.push(name.clone());
+                Self::collect_calls(value, queue);
+            }
+            AstNode::ArrayAssignment {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                queue.push(array.clone());
                 Self::collect_calls(index, queue);
                 Self::collect_calls(value, queue);
             }
-            AstNode::MemberAssignment { value, .. } => Self::collect_calls(value, queue),
+            AstNode::MemberAssignment { object, value, .. } => {
+                Self::collect_calls(object, queue);
+                Self::collect_calls(value, queue);
+            }
             AstNode::If {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
                 Self::collect_calls(condition, queue);
                 Self::collect_calls(then_block, queue);
@@ -389,49 +1078,89 @@ impl CodeGenerator {
                     Self::collect_calls(e, queue);
                 }
             }
-            AstNode::While { condition, body } => {
+            AstNode::While {
+                condition, body, ..
+            } => {
                 Self::collect_calls(condition, queue);
                 Self::collect_calls(body, queue);
             }
             AstNode::For { iterator, body, .. } => {
+                // A loop over a user type calls its `iter` and `next` methods
+                if !matches!(
+                    iterator.as_ref(),
+                    AstNode::BinaryOp {
+                        op: BinOp::DotDot,
+                        ..
+                    }
+                ) {
+                    queue.push(".iter".to_string());
+                    queue.push(".next".to_string());
+                }
                 Self::collect_calls(iterator, queue);
                 Self::collect_calls(body, queue);
             }
-            AstNode::Return(v) => {
-                if let Some(n) = v {
-                    Self::collect_calls(n, queue);
-                }
-            }
+            AstNode::Return(Some(n), _) => Self::collect_calls(n, queue),
             AstNode::BinaryOp { left, right, .. } => {
                 Self::collect_calls(left, queue);
                 Self::collect_calls(right, queue);
             }
-            AstNode::UnaryOp { operand, .. } => Self::collect_calls(operand, queue),
-            AstNode::ExpressionStatement(e) => Self::collect_calls(e, queue),
-            AstNode::Match { value, arms } => {
+            AstNode::UnaryOp { operand, .. } | AstNode::Cast { value: operand, .. } => {
+                Self::collect_calls(operand, queue)
+            }
+            AstNode::ExpressionStatement(e, _) => Self::collect_calls(e, queue),
+            AstNode::Match { value, arms, .. } => {
                 Self::collect_calls(value, queue);
                 for arm in arms {
                     Self::collect_calls(&arm.body, queue);
                 }
             }
-            AstNode::ArrayLit(elems) => {
+            AstNode::ArrayLit(elems, _) => {
                 for e in elems {
                     Self::collect_calls(e, queue);
                 }
             }
-            AstNode::StructInit { fields, .. } => {
+            AstNode::StructInit {
+                name, fields, base, ..
+            } => {
+                queue.push(name.clone());
                 for (_, v) in fields {
                     Self::collect_calls(v, queue);
                 }
+                if let Some(b) = base {
+                    Self::collect_calls(b, queue);
+                }
+            }
+            AstNode::StructDef { fields, .. } => {
+                for default in fields.iter().filter_map(|f| f.default.as_ref()) {
+                    Self::collect_calls(default, queue);
+                }
             }
-            AstNode::Index { array, index } => {
+            AstNode::Index { array, index, .. } => {
                 Self::collect_calls(array, queue);
                 Self::collect_calls(index, queue);
             }
-            AstNode::Reference(e) | AstNode::EnumValue { value: Some(e), .. } => {
-                Self::collect_calls(e, queue);
+            AstNode::Slice {
+                value, start, end, ..
+            } => {
+                Self::collect_calls(value, queue);
+                for bound in [start, end].into_iter().flatten() {
+                    Self::collect_calls(bound, queue);
+                }
+            }
+            AstNode::Reference(e, _) => Self::collect_calls(e, queue),
+            AstNode::EnumValue { values, .. } => {
+                for v in values {
+                    Self::collect_calls(v, queue);
+                }
             }
-            AstNode::MethodCall { object, args, .. } => {
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => {
+                // The receiver's type isn't known here: `.m` stands for every `Type.m`
+                queue.push(format!(".{}", method));
                 Self::collect_calls(object, queue);
                 for a in args {
                     Self::collect_calls(a, queue);
@@ -459,29 +1188,94 @@ impl CodeGenerator {
             self.emit("declare void @InitializeCriticalSection(i8*)");
             self.emit("declare void @EnterCriticalSection(i8*)");
             self.emit("declare void @LeaveCriticalSection(i8*)");
+            self.emit("declare void @ExitProcess(i32)");
+            // Directories
+            self.emit("declare i32 @CreateDirectoryA(i8*, i8*)");
+            self.emit("declare i32 @RemoveDirectoryA(i8*)");
+            self.emit("declare i8* @FindFirstFileA(i8*, i8*)");
+            self.emit("declare i32 @FindNextFileA(i8*, i8*)");
+            self.emit("declare i32 @FindClose(i8*)");
+            // Processes
+            self.emit(
+                "declare i32 @CreateProcessA(i8*, i8*, i8*, i8*, i32, i32, i8*, i8*, i8*, i8*)",
+            );
+            self.emit("declare i32 @WaitForSingleObject(i8*, i32)");
+            self.emit("declare i32 @GetExitCodeProcess(i8*, i32*)");
+            self.emit("declare i32 @CreatePipe(i8**, i8**, i8*, i32)");
+            self.emit("declare i32 @SetHandleInformation(i8*, i32, i32)");
+            self.emit("declare void @Sleep(i32)");
+            self.emit("declare void @GetSystemTimeAsFileTime(i64*)");
+            self.emit("");
+
+            self.emit("define void @brn_mutex_init(i8* %m) {");
+            self.emit("  call void @InitializeCriticalSection(i8* %m)");
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define void @brn_mutex_lock(i8* %m) {");
+            self.emit("  call void @EnterCriticalSection(i8* %m)");
+            self.push(Inst::Ret(None));
+            self.emit("}");
             self.emit("");
 
-            self.emit("define i8* @malloc(i64 %size) {");
-            self.emit("  %heap = call i8* @GetProcessHeap()");
-            self.emit("  %ptr = call i8* @HeapAlloc(i8* %heap, i32 0, i64 %size)");
-            self.emit("  ret i8* %ptr");
+            self.emit("define void @brn_mutex_unlock(i8* %m) {");
+            self.emit("  call void @LeaveCriticalSection(i8* %m)");
+            self.push(Inst::Ret(None));
             self.emit("}");
             self.emit("");
 
-            self.emit("define i8* @realloc(i8* %ptr, i64 %size) {");
-            self.emit("  %heap = call i8* @GetProcessHeap()");
-            self.emit("  %new = call i8* @HeapReAlloc(i8* %heap, i32 0, i8* %ptr, i64 %size)");
-            self.emit("  ret i8* %new");
+            self.emit("define void @brn_write_stderr(i8* %s, i64 %len) {");
+            self.emit("  %we_out = call i8* @GetStdHandle(i32 -12)");
+            self.emit("  %we_len = trunc i64 %len to i32");
+            self.emit("  %we_written = alloca i32");
+            self.emit(
+                "  call i32 @WriteFile(i8* %we_out, i8* %s, i32 %we_len, i32* %we_written, i8* null)",
+            );
+            self.push(Inst::Ret(None));
             self.emit("}");
             self.emit("");
 
-            self.emit("define void @free(i8* %ptr) {");
-            self.emit("  %heap = call i8* @GetProcessHeap()");
-            self.emit("  call i32 @HeapFree(i8* %heap, i32 0, i8* %ptr)");
-            self.emit("  ret void");
+            self.emit("define void @brn_exit(i32 %code) {");
+            self.emit("  call void @ExitProcess(i32 %code)");
+            self.push(Inst::Unreachable);
             self.emit("}");
             self.emit("");
 
+            if self.options.runtime == Runtime::Libc {
+                self.emit_libc_allocator();
+            } else {
+                self.emit(&format!(
+                    "define i8* @{}(i64 %size) {{",
+                    self.sys_alloc("malloc")
+                ));
+                self.emit("  %heap = call i8* @GetProcessHeap()");
+                self.emit("  %ptr = call i8* @HeapAlloc(i8* %heap, i32 0, i64 %size)");
+                self.emit("  ret i8* %ptr");
+                self.emit("}");
+                self.emit("");
+
+                self.emit(&format!(
+                    "define i8* @{}(i8* %ptr, i64 %size) {{",
+                    self.sys_alloc("realloc")
+                ));
+                self.emit("  %heap = call i8* @GetProcessHeap()");
+                self.emit("  %new = call i8* @HeapReAlloc(i8* %heap, i32 0, i8* %ptr, i64 %size)");
+                self.emit("  ret i8* %new");
+                self.emit("}");
+                self.emit("");
+
+                self.emit(&format!(
+                    "define void @{}(i8* %ptr) {{",
+                    self.sys_alloc("free")
+                ));
+                self.emit("  %heap = call i8* @GetProcessHeap()");
+                self.emit("  call i32 @HeapFree(i8* %heap, i32 0, i8* %ptr)");
+                self.push(Inst::Ret(None));
+                self.emit("}");
+                self.emit("");
+            }
+
             // strlen implemented in pure IR
             self.emit("define i64 @strlen(i8* %s) {");
             self.emit("sl_entry:");
@@ -517,13 +1311,13 @@ impl CodeGenerator {
             self.emit("  %sc_next = add i64 %sc_i, 1");
             self.emit("  br i1 %sc_eq, label %sc_loop, label %sc_diff");
             self.emit("sc_diff:");
-            self.emit("  %sc_da = sext i8 %sc_ca to i32");
-            self.emit("  %sc_db = sext i8 %sc_cb to i32");
+            self.emit("  %sc_da = zext i8 %sc_ca to i32");
+            self.emit("  %sc_db = zext i8 %sc_cb to i32");
             self.emit("  %sc_r = sub i32 %sc_da, %sc_db");
             self.emit("  ret i32 %sc_r");
             self.emit("sc_exit:");
-            self.emit("  %sc_fa = sext i8 %sc_ca to i32");
-            self.emit("  %sc_fb = sext i8 %sc_cb to i32");
+            self.emit("  %sc_fa = zext i8 %sc_ca to i32");
+            self.emit("  %sc_fb = zext i8 %sc_cb to i32");
             self.emit("  %sc_fr = sub i32 %sc_fa, %sc_fb");
             self.emit("  ret i32 %sc_fr");
             self.emit("}");
@@ -569,13 +1363,29 @@ impl CodeGenerator {
             self.emit("fo_entry:");
             self.emit("  %fo_mc = load i8, i8* %mode");
             self.emit("  %fo_isw = icmp eq i8 %fo_mc, 119");
-            self.emit("  br i1 %fo_isw, label %fo_write, label %fo_read");
+            self.emit("  br i1 %fo_isw, label %fo_write, label %fo_chk_a");
             self.emit("fo_write:");
             self.emit("  %fo_wh = call i8* @CreateFileA(i8* %filename, i32 1073741824, i32 0, i8* null, i32 2, i32 128, i8* null)");
-            self.emit("  ret i8* %fo_wh");
+            self.emit("  br label %fo_done");
+            self.emit("fo_chk_a:");
+            self.emit("  %fo_isa = icmp eq i8 %fo_mc, 97");
+            self.emit("  br i1 %fo_isa, label %fo_append, label %fo_read");
+            // FILE_APPEND_DATA, OPEN_ALWAYS: every write lands at end of file
+            self.emit("fo_append:");
+            self.emit("  %fo_ah = call i8* @CreateFileA(i8* %filename, i32 4, i32 0, i8* null, i32 4, i32 128, i8* null)");
+            self.emit("  br label %fo_done");
             self.emit("fo_read:");
             self.emit("  %fo_rh = call i8* @CreateFileA(i8* %filename, i32 -2147483648, i32 1, i8* null, i32 3, i32 128, i8* null)");
-            self.emit("  ret i8* %fo_rh");
+            self.emit("  br label %fo_done");
+            // INVALID_HANDLE_VALUE (-1) becomes null, the failure callers check for
+            self.emit("fo_done:");
+            self.emit(
+                "  %fo_h = phi i8* [ %fo_wh, %fo_write ], [ %fo_ah, %fo_append ], [ %fo_rh, %fo_read ]",
+            );
+            self.emit("  %fo_hi = ptrtoint i8* %fo_h to i64");
+            self.emit("  %fo_bad = icmp eq i64 %fo_hi, -1");
+            self.emit("  %fo_r = select i1 %fo_bad, i8* null, i8* %fo_h");
+            self.emit("  ret i8* %fo_r");
             self.emit("}");
             self.emit("");
 
@@ -634,66 +1444,93 @@ impl CodeGenerator {
             self.emit("declare i64 @syscall(i64, ...)");
             self.emit("");
 
-            // brk-based malloc: grow heap with SYS_brk (syscall 12 on x86-64)
-            self.emit("@brn_heap_end = global i8* null");
-            self.emit("@brn_heap_start = global i8* null");
-            self.emit("");
-
-            self.emit("define i8* @malloc(i64 %size) {");
-            self.emit("  %cur = load i8*, i8** @brn_heap_end");
-            self.emit("  %is_null = icmp eq i8* %cur, null");
-            self.emit("  br i1 %is_null, label %init, label %alloc");
-            self.emit("init:");
-            // SYS_brk(0) returns current brk
-            self.emit("  %brk0 = call i64 (i64, ...) @syscall(i64 12, i64 0)");
-            self.emit("  %start = inttoptr i64 %brk0 to i8*");
-            self.emit("  store i8* %start, i8** @brn_heap_start");
-            self.emit("  store i8* %start, i8** @brn_heap_end");
-            self.emit("  br label %alloc");
-            self.emit("alloc:");
-            self.emit("  %base = load i8*, i8** @brn_heap_end");
-            self.emit("  %base_i = ptrtoint i8* %base to i64");
-            // align to 8 bytes
-            self.emit("  %align7 = add i64 %size, 7");
-            self.emit("  %aligned = and i64 %align7, -8");
-            self.emit("  %new_end_i = add i64 %base_i, %aligned");
-            self.emit("  %new_end = inttoptr i64 %new_end_i to i8*");
-            // SYS_brk(new_end) to extend heap
-            self.emit("  call i64 (i64, ...) @syscall(i64 12, i64 %new_end_i)");
-            self.emit("  store i8* %new_end, i8** @brn_heap_end");
-            self.emit("  ret i8* %base");
-            self.emit("}");
-            self.emit("");
-
-            // realloc: alloc new, copy, return (bump allocator — no free)
-            self.emit("define i8* @realloc(i8* %ptr, i64 %size) {");
-            self.emit("  %new = call i8* @malloc(i64 %size)");
-            // copy old data (best-effort, copy %size bytes from old ptr)
-            self.emit("  br label %rc_loop");
-            self.emit("rc_loop:");
-            self.emit("  %rc_i = phi i64 [ 0, %0 ], [ %rc_next, %rc_loop ]");
-            self.emit("  %rc_done = icmp eq i64 %rc_i, %size");
-            self.emit("  br i1 %rc_done, label %rc_exit, label %rc_copy");
-            self.emit("rc_copy:");
-            self.emit("  %rc_sp = getelementptr i8, i8* %ptr, i64 %rc_i");
-            self.emit("  %rc_dp = getelementptr i8, i8* %new, i64 %rc_i");
-            self.emit("  %rc_byte = load i8, i8* %rc_sp");
-            self.emit("  store i8 %rc_byte, i8* %rc_dp");
-            self.emit("  %rc_next = add i64 %rc_i, 1");
-            self.emit("  br label %rc_loop");
-            self.emit("rc_exit:");
-            self.emit("  ret i8* %new");
-            self.emit("}");
-            self.emit("");
-
-            // free: no-op with bump allocator
-            self.emit("define void @free(i8* %ptr) {");
-            self.emit("  ret void");
-            self.emit("}");
-            self.emit("");
+            if self.options.runtime == Runtime::Libc {
+                self.emit_libc_allocator();
+            } else {
+                // brk-based malloc: grow heap with SYS_brk (syscall 12 on x86-64)
+                self.emit("@brn_heap_end = global i8* null");
+                self.emit("@brn_heap_start = global i8* null");
+                self.emit("");
 
-            // strlen — pure IR
-            self.emit("define i64 @strlen(i8* %s) {");
+                self.emit(&format!(
+                    "define i8* @{}(i64 %size) {{",
+                    self.sys_alloc("malloc")
+                ));
+                self.emit("  %cur = load i8*, i8** @brn_heap_end");
+                self.emit("  %is_null = icmp eq i8* %cur, null");
+                self.emit("  br i1 %is_null, label %init, label %alloc");
+                self.emit("init:");
+                // SYS_brk(0) returns current brk
+                self.emit("  %brk0 = call i64 (i64, ...) @syscall(i64 12, i64 0)");
+                self.emit("  %start = inttoptr i64 %brk0 to i8*");
+                self.emit("  store i8* %start, i8** @brn_heap_start");
+                self.emit("  store i8* %start, i8** @brn_heap_end");
+                self.emit("  br label %alloc");
+                self.emit("alloc:");
+                self.emit("  %base = load i8*, i8** @brn_heap_end");
+                self.emit("  %base_i = ptrtoint i8* %base to i64");
+                // align to 8 bytes
+                self.emit("  %align7 = add i64 %size, 7");
+                self.emit("  %aligned = and i64 %align7, -8");
+                self.emit("  %new_end_i = add i64 %base_i, %aligned");
+                self.emit("  %new_end = inttoptr i64 %new_end_i to i8*");
+                // SYS_brk(new_end) to extend heap
+                self.emit("  %brk = call i64 (i64, ...) @syscall(i64 12, i64 %new_end_i)");
+                // A failed brk leaves the break where it was
+                self.emit("  %grown = icmp sge i64 %brk, %new_end_i");
+                self.emit("  br i1 %grown, label %done, label %oom");
+                self.emit("oom:");
+                self.emit(
+                    "  %oom_msg = getelementptr inbounds [14 x i8], [14 x i8]* @.str.oom, i64 0, i64 0",
+                );
+                self.emit("  call void @brn_panic(i8* %oom_msg)");
+                self.push(Inst::Unreachable);
+                self.emit("done:");
+                self.emit("  store i8* %new_end, i8** @brn_heap_end");
+                self.emit("  ret i8* %base");
+                self.emit("}");
+                self.emit("");
+
+                // realloc: alloc new, copy, return (bump allocator — no free)
+                self.emit(&format!(
+                    "define i8* @{}(i8* %ptr, i64 %size) {{",
+                    self.sys_alloc("realloc")
+                ));
+                self.emit("rc_entry:");
+                self.emit(&format!(
+                    "  %new = call i8* @{}(i64 %size)",
+                    self.sys_alloc("malloc")
+                ));
+                // copy old data (best-effort, copy %size bytes from old ptr)
+                self.emit("  br label %rc_loop");
+                self.emit("rc_loop:");
+                self.emit("  %rc_i = phi i64 [ 0, %rc_entry ], [ %rc_next, %rc_copy ]");
+                self.emit("  %rc_done = icmp eq i64 %rc_i, %size");
+                self.emit("  br i1 %rc_done, label %rc_exit, label %rc_copy");
+                self.emit("rc_copy:");
+                self.emit("  %rc_sp = getelementptr i8, i8* %ptr, i64 %rc_i");
+                self.emit("  %rc_dp = getelementptr i8, i8* %new, i64 %rc_i");
+                self.emit("  %rc_byte = load i8, i8* %rc_sp");
+                self.emit("  store i8 %rc_byte, i8* %rc_dp");
+                self.emit("  %rc_next = add i64 %rc_i, 1");
+                self.emit("  br label %rc_loop");
+                self.emit("rc_exit:");
+                self.emit("  ret i8* %new");
+                self.emit("}");
+                self.emit("");
+
+                // free: no-op with bump allocator
+                self.emit(&format!(
+                    "define void @{}(i8* %ptr) {{",
+                    self.sys_alloc("free")
+                ));
+                self.push(Inst::Ret(None));
+                self.emit("}");
+                self.emit("");
+            }
+
+            // strlen — pure IR
+            self.emit("define i64 @strlen(i8* %s) {");
             self.emit("sl_entry:");
             self.emit("  br label %sl_loop");
             self.emit("sl_loop:");
@@ -727,13 +1564,13 @@ impl CodeGenerator {
             self.emit("  %sc_next = add i64 %sc_i, 1");
             self.emit("  br i1 %sc_eq, label %sc_loop, label %sc_diff");
             self.emit("sc_diff:");
-            self.emit("  %sc_da = sext i8 %sc_ca to i32");
-            self.emit("  %sc_db = sext i8 %sc_cb to i32");
+            self.emit("  %sc_da = zext i8 %sc_ca to i32");
+            self.emit("  %sc_db = zext i8 %sc_cb to i32");
             self.emit("  %sc_r = sub i32 %sc_da, %sc_db");
             self.emit("  ret i32 %sc_r");
             self.emit("sc_exit:");
-            self.emit("  %sc_fa = sext i8 %sc_ca to i32");
-            self.emit("  %sc_fb = sext i8 %sc_cb to i32");
+            self.emit("  %sc_fa = zext i8 %sc_ca to i32");
+            self.emit("  %sc_fb = zext i8 %sc_cb to i32");
             self.emit("  %sc_fr = sub i32 %sc_fa, %sc_fb");
             self.emit("  ret i32 %sc_fr");
             self.emit("}");
@@ -773,21 +1610,38 @@ impl CodeGenerator {
             self.emit("fo_entry:");
             self.emit("  %fo_mc = load i8, i8* %mode");
             self.emit("  %fo_isw = icmp eq i8 %fo_mc, 119");
-            self.emit("  br i1 %fo_isw, label %fo_write, label %fo_read");
+            self.emit("  br i1 %fo_isw, label %fo_write, label %fo_chk_a");
             // O_WRONLY|O_CREAT|O_TRUNC = 577, mode 0644
             self.emit("fo_write:");
             self.emit(
                 "  %fo_wfd = call i64 (i64, ...) @syscall(i64 2, i8* %filename, i64 577, i64 420)",
             );
-            self.emit("  %fo_wh = inttoptr i64 %fo_wfd to i8*");
-            self.emit("  ret i8* %fo_wh");
+            self.emit("  br label %fo_done");
+            self.emit("fo_chk_a:");
+            self.emit("  %fo_isa = icmp eq i8 %fo_mc, 97");
+            self.emit("  br i1 %fo_isa, label %fo_append, label %fo_read");
+            // O_WRONLY|O_CREAT|O_APPEND = 1089, mode 0644
+            self.emit("fo_append:");
+            self.emit(
+                "  %fo_afd = call i64 (i64, ...) @syscall(i64 2, i8* %filename, i64 1089, i64 420)",
+            );
+            self.emit("  br label %fo_done");
             // O_RDONLY = 0
             self.emit("fo_read:");
             self.emit(
                 "  %fo_rfd = call i64 (i64, ...) @syscall(i64 2, i8* %filename, i64 0, i64 0)",
             );
-            self.emit("  %fo_rh = inttoptr i64 %fo_rfd to i8*");
-            self.emit("  ret i8* %fo_rh");
+            self.emit("  br label %fo_done");
+            // open() fails with a negative errno; that becomes null, the
+            // failure callers check for
+            self.emit("fo_done:");
+            self.emit(
+                "  %fo_fd = phi i64 [ %fo_wfd, %fo_write ], [ %fo_afd, %fo_append ], [ %fo_rfd, %fo_read ]",
+            );
+            self.emit("  %fo_bad = icmp slt i64 %fo_fd, 0");
+            self.emit("  %fo_h = inttoptr i64 %fo_fd to i8*");
+            self.emit("  %fo_r = select i1 %fo_bad, i8* null, i8* %fo_h");
+            self.emit("  ret i8* %fo_r");
             self.emit("}");
             self.emit("");
 
@@ -835,6 +1689,54 @@ impl CodeGenerator {
             self.emit("  ret i64 %ft_pos");
             self.emit("}");
             self.emit("");
+
+            // Mutex: spinlock on the first word of the 48-byte block
+            self.emit("define void @brn_mutex_init(i8* %m) {");
+            self.emit("  %mi_p = bitcast i8* %m to i32*");
+            self.emit("  store i32 0, i32* %mi_p");
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define void @brn_mutex_lock(i8* %m) {");
+            self.emit("ml_entry:");
+            self.emit("  %ml_p = bitcast i8* %m to i32*");
+            self.emit("  br label %ml_spin");
+            self.emit("ml_spin:");
+            self.emit("  %ml_r = cmpxchg i32* %ml_p, i32 0, i32 1 acquire monotonic");
+            self.emit("  %ml_ok = extractvalue { i32, i1 } %ml_r, 1");
+            self.emit("  br i1 %ml_ok, label %ml_done, label %ml_spin");
+            self.emit("ml_done:");
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define void @brn_mutex_unlock(i8* %m) {");
+            self.emit("  %mu_p = bitcast i8* %m to i32*");
+            self.emit("  store atomic i32 0, i32* %mu_p release, align 4");
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit("");
+
+            // SYS_write(2, ...) — stderr
+            self.emit("define void @brn_write_stderr(i8* %s, i64 %len) {");
+            self.emit("  call i64 (i64, ...) @syscall(i64 1, i64 2, i8* %s, i64 %len)");
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit("");
+
+            // SYS_exit_group (syscall 231); with the C runtime, exit() so
+            // its exit-time work (and the sanitizer's checks) still runs
+            self.emit("define void @brn_exit(i32 %code) {");
+            if self.options.runtime == Runtime::Libc {
+                self.emit("  call void @exit(i32 %code)");
+            } else {
+                self.emit("  %ex_code = sext i32 %code to i64");
+                self.emit("  call i64 (i64, ...) @syscall(i64 231, i64 %ex_code)");
+            }
+            self.push(Inst::Unreachable);
+            self.emit("}");
+            self.emit("");
         }
 
         // int_to_string: pure IR digit extraction, no sprintf needed
@@ -879,6 +1781,8 @@ impl CodeGenerator {
         self.emit("}");
         self.emit("");
 
+        // The digits are written backwards from the end of the buffer, then
+        // moved to its start: the string is the buffer, which can be freed
         self.emit("define i8* @int_to_string_impl(i64 %n) {");
         self.emit("its_entry:");
         self.emit("  %its_buf = call i8* @malloc(i64 32)");
@@ -889,7 +1793,7 @@ impl CodeGenerator {
         self.emit("  store i8 48, i8* %its_zp");
         self.emit("  %its_term = getelementptr i8, i8* %its_buf, i64 31");
         self.emit("  store i8 0, i8* %its_term");
-        self.emit("  ret i8* %its_zp");
+        self.emit("  br label %its_out");
         self.emit("its_nonzero:");
         self.emit("  %its_isneg = icmp slt i64 %n, 0");
         self.emit("  %its_neg = sub i64 0, %n");
@@ -914,161 +1818,867 @@ impl CodeGenerator {
         self.emit("its_addneg:");
         self.emit("  %its_np = getelementptr i8, i8* %its_buf, i64 %its_prev");
         self.emit("  store i8 45, i8* %its_np");
-        self.emit("  ret i8* %its_np");
+        self.emit("  br label %its_out");
         self.emit("its_ret:");
-        self.emit("  %its_rp = getelementptr i8, i8* %its_buf, i64 %its_pos");
-        self.emit("  ret i8* %its_rp");
+        self.emit("  br label %its_out");
+        self.emit("its_out:");
+        self.emit(
+            "  %its_start = phi i64 [ 30, %its_zero ], [ %its_prev, %its_addneg ], [ %its_pos, %its_ret ]",
+        );
+        self.emit("  br label %its_move");
+        self.emit("its_move:");
+        self.emit("  %its_i = phi i64 [ 0, %its_out ], [ %its_next, %its_move ]");
+        self.emit("  %its_from = add i64 %its_start, %its_i");
+        self.emit("  %its_sp = getelementptr i8, i8* %its_buf, i64 %its_from");
+        self.emit("  %its_c = load i8, i8* %its_sp");
+        self.emit("  %its_dp = getelementptr i8, i8* %its_buf, i64 %its_i");
+        self.emit("  store i8 %its_c, i8* %its_dp");
+        self.emit("  %its_next = add i64 %its_i, 1");
+        self.emit("  %its_moved = icmp eq i64 %its_from, 31");
+        self.emit("  br i1 %its_moved, label %its_exit, label %its_move");
+        self.emit("its_exit:");
+        self.emit("  ret i8* %its_buf");
         self.emit("}");
         self.emit("");
 
-        // brn_print_int: on Windows uses WriteFile, on Unix uses puts
+        // brn_write_stdout: on Windows uses WriteFile, on Unix SYS_write
         if cfg!(target_os = "windows") {
-            self.emit("define void @brn_print_int(i64 %n) {");
-            self.emit("  %bpi_buf = alloca [32 x i8]");
+            self.emit("define void @brn_write_stdout(i8* %s, i64 %len) {");
+            self.emit("  %wo_out = call i8* @GetStdHandle(i32 -11)");
+            self.emit("  %wo_len = trunc i64 %len to i32");
+            self.emit("  %wo_written = alloca i32");
+            self.emit("  store i32 0, i32* %wo_written");
             self.emit(
-                "  %bpi_buf_ptr = getelementptr [32 x i8], [32 x i8]* %bpi_buf, i64 0, i64 0",
+                "  call i32 @WriteFile(i8* %wo_out, i8* %s, i32 %wo_len, i32* %wo_written, i8* null)",
             );
-            self.emit("  %bpi_str = call i8* @int_to_string_stack(i64 %n, i8* %bpi_buf_ptr)");
-            self.emit("  %bpi_out = call i8* @GetStdHandle(i32 -11)");
-            self.emit("  %bpi_len64 = call i64 @strlen(i8* %bpi_str)");
-            self.emit("  %bpi_len32 = trunc i64 %bpi_len64 to i32");
-            self.emit("  %bpi_written = alloca i32");
-            self.emit("  store i32 0, i32* %bpi_written");
-            self.emit("  call i32 @WriteFile(i8* %bpi_out, i8* %bpi_str, i32 %bpi_len32, i32* %bpi_written, i8* null)");
-            self.emit("  %bpi_nl = alloca i8");
-            self.emit("  store i8 10, i8* %bpi_nl");
-            self.emit("  call i32 @WriteFile(i8* %bpi_out, i8* %bpi_nl, i32 1, i32* %bpi_written, i8* null)");
-            self.emit("  ret void");
+            self.push(Inst::Ret(None));
             self.emit("}");
         } else {
             // Linux: SYS_write directly — no libc
-            self.emit("define void @brn_print_int(i64 %n) {");
-            self.emit("  %bpi_str = call i8* @int_to_string_impl(i64 %n)");
-            self.emit("  %bpi_len = call i64 @strlen(i8* %bpi_str)");
-            self.emit("  call i64 (i64, ...) @syscall(i64 1, i64 1, i8* %bpi_str, i64 %bpi_len)");
-            self.emit("  %bpi_nl = alloca i8");
-            self.emit("  store i8 10, i8* %bpi_nl");
-            self.emit("  call i64 (i64, ...) @syscall(i64 1, i64 1, i8* %bpi_nl, i64 1)");
-            self.emit("  ret void");
+            self.emit("define void @brn_write_stdout(i8* %s, i64 %len) {");
+            self.emit("  call i64 (i64, ...) @syscall(i64 1, i64 1, i8* %s, i64 %len)");
+            self.push(Inst::Ret(None));
             self.emit("}");
         }
         self.emit("");
 
+        // print(s): no trailing newline (println goes through puts)
+        self.emit("define void @brn_print_str(i8* %s) {");
+        self.emit("  %bps_len = call i64 @strlen(i8* %s)");
+        self.emit("  call void @brn_write_stdout(i8* %s, i64 %bps_len)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // print(n) / println(n): digits formatted on the stack
+        self.emit("define void @brn_print_int_raw(i64 %n) {");
+        self.emit("  %bpi_buf = alloca [32 x i8]");
+        self.emit("  %bpi_buf_ptr = getelementptr [32 x i8], [32 x i8]* %bpi_buf, i64 0, i64 0");
+        self.emit("  %bpi_str = call i8* @int_to_string_stack(i64 %n, i8* %bpi_buf_ptr)");
+        self.emit("  %bpi_len = call i64 @strlen(i8* %bpi_str)");
+        self.emit("  call void @brn_write_stdout(i8* %bpi_str, i64 %bpi_len)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @brn_print_int(i64 %n) {");
+        self.emit("  call void @brn_print_int_raw(i64 %n)");
+        self.emit("  %bpn_nl = alloca i8");
+        self.emit("  store i8 10, i8* %bpn_nl");
+        self.emit("  call void @brn_write_stdout(i8* %bpn_nl, i64 1)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // print(c) / println(c) on a char write the character itself
+        self.emit("define void @brn_print_char_raw(i8 %c) {");
+        self.emit("  %bpc_buf = alloca i8");
+        self.emit("  store i8 %c, i8* %bpc_buf");
+        self.emit("  call void @brn_write_stdout(i8* %bpc_buf, i64 1)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @brn_print_char(i8 %c) {");
+        self.emit("  call void @brn_print_char_raw(i8 %c)");
+        self.emit("  call void @brn_print_char_raw(i8 10)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
         // Shared: file I/O helpers, vec helpers
-        self.emit("define i8* @read_file_impl(i8* %filename) {");
+
+        // Read `filename` to the end into a NUL-terminated buffer, storing
+        // its length in `%len_out`. Reads in chunks rather than trusting the
+        // size the file reports, which is wrong for directories and pipes; a
+        // file that can't be opened or read gives an empty buffer.
+        self.emit("define i8* @brn_read_all(i8* %filename, i64* %len_out) {");
+        self.emit("ra_entry:");
         self.emit(
-            "  %rf_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.r, i64 0, i64 0",
+            "  %ra_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.r, i64 0, i64 0",
         );
-        self.emit("  %rf_file = call i8* @fopen(i8* %filename, i8* %rf_mode)");
-        self.emit("  %rf_null = icmp eq i8* %rf_file, null");
-        self.emit("  br i1 %rf_null, label %rf_error, label %rf_read");
-        self.emit("rf_error:");
-        self.emit("  ret i8* null");
-        self.emit("rf_read:");
-        self.emit("  call i32 @fseek(i8* %rf_file, i64 0, i32 2)");
-        self.emit("  %rf_size = call i64 @ftell(i8* %rf_file)");
-        self.emit("  call i32 @fseek(i8* %rf_file, i64 0, i32 0)");
-        self.emit("  %rf_sz1 = add i64 %rf_size, 1");
-        self.emit("  %rf_buf = call i8* @malloc(i64 %rf_sz1)");
-        self.emit("  call i64 @fread(i8* %rf_buf, i64 1, i64 %rf_size, i8* %rf_file)");
-        self.emit("  %rf_np = getelementptr i8, i8* %rf_buf, i64 %rf_size");
-        self.emit("  store i8 0, i8* %rf_np");
-        self.emit("  call i32 @fclose(i8* %rf_file)");
+        self.emit("  %ra_file = call i8* @fopen(i8* %filename, i8* %ra_mode)");
+        self.emit("  %ra_buf0 = call i8* @malloc(i64 4096)");
+        self.emit("  %ra_null = icmp eq i8* %ra_file, null");
+        self.emit("  br i1 %ra_null, label %ra_exit, label %ra_loop");
+        self.emit("ra_loop:");
+        self.emit(
+            "  %ra_buf = phi i8* [ %ra_buf0, %ra_entry ], [ %ra_buf, %ra_more ], [ %ra_grown, %ra_grow ]",
+        );
+        self.emit(
+            "  %ra_cap = phi i64 [ 4096, %ra_entry ], [ %ra_cap, %ra_more ], [ %ra_dbl, %ra_grow ]",
+        );
+        self.emit(
+            "  %ra_len = phi i64 [ 0, %ra_entry ], [ %ra_nlen, %ra_more ], [ %ra_nlen, %ra_grow ]",
+        );
+        // Keep one byte free for the terminator
+        self.emit("  %ra_at = getelementptr i8, i8* %ra_buf, i64 %ra_len");
+        self.emit("  %ra_room0 = sub i64 %ra_cap, %ra_len");
+        self.emit("  %ra_room = sub i64 %ra_room0, 1");
+        self.emit("  %ra_got = call i64 @fread(i8* %ra_at, i64 1, i64 %ra_room, i8* %ra_file)");
+        self.emit("  %ra_end = icmp sle i64 %ra_got, 0");
+        self.emit("  br i1 %ra_end, label %ra_close, label %ra_more");
+        self.emit("ra_more:");
+        self.emit("  %ra_nlen = add i64 %ra_len, %ra_got");
+        self.emit("  %ra_left = sub i64 %ra_cap, %ra_nlen");
+        self.emit("  %ra_full = icmp sle i64 %ra_left, 1");
+        self.emit("  br i1 %ra_full, label %ra_grow, label %ra_loop");
+        self.emit("ra_grow:");
+        self.emit("  %ra_dbl = mul i64 %ra_cap, 2");
+        self.emit("  %ra_grown = call i8* @realloc(i8* %ra_buf, i64 %ra_dbl)");
+        self.emit("  br label %ra_loop");
+        self.emit("ra_close:");
+        self.emit("  call i32 @fclose(i8* %ra_file)");
+        self.emit("  br label %ra_exit");
+        self.emit("ra_exit:");
+        self.emit("  %ra_rbuf = phi i8* [ %ra_buf0, %ra_entry ], [ %ra_buf, %ra_close ]");
+        self.emit("  %ra_rlen = phi i64 [ 0, %ra_entry ], [ %ra_len, %ra_close ]");
+        self.emit("  %ra_np = getelementptr i8, i8* %ra_rbuf, i64 %ra_rlen");
+        self.emit("  store i8 0, i8* %ra_np");
+        self.emit("  store i64 %ra_rlen, i64* %len_out");
+        self.emit("  ret i8* %ra_rbuf");
+        self.emit("}");
+        self.emit("");
+
+        // read_file(path): the whole file, or "" if it can't be opened or read
+        self.emit("define i8* @read_file_impl(i8* %filename) {");
+        self.emit("  %rf_len = alloca i64");
+        self.emit("  %rf_buf = call i8* @brn_read_all(i8* %filename, i64* %rf_len)");
         self.emit("  ret i8* %rf_buf");
         self.emit("}");
         self.emit("");
 
+        self.emit_line_reader();
+
+        // Open `filename` with `mode` ("w" truncates, "a" appends) and write
+        // `len` bytes of `buf`; 1 on success, 0 if the file can't be opened
+        self.emit("define i32 @brn_write_to(i8* %filename, i8* %buf, i64 %len, i8* %mode) {");
+        self.emit("  %wt_file = call i8* @fopen(i8* %filename, i8* %mode)");
+        self.emit("  %wt_null = icmp eq i8* %wt_file, null");
+        self.emit("  br i1 %wt_null, label %wt_error, label %wt_write");
+        self.emit("wt_error:");
+        self.emit("  ret i32 0");
+        self.emit("wt_write:");
+        self.emit("  call i64 @fwrite(i8* %buf, i64 1, i64 %len, i8* %wt_file)");
+        self.emit("  call i32 @fclose(i8* %wt_file)");
+        self.emit("  ret i32 1");
+        self.emit("}");
+        self.emit("");
+
         self.emit("define i32 @write_file_impl(i8* %filename, i8* %content) {");
         self.emit(
             "  %wf_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.w, i64 0, i64 0",
         );
-        self.emit("  %wf_file = call i8* @fopen(i8* %filename, i8* %wf_mode)");
-        self.emit("  %wf_null = icmp eq i8* %wf_file, null");
-        self.emit("  br i1 %wf_null, label %wf_error, label %wf_write");
-        self.emit("wf_error:");
-        self.emit("  ret i32 0");
-        self.emit("wf_write:");
         self.emit("  %wf_len = call i64 @strlen(i8* %content)");
-        self.emit("  call i64 @fwrite(i8* %content, i64 1, i64 %wf_len, i8* %wf_file)");
-        self.emit("  call i32 @fclose(i8* %wf_file)");
-        self.emit("  ret i32 1");
+        self.emit("  %wf_r = call i32 @brn_write_to(i8* %filename, i8* %content, i64 %wf_len, i8* %wf_mode)");
+        self.emit("  ret i32 %wf_r");
         self.emit("}");
         self.emit("");
 
-        // read_input(): reads one line from stdin, strips \r\n, returns i8*
-        self.emit("define i8* @read_input_impl() {");
-        self.emit("  %ri_buf = call i8* @malloc(i64 256)");
-        self.emit("  %ri_stdin = call i8* @GetStdHandle(i32 -10)");
-        self.emit("  %ri_read = alloca i32");
-        self.emit("  store i32 0, i32* %ri_read");
+        self.emit("define i32 @append_file_impl(i8* %filename, i8* %content) {");
         self.emit(
-            "  call i32 @ReadFile(i8* %ri_stdin, i8* %ri_buf, i32 254, i32* %ri_read, i8* null)",
+            "  %ap_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.a, i64 0, i64 0",
         );
-        self.emit("  %ri_n32 = load i32, i32* %ri_read");
-        self.emit("  %ri_n = sext i32 %ri_n32 to i64");
-        // null-terminate at n
-        self.emit("  %ri_endp = getelementptr i8, i8* %ri_buf, i64 %ri_n");
-        self.emit("  store i8 0, i8* %ri_endp");
-        // strip trailing \n (char 10)
-        self.emit("  %ri_has = icmp sgt i64 %ri_n, 0");
-        self.emit("  br i1 %ri_has, label %ri_chk_n, label %ri_done");
-        self.emit("ri_chk_n:");
-        self.emit("  %ri_n1 = sub i64 %ri_n, 1");
-        self.emit("  %ri_p1 = getelementptr i8, i8* %ri_buf, i64 %ri_n1");
-        self.emit("  %ri_c1 = load i8, i8* %ri_p1");
-        self.emit("  %ri_is_n = icmp eq i8 %ri_c1, 10");
-        self.emit("  br i1 %ri_is_n, label %ri_strip_n, label %ri_chk_r");
-        self.emit("ri_strip_n:");
-        self.emit("  store i8 0, i8* %ri_p1");
-        // now check for \r before it
-        self.emit("  %ri_has2 = icmp sgt i64 %ri_n1, 0");
-        self.emit("  br i1 %ri_has2, label %ri_chk_r, label %ri_done");
-        self.emit("ri_chk_r:");
-        self.emit("  %ri_n2 = sub i64 %ri_n1, 1");
-        self.emit("  %ri_p2 = getelementptr i8, i8* %ri_buf, i64 %ri_n2");
-        self.emit("  %ri_c2 = load i8, i8* %ri_p2");
-        self.emit("  %ri_is_r = icmp eq i8 %ri_c2, 13");
-        self.emit("  br i1 %ri_is_r, label %ri_strip_r, label %ri_done");
-        self.emit("ri_strip_r:");
-        self.emit("  store i8 0, i8* %ri_p2");
-        self.emit("  br label %ri_done");
-        self.emit("ri_done:");
-        self.emit("  ret i8* %ri_buf");
+        self.emit("  %ap_len = call i64 @strlen(i8* %content)");
+        self.emit("  %ap_r = call i32 @brn_write_to(i8* %filename, i8* %content, i64 %ap_len, i8* %ap_mode)");
+        self.emit("  ret i32 %ap_r");
+        self.emit("}");
+        self.emit("");
+
+        // read_bytes(path): the file as a Vec with one element (0-255) per byte;
+        // an unreadable file gives an empty Vec
+        self.emit("define i8* @read_bytes_impl(i8* %filename) {");
+        self.emit("rb_read:");
+        self.emit("  %rb_len = alloca i64");
+        self.emit("  %rb_raw = call i8* @brn_read_all(i8* %filename, i64* %rb_len)");
+        self.emit("  %rb_n = load i64, i64* %rb_len");
+        self.emit("  %rb_small = icmp slt i64 %rb_n, 4");
+        self.emit("  %rb_cap = select i1 %rb_small, i64 4, i64 %rb_n");
+        self.emit("  %rb_hdr = call i8* @malloc(i64 24)");
+        self.emit("  %rb_lp = bitcast i8* %rb_hdr to i64*");
+        self.emit("  store i64 %rb_n, i64* %rb_lp");
+        self.emit("  %rb_cp_raw = getelementptr i8, i8* %rb_hdr, i64 8");
+        self.emit("  %rb_cp = bitcast i8* %rb_cp_raw to i64*");
+        self.emit("  store i64 %rb_cap, i64* %rb_cp");
+        self.emit("  %rb_bytes = mul i64 %rb_cap, 8");
+        self.emit("  %rb_data = call i8* @malloc(i64 %rb_bytes)");
+        self.emit("  %rb_dp_raw = getelementptr i8, i8* %rb_hdr, i64 16");
+        self.emit("  %rb_dp = bitcast i8* %rb_dp_raw to i8**");
+        self.emit("  store i8* %rb_data, i8** %rb_dp");
+        self.emit("  %rb_d64 = bitcast i8* %rb_data to i64*");
+        self.emit("  br label %rb_loop");
+        self.emit("rb_loop:");
+        self.emit("  %rb_i = phi i64 [ 0, %rb_read ], [ %rb_next, %rb_body ]");
+        self.emit("  %rb_done = icmp eq i64 %rb_i, %rb_n");
+        self.emit("  br i1 %rb_done, label %rb_exit, label %rb_body");
+        self.emit("rb_body:");
+        self.emit("  %rb_sp = getelementptr i8, i8* %rb_raw, i64 %rb_i");
+        self.emit("  %rb_b = load i8, i8* %rb_sp");
+        self.emit("  %rb_v = zext i8 %rb_b to i64");
+        self.emit("  %rb_ep = getelementptr i64, i64* %rb_d64, i64 %rb_i");
+        self.emit("  store i64 %rb_v, i64* %rb_ep");
+        self.emit("  %rb_next = add i64 %rb_i, 1");
+        self.emit("  br label %rb_loop");
+        self.emit("rb_exit:");
+        self.emit("  call void @free(i8* %rb_raw)");
+        self.emit("  ret i8* %rb_hdr");
+        self.emit("}");
+        self.emit("");
+
+        // write_bytes(path, v, len): the low byte of each of the first `len`
+        // elements of `v` (clamped to its length)
+        self.emit("define i32 @write_bytes_impl(i8* %filename, i8* %vec, i64 %len) {");
+        self.emit("wb_entry:");
+        self.emit("  %wb_lp = bitcast i8* %vec to i64*");
+        self.emit("  %wb_vlen = load i64, i64* %wb_lp");
+        self.emit("  %wb_over = icmp sgt i64 %len, %wb_vlen");
+        self.emit("  %wb_n0 = select i1 %wb_over, i64 %wb_vlen, i64 %len");
+        self.emit("  %wb_neg = icmp slt i64 %wb_n0, 0");
+        self.emit("  %wb_n = select i1 %wb_neg, i64 0, i64 %wb_n0");
+        self.emit("  %wb_raw = call i8* @malloc(i64 %wb_n)");
+        self.emit("  %wb_dp_raw = getelementptr i8, i8* %vec, i64 16");
+        self.emit("  %wb_dp = bitcast i8* %wb_dp_raw to i8**");
+        self.emit("  %wb_data = load i8*, i8** %wb_dp");
+        self.emit("  %wb_d64 = bitcast i8* %wb_data to i64*");
+        self.emit("  br label %wb_loop");
+        self.emit("wb_loop:");
+        self.emit("  %wb_i = phi i64 [ 0, %wb_entry ], [ %wb_next, %wb_body ]");
+        self.emit("  %wb_done = icmp eq i64 %wb_i, %wb_n");
+        self.emit("  br i1 %wb_done, label %wb_write, label %wb_body");
+        self.emit("wb_body:");
+        self.emit("  %wb_ep = getelementptr i64, i64* %wb_d64, i64 %wb_i");
+        self.emit("  %wb_v = load i64, i64* %wb_ep");
+        self.emit("  %wb_b = trunc i64 %wb_v to i8");
+        self.emit("  %wb_op = getelementptr i8, i8* %wb_raw, i64 %wb_i");
+        self.emit("  store i8 %wb_b, i8* %wb_op");
+        self.emit("  %wb_next = add i64 %wb_i, 1");
+        self.emit("  br label %wb_loop");
+        self.emit("wb_write:");
+        self.emit(
+            "  %wb_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.w, i64 0, i64 0",
+        );
+        self.emit(
+            "  %wb_r = call i32 @brn_write_to(i8* %filename, i8* %wb_raw, i64 %wb_n, i8* %wb_mode)",
+        );
+        self.emit("  call void @free(i8* %wb_raw)");
+        self.emit("  ret i32 %wb_r");
+        self.emit("}");
+        self.emit("");
+
+        // Directories: mkdir/remove_dir return 1 on success, 0 on failure;
+        // read_dir returns the entry names (without "." and "..") as a
+        // Vec<string>, empty if the directory can't be opened
+        self.emit("define i8* @brn_strdup(i8* %s) {");
+        self.emit("  %sd_len = call i64 @strlen(i8* %s)");
+        self.emit("  %sd_size = add i64 %sd_len, 1");
+        self.emit("  %sd_new = call i8* @malloc(i64 %sd_size)");
+        self.emit("  %sd_r = call i8* @strcpy(i8* %sd_new, i8* %s)");
+        self.emit("  ret i8* %sd_new");
+        self.emit("}");
+        self.emit("");
+
+        // Characters (code points) in a UTF-8 string: every byte except
+        // continuation bytes (0b10xxxxxx)
+        self.emit("define i64 @brn_char_count(i8* %s) {");
+        self.emit("cc_entry:");
+        self.emit("  br label %cc_loop");
+        self.emit("cc_loop:");
+        self.emit("  %cc_i = phi i64 [ 0, %cc_entry ], [ %cc_next, %cc_body ]");
+        self.emit("  %cc_n = phi i64 [ 0, %cc_entry ], [ %cc_n2, %cc_body ]");
+        self.emit("  %cc_p = getelementptr i8, i8* %s, i64 %cc_i");
+        self.emit("  %cc_c = load i8, i8* %cc_p");
+        self.emit("  %cc_end = icmp eq i8 %cc_c, 0");
+        self.emit("  br i1 %cc_end, label %cc_done, label %cc_body");
+        self.emit("cc_body:");
+        self.emit("  %cc_top = and i8 %cc_c, -64");
+        self.emit("  %cc_cont = icmp eq i8 %cc_top, -128");
+        self.emit("  %cc_add = select i1 %cc_cont, i64 0, i64 1");
+        self.emit("  %cc_n2 = add i64 %cc_n, %cc_add");
+        self.emit("  %cc_next = add i64 %cc_i, 1");
+        self.emit("  br label %cc_loop");
+        self.emit("cc_done:");
+        self.emit("  ret i64 %cc_n");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define i1 @brn_is_dot_entry(i8* %name) {");
+        self.emit("de_entry:");
+        self.emit("  %de_c0 = load i8, i8* %name");
+        self.emit("  %de_d0 = icmp eq i8 %de_c0, 46");
+        self.emit("  br i1 %de_d0, label %de_one, label %de_no");
+        self.emit("de_one:");
+        self.emit("  %de_p1 = getelementptr i8, i8* %name, i64 1");
+        self.emit("  %de_c1 = load i8, i8* %de_p1");
+        self.emit("  %de_end1 = icmp eq i8 %de_c1, 0");
+        self.emit("  br i1 %de_end1, label %de_yes, label %de_two");
+        self.emit("de_two:");
+        self.emit("  %de_d1 = icmp eq i8 %de_c1, 46");
+        self.emit("  br i1 %de_d1, label %de_three, label %de_no");
+        self.emit("de_three:");
+        self.emit("  %de_p2 = getelementptr i8, i8* %name, i64 2");
+        self.emit("  %de_c2 = load i8, i8* %de_p2");
+        self.emit("  %de_end2 = icmp eq i8 %de_c2, 0");
+        self.emit("  ret i1 %de_end2");
+        self.emit("de_yes:");
+        self.emit("  ret i1 true");
+        self.emit("de_no:");
+        self.emit("  ret i1 false");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @brn_push_dir_entry(i8* %vec, i8* %name) {");
+        self.emit("  %pe_dot = call i1 @brn_is_dot_entry(i8* %name)");
+        self.emit("  br i1 %pe_dot, label %pe_skip, label %pe_push");
+        self.emit("pe_push:");
+        self.emit("  %pe_copy = call i8* @brn_strdup(i8* %name)");
+        self.emit("  %pe_v = ptrtoint i8* %pe_copy to i64");
+        self.emit("  call void @vec_push_impl(i8* %vec, i64 %pe_v)");
+        self.push(Inst::Ret(None));
+        self.emit("pe_skip:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        if cfg!(target_os = "windows") {
+            self.emit("define i32 @mkdir_impl(i8* %path) {");
+            self.emit("  %md_r = call i32 @CreateDirectoryA(i8* %path, i8* null)");
+            self.emit("  %md_ok = icmp ne i32 %md_r, 0");
+            self.emit("  %md_ret = zext i1 %md_ok to i32");
+            self.emit("  ret i32 %md_ret");
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define i32 @remove_dir_impl(i8* %path) {");
+            self.emit("  %rd_r = call i32 @RemoveDirectoryA(i8* %path)");
+            self.emit("  %rd_ok = icmp ne i32 %rd_r, 0");
+            self.emit("  %rd_ret = zext i1 %rd_ok to i32");
+            self.emit("  ret i32 %rd_ret");
+            self.emit("}");
+            self.emit("");
+
+            // FindFirstFileA("<path>\\*"); cFileName sits at offset 44 of the
+            // 320-byte WIN32_FIND_DATAA
+            self.emit("define i8* @read_dir_impl(i8* %path) {");
+            self.emit("  %ld_vec = call i8* @vec_new_impl()");
+            self.emit("  %ld_plen = call i64 @strlen(i8* %path)");
+            self.emit("  %ld_psize = add i64 %ld_plen, 3");
+            self.emit("  %ld_pat = call i8* @malloc(i64 %ld_psize)");
+            self.emit("  %ld_r = call i8* @strcpy(i8* %ld_pat, i8* %path)");
+            self.emit("  %ld_s0 = getelementptr i8, i8* %ld_pat, i64 %ld_plen");
+            self.emit("  store i8 92, i8* %ld_s0");
+            self.emit("  %ld_i1 = add i64 %ld_plen, 1");
+            self.emit("  %ld_s1 = getelementptr i8, i8* %ld_pat, i64 %ld_i1");
+            self.emit("  store i8 42, i8* %ld_s1");
+            self.emit("  %ld_i2 = add i64 %ld_plen, 2");
+            self.emit("  %ld_s2 = getelementptr i8, i8* %ld_pat, i64 %ld_i2");
+            self.emit("  store i8 0, i8* %ld_s2");
+            self.emit("  %ld_fd = alloca [320 x i8]");
+            self.emit("  %ld_fdp = getelementptr [320 x i8], [320 x i8]* %ld_fd, i64 0, i64 0");
+            self.emit("  %ld_name = getelementptr [320 x i8], [320 x i8]* %ld_fd, i64 0, i64 44");
+            self.emit("  %ld_h = call i8* @FindFirstFileA(i8* %ld_pat, i8* %ld_fdp)");
+            self.emit("  call void @free(i8* %ld_pat)");
+            self.emit("  %ld_hi = ptrtoint i8* %ld_h to i64");
+            self.emit("  %ld_bad = icmp eq i64 %ld_hi, -1");
+            self.emit("  br i1 %ld_bad, label %ld_done, label %ld_loop");
+            self.emit("ld_loop:");
+            self.emit("  call void @brn_push_dir_entry(i8* %ld_vec, i8* %ld_name)");
+            self.emit("  %ld_more = call i32 @FindNextFileA(i8* %ld_h, i8* %ld_fdp)");
+            self.emit("  %ld_has = icmp ne i32 %ld_more, 0");
+            self.emit("  br i1 %ld_has, label %ld_loop, label %ld_close");
+            self.emit("ld_close:");
+            self.emit("  call i32 @FindClose(i8* %ld_h)");
+            self.emit("  br label %ld_done");
+            self.emit("ld_done:");
+            self.emit("  ret i8* %ld_vec");
+            self.emit("}");
+            self.emit("");
+        } else {
+            // SYS_mkdir (83), mode 0755
+            self.emit("define i32 @mkdir_impl(i8* %path) {");
+            self.emit("  %md_r = call i64 (i64, ...) @syscall(i64 83, i8* %path, i64 493)");
+            self.emit("  %md_ok = icmp eq i64 %md_r, 0");
+            self.emit("  %md_ret = zext i1 %md_ok to i32");
+            self.emit("  ret i32 %md_ret");
+            self.emit("}");
+            self.emit("");
+
+            // SYS_rmdir (84)
+            self.emit("define i32 @remove_dir_impl(i8* %path) {");
+            self.emit("  %rd_r = call i64 (i64, ...) @syscall(i64 84, i8* %path)");
+            self.emit("  %rd_ok = icmp eq i64 %rd_r, 0");
+            self.emit("  %rd_ret = zext i1 %rd_ok to i32");
+            self.emit("  ret i32 %rd_ret");
+            self.emit("}");
+            self.emit("");
+
+            // open(O_RDONLY|O_DIRECTORY), then SYS_getdents64 (217) until it
+            // returns 0; each linux_dirent64 has d_reclen at 16, d_name at 19
+            self.emit("define i8* @read_dir_impl(i8* %path) {");
+            self.emit("ld_entry:");
+            self.emit("  %ld_vec = call i8* @vec_new_impl()");
+            self.emit(
+                "  %ld_fd = call i64 (i64, ...) @syscall(i64 2, i8* %path, i64 65536, i64 0)",
+            );
+            self.emit("  %ld_bad = icmp slt i64 %ld_fd, 0");
+            self.emit("  br i1 %ld_bad, label %ld_done, label %ld_open");
+            self.emit("ld_open:");
+            self.emit("  %ld_buf = call i8* @malloc(i64 4096)");
+            self.emit("  br label %ld_fill");
+            self.emit("ld_fill:");
+            self.emit(
+                "  %ld_n = call i64 (i64, ...) @syscall(i64 217, i64 %ld_fd, i8* %ld_buf, i64 4096)",
+            );
+            self.emit("  %ld_end = icmp sle i64 %ld_n, 0");
+            self.emit("  br i1 %ld_end, label %ld_close, label %ld_walk");
+            self.emit("ld_walk:");
+            self.emit("  %ld_pos = phi i64 [ 0, %ld_fill ], [ %ld_next, %ld_walk ]");
+            self.emit("  %ld_rec = getelementptr i8, i8* %ld_buf, i64 %ld_pos");
+            self.emit("  %ld_rlp_raw = getelementptr i8, i8* %ld_rec, i64 16");
+            self.emit("  %ld_rlp = bitcast i8* %ld_rlp_raw to i16*");
+            self.emit("  %ld_rl16 = load i16, i16* %ld_rlp");
+            self.emit("  %ld_rl = zext i16 %ld_rl16 to i64");
+            self.emit("  %ld_name = getelementptr i8, i8* %ld_rec, i64 19");
+            self.emit("  call void @brn_push_dir_entry(i8* %ld_vec, i8* %ld_name)");
+            self.emit("  %ld_next = add i64 %ld_pos, %ld_rl");
+            self.emit("  %ld_more = icmp slt i64 %ld_next, %ld_n");
+            self.emit("  br i1 %ld_more, label %ld_walk, label %ld_fill");
+            self.emit("ld_close:");
+            self.emit("  call void @free(i8* %ld_buf)");
+            self.emit("  call i64 (i64, ...) @syscall(i64 3, i64 %ld_fd)");
+            self.emit("  br label %ld_done");
+            self.emit("ld_done:");
+            self.emit("  ret i8* %ld_vec");
+            self.emit("}");
+            self.emit("");
+        }
+
+        // Processes: run(cmd) hands `cmd` to the system shell and returns its
+        // exit code (-1 if it could not be started); run_capture(cmd) returns
+        // what it wrote to stdout instead
+        if cfg!(target_os = "windows") {
+            // "cmd.exe /c <cmd>"; CreateProcessA may write to the command line,
+            // so it must not be a constant
+            self.emit("define i8* @brn_cmdline(i8* %cmd) {");
+            self.emit(
+                "  %cl_pre = getelementptr inbounds [12 x i8], [12 x i8]* @.str.shell, i64 0, i64 0",
+            );
+            self.emit("  %cl_len = call i64 @strlen(i8* %cmd)");
+            self.emit("  %cl_size = add i64 %cl_len, 12");
+            self.emit("  %cl_buf = call i8* @malloc(i64 %cl_size)");
+            self.emit("  %cl_r1 = call i8* @strcpy(i8* %cl_buf, i8* %cl_pre)");
+            self.emit("  %cl_tail = getelementptr i8, i8* %cl_buf, i64 11");
+            self.emit("  %cl_r2 = call i8* @strcpy(i8* %cl_tail, i8* %cmd)");
+            self.emit("  ret i8* %cl_buf");
+            self.emit("}");
+            self.emit("");
+
+            // Start the child; with a non-null `out` its stdout goes there.
+            // Returns the process handle, or null if it could not be started.
+            // STARTUPINFOA is 104 bytes (dwFlags @60, std handles @80/88/96),
+            // PROCESS_INFORMATION 24 (hProcess @0, hThread @8).
+            self.emit("define i8* @brn_spawn(i8* %cmd, i8* %out) {");
+            self.emit("sp_entry:");
+            self.emit("  %sp_line = call i8* @brn_cmdline(i8* %cmd)");
+            self.emit("  %sp_si = alloca [104 x i8]");
+            self.emit("  store [104 x i8] zeroinitializer, [104 x i8]* %sp_si");
+            self.emit("  %sp_sip = getelementptr [104 x i8], [104 x i8]* %sp_si, i64 0, i64 0");
+            self.emit("  %sp_cb = bitcast i8* %sp_sip to i32*");
+            self.emit("  store i32 104, i32* %sp_cb");
+            self.emit("  %sp_pi = alloca [24 x i8]");
+            self.emit("  store [24 x i8] zeroinitializer, [24 x i8]* %sp_pi");
+            self.emit("  %sp_pip = getelementptr [24 x i8], [24 x i8]* %sp_pi, i64 0, i64 0");
+            self.emit("  %sp_redirect = icmp ne i8* %out, null");
+            self.emit("  br i1 %sp_redirect, label %sp_std, label %sp_create");
+            self.emit("sp_std:");
+            self.emit("  %sp_fl_raw = getelementptr i8, i8* %sp_sip, i64 60");
+            self.emit("  %sp_fl = bitcast i8* %sp_fl_raw to i32*");
+            self.emit("  store i32 256, i32* %sp_fl");
+            self.emit("  %sp_in_raw = getelementptr i8, i8* %sp_sip, i64 80");
+            self.emit("  %sp_in = bitcast i8* %sp_in_raw to i8**");
+            self.emit("  %sp_hin = call i8* @GetStdHandle(i32 -10)");
+            self.emit("  store i8* %sp_hin, i8** %sp_in");
+            self.emit("  %sp_o_raw = getelementptr i8, i8* %sp_sip, i64 88");
+            self.emit("  %sp_o = bitcast i8* %sp_o_raw to i8**");
+            self.emit("  store i8* %out, i8** %sp_o");
+            self.emit("  %sp_e_raw = getelementptr i8, i8* %sp_sip, i64 96");
+            self.emit("  %sp_e = bitcast i8* %sp_e_raw to i8**");
+            self.emit("  %sp_herr = call i8* @GetStdHandle(i32 -12)");
+            self.emit("  store i8* %sp_herr, i8** %sp_e");
+            self.emit("  br label %sp_create");
+            self.emit("sp_create:");
+            self.emit("  %sp_inherit = zext i1 %sp_redirect to i32");
+            self.emit("  %sp_ok = call i32 @CreateProcessA(i8* null, i8* %sp_line, i8* null, i8* null, i32 %sp_inherit, i32 0, i8* null, i8* null, i8* %sp_sip, i8* %sp_pip)");
+            self.emit("  call void @free(i8* %sp_line)");
+            self.emit("  %sp_failed = icmp eq i32 %sp_ok, 0");
+            self.emit("  br i1 %sp_failed, label %sp_fail, label %sp_started");
+            self.emit("sp_fail:");
+            self.emit("  ret i8* null");
+            self.emit("sp_started:");
+            self.emit("  %sp_hp = bitcast i8* %sp_pip to i8**");
+            self.emit("  %sp_proc = load i8*, i8** %sp_hp");
+            self.emit("  %sp_ht_raw = getelementptr i8, i8* %sp_pip, i64 8");
+            self.emit("  %sp_ht = bitcast i8* %sp_ht_raw to i8**");
+            self.emit("  %sp_thread = load i8*, i8** %sp_ht");
+            self.emit("  call i32 @CloseHandle(i8* %sp_thread)");
+            self.emit("  ret i8* %sp_proc");
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define i64 @brn_wait_exit(i8* %proc) {");
+            self.emit("  call i32 @WaitForSingleObject(i8* %proc, i32 -1)");
+            self.emit("  %we_code = alloca i32");
+            self.emit("  store i32 -1, i32* %we_code");
+            self.emit("  call i32 @GetExitCodeProcess(i8* %proc, i32* %we_code)");
+            self.emit("  call i32 @CloseHandle(i8* %proc)");
+            self.emit("  %we_c32 = load i32, i32* %we_code");
+            self.emit("  %we_c64 = sext i32 %we_c32 to i64");
+            self.emit("  ret i64 %we_c64");
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define i64 @run_impl(i8* %cmd) {");
+            self.emit("  %rn_proc = call i8* @brn_spawn(i8* %cmd, i8* null)");
+            self.emit("  %rn_fail = icmp eq i8* %rn_proc, null");
+            self.emit("  br i1 %rn_fail, label %rn_err, label %rn_wait");
+            self.emit("rn_err:");
+            self.emit("  ret i64 -1");
+            self.emit("rn_wait:");
+            self.emit("  %rn_code = call i64 @brn_wait_exit(i8* %rn_proc)");
+            self.emit("  ret i64 %rn_code");
+            self.emit("}");
+            self.emit("");
+
+            // Inheritable pipe (SECURITY_ATTRIBUTES {24, null, TRUE}); the
+            // read end is kept out of the child
+            self.emit("define i8* @run_capture_impl(i8* %cmd) {");
+            self.emit("rcp_entry:");
+            self.emit("  %rcp_sb = call i8* @sb_new_impl()");
+            self.emit("  %rcp_sa = alloca [24 x i8]");
+            self.emit("  store [24 x i8] zeroinitializer, [24 x i8]* %rcp_sa");
+            self.emit("  %rcp_sap = getelementptr [24 x i8], [24 x i8]* %rcp_sa, i64 0, i64 0");
+            self.emit("  %rcp_len = bitcast i8* %rcp_sap to i32*");
+            self.emit("  store i32 24, i32* %rcp_len");
+            self.emit("  %rcp_inh_raw = getelementptr i8, i8* %rcp_sap, i64 16");
+            self.emit("  %rcp_inh = bitcast i8* %rcp_inh_raw to i32*");
+            self.emit("  store i32 1, i32* %rcp_inh");
+            self.emit("  %rcp_rp = alloca i8*");
+            self.emit("  %rcp_wp = alloca i8*");
+            self.emit("  %rcp_piped = call i32 @CreatePipe(i8** %rcp_rp, i8** %rcp_wp, i8* %rcp_sap, i32 0)");
+            self.emit("  %rcp_nopipe = icmp eq i32 %rcp_piped, 0");
+            self.emit("  br i1 %rcp_nopipe, label %rcp_done, label %rcp_spawn");
+            self.emit("rcp_spawn:");
+            self.emit("  %rcp_r = load i8*, i8** %rcp_rp");
+            self.emit("  %rcp_w = load i8*, i8** %rcp_wp");
+            self.emit("  call i32 @SetHandleInformation(i8* %rcp_r, i32 1, i32 0)");
+            self.emit("  %rcp_proc = call i8* @brn_spawn(i8* %cmd, i8* %rcp_w)");
+            self.emit("  call i32 @CloseHandle(i8* %rcp_w)");
+            self.emit("  %rcp_chunk = call i8* @malloc(i64 4097)");
+            self.emit("  %rcp_got = alloca i32");
+            self.emit("  br label %rcp_read");
+            self.emit("rcp_read:");
+            self.emit("  store i32 0, i32* %rcp_got");
+            self.emit("  %rcp_ok = call i32 @ReadFile(i8* %rcp_r, i8* %rcp_chunk, i32 4096, i32* %rcp_got, i8* null)");
+            self.emit("  %rcp_n32 = load i32, i32* %rcp_got");
+            self.emit("  %rcp_fail = icmp eq i32 %rcp_ok, 0");
+            self.emit("  %rcp_eof = icmp eq i32 %rcp_n32, 0");
+            self.emit("  %rcp_stop = or i1 %rcp_fail, %rcp_eof");
+            self.emit("  br i1 %rcp_stop, label %rcp_close, label %rcp_append");
+            self.emit("rcp_append:");
+            self.emit("  %rcp_n = sext i32 %rcp_n32 to i64");
+            self.emit("  %rcp_end = getelementptr i8, i8* %rcp_chunk, i64 %rcp_n");
+            self.emit("  store i8 0, i8* %rcp_end");
+            self.emit("  call void @sb_append_impl(i8* %rcp_sb, i8* %rcp_chunk)");
+            self.emit("  br label %rcp_read");
+            self.emit("rcp_close:");
+            self.emit("  call void @free(i8* %rcp_chunk)");
+            self.emit("  call i32 @CloseHandle(i8* %rcp_r)");
+            self.emit("  %rcp_started = icmp ne i8* %rcp_proc, null");
+            self.emit("  br i1 %rcp_started, label %rcp_wait, label %rcp_done");
+            self.emit("rcp_wait:");
+            self.emit("  call i64 @brn_wait_exit(i8* %rcp_proc)");
+            self.emit("  br label %rcp_done");
+            self.emit("rcp_done:");
+            self.emit("  %rcp_out = call i8* @sb_finish_impl(i8* %rcp_sb)");
+            self.emit("  ret i8* %rcp_out");
+            self.emit("}");
+            self.emit("");
+        } else {
+            self.emit("@environ = external global i8**");
+            self.emit("");
+
+            // SYS_fork (57); the child points stdout at `out_fd` (SYS_dup2, 33)
+            // unless it is -1, then SYS_execve (59) "/bin/sh -c <cmd>", exiting
+            // 127 if that fails. Returns the child's pid, negative on failure.
+            self.emit("define i64 @brn_spawn(i8* %cmd, i64 %out_fd) {");
+            self.emit("  %sp_pid = call i64 (i64, ...) @syscall(i64 57)");
+            self.emit("  %sp_child = icmp eq i64 %sp_pid, 0");
+            self.emit("  br i1 %sp_child, label %sp_in_child, label %sp_parent");
+            self.emit("sp_parent:");
+            self.emit("  ret i64 %sp_pid");
+            self.emit("sp_in_child:");
+            self.emit("  %sp_redirect = icmp sge i64 %out_fd, 0");
+            self.emit("  br i1 %sp_redirect, label %sp_dup, label %sp_exec");
+            self.emit("sp_dup:");
+            self.emit("  call i64 (i64, ...) @syscall(i64 33, i64 %out_fd, i64 1)");
+            self.emit("  br label %sp_exec");
+            self.emit("sp_exec:");
+            self.emit(
+                "  %sp_sh = getelementptr inbounds [8 x i8], [8 x i8]* @.str.shell, i64 0, i64 0",
+            );
+            self.emit(
+                "  %sp_c = getelementptr inbounds [3 x i8], [3 x i8]* @.str.shell.c, i64 0, i64 0",
+            );
+            self.emit("  %sp_argv = alloca [4 x i8*]");
+            self.emit("  %sp_a0 = getelementptr [4 x i8*], [4 x i8*]* %sp_argv, i64 0, i64 0");
+            self.emit("  store i8* %sp_sh, i8** %sp_a0");
+            self.emit("  %sp_a1 = getelementptr [4 x i8*], [4 x i8*]* %sp_argv, i64 0, i64 1");
+            self.emit("  store i8* %sp_c, i8** %sp_a1");
+            self.emit("  %sp_a2 = getelementptr [4 x i8*], [4 x i8*]* %sp_argv, i64 0, i64 2");
+            self.emit("  store i8* %cmd, i8** %sp_a2");
+            self.emit("  %sp_a3 = getelementptr [4 x i8*], [4 x i8*]* %sp_argv, i64 0, i64 3");
+            self.emit("  store i8* null, i8** %sp_a3");
+            self.emit("  %sp_env = load i8**, i8*** @environ");
+            self.emit(
+                "  call i64 (i64, ...) @syscall(i64 59, i8* %sp_sh, i8** %sp_a0, i8** %sp_env)",
+            );
+            self.emit("  call void @brn_exit(i32 127)");
+            self.push(Inst::Unreachable);
+            self.emit("}");
+            self.emit("");
+
+            // SYS_wait4 (61); a child killed by a signal reports 128 + signal
+            self.emit("define i64 @brn_wait_exit(i64 %pid) {");
+            self.emit("  %we_st = alloca i32");
+            self.emit("  store i32 0, i32* %we_st");
+            self.emit(
+                "  call i64 (i64, ...) @syscall(i64 61, i64 %pid, i32* %we_st, i64 0, i8* null)",
+            );
+            self.emit("  %we_s = load i32, i32* %we_st");
+            self.emit("  %we_sig = and i32 %we_s, 127");
+            self.emit("  %we_exited = icmp eq i32 %we_sig, 0");
+            self.emit("  %we_hi = lshr i32 %we_s, 8");
+            self.emit("  %we_code = and i32 %we_hi, 255");
+            self.emit("  %we_killed = add i32 %we_sig, 128");
+            self.emit("  %we_r = select i1 %we_exited, i32 %we_code, i32 %we_killed");
+            self.emit("  %we_r64 = sext i32 %we_r to i64");
+            self.emit("  ret i64 %we_r64");
+            self.emit("}");
+            self.emit("");
+
+            self.emit("define i64 @run_impl(i8* %cmd) {");
+            self.emit("  %rn_pid = call i64 @brn_spawn(i8* %cmd, i64 -1)");
+            self.emit("  %rn_fail = icmp slt i64 %rn_pid, 0");
+            self.emit("  br i1 %rn_fail, label %rn_err, label %rn_wait");
+            self.emit("rn_err:");
+            self.emit("  ret i64 -1");
+            self.emit("rn_wait:");
+            self.emit("  %rn_code = call i64 @brn_wait_exit(i64 %rn_pid)");
+            self.emit("  ret i64 %rn_code");
+            self.emit("}");
+            self.emit("");
+
+            // SYS_pipe2 (293) with O_CLOEXEC so only the child's dup'd stdout
+            // survives execve
+            self.emit("define i8* @run_capture_impl(i8* %cmd) {");
+            self.emit("rcp_entry:");
+            self.emit("  %rcp_sb = call i8* @sb_new_impl()");
+            self.emit("  %rcp_fds = alloca [2 x i32]");
+            self.emit("  %rcp_fdp = getelementptr [2 x i32], [2 x i32]* %rcp_fds, i64 0, i64 0");
+            self.emit(
+                "  %rcp_piped = call i64 (i64, ...) @syscall(i64 293, i32* %rcp_fdp, i64 524288)",
+            );
+            self.emit("  %rcp_nopipe = icmp slt i64 %rcp_piped, 0");
+            self.emit("  br i1 %rcp_nopipe, label %rcp_done, label %rcp_spawn");
+            self.emit("rcp_spawn:");
+            self.emit("  %rcp_r32 = load i32, i32* %rcp_fdp");
+            self.emit("  %rcp_wp = getelementptr [2 x i32], [2 x i32]* %rcp_fds, i64 0, i64 1");
+            self.emit("  %rcp_w32 = load i32, i32* %rcp_wp");
+            self.emit("  %rcp_r = sext i32 %rcp_r32 to i64");
+            self.emit("  %rcp_w = sext i32 %rcp_w32 to i64");
+            self.emit("  %rcp_pid = call i64 @brn_spawn(i8* %cmd, i64 %rcp_w)");
+            self.emit("  call i64 (i64, ...) @syscall(i64 3, i64 %rcp_w)");
+            self.emit("  %rcp_chunk = call i8* @malloc(i64 4097)");
+            self.emit("  br label %rcp_read");
+            self.emit("rcp_read:");
+            self.emit(
+                "  %rcp_n = call i64 (i64, ...) @syscall(i64 0, i64 %rcp_r, i8* %rcp_chunk, i64 4096)",
+            );
+            self.emit("  %rcp_stop = icmp sle i64 %rcp_n, 0");
+            self.emit("  br i1 %rcp_stop, label %rcp_close, label %rcp_append");
+            self.emit("rcp_append:");
+            self.emit("  %rcp_end = getelementptr i8, i8* %rcp_chunk, i64 %rcp_n");
+            self.emit("  store i8 0, i8* %rcp_end");
+            self.emit("  call void @sb_append_impl(i8* %rcp_sb, i8* %rcp_chunk)");
+            self.emit("  br label %rcp_read");
+            self.emit("rcp_close:");
+            self.emit("  call void @free(i8* %rcp_chunk)");
+            self.emit("  call i64 (i64, ...) @syscall(i64 3, i64 %rcp_r)");
+            self.emit("  %rcp_started = icmp sge i64 %rcp_pid, 0");
+            self.emit("  br i1 %rcp_started, label %rcp_wait, label %rcp_done");
+            self.emit("rcp_wait:");
+            self.emit("  call i64 @brn_wait_exit(i64 %rcp_pid)");
+            self.emit("  br label %rcp_done");
+            self.emit("rcp_done:");
+            self.emit("  %rcp_out = call i8* @sb_finish_impl(i8* %rcp_sb)");
+            self.emit("  ret i8* %rcp_out");
+            self.emit("}");
+            self.emit("");
+        }
+
+        // sleep_ms(ms): block the calling thread; negative durations return at once
+        self.emit("define void @brn_sleep_ms(i64 %ms) {");
+        self.emit("  %slp_neg = icmp slt i64 %ms, 0");
+        self.emit("  br i1 %slp_neg, label %slp_done, label %slp_wait");
+        self.emit("slp_wait:");
+        if cfg!(target_os = "windows") {
+            self.emit("  %slp_ms32 = trunc i64 %ms to i32");
+            self.emit("  call void @Sleep(i32 %slp_ms32)");
+        } else {
+            // SYS_nanosleep (35) with struct timespec { i64 sec, i64 nsec }
+            self.emit("  %slp_ts = alloca { i64, i64 }");
+            self.emit(
+                "  %slp_secp = getelementptr { i64, i64 }, { i64, i64 }* %slp_ts, i32 0, i32 0",
+            );
+            self.emit(
+                "  %slp_nsecp = getelementptr { i64, i64 }, { i64, i64 }* %slp_ts, i32 0, i32 1",
+            );
+            self.emit("  %slp_sec = sdiv i64 %ms, 1000");
+            self.emit("  %slp_rem = srem i64 %ms, 1000");
+            self.emit("  %slp_nsec = mul i64 %slp_rem, 1000000");
+            self.emit("  store i64 %slp_sec, i64* %slp_secp");
+            self.emit("  store i64 %slp_nsec, i64* %slp_nsecp");
+            self.emit(
+                "  call i64 (i64, ...) @syscall(i64 35, { i64, i64 }* %slp_ts, { i64, i64 }* null)",
+            );
+        }
+        self.emit("  br label %slp_done");
+        self.emit("slp_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // now(): the wall-clock time in whole seconds since 1970-01-01 UTC
+        self.emit("define i64 @brn_now() {");
+        if cfg!(target_os = "windows") {
+            // A FILETIME counts 100ns ticks since 1601-01-01
+            self.emit("  %now_ft = alloca i64");
+            self.emit("  call void @GetSystemTimeAsFileTime(i64* %now_ft)");
+            self.emit("  %now_ticks = load i64, i64* %now_ft");
+            self.emit("  %now_unix = sub i64 %now_ticks, 116444736000000000");
+            self.emit("  %now_sec = sdiv i64 %now_unix, 10000000");
+        } else {
+            // SYS_clock_gettime(CLOCK_REALTIME, &ts) — syscall 228
+            self.emit("  %now_ts = alloca { i64, i64 }");
+            self.emit("  call i64 (i64, ...) @syscall(i64 228, i64 0, { i64, i64 }* %now_ts)");
+            self.emit(
+                "  %now_secp = getelementptr { i64, i64 }, { i64, i64 }* %now_ts, i32 0, i32 0",
+            );
+            self.emit("  %now_sec = load i64, i64* %now_secp");
+        }
+        self.emit("  ret i64 %now_sec");
+        self.emit("}");
+        self.emit("");
+
+        self.emit_stdin_reader();
+
+        // Room for at least 4 elements, so doubling always grows it
+        self.emit("define i8* @vec_with_capacity_impl(i64 %cap) {");
+        self.emit("  %vw_small = icmp slt i64 %cap, 4");
+        self.emit("  %vw_cap = select i1 %vw_small, i64 4, i64 %cap");
+        self.emit("  %vw_hdr = call i8* @malloc(i64 24)");
+        self.emit("  %vw_lp = bitcast i8* %vw_hdr to i64*");
+        self.emit("  store i64 0, i64* %vw_lp");
+        self.emit("  %vw_cp_raw = getelementptr i8, i8* %vw_hdr, i64 8");
+        self.emit("  %vw_cp = bitcast i8* %vw_cp_raw to i64*");
+        self.emit("  store i64 %vw_cap, i64* %vw_cp");
+        self.emit("  %vw_bytes = mul i64 %vw_cap, 8");
+        self.emit("  %vw_buf = call i8* @malloc(i64 %vw_bytes)");
+        self.emit("  %vw_dp_raw = getelementptr i8, i8* %vw_hdr, i64 16");
+        self.emit("  %vw_dp = bitcast i8* %vw_dp_raw to i8**");
+        self.emit("  store i8* %vw_buf, i8** %vw_dp");
+        self.emit("  ret i8* %vw_hdr");
         self.emit("}");
         self.emit("");
 
         self.emit("define i8* @vec_new_impl() {");
-        self.emit("  %vn_hdr = call i8* @malloc(i64 24)");
-        self.emit("  %vn_lp = bitcast i8* %vn_hdr to i64*");
-        self.emit("  store i64 0, i64* %vn_lp");
-        self.emit("  %vn_cp_raw = getelementptr i8, i8* %vn_hdr, i64 8");
-        self.emit("  %vn_cp = bitcast i8* %vn_cp_raw to i64*");
-        self.emit("  store i64 4, i64* %vn_cp");
-        self.emit("  %vn_buf = call i8* @malloc(i64 32)");
-        self.emit("  %vn_dp_raw = getelementptr i8, i8* %vn_hdr, i64 16");
-        self.emit("  %vn_dp = bitcast i8* %vn_dp_raw to i8**");
-        self.emit("  store i8* %vn_buf, i8** %vn_dp");
+        self.emit("  %vn_hdr = call i8* @vec_with_capacity_impl(i64 4)");
         self.emit("  ret i8* %vn_hdr");
         self.emit("}");
         self.emit("");
 
+        // Make room for %need elements: at least double the capacity, so n
+        // pushes copy O(n) elements in all, and copy only the elements in
+        // use into the new buffer
+        self.emit("define void @brn_vec_grow(i8* %vec, i64 %need) {");
+        self.emit("gr_entry:");
+        self.emit("  %gr_cp_raw = getelementptr i8, i8* %vec, i64 8");
+        self.emit("  %gr_cap_ptr = bitcast i8* %gr_cp_raw to i64*");
+        self.emit("  %gr_cap = load i64, i64* %gr_cap_ptr");
+        self.emit("  %gr_enough = icmp sle i64 %need, %gr_cap");
+        self.emit("  br i1 %gr_enough, label %gr_done, label %gr_grow");
+        self.emit("gr_grow:");
+        self.emit("  %gr_dbl = mul i64 %gr_cap, 2");
+        self.emit("  %gr_more = icmp sgt i64 %need, %gr_dbl");
+        self.emit("  %gr_nc = select i1 %gr_more, i64 %need, i64 %gr_dbl");
+        self.emit("  %gr_nb = mul i64 %gr_nc, 8");
+        self.emit("  %gr_new = call i8* @malloc(i64 %gr_nb)");
+        self.emit("  %gr_lp = bitcast i8* %vec to i64*");
+        self.emit("  %gr_len = load i64, i64* %gr_lp");
+        self.emit("  %gr_used = mul i64 %gr_len, 8");
+        self.emit("  %gr_dp_raw = getelementptr i8, i8* %vec, i64 16");
+        self.emit("  %gr_dp = bitcast i8* %gr_dp_raw to i8**");
+        self.emit("  %gr_old = load i8*, i8** %gr_dp");
+        self.emit("  call void @brn_memcpy(i8* %gr_new, i8* %gr_old, i64 %gr_used)");
+        self.emit("  call void @free(i8* %gr_old)");
+        self.emit("  store i8* %gr_new, i8** %gr_dp");
+        self.emit("  store i64 %gr_nc, i64* %gr_cap_ptr");
+        self.emit("  br label %gr_done");
+        self.emit("gr_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
         self.emit("define void @vec_push_impl(i8* %vec, i64 %val) {");
+        self.emit("vp_entry:");
+        self.emit("  %vp_null = icmp eq i8* %vec, null");
+        self.emit("  br i1 %vp_null, label %vp_bad, label %vp_ok");
+        self.emit("vp_bad:");
+        self.emit(
+            "  %vp_msg = getelementptr inbounds [18 x i8], [18 x i8]* @.str.null_vec, i64 0, i64 0",
+        );
+        self.emit("  call void @brn_panic(i8* %vp_msg)");
+        self.push(Inst::Unreachable);
+        self.emit("vp_ok:");
         self.emit("  %vp_lp = bitcast i8* %vec to i64*");
         self.emit("  %vp_len = load i64, i64* %vp_lp");
         self.emit("  %vp_cp_raw = getelementptr i8, i8* %vec, i64 8");
         self.emit("  %vp_cap_ptr = bitcast i8* %vp_cp_raw to i64*");
         self.emit("  %vp_cap = load i64, i64* %vp_cap_ptr");
+        self.emit("  %vp_nl = add i64 %vp_len, 1");
         self.emit("  %vp_need = icmp eq i64 %vp_len, %vp_cap");
         self.emit("  br i1 %vp_need, label %vp_grow, label %vp_store");
         self.emit("vp_grow:");
-        self.emit("  %vp_nc = mul i64 %vp_cap, 2");
-        self.emit("  %vp_nb = mul i64 %vp_nc, 8");
-        self.emit("  %vp_dpp_raw = getelementptr i8, i8* %vec, i64 16");
-        self.emit("  %vp_dpp = bitcast i8* %vp_dpp_raw to i8**");
-        self.emit("  %vp_old = load i8*, i8** %vp_dpp");
-        self.emit("  %vp_new = call i8* @realloc(i8* %vp_old, i64 %vp_nb)");
-        self.emit("  store i8* %vp_new, i8** %vp_dpp");
-        self.emit("  store i64 %vp_nc, i64* %vp_cap_ptr");
+        self.emit("  call void @brn_vec_grow(i8* %vec, i64 %vp_nl)");
         self.emit("  br label %vp_store");
         self.emit("vp_store:");
         self.emit("  %vp_dp2_raw = getelementptr i8, i8* %vec, i64 16");
@@ -1077,13 +2687,39 @@ impl CodeGenerator {
         self.emit("  %vp_di64 = bitcast i8* %vp_data to i64*");
         self.emit("  %vp_elem = getelementptr i64, i64* %vp_di64, i64 %vp_len");
         self.emit("  store i64 %val, i64* %vp_elem");
-        self.emit("  %vp_nl = add i64 %vp_len, 1");
         self.emit("  store i64 %vp_nl, i64* %vp_lp");
-        self.emit("  ret void");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // Room for `extra` more elements without growing again
+        self.emit("define void @vec_reserve_impl(i8* %vec, i64 %extra) {");
+        self.emit("vrs_entry:");
+        self.emit("  %vrs_null = icmp eq i8* %vec, null");
+        self.emit("  br i1 %vrs_null, label %vrs_bad, label %vrs_ok");
+        self.emit("vrs_bad:");
+        self.emit(
+            "  %vrs_msg = getelementptr inbounds [18 x i8], [18 x i8]* @.str.null_vec, i64 0, i64 0",
+        );
+        self.emit("  call void @brn_panic(i8* %vrs_msg)");
+        self.push(Inst::Unreachable);
+        self.emit("vrs_ok:");
+        self.emit("  %vrs_lp = bitcast i8* %vec to i64*");
+        self.emit("  %vrs_len = load i64, i64* %vrs_lp");
+        self.emit("  %vrs_need = add i64 %vrs_len, %extra");
+        self.emit("  call void @brn_vec_grow(i8* %vec, i64 %vrs_need)");
+        self.push(Inst::Ret(None));
         self.emit("}");
         self.emit("");
 
+        if !self.options.no_checks {
+            self.emit_vec_check();
+        }
+
         self.emit("define i64 @vec_get_impl(i8* %vec, i64 %idx) {");
+        if !self.options.no_checks {
+            self.emit("  call void @brn_vec_check(i8* %vec, i64 %idx)");
+        }
         self.emit("  %vg_dp_raw = getelementptr i8, i8* %vec, i64 16");
         self.emit("  %vg_dp = bitcast i8* %vg_dp_raw to i8**");
         self.emit("  %vg_data = load i8*, i8** %vg_dp");
@@ -1095,1117 +2731,2165 @@ impl CodeGenerator {
         self.emit("");
 
         self.emit("define void @vec_set_impl(i8* %vec, i64 %idx, i64 %val) {");
+        if !self.options.no_checks {
+            self.emit("  call void @brn_vec_check(i8* %vec, i64 %idx)");
+        }
         self.emit("  %vs_dp_raw = getelementptr i8, i8* %vec, i64 16");
         self.emit("  %vs_dp = bitcast i8* %vs_dp_raw to i8**");
         self.emit("  %vs_data = load i8*, i8** %vs_dp");
         self.emit("  %vs_di64 = bitcast i8* %vs_data to i64*");
         self.emit("  %vs_ep = getelementptr i64, i64* %vs_di64, i64 %idx");
         self.emit("  store i64 %val, i64* %vs_ep");
-        self.emit("  ret void");
+        self.push(Inst::Ret(None));
         self.emit("}");
         self.emit("");
 
+        // A null Vec reads as empty
         self.emit("define i64 @vec_len_impl(i8* %vec) {");
+        self.emit("vl_entry:");
+        self.emit("  %vl_null = icmp eq i8* %vec, null");
+        self.emit("  br i1 %vl_null, label %vl_empty, label %vl_load");
+        self.emit("vl_empty:");
+        self.emit("  ret i64 0");
+        self.emit("vl_load:");
         self.emit("  %vl_lp = bitcast i8* %vec to i64*");
         self.emit("  %vl_len = load i64, i64* %vl_lp");
         self.emit("  ret i64 %vl_len");
         self.emit("}");
         self.emit("");
 
-        self.string_literals
-            .push((".str.mode.r".to_string(), "r".to_string()));
-        self.string_literals
-            .push((".str.mode.w".to_string(), "w".to_string()));
-    }
+        // vec_pop: remove and return the last element; 0 when empty
+        self.emit("define i64 @vec_pop_impl(i8* %vec) {");
+        self.emit("  %vpo_lp = bitcast i8* %vec to i64*");
+        self.emit("  %vpo_len = call i64 @vec_len_impl(i8* %vec)");
+        self.emit("  %vpo_empty = icmp eq i64 %vpo_len, 0");
+        self.emit("  br i1 %vpo_empty, label %vpo_none, label %vpo_take");
+        self.emit("vpo_none:");
+        self.emit("  ret i64 0");
+        self.emit("vpo_take:");
+        self.emit("  %vpo_last = sub i64 %vpo_len, 1");
+        self.emit("  %vpo_val = call i64 @vec_get_impl(i8* %vec, i64 %vpo_last)");
+        self.emit("  store i64 %vpo_last, i64* %vpo_lp");
+        self.emit("  ret i64 %vpo_val");
+        self.emit("}");
+        self.emit("");
 
-    fn emit_footer(&mut self) {
-        // Build the header block first, then prepend to output in one allocation
-        // instead of shifting the entire buffer on every string literal/struct decl.
-        let mut header = String::with_capacity(4096);
-        for decl in self.struct_decls.iter().rev() {
-            header.push_str(decl);
-            header.push('\n');
-        }
-        for (id, value) in &self.string_literals {
-            let len = value.len() + 1;
-            let escaped = self.escape_string(value);
-            header.push_str(&format!(
-                "@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\", align 1\n",
-                id, len, escaped
-            ));
-        }
-        header.push_str(&self.output);
-        self.output = header;
-    }
+        // vec_insert: index is clamped to 0..=len; later elements shift up
+        self.emit("define void @vec_insert_impl(i8* %vec, i64 %idx, i64 %val) {");
+        self.emit("vi_entry:");
+        self.emit("  %vi_len = call i64 @vec_len_impl(i8* %vec)");
+        self.emit("  %vi_neg = icmp slt i64 %idx, 0");
+        self.emit("  %vi_i0 = select i1 %vi_neg, i64 0, i64 %idx");
+        self.emit("  %vi_over = icmp sgt i64 %vi_i0, %vi_len");
+        self.emit("  %vi_at = select i1 %vi_over, i64 %vi_len, i64 %vi_i0");
+        // push grows the buffer; the pushed value is overwritten below
+        self.emit("  call void @vec_push_impl(i8* %vec, i64 %val)");
+        self.emit("  br label %vi_loop");
+        self.emit("vi_loop:");
+        self.emit("  %vi_i = phi i64 [ %vi_len, %vi_entry ], [ %vi_prev, %vi_shift ]");
+        self.emit("  %vi_done = icmp sle i64 %vi_i, %vi_at");
+        self.emit("  br i1 %vi_done, label %vi_store, label %vi_shift");
+        self.emit("vi_shift:");
+        self.emit("  %vi_prev = sub i64 %vi_i, 1");
+        self.emit("  %vi_v = call i64 @vec_get_impl(i8* %vec, i64 %vi_prev)");
+        self.emit("  call void @vec_set_impl(i8* %vec, i64 %vi_i, i64 %vi_v)");
+        self.emit("  br label %vi_loop");
+        self.emit("vi_store:");
+        self.emit("  call void @vec_set_impl(i8* %vec, i64 %vi_at, i64 %val)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-    fn gen_node(&mut self, node: &AstNode) -> String {
-        match node {
-            AstNode::Import { .. } => "0".to_string(),
+        // vec_remove: take the element at idx, shifting later ones down; an
+        // out-of-range index leaves the Vec alone and returns 0
+        self.emit("define i64 @vec_remove_impl(i8* %vec, i64 %idx) {");
+        self.emit("vr_entry:");
+        self.emit("  %vr_lp = bitcast i8* %vec to i64*");
+        self.emit("  %vr_len = call i64 @vec_len_impl(i8* %vec)");
+        self.emit("  %vr_neg = icmp slt i64 %idx, 0");
+        self.emit("  %vr_over = icmp sge i64 %idx, %vr_len");
+        self.emit("  %vr_bad = or i1 %vr_neg, %vr_over");
+        self.emit("  br i1 %vr_bad, label %vr_none, label %vr_take");
+        self.emit("vr_none:");
+        self.emit("  ret i64 0");
+        self.emit("vr_take:");
+        self.emit("  %vr_val = call i64 @vec_get_impl(i8* %vec, i64 %idx)");
+        self.emit("  %vr_last = sub i64 %vr_len, 1");
+        self.emit("  br label %vr_loop");
+        self.emit("vr_loop:");
+        self.emit("  %vr_i = phi i64 [ %idx, %vr_take ], [ %vr_next, %vr_shift ]");
+        self.emit("  %vr_done = icmp sge i64 %vr_i, %vr_last");
+        self.emit("  br i1 %vr_done, label %vr_exit, label %vr_shift");
+        self.emit("vr_shift:");
+        self.emit("  %vr_next = add i64 %vr_i, 1");
+        self.emit("  %vr_v = call i64 @vec_get_impl(i8* %vec, i64 %vr_next)");
+        self.emit("  call void @vec_set_impl(i8* %vec, i64 %vr_i, i64 %vr_v)");
+        self.emit("  br label %vr_loop");
+        self.emit("vr_exit:");
+        self.emit("  store i64 %vr_last, i64* %vr_lp");
+        self.emit("  ret i64 %vr_val");
+        self.emit("}");
+        self.emit("");
 
-            AstNode::StructDef { .. } => "0".to_string(),
+        self.emit("define void @vec_clear_impl(i8* %vec) {");
+        self.emit("vc_entry:");
+        self.emit("  %vc_null = icmp eq i8* %vec, null");
+        self.emit("  br i1 %vc_null, label %vc_done, label %vc_clear");
+        self.emit("vc_clear:");
+        self.emit("  %vc_lp = bitcast i8* %vec to i64*");
+        self.emit("  store i64 0, i64* %vc_lp");
+        self.emit("  br label %vc_done");
+        self.emit("vc_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-            AstNode::StructInit { name, fields } => {
-                let struct_fields = self.struct_types.get(name).cloned().unwrap_or_default();
-                let num_fields = struct_fields.len();
-
-                let stack_promote = self
-                    .current_binding
-                    .as_ref()
-                    .map(|b| self.non_escaping.contains(b))
-                    .unwrap_or(false);
-
-                let struct_ptr;
-                if stack_promote {
-                    struct_ptr = self.new_temp();
-                    self.emit(&format!("  {} = alloca %{}", struct_ptr, name));
-                } else {
-                    let size = (num_fields as i64) * 8;
-                    let raw_ptr = self.new_temp();
-                    struct_ptr = self.new_temp();
-                    self.emit(&format!("  {} = call i8* @malloc(i64 {})", raw_ptr, size));
-                    self.emit(&format!(
-                        "  {} = bitcast i8* {} to %{}*",
-                        struct_ptr, raw_ptr, name
-                    ));
-                }
+        // A string taken out of a Vec<string> (pop/remove), or a fresh ""
+        // when there was nothing to take
+        self.emit("define i8* @brn_taken_str(i64 %slot) {");
+        self.emit("  %ts_p = inttoptr i64 %slot to i8*");
+        self.emit("  %ts_none = icmp eq i8* %ts_p, null");
+        self.emit("  br i1 %ts_none, label %ts_empty, label %ts_some");
+        self.emit("ts_some:");
+        self.emit("  ret i8* %ts_p");
+        self.emit("ts_empty:");
+        self.emit("  %ts_e = call i8* @malloc(i64 1)");
+        self.emit("  store i8 0, i8* %ts_e");
+        self.emit("  ret i8* %ts_e");
+        self.emit("}");
+        self.emit("");
 
-                for (field_name, field_value) in fields.iter() {
-                    let val_reg = self.gen_node(field_value);
-                    let field_idx = struct_fields
-                        .iter()
-                        .position(|(n, _)| n == field_name)
-                        .unwrap_or(0);
-                    let field_type = struct_fields
-                        .get(field_idx)
-                        .map(|(_, t)| t.clone())
-                        .unwrap_or_else(|| "int".to_string());
-                    let llvm_field_type = self.type_to_llvm(&field_type);
+        // Free what each slot of a Vec<string> (or of structs) points to
+        self.emit("define void @vec_free_strings_impl(i8* %vec) {");
+        self.emit("vfs_entry:");
+        self.emit("  %vfs_len = call i64 @vec_len_impl(i8* %vec)");
+        self.emit("  br label %vfs_loop");
+        self.emit("vfs_loop:");
+        self.emit("  %vfs_i = phi i64 [ 0, %vfs_entry ], [ %vfs_next, %vfs_body ]");
+        self.emit("  %vfs_done = icmp eq i64 %vfs_i, %vfs_len");
+        self.emit("  br i1 %vfs_done, label %vfs_exit, label %vfs_body");
+        self.emit("vfs_body:");
+        self.emit("  %vfs_v = call i64 @vec_get_impl(i8* %vec, i64 %vfs_i)");
+        self.emit("  %vfs_p = inttoptr i64 %vfs_v to i8*");
+        self.emit("  call void @free(i8* %vfs_p)");
+        self.emit("  %vfs_next = add i64 %vfs_i, 1");
+        self.emit("  br label %vfs_loop");
+        self.emit("vfs_exit:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-                    let gep = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = getelementptr %{}, %{}* {}, i32 0, i32 {}",
-                        gep, name, name, struct_ptr, field_idx
-                    ));
-                    self.emit(&format!(
-                        "  store {} {}, {}* {}",
-                        llvm_field_type, val_reg, llvm_field_type, gep
-                    ));
-                }
+        // StringBuilder: same 24-byte header as Vec {len, cap, data}, but data
+        // holds bytes and is always NUL-terminated at data[len].
+        self.emit("define i8* @sb_new_impl() {");
+        self.emit("  %sn_hdr = call i8* @malloc(i64 24)");
+        self.emit("  %sn_lp = bitcast i8* %sn_hdr to i64*");
+        self.emit("  store i64 0, i64* %sn_lp");
+        self.emit("  %sn_cp_raw = getelementptr i8, i8* %sn_hdr, i64 8");
+        self.emit("  %sn_cp = bitcast i8* %sn_cp_raw to i64*");
+        self.emit("  store i64 32, i64* %sn_cp");
+        self.emit("  %sn_buf = call i8* @malloc(i64 32)");
+        self.emit("  store i8 0, i8* %sn_buf");
+        self.emit("  %sn_dp_raw = getelementptr i8, i8* %sn_hdr, i64 16");
+        self.emit("  %sn_dp = bitcast i8* %sn_dp_raw to i8**");
+        self.emit("  store i8* %sn_buf, i8** %sn_dp");
+        self.emit("  ret i8* %sn_hdr");
+        self.emit("}");
+        self.emit("");
 
-                struct_ptr
-            }
+        self.emit("define void @sb_append_impl(i8* %sb, i8* %s) {");
+        self.emit("  %sa_slen = call i64 @strlen(i8* %s)");
+        self.emit("  %sa_lp = bitcast i8* %sb to i64*");
+        self.emit("  %sa_len = load i64, i64* %sa_lp");
+        self.emit("  %sa_cp_raw = getelementptr i8, i8* %sb, i64 8");
+        self.emit("  %sa_cap_ptr = bitcast i8* %sa_cp_raw to i64*");
+        self.emit("  %sa_cap = load i64, i64* %sa_cap_ptr");
+        self.emit("  %sa_dp_raw = getelementptr i8, i8* %sb, i64 16");
+        self.emit("  %sa_dp = bitcast i8* %sa_dp_raw to i8**");
+        self.emit("  %sa_nl = add i64 %sa_len, %sa_slen");
+        self.emit("  %sa_need = add i64 %sa_nl, 1");
+        self.emit("  %sa_fits = icmp ule i64 %sa_need, %sa_cap");
+        self.emit("  br i1 %sa_fits, label %sa_copy, label %sa_grow");
+        self.emit("sa_grow:");
+        self.emit("  %sa_dbl = mul i64 %sa_cap, 2");
+        self.emit("  %sa_small = icmp ult i64 %sa_dbl, %sa_need");
+        self.emit("  %sa_nc = select i1 %sa_small, i64 %sa_need, i64 %sa_dbl");
+        self.emit("  %sa_old = load i8*, i8** %sa_dp");
+        self.emit("  %sa_new = call i8* @realloc(i8* %sa_old, i64 %sa_nc)");
+        self.emit("  store i8* %sa_new, i8** %sa_dp");
+        self.emit("  store i64 %sa_nc, i64* %sa_cap_ptr");
+        self.emit("  br label %sa_copy");
+        self.emit("sa_copy:");
+        self.emit("  %sa_data = load i8*, i8** %sa_dp");
+        self.emit("  %sa_dst = getelementptr i8, i8* %sa_data, i64 %sa_len");
+        self.emit("  %sa_r = call i8* @strcpy(i8* %sa_dst, i8* %s)");
+        self.emit("  store i64 %sa_nl, i64* %sa_lp");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-            AstNode::MemberAccess { object, field } => {
-                if let AstNode::Identifier { name: obj_name, .. } = object.as_ref() {
-                    if (self.guard_vars.contains(obj_name.as_str())
-                        || self
-                            .current_function_vars
-                            .get(obj_name.as_str())
-                            .map(|m| m.var_type.starts_with("MutexGuard<"))
-                            .unwrap_or(false))
-                        && field == "value"
-                        && !self.is_unsafe_fn
-                    {
-                        let guard_ptr = if let Some(meta) =
-                            self.current_function_vars.get(obj_name.as_str()).cloned()
-                        {
-                            if meta.llvm_name.starts_with("%arg_") {
-                                meta.llvm_name.clone()
-                            } else {
-                                let loaded = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = load i8*, i8** {}",
-                                    loaded, meta.llvm_name
-                                ));
-                                loaded
-                            }
-                        } else {
-                            obj_name.to_string()
-                        };
-                        let val_gep = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = getelementptr i8, i8* {}, i64 40",
-                            val_gep, guard_ptr
-                        ));
-                        let val_ptr = self.new_temp();
-                        self.emit(&format!("  {} = bitcast i8* {} to i64*", val_ptr, val_gep));
-                        let result = self.new_temp();
-                        // volatile load — prevents register caching across lock boundary
-                        self.emit(&format!(
-                            "  {} = load volatile i64, i64* {}",
-                            result, val_ptr
-                        ));
-                        return result;
-                    }
-                }
+        self.emit("define i8* @sb_to_string_impl(i8* %sb) {");
+        self.emit("  %ss_lp = bitcast i8* %sb to i64*");
+        self.emit("  %ss_len = load i64, i64* %ss_lp");
+        self.emit("  %ss_size = add i64 %ss_len, 1");
+        self.emit("  %ss_out = call i8* @malloc(i64 %ss_size)");
+        self.emit("  %ss_dp_raw = getelementptr i8, i8* %sb, i64 16");
+        self.emit("  %ss_dp = bitcast i8* %ss_dp_raw to i8**");
+        self.emit("  %ss_data = load i8*, i8** %ss_dp");
+        self.emit("  %ss_r = call i8* @strcpy(i8* %ss_out, i8* %ss_data)");
+        self.emit("  ret i8* %ss_out");
+        self.emit("}");
+        self.emit("");
 
-                let obj_reg = self.gen_node(object);
-                let struct_name = self.infer_struct_name(object);
+        self.emit("define void @brn_memcpy(i8* %dst, i8* %src, i64 %n) {");
+        self.emit("mc_entry:");
+        self.emit("  br label %mc_loop");
+        self.emit("mc_loop:");
+        self.emit("  %mc_i = phi i64 [ 0, %mc_entry ], [ %mc_next, %mc_body ]");
+        self.emit("  %mc_done = icmp sge i64 %mc_i, %n");
+        self.emit("  br i1 %mc_done, label %mc_exit, label %mc_body");
+        self.emit("mc_body:");
+        self.emit("  %mc_sp = getelementptr i8, i8* %src, i64 %mc_i");
+        self.emit("  %mc_c = load i8, i8* %mc_sp");
+        self.emit("  %mc_dp = getelementptr i8, i8* %dst, i64 %mc_i");
+        self.emit("  store i8 %mc_c, i8* %mc_dp");
+        self.emit("  %mc_next = add i64 %mc_i, 1");
+        self.emit("  br label %mc_loop");
+        self.emit("mc_exit:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-                if let Some(struct_fields) = self.struct_types.get(&struct_name).cloned() {
-                    if let Some(field_idx) = struct_fields.iter().position(|(n, _)| n == field) {
-                        let field_type = struct_fields[field_idx].1.clone();
-                        let llvm_field_type = self.type_to_llvm(&field_type);
+        // Bytes: the Vec header {len, cap, data} over a byte buffer of
+        // exactly len bytes, zero-filled on creation
+        self.emit("define i8* @bytes_new_impl(i64 %len) {");
+        self.emit("bn_entry:");
+        self.emit("  %bn_neg = icmp slt i64 %len, 0");
+        self.emit("  %bn_len = select i1 %bn_neg, i64 0, i64 %len");
+        self.emit("  %bn_hdr = call i8* @malloc(i64 24)");
+        self.emit("  %bn_lp = bitcast i8* %bn_hdr to i64*");
+        self.emit("  store i64 %bn_len, i64* %bn_lp");
+        self.emit("  %bn_cp_raw = getelementptr i8, i8* %bn_hdr, i64 8");
+        self.emit("  %bn_cp = bitcast i8* %bn_cp_raw to i64*");
+        self.emit("  store i64 %bn_len, i64* %bn_cp");
+        self.emit("  %bn_size = add i64 %bn_len, 1");
+        self.emit("  %bn_buf = call i8* @malloc(i64 %bn_size)");
+        self.emit("  %bn_dp_raw = getelementptr i8, i8* %bn_hdr, i64 16");
+        self.emit("  %bn_dp = bitcast i8* %bn_dp_raw to i8**");
+        self.emit("  store i8* %bn_buf, i8** %bn_dp");
+        self.emit("  br label %bn_loop");
+        self.emit("bn_loop:");
+        self.emit("  %bn_i = phi i64 [ 0, %bn_entry ], [ %bn_next, %bn_body ]");
+        self.emit("  %bn_done = icmp sge i64 %bn_i, %bn_len");
+        self.emit("  br i1 %bn_done, label %bn_exit, label %bn_body");
+        self.emit("bn_body:");
+        self.emit("  %bn_p = getelementptr i8, i8* %bn_buf, i64 %bn_i");
+        self.emit("  store i8 0, i8* %bn_p");
+        self.emit("  %bn_next = add i64 %bn_i, 1");
+        self.emit("  br label %bn_loop");
+        self.emit("bn_exit:");
+        self.emit("  ret i8* %bn_hdr");
+        self.emit("}");
+        self.emit("");
 
-                        let gep = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = getelementptr %{}, %{}* {}, i32 0, i32 {}",
-                            gep, struct_name, struct_name, obj_reg, field_idx
-                        ));
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = load {}, {}* {}",
-                            result, llvm_field_type, llvm_field_type, gep
-                        ));
-                        return result;
-                    }
-                }
-                "0".to_string()
-            }
+        // Address of byte idx, or null when idx is out of range
+        self.emit("define i8* @brn_bytes_at(i8* %b, i64 %idx) {");
+        self.emit("  %ba_lp = bitcast i8* %b to i64*");
+        self.emit("  %ba_len = load i64, i64* %ba_lp");
+        self.emit("  %ba_neg = icmp slt i64 %idx, 0");
+        self.emit("  %ba_over = icmp sge i64 %idx, %ba_len");
+        self.emit("  %ba_bad = or i1 %ba_neg, %ba_over");
+        self.emit("  br i1 %ba_bad, label %ba_none, label %ba_some");
+        self.emit("ba_none:");
+        self.emit("  ret i8* null");
+        self.emit("ba_some:");
+        self.emit("  %ba_dp_raw = getelementptr i8, i8* %b, i64 16");
+        self.emit("  %ba_dp = bitcast i8* %ba_dp_raw to i8**");
+        self.emit("  %ba_data = load i8*, i8** %ba_dp");
+        self.emit("  %ba_p = getelementptr i8, i8* %ba_data, i64 %idx");
+        self.emit("  ret i8* %ba_p");
+        self.emit("}");
+        self.emit("");
 
-            AstNode::EnumDef { name, variants, .. } => {
-                let variant_names: Vec<String> = variants.iter().map(|v| v.name.clone()).collect();
-                self.enum_types.insert(name.clone(), variant_names);
-                "0".to_string()
-            }
+        // Out-of-range reads give 0 and writes are ignored
+        self.emit("define i64 @bytes_get_impl(i8* %b, i64 %idx) {");
+        self.emit("  %bg_p = call i8* @brn_bytes_at(i8* %b, i64 %idx)");
+        self.emit("  %bg_none = icmp eq i8* %bg_p, null");
+        self.emit("  br i1 %bg_none, label %bg_zero, label %bg_load");
+        self.emit("bg_zero:");
+        self.emit("  ret i64 0");
+        self.emit("bg_load:");
+        self.emit("  %bg_c = load i8, i8* %bg_p");
+        self.emit("  %bg_v = zext i8 %bg_c to i64");
+        self.emit("  ret i64 %bg_v");
+        self.emit("}");
+        self.emit("");
 
-            AstNode::EnumValue {
-                enum_name,
-                variant,
-                value,
-            } => {
-                if enum_name == "Mutex" && variant == "new" {
-                    let inner_val = if let Some(v) = value {
-                        self.gen_node(v)
-                    } else {
-                        "0".to_string()
-                    };
-                    let mutex_raw = self.new_temp();
-                    self.emit(&format!("  {} = call i8* @malloc(i64 48)", mutex_raw));
-                    self.emit(&format!(
-                        "  call void @InitializeCriticalSection(i8* {})",
-                        mutex_raw
-                    ));
-                    let val_gep = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = getelementptr i8, i8* {}, i64 40",
-                        val_gep, mutex_raw
-                    ));
-                    let val_ptr = self.new_temp();
-                    self.emit(&format!("  {} = bitcast i8* {} to i64*", val_ptr, val_gep));
-                    self.emit(&format!("  store i64 {}, i64* {}", inner_val, val_ptr));
-                    return mutex_raw;
-                }
+        self.emit("define void @bytes_set_impl(i8* %b, i64 %idx, i64 %val) {");
+        self.emit("  %bs_p = call i8* @brn_bytes_at(i8* %b, i64 %idx)");
+        self.emit("  %bs_none = icmp eq i8* %bs_p, null");
+        self.emit("  br i1 %bs_none, label %bs_skip, label %bs_store");
+        self.emit("bs_skip:");
+        self.push(Inst::Ret(None));
+        self.emit("bs_store:");
+        self.emit("  %bs_c = trunc i64 %val to i8");
+        self.emit("  store i8 %bs_c, i8* %bs_p");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-                let tag = if let Some(variants) = self.enum_types.get(enum_name) {
-                    variants.iter().position(|v| v == variant).unwrap_or(0) as i64
-                } else {
-                    0
-                };
+        self.emit("define i8* @brn_bytes_data(i8* %b) {");
+        self.emit("  %bd_dp_raw = getelementptr i8, i8* %b, i64 16");
+        self.emit("  %bd_dp = bitcast i8* %bd_dp_raw to i8**");
+        self.emit("  %bd_data = load i8*, i8** %bd_dp");
+        self.emit("  ret i8* %bd_data");
+        self.emit("}");
+        self.emit("");
 
-                let ptr = self.new_temp();
-                self.emit(&format!("  {} = alloca {{ i32, i64 }}", ptr));
+        // bytes_slice(b, start, end): a copy of [start, end), both clamped
+        // to the buffer
+        self.emit("define i8* @bytes_slice_impl(i8* %b, i64 %start, i64 %end) {");
+        self.emit("  %bsl_len = call i64 @vec_len_impl(i8* %b)");
+        self.emit("  %bsl_sneg = icmp slt i64 %start, 0");
+        self.emit("  %bsl_s0 = select i1 %bsl_sneg, i64 0, i64 %start");
+        self.emit("  %bsl_sover = icmp sgt i64 %bsl_s0, %bsl_len");
+        self.emit("  %bsl_s = select i1 %bsl_sover, i64 %bsl_len, i64 %bsl_s0");
+        self.emit("  %bsl_eover = icmp sgt i64 %end, %bsl_len");
+        self.emit("  %bsl_e0 = select i1 %bsl_eover, i64 %bsl_len, i64 %end");
+        self.emit("  %bsl_eunder = icmp slt i64 %bsl_e0, %bsl_s");
+        self.emit("  %bsl_e = select i1 %bsl_eunder, i64 %bsl_s, i64 %bsl_e0");
+        self.emit("  %bsl_n = sub i64 %bsl_e, %bsl_s");
+        self.emit("  %bsl_out = call i8* @bytes_new_impl(i64 %bsl_n)");
+        self.emit("  %bsl_src0 = call i8* @brn_bytes_data(i8* %b)");
+        self.emit("  %bsl_src = getelementptr i8, i8* %bsl_src0, i64 %bsl_s");
+        self.emit("  %bsl_dst = call i8* @brn_bytes_data(i8* %bsl_out)");
+        self.emit("  call void @brn_memcpy(i8* %bsl_dst, i8* %bsl_src, i64 %bsl_n)");
+        self.emit("  ret i8* %bsl_out");
+        self.emit("}");
+        self.emit("");
 
-                let tag_ptr = self.new_temp();
-                self.emit(&format!(
-                    "  {} = getelementptr {{ i32, i64 }}, {{ i32, i64 }}* {}, i32 0, i32 0",
-                    tag_ptr, ptr
-                ));
-                self.emit(&format!("  store i32 {}, i32* {}", tag, tag_ptr));
+        // A new buffer holding a copy of the n bytes at p
+        self.emit("define i8* @bytes_from_buffer_impl(i8* %p, i64 %n) {");
+        self.emit("  %bfb_out = call i8* @bytes_new_impl(i64 %n)");
+        self.emit("  %bfb_dst = call i8* @brn_bytes_data(i8* %bfb_out)");
+        self.emit("  call void @brn_memcpy(i8* %bfb_dst, i8* %p, i64 %n)");
+        self.emit("  ret i8* %bfb_out");
+        self.emit("}");
+        self.emit("");
 
-                let val = if let Some(v) = value {
-                    self.gen_node(v)
-                } else {
-                    "0".to_string()
-                };
+        self.emit("define i8* @bytes_from_string_impl(i8* %s) {");
+        self.emit("  %bfs_n = call i64 @strlen(i8* %s)");
+        self.emit("  %bfs_out = call i8* @bytes_from_buffer_impl(i8* %s, i64 %bfs_n)");
+        self.emit("  ret i8* %bfs_out");
+        self.emit("}");
+        self.emit("");
 
-                let val_ptr = self.new_temp();
-                self.emit(&format!(
-                    "  {} = getelementptr {{ i32, i64 }}, {{ i32, i64 }}* {}, i32 0, i32 1",
-                    val_ptr, ptr
-                ));
-                self.emit(&format!("  store i64 {}, i64* {}", val, val_ptr));
+        // The string ends at the first zero byte, if there is one
+        self.emit("define i8* @bytes_to_string_impl(i8* %b) {");
+        self.emit("  %bts_n = call i64 @vec_len_impl(i8* %b)");
+        self.emit("  %bts_size = add i64 %bts_n, 1");
+        self.emit("  %bts_out = call i8* @malloc(i64 %bts_size)");
+        self.emit("  %bts_src = call i8* @brn_bytes_data(i8* %b)");
+        self.emit("  call void @brn_memcpy(i8* %bts_out, i8* %bts_src, i64 %bts_n)");
+        self.emit("  %bts_end = getelementptr i8, i8* %bts_out, i64 %bts_n");
+        self.emit("  store i8 0, i8* %bts_end");
+        self.emit("  ret i8* %bts_out");
+        self.emit("}");
+        self.emit("");
 
-                ptr
-            }
+        // A new string holding the n bytes at p
+        self.emit("define i8* @brn_substr_copy(i8* %p, i64 %n) {");
+        self.emit("  %sc_size = add i64 %n, 1");
+        self.emit("  %sc_out = call i8* @malloc(i64 %sc_size)");
+        self.emit("  call void @brn_memcpy(i8* %sc_out, i8* %p, i64 %n)");
+        self.emit("  %sc_end = getelementptr i8, i8* %sc_out, i64 %n");
+        self.emit("  store i8 0, i8* %sc_end");
+        self.emit("  ret i8* %sc_out");
+        self.emit("}");
+        self.emit("");
 
-            AstNode::Match { value, arms } => {
-                let value_reg = self.gen_node(value);
-                let end_label = self.new_label("match_end");
+        // to_string(b) on a bool: "true" or "false", in a new string
+        self.emit("define i8* @bool_to_string_impl(i1 %b) {");
+        self.emit("  %bls_t = getelementptr inbounds [5 x i8], [5 x i8]* @.str.true, i64 0, i64 0");
+        self.emit(
+            "  %bls_f = getelementptr inbounds [6 x i8], [6 x i8]* @.str.false, i64 0, i64 0",
+        );
+        self.emit("  %bls_s = select i1 %b, i8* %bls_t, i8* %bls_f");
+        self.emit("  %bls_n = select i1 %b, i64 4, i64 5");
+        self.emit("  %bls_out = call i8* @brn_substr_copy(i8* %bls_s, i64 %bls_n)");
+        self.emit("  ret i8* %bls_out");
+        self.emit("}");
+        self.emit("");
 
-                let is_enum_match = arms
-                    .iter()
-                    .any(|a| matches!(a.pattern, Pattern::EnumPattern { .. }));
+        // to_string(c) on a char: a one-character string
+        self.emit("define i8* @char_to_string_impl(i8 %c) {");
+        self.emit("  %cts_buf = alloca i8");
+        self.emit("  store i8 %c, i8* %cts_buf");
+        self.emit("  %cts_out = call i8* @brn_substr_copy(i8* %cts_buf, i64 1)");
+        self.emit("  ret i8* %cts_out");
+        self.emit("}");
+        self.emit("");
 
-                if is_enum_match {
-                    let tag_ptr = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = getelementptr {{ i32, i64 }}, {{ i32, i64 }}* {}, i32 0, i32 0",
-                        tag_ptr, value_reg
-                    ));
-                    let tag = self.new_temp();
-                    self.emit(&format!("  {} = load i32, i32* {}", tag, tag_ptr));
+        // s[start..end]: a negative bound counts back from the end of the
+        // string, then both are clamped to it; an end before the start
+        // gives ""
+        self.emit("define i8* @str_slice_impl(i8* %s, i64 %start, i64 %end) {");
+        self.emit("  %ssl_len = call i64 @strlen(i8* %s)");
+        self.emit("  %ssl_sneg = icmp slt i64 %start, 0");
+        self.emit("  %ssl_sback = add i64 %start, %ssl_len");
+        self.emit("  %ssl_s0 = select i1 %ssl_sneg, i64 %ssl_sback, i64 %start");
+        self.emit("  %ssl_sunder = icmp slt i64 %ssl_s0, 0");
+        self.emit("  %ssl_s1 = select i1 %ssl_sunder, i64 0, i64 %ssl_s0");
+        self.emit("  %ssl_sover = icmp sgt i64 %ssl_s1, %ssl_len");
+        self.emit("  %ssl_s = select i1 %ssl_sover, i64 %ssl_len, i64 %ssl_s1");
+        self.emit("  %ssl_eneg = icmp slt i64 %end, 0");
+        self.emit("  %ssl_eback = add i64 %end, %ssl_len");
+        self.emit("  %ssl_e0 = select i1 %ssl_eneg, i64 %ssl_eback, i64 %end");
+        self.emit("  %ssl_eover = icmp sgt i64 %ssl_e0, %ssl_len");
+        self.emit("  %ssl_e1 = select i1 %ssl_eover, i64 %ssl_len, i64 %ssl_e0");
+        self.emit("  %ssl_eunder = icmp slt i64 %ssl_e1, %ssl_s");
+        self.emit("  %ssl_e = select i1 %ssl_eunder, i64 %ssl_s, i64 %ssl_e1");
+        self.emit("  %ssl_n = sub i64 %ssl_e, %ssl_s");
+        self.emit("  %ssl_p = getelementptr i8, i8* %s, i64 %ssl_s");
+        self.emit("  %ssl_out = call i8* @brn_substr_copy(i8* %ssl_p, i64 %ssl_n)");
+        self.emit("  ret i8* %ssl_out");
+        self.emit("}");
+        self.emit("");
 
-                    for (i, arm) in arms.iter().enumerate() {
-                        let arm_label = self.new_label(&format!("match_arm_{}", i));
-                        let next_label = if i < arms.len() - 1 {
-                            self.new_label(&format!("match_check_{}", i + 1))
-                        } else {
-                            end_label.clone()
-                        };
+        self.emit("define void @brn_push_substr(i8* %vec, i8* %p, i64 %n) {");
+        self.emit("  %ps_s = call i8* @brn_substr_copy(i8* %p, i64 %n)");
+        self.emit("  %ps_slot = ptrtoint i8* %ps_s to i64");
+        self.emit("  call void @vec_push_impl(i8* %vec, i64 %ps_slot)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-                        match &arm.pattern {
-                            Pattern::EnumPattern {
-                                enum_name,
-                                variant,
-                                binding,
-                            } => {
-                                let variant_tag = self
-                                    .enum_types
-                                    .get(enum_name)
-                                    .and_then(|variants| variants.iter().position(|v| v == variant))
-                                    .unwrap_or(i)
-                                    as i32;
-
-                                let cond = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = icmp eq i32 {}, {}",
-                                    cond, tag, variant_tag
-                                ));
-                                self.emit(&format!(
-                                    "  br i1 {}, label %{}, label %{}",
-                                    cond, arm_label, next_label
-                                ));
-                                self.emit(&format!("{}:", arm_label));
-
-                                if let Some(binding) = binding {
-                                    let val_ptr = self.new_temp();
-                                    self.emit(&format!(
-                                        "  {} = getelementptr {{ i32, i64 }}, {{ i32, i64 }}* {}, i32 0, i32 1",
-                                        val_ptr, value_reg
-                                    ));
-                                    let val = self.new_temp();
-                                    self.emit(&format!("  {} = load i64, i64* {}", val, val_ptr));
-                                    let var_ptr = self.new_temp();
-                                    self.emit(&format!("  {} = alloca i64", var_ptr));
-                                    self.emit(&format!("  store i64 {}, i64* {}", val, var_ptr));
-                                    self.current_function_vars.insert(
-                                        binding.clone(),
-                                        VarMetadata {
-                                            llvm_name: var_ptr,
-                                            var_type: "int".to_string(),
-                                            is_heap: false,
-                                            array_size: None,
-                                            is_string_literal: false,
-                                        },
-                                    );
-                                }
+        self.emit("define i1 @str_ends_with_impl(i8* %s, i8* %suffix) {");
+        self.emit("  %ew_len = call i64 @strlen(i8* %s)");
+        self.emit("  %ew_slen = call i64 @strlen(i8* %suffix)");
+        self.emit("  %ew_fits = icmp sge i64 %ew_len, %ew_slen");
+        self.emit("  br i1 %ew_fits, label %ew_cmp, label %ew_no");
+        self.emit("ew_cmp:");
+        self.emit("  %ew_at = sub i64 %ew_len, %ew_slen");
+        self.emit("  %ew_p = getelementptr i8, i8* %s, i64 %ew_at");
+        self.emit("  %ew_c = call i32 @strcmp(i8* %ew_p, i8* %suffix)");
+        self.emit("  %ew_eq = icmp eq i32 %ew_c, 0");
+        self.emit("  ret i1 %ew_eq");
+        self.emit("ew_no:");
+        self.emit("  ret i1 false");
+        self.emit("}");
+        self.emit("");
 
-                                self.block_terminated = false;
-                                let arm_val = self.gen_node(&arm.body);
-                                if !self.block_terminated {
-                                    if self.current_function_return_type != "void" {
-                                        self.emit(&format!(
-                                            "  ret {} {}",
-                                            self.current_function_return_type, arm_val
-                                        ));
-                                        self.block_terminated = true;
-                                    } else {
-                                        self.emit(&format!("  br label %{}", end_label));
-                                    }
-                                }
-                            }
-                            Pattern::Wildcard | Pattern::Identifier(_) => {
-                                self.emit(&format!("  br label %{}", arm_label));
-                                self.emit(&format!("{}:", arm_label));
-                                self.block_terminated = false;
-                                let arm_val = self.gen_node(&arm.body);
-                                if !self.block_terminated {
-                                    if self.current_function_return_type != "void" {
-                                        self.emit(&format!(
-                                            "  ret {} {}",
-                                            self.current_function_return_type, arm_val
-                                        ));
-                                        self.block_terminated = true;
-                                    } else {
-                                        self.emit(&format!("  br label %{}", end_label));
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
+        self.emit("define i1 @str_starts_with_impl(i8* %s, i8* %prefix) {");
+        self.emit("sw_entry:");
+        self.emit("  br label %sw_loop");
+        self.emit("sw_loop:");
+        self.emit("  %sw_i = phi i64 [ 0, %sw_entry ], [ %sw_next, %sw_same ]");
+        self.emit("  %sw_pp = getelementptr i8, i8* %prefix, i64 %sw_i");
+        self.emit("  %sw_pc = load i8, i8* %sw_pp");
+        self.emit("  %sw_end = icmp eq i8 %sw_pc, 0");
+        self.emit("  br i1 %sw_end, label %sw_yes, label %sw_cmp");
+        self.emit("sw_cmp:");
+        self.emit("  %sw_sp = getelementptr i8, i8* %s, i64 %sw_i");
+        self.emit("  %sw_sc = load i8, i8* %sw_sp");
+        self.emit("  %sw_eq = icmp eq i8 %sw_sc, %sw_pc");
+        self.emit("  br i1 %sw_eq, label %sw_same, label %sw_no");
+        self.emit("sw_same:");
+        self.emit("  %sw_next = add i64 %sw_i, 1");
+        self.emit("  br label %sw_loop");
+        self.emit("sw_yes:");
+        self.emit("  ret i1 true");
+        self.emit("sw_no:");
+        self.emit("  ret i1 false");
+        self.emit("}");
+        self.emit("");
 
-                        if i < arms.len() - 1 {
-                            self.emit(&format!("{}:", next_label));
-                        }
-                    }
-                } else {
-                    for (i, arm) in arms.iter().enumerate() {
-                        let arm_label = self.new_label(&format!("match_arm_{}", i));
-                        let next_label = if i < arms.len() - 1 {
-                            self.new_label(&format!("match_check_{}", i + 1))
-                        } else {
-                            end_label.clone()
-                        };
+        // hash(n): the splitmix64 finalizer, so nearby ints spread out
+        self.emit("define i64 @brn_hash_int(i64 %n) {");
+        self.emit("  %hi_a = lshr i64 %n, 30");
+        self.emit("  %hi_b = xor i64 %n, %hi_a");
+        self.emit("  %hi_c = mul i64 %hi_b, -4658895280553007687");
+        self.emit("  %hi_d = lshr i64 %hi_c, 27");
+        self.emit("  %hi_e = xor i64 %hi_c, %hi_d");
+        self.emit("  %hi_f = mul i64 %hi_e, -7723592293110705685");
+        self.emit("  %hi_g = lshr i64 %hi_f, 31");
+        self.emit("  %hi_h = xor i64 %hi_f, %hi_g");
+        self.emit("  ret i64 %hi_h");
+        self.emit("}");
+        self.emit("");
 
-                        match &arm.pattern {
-                            Pattern::NumberPattern(n) => {
-                                let cond = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = icmp eq i64 {}, {}",
-                                    cond, value_reg, n
-                                ));
-                                self.emit(&format!(
-                                    "  br i1 {}, label %{}, label %{}",
-                                    cond, arm_label, next_label
-                                ));
-                                self.emit(&format!("{}:", arm_label));
-                                self.block_terminated = false;
-                                let arm_val = self.gen_node(&arm.body);
-                                if !self.block_terminated {
-                                    if self.current_function_return_type != "void" {
-                                        self.emit(&format!(
-                                            "  ret {} {}",
-                                            self.current_function_return_type, arm_val
-                                        ));
-                                        self.block_terminated = true;
-                                    } else {
-                                        self.emit(&format!("  br label %{}", end_label));
-                                    }
-                                }
-                            }
-                            Pattern::StringPattern(s) => {
-                                let str_id = self.new_string_literal(s);
-                                let str_len = s.len() + 1;
-                                let str_ptr = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
-                                    str_ptr, str_len, str_len, str_id
-                                ));
-                                let cmp_result = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = call i32 @strcmp(i8* {}, i8* {})",
-                                    cmp_result, value_reg, str_ptr
-                                ));
-                                let cond = self.new_temp();
-                                self.emit(&format!("  {} = icmp eq i32 {}, 0", cond, cmp_result));
-                                self.emit(&format!(
-                                    "  br i1 {}, label %{}, label %{}",
-                                    cond, arm_label, next_label
-                                ));
-                                self.emit(&format!("{}:", arm_label));
-                                self.block_terminated = false;
-                                let arm_val = self.gen_node(&arm.body);
-                                if !self.block_terminated {
-                                    if self.current_function_return_type != "void" {
-                                        self.emit(&format!(
-                                            "  ret {} {}",
-                                            self.current_function_return_type, arm_val
-                                        ));
-                                        self.block_terminated = true;
-                                    } else {
-                                        self.emit(&format!("  br label %{}", end_label));
-                                    }
-                                }
-                            }
-                            Pattern::Wildcard | Pattern::Identifier(_) => {
-                                self.emit(&format!("  br label %{}", arm_label));
-                                self.emit(&format!("{}:", arm_label));
-                                self.block_terminated = false;
-                                let arm_val = self.gen_node(&arm.body);
-                                if !self.block_terminated {
-                                    if self.current_function_return_type != "void" {
-                                        self.emit(&format!(
-                                            "  ret {} {}",
-                                            self.current_function_return_type, arm_val
-                                        ));
-                                        self.block_terminated = true;
-                                    } else {
-                                        self.emit(&format!("  br label %{}", end_label));
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
+        // hash(s): 64-bit FNV-1a over the bytes
+        self.emit("define i64 @brn_hash_str(i8* %s) {");
+        self.emit("hs_entry:");
+        self.emit("  br label %hs_loop");
+        self.emit("hs_loop:");
+        self.emit("  %hs_h = phi i64 [ -3750763034362895579, %hs_entry ], [ %hs_next, %hs_body ]");
+        self.emit("  %hs_i = phi i64 [ 0, %hs_entry ], [ %hs_i1, %hs_body ]");
+        self.emit("  %hs_p = getelementptr i8, i8* %s, i64 %hs_i");
+        self.emit("  %hs_c = load i8, i8* %hs_p");
+        self.emit("  %hs_end = icmp eq i8 %hs_c, 0");
+        self.emit("  br i1 %hs_end, label %hs_done, label %hs_body");
+        self.emit("hs_body:");
+        self.emit("  %hs_c64 = zext i8 %hs_c to i64");
+        self.emit("  %hs_x = xor i64 %hs_h, %hs_c64");
+        self.emit("  %hs_next = mul i64 %hs_x, 1099511628211");
+        self.emit("  %hs_i1 = add i64 %hs_i, 1");
+        self.emit("  br label %hs_loop");
+        self.emit("hs_done:");
+        self.emit("  ret i64 %hs_h");
+        self.emit("}");
+        self.emit("");
 
-                        if i < arms.len() - 1 {
-                            self.emit(&format!("{}:", next_label));
-                        }
-                    }
-                }
+        // s.split(sep): the pieces between occurrences of sep, so n
+        // separators always give n + 1 pieces; an empty sep gives [s]
+        self.emit("define i8* @str_split_impl(i8* %s, i8* %sep) {");
+        self.emit("sp_entry:");
+        self.emit("  %sp_out = call i8* @vec_new_impl()");
+        self.emit("  %sp_seplen = call i64 @strlen(i8* %sep)");
+        self.emit("  %sp_len = call i64 @strlen(i8* %s)");
+        self.emit("  %sp_nosep = icmp eq i64 %sp_seplen, 0");
+        self.emit("  br i1 %sp_nosep, label %sp_tail, label %sp_loop");
+        self.emit("sp_loop:");
+        self.emit(
+            "  %sp_i = phi i64 [ 0, %sp_entry ], [ %sp_inext, %sp_miss ], [ %sp_after, %sp_hit ]",
+        );
+        self.emit("  %sp_start = phi i64 [ 0, %sp_entry ], [ %sp_start, %sp_miss ], [ %sp_after, %sp_hit ]");
+        self.emit("  %sp_done = icmp sge i64 %sp_i, %sp_len");
+        self.emit("  br i1 %sp_done, label %sp_tail, label %sp_test");
+        self.emit("sp_test:");
+        self.emit("  %sp_p = getelementptr i8, i8* %s, i64 %sp_i");
+        self.emit("  %sp_match = call i1 @str_starts_with_impl(i8* %sp_p, i8* %sep)");
+        self.emit("  br i1 %sp_match, label %sp_hit, label %sp_miss");
+        self.emit("sp_hit:");
+        self.emit("  %sp_from = getelementptr i8, i8* %s, i64 %sp_start");
+        self.emit("  %sp_n = sub i64 %sp_i, %sp_start");
+        self.emit("  call void @brn_push_substr(i8* %sp_out, i8* %sp_from, i64 %sp_n)");
+        self.emit("  %sp_after = add i64 %sp_i, %sp_seplen");
+        self.emit("  br label %sp_loop");
+        self.emit("sp_miss:");
+        self.emit("  %sp_inext = add i64 %sp_i, 1");
+        self.emit("  br label %sp_loop");
+        self.emit("sp_tail:");
+        self.emit("  %sp_last = phi i64 [ 0, %sp_entry ], [ %sp_start, %sp_loop ]");
+        self.emit("  %sp_lfrom = getelementptr i8, i8* %s, i64 %sp_last");
+        self.emit("  %sp_ln = sub i64 %sp_len, %sp_last");
+        self.emit("  call void @brn_push_substr(i8* %sp_out, i8* %sp_lfrom, i64 %sp_ln)");
+        self.emit("  ret i8* %sp_out");
+        self.emit("}");
+        self.emit("");
 
-                self.emit(&format!("{}:", end_label));
-                self.block_terminated = false;
-                "0".to_string()
-            }
+        // s.lines(): split on \n, dropping a trailing \r from each line and
+        // the empty piece after a final newline
+        self.emit("define i8* @str_lines_impl(i8* %s) {");
+        self.emit("ln_entry:");
+        self.emit("  %ln_out = call i8* @vec_new_impl()");
+        self.emit("  br label %ln_loop");
+        self.emit("ln_loop:");
+        self.emit(
+            "  %ln_i = phi i64 [ 0, %ln_entry ], [ %ln_inext, %ln_char ], [ %ln_inext, %ln_push ]",
+        );
+        self.emit("  %ln_start = phi i64 [ 0, %ln_entry ], [ %ln_start, %ln_char ], [ %ln_inext, %ln_push ]");
+        self.emit("  %ln_p = getelementptr i8, i8* %s, i64 %ln_i");
+        self.emit("  %ln_c = load i8, i8* %ln_p");
+        self.emit("  %ln_inext = add i64 %ln_i, 1");
+        self.emit("  %ln_eos = icmp eq i8 %ln_c, 0");
+        self.emit("  br i1 %ln_eos, label %ln_end, label %ln_check");
+        self.emit("ln_check:");
+        self.emit("  %ln_nl = icmp eq i8 %ln_c, 10");
+        self.emit("  br i1 %ln_nl, label %ln_push, label %ln_char");
+        self.emit("ln_char:");
+        self.emit("  br label %ln_loop");
+        self.emit("ln_push:");
+        self.emit("  call void @brn_push_line(i8* %ln_out, i8* %s, i64 %ln_start, i64 %ln_i)");
+        self.emit("  br label %ln_loop");
+        self.emit("ln_end:");
+        self.emit("  %ln_rest = icmp sgt i64 %ln_i, %ln_start");
+        self.emit("  br i1 %ln_rest, label %ln_last, label %ln_ret");
+        self.emit("ln_last:");
+        self.emit("  call void @brn_push_line(i8* %ln_out, i8* %s, i64 %ln_start, i64 %ln_i)");
+        self.emit("  br label %ln_ret");
+        self.emit("ln_ret:");
+        self.emit("  ret i8* %ln_out");
+        self.emit("}");
+        self.emit("");
 
-            AstNode::FunctionDef {
-                name,
-                params,
-                body,
-                return_type,
-                is_unsafe,
-                ..
-            } => self.gen_function(name, params, body, return_type, *is_unsafe),
+        self.emit("define void @brn_push_line(i8* %vec, i8* %s, i64 %start, i64 %end) {");
+        self.emit("pl_entry:");
+        self.emit("  %pl_from = getelementptr i8, i8* %s, i64 %start");
+        self.emit("  %pl_n = sub i64 %end, %start");
+        self.emit("  %pl_some = icmp sgt i64 %pl_n, 0");
+        self.emit("  br i1 %pl_some, label %pl_check, label %pl_push");
+        self.emit("pl_check:");
+        self.emit("  %pl_li = sub i64 %end, 1");
+        self.emit("  %pl_lp = getelementptr i8, i8* %s, i64 %pl_li");
+        self.emit("  %pl_lc = load i8, i8* %pl_lp");
+        self.emit("  %pl_cr = icmp eq i8 %pl_lc, 13");
+        self.emit("  %pl_trim = sub i64 %pl_n, 1");
+        self.emit("  %pl_tn = select i1 %pl_cr, i64 %pl_trim, i64 %pl_n");
+        self.emit("  br label %pl_push");
+        self.emit("pl_push:");
+        self.emit("  %pl_len = phi i64 [ %pl_n, %pl_entry ], [ %pl_tn, %pl_check ]");
+        self.emit("  call void @brn_push_substr(i8* %vec, i8* %pl_from, i64 %pl_len)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
 
-            AstNode::LetBinding { name, value, .. } => {
-                self.current_binding = Some(name.clone());
-                let value_reg = self.gen_node(value);
-                self.current_binding = None;
-                let var_type = self.infer_type(value);
+        // ASCII case conversion into a new string; other bytes are copied
+        self.emit("define i8* @brn_str_case(i8* %s, i1 %upper) {");
+        self.emit("cs_entry:");
+        self.emit("  %cs_len = call i64 @strlen(i8* %s)");
+        self.emit("  %cs_out = call i8* @brn_substr_copy(i8* %s, i64 %cs_len)");
+        self.emit("  %cs_lo = select i1 %upper, i8 97, i8 65");
+        self.emit("  %cs_hi = select i1 %upper, i8 122, i8 90");
+        self.emit("  %cs_delta = select i1 %upper, i8 -32, i8 32");
+        self.emit("  br label %cs_loop");
+        self.emit("cs_loop:");
+        self.emit("  %cs_i = phi i64 [ 0, %cs_entry ], [ %cs_next, %cs_step ]");
+        self.emit("  %cs_done = icmp sge i64 %cs_i, %cs_len");
+        self.emit("  br i1 %cs_done, label %cs_exit, label %cs_body");
+        self.emit("cs_body:");
+        self.emit("  %cs_p = getelementptr i8, i8* %cs_out, i64 %cs_i");
+        self.emit("  %cs_c = load i8, i8* %cs_p");
+        self.emit("  %cs_ge = icmp sge i8 %cs_c, %cs_lo");
+        self.emit("  %cs_le = icmp sle i8 %cs_c, %cs_hi");
+        self.emit("  %cs_in = and i1 %cs_ge, %cs_le");
+        self.emit("  %cs_moved = add i8 %cs_c, %cs_delta");
+        self.emit("  %cs_new = select i1 %cs_in, i8 %cs_moved, i8 %cs_c");
+        self.emit("  store i8 %cs_new, i8* %cs_p");
+        self.emit("  br label %cs_step");
+        self.emit("cs_step:");
+        self.emit("  %cs_next = add i64 %cs_i, 1");
+        self.emit("  br label %cs_loop");
+        self.emit("cs_exit:");
+        self.emit("  ret i8* %cs_out");
+        self.emit("}");
+        self.emit("");
 
-                // If the value is a .lock() call, register this binding as a guard
-                if let AstNode::MethodCall { method, .. } = value.as_ref() {
-                    if method == "lock" && !self.is_unsafe_fn {
-                        self.guard_vars.insert(name.clone());
-                    }
-                }
+        self.emit_path_ops();
+
+        // argv as a Vec<string> for `fn main(args: Vec<string>)`; args[0] is
+        // the program name
+        self.emit("define i8* @brn_args_impl(i32 %argc, i8** %argv) {");
+        self.emit("ar_entry:");
+        self.emit("  %ar_vec = call i8* @vec_new_impl()");
+        self.emit("  %ar_n = sext i32 %argc to i64");
+        self.emit("  br label %ar_loop");
+        self.emit("ar_loop:");
+        self.emit("  %ar_i = phi i64 [ 0, %ar_entry ], [ %ar_next, %ar_body ]");
+        self.emit("  %ar_done = icmp sge i64 %ar_i, %ar_n");
+        self.emit("  br i1 %ar_done, label %ar_exit, label %ar_body");
+        self.emit("ar_body:");
+        self.emit("  %ar_pp = getelementptr i8*, i8** %argv, i64 %ar_i");
+        self.emit("  %ar_arg = load i8*, i8** %ar_pp");
+        self.emit("  %ar_copy = call i8* @brn_strdup(i8* %ar_arg)");
+        self.emit("  %ar_slot = ptrtoint i8* %ar_copy to i64");
+        self.emit("  call void @vec_push_impl(i8* %ar_vec, i64 %ar_slot)");
+        self.emit("  %ar_next = add i64 %ar_i, 1");
+        self.emit("  br label %ar_loop");
+        self.emit("ar_exit:");
+        self.emit("  ret i8* %ar_vec");
+        self.emit("}");
+        self.emit("");
 
-                let is_string_literal = matches!(value.as_ref(), AstNode::StringLit(_));
-                let is_struct = self.struct_types.contains_key(&var_type);
-                let stack_promote = self.non_escaping.contains(name) && !is_struct;
+        // Hand the builder's buffer over as the string and free only the
+        // header — used for chained `+`, where the builder is a temporary.
+        self.emit("define i8* @sb_finish_impl(i8* %sb) {");
+        self.emit("  %sf_dp_raw = getelementptr i8, i8* %sb, i64 16");
+        self.emit("  %sf_dp = bitcast i8* %sf_dp_raw to i8**");
+        self.emit("  %sf_data = load i8*, i8** %sf_dp");
+        self.emit("  call void @free(i8* %sb)");
+        self.emit("  ret i8* %sf_data");
+        self.emit("}");
+        self.emit("");
 
-                let is_mutex =
-                    var_type.starts_with("Mutex<") || var_type.starts_with("MutexGuard<");
-                let is_heap = !stack_promote
-                    && !is_mutex
-                    && ((var_type == "string" && !is_string_literal)
-                        || (var_type == "Vec")
-                        || is_struct);
+        // assert(cond, msg) failure: "assertion failed[: msg]" on stderr, exit 101
+        self.emit("define void @brn_assert_fail(i8* %msg) {");
+        self.emit(
+            "  %af_pre = getelementptr inbounds [19 x i8], [19 x i8]* @.str.assert, i64 0, i64 0",
+        );
+        self.emit("  %af_has = icmp ne i8* %msg, null");
+        self.emit("  br i1 %af_has, label %af_msg, label %af_bare");
+        self.emit("af_msg:");
+        self.emit("  call void @brn_write_stderr(i8* %af_pre, i64 18)");
+        self.emit("  %af_len = call i64 @strlen(i8* %msg)");
+        self.emit("  call void @brn_write_stderr(i8* %msg, i64 %af_len)");
+        self.emit("  br label %af_end");
+        self.emit("af_bare:");
+        self.emit("  call void @brn_write_stderr(i8* %af_pre, i64 16)");
+        self.emit("  br label %af_end");
+        self.emit("af_end:");
+        self.emit("  %af_nl = alloca i8");
+        self.emit("  store i8 10, i8* %af_nl");
+        self.emit("  call void @brn_write_stderr(i8* %af_nl, i64 1)");
+        self.emit("  call void @brn_exit(i32 101)");
+        self.push(Inst::Unreachable);
+        self.emit("}");
+        self.emit("");
 
-                if let AstNode::ArrayLit(elements) = value.as_ref() {
-                    let size = elements.len();
-                    let sized_type = format!("[{}; int]", size);
-                    self.current_function_vars.insert(
-                        name.clone(),
-                        VarMetadata {
-                            llvm_name: value_reg.clone(),
-                            var_type: sized_type,
-                            is_heap: false,
-                            array_size: Some(size),
-                            is_string_literal: false,
-                        },
-                    );
-                    return value_reg;
-                }
+        // Runtime errors: "panic: msg" on stderr, exit 101 like a failed assert
+        self.emit("define void @brn_panic(i8* %msg) {");
+        self.emit(
+            "  %pn_pre = getelementptr inbounds [8 x i8], [8 x i8]* @.str.panic, i64 0, i64 0",
+        );
+        self.emit("  call void @brn_write_stderr(i8* %pn_pre, i64 7)");
+        self.emit("  %pn_len = call i64 @strlen(i8* %msg)");
+        self.emit("  call void @brn_write_stderr(i8* %msg, i64 %pn_len)");
+        self.emit("  %pn_nl = alloca i8");
+        self.emit("  store i8 10, i8* %pn_nl");
+        self.emit("  call void @brn_write_stderr(i8* %pn_nl, i64 1)");
+        self.emit("  call void @brn_exit(i32 101)");
+        self.push(Inst::Unreachable);
+        self.emit("}");
+        self.emit("");
 
-                if self.struct_types.contains_key(&var_type) && !is_heap {
-                    self.current_function_vars.insert(
-                        name.clone(),
-                        VarMetadata {
-                            llvm_name: value_reg.clone(),
-                            var_type,
-                            is_heap: false,
-                            array_size: None,
-                            is_string_literal,
-                        },
-                    );
-                    return value_reg;
-                }
+        self.string_literals
+            .push((".str.assert".to_string(), "assertion failed: ".to_string()));
+        self.string_literals
+            .push((".str.panic".to_string(), "panic: ".to_string()));
+        self.string_literals
+            .push((".str.null_vec".to_string(), "use of a null Vec".to_string()));
+        self.string_literals.push((
+            ".str.vec_oob".to_string(),
+            "Vec index out of bounds".to_string(),
+        ));
+        self.string_literals
+            .push((".str.oom".to_string(), "out of memory".to_string()));
+        self.string_literals
+            .push((".str.true".to_string(), "true".to_string()));
+        self.string_literals
+            .push((".str.false".to_string(), "false".to_string()));
+        self.string_literals
+            .push((".str.mode.r".to_string(), "r".to_string()));
+        self.string_literals
+            .push((".str.mode.w".to_string(), "w".to_string()));
+        self.string_literals
+            .push((".str.mode.a".to_string(), "a".to_string()));
+        if cfg!(target_os = "windows") {
+            self.string_literals
+                .push((".str.shell".to_string(), "cmd.exe /c ".to_string()));
+        } else {
+            self.string_literals
+                .push((".str.shell".to_string(), "/bin/sh".to_string()));
+            self.string_literals
+                .push((".str.shell.c".to_string(), "-c".to_string()));
+        }
+    }
 
-                let ptr = self.new_temp();
-                let llvm_type_str = self.type_to_llvm(&var_type);
-                self.emit(&format!("  {} = alloca {}", ptr, llvm_type_str));
-                self.emit(&format!(
-                    "  store {} {}, {}* {}",
-                    llvm_type_str, value_reg, llvm_type_str, ptr
-                ));
+    /// Linux executables are linked without a C runtime, so nothing else
+    /// calls `main` or turns its result into the exit status: `_start` hands
+    /// the initial stack (argc, then argv) to `brn_entry`, which calls `main`
+    /// and exits with `SYS_exit_group`. The JIT calls `main` itself.
+    fn emit_linux_entry(&mut self, main_takes_args: bool) {
+        self.emit_runtime_init();
 
-                self.current_function_vars.insert(
-                    name.clone(),
-                    VarMetadata {
-                        llvm_name: ptr.clone(),
-                        var_type,
-                        is_heap,
-                        array_size: None,
-                        is_string_literal,
-                    },
-                );
+        self.emit("\ndefine void @_start() naked noreturn nounwind {");
+        self.emit(
+            "  call void asm sideeffect \"xor %rbp, %rbp\\0Amov %rsp, %rdi\\0Aand $$-16, %rsp\\0Acall brn_entry\", \"~{dirflag},~{fpsr},~{flags}\"()",
+        );
+        self.push(Inst::Unreachable);
+        self.emit("}");
+        self.emit("");
+        self.emit("define void @brn_entry(i64* %sp) noreturn nounwind {");
+        self.emit("  call void @brn_runtime_init()");
+        if main_takes_args {
+            self.emit("  %argc = load i64, i64* %sp");
+            self.emit("  %argc32 = trunc i64 %argc to i32");
+            self.emit("  %argv_raw = getelementptr i64, i64* %sp, i64 1");
+            self.emit("  %argv = bitcast i64* %argv_raw to i8**");
+            self.emit("  %code = call i32 @main(i32 %argc32, i8** %argv)");
+        } else {
+            self.emit("  %code = call i32 @main()");
+        }
+        self.emit("  %code64 = sext i32 %code to i64");
+        self.emit("  %r = call i64 (i64, ...) @syscall(i64 231, i64 %code64)");
+        self.push(Inst::Unreachable);
+        self.emit("}");
+    }
 
-                ptr
-            }
+    /// Whether `main` itself calls `@brn_runtime_init`: on Linux with the
+    /// C runtime, which has no `brn_entry` to do it. AddressSanitizer
+    /// handles faults its own way.
+    fn main_inits_runtime(&self) -> bool {
+        cfg!(target_os = "linux") && self.options.runtime == Runtime::Libc && !self.options.sanitize
+    }
 
-            AstNode::ArrayAssignment {
-                array,
-                index,
-                value,
-                ..
-            } => {
-                let index_val = self.gen_node(index);
-                let value_reg = self.gen_node(value);
+    /// `@brn_runtime_init`, run before `main` on Linux: it raises the stack
+    /// limit to `--stack-size` and has `SIGSEGV` handled on a stack of its
+    /// own, so that running off the end of the stack panics with a message
+    /// instead of dying silently. A fault within a megabyte of the stack
+    /// pointer is the stack running into its guard pages; anything else is
+    /// a bad pointer from `unsafe` code.
+    fn emit_runtime_init(&mut self) {
+        self.emit("");
+        self.emit("@brn_sigstack = internal global [65536 x i8] zeroinitializer");
+        self.emit("");
+        self.emit("define internal void @brn_runtime_init() nounwind {");
+        if let Some(size) = self.options.stack_size {
+            // SYS_getrlimit / SYS_setrlimit (97 / 160) on RLIMIT_STACK (3);
+            // beyond the hard limit the call fails and the old limit stays
+            self.emit("  %rl = alloca { i64, i64 }");
+            self.emit("  %rl_raw = bitcast { i64, i64 }* %rl to i8*");
+            self.emit("  call i64 (i64, ...) @syscall(i64 97, i64 3, i8* %rl_raw)");
+            self.emit("  %rl_cur = getelementptr { i64, i64 }, { i64, i64 }* %rl, i32 0, i32 0");
+            self.emit(&format!("  store i64 {}, i64* %rl_cur", size));
+            self.emit("  call i64 (i64, ...) @syscall(i64 160, i64 3, i8* %rl_raw)");
+        }
+        // SYS_sigaltstack (131), then SYS_rt_sigaction (13) for SIGSEGV with
+        // SA_SIGINFO | SA_ONSTACK | SA_RESTORER. The kernel insists on a
+        // restorer, but the handler never returns to use it.
+        self.emit("  %alt = alloca { i8*, i32, i64 }");
+        self.emit(
+            "  %alt_sp = getelementptr { i8*, i32, i64 }, { i8*, i32, i64 }* %alt, i32 0, i32 0",
+        );
+        self.emit(
+            "  %alt_mem = getelementptr [65536 x i8], [65536 x i8]* @brn_sigstack, i64 0, i64 0",
+        );
+        self.emit("  store i8* %alt_mem, i8** %alt_sp");
+        self.emit(
+            "  %alt_flags = getelementptr { i8*, i32, i64 }, { i8*, i32, i64 }* %alt, i32 0, i32 1",
+        );
+        self.emit("  store i32 0, i32* %alt_flags");
+        self.emit(
+            "  %alt_size = getelementptr { i8*, i32, i64 }, { i8*, i32, i64 }* %alt, i32 0, i32 2",
+        );
+        self.emit("  store i64 65536, i64* %alt_size");
+        self.emit("  call i64 (i64, ...) @syscall(i64 131, { i8*, i32, i64 }* %alt, i8* null)");
+        self.emit("  %act = alloca { i8*, i64, i8*, i64 }");
+        self.emit(
+            "  store { i8*, i64, i8*, i64 } { i8* bitcast (void (i32, i8*, i8*)* @brn_on_segv to i8*), i64 201326596, i8* bitcast (void (i32, i8*, i8*)* @brn_on_segv to i8*), i64 0 }, { i8*, i64, i8*, i64 }* %act",
+        );
+        self.emit(
+            "  call i64 (i64, ...) @syscall(i64 13, i64 11, { i8*, i64, i8*, i64 }* %act, i8* null, i64 8)",
+        );
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+        self.emit(
+            "define internal void @brn_on_segv(i32 %sig, i8* %info, i8* %ctx) noreturn nounwind {",
+        );
+        // siginfo_t.si_addr, and the saved %rsp in ucontext_t's registers
+        self.emit("  %sv_addr_raw = getelementptr i8, i8* %info, i64 16");
+        self.emit("  %sv_addr_p = bitcast i8* %sv_addr_raw to i64*");
+        self.emit("  %sv_addr = load i64, i64* %sv_addr_p");
+        self.emit("  %sv_rsp_raw = getelementptr i8, i8* %ctx, i64 160");
+        self.emit("  %sv_rsp_p = bitcast i8* %sv_rsp_raw to i64*");
+        self.emit("  %sv_rsp = load i64, i64* %sv_rsp_p");
+        self.emit("  %sv_dist = sub i64 %sv_addr, %sv_rsp");
+        self.emit("  %sv_shifted = add i64 %sv_dist, 1048576");
+        self.emit("  %sv_is_stack = icmp ult i64 %sv_shifted, 2097152");
+        self.emit("  br i1 %sv_is_stack, label %overflow, label %bad_access");
+        self.emit("overflow:");
+        let overflow = "stack overflow (build with a larger --stack-size for deeper recursion)";
+        self.emit(&format!(
+            "  %sv_overflow = getelementptr inbounds [{} x i8], [{} x i8]* @.str.stack_overflow, i64 0, i64 0",
+            overflow.len() + 1,
+            overflow.len() + 1
+        ));
+        self.emit("  call void @brn_panic(i8* %sv_overflow)");
+        self.push(Inst::Unreachable);
+        self.emit("bad_access:");
+        let bad_access = "invalid memory access";
+        self.emit(&format!(
+            "  %sv_bad = getelementptr inbounds [{} x i8], [{} x i8]* @.str.bad_access, i64 0, i64 0",
+            bad_access.len() + 1,
+            bad_access.len() + 1
+        ));
+        self.emit("  call void @brn_panic(i8* %sv_bad)");
+        self.push(Inst::Unreachable);
+        self.emit("}");
+        self.string_literals
+            .push((".str.stack_overflow".to_string(), overflow.to_string()));
+        self.string_literals
+            .push((".str.bad_access".to_string(), bad_access.to_string()));
+    }
 
-                if let Some(meta) = self.current_function_vars.get(array).cloned() {
-                    let array_size = meta.array_size.unwrap_or(100);
-                    let elem_ptr = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = getelementptr [{} x i64], [{} x i64]* {}, i64 0, i64 {}",
-                        elem_ptr, array_size, array_size, meta.llvm_name, index_val
-                    ));
-                    self.emit(&format!("  store i64 {}, i64* {}", value_reg, elem_ptr));
-                }
+    fn emit_footer(&mut self) {
+        // Build the header block first, then prepend to output in one allocation
+        // instead of shifting the entire buffer on every string literal/struct decl.
+        let mut header = String::with_capacity(4096);
+        for decl in self.struct_decls.iter().rev() {
+            header.push_str(decl);
+            header.push('\n');
+        }
+        for (id, value) in &self.string_literals {
+            let len = value.len() + 1;
+            let escaped = Self::escape_bytes(value.as_bytes());
+            header.push_str(&format!(
+                "@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\", align 1\n",
+                id, len, escaped
+            ));
+        }
+        for (id, bytes) in &self.byte_literals {
+            // `c""` isn't a valid constant
+            let init = if bytes.is_empty() {
+                "zeroinitializer".to_string()
+            } else {
+                format!("c\"{}\"", Self::escape_bytes(bytes))
+            };
+            header.push_str(&format!(
+                "@{} = private unnamed_addr constant [{} x i8] {}, align 1\n",
+                id,
+                bytes.len(),
+                init
+            ));
+        }
+        for (id, values) in &self.array_literals {
+            header.push_str(&format!(
+                "@{} = private unnamed_addr constant [{} x i64] [{}], align 8\n",
+                id,
+                values.len(),
+                Self::array_initializer(values)
+            ));
+        }
+        for enum_name in &self.enum_name_tables {
+            let names: Vec<String> = self.enum_types[enum_name]
+                .iter()
+                .map(|variant| {
+                    let len = variant.len() + 1;
+                    format!(
+                        "i8* getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0)",
+                        len, len, self.string_literal_map[variant]
+                    )
+                })
+                .collect();
+            header.push_str(&format!(
+                "@.enum.{} = private unnamed_addr constant [{} x i8*] [{}], align 8\n",
+                enum_name,
+                names.len(),
+                names.join(", ")
+            ));
+        }
+        header.push_str(&self.output);
+        self.output = header;
+    }
 
-                value_reg
+    fn gen_node(&mut self, node: &AstNode) -> Value {
+        let key = node as *const AstNode;
+        let reg = match self.loop_values.get(&key) {
+            Some(LoopValue::Hoisted(reg)) => reg.clone(),
+            Some(LoopValue::Running(slot)) => {
+                let slot = slot.clone();
+                let reg = self.new_temp();
+                self.load_into(&reg, "i64", &slot);
+                reg
+            }
+            None => self.gen_node_reg(node),
+        };
+        if let Some(steps) = self.induction_steps.get(&key).cloned() {
+            for (slot, step) in steps {
+                self.emit_slot_add(&slot, step);
             }
+        }
+        let brain_ty = match node {
+            AstNode::ArrayLit(elements, _) => format!("[{}; int]", elements.len()),
+            _ => self.infer_type(node),
+        };
+        // What the instruction defining it says beats what the AST suggests
+        let defined = self.function.as_ref().and_then(|f| f.type_of(&reg));
+        let llvm_ty = match (defined, Self::array_len(&brain_ty)) {
+            (Some(ty), _) => ty.to_string(),
+            (None, Some(len)) => format!("[{} x i64]*", len),
+            (None, None) => self.type_to_llvm(&brain_ty),
+        };
+        Value {
+            reg,
+            llvm_ty,
+            brain_ty,
+        }
+    }
 
-            AstNode::Assignment { name, value, .. } => {
-                let value_reg = self.gen_node(value);
+    /// `n` for a fixed-size array type `[n; int]`.
+    fn array_len(brain_ty: &str) -> Option<usize> {
+        brain_ty
+            .strip_prefix('[')?
+            .split(';')
+            .next()?
+            .trim()
+            .parse()
+            .ok()
+    }
 
-                if let Some(meta) = self.current_function_vars.get(name).cloned() {
-                    let llvm_type_str = self.type_to_llvm(&meta.var_type);
-                    let llvm_name = meta.llvm_name.clone();
-                    self.emit(&format!(
-                        "  store {} {}, {}* {}",
-                        llvm_type_str, value_reg, llvm_type_str, llvm_name
-                    ));
+    /// `value` as the `i64` that array elements, enum payloads and other
+    /// untyped slots hold; a `bool` is widened to 0 or 1.
+    fn coerce_i64(&mut self, value: Value) -> String {
+        match value.llvm_ty.as_str() {
+            "i1" => self.widen(&value.reg, "i1", "zext"),
+            "i8" => self.widen(&value.reg, "i8", "zext"),
+            _ => value.reg,
+        }
+    }
+
+    /// `value` as an `i1` condition; an `i64` is true when non-zero.
+    fn coerce_i1(&mut self, value: Value) -> String {
+        if value.llvm_ty != "i64" {
+            return value.reg;
+        }
+        let result = self.new_temp();
+        self.push(Inst::Icmp {
+            dest: result.clone(),
+            pred: "ne",
+            ty: value.llvm_ty,
+            lhs: value.reg,
+            rhs: "0".to_string(),
+        });
+        result
+    }
+
+    fn widen(&mut self, reg: &str, from: &str, how: &'static str) -> String {
+        // Constants need no instruction; `true` widens to 1 either way
+        match reg {
+            "true" => return "1".to_string(),
+            "false" => return "0".to_string(),
+            _ if reg.parse::<i64>().is_ok() => return reg.to_string(),
+            _ => {}
+        }
+        let result = self.new_temp();
+        self.push(Inst::Cast {
+            dest: result.clone(),
+            op: how,
+            from: from.to_string(),
+            value: reg.to_string(),
+            to: "i64".to_string(),
+        });
+        result
+    }
+
+    /// `value` converted to an `int` or a `char`, implicitly or by `as`: a
+    /// `bool` or `char` widens with zeros, and an `int` keeps only its low
+    /// byte as a `char`.
+    fn convert(&mut self, value: Value, to: &str) -> String {
+        let how = match (value.llvm_ty.as_str(), to) {
+            ("i1" | "i8", "int") => return self.coerce_i64(value),
+            ("i64", "char") => "trunc",
+            ("i1", "char") => "zext",
+            _ => return value.reg,
+        };
+        match value.reg.as_str() {
+            "true" => return "1".to_string(),
+            "false" => return "0".to_string(),
+            reg => {
+                if let Ok(n) = reg.parse::<i64>() {
+                    return (n as u8).to_string();
                 }
+            }
+        }
+        let result = self.new_temp();
+        self.push(Inst::Cast {
+            dest: result.clone(),
+            op: how,
+            from: value.llvm_ty,
+            value: value.reg,
+            to: "i8".to_string(),
+        });
+        result
+    }
 
-                value_reg
+    fn gen_node_reg(&mut self, node: &AstNode) -> String {
+        match node {
+            AstNode::Import { .. } | AstNode::When { .. } | AstNode::ExternFn { .. } => {
+                "0".to_string()
             }
 
-            AstNode::MemberAssignment {
-                object,
-                field,
-                value,
-                ..
+            AstNode::StructDef { .. } => "0".to_string(),
+
+            AstNode::StructInit {
+                name, fields, base, ..
             } => {
-                let value_reg = self.gen_node(value);
+                let struct_fields = self.struct_types.get(name).cloned().unwrap_or_default();
 
-                let is_guard = self.guard_vars.contains(object.as_str())
+                // Value structs are always built on the stack; every use copies them.
+                let stack_promote = self.is_value_struct(name)
                     || self
-                        .current_function_vars
-                        .get(object.as_str())
-                        .map(|m| m.var_type.starts_with("MutexGuard<"))
+                        .current_binding
+                        .as_ref()
+                        .map(|b| self.non_escaping.contains(b))
                         .unwrap_or(false);
 
-                if is_guard && field == "value" && !self.is_unsafe_fn {
-                    // volatile store through the mutex guard
-                    if let Some(meta) = self.current_function_vars.get(object.as_str()).cloned() {
-                        let guard_ptr = if meta.llvm_name.starts_with("%arg_") {
-                            meta.llvm_name.clone()
+                let struct_ptr = self.alloc_struct(name, !stack_promote);
+
+                for (field_name, field_value) in fields.iter() {
+                    let val_reg = self.gen_node(field_value).reg;
+                    let field_idx = struct_fields
+                        .iter()
+                        .position(|(n, _)| n == field_name)
+                        .unwrap_or(0);
+                    let field_type = struct_fields
+                        .get(field_idx)
+                        .map(|(_, t)| t.clone())
+                        .unwrap_or_else(|| "int".to_string());
+                    let val_reg = if field_type.starts_with('*') {
+                        self.store_through_pointer(field_value, val_reg)
+                    } else if field_type == "string"
+                        && self.string_ownership(field_value) == StringOwnership::Borrowed
+                    {
+                        // The field keeps the string past whatever lends it
+                        self.take_string(field_value, val_reg)
+                    } else {
+                        val_reg
+                    };
+
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", name),
+                        &struct_ptr,
+                        &[("i32", "0"), ("i32", &field_idx.to_string())],
+                    );
+                    self.gen_store_field(&field_type, &val_reg, &gep);
+                }
+
+                // Fields left out: copied from `..base`, else the default
+                let base_reg = base.as_ref().map(|b| self.gen_node(b).reg);
+                for (field_idx, (field_name, field_type)) in struct_fields.iter().enumerate() {
+                    if fields.iter().any(|(n, _)| n == field_name) {
+                        continue;
+                    }
+                    let val_reg = if let Some(base_reg) = &base_reg {
+                        let src = self.new_temp();
+                        self.gep(
+                            &src,
+                            &format!("%{}", name),
+                            base_reg,
+                            &[("i32", "0"), ("i32", &field_idx.to_string())],
+                        );
+                        if self.is_value_struct(field_type) {
+                            src
                         } else {
+                            let llvm_type = self.type_to_llvm(field_type);
                             let loaded = self.new_temp();
-                            self.emit(&format!("  {} = load i8*, i8** {}", loaded, meta.llvm_name));
-                            loaded
-                        };
-                        let val_gep = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = getelementptr i8, i8* {}, i64 40",
-                            val_gep, guard_ptr
-                        ));
-                        let val_ptr = self.new_temp();
-                        self.emit(&format!("  {} = bitcast i8* {} to i64*", val_ptr, val_gep));
-                        self.emit(&format!(
-                            "  store volatile i64 {}, i64* {}",
-                            value_reg, val_ptr
-                        ));
-                    }
-                } else if let Some(struct_fields) = self
-                    .current_function_vars
-                    .get(object.as_str())
-                    .map(|m| m.var_type.clone())
-                    .and_then(|t| self.struct_types.get(&t).cloned())
-                {
-                    if let Some(meta) = self.current_function_vars.get(object.as_str()).cloned() {
-                        if let Some(field_idx) = struct_fields.iter().position(|(n, _)| n == field)
-                        {
-                            let struct_name = meta.var_type.clone();
-                            // %arg_* params are already %StructName* — skip the extra load.
-                            let obj_ptr = if meta.llvm_name.starts_with("%arg_") {
-                                meta.llvm_name.clone()
+                            self.load_into(&loaded, &llvm_type, &src);
+                            if field_type == "string" {
+                                let copy = self.new_temp();
+                                self.call(Some(&copy), "i8*", "@brn_strdup", &[("i8*", &loaded)]);
+                                copy
                             } else {
-                                let loaded = self.new_temp();
-                                self.emit(&format!(
-                                    "  {} = load %{}*, %{}** {}",
-                                    loaded, struct_name, struct_name, meta.llvm_name
-                                ));
                                 loaded
-                            };
-                            let field_type = struct_fields[field_idx].1.clone();
-                            let llvm_ft = self.type_to_llvm(&field_type);
-                            let gep = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = getelementptr %{}, %{}* {}, i32 0, i32 {}",
-                                gep, struct_name, struct_name, obj_ptr, field_idx
-                            ));
-                            self.emit(&format!(
-                                "  store {} {}, {}* {}",
-                                llvm_ft, value_reg, llvm_ft, gep
-                            ));
+                            }
                         }
-                    }
+                    } else if let Some(default) = self
+                        .struct_defaults
+                        .get(name)
+                        .and_then(|d| d.get(field_name))
+                        .cloned()
+                    {
+                        let val_reg = self.gen_node(&default).reg;
+                        if field_type.starts_with('*') {
+                            self.store_through_pointer(&default, val_reg)
+                        } else {
+                            val_reg
+                        }
+                    } else {
+                        continue;
+                    };
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", name),
+                        &struct_ptr,
+                        &[("i32", "0"), ("i32", &field_idx.to_string())],
+                    );
+                    self.gen_store_field(field_type, &val_reg, &gep);
                 }
 
-                value_reg
+                struct_ptr
             }
 
-            AstNode::If {
-                condition,
-                then_block,
-                else_block,
-            } => {
-                let cond_reg = self.gen_node(condition);
-                let then_label = self.new_label("then");
-                let else_label = self.new_label("else");
-                let end_label = self.new_label("endif");
-
-                if else_block.is_some() {
-                    self.emit(&format!(
-                        "  br i1 {}, label %{}, label %{}",
-                        cond_reg, then_label, else_label
-                    ));
-                } else {
+            AstNode::MemberAccess { object, field, .. } => {
+                if let AstNode::Identifier { name: obj_name, .. } = object.as_ref()
+                    && (self.guard_vars.contains(obj_name.as_str())
+                        || self
+                            .current_function_vars
+                            .get(obj_name.as_str())
+                            .map(|m| m.var_type.starts_with("MutexGuard<"))
+                            .unwrap_or(false))
+                    && field == "value"
+                    && !self.is_unsafe_fn
+                {
+                    let guard_ptr = if let Some(meta) =
+                        self.current_function_vars.get(obj_name.as_str()).cloned()
+                    {
+                        if meta.llvm_name.starts_with("%arg_") {
+                            meta.llvm_name.clone()
+                        } else {
+                            let loaded = self.new_temp();
+                            self.load_into(&loaded, "i8*", &meta.llvm_name);
+                            loaded
+                        }
+                    } else {
+                        obj_name.to_string()
+                    };
+                    let val_gep = self.new_temp();
+                    self.gep(&val_gep, "i8", &guard_ptr, &[("i64", "40")]);
+                    let val_ptr = self.new_temp();
+                    self.cast(&val_ptr, "bitcast", "i8*", &val_gep, "i64*");
+                    let result = self.new_temp();
+                    // volatile load — prevents register caching across lock boundary
                     self.emit(&format!(
-                        "  br i1 {}, label %{}, label %{}",
-                        cond_reg, then_label, end_label
+                        "  {} = load volatile i64, i64* {}",
+                        result, val_ptr
                     ));
+                    return result;
                 }
 
-                self.emit(&format!("{}:", then_label));
-                self.block_terminated = false;
-                self.gen_node(then_block);
-                let then_terminated = self.block_terminated;
-                if !self.block_terminated {
-                    self.emit(&format!("  br label %{}", end_label));
-                }
+                let obj_reg = self.gen_node(object).reg;
+                let struct_name = self.infer_struct_name(object);
 
-                let mut else_terminated = false;
-                if let Some(else_block) = else_block {
-                    self.emit(&format!("{}:", else_label));
-                    self.block_terminated = false;
-                    self.gen_node(else_block);
-                    else_terminated = self.block_terminated;
-                    if !self.block_terminated {
-                        self.emit(&format!("  br label %{}", end_label));
-                    }
-                }
+                if let Some(struct_fields) = self.struct_types.get(&struct_name).cloned()
+                    && let Some(field_idx) = Self::field_index(&struct_fields, field)
+                {
+                    let field_type = struct_fields[field_idx].1.clone();
+                    let llvm_field_type = self.type_to_llvm(&field_type);
 
-                self.emit(&format!("{}:", end_label));
-                if then_terminated && else_terminated {
-                    self.emit("  unreachable");
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", struct_name),
+                        &obj_reg,
+                        &[("i32", "0"), ("i32", &field_idx.to_string())],
+                    );
+                    // A value struct field is stored inline: hand out its address
+                    if self.is_value_struct(&field_type) {
+                        return gep;
+                    }
+                    let result = self.new_temp();
+                    self.load_into(&result, &llvm_field_type, &gep);
+                    return result;
                 }
-                self.block_terminated = false;
                 "0".to_string()
             }
 
-            AstNode::While { condition, body } => {
-                let cond_label = self.new_label("while_cond");
-                let body_label = self.new_label("while_body");
-                let end_label = self.new_label("while_end");
-
-                self.loop_stack.push(LoopLabels {
-                    continue_label: cond_label.clone(),
-                    break_label: end_label.clone(),
-                });
-
-                self.emit(&format!("  br label %{}", cond_label));
-                self.emit(&format!("{}:", cond_label));
-                let cond_reg = self.gen_node(condition);
-                self.emit(&format!(
-                    "  br i1 {}, label %{}, label %{}",
-                    cond_reg, body_label, end_label
-                ));
-
-                self.emit(&format!("{}:", body_label));
-                self.block_terminated = false;
-                self.gen_node(body);
-                if !self.block_terminated {
-                    self.emit(&format!("  br label %{}", cond_label));
-                }
-
-                self.emit(&format!("{}:", end_label));
-                self.loop_stack.pop();
-                self.block_terminated = false;
+            AstNode::EnumDef { name, variants, .. } => {
+                let variant_names: Vec<String> = variants.iter().map(|v| v.name.clone()).collect();
+                self.enum_types.insert(name.clone(), variant_names);
+                let fields = variants.iter().map(|v| v.fields.clone()).collect();
+                self.enum_fields.insert(name.clone(), fields);
                 "0".to_string()
             }
 
-            AstNode::For {
-                variable,
-                iterator,
-                body,
+            AstNode::EnumValue {
+                enum_name,
+                variant,
+                values,
+                ..
             } => {
-                let (start_val, end_val) = if let AstNode::BinaryOp {
-                    op: BinOp::DotDot,
-                    left,
-                    right,
-                } = iterator.as_ref()
-                {
-                    (self.gen_node(left), self.gen_node(right))
+                if enum_name == "Mutex" && variant == "new" {
+                    let inner_val = if let Some(v) = values.first() {
+                        let v = self.gen_node(v);
+                        self.coerce_i64(v)
+                    } else {
+                        "0".to_string()
+                    };
+                    let mutex_raw = self.new_temp();
+                    self.call(Some(&mutex_raw), "i8*", "@malloc", &[("i64", "48")]);
+                    self.call(None, "void", "@brn_mutex_init", &[("i8*", &mutex_raw)]);
+                    let val_gep = self.new_temp();
+                    self.gep(&val_gep, "i8", &mutex_raw, &[("i64", "40")]);
+                    let val_ptr = self.new_temp();
+                    self.cast(&val_ptr, "bitcast", "i8*", &val_gep, "i64*");
+                    self.store("i64", &inner_val, &val_ptr);
+                    return mutex_raw;
+                }
+
+                let tag = if let Some(variants) = self.enum_types.get(enum_name) {
+                    variants.iter().position(|v| v == variant).unwrap_or(0) as i64
                 } else {
-                    ("0".to_string(), self.gen_node(iterator))
+                    0
                 };
 
-                let start_label = self.new_label("for_start");
-                let body_label = self.new_label("for_body");
-                let end_label = self.new_label("for_end");
-
-                self.loop_stack.push(LoopLabels {
-                    continue_label: start_label.clone(),
-                    break_label: end_label.clone(),
-                });
+                let ptr = if self.returning {
+                    let raw = self.new_temp();
+                    let size = self.size_of(enum_name);
+                    self.call(Some(&raw), "i8*", "@malloc", &[("i64", &size.to_string())]);
+                    let ptr = self.new_temp();
+                    self.cast(&ptr, "bitcast", "i8*", &raw, "{ i32, i64 }*");
+                    ptr
+                } else {
+                    let ptr = self.new_temp();
+                    self.alloca_into(&ptr, "{ i32, i64 }");
+                    ptr
+                };
 
-                let loop_var = self.new_temp();
-                self.emit(&format!("  {} = alloca i64", loop_var));
-                self.emit(&format!("  store i64 {}, i64* {}", start_val, loop_var));
+                let tag_ptr = self.new_temp();
+                self.gep(
+                    &tag_ptr,
+                    "{ i32, i64 }",
+                    &ptr,
+                    &[("i32", "0"), ("i32", "0")],
+                );
+                self.store("i32", &tag.to_string(), &tag_ptr);
 
-                let end_ptr = self.new_temp();
-                self.emit(&format!("  {} = alloca i64", end_ptr));
-                self.emit(&format!("  store i64 {}, i64* {}", end_val, end_ptr));
+                let val = self.gen_payload(values);
 
-                self.current_function_vars.insert(
-                    variable.clone(),
-                    VarMetadata {
-                        llvm_name: loop_var.clone(),
-                        var_type: "int".to_string(),
-                        is_heap: false,
-                        array_size: None,
-                        is_string_literal: false,
-                    },
+                let val_ptr = self.new_temp();
+                self.gep(
+                    &val_ptr,
+                    "{ i32, i64 }",
+                    &ptr,
+                    &[("i32", "0"), ("i32", "1")],
                 );
+                self.store("i64", &val, &val_ptr);
 
-                self.emit(&format!("  br label %{}", start_label));
-                self.emit(&format!("{}:", start_label));
-
-                let current = self.new_temp();
-                let end_loaded = self.new_temp();
-                self.emit(&format!("  {} = load i64, i64* {}", current, loop_var));
-                self.emit(&format!("  {} = load i64, i64* {}", end_loaded, end_ptr));
+                ptr
+            }
 
-                let cond = self.new_temp();
-                self.emit(&format!(
-                    "  {} = icmp slt i64 {}, {}",
-                    cond, current, end_loaded
-                ));
-                self.emit(&format!(
-                    "  br i1 {}, label %{}, label %{}",
-                    cond, body_label, end_label
-                ));
+            AstNode::Match { value, arms, .. } => {
+                let scrutinee = self.gen_node(value);
+                let value_reg = scrutinee.reg.clone();
+                let end_label = self.new_label("match_end");
 
-                self.emit(&format!("{}:", body_label));
-                self.gen_node(body);
+                let is_enum_match = arms
+                    .iter()
+                    .any(|a| matches!(a.pattern, Pattern::EnumPattern { .. }));
+                let is_string_match = !is_enum_match
+                    && arms.iter().any(|a| {
+                        matches!(
+                            a.pattern,
+                            Pattern::StringPattern(_)
+                                | Pattern::PrefixPattern { .. }
+                                | Pattern::SuffixPattern { .. }
+                        )
+                    });
+
+                let destructures = arms.iter().any(|a| match &a.pattern {
+                    Pattern::EnumPattern {
+                        enum_name, payload, ..
+                    } => {
+                        enum_name.is_empty()
+                            || payload.len() > 1
+                            || payload
+                                .iter()
+                                .any(|p| !matches!(p, Pattern::Identifier(_) | Pattern::Wildcard))
+                    }
+                    Pattern::StructPattern { .. } => true,
+                    _ => false,
+                });
 
-                let curr2 = self.new_temp();
-                let next = self.new_temp();
-                self.emit(&format!("  {} = load i64, i64* {}", curr2, loop_var));
-                self.emit(&format!("  {} = add i64 {}, 1", next, curr2));
-                self.emit(&format!("  store i64 {}, i64* {}", next, loop_var));
-                self.emit(&format!("  br label %{}", start_label));
+                if is_string_match {
+                    self.gen_string_match(&value_reg, arms, &end_label);
+                } else if destructures {
+                    self.gen_destructuring_match(scrutinee, arms, &end_label);
+                } else {
+                    self.gen_switch_match(&value_reg, arms, is_enum_match, &end_label);
+                }
 
-                self.emit(&format!("{}:", end_label));
-                self.loop_stack.pop();
+                self.label(&end_label);
                 "0".to_string()
             }
 
-            AstNode::Break => {
-                if let Some(labels) = self.loop_stack.last() {
-                    let break_label = labels.break_label.clone();
-                    self.emit(&format!("  br label %{}", break_label));
-                    self.block_terminated = true;
+            AstNode::FunctionDef {
+                name,
+                params,
+                body,
+                return_type,
+                is_unsafe,
+                ..
+            } => self.gen_function(name, params, body, return_type, *is_unsafe),
+
+            AstNode::LetBinding {
+                mutable,
+                name,
+                value,
+                type_annotation,
+                ..
+            } => {
+                self.current_binding = Some(name.clone());
+                let generated = self.gen_node(value);
+                self.current_binding = None;
+                let mut value_reg = generated.reg.clone();
+                let var_type = match type_annotation {
+                    // `let n: int = c;` widens a `char`, `let c: char = 65;`
+                    // narrows the literal
+                    Some(t)
+                        if (t == "int" || t == "char")
+                            && matches!(generated.llvm_ty.as_str(), "i64" | "i8" | "i1") =>
+                    {
+                        value_reg = self.convert(generated, t);
+                        t.clone()
+                    }
+                    // `let v: Vec<string> = vec_new();` picks the element type
+                    Some(t) if t.starts_with("Vec<") && self.infer_type(value) == "Vec" => {
+                        t.clone()
+                    }
+                    Some(t) if t.starts_with('*') => {
+                        value_reg = self.store_through_pointer(value, value_reg);
+                        t.clone()
+                    }
+                    _ => self.infer_type(value),
+                };
+                // `let q = p;` moves a heap struct: only q frees it now
+                if let AstNode::Identifier { name: source, .. } = value.as_ref()
+                    && let Some(meta) = self.current_function_vars.get(source)
+                    && meta.is_heap
+                    && self.struct_types.contains_key(&meta.var_type)
+                {
+                    self.moved_vars.insert(meta.llvm_name.clone());
                 }
-                "0".to_string()
-            }
 
-            AstNode::Continue => {
-                if let Some(labels) = self.loop_stack.last() {
-                    let continue_label = labels.continue_label.clone();
-                    self.emit(&format!("  br label %{}", continue_label));
-                    self.block_terminated = true;
+                // If the value is a .lock() call, register this binding as a guard
+                if let AstNode::MethodCall { method, .. } = value.as_ref()
+                    && method == "lock"
+                    && !self.is_unsafe_fn
+                {
+                    self.guard_vars.insert(name.clone());
                 }
-                "0".to_string()
-            }
 
-            AstNode::Return(value) => {
-                if let Some(value) = value {
-                    let value_reg = self.gen_node(value);
-                    let ret_type = self.current_function_return_type.clone();
-                    self.emit(&format!("  ret {} {}", ret_type, value_reg));
-                } else if self.current_function_return_type == "void" {
-                    self.emit("  ret void");
+                let is_struct = self.struct_types.contains_key(&var_type);
+                let is_value_struct = self.is_value_struct(&var_type);
+                // Structs live on the stack when StructInit put them there
+                let stack_promote = if is_struct {
+                    is_value_struct
+                        || (self.non_escaping.contains(name)
+                            && matches!(value.as_ref(), AstNode::StructInit { .. }))
                 } else {
-                    let ret_type = self.current_function_return_type.clone();
-                    self.emit(&format!("  ret {} 0", ret_type));
+                    self.non_escaping.contains(name)
+                };
+                // `let r = &x;` borrows: x is neither copied nor freed through r
+                let is_borrow = matches!(value.as_ref(), AstNode::Reference(..));
+                // A value struct read from another place gets its own copy;
+                // literals and call results are already fresh.
+                if is_value_struct
+                    && !is_borrow
+                    && !matches!(
+                        value.as_ref(),
+                        AstNode::StructInit { .. } | AstNode::Call { .. }
+                    )
+                {
+                    value_reg = self.copy_struct(&var_type, &value_reg, false);
                 }
-                self.block_terminated = true;
-                "0".to_string()
+
+                // A string binding owns what it holds (see `StringOwnership`),
+                // unless it is immutable and holds a literal, or holds a
+                // concatenation built on the stack for it
+                let mut owns_string = false;
+                if var_type == "string" && !is_borrow {
+                    match self.string_ownership(value) {
+                        StringOwnership::Static if !*mutable => {}
+                        StringOwnership::Owned
+                            if stack_promote
+                                && matches!(value.as_ref(), AstNode::BinaryOp { .. }) => {}
+                        StringOwnership::Owned => owns_string = true,
+                        _ => {
+                            value_reg = self.take_string(value, value_reg);
+                            owns_string = true;
+                        }
+                    }
+                }
+
+                let is_mutex =
+                    var_type.starts_with("Mutex<") || var_type.starts_with("MutexGuard<");
+                let is_heap = owns_string
+                    || !stack_promote
+                        && !is_mutex
+                        && !is_borrow
+                        && (Self::is_vec_type(&var_type)
+                            || var_type == "StringBuilder"
+                            || var_type == "Bytes"
+                            || is_struct)
+                    || var_type == "LineReader";
+
+                if let AstNode::ArrayLit(elements, _) = value.as_ref() {
+                    let size = elements.len();
+                    let sized_type = format!("[{}; int]", size);
+                    self.current_function_vars.insert(
+                        name.clone(),
+                        VarMetadata {
+                            llvm_name: value_reg.clone(),
+                            var_type: sized_type,
+                            is_heap: false,
+                            array_size: Some(size),
+                        },
+                    );
+                    return value_reg;
+                }
+
+                if self.struct_types.contains_key(&var_type) && !is_heap {
+                    self.current_function_vars.insert(
+                        name.clone(),
+                        VarMetadata {
+                            llvm_name: value_reg.clone(),
+                            var_type,
+                            is_heap: false,
+                            array_size: None,
+                        },
+                    );
+                    return value_reg;
+                }
+
+                let ptr = self.new_temp();
+                let llvm_type_str = self.type_to_llvm(&var_type);
+                self.alloca_into(&ptr, &llvm_type_str);
+                self.store(&llvm_type_str, &value_reg, &ptr);
+
+                self.current_function_vars.insert(
+                    name.clone(),
+                    VarMetadata {
+                        llvm_name: ptr.clone(),
+                        var_type,
+                        is_heap,
+                        array_size: None,
+                    },
+                );
+
+                ptr
             }
 
-            AstNode::Block(statements) => {
-                let mut last_reg = String::new();
-                // Snapshot only the key sets — cheaper than cloning all VarMetadata values
-                let keys_before: std::collections::HashSet<String> =
-                    self.current_function_vars.keys().cloned().collect();
-                let guards_before = self.guard_vars.clone();
+            AstNode::ArrayAssignment {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                let index_val = self.gen_node(index).reg;
+                let value = self.gen_node(value);
+                let value_reg = self.coerce_i64(value);
 
-                for stmt in statements {
-                    last_reg = self.gen_node(stmt);
+                if let Some(meta) = self.current_function_vars.get(array).cloned() {
+                    let array_size = meta.array_size.unwrap_or(100);
+                    let elem_ptr = self.new_temp();
+                    self.gep(
+                        &elem_ptr,
+                        &format!("[{} x i64]", array_size),
+                        &meta.llvm_name,
+                        &[("i64", "0"), ("i64", &index_val)],
+                    );
+                    self.store("i64", &value_reg, &elem_ptr);
                 }
 
-                // Guards that were created in this block — unlock at scope exit
-                let guards_to_unlock: Vec<_> = self
-                    .current_function_vars
-                    .iter()
-                    .filter(|(name, meta)| {
-                        meta.var_type.starts_with("MutexGuard<")
-                            && !keys_before.contains(name.as_str())
-                            && !self.is_unsafe_fn
-                    })
-                    .map(|(_, meta)| meta.llvm_name.clone())
-                    .collect();
+                value_reg
+            }
 
-                let vars_to_free: Vec<_> = self
-                    .current_function_vars
-                    .iter()
-                    .filter(|(name, meta)| {
-                        meta.is_heap
-                            && !meta.is_string_literal
-                            && !keys_before.contains(name.as_str())
-                    })
-                    .map(|(_, meta)| (meta.llvm_name.clone(), meta.var_type.clone()))
-                    .collect();
+            AstNode::Assignment { name, value, .. } => {
+                let generated = self.gen_node(value);
+                let mut value_reg = generated.reg.clone();
 
-                if !self.block_terminated {
-                    // Emit LeaveCriticalSection for each guard going out of scope
-                    for guard_slot in guards_to_unlock {
-                        let mutex_ptr = self.new_temp();
-                        self.emit(&format!("  {} = load i8*, i8** {}", mutex_ptr, guard_slot));
-                        self.emit(&format!(
-                            "  call void @LeaveCriticalSection(i8* {})",
-                            mutex_ptr
-                        ));
+                if let Some(meta) = self.current_function_vars.get(name).cloned() {
+                    if meta.var_type == "int" || meta.var_type == "char" {
+                        value_reg = self.convert(generated, &meta.var_type);
                     }
-
-                    for (llvm_name, var_type) in vars_to_free {
-                        if self.struct_types.contains_key(&var_type) {
-                            let struct_ptr = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = load %{}*, %{}** {}",
-                                struct_ptr, var_type, var_type, llvm_name
-                            ));
-                            let i8_ptr = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = bitcast %{}* {} to i8*",
-                                i8_ptr, var_type, struct_ptr
-                            ));
-                            self.emit(&format!("  call void @free(i8* {})", i8_ptr));
-                        } else if var_type == "Vec" {
-                            let ptr_reg = self.new_temp();
-                            self.emit(&format!("  {} = load i8*, i8** {}", ptr_reg, llvm_name));
-                            let dp_raw = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = getelementptr i8, i8* {}, i64 16",
-                                dp_raw, ptr_reg
-                            ));
-                            let dp = self.new_temp();
-                            self.emit(&format!("  {} = bitcast i8* {} to i8**", dp, dp_raw));
-                            let data = self.new_temp();
-                            self.emit(&format!("  {} = load i8*, i8** {}", data, dp));
-                            self.emit(&format!("  call void @free(i8* {})", data));
-                            self.emit(&format!("  call void @free(i8* {})", ptr_reg));
+                    if meta.var_type == "string" && meta.is_heap {
+                        // The variable owns its string: it takes the new one
+                        // and frees the one it replaces
+                        let owned = self.take_string(value, value_reg.clone());
+                        let old = self.new_temp();
+                        self.load_into(&old, "i8*", &meta.llvm_name);
+                        self.store("i8*", &owned, &meta.llvm_name);
+                        self.call(None, "void", "@free", &[("i8*", &old)]);
+                    } else if self.is_value_struct(&meta.var_type) {
+                        // The variable is the struct's own stack slot: copy into it
+                        self.gen_store_field(&meta.var_type, &value_reg, &meta.llvm_name);
+                    } else {
+                        let stored = if meta.var_type.starts_with('*') {
+                            self.store_through_pointer(value, value_reg.clone())
                         } else {
-                            let ptr_reg = self.new_temp();
-                            self.emit(&format!("  {} = load i8*, i8** {}", ptr_reg, llvm_name));
-                            self.emit(&format!("  call void @free(i8* {})", ptr_reg));
-                        }
+                            value_reg.clone()
+                        };
+                        let llvm_type_str = self.type_to_llvm(&meta.var_type);
+                        let llvm_name = meta.llvm_name.clone();
+                        self.store(&llvm_type_str, &stored, &llvm_name);
                     }
                 }
 
-                // Remove vars introduced in this block; restore guard tracking
-                self.current_function_vars
-                    .retain(|k, _| keys_before.contains(k));
-                self.guard_vars = guards_before;
-
-                last_reg
+                value_reg
             }
 
-            AstNode::ExpressionStatement(expr) => self.gen_node(expr),
-
-            AstNode::BinaryOp { op, left, right } => {
-                let left_reg = self.gen_node(left);
-                let right_reg = self.gen_node(right);
+            AstNode::MemberAssignment {
+                object,
+                field,
+                value,
+                ..
+            } => {
+                let value_reg = self.gen_node(value).reg;
+
+                let guard_name = match object.as_ref() {
+                    AstNode::Identifier { name, .. }
+                        if self.guard_vars.contains(name.as_str())
+                            || self
+                                .current_function_vars
+                                .get(name.as_str())
+                                .map(|m| m.var_type.starts_with("MutexGuard<"))
+                                .unwrap_or(false) =>
+                    {
+                        Some(name.as_str())
+                    }
+                    _ => None,
+                };
 
-                match op {
-                    BinOp::DotDot => right_reg,
-                    BinOp::Add => {
-                        if self.infer_type(left) == "string" {
-                            let result = self.gen_string_concat(&left_reg, &right_reg);
-                            let free_if_owned = |cg: &mut CodeGenerator, node: &AstNode| {
-                                if let AstNode::Identifier { name, .. } = node {
-                                    if let Some(meta) = cg.current_function_vars.get(name).cloned()
-                                    {
-                                        if !meta.is_string_literal {
-                                            let loaded = cg.new_temp();
-                                            cg.emit(&format!(
-                                                "  {} = load i8*, i8** {}",
-                                                loaded, meta.llvm_name
-                                            ));
-                                            cg.emit(&format!("  call void @free(i8* {})", loaded));
-                                        }
-                                    }
-                                }
-                            };
-                            free_if_owned(self, right);
-                            free_if_owned(self, left);
-                            result
+                if let Some(guard_name) = guard_name
+                    && field == "value"
+                    && !self.is_unsafe_fn
+                {
+                    // volatile store through the mutex guard
+                    if let Some(meta) = self.current_function_vars.get(guard_name).cloned() {
+                        let guard_ptr = if meta.llvm_name.starts_with("%arg_") {
+                            meta.llvm_name.clone()
                         } else {
-                            let result = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = add i64 {}, {}",
-                                result, left_reg, right_reg
-                            ));
-                            result
-                        }
-                    }
-                    BinOp::Sub => {
-                        let result = self.new_temp();
+                            let loaded = self.new_temp();
+                            self.load_into(&loaded, "i8*", &meta.llvm_name);
+                            loaded
+                        };
+                        let val_gep = self.new_temp();
+                        self.gep(&val_gep, "i8", &guard_ptr, &[("i64", "40")]);
+                        let val_ptr = self.new_temp();
+                        self.cast(&val_ptr, "bitcast", "i8*", &val_gep, "i64*");
                         self.emit(&format!(
-                            "  {} = sub i64 {}, {}",
-                            result, left_reg, right_reg
+                            "  store volatile i64 {}, i64* {}",
+                            value_reg, val_ptr
                         ));
-                        result
                     }
-                    BinOp::Mul => {
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = mul i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
-                        result
+                } else {
+                    let struct_name = self.infer_struct_name(object);
+                    if let Some(struct_fields) = self.struct_types.get(&struct_name).cloned()
+                        && let Some(field_idx) = struct_fields.iter().position(|(n, _)| n == field)
+                    {
+                        // The object evaluates to its %StructName*, however deep the chain
+                        let obj_ptr = self.gen_node(object).reg;
+                        let field_type = struct_fields[field_idx].1.clone();
+                        let stored = if field_type.starts_with('*') {
+                            self.store_through_pointer(value, value_reg.clone())
+                        } else if field_type == "string"
+                            && self.string_ownership(value) == StringOwnership::Borrowed
+                        {
+                            // As in a struct literal, the field takes the string
+                            self.take_string(value, value_reg.clone())
+                        } else {
+                            if let AstNode::Identifier { name, .. } = value.as_ref()
+                                && let Some(meta) = self.current_function_vars.get(name)
+                                && meta.is_heap
+                                && self.struct_types.contains_key(&meta.var_type)
+                            {
+                                self.moved_vars.insert(meta.llvm_name.clone());
+                            }
+                            value_reg.clone()
+                        };
+                        let gep = self.new_temp();
+                        self.gep(
+                            &gep,
+                            &format!("%{}", struct_name),
+                            &obj_ptr,
+                            &[("i32", "0"), ("i32", &field_idx.to_string())],
+                        );
+                        self.gen_store_field(&field_type, &stored, &gep);
+                    }
+                }
+
+                value_reg
+            }
+
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                let cond = self.gen_node(condition);
+                let cond_reg = self.coerce_i1(cond);
+                let then_label = self.new_label("then");
+                let else_label = self.new_label("else");
+                let end_label = self.new_label("endif");
+
+                if else_block.is_some() {
+                    self.cond_br(&cond_reg, &then_label, &else_label);
+                } else {
+                    self.cond_br(&cond_reg, &then_label, &end_label);
+                }
+
+                self.label(&then_label);
+                self.gen_node(then_block);
+                let then_terminated = self.block_terminated();
+                if !self.block_terminated() {
+                    self.br(&end_label);
+                }
+
+                let mut else_terminated = false;
+                if let Some(else_block) = else_block {
+                    self.label(&else_label);
+                    self.gen_node(else_block);
+                    else_terminated = self.block_terminated();
+                    if !self.block_terminated() {
+                        self.br(&end_label);
+                    }
+                }
+
+                self.label(&end_label);
+                // Both branches left: nothing reaches the end
+                if then_terminated && else_terminated {
+                    self.push(Inst::Unreachable);
+                }
+                "0".to_string()
+            }
+
+            AstNode::While {
+                condition, body, ..
+            } => {
+                let cond_label = self.new_label("while_cond");
+                let body_label = self.new_label("while_body");
+                let end_label = self.new_label("while_end");
+
+                self.loop_stack.push(LoopLabels {
+                    continue_label: cond_label.clone(),
+                    break_label: end_label.clone(),
+                });
+
+                let scope = self.enter_loop(&[condition, body], None);
+                self.br(&cond_label);
+                self.label(&cond_label);
+                let cond = self.gen_node(condition);
+                let cond_reg = self.coerce_i1(cond);
+                self.cond_br(&cond_reg, &body_label, &end_label);
+
+                self.label(&body_label);
+                self.gen_node(body);
+                if !self.block_terminated() {
+                    self.br(&cond_label);
+                }
+
+                self.label(&end_label);
+                self.leave_loop(scope);
+                self.loop_stack.pop();
+                "0".to_string()
+            }
+
+            AstNode::For {
+                variable,
+                index,
+                iterator,
+                body,
+                ..
+            } => {
+                if let Some(source) = self.iterator_source(iterator) {
+                    return self.gen_for_iterator(variable, index.as_deref(), &source, body);
+                }
+                if Self::is_vec_type(&self.infer_type(iterator)) {
+                    return self.gen_for_vec(variable, index.as_deref(), iterator, body);
+                }
+
+                let (start_val, end_val) = if let AstNode::BinaryOp {
+                    op: BinOp::DotDot,
+                    left,
+                    right,
+                    ..
+                } = iterator.as_ref()
+                {
+                    (self.gen_node(left).reg, self.gen_node(right).reg)
+                } else {
+                    ("0".to_string(), self.gen_node(iterator).reg)
+                };
+
+                let start_label = self.new_label("for_start");
+                let body_label = self.new_label("for_body");
+                let step_label = self.new_label("for_step");
+                let end_label = self.new_label("for_end");
+
+                // `continue` still has to advance the loop variable
+                self.loop_stack.push(LoopLabels {
+                    continue_label: step_label.clone(),
+                    break_label: end_label.clone(),
+                });
+
+                let loop_var = self.new_temp();
+                self.alloca_into(&loop_var, "i64");
+                self.store("i64", &start_val, &loop_var);
+
+                let end_ptr = self.new_temp();
+                self.alloca_into(&end_ptr, "i64");
+                self.store("i64", &end_val, &end_ptr);
+
+                let scope = self.enter_loop(&[body], Some((variable, &start_val)));
+                self.current_function_vars.insert(
+                    variable.clone(),
+                    VarMetadata {
+                        llvm_name: loop_var.clone(),
+                        var_type: "int".to_string(),
+                        is_heap: false,
+                        array_size: None,
+                    },
+                );
+
+                self.br(&start_label);
+                self.label(&start_label);
+
+                let current = self.new_temp();
+                let end_loaded = self.new_temp();
+                self.load_into(&current, "i64", &loop_var);
+                self.load_into(&end_loaded, "i64", &end_ptr);
+
+                let cond = self.new_temp();
+                self.icmp(&cond, "slt", "i64", &current, &end_loaded);
+                self.cond_br(&cond, &body_label, &end_label);
+
+                self.label(&body_label);
+                if let Some(index) = index {
+                    let count = self.new_temp();
+                    self.binary(&count, "sub", "i64", &current, &start_val);
+                    self.bind_loop_index(index, &count);
+                }
+                self.gen_node(body);
+                if !self.block_terminated() {
+                    self.br(&step_label);
+                }
+
+                self.label(&step_label);
+                let curr2 = self.new_temp();
+                let next = self.new_temp();
+                self.load_into(&curr2, "i64", &loop_var);
+                self.binary(&next, "add", "i64", &curr2, "1");
+                self.store("i64", &next, &loop_var);
+                for (slot, step) in scope.counter_steps.clone() {
+                    self.emit_slot_add(&slot, step);
+                }
+                self.br(&start_label);
+
+                self.label(&end_label);
+                self.leave_loop(scope);
+                self.loop_stack.pop();
+                "0".to_string()
+            }
+
+            AstNode::Break(_) => {
+                if let Some(labels) = self.loop_stack.last() {
+                    let break_label = labels.break_label.clone();
+                    self.br(&break_label);
+                }
+                "0".to_string()
+            }
+
+            AstNode::Continue(_) => {
+                if let Some(labels) = self.loop_stack.last() {
+                    let continue_label = labels.continue_label.clone();
+                    self.br(&continue_label);
+                }
+                "0".to_string()
+            }
+
+            AstNode::Return(value, _) => {
+                if let Some(value) = value {
+                    self.returning = true;
+                    let generated = self.gen_node(value);
+                    self.returning = false;
+                    let mut value_reg = self.returned_string(value, generated.reg.clone());
+                    let ret_type = self.current_function_return_type.clone();
+                    // An `int` or `char` return converts what it is given;
+                    // `main`'s exit status is an `int`
+                    let to = match ret_type.as_str() {
+                        "i64" => Some("int"),
+                        "i8" => Some("char"),
+                        "i32" if generated.llvm_ty == "i8" => Some("int"),
+                        _ => None,
+                    };
+                    if let Some(to) = to {
+                        value_reg = self.convert(generated, to);
+                    }
+                    // `main` returns the process exit status as a C int
+                    if self.current_function_name == "main" {
+                        let widened = self.infer_type(value) == "bool";
+                        let code = self.new_temp();
+                        self.cast(
+                            &code,
+                            if widened { "zext" } else { "trunc" },
+                            if widened { "i1" } else { "i64" },
+                            &value_reg,
+                            "i32",
+                        );
+                        value_reg = code;
+                    }
+                    if let Some(struct_name) = ret_type.strip_prefix('%')
+                        && !struct_name.ends_with('*')
+                    {
+                        let whole = self.new_temp();
+                        self.load_into(&whole, &ret_type, &value_reg);
+                        value_reg = whole;
+                    }
+                    self.free_on_return(Some(value));
+                    self.emit_function_exit();
+                    self.ret(&ret_type, &value_reg);
+                } else if self.current_function_return_type == "void" {
+                    self.free_on_return(None);
+                    self.emit_function_exit();
+                    self.push(Inst::Ret(None));
+                } else {
+                    self.emit_function_exit();
+                    let ret_type = self.current_function_return_type.clone();
+                    self.ret(&ret_type, "0");
+                }
+                "0".to_string()
+            }
+
+            AstNode::Block(statements, _) => {
+                let mut last_reg = String::new();
+                // Snapshot only the key sets — cheaper than cloning all VarMetadata values
+                let keys_before: std::collections::HashSet<String> =
+                    self.current_function_vars.keys().cloned().collect();
+                let guards_before = self.guard_vars.clone();
+
+                for stmt in statements {
+                    last_reg = self.gen_node(stmt).reg;
+                    // What follows a `return`, `break` or `continue` never runs
+                    if self.block_terminated() {
+                        break;
+                    }
+                }
+
+                // Guards that were created in this block — unlock at scope exit
+                let mut guards_to_unlock: Vec<_> = self
+                    .current_function_vars
+                    .iter()
+                    .filter(|(name, meta)| {
+                        meta.var_type.starts_with("MutexGuard<")
+                            && !keys_before.contains(name.as_str())
+                            && !self.is_unsafe_fn
+                    })
+                    .map(|(_, meta)| meta.llvm_name.clone())
+                    .collect();
+
+                let mut vars_to_free: Vec<_> = self
+                    .current_function_vars
+                    .iter()
+                    .filter(|(name, meta)| {
+                        meta.is_heap
+                            && !keys_before.contains(name.as_str())
+                            && !self.moved_vars.contains(&meta.llvm_name)
+                    })
+                    .map(|(_, meta)| (meta.llvm_name.clone(), meta.var_type.clone()))
+                    .collect();
+                // In a fixed order rather than the map's
+                guards_to_unlock.sort();
+                vars_to_free.sort();
+
+                if !self.block_terminated() {
+                    // Unlock each guard going out of scope
+                    for guard_slot in guards_to_unlock {
+                        let mutex_ptr = self.new_temp();
+                        self.load_into(&mutex_ptr, "i8*", &guard_slot);
+                        self.call(None, "void", "@brn_mutex_unlock", &[("i8*", &mutex_ptr)]);
+                    }
+
+                    self.free_vars(vars_to_free);
+                }
+
+                // Remove vars introduced in this block; restore guard tracking
+                self.current_function_vars
+                    .retain(|k, _| keys_before.contains(k));
+                self.guard_vars = guards_before;
+
+                last_reg
+            }
+
+            AstNode::ExpressionStatement(expr, _) => self.gen_node(expr).reg,
+
+            AstNode::BinaryOp {
+                op,
+                left,
+                right,
+                location,
+                ..
+            } => {
+                if matches!(op, BinOp::Add) && self.infer_type(left) == "string" {
+                    let mut parts = Vec::new();
+                    self.collect_concat_operands(node, &mut parts);
+                    if parts.len() > 2 && !self.concat_on_stack() {
+                        return self.gen_string_concat_chain(&parts);
+                    }
+                }
+
+                let left_val = self.gen_node(left);
+                let right_val = self.gen_node(right);
+                let left_reg = left_val.reg.clone();
+                let right_reg = right_val.reg.clone();
+
+                match op {
+                    BinOp::DotDot => right_reg,
+                    BinOp::Add => {
+                        if self.infer_type(left) == "string" {
+                            let result = self.gen_string_concat(&left_reg, &right_reg);
+                            self.free_if_temporary(right, &right_reg);
+                            self.free_if_temporary(left, &left_reg);
+                            result
+                        } else {
+                            // A `char` or `bool` operand is widened to an `int`
+                            let left_reg = self.coerce_i64(left_val);
+                            let right_reg = self.coerce_i64(right_val);
+                            let result = self.new_temp();
+                            self.binary(&result, "add", "i64", &left_reg, &right_reg);
+                            result
+                        }
                     }
-                    BinOp::Div => {
+                    BinOp::Sub => {
+                        let left_reg = self.coerce_i64(left_val);
+                        let right_reg = self.coerce_i64(right_val);
                         let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = sdiv i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
+                        self.binary(&result, "sub", "i64", &left_reg, &right_reg);
                         result
                     }
-                    BinOp::Mod => {
+                    BinOp::Mul => {
+                        let left_reg = self.coerce_i64(left_val);
+                        let right_reg = self.coerce_i64(right_val);
                         let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = srem i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
+                        self.binary(&result, "mul", "i64", &left_reg, &right_reg);
                         result
                     }
+                    BinOp::Div | BinOp::Mod => {
+                        let left_reg = self.coerce_i64(left_val);
+                        let right_reg = self.coerce_i64(right_val);
+                        let op = if matches!(op, BinOp::Div) {
+                            "sdiv"
+                        } else {
+                            "srem"
+                        };
+                        self.gen_division(op, &left_reg, right, &right_reg, location)
+                    }
                     BinOp::Equal => {
-                        if self.infer_type(left) == "string" {
+                        if self.is_struct_comparison(&left_val, &right_val) {
+                            self.gen_struct_eq(&left_val, &right_val)
+                        } else if let Some(ptr_type) = self.pointer_cmp_type(left, right) {
+                            let result = self.new_temp();
+                            self.icmp(&result, "eq", &ptr_type, &left_reg, &right_reg);
+                            result
+                        } else if self.infer_type(left) == "string" {
                             let cmp = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = call i32 @strcmp(i8* {}, i8* {})",
-                                cmp, left_reg, right_reg
-                            ));
+                            self.call(
+                                Some(&cmp),
+                                "i32",
+                                "@strcmp",
+                                &[("i8*", &left_reg), ("i8*", &right_reg)],
+                            );
                             let result = self.new_temp();
-                            self.emit(&format!("  {} = icmp eq i32 {}, 0", result, cmp));
+                            self.icmp(&result, "eq", "i32", &cmp, "0");
                             result
                         } else {
+                            // A `bool` compares with an `int` or another
+                            // `bool` as 0 or 1
+                            let left_int = self.coerce_i64(left_val);
+                            let right_int = self.coerce_i64(right_val);
                             let result = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = icmp eq i64 {}, {}",
-                                result, left_reg, right_reg
-                            ));
+                            self.icmp(&result, "eq", "i64", &left_int, &right_int);
                             result
                         }
                     }
                     BinOp::NotEqual => {
-                        if self.infer_type(left) == "string" {
+                        if self.is_struct_comparison(&left_val, &right_val) {
+                            let eq = self.gen_struct_eq(&left_val, &right_val);
+                            let result = self.new_temp();
+                            self.binary(&result, "xor", "i1", &eq, "true");
+                            result
+                        } else if let Some(ptr_type) = self.pointer_cmp_type(left, right) {
+                            let result = self.new_temp();
+                            self.icmp(&result, "ne", &ptr_type, &left_reg, &right_reg);
+                            result
+                        } else if self.infer_type(left) == "string" {
                             let cmp = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = call i32 @strcmp(i8* {}, i8* {})",
-                                cmp, left_reg, right_reg
-                            ));
+                            self.call(
+                                Some(&cmp),
+                                "i32",
+                                "@strcmp",
+                                &[("i8*", &left_reg), ("i8*", &right_reg)],
+                            );
                             let result = self.new_temp();
-                            self.emit(&format!("  {} = icmp ne i32 {}, 0", result, cmp));
+                            self.icmp(&result, "ne", "i32", &cmp, "0");
                             result
                         } else {
+                            // A `bool` compares with an `int` or another
+                            // `bool` as 0 or 1
+                            let left_int = self.coerce_i64(left_val);
+                            let right_int = self.coerce_i64(right_val);
                             let result = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = icmp ne i64 {}, {}",
-                                result, left_reg, right_reg
-                            ));
+                            self.icmp(&result, "ne", "i64", &left_int, &right_int);
                             result
                         }
                     }
-                    BinOp::LessThan => {
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = icmp slt i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
-                        result
-                    }
-                    BinOp::LessEqual => {
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = icmp sle i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
-                        result
-                    }
-                    BinOp::GreaterThan => {
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = icmp sgt i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
-                        result
-                    }
-                    BinOp::GreaterEqual => {
-                        let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = icmp sge i64 {}, {}",
-                            result, left_reg, right_reg
-                        ));
-                        result
+                    BinOp::LessThan
+                    | BinOp::LessEqual
+                    | BinOp::GreaterThan
+                    | BinOp::GreaterEqual => {
+                        let pred = match op {
+                            BinOp::LessThan => "slt",
+                            BinOp::LessEqual => "sle",
+                            BinOp::GreaterThan => "sgt",
+                            _ => "sge",
+                        };
+                        let left_reg = self.coerce_i64(left_val);
+                        let right_reg = self.coerce_i64(right_val);
+                        self.gen_ordering(pred, left, right, &left_reg, &right_reg)
                     }
                     BinOp::And => {
+                        let left_reg = self.coerce_i1(left_val);
+                        let right_reg = self.coerce_i1(right_val);
                         let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = and i1 {}, {}",
-                            result, left_reg, right_reg
-                        ));
+                        self.binary(&result, "and", "i1", &left_reg, &right_reg);
                         result
                     }
                     BinOp::Or => {
+                        let left_reg = self.coerce_i1(left_val);
+                        let right_reg = self.coerce_i1(right_val);
                         let result = self.new_temp();
-                        self.emit(&format!("  {} = or i1 {}, {}", result, left_reg, right_reg));
+                        self.binary(&result, "or", "i1", &left_reg, &right_reg);
                         result
                     }
                 }
             }
 
-            AstNode::UnaryOp { op, operand } => {
-                let operand_reg = self.gen_node(operand);
-                let result = self.new_temp();
+            AstNode::Cast { value, target, .. } => {
+                let value = self.gen_node(value);
+                self.convert(value, target)
+            }
+
+            AstNode::UnaryOp { op, operand, .. } => {
+                let operand = self.gen_node(operand);
 
                 match op {
                     crate::parser::UnOp::Not => {
-                        self.emit(&format!("  {} = xor i1 {}, true", result, operand_reg));
+                        let operand_reg = self.coerce_i1(operand);
+                        let result = self.new_temp();
+                        self.binary(&result, "xor", "i1", &operand_reg, "true");
+                        result
                     }
                     crate::parser::UnOp::Negate => {
-                        self.emit(&format!("  {} = sub i64 0, {}", result, operand_reg));
+                        let operand_reg = self.coerce_i64(operand);
+                        let result = self.new_temp();
+                        self.binary(&result, "sub", "i64", "0", &operand_reg);
+                        result
                     }
                 }
-
-                result
             }
 
-            AstNode::Number(n) => n.to_string(),
+            AstNode::Number(n, _) => n.to_string(),
 
-            AstNode::Boolean(b) => if *b { "1" } else { "0" }.to_string(),
+            AstNode::Boolean(b, _) => if *b { "1" } else { "0" }.to_string(),
 
-            AstNode::Character(c) => (*c as i64).to_string(),
+            AstNode::Null(_) => "null".to_string(),
 
-            AstNode::StringLit(s) => {
+            AstNode::Character(c, _) => (*c as i64).to_string(),
+
+            AstNode::SizeOf(ty, _) => self.size_of(ty).to_string(),
+
+            AstNode::StringLit(s, _) => {
                 let id = self.new_string_literal(s);
                 let ptr = self.new_temp();
                 let len = s.len() + 1;
+                self.gep_inbounds(
+                    &ptr,
+                    &format!("[{} x i8]", len),
+                    &format!("@{}", id),
+                    &[("i64", "0"), ("i64", "0")],
+                );
+                ptr
+            }
+
+            // A new buffer each time, since `Bytes` can be written to
+            AstNode::BytesLit(bytes, _) => {
+                let id = self.new_byte_literal(bytes);
+                let result = self.new_temp();
                 self.emit(&format!(
-                    "  {} = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
-                    ptr, len, len, id
+                    "  {} = call i8* @bytes_from_buffer_impl(i8* getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0), i64 {})",
+                    result,
+                    bytes.len(),
+                    bytes.len(),
+                    id,
+                    bytes.len()
                 ));
-                ptr
+                result
             }
 
-            AstNode::ArrayLit(elements) => {
+            AstNode::ArrayLit(elements, _) => {
                 if elements.is_empty() {
                     return "null".to_string();
                 }
@@ -2213,23 +4897,42 @@ impl CodeGenerator {
                 let size = elements.len();
                 let array_type = format!("[{} x i64]", size);
                 let ptr = self.new_temp();
-                self.emit(&format!("  {} = alloca {}", ptr, array_type));
+                self.alloca_into(&ptr, &array_type);
+
+                // Arrays can be assigned into, so a constant literal is
+                // copied out of its global rather than referenced
+                if let Some(values) = Self::constant_array(elements) {
+                    let id = self.new_array_literal(values);
+                    let whole = self.new_temp();
+                    self.load_into(&whole, &array_type, &format!("@{}", id));
+                    self.store(&array_type, &whole, &ptr);
+                    return ptr;
+                }
 
                 for (i, elem) in elements.iter().enumerate() {
                     let value = self.gen_node(elem);
+                    let value = self.coerce_i64(value);
                     let elem_ptr = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = getelementptr [{} x i64], [{} x i64]* {}, i64 0, i64 {}",
-                        elem_ptr, size, size, ptr, i
-                    ));
-                    self.emit(&format!("  store i64 {}, i64* {}", value, elem_ptr));
+                    self.gep(
+                        &elem_ptr,
+                        &format!("[{} x i64]", size),
+                        &ptr,
+                        &[("i64", "0"), ("i64", &i.to_string())],
+                    );
+                    self.store("i64", &value, &elem_ptr);
                 }
 
                 ptr
             }
 
-            AstNode::Index { array, index } => {
-                let index_val = self.gen_node(index);
+            AstNode::Index { array, index, .. } => {
+                let array_type = self.infer_type(array);
+                if Self::is_vec_type(&array_type) {
+                    let vec_reg = self.gen_node(array).reg;
+                    let idx_reg = self.gen_node(index).reg;
+                    return self.gen_vec_element(&array_type, &vec_reg, &idx_reg);
+                }
+                let index_val = self.gen_node(index).reg;
 
                 let (array_ptr, array_size) = match array.as_ref() {
                     AstNode::Identifier { name, .. } => {
@@ -2241,35 +4944,60 @@ impl CodeGenerator {
                             return "0".to_string();
                         }
                     }
-                    _ => (self.gen_node(array), 100),
+                    _ => (self.gen_node(array).reg, 100),
                 };
 
                 let elem_ptr = self.new_temp();
                 let result = self.new_temp();
 
-                self.emit(&format!(
-                    "  {} = getelementptr [{} x i64], [{} x i64]* {}, i64 0, i64 {}",
-                    elem_ptr, array_size, array_size, array_ptr, index_val
-                ));
-                self.emit(&format!("  {} = load i64, i64* {}", result, elem_ptr));
+                self.gep(
+                    &elem_ptr,
+                    &format!("[{} x i64]", array_size),
+                    &array_ptr,
+                    &[("i64", "0"), ("i64", &index_val)],
+                );
+                self.load_into(&result, "i64", &elem_ptr);
+
+                result
+            }
 
+            AstNode::Slice {
+                value, start, end, ..
+            } => {
+                let string_reg = self.gen_node(value).reg;
+                let start_reg = match start {
+                    Some(start) => self.gen_node(start).reg,
+                    None => "0".to_string(),
+                };
+                let end_reg = match end {
+                    Some(end) => self.gen_node(end).reg,
+                    None => i64::MAX.to_string(),
+                };
+                let result = self.new_temp();
+                self.call(
+                    Some(&result),
+                    "i8*",
+                    "@str_slice_impl",
+                    &[("i8*", &string_reg), ("i64", &start_reg), ("i64", &end_reg)],
+                );
                 result
             }
 
             AstNode::Identifier { name, .. } => {
                 if let Some(meta) = self.current_function_vars.get(name).cloned() {
-                    if meta.llvm_name.starts_with("%arg_") {
-                        meta.llvm_name.clone()
-                    } else if self.struct_types.contains_key(&meta.var_type) && !meta.is_heap {
+                    // A `&int` parameter is the int's address; a `&string`,
+                    // `&Vec` or `&Point` one is the pointer the value is
+                    if (meta.llvm_name.starts_with("%arg_")
+                        && (meta.array_size.is_some()
+                            || self.type_to_llvm(&meta.var_type).ends_with('*')))
+                        || (self.struct_types.contains_key(&meta.var_type) && !meta.is_heap)
+                    {
                         meta.llvm_name.clone()
                     } else {
                         let result = self.new_temp();
                         let llvm_type_str = self.type_to_llvm(&meta.var_type);
                         let llvm_name = meta.llvm_name.clone();
-                        self.emit(&format!(
-                            "  {} = load {}, {}* {}",
-                            result, llvm_type_str, llvm_type_str, llvm_name
-                        ));
+                        self.load_into(&result, &llvm_type_str, &llvm_name);
                         result
                     }
                 } else {
@@ -2281,7 +5009,7 @@ impl CodeGenerator {
                 }
             }
 
-            AstNode::Reference(expr) => match expr.as_ref() {
+            AstNode::Reference(expr, _) => match expr.as_ref() {
                 AstNode::Identifier { name, .. } => {
                     if let Some(meta) = self.current_function_vars.get(name).cloned() {
                         if meta.var_type.starts_with('[') || meta.var_type == "array" {
@@ -2296,10 +5024,7 @@ impl CodeGenerator {
                         let result = self.new_temp();
                         let llvm_type_str = self.type_to_llvm(&meta.var_type);
                         let llvm_name = meta.llvm_name.clone();
-                        self.emit(&format!(
-                            "  {} = load {}, {}* {}",
-                            result, llvm_type_str, llvm_type_str, llvm_name
-                        ));
+                        self.load_into(&result, &llvm_type_str, &llvm_name);
                         result
                     } else {
                         eprintln!(
@@ -2309,115 +5034,545 @@ impl CodeGenerator {
                         "null".to_string()
                     }
                 }
-                _ => self.gen_node(expr),
+                _ => self.gen_node(expr).reg,
             },
 
-            AstNode::Call { name, args } => match name.as_str() {
-                "print" if !args.is_empty() => match self.infer_type(&args[0]).as_str() {
-                    "string" => {
-                        let arg_reg = self.gen_node(&args[0]);
-                        let result = self.new_temp();
-                        self.emit(&format!("  {} = call i32 @puts(i8* {})", result, arg_reg));
-                        result
-                    }
-                    "bool" => {
-                        let arg_reg = self.gen_node(&args[0]);
-                        let ext = self.new_temp();
-                        self.emit(&format!("  {} = zext i1 {} to i64", ext, arg_reg));
-                        self.emit(&format!("  call void @brn_print_int(i64 {})", ext));
-                        "0".to_string()
-                    }
-                    _ => {
-                        let arg_reg = self.gen_node(&args[0]);
-                        self.emit(&format!("  call void @brn_print_int(i64 {})", arg_reg));
-                        "0".to_string()
+            AstNode::Call { name, args, .. } => match name.as_str() {
+                // Declared externs take the name over from any builtin
+                _ if self.externs.contains_key(name) => self.gen_extern_call(name, args),
+                // `debug` is `println`, which shows a struct as `Point { x: 1, y: 2 }`
+                "print" | "println" | "debug" if !args.is_empty() => {
+                    let newline = name != "print";
+                    let arg = self.gen_node(&args[0]);
+                    let arg_reg = arg.reg.clone();
+                    match arg.brain_ty.as_str() {
+                        "string" if newline => {
+                            let result = self.new_temp();
+                            self.call(Some(&result), "i32", "@puts", &[("i8*", &arg_reg)]);
+                            result
+                        }
+                        "string" => {
+                            self.call(None, "void", "@brn_print_str", &[("i8*", &arg_reg)]);
+                            "0".to_string()
+                        }
+                        "char" => {
+                            let func = if newline {
+                                "brn_print_char"
+                            } else {
+                                "brn_print_char_raw"
+                            };
+                            self.call(None, "void", &format!("@{}", func), &[("i8", &arg_reg)]);
+                            "0".to_string()
+                        }
+                        ty if self.struct_types.contains_key(ty) => {
+                            self.gen_debug_call(&arg);
+                            if newline {
+                                self.call(None, "void", "@brn_print_char_raw", &[("i8", "10")]);
+                            }
+                            "0".to_string()
+                        }
+                        ty if self.enum_types.contains_key(ty) => {
+                            let ty = ty.to_string();
+                            let name = self.gen_variant_name(&arg_reg, &ty);
+                            if newline {
+                                let result = self.new_temp();
+                                self.call(Some(&result), "i32", "@puts", &[("i8*", &name)]);
+                                result
+                            } else {
+                                self.call(None, "void", "@brn_print_str", &[("i8*", &name)]);
+                                "0".to_string()
+                            }
+                        }
+                        _ => {
+                            let int_reg = self.coerce_i64(arg);
+                            let func = if newline {
+                                "brn_print_int"
+                            } else {
+                                "brn_print_int_raw"
+                            };
+                            self.call(None, "void", &format!("@{}", func), &[("i64", &int_reg)]);
+                            "0".to_string()
+                        }
                     }
-                },
+                }
+                "println" => {
+                    let empty = self
+                        .gen_node(&AstNode::StringLit(String::new(), Span::default()))
+                        .reg;
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i32", "@puts", &[("i8*", &empty)]);
+                    result
+                }
                 "read_file" if !args.is_empty() => {
-                    let filename_reg = self.gen_node(&args[0]);
+                    let filename_reg = self.gen_node(&args[0]).reg;
                     let result = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = call i8* @read_file_impl(i8* {})",
-                        result, filename_reg
-                    ));
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@read_file_impl",
+                        &[("i8*", &filename_reg)],
+                    );
+                    result
+                }
+                "path_join" if args.len() >= 2 => {
+                    let a_reg = self.gen_node(&args[0]).reg;
+                    let b_reg = self.gen_node(&args[1]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@path_join_impl",
+                        &[("i8*", &a_reg), ("i8*", &b_reg)],
+                    );
+                    result
+                }
+                "path_basename" | "path_extension" | "path_parent" if !args.is_empty() => {
+                    let path_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        &format!("@{}_impl", name),
+                        &[("i8*", &path_reg)],
+                    );
+                    result
+                }
+                "open_lines" if !args.is_empty() => {
+                    let path_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@open_lines_impl",
+                        &[("i8*", &path_reg)],
+                    );
                     result
                 }
+                "next_line" if !args.is_empty() => {
+                    let reader_reg = self.gen_node(&args[0]).reg;
+                    self.gen_next_line(&reader_reg)
+                }
+                "has_next_line" if !args.is_empty() => {
+                    let reader_reg = self.gen_node(&args[0]).reg;
+                    self.gen_has_next_line(&reader_reg)
+                }
                 "read_input" => {
                     let result = self.new_temp();
-                    self.emit(&format!("  {} = call i8* @read_input_impl()", result));
+                    self.call(Some(&result), "i8*", "@read_input_impl", &[]);
                     result
                 }
                 "write_file" if args.len() >= 2 => {
-                    let filename_reg = self.gen_node(&args[0]);
-                    let content_reg = self.gen_node(&args[1]);
+                    let filename_reg = self.gen_node(&args[0]).reg;
+                    let content_reg = self.gen_node(&args[1]).reg;
                     let result = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = call i32 @write_file_impl(i8* {}, i8* {})",
-                        result, filename_reg, content_reg
-                    ));
+                    self.call(
+                        Some(&result),
+                        "i32",
+                        "@write_file_impl",
+                        &[("i8*", &filename_reg), ("i8*", &content_reg)],
+                    );
+                    let result_i64 = self.new_temp();
+                    self.cast(&result_i64, "sext", "i32", &result, "i64");
+                    result_i64
+                }
+                "append_file" if args.len() >= 2 => {
+                    let filename_reg = self.gen_node(&args[0]).reg;
+                    let content_reg = self.gen_node(&args[1]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i32",
+                        "@append_file_impl",
+                        &[("i8*", &filename_reg), ("i8*", &content_reg)],
+                    );
+                    let result_i64 = self.new_temp();
+                    self.cast(&result_i64, "sext", "i32", &result, "i64");
+                    result_i64
+                }
+                "read_bytes" if !args.is_empty() => {
+                    let filename_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@read_bytes_impl",
+                        &[("i8*", &filename_reg)],
+                    );
+                    result
+                }
+                "write_bytes" if args.len() >= 3 => {
+                    let filename_reg = self.gen_node(&args[0]).reg;
+                    let vec_reg = self.gen_node(&args[1]).reg;
+                    let len_reg = self.gen_node(&args[2]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i32",
+                        "@write_bytes_impl",
+                        &[("i8*", &filename_reg), ("i8*", &vec_reg), ("i64", &len_reg)],
+                    );
                     let result_i64 = self.new_temp();
-                    self.emit(&format!("  {} = sext i32 {} to i64", result_i64, result));
+                    self.cast(&result_i64, "sext", "i32", &result, "i64");
                     result_i64
                 }
                 "vec_new" => {
                     let result = self.new_temp();
-                    self.emit(&format!("  {} = call i8* @vec_new_impl()", result));
+                    self.call(Some(&result), "i8*", "@vec_new_impl", &[]);
+                    result
+                }
+                "vec_with_capacity" if !args.is_empty() => {
+                    let cap_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@vec_with_capacity_impl",
+                        &[("i64", &cap_reg)],
+                    );
                     result
                 }
+                "vec_reserve" if args.len() >= 2 => {
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let extra_reg = self.gen_node(&args[1]).reg;
+                    self.gen_vec_reserve(&vec_reg, &extra_reg)
+                }
                 "vec_push" if args.len() >= 2 => {
-                    let vec_reg = self.gen_node(&args[0]);
-                    let val_reg = self.gen_node(&args[1]);
-                    self.emit(&format!(
-                        "  call void @vec_push_impl(i8* {}, i64 {})",
-                        vec_reg, val_reg
-                    ));
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let slot = self.gen_vec_slot(&vec_type, &args[1]);
+                    self.call(
+                        None,
+                        "void",
+                        "@vec_push_impl",
+                        &[("i8*", &vec_reg), ("i64", &slot)],
+                    );
                     "0".to_string()
                 }
                 "vec_get" if args.len() >= 2 => {
-                    let vec_reg = self.gen_node(&args[0]);
-                    let idx_reg = self.gen_node(&args[1]);
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    self.gen_vec_get(&vec_type, &vec_reg, &idx_reg)
+                }
+                "vec_set" if args.len() >= 3 => {
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    self.gen_vec_set(&vec_type, &vec_reg, &idx_reg, &args[2])
+                }
+                "vec_pop" if !args.is_empty() => {
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    self.gen_vec_take(&vec_type, &vec_reg, None)
+                }
+                "vec_remove" if args.len() >= 2 => {
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    self.gen_vec_take(&vec_type, &vec_reg, Some(&idx_reg))
+                }
+                "vec_insert" if args.len() >= 3 => {
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    let slot = self.gen_vec_slot(&vec_type, &args[2]);
+                    self.call(
+                        None,
+                        "void",
+                        "@vec_insert_impl",
+                        &[("i8*", &vec_reg), ("i64", &idx_reg), ("i64", &slot)],
+                    );
+                    "0".to_string()
+                }
+                "vec_clear" if !args.is_empty() => {
+                    let vec_type = self.infer_type(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
+                    self.gen_vec_clear(&vec_type, &vec_reg)
+                }
+                "mkdir" | "remove_dir" if !args.is_empty() => {
+                    let path_reg = self.gen_node(&args[0]).reg;
                     let result = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = call i64 @vec_get_impl(i8* {}, i64 {})",
-                        result, vec_reg, idx_reg
-                    ));
+                    self.call(
+                        Some(&result),
+                        "i32",
+                        &format!("@{}_impl", name),
+                        &[("i8*", &path_reg)],
+                    );
+                    let result_i64 = self.new_temp();
+                    self.cast(&result_i64, "sext", "i32", &result, "i64");
+                    result_i64
+                }
+                "now" => {
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i64", "@brn_now", &[]);
                     result
                 }
-                "vec_set" if args.len() >= 3 => {
-                    let vec_reg = self.gen_node(&args[0]);
-                    let idx_reg = self.gen_node(&args[1]);
-                    let val_reg = self.gen_node(&args[2]);
-                    self.emit(&format!(
-                        "  call void @vec_set_impl(i8* {}, i64 {}, i64 {})",
-                        vec_reg, idx_reg, val_reg
-                    ));
+                "sleep" | "sleep_ms" if !args.is_empty() => {
+                    let n_reg = self.gen_node(&args[0]).reg;
+                    let ms_reg = if name == "sleep" {
+                        let ms = self.new_temp();
+                        self.binary(&ms, "mul", "i64", &n_reg, "1000");
+                        ms
+                    } else {
+                        n_reg
+                    };
+                    self.call(None, "void", "@brn_sleep_ms", &[("i64", &ms_reg)]);
                     "0".to_string()
                 }
+                "run" if !args.is_empty() => {
+                    let cmd_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i64", "@run_impl", &[("i8*", &cmd_reg)]);
+                    result
+                }
+                "run_capture" if !args.is_empty() => {
+                    let cmd_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@run_capture_impl",
+                        &[("i8*", &cmd_reg)],
+                    );
+                    result
+                }
+                "read_dir" if !args.is_empty() => {
+                    let path_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@read_dir_impl",
+                        &[("i8*", &path_reg)],
+                    );
+                    result
+                }
                 "vec_len" if !args.is_empty() => {
-                    let vec_reg = self.gen_node(&args[0]);
+                    let vec_reg = self.gen_node(&args[0]).reg;
                     let result = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = call i64 @vec_len_impl(i8* {})",
-                        result, vec_reg
-                    ));
+                    self.call(Some(&result), "i64", "@vec_len_impl", &[("i8*", &vec_reg)]);
+                    result
+                }
+                "sb_new" => {
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i8*", "@sb_new_impl", &[]);
+                    result
+                }
+                "sb_append" if args.len() >= 2 => {
+                    let sb_reg = self.gen_node(&args[0]).reg;
+                    let str_reg = self.gen_node(&args[1]).reg;
+                    self.call(
+                        None,
+                        "void",
+                        "@sb_append_impl",
+                        &[("i8*", &sb_reg), ("i8*", &str_reg)],
+                    );
+                    "0".to_string()
+                }
+                "bytes_new" if !args.is_empty() => {
+                    let len_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@bytes_new_impl",
+                        &[("i64", &len_reg)],
+                    );
+                    result
+                }
+                "bytes_len" if !args.is_empty() => {
+                    let b_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i64", "@vec_len_impl", &[("i8*", &b_reg)]);
+                    result
+                }
+                "bytes_get" if args.len() >= 2 => {
+                    let b_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    self.gen_bytes_get(&b_reg, &idx_reg)
+                }
+                "bytes_set" if args.len() >= 3 => {
+                    let b_reg = self.gen_node(&args[0]).reg;
+                    let idx_reg = self.gen_node(&args[1]).reg;
+                    let val_reg = self.gen_node(&args[2]).reg;
+                    self.call(
+                        None,
+                        "void",
+                        "@bytes_set_impl",
+                        &[("i8*", &b_reg), ("i64", &idx_reg), ("i64", &val_reg)],
+                    );
+                    "0".to_string()
+                }
+                "bytes_slice" if args.len() >= 3 => {
+                    let b_reg = self.gen_node(&args[0]).reg;
+                    let start_reg = self.gen_node(&args[1]).reg;
+                    let end_reg = self.gen_node(&args[2]).reg;
+                    self.gen_bytes_slice(&b_reg, &start_reg, &end_reg)
+                }
+                "bytes_from_string" | "bytes_to_string" if !args.is_empty() => {
+                    let arg_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        &format!("@{}_impl", name),
+                        &[("i8*", &arg_reg)],
+                    );
+                    result
+                }
+                "to_uppercase" | "to_lowercase" if !args.is_empty() => {
+                    let s_reg = self.gen_node(&args[0]).reg;
+                    self.gen_str_case(&s_reg, name == "to_uppercase")
+                }
+                "char_count" if !args.is_empty() => {
+                    let s_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(Some(&result), "i64", "@brn_char_count", &[("i8*", &s_reg)]);
                     result
                 }
+                "sb_to_string" if !args.is_empty() => {
+                    let sb_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@sb_to_string_impl",
+                        &[("i8*", &sb_reg)],
+                    );
+                    result
+                }
+                "assert" if !args.is_empty() => {
+                    let cond_reg = self.gen_node(&args[0]).reg;
+                    let msg_reg = if args.len() > 1 {
+                        self.gen_node(&args[1]).reg
+                    } else {
+                        "null".to_string()
+                    };
+                    let fail_label = self.new_label("assert_fail");
+                    let ok_label = self.new_label("assert_ok");
+                    self.cond_br(&cond_reg, &ok_label, &fail_label);
+                    self.label(&fail_label);
+                    self.call(None, "void", "@brn_assert_fail", &[("i8*", &msg_reg)]);
+                    self.push(Inst::Unreachable);
+                    self.label(&ok_label);
+                    "0".to_string()
+                }
+                // A char's code as an int; ints (like `s.char_at(i)`) pass through
+                "char_to_int" if !args.is_empty() => {
+                    let arg_reg = self.gen_node(&args[0]).reg;
+                    if self.infer_type(&args[0]) != "char" {
+                        return arg_reg;
+                    }
+                    let result = self.new_temp();
+                    self.cast(&result, "zext", "i8", &arg_reg, "i64");
+                    result
+                }
+                // The char with code n, keeping only the low byte
+                "int_to_char" if !args.is_empty() => {
+                    let arg_reg = self.gen_node(&args[0]).reg;
+                    if self.infer_type(&args[0]) == "char" {
+                        return arg_reg;
+                    }
+                    let result = self.new_temp();
+                    self.cast(&result, "trunc", "i64", &arg_reg, "i8");
+                    result
+                }
+                // -1, 0 or 1 as a orders before, the same as or after b
+                "compare" if args.len() == 2 => {
+                    let a_reg = self.gen_node(&args[0]).reg;
+                    let b_reg = self.gen_node(&args[1]).reg;
+                    let (cmp_type, a_reg, b_reg) = if self.infer_type(&args[0]) == "string"
+                        || self.infer_type(&args[1]) == "string"
+                    {
+                        let cmp = self.new_temp();
+                        self.call(
+                            Some(&cmp),
+                            "i32",
+                            "@strcmp",
+                            &[("i8*", &a_reg), ("i8*", &b_reg)],
+                        );
+                        ("i32", cmp, "0".to_string())
+                    } else {
+                        ("i64", a_reg, b_reg)
+                    };
+                    let greater = self.new_temp();
+                    let less = self.new_temp();
+                    self.icmp(&greater, "sgt", cmp_type, &a_reg, &b_reg);
+                    self.icmp(&less, "slt", cmp_type, &a_reg, &b_reg);
+                    let greater_int = self.new_temp();
+                    let less_int = self.new_temp();
+                    self.cast(&greater_int, "zext", "i1", &greater, "i64");
+                    self.cast(&less_int, "zext", "i1", &less, "i64");
+                    let result = self.new_temp();
+                    self.binary(&result, "sub", "i64", &greater_int, &less_int);
+                    result
+                }
+                // Converts by the argument's type; a string is copied
+                "to_string" | "bool_to_string" if !args.is_empty() => {
+                    let mut arg_reg = self.gen_node(&args[0]).reg;
+                    let result = self.new_temp();
+                    let ty = self.infer_type(&args[0]);
+                    if self.enum_types.contains_key(&ty) {
+                        arg_reg = self.gen_variant_name(&arg_reg, &ty);
+                    }
+                    let (callee, arg_ty) = match ty.as_str() {
+                        "bool" => ("@bool_to_string_impl", "i1"),
+                        "char" => ("@char_to_string_impl", "i8"),
+                        ty if ty == "string" || self.enum_types.contains_key(ty) => {
+                            let len = result.clone();
+                            self.call(Some(&len), "i64", "@strlen", &[("i8*", &arg_reg)]);
+                            let copy = self.new_temp();
+                            self.call(
+                                Some(&copy),
+                                "i8*",
+                                "@brn_substr_copy",
+                                &[("i8*", &arg_reg), ("i64", &len)],
+                            );
+                            return copy;
+                        }
+                        _ => ("@int_to_string_impl", "i64"),
+                    };
+                    self.call(Some(&result), "i8*", callee, &[(arg_ty, &arg_reg)]);
+                    result
+                }
+                "hash" if !args.is_empty() => {
+                    let value = self.gen_node(&args[0]);
+                    let reg = if self.struct_types.contains_key(&value.brain_ty) {
+                        self.struct_ptr(&value)
+                    } else {
+                        value.reg.clone()
+                    };
+                    self.gen_hash(&reg, &value.brain_ty, &value.llvm_ty)
+                }
+                // The variant's name, borrowed from the enum's table
+                "enum_name" if !args.is_empty() => {
+                    let value = self.gen_node(&args[0]);
+                    if self.enum_types.contains_key(&value.brain_ty) {
+                        self.gen_variant_name(&value.reg, &value.brain_ty)
+                    } else {
+                        self.string_literal_ptr("?")
+                    }
+                }
                 "int_to_string" if !args.is_empty() => {
-                    let n_reg = self.gen_node(&args[0]);
+                    let n_reg = self.gen_node(&args[0]).reg;
                     let result = self.new_temp();
-                    self.emit(&format!(
-                        "  {} = call i8* @int_to_string_impl(i64 {})",
-                        result, n_reg
-                    ));
+                    self.call(
+                        Some(&result),
+                        "i8*",
+                        "@int_to_string_impl",
+                        &[("i64", &n_reg)],
+                    );
                     result
                 }
                 _ => {
                     let mut arg_regs = Vec::new();
                     let mut arg_types = Vec::new();
+                    // Strings lent to the call, freed after it if temporary
+                    let mut borrowed_strings = Vec::new();
 
-                    for arg_node in args {
+                    for (i, arg_node) in args.iter().enumerate() {
                         match arg_node {
-                            AstNode::Reference(inner) => match inner.as_ref() {
+                            AstNode::Reference(inner, _) => match inner.as_ref() {
                                 AstNode::Identifier { name: var_name, .. } => {
                                     if let Some(meta) =
                                         self.current_function_vars.get(var_name).cloned()
@@ -2428,38 +5583,63 @@ impl CodeGenerator {
                                         } else if meta.var_type.starts_with("Mutex<") {
                                             // Mutex is already an i8* — load and pass directly
                                             let loaded = self.new_temp();
-                                            self.emit(&format!(
-                                                "  {} = load i8*, i8** {}",
-                                                loaded, meta.llvm_name
-                                            ));
+                                            self.load_into(&loaded, "i8*", &meta.llvm_name);
                                             arg_regs.push(loaded);
                                             arg_types.push("i8*".to_string());
-                                        } else if meta.var_type == "string" {
-                                            let loaded = self.new_temp();
-                                            self.emit(&format!(
-                                                "  {} = load i8*, i8** {}",
-                                                loaded, meta.llvm_name
-                                            ));
-                                            arg_regs.push(loaded);
+                                        } else if meta.var_type == "string"
+                                            || Self::is_vec_type(&meta.var_type)
+                                            || meta.var_type == "StringBuilder"
+                                            || meta.var_type == "Bytes"
+                                        {
+                                            // Borrowed params are already the pointer itself
+                                            if meta.llvm_name.starts_with("%arg_") {
+                                                arg_regs.push(meta.llvm_name.clone());
+                                            } else {
+                                                let loaded = self.new_temp();
+                                                self.load_into(&loaded, "i8*", &meta.llvm_name);
+                                                arg_regs.push(loaded);
+                                            }
                                             arg_types.push("i8*".to_string());
+                                        } else if meta.var_type == "enum"
+                                            || self.enum_types.contains_key(&meta.var_type)
+                                        {
+                                            // Enum locals hold a pointer to the { tag, payload } pair
+                                            if meta.llvm_name.starts_with("%arg_") {
+                                                arg_regs.push(meta.llvm_name.clone());
+                                            } else {
+                                                let loaded = self.new_temp();
+                                                self.load_into(
+                                                    &loaded,
+                                                    "{ i32, i64 }*",
+                                                    &meta.llvm_name,
+                                                );
+                                                arg_regs.push(loaded);
+                                            }
+                                            arg_types.push("{ i32, i64 }*".to_string());
                                         } else if self.struct_types.contains_key(&meta.var_type) {
                                             // Heap struct locals are %StructName** allocas.
                                             // Load once to get the actual %StructName*.
                                             let struct_name = meta.var_type.clone();
-                                            if meta.llvm_name.starts_with("%arg_") {
+                                            if meta.llvm_name.starts_with("%arg_") || !meta.is_heap
+                                            {
                                                 arg_regs.push(meta.llvm_name.clone());
                                             } else {
                                                 let loaded = self.new_temp();
-                                                self.emit(&format!(
-                                                    "  {} = load %{}*, %{}** {}",
-                                                    loaded,
-                                                    struct_name,
-                                                    struct_name,
-                                                    meta.llvm_name
-                                                ));
+                                                self.load_into(
+                                                    &loaded,
+                                                    &format!("%{}*", struct_name),
+                                                    &meta.llvm_name,
+                                                );
                                                 arg_regs.push(loaded);
                                             }
                                             arg_types.push(format!("%{}*", struct_name));
+                                        } else if meta.var_type.starts_with('*') {
+                                            // Borrowing through a pointer borrows what it points at
+                                            let ptr_type = self.type_to_llvm(&meta.var_type);
+                                            let loaded = self.new_temp();
+                                            self.load_into(&loaded, &ptr_type, &meta.llvm_name);
+                                            arg_regs.push(loaded);
+                                            arg_types.push(ptr_type);
                                         } else {
                                             arg_regs.push(meta.llvm_name.clone());
                                             arg_types.push(format!(
@@ -2473,47 +5653,108 @@ impl CodeGenerator {
                                     }
                                 }
                                 _ => {
-                                    let reg = self.gen_node(inner);
+                                    let reg = self.gen_node(inner).reg;
+                                    let inner_type = self.infer_type(inner);
                                     arg_regs.push(reg);
-                                    arg_types.push("i8*".to_string());
+                                    // `&a.b` of a struct field is already its %StructName*
+                                    if self.struct_types.contains_key(Self::pointee(&inner_type)) {
+                                        arg_types.push(self.type_to_llvm(&inner_type));
+                                    } else {
+                                        arg_types.push("i8*".to_string());
+                                    }
                                 }
                             },
+                            // A `&int` parameter given a reference it holds
+                            // passes the address along; given a value, a copy's
+                            _ if self
+                                .param_types
+                                .get(name)
+                                .and_then(|types| types.get(i))
+                                .is_some_and(|t| {
+                                    let (is_ref, _, inner) = Self::strip_ref_prefix(t);
+                                    is_ref && matches!(inner, "int" | "bool" | "char")
+                                }) =>
+                            {
+                                let inner = Self::strip_ref_prefix(&self.param_types[name][i])
+                                    .2
+                                    .to_string();
+                                let llvm_ty = self.type_to_llvm(&inner);
+                                let held = match arg_node {
+                                    AstNode::Identifier { name: var_name, .. } => self
+                                        .current_function_vars
+                                        .get(var_name)
+                                        .map(|meta| meta.llvm_name.clone())
+                                        .filter(|reg| reg.starts_with("%arg_")),
+                                    _ => None,
+                                };
+                                let ptr = match held {
+                                    Some(ptr) => ptr,
+                                    None => {
+                                        let value = self.gen_node(arg_node);
+                                        let value = self.convert(value, &inner);
+                                        let slot = self.new_temp();
+                                        self.alloca_into(&slot, &llvm_ty);
+                                        self.store(&llvm_ty, &value, &slot);
+                                        slot
+                                    }
+                                };
+                                arg_regs.push(ptr);
+                                arg_types.push(format!("{}*", llvm_ty));
+                            }
                             _ => {
-                                let reg = self.gen_node(arg_node);
+                                let generated = self.gen_node(arg_node);
+                                let reg = generated.reg.clone();
+                                // An `int` or `char` parameter converts what it is given
+                                if let Some(to) = self
+                                    .param_types
+                                    .get(name)
+                                    .and_then(|types| types.get(i))
+                                    .filter(|t| *t == "int" || *t == "char")
+                                    .cloned()
+                                    && matches!(generated.llvm_ty.as_str(), "i64" | "i8" | "i1")
+                                {
+                                    arg_regs.push(self.convert(generated, &to));
+                                    arg_types.push(self.type_to_llvm(&to));
+                                    continue;
+                                }
                                 let arg_type = self.infer_type(arg_node);
-                                if arg_type == "string" {
-                                    let len = self.new_temp();
-                                    let len1 = self.new_temp();
-                                    let copy = self.new_temp();
-                                    let copied = self.new_temp();
-                                    self.emit(&format!(
-                                        "  {} = call i64 @strlen(i8* {})",
-                                        len, reg
-                                    ));
-                                    self.emit(&format!("  {} = add i64 {}, 1", len1, len));
-                                    self.emit(&format!(
-                                        "  {} = call i8* @malloc(i64 {})",
-                                        copy, len1
-                                    ));
-                                    self.emit(&format!(
-                                        "  {} = call i8* @strcpy(i8* {}, i8* {})",
-                                        copied, copy, reg
-                                    ));
-                                    arg_regs.push(copied);
+                                if arg_type == "string" && self.no_mangle.contains(name) {
+                                    // A `#[no_mangle]` function only borrows it
+                                    borrowed_strings.push((arg_node, reg.clone()));
+                                    arg_regs.push(reg);
+                                } else if arg_type == "string" {
+                                    // A by-value parameter owns its string
+                                    let owned = self.owned_string(arg_node, reg);
+                                    arg_regs.push(owned);
+                                } else if self.is_value_struct(&arg_type)
+                                    && self
+                                        .param_types
+                                        .get(name)
+                                        .and_then(|types| types.get(i))
+                                        .is_some_and(|t| Self::strip_ref_prefix(t).0)
+                                {
+                                    // A reference passed on to a `&C` parameter is
+                                    // still the struct's address
+                                    arg_regs.push(reg);
+                                    arg_types.push(format!("%{}*", arg_type));
+                                    continue;
+                                } else if self.is_value_struct(&arg_type) {
+                                    let whole = self.new_temp();
+                                    self.load_into(&whole, &format!("%{}", arg_type), &reg);
+                                    arg_regs.push(whole);
                                 } else {
                                     arg_regs.push(reg);
                                 }
-                                arg_types.push(self.type_to_llvm(&arg_type));
+                                arg_types.push(self.value_type_to_llvm(&arg_type));
                             }
                         }
                     }
 
-                    let args_str = arg_types
+                    let args: Vec<(&str, &str)> = arg_types
                         .iter()
                         .zip(&arg_regs)
-                        .map(|(ty, reg)| format!("{} {}", ty, reg))
-                        .collect::<Vec<_>>()
-                        .join(", ");
+                        .map(|(ty, reg)| (ty.as_str(), reg.as_str()))
+                        .collect();
 
                     let return_type = self
                         .function_signatures
@@ -2521,16 +5762,27 @@ impl CodeGenerator {
                         .cloned()
                         .unwrap_or_else(|| "i64".to_string());
 
-                    let mangled = Self::mangle_fn(name);
+                    let mangled = self.mangle_fn(name);
                     if return_type == "void" {
-                        self.emit(&format!("  call void @{}({})", mangled, args_str));
+                        self.call(None, "void", &format!("@{}", mangled), &args);
+                        for (node, reg) in borrowed_strings {
+                            self.free_if_temporary(node, &reg);
+                        }
                         "0".to_string()
                     } else {
                         let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = call {} @{}({})",
-                            result, return_type, mangled, args_str
-                        ));
+                        self.call(Some(&result), &return_type, &format!("@{}", mangled), &args);
+                        for (node, reg) in borrowed_strings {
+                            self.free_if_temporary(node, &reg);
+                        }
+                        // A returned value struct is spilled so it is addressed like any other struct
+                        if let Some(struct_name) = return_type.strip_prefix('%')
+                            && !struct_name.ends_with('*')
+                        {
+                            let slot = self.alloc_struct(struct_name, false);
+                            self.store(&return_type, &result, &slot);
+                            return slot;
+                        }
                         result
                     }
                 }
@@ -2540,94 +5792,185 @@ impl CodeGenerator {
                 object,
                 method,
                 args,
+                ..
             } => {
+                if let Some(call) = self.method_as_call(object, method, args) {
+                    return self.gen_node(&call).reg;
+                }
                 let obj_type = self.infer_type(object);
                 match method.as_str() {
                     "len" => {
-                        let obj_reg = self.gen_node(object);
-                        if obj_type == "Vec" {
+                        let obj_reg = self.gen_node(object).reg;
+                        if Self::is_vec_type(&obj_type) || obj_type == "Bytes" {
                             let result = self.new_temp();
-                            self.emit(&format!(
-                                "  {} = call i64 @vec_len_impl(i8* {})",
-                                result, obj_reg
-                            ));
+                            self.call(Some(&result), "i64", "@vec_len_impl", &[("i8*", &obj_reg)]);
                             result
                         } else {
                             let result = self.new_temp();
-                            self.emit(&format!("  {} = call i64 @strlen(i8* {})", result, obj_reg));
+                            self.call(Some(&result), "i64", "@strlen", &[("i8*", &obj_reg)]);
                             result
                         }
                     }
+                    "char_count" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let result = self.new_temp();
+                        self.call(
+                            Some(&result),
+                            "i64",
+                            "@brn_char_count",
+                            &[("i8*", &obj_reg)],
+                        );
+                        result
+                    }
                     "char_at" if !args.is_empty() => {
-                        let obj_reg = self.gen_node(object);
-                        let index_reg = self.gen_node(&args[0]);
+                        let obj_reg = self.gen_node(object).reg;
+                        let index_reg = self.gen_node(&args[0]).reg;
                         let char_ptr = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = getelementptr i8, i8* {}, i64 {}",
-                            char_ptr, obj_reg, index_reg
-                        ));
+                        self.gep(&char_ptr, "i8", &obj_reg, &[("i64", &index_reg)]);
                         let result = self.new_temp();
-                        self.emit(&format!("  {} = load i8, i8* {}", result, char_ptr));
+                        self.load_into(&result, "i8", &char_ptr);
                         let extended = self.new_temp();
-                        self.emit(&format!("  {} = sext i8 {} to i64", extended, result));
+                        self.cast(&extended, "sext", "i8", &result, "i64");
                         extended
                     }
-                    "push" if !args.is_empty() => {
-                        let obj_reg = self.gen_node(object);
-                        let val_reg = self.gen_node(&args[0]);
-                        self.emit(&format!(
-                            "  call void @vec_push_impl(i8* {}, i64 {})",
-                            obj_reg, val_reg
-                        ));
+                    "get" if obj_type == "Bytes" && !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        self.gen_bytes_get(&obj_reg, &idx_reg)
+                    }
+                    "set" if obj_type == "Bytes" && args.len() >= 2 => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        let val_reg = self.gen_node(&args[1]).reg;
+                        self.call(
+                            None,
+                            "void",
+                            "@bytes_set_impl",
+                            &[("i8*", &obj_reg), ("i64", &idx_reg), ("i64", &val_reg)],
+                        );
                         "0".to_string()
                     }
-                    "get" if !args.is_empty() => {
-                        let obj_reg = self.gen_node(object);
-                        let idx_reg = self.gen_node(&args[0]);
+                    "slice" if obj_type == "Bytes" && args.len() >= 2 => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let start_reg = self.gen_node(&args[0]).reg;
+                        let end_reg = self.gen_node(&args[1]).reg;
+                        self.gen_bytes_slice(&obj_reg, &start_reg, &end_reg)
+                    }
+                    "to_string" if obj_type == "Bytes" => {
+                        let obj_reg = self.gen_node(object).reg;
                         let result = self.new_temp();
-                        self.emit(&format!(
-                            "  {} = call i64 @vec_get_impl(i8* {}, i64 {})",
-                            result, obj_reg, idx_reg
-                        ));
+                        self.call(
+                            Some(&result),
+                            "i8*",
+                            "@bytes_to_string_impl",
+                            &[("i8*", &obj_reg)],
+                        );
                         result
                     }
-                    "set" if args.len() >= 2 => {
-                        let obj_reg = self.gen_node(object);
-                        let idx_reg = self.gen_node(&args[0]);
-                        let val_reg = self.gen_node(&args[1]);
-                        self.emit(&format!(
-                            "  call void @vec_set_impl(i8* {}, i64 {}, i64 {})",
-                            obj_reg, idx_reg, val_reg
-                        ));
-                        "0".to_string()
+                    "next_line" if obj_type == "LineReader" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        self.gen_next_line(&obj_reg)
+                    }
+                    "has_next_line" if obj_type == "LineReader" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        self.gen_has_next_line(&obj_reg)
+                    }
+                    "split" if !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let sep_reg = self.gen_node(&args[0]).reg;
+                        let result = self.new_temp();
+                        self.call(
+                            Some(&result),
+                            "i8*",
+                            "@str_split_impl",
+                            &[("i8*", &obj_reg), ("i8*", &sep_reg)],
+                        );
+                        result
+                    }
+                    "to_uppercase" | "to_lowercase" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        self.gen_str_case(&obj_reg, method == "to_uppercase")
+                    }
+                    "lines" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let result = self.new_temp();
+                        self.call(
+                            Some(&result),
+                            "i8*",
+                            "@str_lines_impl",
+                            &[("i8*", &obj_reg)],
+                        );
+                        result
+                    }
+                    "push" if !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let slot = self.gen_vec_slot(&obj_type, &args[0]);
+                        self.call(
+                            None,
+                            "void",
+                            "@vec_push_impl",
+                            &[("i8*", &obj_reg), ("i64", &slot)],
+                        );
+                        "0".to_string()
+                    }
+                    "get" if !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        self.gen_vec_get(&obj_type, &obj_reg, &idx_reg)
+                    }
+                    "set" if args.len() >= 2 => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        self.gen_vec_set(&obj_type, &obj_reg, &idx_reg, &args[1])
+                    }
+                    "pop" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        self.gen_vec_take(&obj_type, &obj_reg, None)
+                    }
+                    "remove" if !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        self.gen_vec_take(&obj_type, &obj_reg, Some(&idx_reg))
+                    }
+                    "insert" if args.len() >= 2 => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let idx_reg = self.gen_node(&args[0]).reg;
+                        let slot = self.gen_vec_slot(&obj_type, &args[1]);
+                        self.call(
+                            None,
+                            "void",
+                            "@vec_insert_impl",
+                            &[("i8*", &obj_reg), ("i64", &idx_reg), ("i64", &slot)],
+                        );
+                        "0".to_string()
+                    }
+                    "clear" => {
+                        let obj_reg = self.gen_node(object).reg;
+                        self.gen_vec_clear(&obj_type, &obj_reg)
+                    }
+                    "reserve" if !args.is_empty() => {
+                        let obj_reg = self.gen_node(object).reg;
+                        let extra_reg = self.gen_node(&args[0]).reg;
+                        self.gen_vec_reserve(&obj_reg, &extra_reg)
                     }
                     "lock" if !self.is_unsafe_fn => {
-                        if let AstNode::Identifier { name: obj_name, .. } = object.as_ref() {
-                            if let Some(meta) = self.current_function_vars.get(obj_name).cloned() {
-                                let mutex_ptr = if meta.llvm_name.starts_with("%arg_") {
-                                    meta.llvm_name.clone()
-                                } else {
-                                    let loaded = self.new_temp();
-                                    self.emit(&format!(
-                                        "  {} = load i8*, i8** {}",
-                                        loaded, meta.llvm_name
-                                    ));
-                                    loaded
-                                };
-                                self.emit(&format!(
-                                    "  call void @EnterCriticalSection(i8* {})",
-                                    mutex_ptr
-                                ));
-                                self.guard_vars.insert(obj_name.clone());
-                                return mutex_ptr;
-                            }
+                        if let AstNode::Identifier { name: obj_name, .. } = object.as_ref()
+                            && let Some(meta) = self.current_function_vars.get(obj_name).cloned()
+                        {
+                            let mutex_ptr = if meta.llvm_name.starts_with("%arg_") {
+                                meta.llvm_name.clone()
+                            } else {
+                                let loaded = self.new_temp();
+                                self.load_into(&loaded, "i8*", &meta.llvm_name);
+                                loaded
+                            };
+                            self.call(None, "void", "@brn_mutex_lock", &[("i8*", &mutex_ptr)]);
+                            self.guard_vars.insert(obj_name.clone());
+                            return mutex_ptr;
                         }
                         "null".to_string()
                     }
-                    "lock" => {
-                        let obj_reg = self.gen_node(object);
-                        obj_reg
-                    }
+                    "lock" => self.gen_node(object).reg,
                     _ => "0".to_string(),
                 }
             }
@@ -2636,13 +5979,278 @@ impl CodeGenerator {
         }
     }
 
+    fn gen_str_case(&mut self, s_reg: &str, upper: bool) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i8*",
+            "@brn_str_case",
+            &[("i8*", s_reg), ("i1", &upper.to_string())],
+        );
+        result
+    }
+
+    fn gen_bytes_get(&mut self, b_reg: &str, idx_reg: &str) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i64",
+            "@bytes_get_impl",
+            &[("i8*", b_reg), ("i64", idx_reg)],
+        );
+        result
+    }
+
+    fn gen_next_line(&mut self, reader_reg: &str) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i8*",
+            "@next_line_impl",
+            &[("i8*", reader_reg)],
+        );
+        result
+    }
+
+    fn gen_has_next_line(&mut self, reader_reg: &str) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i1",
+            "@has_next_line_impl",
+            &[("i8*", reader_reg)],
+        );
+        result
+    }
+
+    fn gen_bytes_slice(&mut self, b_reg: &str, start_reg: &str, end_reg: &str) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i8*",
+            "@bytes_slice_impl",
+            &[("i8*", b_reg), ("i64", start_reg), ("i64", end_reg)],
+        );
+        result
+    }
+
+    fn is_vec_type(ty: &str) -> bool {
+        ty == "Vec" || ty.starts_with("Vec<")
+    }
+
+    /// Every element lives in an i64 slot: `Vec` holds ints, `Vec<bool>`
+    /// and `Vec<char>` widen their values, and `Vec<string>` stores owned
+    /// string pointers that are freed with the Vec.
+    fn vec_element_type(vec_type: &str) -> String {
+        match vec_type {
+            "Vec<string>" => "string".to_string(),
+            "Vec<bool>" => "bool".to_string(),
+            "Vec<char>" => "char".to_string(),
+            _ => "int".to_string(),
+        }
+    }
+
+    /// The struct a `Vec<T>` holds, if `T` is one. Its elements are owned
+    /// `%T*` pointers.
+    fn vec_struct_element(&self, vec_type: &str) -> Option<String> {
+        let inner = vec_type.strip_prefix("Vec<")?.strip_suffix('>')?;
+        self.struct_types
+            .contains_key(inner)
+            .then(|| inner.to_string())
+    }
+
+    /// `vec_element_type`, telling the structs a Vec holds apart.
+    fn vec_element(&self, vec_type: &str) -> String {
+        self.vec_struct_element(vec_type)
+            .unwrap_or_else(|| Self::vec_element_type(vec_type))
+    }
+
+    /// Whether the Vec frees its elements (strings, structs) along with
+    /// itself.
+    fn vec_owns_elements(&self, vec_type: &str) -> bool {
+        Self::vec_element_type(vec_type) == "string" || self.vec_struct_element(vec_type).is_some()
+    }
+
+    /// Strings and structs come out of a Vec as copies, so the caller owns
+    /// the result like any other value.
+    fn gen_vec_get(&mut self, vec_type: &str, vec_reg: &str, idx_reg: &str) -> String {
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i64",
+            "@vec_get_impl",
+            &[("i8*", vec_reg), ("i64", idx_reg)],
+        );
+        let value = self.gen_from_vec_slot(vec_type, &result);
+        match self.vec_struct_element(vec_type) {
+            Some(elem) => {
+                let heap = !self.is_value_struct(&elem);
+                self.copy_struct(&elem, &value, heap)
+            }
+            None => value,
+        }
+    }
+
+    /// `v[i]` or `&v[i]`: the element itself rather than a copy, for a
+    /// struct or a string (borrowed, see `StringOwnership`). Anything else
+    /// is read as `vec_get` reads it.
+    fn gen_vec_element(&mut self, vec_type: &str, vec_reg: &str, idx_reg: &str) -> String {
+        let slot = self.new_temp();
+        self.call(
+            Some(&slot),
+            "i64",
+            "@vec_get_impl",
+            &[("i8*", vec_reg), ("i64", idx_reg)],
+        );
+        let ty = match self.vec_struct_element(vec_type) {
+            Some(elem) => format!("%{}*", elem),
+            None if Self::vec_element_type(vec_type) == "string" => "i8*".to_string(),
+            None => return self.gen_from_vec_slot(vec_type, &slot),
+        };
+        let element = self.new_temp();
+        self.cast(&element, "inttoptr", "i64", &slot, &ty);
+        element
+    }
+
+    fn gen_from_vec_slot(&mut self, vec_type: &str, slot: &str) -> String {
+        if let Some(elem) = self.vec_struct_element(vec_type) {
+            let element = self.new_temp();
+            self.cast(&element, "inttoptr", "i64", slot, &format!("%{}*", elem));
+            return element;
+        }
+        let (op, ty) = match Self::vec_element_type(vec_type).as_str() {
+            "string" => ("inttoptr", "i8*"),
+            "bool" => ("trunc", "i1"),
+            "char" => ("trunc", "i8"),
+            _ => return slot.to_string(),
+        };
+        let value = self.new_temp();
+        self.cast(&value, op, "i64", slot, ty);
+        if ty != "i8*" {
+            return value;
+        }
+        let copy = self.new_temp();
+        self.call(Some(&copy), "i8*", "@brn_strdup", &[("i8*", &value)]);
+        copy
+    }
+
+    /// Evaluate `value` into an i64 slot for a Vec of `vec_type`. The Vec
+    /// owns its strings: only an `Owned` one is stored as it is.
+    fn gen_vec_slot(&mut self, vec_type: &str, value: &AstNode) -> String {
+        let mut reg = self.gen_node(value).reg;
+        // The Vec owns its structs: a variable's moves in, a value struct is
+        // copied to the heap
+        if let Some(elem) = self.vec_struct_element(vec_type) {
+            let owned = self.store_through_pointer(value, reg);
+            let slot = self.new_temp();
+            self.cast(&slot, "ptrtoint", &format!("%{}*", elem), &owned, "i64");
+            return slot;
+        }
+        let (op, ty) = match Self::vec_element_type(vec_type).as_str() {
+            "string" => ("ptrtoint", "i8*"),
+            "bool" => ("zext", "i1"),
+            "char" => ("sext", "i8"),
+            _ => return reg,
+        };
+        if ty == "i8*" {
+            reg = self.owned_string(value, reg);
+        }
+        let slot = self.new_temp();
+        self.cast(&slot, op, ty, &reg, "i64");
+        slot
+    }
+
+    /// `pop` (no index) or `remove`: the element leaves the Vec, so a string
+    /// is handed over as is rather than copied.
+    fn gen_vec_take(&mut self, vec_type: &str, vec_reg: &str, idx_reg: Option<&str>) -> String {
+        let slot = self.new_temp();
+        match idx_reg {
+            Some(idx) => {
+                self.call(
+                    Some(&slot),
+                    "i64",
+                    "@vec_remove_impl",
+                    &[("i8*", vec_reg), ("i64", idx)],
+                );
+            }
+            None => {
+                self.call(Some(&slot), "i64", "@vec_pop_impl", &[("i8*", vec_reg)]);
+            }
+        }
+        if Self::vec_element_type(vec_type) != "string"
+            || self.vec_struct_element(vec_type).is_some()
+        {
+            return self.gen_from_vec_slot(vec_type, &slot);
+        }
+        let result = self.new_temp();
+        self.call(Some(&result), "i8*", "@brn_taken_str", &[("i64", &slot)]);
+        result
+    }
+
+    fn gen_vec_reserve(&mut self, vec_reg: &str, extra_reg: &str) -> String {
+        self.call(
+            None,
+            "void",
+            "@vec_reserve_impl",
+            &[("i8*", vec_reg), ("i64", extra_reg)],
+        );
+        "0".to_string()
+    }
+
+    fn gen_vec_clear(&mut self, vec_type: &str, vec_reg: &str) -> String {
+        if self.vec_owns_elements(vec_type) {
+            self.call(None, "void", "@vec_free_strings_impl", &[("i8*", vec_reg)]);
+        }
+        self.call(None, "void", "@vec_clear_impl", &[("i8*", vec_reg)]);
+        "0".to_string()
+    }
+
+    fn gen_vec_set(
+        &mut self,
+        vec_type: &str,
+        vec_reg: &str,
+        idx_reg: &str,
+        value: &AstNode,
+    ) -> String {
+        let slot = self.gen_vec_slot(vec_type, value);
+        if self.vec_owns_elements(vec_type) {
+            let old = self.new_temp();
+            self.call(
+                Some(&old),
+                "i64",
+                "@vec_get_impl",
+                &[("i8*", vec_reg), ("i64", idx_reg)],
+            );
+            let old_ptr = self.new_temp();
+            self.cast(&old_ptr, "inttoptr", "i64", &old, "i8*");
+            self.call(None, "void", "@free", &[("i8*", &old_ptr)]);
+        }
+        self.call(
+            None,
+            "void",
+            "@vec_set_impl",
+            &[("i8*", vec_reg), ("i64", idx_reg), ("i64", &slot)],
+        );
+        "0".to_string()
+    }
+
     fn is_pointer_llvm_type(ty: &str) -> bool {
         matches!(ty, "string" | "Vec")
             || ty.starts_with('[')
             || (!matches!(ty, "int" | "bool" | "char" | "void") && !ty.is_empty())
     }
 
-    fn infer_purity(params: &[Parameter], body: &AstNode) -> bool {
+    /// What a function needs from others to be pure, or `None` if its own
+    /// body already rules it out.
+    fn infer_purity(
+        params: &[Parameter],
+        body: &AstNode,
+        return_type: &Option<String>,
+    ) -> Option<PurityFacts> {
+        // Each call hands back a new string (see `StringOwnership`)
+        if return_type.as_deref() == Some("string") {
+            return None;
+        }
         let has_string_param = params.iter().any(|p| {
             let (_, _, inner) = Self::strip_ref_prefix(&p.param_type);
             inner == "string"
@@ -2653,18 +6261,102 @@ impl CodeGenerator {
             inner.starts_with("Mutex<")
         });
         if has_mutex_param {
-            return false;
+            return None;
         }
         for p in params {
             let (is_ref, is_mut, _) = Self::strip_ref_prefix(&p.param_type);
             if (p.is_reference || is_ref) && (p.is_mutable || is_mut) {
-                return false;
+                return None;
             }
         }
         if has_string_param && Self::body_contains_add(body) {
-            return false;
+            return None;
+        }
+        let mut facts = PurityFacts::default();
+        Self::body_is_pure(body, &mut facts).then_some(facts)
+    }
+
+    /// Purity as a fixpoint over the call graph: every function starts out
+    /// pure if its own body allows it, and loses it when it calls something
+    /// that isn't. Recursion among pure functions stays pure. A pure function
+    /// is also `willreturn` if it has no loops and only calls functions that
+    /// are, which leaves out anything recursive.
+    fn infer_call_graph_purity(
+        &mut self,
+        facts: &HashMap<String, PurityFacts>,
+        user_functions: &std::collections::HashSet<String>,
+    ) {
+        let checked = !self.options.no_checks;
+        let resolves = |found: &std::collections::HashSet<String>, callee: &str| match callee
+            .strip_prefix('.')
+        {
+            // `x.m()`: every user method named m, or a builtin that only reads
+            Some(method) => {
+                let suffix = format!(".{}", method);
+                let mut user = user_functions
+                    .iter()
+                    .filter(|f| f.ends_with(&suffix))
+                    .peekable();
+                if user.peek().is_none() {
+                    matches!(method, "len" | "char_count")
+                        || (!checked && matches!(method, "get" | "char_at"))
+                } else {
+                    user.all(|f| found.contains(f))
+                }
+            }
+            None if user_functions.contains(callee) => found.contains(callee),
+            None => Self::is_readonly_builtin(callee, checked),
+        };
+
+        // A call that may panic must not be dropped when its result is unused
+        let mut pure: std::collections::HashSet<String> = facts
+            .iter()
+            .filter(|(_, facts)| !(checked && facts.indexes))
+            .map(|(name, _)| name.clone())
+            .collect();
+        loop {
+            let lost: Vec<String> = pure
+                .iter()
+                .filter(|f| !facts[*f].callees.iter().all(|c| resolves(&pure, c)))
+                .cloned()
+                .collect();
+            if lost.is_empty() {
+                break;
+            }
+            for f in lost {
+                pure.remove(&f);
+            }
+        }
+
+        let mut terminating = std::collections::HashSet::new();
+        loop {
+            let found: Vec<String> = pure
+                .iter()
+                .filter(|f| !terminating.contains(*f))
+                .filter(|f| {
+                    let facts = &facts[*f];
+                    !facts.has_loop && facts.callees.iter().all(|c| resolves(&terminating, c))
+                })
+                .cloned()
+                .collect();
+            if found.is_empty() {
+                break;
+            }
+            terminating.extend(found);
         }
-        Self::body_is_pure(body)
+        self.pure_functions = pure;
+        self.terminating_functions = terminating;
+    }
+
+    /// Builtins that read their arguments and nothing else. Ones that
+    /// allocate (`vec_new`, `int_to_string`, ...) are not: two calls must
+    /// not be merged into one result. With bounds checks `vec_get` is not
+    /// either, since it can panic.
+    fn is_readonly_builtin(name: &str, checked: bool) -> bool {
+        matches!(
+            name,
+            "vec_len" | "len" | "char_count" | "compare" | "char_to_int" | "int_to_char"
+        ) || (!checked && name == "vec_get")
     }
 
     fn body_contains_add(node: &AstNode) -> bool {
@@ -2673,103 +6365,139 @@ impl CodeGenerator {
             AstNode::BinaryOp { left, right, .. } => {
                 Self::body_contains_add(left) || Self::body_contains_add(right)
             }
-            AstNode::Block(nodes) | AstNode::Program(nodes) => {
+            AstNode::Block(nodes, _) | AstNode::Program(nodes, _) => {
                 nodes.iter().any(Self::body_contains_add)
             }
-            AstNode::Return(Some(v)) => Self::body_contains_add(v),
+            AstNode::Return(Some(v), _) => Self::body_contains_add(v),
             AstNode::LetBinding { value, .. } => Self::body_contains_add(value),
             AstNode::If {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
                 Self::body_contains_add(condition)
                     || Self::body_contains_add(then_block)
                     || else_block
                         .as_ref()
-                        .map_or(false, |e| Self::body_contains_add(e))
+                        .is_some_and(|e| Self::body_contains_add(e))
             }
             AstNode::Call { args, .. } => args.iter().any(Self::body_contains_add),
-            AstNode::ExpressionStatement(e) => Self::body_contains_add(e),
+            AstNode::ExpressionStatement(e, _) => Self::body_contains_add(e),
             _ => false,
         }
     }
 
-    fn body_is_pure(node: &AstNode) -> bool {
+    /// Whether `node` neither writes memory nor allocates, apart from the
+    /// calls it makes, which are recorded in `facts`.
+    fn body_is_pure(node: &AstNode, facts: &mut PurityFacts) -> bool {
         match node {
             AstNode::Assignment { .. }
             | AstNode::ArrayAssignment { .. }
             | AstNode::MemberAssignment { .. } => false,
-            AstNode::Call { name, args } => {
-                let known_pure = matches!(
-                    name.as_str(),
-                    "vec_new"
-                        | "vec_get"
-                        | "vec_len"
-                        | "int_to_string"
-                        | "fib"
-                        | "add"
-                        | "is_between"
-                );
-                known_pure && args.iter().all(Self::body_is_pure)
+            AstNode::Call { name, args, .. } => {
+                facts.callees.push(name.clone());
+                args.iter().all(|a| Self::body_is_pure(a, facts))
             }
-            AstNode::Program(nodes) | AstNode::Block(nodes) => nodes.iter().all(Self::body_is_pure),
-            AstNode::FunctionDef { body, .. } => Self::body_is_pure(body),
-            AstNode::LetBinding { value, .. } => Self::body_is_pure(value),
+            AstNode::Program(nodes, _) | AstNode::Block(nodes, _) => {
+                nodes.iter().all(|n| Self::body_is_pure(n, facts))
+            }
+            AstNode::FunctionDef { body, .. } => Self::body_is_pure(body, facts),
+            AstNode::LetBinding { value, .. } => Self::body_is_pure(value, facts),
             AstNode::If {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
-                Self::body_is_pure(condition)
-                    && Self::body_is_pure(then_block)
-                    && else_block.as_ref().map_or(true, |e| Self::body_is_pure(e))
+                Self::body_is_pure(condition, facts)
+                    && Self::body_is_pure(then_block, facts)
+                    && else_block
+                        .as_ref()
+                        .is_none_or(|e| Self::body_is_pure(e, facts))
             }
-            AstNode::While { condition, body } => {
-                Self::body_is_pure(condition) && Self::body_is_pure(body)
+            AstNode::While {
+                condition, body, ..
+            } => {
+                facts.has_loop = true;
+                Self::body_is_pure(condition, facts) && Self::body_is_pure(body, facts)
             }
+            // Anything but a range may be an iterator whose `next` mutates it
             AstNode::For { iterator, body, .. } => {
-                Self::body_is_pure(iterator) && Self::body_is_pure(body)
+                facts.has_loop = true;
+                matches!(
+                    iterator.as_ref(),
+                    AstNode::BinaryOp {
+                        op: BinOp::DotDot,
+                        ..
+                    } | AstNode::Number(_, _)
+                ) && Self::body_is_pure(iterator, facts)
+                    && Self::body_is_pure(body, facts)
             }
-            AstNode::Return(v) => v.as_ref().map_or(true, |n| Self::body_is_pure(n)),
-            AstNode::BinaryOp { op, left, right } => {
+            AstNode::Return(v, _) => v.as_ref().is_none_or(|n| Self::body_is_pure(n, facts)),
+            AstNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                // A checked division can panic
+                if matches!(op, BinOp::Div | BinOp::Mod)
+                    && !matches!(right.as_ref(), AstNode::Number(n, _) if *n != 0)
+                {
+                    return false;
+                }
                 if matches!(op, BinOp::Add) {
-                    let has_string_lit = matches!(left.as_ref(), AstNode::StringLit(_))
-                        || matches!(right.as_ref(), AstNode::StringLit(_));
+                    let has_string_lit = matches!(left.as_ref(), AstNode::StringLit(_, _))
+                        || matches!(right.as_ref(), AstNode::StringLit(_, _));
                     if has_string_lit {
                         return false;
                     }
                 }
-                Self::body_is_pure(left) && Self::body_is_pure(right)
+                Self::body_is_pure(left, facts) && Self::body_is_pure(right, facts)
             }
-            AstNode::UnaryOp { operand, .. } => Self::body_is_pure(operand),
-            AstNode::ExpressionStatement(e) => Self::body_is_pure(e),
-            AstNode::Match { value, arms } => {
-                Self::body_is_pure(value) && arms.iter().all(|a| Self::body_is_pure(&a.body))
+            AstNode::UnaryOp { operand, .. } | AstNode::Cast { value: operand, .. } => {
+                Self::body_is_pure(operand, facts)
             }
-            AstNode::ArrayLit(elems) => elems.iter().all(Self::body_is_pure),
-            AstNode::StructInit { fields, .. } => fields.iter().all(|(_, v)| Self::body_is_pure(v)),
-            AstNode::Index { array, index } => {
-                Self::body_is_pure(array) && Self::body_is_pure(index)
+            AstNode::ExpressionStatement(e, _) => Self::body_is_pure(e, facts),
+            AstNode::Match { value, arms, .. } => {
+                Self::body_is_pure(value, facts)
+                    && arms.iter().all(|a| Self::body_is_pure(&a.body, facts))
             }
-            AstNode::Reference(e) => Self::body_is_pure(e),
-            AstNode::EnumValue { value: Some(e), .. } => Self::body_is_pure(e),
-            AstNode::MethodCall { object, args, .. } => {
-                Self::body_is_pure(object) && args.iter().all(Self::body_is_pure)
+            AstNode::ArrayLit(elems, _) => elems.iter().all(|e| Self::body_is_pure(e, facts)),
+            AstNode::Index { array, index, .. } => {
+                facts.indexes = true;
+                Self::body_is_pure(array, facts) && Self::body_is_pure(index, facts)
+            }
+            // Each of these builds a new value in memory the caller can see
+            AstNode::StructInit { .. }
+            | AstNode::EnumValue { .. }
+            | AstNode::Slice { .. }
+            | AstNode::BytesLit(_, _) => false,
+            AstNode::Reference(e, _) => Self::body_is_pure(e, facts),
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => {
+                facts.callees.push(format!(".{}", method));
+                Self::body_is_pure(object, facts)
+                    && args.iter().all(|a| Self::body_is_pure(a, facts))
             }
-            AstNode::MemberAccess { object, .. } => Self::body_is_pure(object),
+            AstNode::MemberAccess { object, .. } => Self::body_is_pure(object, facts),
             AstNode::Identifier { .. }
-            | AstNode::Number(_)
-            | AstNode::Boolean(_)
-            | AstNode::StringLit(_)
-            | AstNode::Character(_)
-            | AstNode::Break
-            | AstNode::Continue
+            | AstNode::Number(_, _)
+            | AstNode::Boolean(_, _)
+            | AstNode::Null(_)
+            | AstNode::StringLit(_, _)
+            | AstNode::Character(_, _)
+            | AstNode::SizeOf(_, _)
+            | AstNode::Break(_)
+            | AstNode::Continue(_)
             | AstNode::Import { .. }
+            | AstNode::When { .. }
+            | AstNode::ExternFn { .. }
             | AstNode::StructDef { .. }
             | AstNode::EnumDef { .. }
-            | AstNode::ArrayType { .. }
-            | AstNode::EnumValue { value: None, .. } => true,
+            | AstNode::ArrayType { .. } => true,
         }
     }
 
@@ -2783,223 +6511,3450 @@ impl CodeGenerator {
         }
     }
 
-    fn mangle_fn(name: &str) -> String {
-        match name {
-            "main" => "main".to_string(),
-            _ => format!("brn_{}", name),
+    /// Declare each `extern fn` the runtime doesn't already provide. One it
+    /// does is called through a cast when the types differ, so a program
+    /// can declare `puts` or `malloc` the way it wants to use them.
+    fn declare_externs(&mut self) {
+        let mut names: Vec<String> = self.externs.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let sig = &self.externs[&name];
+            let mut params = sig.params.clone();
+            if sig.variadic {
+                params.push("...".to_string());
+            }
+            let fn_type = format!("{} ({})", sig.return_type, params.join(", "));
+            match self.declared_fn_type(&name) {
+                None => {
+                    let decl = format!(
+                        "declare {} @{}({})",
+                        sig.return_type,
+                        name,
+                        params.join(", ")
+                    );
+                    self.emit(&decl);
+                }
+                Some(existing) if existing == fn_type => {}
+                Some(existing) => {
+                    let callee = format!("bitcast ({}* @{} to {}*)", existing, name, fn_type);
+                    if let Some(sig) = self.externs.get_mut(&name) {
+                        sig.callee = callee;
+                    }
+                }
+            }
         }
     }
 
-    fn gen_function(
-        &mut self,
-        name: &str,
-        params: &[Parameter],
-        body: &AstNode,
-        return_type: &Option<String>,
-        is_unsafe: bool,
-    ) -> String {
-        self.current_function_vars.clear();
-        self.temp_counter = 0;
-        self.label_counter = 0;
-        self.is_unsafe_fn = is_unsafe;
-        self.guard_vars.clear();
+    /// The function type (`i32 (i8*)`) the IR so far declares or defines
+    /// `@name` with.
+    fn declared_fn_type(&self, name: &str) -> Option<String> {
+        let marker = format!(" @{}(", name);
+        self.output.lines().find_map(|line| {
+            if !line.starts_with("declare ") && !line.starts_with("define ") {
+                return None;
+            }
+            let (head, rest) = line.split_once(&marker)?;
+            let return_type = head.split_whitespace().last()?;
+            let params = rest.split_once(')')?.0;
+            let params: Vec<&str> = params
+                .split(", ")
+                .filter(|p| !p.is_empty())
+                // `define` lines name their parameters: `i8* %s`
+                .map(|p| p.split_once(" %").map_or(p, |(ty, _)| ty))
+                .collect();
+            Some(format!("{} ({})", return_type, params.join(", ")))
+        })
+    }
 
-        let escaping = EscapeAnalysis::analyze(params, body);
-        self.non_escaping.clear();
-        if let AstNode::Block(stmts) = body {
-            for stmt in stmts {
-                if let AstNode::LetBinding { name, .. } = stmt {
-                    if !escaping.contains(name) {
-                        self.non_escaping.insert(name.clone());
-                    }
+    /// A call to an `extern fn`. Arguments are converted to the declared
+    /// parameter types; ones passed to `...` follow C's promotions, so a
+    /// `bool` or `char` goes as an `i32`.
+    fn gen_extern_call(&mut self, name: &str, args: &[AstNode]) -> String {
+        let (params, variadic, return_type, callee) = {
+            let sig = &self.externs[name];
+            (
+                sig.params.clone(),
+                sig.variadic,
+                sig.return_type.clone(),
+                sig.callee.clone(),
+            )
+        };
+
+        let mut arg_list = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let value = self.gen_node(arg);
+            let typed = match params.get(i).map(String::as_str) {
+                Some("i64") => ("i64".to_string(), self.coerce_i64(value)),
+                Some("i1") => ("i1".to_string(), self.coerce_i1(value)),
+                Some("i8") => ("i8".to_string(), self.convert(value, "char")),
+                Some(ty) => (ty.to_string(), value.reg),
+                None if value.llvm_ty == "i1" || value.llvm_ty == "i8" => {
+                    let promoted = self.new_temp();
+                    self.cast(&promoted, "zext", &value.llvm_ty, &value.reg, "i32");
+                    ("i32".to_string(), promoted)
                 }
-            }
+                None => (value.llvm_ty, value.reg),
+            };
+            arg_list.push(typed);
         }
 
-        let ret_type = if name == "main" {
+        let dest = (return_type != "void").then(|| self.new_temp());
+        self.push(Inst::Call {
+            dest: dest.clone(),
+            ret: return_type,
+            callee,
+            args: arg_list,
+            // A variadic callee is called with its whole function type
+            variadic: variadic.then_some(params),
+        });
+        dest.unwrap_or_else(|| "0".to_string())
+    }
+
+    /// Record `name`'s return type for its call sites.
+    fn register_signature(&mut self, name: &str, return_type: &Option<String>) {
+        let ret_llvm = if name == "main" {
             "i32".to_string()
         } else if let Some(rt) = return_type {
-            self.type_to_llvm(rt)
+            // A returned `&T` is what `T` is; see `borrowed_returns`
+            self.value_type_to_llvm(Self::strip_ref_prefix(rt).2)
         } else {
             "void".to_string()
         };
+        self.function_signatures.insert(name.to_string(), ret_llvm);
+        if let Some(rt) = return_type
+            && (Self::is_vec_type(rt)
+                || rt == "Bytes"
+                || rt.starts_with('*')
+                || self.enum_types.contains_key(rt))
+        {
+            self.vec_return_types.insert(name.to_string(), rt.clone());
+        }
+        if return_type.as_ref().is_some_and(|rt| rt.starts_with('&')) {
+            self.borrowed_returns.insert(name.to_string());
+        }
+    }
 
-        self.function_signatures
-            .insert(name.to_string(), ret_type.clone());
-        self.current_function_name = name.to_string();
-        self.current_function_return_type = ret_type.clone();
+    /// Record `name`'s parameter types for its call sites.
+    fn register_params(&mut self, name: &str, params: &[Parameter]) {
+        let types = params.iter().map(|p| p.param_type.clone()).collect();
+        self.param_types.insert(name.to_string(), types);
+    }
 
-        let param_list = if params.is_empty() {
-            String::new()
-        } else {
-            params
-                .iter()
-                .map(|p| {
-                    let (type_is_ref, type_is_mut, inner_type) =
-                        Self::strip_ref_prefix(&p.param_type);
-                    let type_is_ref = type_is_ref || p.is_reference;
-                    let type_is_mut = type_is_mut || p.is_mutable;
+    /// Ready a loop whose condition and body are `parts`. Pure integer
+    /// expressions of variables the loop never changes (like `s.len()` of a
+    /// string it doesn't reassign) are computed once, here, and `i * c` for a
+    /// variable that only moves in constant steps becomes a running product
+    /// that is advanced instead. `counter` is a counted `for`'s variable and
+    /// start; it moves by one per turn.
+    fn enter_loop(&mut self, parts: &[&AstNode], counter: Option<(&str, &str)>) -> LoopScope {
+        let mut scope = LoopScope {
+            saved_values: Vec::new(),
+            saved_steps: Vec::new(),
+            counter_steps: Vec::new(),
+        };
+        let mut facts = LoopFacts::analyze(parts, &self.current_function_vars);
+
+        // Induction variables: locals changed only by constant steps, each
+        // with the steps to follow; a counted `for` moves by one per turn
+        let mut inductions: HashMap<&str, Option<Vec<(&AstNode, i64)>>> = HashMap::new();
+        for (name, steps) in &facts.steps {
+            if let Some(steps) = steps
+                && !facts.bound.contains(name)
+                && self.current_function_vars.get(*name).is_some_and(|m| {
+                    m.var_type == "int"
+                        && m.llvm_name.starts_with('%')
+                        && !m.llvm_name.starts_with("%arg_")
+                })
+            {
+                inductions.insert(name, Some(steps.clone()));
+            }
+        }
+        if let Some((name, _)) = counter {
+            if !facts.bound.contains(name) && !facts.changed.contains(name) {
+                inductions.insert(name, None);
+            }
+            // Inside the body the name is the loop's own variable
+            facts.bound.insert(name);
+        }
 
-                    let param_type_str = if type_is_ref {
-                        if inner_type.starts_with('[') {
-                            if let Some(size_str) = inner_type.split(';').nth(1) {
-                                let size = size_str
-                                    .trim()
-                                    .trim_end_matches(']')
-                                    .trim()
-                                    .parse::<usize>()
-                                    .unwrap_or(100);
-                                format!("[{} x i64]*", size)
-                            } else {
-                                "i64*".to_string()
-                            }
-                        } else if inner_type.starts_with("Mutex<") {
-                            "i8*".to_string()
-                        } else {
-                            let base = self.type_to_llvm(inner_type);
-                            if base.ends_with('*') {
-                                base
-                            } else {
-                                format!("{}*", base)
-                            }
+        let mut scaled = Vec::new();
+        for part in parts {
+            Self::collect_scaled(part, &inductions, &mut scaled);
+        }
+        let mut running: HashMap<(&str, i64), String> = HashMap::new();
+        for (node, name, factor) in scaled {
+            let slot = match running.get(&(name, factor)) {
+                Some(slot) => slot.clone(),
+                None => {
+                    let slot = format!("%{}", self.new_label("sr"));
+                    self.builder().alloca_in_entry(slot.clone(), "i64");
+                    let variable = self
+                        .current_function_vars
+                        .get(name)
+                        .map(|m| m.llvm_name.clone());
+                    let start = match (counter, variable) {
+                        (Some((counter_name, start)), _) if counter_name == name => {
+                            start.to_string()
                         }
-                    } else {
-                        self.type_to_llvm(&p.param_type)
+                        (_, Some(variable)) => {
+                            let current = self.new_temp();
+                            self.load_into(&current, "i64", &variable);
+                            current
+                        }
+                        (_, None) => continue,
                     };
+                    let product = self.new_temp();
+                    self.binary(&product, "mul", "i64", &start, &factor.to_string());
+                    self.store("i64", &product, &slot);
+                    match &inductions[name] {
+                        Some(steps) => {
+                            for (step_node, k) in steps {
+                                let key = *step_node as *const AstNode;
+                                if !scope.saved_steps.iter().any(|(saved, _)| *saved == key) {
+                                    scope
+                                        .saved_steps
+                                        .push((key, self.induction_steps.get(&key).cloned()));
+                                }
+                                self.induction_steps
+                                    .entry(key)
+                                    .or_default()
+                                    .push((slot.clone(), k.wrapping_mul(factor)));
+                            }
+                        }
+                        None => scope.counter_steps.push((slot.clone(), factor)),
+                    }
+                    running.insert((name, factor), slot.clone());
+                    slot
+                }
+            };
+            let key = node as *const AstNode;
+            scope
+                .saved_values
+                .push((key, self.loop_values.insert(key, LoopValue::Running(slot))));
+        }
 
-                    let is_mutex_param = inner_type.starts_with("Mutex<");
-                    let is_simple_ptr = type_is_ref && !inner_type.starts_with('[');
-                    let is_owned_ptr =
-                        !type_is_ref && Self::is_pointer_llvm_type(&p.param_type) && !type_is_mut;
+        let mut hoisted = Vec::new();
+        for part in parts {
+            self.collect_invariant(part, &facts, &mut hoisted);
+        }
+        for node in hoisted {
+            let reg = self.gen_node(node).reg;
+            let key = node as *const AstNode;
+            scope
+                .saved_values
+                .push((key, self.loop_values.insert(key, LoopValue::Hoisted(reg))));
+        }
+        scope
+    }
 
-                    let attrs = if is_mutex_param {
-                        ""
-                    } else if is_simple_ptr {
-                        if !type_is_mut {
-                            "noalias readonly"
-                        } else {
-                            "noalias"
-                        }
-                    } else if is_owned_ptr {
+    /// Put back what `enter_loop` replaced, innermost first.
+    fn leave_loop(&mut self, scope: LoopScope) {
+        for (key, saved) in scope.saved_values.into_iter().rev() {
+            match saved {
+                Some(value) => self.loop_values.insert(key, value),
+                None => self.loop_values.remove(&key),
+            };
+        }
+        for (key, saved) in scope.saved_steps.into_iter().rev() {
+            match saved {
+                Some(steps) => self.induction_steps.insert(key, steps),
+                None => self.induction_steps.remove(&key),
+            };
+        }
+    }
+
+    fn emit_slot_add(&mut self, slot: &str, step: i64) {
+        let current = self.new_temp();
+        let next = self.new_temp();
+        self.load_into(&current, "i64", slot);
+        self.binary(&next, "add", "i64", &current, &step.to_string());
+        self.store("i64", &next, slot);
+    }
+
+    /// Every `i * c` or `c * i` under `node` for an induction variable `i`.
+    fn collect_scaled<'n>(
+        node: &'n AstNode,
+        inductions: &HashMap<&str, Option<Vec<(&AstNode, i64)>>>,
+        out: &mut Vec<(&'n AstNode, &'n str, i64)>,
+    ) {
+        if let AstNode::BinaryOp {
+            op: BinOp::Mul,
+            left,
+            right,
+            ..
+        } = node
+        {
+            match (left.as_ref(), right.as_ref()) {
+                (AstNode::Identifier { name, .. }, AstNode::Number(c, _))
+                | (AstNode::Number(c, _), AstNode::Identifier { name, .. })
+                    if inductions.contains_key(name.as_str()) =>
+                {
+                    out.push((node, name, *c));
+                    return;
+                }
+                _ => {}
+            }
+        }
+        for child in node.children() {
+            Self::collect_scaled(child, inductions, out);
+        }
+    }
+
+    /// The largest expressions under `node` worth computing once before
+    /// the loop `facts` describes.
+    fn collect_invariant<'n>(
+        &self,
+        node: &'n AstNode,
+        facts: &LoopFacts,
+        out: &mut Vec<&'n AstNode>,
+    ) {
+        match node {
+            AstNode::BinaryOp { .. } | AstNode::UnaryOp { .. } | AstNode::MethodCall { .. }
+                if self.is_loop_invariant(node, facts)
+                    && !self.loop_values.contains_key(&(node as *const AstNode)) =>
+            {
+                out.push(node)
+            }
+            // A reference needs the place itself
+            AstNode::Reference(..) => {}
+            _ => {
+                for child in node.children() {
+                    self.collect_invariant(child, facts, out);
+                }
+            }
+        }
+    }
+
+    /// Whether `node` has the same value every time round the loop and is
+    /// safe to compute even if the loop never runs: no division, which can
+    /// stop the program, and nothing that allocates.
+    fn is_loop_invariant(&self, node: &AstNode, facts: &LoopFacts) -> bool {
+        match node {
+            AstNode::Number(..) | AstNode::Boolean(..) | AstNode::Character(..) => true,
+            // Globals are left out: any function called could change one
+            AstNode::Identifier { name, .. } => {
+                !facts.bound.contains(name.as_str())
+                    && !facts.changed.contains(name.as_str())
+                    && self
+                        .current_function_vars
+                        .get(name)
+                        .is_some_and(|m| !m.llvm_name.starts_with('@'))
+            }
+            AstNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                !matches!(op, BinOp::Div | BinOp::Mod | BinOp::DotDot)
+                    && matches!(self.infer_type(left).as_str(), "int" | "bool" | "char")
+                    && self.is_loop_invariant(left, facts)
+                    && self.is_loop_invariant(right, facts)
+            }
+            AstNode::UnaryOp { operand, .. } => self.is_loop_invariant(operand, facts),
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => {
+                let object_type = self.infer_type(object);
+                args.is_empty()
+                    && matches!(method.as_str(), "len" | "char_count")
+                    && matches!(object.as_ref(), AstNode::Identifier { .. })
+                    && (object_type == "string"
+                        || object_type == "Bytes"
+                        || Self::is_vec_type(&object_type))
+                    && self.is_loop_invariant(object, facts)
+            }
+            _ => false,
+        }
+    }
+
+    /// A match on an integer, or on an enum's tag, as one `switch`. Each
+    /// value goes to the first arm that matches it and anything else to the
+    /// first `_` or binding arm, or past the match; arms after that one can
+    /// never run and aren't generated.
+    fn gen_switch_match(
+        &mut self,
+        value_reg: &str,
+        arms: &[MatchArm],
+        is_enum_match: bool,
+        end_label: &str,
+    ) {
+        let (ty, scrutinee) = if is_enum_match {
+            let tag_ptr = self.new_temp();
+            self.gep(
+                &tag_ptr,
+                "{ i32, i64 }",
+                value_reg,
+                &[("i32", "0"), ("i32", "0")],
+            );
+            let tag = self.new_temp();
+            self.load_into(&tag, "i32", &tag_ptr);
+            ("i32", tag)
+        } else {
+            ("i64", value_reg.to_string())
+        };
+
+        let mut cases: Vec<(i64, String)> = Vec::new();
+        let mut live: Vec<(String, &MatchArm)> = Vec::new();
+        let mut default_label = end_label.to_string();
+        for (i, arm) in arms.iter().enumerate() {
+            let case = match &arm.pattern {
+                Pattern::EnumPattern {
+                    enum_name, variant, ..
+                } if is_enum_match => Some(
+                    self.enum_types
+                        .get(enum_name)
+                        .and_then(|variants| variants.iter().position(|v| v == variant))
+                        .unwrap_or(i) as i64,
+                ),
+                Pattern::NumberPattern(n) if !is_enum_match => Some(*n),
+                Pattern::Wildcard | Pattern::Identifier(_) => None,
+                _ => continue,
+            };
+            // A value an earlier arm already matches never reaches this one
+            if case.is_some_and(|v| cases.iter().any(|(seen, _)| *seen == v)) {
+                continue;
+            }
+            let arm_label = self.new_label(&format!("match_arm_{}", i));
+            live.push((arm_label.clone(), arm));
+            match case {
+                Some(v) => cases.push((v, arm_label)),
+                None => {
+                    default_label = arm_label;
+                    break;
+                }
+            }
+        }
+
+        self.push(Inst::Switch {
+            ty: ty.to_string(),
+            value: scrutinee,
+            default: default_label,
+            cases,
+        });
+
+        for (arm_label, arm) in live {
+            self.label(&arm_label);
+            if let Pattern::EnumPattern {
+                enum_name,
+                variant,
+                payload,
+            } = &arm.pattern
+                && let Some(Pattern::Identifier(binding)) = payload.first()
+            {
+                let ty = self.variant_fields(enum_name, variant).swap_remove(0);
+                let held = self.load_payload(value_reg);
+                self.bind_word(binding, &ty, &held);
+            }
+            self.gen_match_arm(&arm.body, end_label);
+        }
+    }
+
+    /// The `i64` an enum stores for a variant's `values`: nothing is 0, one
+    /// value is stored as itself and more go in an `[n x i64]` block, one
+    /// slot each, whose address is stored.
+    fn gen_payload(&mut self, values: &[AstNode]) -> String {
+        let words: Vec<String> = values.iter().map(|v| self.payload_word(v)).collect();
+        match words.as_slice() {
+            [] => "0".to_string(),
+            [word] => word.clone(),
+            words => {
+                let block_ty = format!("[{} x i64]", words.len());
+                let block = self.new_temp();
+                if self.returning {
+                    let raw = self.new_temp();
+                    self.call(
+                        Some(&raw),
+                        "i8*",
+                        "@malloc",
+                        &[("i64", &(8 * words.len()).to_string())],
+                    );
+                    self.cast(&block, "bitcast", "i8*", &raw, &format!("{}*", block_ty));
+                } else {
+                    self.alloca_into(&block, &block_ty);
+                }
+                for (i, word) in words.iter().enumerate() {
+                    let slot = self.new_temp();
+                    self.gep(
+                        &slot,
+                        &block_ty,
+                        &block,
+                        &[("i64", "0"), ("i64", &i.to_string())],
+                    );
+                    self.store("i64", word, &slot);
+                }
+                self.ptr_to_word(&format!("{}*", block_ty), &block)
+            }
+        }
+    }
+
+    /// One value a variant holds, as an `i64`: a number as itself, anything
+    /// else by its address. A string or struct a variable owned moves into
+    /// the enum, and a value struct is copied so the enum has its own.
+    fn payload_word(&mut self, value: &AstNode) -> String {
+        let generated = self.gen_node(value);
+        if !generated.llvm_ty.ends_with('*') {
+            return self.coerce_i64(generated);
+        }
+        let ty = generated.brain_ty.clone();
+        let llvm_ty = generated.llvm_ty.clone();
+        let mut reg = generated.reg;
+        if ty == "string" {
+            reg = self.take_string(value, reg);
+        } else if self.is_value_struct(&ty) {
+            if self.returning || !matches!(value, AstNode::StructInit { .. } | AstNode::Call { .. })
+            {
+                reg = self.copy_struct(&ty, &reg, self.returning);
+            }
+        } else if let AstNode::Identifier { name, .. } = value
+            && let Some(meta) = self.current_function_vars.get(name)
+            && meta.is_heap
+        {
+            self.moved_vars.insert(meta.llvm_name.clone());
+        }
+        self.ptr_to_word(&llvm_ty, &reg)
+    }
+
+    /// The address `ptr` of type `ty` as an `i64`.
+    fn ptr_to_word(&mut self, ty: &str, ptr: &str) -> String {
+        let word = self.new_temp();
+        self.push(Inst::Cast {
+            dest: word.clone(),
+            op: "ptrtoint",
+            from: ty.to_string(),
+            value: ptr.to_string(),
+            to: "i64".to_string(),
+        });
+        word
+    }
+
+    /// The types `enum_name::variant` holds, or one `int` for a variant
+    /// this enum doesn't declare.
+    fn variant_fields(&self, enum_name: &str, variant: &str) -> Vec<String> {
+        let index = self
+            .enum_types
+            .get(enum_name)
+            .and_then(|variants| variants.iter().position(|v| v == variant));
+        match index.and_then(|i| self.enum_fields.get(enum_name)?.get(i)) {
+            Some(fields) if !fields.is_empty() => fields.clone(),
+            _ => vec!["int".to_string()],
+        }
+    }
+
+    /// Bind `name` in a match arm to the `ty` that `word` holds (see
+    /// `payload_word`). The binding borrows it from the value matched.
+    fn bind_word(&mut self, name: &str, ty: &str, word: &str) {
+        let ty = if ty == "unknown" { "int" } else { ty };
+        let llvm_ty = self.type_to_llvm(ty);
+        let value = match llvm_ty.as_str() {
+            "i64" => word.to_string(),
+            "i1" | "i8" => {
+                let value = self.new_temp();
+                self.push(Inst::Cast {
+                    dest: value.clone(),
+                    op: "trunc",
+                    from: "i64".to_string(),
+                    value: word.to_string(),
+                    to: llvm_ty.clone(),
+                });
+                value
+            }
+            _ => self.word_to_ptr(&llvm_ty, word),
+        };
+        // A struct is used through its pointer, like one built on the stack
+        let llvm_name = if self.struct_types.contains_key(ty) {
+            value
+        } else {
+            let var_ptr = self.new_temp();
+            self.alloca_into(&var_ptr, &llvm_ty);
+            self.store(&llvm_ty, &value, &var_ptr);
+            var_ptr
+        };
+        self.current_function_vars.insert(
+            name.to_string(),
+            VarMetadata {
+                llvm_name,
+                var_type: ty.to_string(),
+                is_heap: false,
+                array_size: None,
+            },
+        );
+    }
+
+    /// The `ty` pointer an `i64` from `ptr_to_word` holds.
+    fn word_to_ptr(&mut self, ty: &str, word: &str) -> String {
+        let ptr = self.new_temp();
+        self.push(Inst::Cast {
+            dest: ptr.clone(),
+            op: "inttoptr",
+            from: "i64".to_string(),
+            value: word.to_string(),
+            to: ty.to_string(),
+        });
+        ptr
+    }
+
+    /// Test `pattern` against `word`, an `i64` holding a `ty` the way a
+    /// variant holds it, and bind the names in it. A value that doesn't
+    /// match goes to `next_label`; one that does carries on from where this
+    /// leaves off. Variants' values and structs' fields are tested in
+    /// order, each with its own pattern.
+    fn gen_pattern(&mut self, pattern: &Pattern, ty: &str, word: &str, next_label: &str) {
+        match pattern {
+            Pattern::Identifier(name) => self.bind_word(name, ty, word),
+            Pattern::NumberPattern(n) => {
+                let cond = self.new_temp();
+                self.icmp(&cond, "eq", "i64", word, &n.to_string());
+                self.pattern_passes(&cond, next_label);
+            }
+            Pattern::StringPattern(text) => {
+                let value = self.word_to_ptr("i8*", word);
+                let str_ptr = self.string_literal_ptr(text);
+                let cmp_result = self.new_temp();
+                self.call(
+                    Some(&cmp_result),
+                    "i32",
+                    "@strcmp",
+                    &[("i8*", &value), ("i8*", &str_ptr)],
+                );
+                let cond = self.new_temp();
+                self.icmp(&cond, "eq", "i32", &cmp_result, "0");
+                self.pattern_passes(&cond, next_label);
+            }
+            Pattern::EnumPattern {
+                enum_name,
+                variant,
+                payload,
+            } => {
+                let enum_name = if enum_name.is_empty() { ty } else { enum_name };
+                let value = self.word_to_ptr("{ i32, i64 }*", word);
+                let tag_ptr = self.new_temp();
+                self.gep(
+                    &tag_ptr,
+                    "{ i32, i64 }",
+                    &value,
+                    &[("i32", "0"), ("i32", "0")],
+                );
+                let tag = self.new_temp();
+                self.load_into(&tag, "i32", &tag_ptr);
+                let index = self
+                    .enum_types
+                    .get(enum_name)
+                    .and_then(|variants| variants.iter().position(|v| v == variant))
+                    .unwrap_or(0);
+                let is_variant = self.new_temp();
+                self.icmp(&is_variant, "eq", "i32", &tag, &index.to_string());
+                self.pattern_passes(&is_variant, next_label);
+                if payload.iter().all(|p| matches!(p, Pattern::Wildcard)) {
+                    return;
+                }
+                let fields = self.variant_fields(enum_name, variant);
+                let held = self.load_payload(&value);
+                if let [pattern] = payload.as_slice() {
+                    self.gen_pattern(pattern, &fields[0], &held, next_label);
+                    return;
+                }
+                let block_ty = format!("[{} x i64]", payload.len());
+                let block = self.word_to_ptr(&format!("{}*", block_ty), &held);
+                for (i, (pattern, field_ty)) in payload.iter().zip(&fields).enumerate() {
+                    if matches!(pattern, Pattern::Wildcard) {
+                        continue;
+                    }
+                    let slot = self.new_temp();
+                    self.gep(
+                        &slot,
+                        &block_ty,
+                        &block,
+                        &[("i64", "0"), ("i64", &i.to_string())],
+                    );
+                    let field = self.new_temp();
+                    self.load_into(&field, "i64", &slot);
+                    self.gen_pattern(pattern, field_ty, &field, next_label);
+                }
+            }
+            Pattern::StructPattern {
+                struct_name,
+                fields,
+                ..
+            } => {
+                let declared = self
+                    .struct_types
+                    .get(struct_name)
+                    .cloned()
+                    .unwrap_or_default();
+                let value = self.word_to_ptr(&format!("%{}*", struct_name), word);
+                for (field, pattern) in fields {
+                    let Some(index) = Self::field_index(&declared, field) else {
+                        continue;
+                    };
+                    if matches!(pattern, Pattern::Wildcard) {
+                        continue;
+                    }
+                    let field_ty = declared[index].1.clone();
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", struct_name),
+                        &value,
+                        &[("i32", "0"), ("i32", &index.to_string())],
+                    );
+                    // A value struct field is stored inline: its address is
+                    // what the pattern gets
+                    let held = if self.is_value_struct(&field_ty) {
+                        self.ptr_to_word(&format!("%{}*", field_ty), &gep)
+                    } else {
+                        let llvm_ty = self.type_to_llvm(&field_ty);
+                        let loaded = self.new_temp();
+                        self.load_into(&loaded, &llvm_ty, &gep);
+                        match llvm_ty.as_str() {
+                            "i64" => loaded,
+                            "i1" | "i8" => self.widen(&loaded, &llvm_ty, "zext"),
+                            _ => self.ptr_to_word(&llvm_ty, &loaded),
+                        }
+                    };
+                    self.gen_pattern(pattern, &field_ty, &held, next_label);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Carry on past a pattern's test when `cond` holds, and go to
+    /// `next_label` otherwise.
+    fn pattern_passes(&mut self, cond: &str, next_label: &str) {
+        let matched = self.new_label("pattern_ok");
+        self.cond_br(cond, &matched, next_label);
+        self.label(&matched);
+    }
+
+    /// The value an enum at `value_reg` holds.
+    fn load_payload(&mut self, value_reg: &str) -> String {
+        let val_ptr = self.new_temp();
+        self.gep(
+            &val_ptr,
+            "{ i32, i64 }",
+            value_reg,
+            &[("i32", "0"), ("i32", "1")],
+        );
+        let val = self.new_temp();
+        self.load_into(&val, "i64", &val_ptr);
+        val
+    }
+
+    /// The name of the variant the `enum_name` value at `value_reg` holds, read
+    /// from the enum's `@.enum.<Name>` table. The string is a constant, so
+    /// nothing owns it.
+    fn gen_variant_name(&mut self, value_reg: &str, enum_name: &str) -> String {
+        let count = self.enum_types[enum_name].len();
+        if !self.enum_name_tables.iter().any(|e| e == enum_name) {
+            for variant in self.enum_types[enum_name].clone() {
+                self.new_string_literal(&variant);
+            }
+            self.enum_name_tables.push(enum_name.to_string());
+        }
+        let tag_ptr = self.new_temp();
+        self.gep(
+            &tag_ptr,
+            "{ i32, i64 }",
+            value_reg,
+            &[("i32", "0"), ("i32", "0")],
+        );
+        let tag = self.new_temp();
+        self.load_into(&tag, "i32", &tag_ptr);
+        let slot = self.new_temp();
+        self.gep_inbounds(
+            &slot,
+            &format!("[{} x i8*]", count),
+            &format!("@.enum.{}", enum_name),
+            &[("i64", "0"), ("i32", &tag)],
+        );
+        let name = self.new_temp();
+        self.load_into(&name, "i8*", &slot);
+        name
+    }
+
+    /// Print the struct `value` as `Point { x: 1, y: 2 }`, through its
+    /// formatter.
+    fn gen_debug_call(&mut self, value: &Value) {
+        let name = value.brain_ty.clone();
+        if !self.debug_structs.contains(&name) {
+            self.debug_structs.push(name.clone());
+        }
+        let ptr = self.struct_ptr(value);
+        self.call(
+            None,
+            "void",
+            &format!("@brn_debug.{}", name),
+            &[(&format!("%{}*", name), &ptr)],
+        );
+    }
+
+    /// The formatters `debug` asked for, and those of the structs their
+    /// fields hold in turn.
+    fn emit_debug_formatters(&mut self) {
+        let mut next = 0;
+        while let Some(name) = self.debug_structs.get(next).cloned() {
+            next += 1;
+            self.temp_counter = 0;
+            self.label_counter = 0;
+            self.begin_function(format!(
+                "define internal void @brn_debug.{}(%{}* %self) nounwind {{",
+                name, name
+            ));
+            let fields = self.struct_types[&name].clone();
+            if fields.is_empty() {
+                self.print_constant(&name);
+            } else {
+                self.print_constant(&format!("{} {{ ", name));
+                for (i, (field, ty)) in fields.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    self.print_constant(&format!("{}{}: ", separator, field));
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", name),
+                        "%self",
+                        &[("i32", "0"), ("i32", &i.to_string())],
+                    );
+                    self.gen_debug_field(&gep, ty);
+                }
+                self.print_constant(" }");
+            }
+            self.push(Inst::Ret(None));
+            self.end_function();
+        }
+    }
+
+    /// Print the `ty` field at `ptr`. Strings and chars are quoted, a null
+    /// struct pointer is `null`, and what has no short form (a `Vec`, an
+    /// array) is `..`.
+    fn gen_debug_field(&mut self, ptr: &str, ty: &str) {
+        let pointee = ty.strip_prefix('*').unwrap_or(ty);
+        match ty {
+            "int" => {
+                let n = self.new_temp();
+                self.load_into(&n, "i64", ptr);
+                self.call(None, "void", "@brn_print_int_raw", &[("i64", &n)]);
+            }
+            "bool" => {
+                let b = self.new_temp();
+                self.load_into(&b, "i1", ptr);
+                let (yes, no) = (
+                    self.string_literal_ptr("true"),
+                    self.string_literal_ptr("false"),
+                );
+                let text = self.new_temp();
+                self.emit(&format!(
+                    "  {} = select i1 {}, i8* {}, i8* {}",
+                    text, b, yes, no
+                ));
+                self.call(None, "void", "@brn_print_str", &[("i8*", &text)]);
+            }
+            "char" => {
+                let c = self.new_temp();
+                self.load_into(&c, "i8", ptr);
+                self.print_constant("'");
+                self.call(None, "void", "@brn_print_char_raw", &[("i8", &c)]);
+                self.print_constant("'");
+            }
+            "string" => {
+                let s = self.new_temp();
+                self.load_into(&s, "i8*", ptr);
+                self.print_constant("\"");
+                self.call(None, "void", "@brn_print_str", &[("i8*", &s)]);
+                self.print_constant("\"");
+            }
+            _ if self.is_value_struct(ty) => {
+                if !self.debug_structs.iter().any(|s| s == ty) {
+                    self.debug_structs.push(ty.to_string());
+                }
+                self.call(
+                    None,
+                    "void",
+                    &format!("@brn_debug.{}", ty),
+                    &[(&format!("%{}*", ty), ptr)],
+                );
+            }
+            _ if self.struct_types.contains_key(pointee) => {
+                if !self.debug_structs.iter().any(|s| s == pointee) {
+                    self.debug_structs.push(pointee.to_string());
+                }
+                let inner = self.new_temp();
+                self.load_into(&inner, &format!("%{}*", pointee), ptr);
+                let is_null = self.new_temp();
+                self.icmp(&is_null, "eq", &format!("%{}*", pointee), &inner, "null");
+                let null_label = self.new_label("debug_null");
+                let some_label = self.new_label("debug_some");
+                let end_label = self.new_label("debug_end");
+                self.cond_br(&is_null, &null_label, &some_label);
+                self.label(&null_label);
+                self.print_constant("null");
+                self.br(&end_label);
+                self.label(&some_label);
+                self.call(
+                    None,
+                    "void",
+                    &format!("@brn_debug.{}", pointee),
+                    &[(&format!("%{}*", pointee), &inner)],
+                );
+                self.br(&end_label);
+                self.label(&end_label);
+            }
+            _ if self.enum_types.contains_key(ty) => {
+                let value = self.new_temp();
+                self.load_into(&value, "{ i32, i64 }*", ptr);
+                let name = self.gen_variant_name(&value, ty);
+                self.call(None, "void", "@brn_print_str", &[("i8*", &name)]);
+            }
+            _ => self.print_constant(".."),
+        }
+    }
+
+    /// A pointer to the struct `value`; a value struct may be held as the
+    /// aggregate itself, which is spilled to the stack.
+    fn struct_ptr(&mut self, value: &Value) -> String {
+        if value.llvm_ty == format!("%{}", value.brain_ty) {
+            let slot = self.new_temp();
+            self.alloca_into(&slot, &value.llvm_ty);
+            self.store(&value.llvm_ty, &value.reg, &slot);
+            slot
+        } else {
+            value.reg.clone()
+        }
+    }
+
+    /// `left == right` for two structs of the same type, field by field.
+    fn gen_struct_eq(&mut self, left: &Value, right: &Value) -> String {
+        let name = left.brain_ty.clone();
+        if !self.eq_structs.contains(&name) {
+            self.eq_structs.push(name.clone());
+        }
+        let (left, right) = (self.struct_ptr(left), self.struct_ptr(right));
+        let result = self.new_temp();
+        self.call(
+            Some(&result),
+            "i1",
+            &format!("@brn_eq.{}", name),
+            &[
+                (&format!("%{}*", name), &left),
+                (&format!("%{}*", name), &right),
+            ],
+        );
+        result
+    }
+
+    /// The equality functions `==` asked for, and those of the structs their
+    /// fields hold in turn. Each returns false at the first field that
+    /// differs.
+    fn emit_eq_functions(&mut self) {
+        let mut next = 0;
+        while let Some(name) = self.eq_structs.get(next).cloned() {
+            next += 1;
+            self.temp_counter = 0;
+            self.label_counter = 0;
+            self.begin_function(format!(
+                "define internal i1 @brn_eq.{}(%{}* %a, %{}* %b) nounwind {{",
+                name, name, name
+            ));
+            let differ = self.new_label("eq_differ");
+            for (i, (_, ty)) in self.struct_types[&name].clone().iter().enumerate() {
+                let mut fields = Vec::new();
+                for side in ["%a", "%b"] {
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        &format!("%{}", name),
+                        side,
+                        &[("i32", "0"), ("i32", &i.to_string())],
+                    );
+                    fields.push(gep);
+                }
+                let same = self.gen_field_eq(&fields[0], &fields[1], ty);
+                let next_field = self.new_label("eq_field");
+                self.cond_br(&same, &next_field, &differ);
+                self.label(&next_field);
+            }
+            self.ret("i1", "true");
+            self.label(&differ);
+            self.ret("i1", "false");
+            self.end_function();
+        }
+    }
+
+    /// Whether the `ty` fields at `a` and `b` are equal: strings by their
+    /// contents, structs field by field, enums by variant and value. A `*T`
+    /// is equal to the same pointer, and a `Vec` to the same `Vec`.
+    fn gen_field_eq(&mut self, a: &str, b: &str, ty: &str) -> String {
+        let llvm_ty = self.value_type_to_llvm(ty);
+        let load = |this: &mut Self, ptr: &str, ty: &str| {
+            let reg = this.new_temp();
+            this.load_into(&reg, ty, ptr);
+            reg
+        };
+        let result = self.new_temp();
+        if ty == "string" {
+            let (x, y) = (load(self, a, "i8*"), load(self, b, "i8*"));
+            let cmp = self.new_temp();
+            self.call(Some(&cmp), "i32", "@strcmp", &[("i8*", &x), ("i8*", &y)]);
+            self.icmp(&result, "eq", "i32", &cmp, "0");
+        } else if self.is_value_struct(ty) {
+            if !self.eq_structs.iter().any(|s| s == ty) {
+                self.eq_structs.push(ty.to_string());
+            }
+            self.call(
+                Some(&result),
+                "i1",
+                &format!("@brn_eq.{}", ty),
+                &[(&format!("%{}*", ty), a), (&format!("%{}*", ty), b)],
+            );
+        } else if self.struct_types.contains_key(ty) {
+            if !self.eq_structs.iter().any(|s| s == ty) {
+                self.eq_structs.push(ty.to_string());
+            }
+            // Only two non-null pointers are compared field by field
+            let (x, y) = (load(self, a, &llvm_ty), load(self, b, &llvm_ty));
+            let (x_null, y_null, any_null) = (self.new_temp(), self.new_temp(), self.new_temp());
+            self.icmp(&x_null, "eq", &llvm_ty, &x, "null");
+            self.icmp(&y_null, "eq", &llvm_ty, &y, "null");
+            self.binary(&any_null, "or", "i1", &x_null, &y_null);
+            let null_label = self.new_label("eq_null");
+            let deep_label = self.new_label("eq_deep");
+            let join_label = self.new_label("eq_join");
+            self.cond_br(&any_null, &null_label, &deep_label);
+            self.label(&null_label);
+            let same = self.new_temp();
+            self.icmp(&same, "eq", &llvm_ty, &x, &y);
+            self.br(&join_label);
+            self.label(&deep_label);
+            let deep = self.new_temp();
+            self.call(
+                Some(&deep),
+                "i1",
+                &format!("@brn_eq.{}", ty),
+                &[(&llvm_ty, &x), (&llvm_ty, &y)],
+            );
+            self.br(&join_label);
+            self.label(&join_label);
+            self.emit(&format!(
+                "  {} = phi i1 [ {}, %{} ], [ {}, %{} ]",
+                result, same, null_label, deep, deep_label
+            ));
+        } else if self.enum_types.contains_key(ty) {
+            let (x, y) = (load(self, a, &llvm_ty), load(self, b, &llvm_ty));
+            let mut parts = Vec::new();
+            for (index, part_ty) in [(0, "i32"), (1, "i64")] {
+                let mut values = Vec::new();
+                for value in [&x, &y] {
+                    let gep = self.new_temp();
+                    self.gep(
+                        &gep,
+                        "{ i32, i64 }",
+                        value,
+                        &[("i32", "0"), ("i32", &index.to_string())],
+                    );
+                    values.push(load(self, &gep, part_ty));
+                }
+                let eq = self.new_temp();
+                self.icmp(&eq, "eq", part_ty, &values[0], &values[1]);
+                parts.push(eq);
+            }
+            self.binary(&result, "and", "i1", &parts[0], &parts[1]);
+        } else {
+            let (x, y) = (load(self, a, &llvm_ty), load(self, b, &llvm_ty));
+            self.icmp(&result, "eq", &llvm_ty, &x, &y);
+        }
+        result
+    }
+
+    /// The hash functions `hash` asked for, and those of the structs their
+    /// fields hold in turn. Fields are mixed in FNV-1a style, one hash at a
+    /// time, so values that are `==` hash the same.
+    fn emit_hash_functions(&mut self) {
+        let mut next = 0;
+        while let Some(name) = self.hash_structs.get(next).cloned() {
+            next += 1;
+            self.temp_counter = 0;
+            self.label_counter = 0;
+            self.begin_function(format!(
+                "define internal i64 @brn_hash.{}(%{}* %self) nounwind {{",
+                name, name
+            ));
+            let mut hash = "-3750763034362895579".to_string();
+            for (i, (_, ty)) in self.struct_types[&name].clone().iter().enumerate() {
+                let gep = self.new_temp();
+                self.gep(
+                    &gep,
+                    &format!("%{}", name),
+                    "%self",
+                    &[("i32", "0"), ("i32", &i.to_string())],
+                );
+                let llvm_ty = self.value_type_to_llvm(ty);
+                let field = if self.is_value_struct(ty) {
+                    gep
+                } else {
+                    let loaded = self.new_temp();
+                    self.load_into(&loaded, &llvm_ty, &gep);
+                    loaded
+                };
+                let field_hash = self.gen_hash(&field, ty, &llvm_ty);
+                let mixed = self.new_temp();
+                self.binary(&mixed, "xor", "i64", &hash, &field_hash);
+                hash = self.new_temp();
+                self.binary(&hash, "mul", "i64", &mixed, "1099511628211");
+            }
+            self.ret("i64", &hash);
+            self.end_function();
+        }
+    }
+
+    /// The hash of `reg`, a `ty` held as `llvm_ty`; a struct is given by
+    /// pointer. Strings hash their contents, enums their variant and value,
+    /// and a null struct pointer hashes to 0. Anything else (a `*T`, a
+    /// `Vec`) hashes its address, as `==` compares it.
+    fn gen_hash(&mut self, reg: &str, ty: &str, llvm_ty: &str) -> String {
+        let result = self.new_temp();
+        if ty == "string" {
+            self.call(Some(&result), "i64", "@brn_hash_str", &[("i8*", reg)]);
+        } else if self.is_value_struct(ty) {
+            self.push_hash_struct(ty);
+            self.call(
+                Some(&result),
+                "i64",
+                &format!("@brn_hash.{}", ty),
+                &[(&format!("%{}*", ty), reg)],
+            );
+        } else if self.struct_types.contains_key(ty) {
+            self.push_hash_struct(ty);
+            let is_null = self.new_temp();
+            self.icmp(&is_null, "eq", &format!("%{}*", ty), reg, "null");
+            let null_label = self.new_label("hash_null");
+            let some_label = self.new_label("hash_some");
+            let join_label = self.new_label("hash_join");
+            self.cond_br(&is_null, &null_label, &some_label);
+            self.label(&null_label);
+            self.br(&join_label);
+            self.label(&some_label);
+            let deep = self.new_temp();
+            self.call(
+                Some(&deep),
+                "i64",
+                &format!("@brn_hash.{}", ty),
+                &[(&format!("%{}*", ty), reg)],
+            );
+            self.br(&join_label);
+            self.label(&join_label);
+            self.emit(&format!(
+                "  {} = phi i64 [ 0, %{} ], [ {}, %{} ]",
+                result, null_label, deep, some_label
+            ));
+        } else if self.enum_types.contains_key(ty) || llvm_ty == "{ i32, i64 }*" {
+            let tag_ptr = self.new_temp();
+            self.gep(&tag_ptr, "{ i32, i64 }", reg, &[("i32", "0"), ("i32", "0")]);
+            let tag = self.new_temp();
+            self.load_into(&tag, "i32", &tag_ptr);
+            let tag = self.widen(&tag, "i32", "zext");
+            let payload = self.load_payload(reg);
+            let tag_hash = self.gen_hash(&tag, "int", "i64");
+            let mixed = self.new_temp();
+            self.binary(&mixed, "xor", "i64", &tag_hash, &payload);
+            self.call(Some(&result), "i64", "@brn_hash_int", &[("i64", &mixed)]);
+        } else {
+            let n = match llvm_ty {
+                "i64" => reg.to_string(),
+                "i1" | "i8" => self.widen(reg, llvm_ty, "zext"),
+                _ => {
+                    let n = self.new_temp();
+                    self.cast(&n, "ptrtoint", llvm_ty, reg, "i64");
+                    n
+                }
+            };
+            self.call(Some(&result), "i64", "@brn_hash_int", &[("i64", &n)]);
+        }
+        result
+    }
+
+    fn push_hash_struct(&mut self, name: &str) {
+        if !self.hash_structs.iter().any(|s| s == name) {
+            self.hash_structs.push(name.to_string());
+        }
+    }
+
+    fn print_constant(&mut self, text: &str) {
+        let ptr = self.string_literal_ptr(text);
+        self.call(None, "void", "@brn_print_str", &[("i8*", &ptr)]);
+    }
+
+    /// A match whose arms look inside what they match: a variant's values,
+    /// `Option::Some(0)` or `Shape::Rect(w, h)`, or a struct's fields,
+    /// `Point { x: 0, .. }`. A `switch` on the tag alone can't tell those
+    /// arms apart, so each one is tried in turn, and a value that fails any
+    /// part of a pattern moves on to the next arm.
+    fn gen_destructuring_match(&mut self, scrutinee: Value, arms: &[MatchArm], end_label: &str) {
+        let ty = scrutinee
+            .brain_ty
+            .trim_start_matches("&mut ")
+            .trim_start_matches('&')
+            .to_string();
+        let word = if scrutinee.llvm_ty.ends_with('*') {
+            self.ptr_to_word(&scrutinee.llvm_ty, &scrutinee.reg)
+        } else {
+            self.coerce_i64(scrutinee)
+        };
+        for (i, arm) in arms.iter().enumerate() {
+            let next_label = if i < arms.len() - 1 {
+                self.new_label(&format!("match_check_{}", i + 1))
+            } else {
+                end_label.to_string()
+            };
+            self.gen_pattern(&arm.pattern, &ty, &word, &next_label);
+            self.gen_match_arm(&arm.body, end_label);
+            if i < arms.len() - 1 {
+                self.label(&next_label);
+            }
+        }
+    }
+
+    /// A match on a string: each arm's pattern is compared with `strcmp` in
+    /// turn, or with `str_starts_with_impl` / `str_ends_with_impl` for
+    /// `"prefix" ++ rest` and `rest ++ "suffix"`, and the first that
+    /// matches runs.
+    fn gen_string_match(&mut self, value_reg: &str, arms: &[MatchArm], end_label: &str) {
+        for (i, arm) in arms.iter().enumerate() {
+            let arm_label = self.new_label(&format!("match_arm_{}", i));
+            let next_label = if i < arms.len() - 1 {
+                self.new_label(&format!("match_check_{}", i + 1))
+            } else {
+                end_label.to_string()
+            };
+
+            match &arm.pattern {
+                Pattern::NumberPattern(n) => {
+                    let cond = self.new_temp();
+                    self.icmp(&cond, "eq", "i64", value_reg, &n.to_string());
+                    self.cond_br(&cond, &arm_label, &next_label);
+                    self.label(&arm_label);
+                    self.gen_match_arm(&arm.body, end_label);
+                }
+                Pattern::StringPattern(s) => {
+                    let str_ptr = self.string_literal_ptr(s);
+                    let cmp_result = self.new_temp();
+                    self.call(
+                        Some(&cmp_result),
+                        "i32",
+                        "@strcmp",
+                        &[("i8*", value_reg), ("i8*", &str_ptr)],
+                    );
+                    let cond = self.new_temp();
+                    self.icmp(&cond, "eq", "i32", &cmp_result, "0");
+                    self.cond_br(&cond, &arm_label, &next_label);
+                    self.label(&arm_label);
+                    self.gen_match_arm(&arm.body, end_label);
+                }
+                Pattern::PrefixPattern { prefix, rest } => {
+                    let prefix_ptr = self.string_literal_ptr(prefix);
+                    let cond = self.new_temp();
+                    self.call(
+                        Some(&cond),
+                        "i1",
+                        "@str_starts_with_impl",
+                        &[("i8*", value_reg), ("i8*", &prefix_ptr)],
+                    );
+                    self.cond_br(&cond, &arm_label, &next_label);
+                    self.label(&arm_label);
+                    if rest != "_" {
+                        // What follows the prefix, as its own string
+                        let from = self.new_temp();
+                        self.gep(
+                            &from,
+                            "i8",
+                            value_reg,
+                            &[("i64", &prefix.len().to_string())],
+                        );
+                        let len = self.new_temp();
+                        self.call(Some(&len), "i64", "@strlen", &[("i8*", &from)]);
+                        self.bind_match_string(rest, &from, &len);
+                    }
+                    self.gen_match_arm(&arm.body, end_label);
+                }
+                Pattern::SuffixPattern { rest, suffix } => {
+                    let suffix_ptr = self.string_literal_ptr(suffix);
+                    let cond = self.new_temp();
+                    self.call(
+                        Some(&cond),
+                        "i1",
+                        "@str_ends_with_impl",
+                        &[("i8*", value_reg), ("i8*", &suffix_ptr)],
+                    );
+                    self.cond_br(&cond, &arm_label, &next_label);
+                    self.label(&arm_label);
+                    if rest != "_" {
+                        // Everything before the suffix
+                        let total = self.new_temp();
+                        self.call(Some(&total), "i64", "@strlen", &[("i8*", value_reg)]);
+                        let len = self.new_temp();
+                        self.binary(&len, "sub", "i64", &total, &suffix.len().to_string());
+                        self.bind_match_string(rest, value_reg, &len);
+                    }
+                    self.gen_match_arm(&arm.body, end_label);
+                }
+                Pattern::Wildcard | Pattern::Identifier(_) => {
+                    self.br(&arm_label);
+                    self.label(&arm_label);
+                    self.gen_match_arm(&arm.body, end_label);
+                }
+                _ => {}
+            }
+
+            if i < arms.len() - 1 {
+                self.label(&next_label);
+            }
+        }
+    }
+
+    /// Bind `name` in a match arm to a copy of the `len` bytes at `from`.
+    fn bind_match_string(&mut self, name: &str, from: &str, len: &str) {
+        let copy = self.new_temp();
+        self.call(
+            Some(&copy),
+            "i8*",
+            "@brn_substr_copy",
+            &[("i8*", from), ("i64", len)],
+        );
+        let var_ptr = self.new_temp();
+        self.alloca_into(&var_ptr, "i8*");
+        self.store("i8*", &copy, &var_ptr);
+        self.current_function_vars.insert(
+            name.to_string(),
+            VarMetadata {
+                llvm_name: var_ptr,
+                var_type: "string".to_string(),
+                is_heap: false,
+                array_size: None,
+            },
+        );
+    }
+
+    /// A match arm's body. In a function returning a value the arm's value
+    /// is returned; otherwise control continues after the match.
+    fn gen_match_arm(&mut self, body: &AstNode, end_label: &str) {
+        let arm_val = self.gen_node(body).reg;
+        if !self.block_terminated() {
+            if self.current_function_return_type != "void" {
+                let arm_val = self.returned_string(body, arm_val);
+                let ty = self.current_function_return_type.clone();
+                self.ret(&ty, &arm_val);
+            } else {
+                self.br(end_label);
+            }
+        }
+    }
+
+    fn mangle_fn(&self, name: &str) -> String {
+        if name == "main" || self.no_mangle.contains(name) {
+            name.to_string()
+        } else {
+            format!("brn_{}", name)
+        }
+    }
+
+    fn gen_function(
+        &mut self,
+        name: &str,
+        params: &[Parameter],
+        body: &AstNode,
+        return_type: &Option<String>,
+        is_unsafe: bool,
+    ) -> String {
+        self.current_function_vars.clear();
+        self.current_function_vars
+            .extend(self.globals.iter().cloned());
+        self.temp_counter = 0;
+        self.label_counter = 0;
+        self.is_unsafe_fn = is_unsafe;
+        self.guard_vars.clear();
+        self.moved_vars.clear();
+
+        let escaping = EscapeAnalysis::analyze(params, body);
+        self.non_escaping.clear();
+        if let AstNode::Block(stmts, _) = body {
+            for stmt in stmts {
+                if let AstNode::LetBinding { name, .. } = stmt
+                    && !escaping.contains(name)
+                {
+                    self.non_escaping.insert(name.clone());
+                }
+            }
+        }
+
+        let ret_type = if name == "main" {
+            "i32".to_string()
+        } else if let Some(rt) = return_type {
+            // A returned `&T` is what `T` is; see `borrowed_returns`
+            self.value_type_to_llvm(Self::strip_ref_prefix(rt).2)
+        } else {
+            "void".to_string()
+        };
+
+        self.function_signatures
+            .insert(name.to_string(), ret_type.clone());
+        self.current_function_name = name.to_string();
+        self.current_function_return_type = ret_type.clone();
+
+        let param_list = if params.is_empty() {
+            String::new()
+        } else {
+            params
+                .iter()
+                .map(|p| {
+                    let (type_is_ref, type_is_mut, inner_type) =
+                        Self::strip_ref_prefix(&p.param_type);
+                    let type_is_ref = type_is_ref || p.is_reference;
+                    let type_is_mut = type_is_mut || p.is_mutable;
+
+                    let param_type_str = if type_is_ref {
+                        if inner_type.starts_with('[') {
+                            if let Some(size_str) = inner_type.split(';').nth(1) {
+                                let size = size_str
+                                    .trim()
+                                    .trim_end_matches(']')
+                                    .trim()
+                                    .parse::<usize>()
+                                    .unwrap_or(100);
+                                format!("[{} x i64]*", size)
+                            } else {
+                                "i64*".to_string()
+                            }
+                        } else if inner_type.starts_with("Mutex<") {
+                            "i8*".to_string()
+                        } else {
+                            let base = self.type_to_llvm(inner_type);
+                            if base.ends_with('*') {
+                                base
+                            } else {
+                                format!("{}*", base)
+                            }
+                        }
+                    } else {
+                        self.value_type_to_llvm(&p.param_type)
+                    };
+
+                    let is_mutex_param = inner_type.starts_with("Mutex<");
+                    let is_simple_ptr = type_is_ref && !inner_type.starts_with('[');
+                    let is_owned_ptr = !type_is_ref
+                        && Self::is_pointer_llvm_type(&p.param_type)
+                        && !type_is_mut
+                        && !self.is_value_struct(&p.param_type);
+
+                    let attrs = if is_mutex_param {
+                        ""
+                    } else if is_simple_ptr {
+                        if !type_is_mut {
+                            "noalias readonly"
+                        } else {
+                            "noalias"
+                        }
+                    } else if is_owned_ptr {
                         "noalias readonly"
                     } else {
                         ""
                     };
 
-                    if attrs.is_empty() {
-                        format!("{} %arg_{}", param_type_str, p.name)
-                    } else {
-                        format!("{} {} %arg_{}", param_type_str, attrs, p.name)
-                    }
+                    if attrs.is_empty() {
+                        format!("{} %arg_{}", param_type_str, p.name)
+                    } else {
+                        format!("{} {} %arg_{}", param_type_str, attrs, p.name)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        // `fn main(args: Vec<string>)` becomes an ordinary function behind a
+        // generated C `main(argc, argv)` that builds the Vec.
+        let takes_args = name == "main" && !params.is_empty();
+        let mangled = if takes_args {
+            "brn.user_main".to_string()
+        } else {
+            self.mangle_fn(name)
+        };
+
+        // Profiling writes global counters, so no function may claim purity.
+        let fn_attrs = if name == "main" || self.options.profile || self.tracks_allocs() {
+            " nounwind"
+        } else if self.terminating_functions.contains(name) {
+            " nounwind readonly willreturn"
+        } else if self.pure_functions.contains(name) {
+            " nounwind readonly"
+        } else {
+            " nounwind"
+        };
+        let inline_hint = self.inline_hints.get(name).copied().unwrap_or("");
+
+        let fn_start = self.output.len();
+        self.begin_function(format!(
+            "define {} @{}({}){}{} {{",
+            ret_type, mangled, param_list, fn_attrs, inline_hint
+        ));
+
+        for param in params {
+            let (type_is_ref, _type_is_mut, inner_type) = Self::strip_ref_prefix(&param.param_type);
+            let type_is_ref = type_is_ref || param.is_reference;
+
+            if type_is_ref {
+                let param_type_name = inner_type.to_string();
+
+                let array_size = if inner_type.starts_with('[') {
+                    if let Some(size_str) = inner_type.split(';').nth(1) {
+                        size_str
+                            .trim()
+                            .trim_end_matches(']')
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                };
+
+                self.current_function_vars.insert(
+                    param.name.clone(),
+                    VarMetadata {
+                        llvm_name: format!("%arg_{}", param.name),
+                        var_type: param_type_name,
+                        is_heap: false,
+                        array_size,
+                    },
+                );
+            } else if self.struct_types.contains_key(&param.param_type)
+                && !self.is_value_struct(&param.param_type)
+            {
+                // Already the %StructName* the body works with
+                self.current_function_vars.insert(
+                    param.name.clone(),
+                    VarMetadata {
+                        llvm_name: format!("%arg_{}", param.name),
+                        var_type: param.param_type.clone(),
+                        is_heap: false,
+                        array_size: None,
+                    },
+                );
+            } else {
+                // A value struct param is the aggregate itself; its slot is
+                // then used like any stack struct.
+                let param_type_str = self.value_type_to_llvm(&param.param_type);
+                let param_type_name = param.param_type.clone();
+
+                let ptr = self.new_temp();
+                self.alloca_into(&ptr, &param_type_str);
+                self.store(&param_type_str, &format!("%arg_{}", param.name), &ptr);
+
+                // A by-value string parameter owns its argument, except
+                // when C calls: the caller keeps its `char *`
+                let is_heap = param_type_name == "string" && !self.no_mangle.contains(name);
+                self.current_function_vars.insert(
+                    param.name.clone(),
+                    VarMetadata {
+                        llvm_name: ptr,
+                        var_type: param_type_name,
+                        is_heap,
+                        array_size: None,
+                    },
+                );
+            }
+        }
+
+        if name == "main" {
+            if !self.globals.is_empty() {
+                self.call(None, "void", "@brn.init_globals", &[]);
+            }
+            if self.main_inits_runtime() {
+                self.call(None, "void", "@brn_runtime_init", &[]);
+            }
+            if self.has_module_init {
+                self.call(None, "void", "@brn.init_modules", &[]);
+            }
+            self.emit_hook_calls(&self.startup_hooks.clone());
+        }
+        self.emit_profile_entry();
+
+        self.gen_node(body);
+
+        if name == "main" && !self.block_terminated() {
+            self.emit_function_exit();
+            self.ret("i32", "0");
+        } else if ret_type == "void" && !self.block_terminated() {
+            self.emit_function_exit();
+            self.push(Inst::Ret(None));
+        } else if !self.block_terminated() {
+            self.push(Inst::Unreachable);
+        }
+
+        self.end_function();
+        if self.tracks_allocs() {
+            self.mark_alloc_sites(fn_start);
+        }
+
+        if takes_args {
+            self.emit("\ndefine i32 @main(i32 %argc, i8** %argv) {");
+            self.label("entry");
+            self.call(
+                Some("%args"),
+                "i8*",
+                "@brn_args_impl",
+                &[("i32", "%argc"), ("i8**", "%argv")],
+            );
+            self.call(Some("%code"), "i32", "@brn.user_main", &[("i8*", "%args")]);
+            self.ret("i32", "%code");
+            self.emit("}");
+        }
+        String::new()
+    }
+
+    /// Top-level `let` bindings become internal globals, set in `ordered`
+    /// (initialization) order by `@brn.init_globals`, which `main` (or, in a
+    /// library, `emit_library_hooks`) calls before anything else. Functions then use them like variables whose
+    /// slot is the global; they are never freed.
+    fn gen_global_init(&mut self, ordered: &[&AstNode]) {
+        self.current_function_vars.clear();
+        self.temp_counter = 0;
+        self.label_counter = 0;
+        self.is_unsafe_fn = false;
+        self.guard_vars.clear();
+        self.moved_vars.clear();
+        self.non_escaping.clear();
+        self.current_function_name = "brn.init_globals".to_string();
+        self.current_function_return_type = "void".to_string();
+
+        self.begin_function("define internal void @brn.init_globals() nounwind {".to_string());
+        let mut decls = Vec::new();
+        for node in ordered {
+            let AstNode::LetBinding {
+                name,
+                value,
+                type_annotation,
+                ..
+            } = node
+            else {
+                continue;
+            };
+            // A constant array needs no code: the global starts out holding it
+            if let AstNode::ArrayLit(elements, _) = value.as_ref()
+                && let Some(values) = Self::constant_array(elements)
+                && !values.is_empty()
+            {
+                let global = format!("@brn.g.{}", name);
+                decls.push(format!(
+                    "{} = internal global [{} x i64] [{}]",
+                    global,
+                    values.len(),
+                    Self::array_initializer(&values)
+                ));
+                let meta = VarMetadata {
+                    llvm_name: global,
+                    var_type: format!("[{}; int]", values.len()),
+                    is_heap: false,
+                    array_size: Some(values.len()),
+                };
+                self.current_function_vars
+                    .insert(name.clone(), meta.clone());
+                self.globals.push((name.clone(), meta));
+                continue;
+            }
+            let mut value_reg = self.gen_node(value).reg;
+            let var_type = match type_annotation {
+                Some(t) if t.starts_with("Vec<") && self.infer_type(value) == "Vec" => t.clone(),
+                Some(t) if t.starts_with('*') => {
+                    value_reg = self.store_through_pointer(value, value_reg);
+                    t.clone()
+                }
+                _ => self.infer_type(value),
+            };
+            let global = format!("@brn.g.{}", name);
+            let mut meta = VarMetadata {
+                llvm_name: global.clone(),
+                var_type: var_type.clone(),
+                is_heap: false,
+                array_size: None,
+            };
+
+            if let AstNode::ArrayLit(elements, _) = value.as_ref() {
+                let array_type = format!("[{} x i64]", elements.len());
+                decls.push(format!(
+                    "{} = internal global {} zeroinitializer",
+                    global, array_type
+                ));
+                let whole = self.new_temp();
+                self.load_into(&whole, &array_type, &value_reg);
+                self.store(&array_type, &whole, &global);
+                meta.var_type = format!("[{}; int]", elements.len());
+                meta.array_size = Some(elements.len());
+            } else if self.is_value_struct(&var_type) {
+                decls.push(format!(
+                    "{} = internal global %{} zeroinitializer",
+                    global, var_type
+                ));
+                self.gen_store_field(&var_type, &value_reg, &global);
+            } else {
+                let llvm_type = self.type_to_llvm(&var_type);
+                decls.push(format!(
+                    "{} = internal global {} zeroinitializer",
+                    global, llvm_type
+                ));
+                self.store(&llvm_type, &value_reg, &global);
+                // Heap values sit in the global the way a local's sit in
+                // its stack slot
+                meta.is_heap = self.struct_types.contains_key(&var_type)
+                    || (var_type == "string"
+                        && self.string_ownership(value) == StringOwnership::Owned)
+                    || Self::is_vec_type(&var_type)
+                    || var_type == "StringBuilder"
+                    || var_type == "Bytes";
+            }
+            self.current_function_vars
+                .insert(name.clone(), meta.clone());
+            self.globals.push((name.clone(), meta));
+        }
+        self.push(Inst::Ret(None));
+        self.end_function();
+        for decl in decls {
+            self.emit(&decl);
+        }
+    }
+
+    /// Statements at the top level of the program and its modules, run
+    /// by `@brn.init_modules` in the order the flattened program has them,
+    /// so a module's run after those of the modules it imports. Globals
+    /// are all set by then.
+    fn gen_module_init(&mut self, statements: &[&AstNode]) {
+        self.current_function_vars.clear();
+        self.current_function_vars
+            .extend(self.globals.iter().cloned());
+        self.temp_counter = 0;
+        self.label_counter = 0;
+        self.is_unsafe_fn = false;
+        self.guard_vars.clear();
+        self.moved_vars.clear();
+        self.non_escaping.clear();
+        self.current_function_name = "brn.init_modules".to_string();
+        self.current_function_return_type = "void".to_string();
+
+        self.begin_function("define internal void @brn.init_modules() nounwind {".to_string());
+        for statement in statements {
+            self.gen_node(statement);
+        }
+        if !self.block_terminated() {
+            self.push(Inst::Ret(None));
+        }
+        self.end_function();
+        self.has_module_init = true;
+    }
+
+    /// Without a `main` to do it, a library has the loader set up its
+    /// globals and run its `#[startup]` functions before the host program's
+    /// `main`, and its `#[shutdown]` ones at exit.
+    fn emit_library_hooks(&mut self) {
+        let mut startup = self.startup_hooks.clone();
+        if self.has_module_init {
+            startup.insert(0, "brn.init_modules".to_string());
+        }
+        if !self.globals.is_empty() {
+            startup.insert(0, "brn.init_globals".to_string());
+        }
+        for (list, hooks, function) in [
+            ("llvm.global_ctors", startup, "brn.lib_startup"),
+            (
+                "llvm.global_dtors",
+                self.shutdown_hooks.clone(),
+                "brn.lib_shutdown",
+            ),
+        ] {
+            if hooks.is_empty() {
+                continue;
+            }
+            self.emit("");
+            self.emit(&format!("define internal void @{}() nounwind {{", function));
+            for hook in &hooks {
+                let mangled = if hook.starts_with("brn.") {
+                    hook.clone()
+                } else {
+                    self.mangle_fn(hook)
+                };
+                self.emit(&format!("  call void @{}()", mangled));
+            }
+            self.push(Inst::Ret(None));
+            self.emit("}");
+            self.emit(&format!(
+                "@{} = appending global [1 x {{ i32, void ()*, i8* }}] [{{ i32, void ()*, i8* }} {{ i32 65535, void ()* @{}, i8* null }}]",
+                list, function
+            ));
+        }
+    }
+
+    /// `--profile`: take the entry timestamp and bump the call counter.
+    fn emit_profile_entry(&mut self) {
+        let Some(&id) = self.profile_ids.get(&self.current_function_name) else {
+            return;
+        };
+        let n = self.profile_ids.len();
+        self.call(Some("%prof_t0"), "i64", "@brn_prof_now", &[]);
+        self.gep(
+            "%prof_cp",
+            &format!("[{} x i64]", n),
+            "@brn_prof_calls",
+            &[("i64", "0"), ("i64", &id.to_string())],
+        );
+        self.load_into("%prof_c", "i64", "%prof_cp");
+        self.binary("%prof_c1", "add", "i64", "%prof_c", "1");
+        self.store("i64", "%prof_c1", "%prof_cp");
+        self.gep(
+            "%prof_dp",
+            &format!("[{} x i64]", n),
+            "@brn_prof_depth",
+            &[("i64", "0"), ("i64", &id.to_string())],
+        );
+        self.load_into("%prof_d", "i64", "%prof_dp");
+        self.binary("%prof_d1", "add", "i64", "%prof_d", "1");
+        self.store("i64", "%prof_d1", "%prof_dp");
+    }
+
+    /// Everything that happens before a `ret`: leaving `main` runs the
+    /// `#[shutdown]` functions, then the profilers have their say.
+    fn emit_function_exit(&mut self) {
+        if self.current_function_name == "main" {
+            self.emit_hook_calls(&self.shutdown_hooks.clone());
+        }
+        self.emit_profile_exit();
+    }
+
+    fn emit_hook_calls(&mut self, hooks: &[String]) {
+        for hook in hooks {
+            let mangled = self.mangle_fn(hook);
+            self.call(None, "void", &format!("@{}", mangled), &[]);
+        }
+    }
+
+    /// `--profile`: add this call's elapsed time before a `ret`. Only the
+    /// outermost active call of a function is counted, so recursion does
+    /// not inflate its total. Leaving `main` also prints the report, and
+    /// the `--profile-alloc` and `--detect-leaks` ones.
+    fn emit_profile_exit(&mut self) {
+        if self.options.profile_alloc && self.current_function_name == "main" {
+            self.call(None, "void", "@brn_alloc_report", &[]);
+        }
+        if self.options.detect_leaks && self.current_function_name == "main" {
+            self.call(None, "void", "@brn_leak_report", &[]);
+        }
+        if !self.profile_ids.contains_key(&self.current_function_name) {
+            return;
+        }
+        let n = self.profile_ids.len();
+        let depth = self.new_temp();
+        self.load_into(&depth, "i64", "%prof_dp");
+        let depth_after = self.new_temp();
+        self.binary(&depth_after, "sub", "i64", &depth, "1");
+        self.store("i64", &depth_after, "%prof_dp");
+        let outermost = self.new_temp();
+        self.icmp(&outermost, "eq", "i64", &depth_after, "0");
+        let now = self.new_temp();
+        self.call(Some(&now), "i64", "@brn_prof_now", &[]);
+        let span = self.new_temp();
+        self.binary(&span, "sub", "i64", &now, "%prof_t0");
+        let elapsed = self.new_temp();
+        self.emit(&format!(
+            "  {} = select i1 {}, i64 {}, i64 0",
+            elapsed, outermost, span
+        ));
+        let slot = self.new_temp();
+        let id = self.profile_ids[&self.current_function_name];
+        self.gep(
+            &slot,
+            &format!("[{} x i64]", n),
+            "@brn_prof_time",
+            &[("i64", "0"), ("i64", &id.to_string())],
+        );
+        let old = self.new_temp();
+        self.load_into(&old, "i64", &slot);
+        let total = self.new_temp();
+        self.binary(&total, "add", "i64", &old, &elapsed);
+        self.store("i64", &total, &slot);
+        if self.current_function_name == "main" {
+            self.call(None, "void", "@brn_prof_report", &[]);
+        }
+    }
+
+    /// Counter tables, clock and the exit-time report for `--profile`.
+    /// The report goes to stderr as `calls<TAB>total_us<TAB>function` rows
+    /// (inclusive time), skipping functions that were never called.
+    fn emit_profile_runtime(&mut self) {
+        let mut names: Vec<(usize, String)> = self
+            .profile_ids
+            .iter()
+            .map(|(name, id)| (*id, name.clone()))
+            .collect();
+        names.sort();
+        let n = names.len();
+
+        self.emit("");
+        self.emit(&format!(
+            "@brn_prof_calls = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit(&format!(
+            "@brn_prof_time = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit(&format!(
+            "@brn_prof_depth = global [{} x i64] zeroinitializer",
+            n
+        ));
+        let mut entries = Vec::new();
+        for (_, name) in &names {
+            let id = self.new_string_literal(name);
+            let len = name.len() + 1;
+            entries.push(format!(
+                "i8* getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0)",
+                len, len, id
+            ));
+        }
+        self.emit(&format!(
+            "@brn_prof_names = private constant [{} x i8*] [{}]",
+            n,
+            entries.join(", ")
+        ));
+        self.emit("");
+
+        if cfg!(target_os = "windows") {
+            self.emit("declare i32 @QueryPerformanceCounter(i64*)");
+            self.emit("declare i32 @QueryPerformanceFrequency(i64*)");
+            self.emit("");
+            self.emit("define i64 @brn_prof_now() {");
+            self.emit("  %pn_c = alloca i64");
+            self.emit("  %pn_f = alloca i64");
+            self.emit("  call i32 @QueryPerformanceCounter(i64* %pn_c)");
+            self.emit("  call i32 @QueryPerformanceFrequency(i64* %pn_f)");
+            self.emit("  %pn_cv = load i64, i64* %pn_c");
+            self.emit("  %pn_fv = load i64, i64* %pn_f");
+            // split into seconds + remainder so the ns conversion cannot overflow
+            self.emit("  %pn_sec = udiv i64 %pn_cv, %pn_fv");
+            self.emit("  %pn_rem = urem i64 %pn_cv, %pn_fv");
+            self.emit("  %pn_sec_ns = mul i64 %pn_sec, 1000000000");
+            self.emit("  %pn_rem_scaled = mul i64 %pn_rem, 1000000000");
+            self.emit("  %pn_rem_ns = udiv i64 %pn_rem_scaled, %pn_fv");
+            self.emit("  %pn_ns = add i64 %pn_sec_ns, %pn_rem_ns");
+            self.emit("  ret i64 %pn_ns");
+            self.emit("}");
+        } else {
+            // SYS_clock_gettime(CLOCK_MONOTONIC, &ts) — syscall 228
+            self.emit("define i64 @brn_prof_now() {");
+            self.emit("  %pn_ts = alloca [2 x i64]");
+            self.emit("  %pn_tsp = bitcast [2 x i64]* %pn_ts to i8*");
+            self.emit("  call i64 (i64, ...) @syscall(i64 228, i64 1, i8* %pn_tsp)");
+            self.emit("  %pn_sp = getelementptr [2 x i64], [2 x i64]* %pn_ts, i64 0, i64 0");
+            self.emit("  %pn_np = getelementptr [2 x i64], [2 x i64]* %pn_ts, i64 0, i64 1");
+            self.emit("  %pn_sec = load i64, i64* %pn_sp");
+            self.emit("  %pn_nsec = load i64, i64* %pn_np");
+            self.emit("  %pn_sec_ns = mul i64 %pn_sec, 1000000000");
+            self.emit("  %pn_ns = add i64 %pn_sec_ns, %pn_nsec");
+            self.emit("  ret i64 %pn_ns");
+            self.emit("}");
+        }
+        self.emit("");
+
+        let header = "--- profile ---\ncalls\ttotal_us\tfunction\n";
+        let header_id = self.new_string_literal(header);
+        let tab_id = self.new_string_literal("\t");
+        let nl_id = self.new_string_literal("\n");
+        let header_len = header.len() + 1;
+
+        self.emit("define void @brn_prof_report() {");
+        self.emit("pr_entry:");
+        self.emit(&format!(
+            "  %pr_hdr = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
+            header_len, header_len, header_id
+        ));
+        self.emit(&format!(
+            "  call void @brn_write_stderr(i8* %pr_hdr, i64 {})",
+            header.len()
+        ));
+        self.emit(&format!(
+            "  %pr_tab = getelementptr inbounds [2 x i8], [2 x i8]* @{}, i64 0, i64 0",
+            tab_id
+        ));
+        self.emit(&format!(
+            "  %pr_nl = getelementptr inbounds [2 x i8], [2 x i8]* @{}, i64 0, i64 0",
+            nl_id
+        ));
+        self.emit("  %pr_buf = alloca [32 x i8]");
+        self.emit("  %pr_bufp = getelementptr [32 x i8], [32 x i8]* %pr_buf, i64 0, i64 0");
+        self.emit("  br label %pr_loop");
+        self.emit("pr_loop:");
+        self.emit("  %pr_i = phi i64 [ 0, %pr_entry ], [ %pr_next, %pr_step ]");
+        self.emit(&format!("  %pr_done = icmp eq i64 %pr_i, {}", n));
+        self.emit("  br i1 %pr_done, label %pr_exit, label %pr_row");
+        self.emit("pr_row:");
+        self.emit(&format!(
+            "  %pr_cp = getelementptr [{} x i64], [{} x i64]* @brn_prof_calls, i64 0, i64 %pr_i",
+            n, n
+        ));
+        self.emit("  %pr_calls = load i64, i64* %pr_cp");
+        self.emit("  %pr_unused = icmp eq i64 %pr_calls, 0");
+        self.emit("  br i1 %pr_unused, label %pr_step, label %pr_print");
+        self.emit("pr_print:");
+        self.emit("  %pr_cs = call i8* @int_to_string_stack(i64 %pr_calls, i8* %pr_bufp)");
+        self.emit("  %pr_csl = call i64 @strlen(i8* %pr_cs)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_cs, i64 %pr_csl)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_tab, i64 1)");
+        self.emit(&format!(
+            "  %pr_tp = getelementptr [{} x i64], [{} x i64]* @brn_prof_time, i64 0, i64 %pr_i",
+            n, n
+        ));
+        self.emit("  %pr_ns = load i64, i64* %pr_tp");
+        self.emit("  %pr_us = sdiv i64 %pr_ns, 1000");
+        self.emit("  %pr_ts = call i8* @int_to_string_stack(i64 %pr_us, i8* %pr_bufp)");
+        self.emit("  %pr_tsl = call i64 @strlen(i8* %pr_ts)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_ts, i64 %pr_tsl)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_tab, i64 1)");
+        self.emit(&format!(
+            "  %pr_np = getelementptr [{} x i8*], [{} x i8*]* @brn_prof_names, i64 0, i64 %pr_i",
+            n, n
+        ));
+        self.emit("  %pr_name = load i8*, i8** %pr_np");
+        self.emit("  %pr_nl_len = call i64 @strlen(i8* %pr_name)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_name, i64 %pr_nl_len)");
+        self.emit("  call void @brn_write_stderr(i8* %pr_nl, i64 1)");
+        self.emit("  br label %pr_step");
+        self.emit("pr_step:");
+        self.emit("  %pr_next = add i64 %pr_i, 1");
+        self.emit("  br label %pr_loop");
+        self.emit("pr_exit:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+    }
+
+    /// `read_input()` reads standard input 4 KiB at a time into a buffer it
+    /// keeps between calls, and returns one line of it, without its "\n" or
+    /// "\r\n", as a new string; "" once the input has run out. Lines piped
+    /// in together are returned one call at a time.
+    fn emit_stdin_reader(&mut self) {
+        const STDIN_BUFFER: u64 = 4096;
+        self.emit(&format!(
+            "@brn_stdin_buf = internal global [{} x i8] zeroinitializer",
+            STDIN_BUFFER
+        ));
+        self.emit("@brn_stdin_pos = internal global i64 0");
+        self.emit("@brn_stdin_end = internal global i64 0");
+        self.emit("");
+
+        // Unread bytes in the buffer, refilling it when they have run out;
+        // false at the end of the input or on an error
+        self.emit("define i1 @brn_stdin_fill() {");
+        self.emit("sf_entry:");
+        self.emit("  %sf_pos = load i64, i64* @brn_stdin_pos");
+        self.emit("  %sf_end = load i64, i64* @brn_stdin_end");
+        self.emit("  %sf_left = icmp slt i64 %sf_pos, %sf_end");
+        self.emit("  br i1 %sf_left, label %sf_yes, label %sf_read");
+        self.emit("sf_read:");
+        self.emit(&format!(
+            "  %sf_buf = getelementptr [{} x i8], [{} x i8]* @brn_stdin_buf, i64 0, i64 0",
+            STDIN_BUFFER, STDIN_BUFFER
+        ));
+        if cfg!(target_os = "windows") {
+            self.emit("  %sf_stdin = call i8* @GetStdHandle(i32 -10)");
+            self.emit("  %sf_read_n = alloca i32");
+            self.emit("  store i32 0, i32* %sf_read_n");
+            self.emit(&format!(
+                "  call i32 @ReadFile(i8* %sf_stdin, i8* %sf_buf, i32 {}, i32* %sf_read_n, i8* null)",
+                STDIN_BUFFER
+            ));
+            self.emit("  %sf_n32 = load i32, i32* %sf_read_n");
+            self.emit("  %sf_got = sext i32 %sf_n32 to i64");
+        } else {
+            // SYS_read(0, buf, size)
+            self.emit(&format!(
+                "  %sf_got = call i64 (i64, ...) @syscall(i64 0, i64 0, i8* %sf_buf, i64 {})",
+                STDIN_BUFFER
+            ));
+        }
+        self.emit("  %sf_eof = icmp sle i64 %sf_got, 0");
+        self.emit("  br i1 %sf_eof, label %sf_no, label %sf_filled");
+        self.emit("sf_filled:");
+        self.emit("  store i64 0, i64* @brn_stdin_pos");
+        self.emit("  store i64 %sf_got, i64* @brn_stdin_end");
+        self.emit("  br label %sf_yes");
+        self.emit("sf_yes:");
+        self.emit("  ret i1 1");
+        self.emit("sf_no:");
+        self.emit("  ret i1 0");
+        self.emit("}");
+        self.emit("");
+
+        // A line can span several reads, so it is collected in its own
+        // buffer, doubled as needed
+        self.emit("define i8* @read_input_impl() {");
+        self.emit("ri_entry:");
+        self.emit("  %ri_line0 = call i8* @malloc(i64 128)");
+        self.emit("  br label %ri_fill");
+        self.emit("ri_fill:");
+        self.emit("  %ri_line = phi i8* [ %ri_line0, %ri_entry ], [ %ri_line2, %ri_more ]");
+        self.emit("  %ri_len = phi i64 [ 0, %ri_entry ], [ %ri_len2, %ri_more ]");
+        self.emit("  %ri_cap = phi i64 [ 128, %ri_entry ], [ %ri_cap2, %ri_more ]");
+        self.emit("  %ri_any = call i1 @brn_stdin_fill()");
+        self.emit("  br i1 %ri_any, label %ri_scan_start, label %ri_finish");
+        self.emit("ri_scan_start:");
+        self.emit(&format!(
+            "  %ri_buf = getelementptr [{} x i8], [{} x i8]* @brn_stdin_buf, i64 0, i64 0",
+            STDIN_BUFFER, STDIN_BUFFER
+        ));
+        self.emit("  %ri_pos = load i64, i64* @brn_stdin_pos");
+        self.emit("  %ri_end = load i64, i64* @brn_stdin_end");
+        self.emit("  br label %ri_scan");
+        // Find the next "\n" in the buffer, or its end
+        self.emit("ri_scan:");
+        self.emit("  %ri_i = phi i64 [ %ri_pos, %ri_scan_start ], [ %ri_i1, %ri_next ]");
+        self.emit("  %ri_at_end = icmp sge i64 %ri_i, %ri_end");
+        self.emit("  br i1 %ri_at_end, label %ri_copy, label %ri_look");
+        self.emit("ri_look:");
+        self.emit("  %ri_cp = getelementptr i8, i8* %ri_buf, i64 %ri_i");
+        self.emit("  %ri_c = load i8, i8* %ri_cp");
+        self.emit("  %ri_is_nl = icmp eq i8 %ri_c, 10");
+        self.emit("  br i1 %ri_is_nl, label %ri_copy, label %ri_next");
+        self.emit("ri_next:");
+        self.emit("  %ri_i1 = add i64 %ri_i, 1");
+        self.emit("  br label %ri_scan");
+        // Append buf[pos..i] to the line, growing it to fit the terminator
+        self.emit("ri_copy:");
+        self.emit("  %ri_chunk = sub i64 %ri_i, %ri_pos");
+        self.emit("  %ri_want0 = add i64 %ri_len, %ri_chunk");
+        self.emit("  %ri_want = add i64 %ri_want0, 1");
+        self.emit("  %ri_fits = icmp sle i64 %ri_want, %ri_cap");
+        self.emit("  br i1 %ri_fits, label %ri_append, label %ri_grow");
+        self.emit("ri_grow:");
+        self.emit("  %ri_dbl = mul i64 %ri_cap, 2");
+        self.emit("  %ri_small = icmp slt i64 %ri_dbl, %ri_want");
+        self.emit("  %ri_ncap = select i1 %ri_small, i64 %ri_want, i64 %ri_dbl");
+        self.emit("  %ri_nline = call i8* @realloc(i8* %ri_line, i64 %ri_ncap)");
+        self.emit("  br label %ri_append");
+        self.emit("ri_append:");
+        self.emit("  %ri_line2 = phi i8* [ %ri_line, %ri_copy ], [ %ri_nline, %ri_grow ]");
+        self.emit("  %ri_cap2 = phi i64 [ %ri_cap, %ri_copy ], [ %ri_ncap, %ri_grow ]");
+        self.emit("  %ri_dst = getelementptr i8, i8* %ri_line2, i64 %ri_len");
+        self.emit("  %ri_src = getelementptr i8, i8* %ri_buf, i64 %ri_pos");
+        self.emit("  call void @brn_memcpy(i8* %ri_dst, i8* %ri_src, i64 %ri_chunk)");
+        self.emit("  %ri_len2 = add i64 %ri_len, %ri_chunk");
+        self.emit("  br i1 %ri_at_end, label %ri_more, label %ri_found");
+        // The line goes on in the next read
+        self.emit("ri_more:");
+        self.emit("  store i64 %ri_end, i64* @brn_stdin_pos");
+        self.emit("  br label %ri_fill");
+        self.emit("ri_found:");
+        self.emit("  %ri_after = add i64 %ri_i, 1");
+        self.emit("  store i64 %ri_after, i64* @brn_stdin_pos");
+        self.emit("  br label %ri_finish");
+        self.emit("ri_finish:");
+        self.emit("  %ri_fline = phi i8* [ %ri_line, %ri_fill ], [ %ri_line2, %ri_found ]");
+        self.emit("  %ri_flen = phi i64 [ %ri_len, %ri_fill ], [ %ri_len2, %ri_found ]");
+        // Drop the "\r" of a "\r\n"
+        self.emit("  %ri_has = icmp sgt i64 %ri_flen, 0");
+        self.emit("  br i1 %ri_has, label %ri_check_cr, label %ri_done");
+        self.emit("ri_check_cr:");
+        self.emit("  %ri_last = sub i64 %ri_flen, 1");
+        self.emit("  %ri_lp = getelementptr i8, i8* %ri_fline, i64 %ri_last");
+        self.emit("  %ri_lc = load i8, i8* %ri_lp");
+        self.emit("  %ri_is_cr = icmp eq i8 %ri_lc, 13");
+        self.emit("  %ri_cut = select i1 %ri_is_cr, i64 %ri_last, i64 %ri_flen");
+        self.emit("  br label %ri_done");
+        self.emit("ri_done:");
+        self.emit("  %ri_n = phi i64 [ 0, %ri_finish ], [ %ri_cut, %ri_check_cr ]");
+        self.emit("  %ri_term = getelementptr i8, i8* %ri_fline, i64 %ri_n");
+        self.emit("  store i8 0, i8* %ri_term");
+        self.emit("  ret i8* %ri_fline");
+        self.emit("}");
+        self.emit("");
+    }
+
+    /// `open_lines(path)` gives a `LineReader`, `{ file, buffer, pos, end }`
+    /// on the heap, that reads the file 64 KiB at a time, so a file of any size is read in constant memory apart from
+    /// the line being returned. The file is closed as soon as it runs out,
+    /// or when the reader goes out of scope (`brn_lines_free`).
+    fn emit_line_reader(&mut self) {
+        const READER: &str = "{ i8*, i8*, i64, i64 }";
+        const LINE_BUFFER: u64 = 64 * 1024;
+        let field = |this: &mut Self, dest: &str, reader: &str, index: usize| {
+            this.emit(&format!(
+                "  {} = getelementptr {}, {}* {}, i32 0, i32 {}",
+                dest, READER, READER, reader, index
+            ));
+        };
+
+        // A file that can't be opened reads as having no lines
+        self.emit("define i8* @open_lines_impl(i8* %path) {");
+        self.emit(
+            "  %ol_mode = getelementptr inbounds [2 x i8], [2 x i8]* @.str.mode.r, i64 0, i64 0",
+        );
+        self.emit("  %ol_file = call i8* @fopen(i8* %path, i8* %ol_mode)");
+        self.emit("  %ol_raw = call i8* @malloc(i64 32)");
+        self.emit(&format!("  %ol_r = bitcast i8* %ol_raw to {}*", READER));
+        self.emit(&format!(
+            "  %ol_buf = call i8* @malloc(i64 {})",
+            LINE_BUFFER
+        ));
+        field(self, "%ol_fp", "%ol_r", 0);
+        self.emit("  store i8* %ol_file, i8** %ol_fp");
+        field(self, "%ol_bp", "%ol_r", 1);
+        self.emit("  store i8* %ol_buf, i8** %ol_bp");
+        field(self, "%ol_pp", "%ol_r", 2);
+        self.emit("  store i64 0, i64* %ol_pp");
+        field(self, "%ol_ep", "%ol_r", 3);
+        self.emit("  store i64 0, i64* %ol_ep");
+        self.emit("  ret i8* %ol_raw");
+        self.emit("}");
+        self.emit("");
+
+        // Unread bytes in the buffer, refilling it when they have run out;
+        // false at the end of the file, which is then closed
+        self.emit("define i1 @brn_lines_fill(i8* %reader) {");
+        self.emit("lf_entry:");
+        self.emit(&format!("  %lf_r = bitcast i8* %reader to {}*", READER));
+        field(self, "%lf_pp", "%lf_r", 2);
+        self.emit("  %lf_pos = load i64, i64* %lf_pp");
+        field(self, "%lf_ep", "%lf_r", 3);
+        self.emit("  %lf_end = load i64, i64* %lf_ep");
+        self.emit("  %lf_left = icmp slt i64 %lf_pos, %lf_end");
+        self.emit("  br i1 %lf_left, label %lf_yes, label %lf_check");
+        self.emit("lf_check:");
+        field(self, "%lf_fp", "%lf_r", 0);
+        self.emit("  %lf_file = load i8*, i8** %lf_fp");
+        self.emit("  %lf_closed = icmp eq i8* %lf_file, null");
+        self.emit("  br i1 %lf_closed, label %lf_no, label %lf_read");
+        self.emit("lf_read:");
+        field(self, "%lf_bp", "%lf_r", 1);
+        self.emit("  %lf_buf = load i8*, i8** %lf_bp");
+        self.emit(&format!(
+            "  %lf_got = call i64 @fread(i8* %lf_buf, i64 1, i64 {}, i8* %lf_file)",
+            LINE_BUFFER
+        ));
+        self.emit("  %lf_eof = icmp sle i64 %lf_got, 0");
+        self.emit("  br i1 %lf_eof, label %lf_close, label %lf_filled");
+        self.emit("lf_filled:");
+        self.emit("  store i64 0, i64* %lf_pp");
+        self.emit("  store i64 %lf_got, i64* %lf_ep");
+        self.emit("  br label %lf_yes");
+        self.emit("lf_close:");
+        self.emit("  call i32 @fclose(i8* %lf_file)");
+        self.emit("  store i8* null, i8** %lf_fp");
+        self.emit("  br label %lf_no");
+        self.emit("lf_yes:");
+        self.emit("  ret i1 1");
+        self.emit("lf_no:");
+        self.emit("  ret i1 0");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define i1 @has_next_line_impl(i8* %reader) {");
+        self.emit("  %hl_more = call i1 @brn_lines_fill(i8* %reader)");
+        self.emit("  ret i1 %hl_more");
+        self.emit("}");
+        self.emit("");
+
+        // The next line without its "\n" or "\r\n", as a new string; "" once
+        // the file has run out. A line can span several buffers, so it is
+        // collected in its own buffer, doubled as needed.
+        self.emit("define i8* @next_line_impl(i8* %reader) {");
+        self.emit("nl_entry:");
+        self.emit(&format!("  %nl_r = bitcast i8* %reader to {}*", READER));
+        field(self, "%nl_bp", "%nl_r", 1);
+        field(self, "%nl_pp", "%nl_r", 2);
+        field(self, "%nl_ep", "%nl_r", 3);
+        self.emit("  %nl_line0 = call i8* @malloc(i64 128)");
+        self.emit("  br label %nl_fill");
+        self.emit("nl_fill:");
+        self.emit("  %nl_line = phi i8* [ %nl_line0, %nl_entry ], [ %nl_line2, %nl_more ]");
+        self.emit("  %nl_len = phi i64 [ 0, %nl_entry ], [ %nl_len2, %nl_more ]");
+        self.emit("  %nl_cap = phi i64 [ 128, %nl_entry ], [ %nl_cap2, %nl_more ]");
+        self.emit("  %nl_any = call i1 @brn_lines_fill(i8* %reader)");
+        self.emit("  br i1 %nl_any, label %nl_scan_start, label %nl_finish");
+        self.emit("nl_scan_start:");
+        self.emit("  %nl_buf = load i8*, i8** %nl_bp");
+        self.emit("  %nl_pos = load i64, i64* %nl_pp");
+        self.emit("  %nl_end = load i64, i64* %nl_ep");
+        self.emit("  br label %nl_scan");
+        // Find the next "\n" in the buffer, or its end
+        self.emit("nl_scan:");
+        self.emit("  %nl_i = phi i64 [ %nl_pos, %nl_scan_start ], [ %nl_i1, %nl_next ]");
+        self.emit("  %nl_at_end = icmp sge i64 %nl_i, %nl_end");
+        self.emit("  br i1 %nl_at_end, label %nl_copy, label %nl_look");
+        self.emit("nl_look:");
+        self.emit("  %nl_cp = getelementptr i8, i8* %nl_buf, i64 %nl_i");
+        self.emit("  %nl_c = load i8, i8* %nl_cp");
+        self.emit("  %nl_is_nl = icmp eq i8 %nl_c, 10");
+        self.emit("  br i1 %nl_is_nl, label %nl_copy, label %nl_next");
+        self.emit("nl_next:");
+        self.emit("  %nl_i1 = add i64 %nl_i, 1");
+        self.emit("  br label %nl_scan");
+        // Append buf[pos..i] to the line, growing it to fit the terminator
+        self.emit("nl_copy:");
+        self.emit("  %nl_chunk = sub i64 %nl_i, %nl_pos");
+        self.emit("  %nl_want0 = add i64 %nl_len, %nl_chunk");
+        self.emit("  %nl_want = add i64 %nl_want0, 1");
+        self.emit("  %nl_fits = icmp sle i64 %nl_want, %nl_cap");
+        self.emit("  br i1 %nl_fits, label %nl_append, label %nl_grow");
+        self.emit("nl_grow:");
+        self.emit("  %nl_dbl = mul i64 %nl_cap, 2");
+        self.emit("  %nl_small = icmp slt i64 %nl_dbl, %nl_want");
+        self.emit("  %nl_ncap = select i1 %nl_small, i64 %nl_want, i64 %nl_dbl");
+        self.emit("  %nl_nline = call i8* @realloc(i8* %nl_line, i64 %nl_ncap)");
+        self.emit("  br label %nl_append");
+        self.emit("nl_append:");
+        self.emit("  %nl_line2 = phi i8* [ %nl_line, %nl_copy ], [ %nl_nline, %nl_grow ]");
+        self.emit("  %nl_cap2 = phi i64 [ %nl_cap, %nl_copy ], [ %nl_ncap, %nl_grow ]");
+        self.emit("  %nl_dst = getelementptr i8, i8* %nl_line2, i64 %nl_len");
+        self.emit("  %nl_src = getelementptr i8, i8* %nl_buf, i64 %nl_pos");
+        self.emit("  call void @brn_memcpy(i8* %nl_dst, i8* %nl_src, i64 %nl_chunk)");
+        self.emit("  %nl_len2 = add i64 %nl_len, %nl_chunk");
+        self.emit("  br i1 %nl_at_end, label %nl_more, label %nl_found");
+        // The line goes on in the next buffer
+        self.emit("nl_more:");
+        self.emit("  store i64 %nl_end, i64* %nl_pp");
+        self.emit("  br label %nl_fill");
+        self.emit("nl_found:");
+        self.emit("  %nl_after = add i64 %nl_i, 1");
+        self.emit("  store i64 %nl_after, i64* %nl_pp");
+        self.emit("  br label %nl_finish");
+        self.emit("nl_finish:");
+        self.emit("  %nl_fline = phi i8* [ %nl_line, %nl_fill ], [ %nl_line2, %nl_found ]");
+        self.emit("  %nl_flen = phi i64 [ %nl_len, %nl_fill ], [ %nl_len2, %nl_found ]");
+        // Drop the "\r" of a "\r\n"
+        self.emit("  %nl_has = icmp sgt i64 %nl_flen, 0");
+        self.emit("  br i1 %nl_has, label %nl_check_cr, label %nl_done");
+        self.emit("nl_check_cr:");
+        self.emit("  %nl_last = sub i64 %nl_flen, 1");
+        self.emit("  %nl_lp = getelementptr i8, i8* %nl_fline, i64 %nl_last");
+        self.emit("  %nl_lc = load i8, i8* %nl_lp");
+        self.emit("  %nl_is_cr = icmp eq i8 %nl_lc, 13");
+        self.emit("  %nl_cut = select i1 %nl_is_cr, i64 %nl_last, i64 %nl_flen");
+        self.emit("  br label %nl_done");
+        self.emit("nl_done:");
+        self.emit("  %nl_n = phi i64 [ 0, %nl_finish ], [ %nl_cut, %nl_check_cr ]");
+        self.emit("  %nl_term = getelementptr i8, i8* %nl_fline, i64 %nl_n");
+        self.emit("  store i8 0, i8* %nl_term");
+        self.emit("  ret i8* %nl_fline");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @brn_lines_free(i8* %reader) {");
+        self.emit("lx_entry:");
+        self.emit(&format!("  %lx_r = bitcast i8* %reader to {}*", READER));
+        field(self, "%lx_fp", "%lx_r", 0);
+        self.emit("  %lx_file = load i8*, i8** %lx_fp");
+        self.emit("  %lx_open = icmp ne i8* %lx_file, null");
+        self.emit("  br i1 %lx_open, label %lx_close, label %lx_free");
+        self.emit("lx_close:");
+        self.emit("  call i32 @fclose(i8* %lx_file)");
+        self.emit("  store i8* null, i8** %lx_fp");
+        self.emit("  br label %lx_free");
+        self.emit("lx_free:");
+        field(self, "%lx_bp", "%lx_r", 1);
+        self.emit("  %lx_buf = load i8*, i8** %lx_bp");
+        self.emit("  call void @free(i8* %lx_buf)");
+        self.emit("  call void @free(i8* %reader)");
+        self.emit("  ret void");
+        self.emit("}");
+        self.emit("");
+    }
+
+    /// `path_join`, `path_basename`, `path_extension` and `path_parent`,
+    /// which work on the text of a path without touching the file system.
+    /// `/` separates components everywhere and `\\` does too on Windows,
+    /// where `path_join` puts `\\` between the parts it joins.
+    fn emit_path_ops(&mut self) {
+        let windows = cfg!(target_os = "windows");
+
+        self.emit("define i1 @brn_is_sep(i8 %c) {");
+        self.emit("  %is_slash = icmp eq i8 %c, 47");
+        if windows {
+            self.emit("  %is_back = icmp eq i8 %c, 92");
+            self.emit("  %is_sep = or i1 %is_slash, %is_back");
+            self.emit("  ret i1 %is_sep");
+        } else {
+            self.emit("  ret i1 %is_slash");
+        }
+        self.emit("}");
+        self.emit("");
+
+        // Where the last component of `path` ends, trailing separators
+        // left out, with where it starts stored in `%start_out`; both are
+        // 0 for "" and for a path of nothing but separators
+        self.emit("define i64 @brn_path_last(i8* %path, i64* %start_out) {");
+        self.emit("pt_entry:");
+        self.emit("  %pt_len = call i64 @strlen(i8* %path)");
+        self.emit("  br label %pt_trim");
+        self.emit("pt_trim:");
+        self.emit("  %pt_end = phi i64 [ %pt_len, %pt_entry ], [ %pt_last, %pt_trim_check ]");
+        self.emit("  %pt_empty = icmp eq i64 %pt_end, 0");
+        self.emit("  br i1 %pt_empty, label %pt_scan, label %pt_trim_check");
+        self.emit("pt_trim_check:");
+        self.emit("  %pt_last = sub i64 %pt_end, 1");
+        self.emit("  %pt_lp = getelementptr i8, i8* %path, i64 %pt_last");
+        self.emit("  %pt_lc = load i8, i8* %pt_lp");
+        self.emit("  %pt_lsep = call i1 @brn_is_sep(i8 %pt_lc)");
+        self.emit("  br i1 %pt_lsep, label %pt_trim, label %pt_scan");
+        self.emit("pt_scan:");
+        self.emit(
+            "  %pt_i = phi i64 [ %pt_end, %pt_trim ], [ %pt_end, %pt_trim_check ], [ %pt_prev, %pt_scan_check ]",
+        );
+        self.emit("  %pt_start = icmp eq i64 %pt_i, 0");
+        self.emit("  br i1 %pt_start, label %pt_done, label %pt_scan_check");
+        self.emit("pt_scan_check:");
+        self.emit("  %pt_prev = sub i64 %pt_i, 1");
+        self.emit("  %pt_cp = getelementptr i8, i8* %path, i64 %pt_prev");
+        self.emit("  %pt_c = load i8, i8* %pt_cp");
+        self.emit("  %pt_sep = call i1 @brn_is_sep(i8 %pt_c)");
+        self.emit("  br i1 %pt_sep, label %pt_done, label %pt_scan");
+        self.emit("pt_done:");
+        self.emit("  store i64 %pt_i, i64* %start_out");
+        self.emit("  ret i64 %pt_end");
+        self.emit("}");
+        self.emit("");
+
+        // path_join(a, b): b alone if a is empty or b is absolute, else
+        // the two with one separator between them
+        self.emit("define i8* @path_join_impl(i8* %a, i8* %b) {");
+        self.emit("pj_entry:");
+        self.emit("  %pj_alen = call i64 @strlen(i8* %a)");
+        self.emit("  %pj_blen = call i64 @strlen(i8* %b)");
+        self.emit("  %pj_aempty = icmp eq i64 %pj_alen, 0");
+        self.emit("  br i1 %pj_aempty, label %pj_just_b, label %pj_check_b");
+        self.emit("pj_check_b:");
+        self.emit("  %pj_b0 = load i8, i8* %b");
+        self.emit("  %pj_rooted = call i1 @brn_is_sep(i8 %pj_b0)");
+        if windows {
+            // ... or starts with a drive, "C:"
+            self.emit("  br i1 %pj_rooted, label %pj_just_b, label %pj_check_drive");
+            self.emit("pj_check_drive:");
+            self.emit("  %pj_long = icmp sge i64 %pj_blen, 2");
+            self.emit("  br i1 %pj_long, label %pj_drive, label %pj_join");
+            self.emit("pj_drive:");
+            self.emit("  %pj_b1p = getelementptr i8, i8* %b, i64 1");
+            self.emit("  %pj_b1 = load i8, i8* %pj_b1p");
+            self.emit("  %pj_colon = icmp eq i8 %pj_b1, 58");
+            self.emit("  br i1 %pj_colon, label %pj_just_b, label %pj_join");
+        } else {
+            self.emit("  br i1 %pj_rooted, label %pj_just_b, label %pj_join");
+        }
+        self.emit("pj_join:");
+        self.emit("  %pj_alast = sub i64 %pj_alen, 1");
+        self.emit("  %pj_alp = getelementptr i8, i8* %a, i64 %pj_alast");
+        self.emit("  %pj_alc = load i8, i8* %pj_alp");
+        self.emit("  %pj_has_sep = call i1 @brn_is_sep(i8 %pj_alc)");
+        self.emit("  %pj_seplen = select i1 %pj_has_sep, i64 0, i64 1");
+        self.emit("  %pj_bat = add i64 %pj_alen, %pj_seplen");
+        self.emit("  %pj_n = add i64 %pj_bat, %pj_blen");
+        self.emit("  %pj_size = add i64 %pj_n, 1");
+        self.emit("  %pj_out = call i8* @malloc(i64 %pj_size)");
+        self.emit("  call void @brn_memcpy(i8* %pj_out, i8* %a, i64 %pj_alen)");
+        // When a already ends in a separator, b (or the terminator) is
+        // copied over this one
+        self.emit("  %pj_sp = getelementptr i8, i8* %pj_out, i64 %pj_alen");
+        self.emit(&format!(
+            "  store i8 {}, i8* %pj_sp",
+            if windows { 92 } else { 47 }
+        ));
+        self.emit("  %pj_bp = getelementptr i8, i8* %pj_out, i64 %pj_bat");
+        self.emit("  call void @brn_memcpy(i8* %pj_bp, i8* %b, i64 %pj_blen)");
+        self.emit("  %pj_np = getelementptr i8, i8* %pj_out, i64 %pj_n");
+        self.emit("  store i8 0, i8* %pj_np");
+        self.emit("  ret i8* %pj_out");
+        self.emit("pj_just_b:");
+        self.emit("  %pj_copy = call i8* @brn_substr_copy(i8* %b, i64 %pj_blen)");
+        self.emit("  ret i8* %pj_copy");
+        self.emit("}");
+        self.emit("");
+
+        // path_basename(p): the last component, "" if there is none
+        self.emit("define i8* @path_basename_impl(i8* %path) {");
+        self.emit("  %pb_startp = alloca i64");
+        self.emit("  %pb_end = call i64 @brn_path_last(i8* %path, i64* %pb_startp)");
+        self.emit("  %pb_start = load i64, i64* %pb_startp");
+        self.emit("  %pb_n = sub i64 %pb_end, %pb_start");
+        self.emit("  %pb_from = getelementptr i8, i8* %path, i64 %pb_start");
+        self.emit("  %pb_out = call i8* @brn_substr_copy(i8* %pb_from, i64 %pb_n)");
+        self.emit("  ret i8* %pb_out");
+        self.emit("}");
+        self.emit("");
+
+        // path_extension(p): what follows the last '.' of the last
+        // component, without the dot; "" if it has none, or its only dot
+        // starts it (".bashrc")
+        self.emit("define i8* @path_extension_impl(i8* %path) {");
+        self.emit("px_entry:");
+        self.emit("  %px_startp = alloca i64");
+        self.emit("  %px_end = call i64 @brn_path_last(i8* %path, i64* %px_startp)");
+        self.emit("  %px_start = load i64, i64* %px_startp");
+        self.emit("  br label %px_scan");
+        self.emit("px_scan:");
+        self.emit("  %px_i = phi i64 [ %px_end, %px_entry ], [ %px_prev, %px_check ]");
+        self.emit("  %px_out_of = icmp sle i64 %px_i, %px_start");
+        self.emit("  br i1 %px_out_of, label %px_none, label %px_check");
+        self.emit("px_check:");
+        self.emit("  %px_prev = sub i64 %px_i, 1");
+        self.emit("  %px_cp = getelementptr i8, i8* %path, i64 %px_prev");
+        self.emit("  %px_c = load i8, i8* %px_cp");
+        self.emit("  %px_dot = icmp eq i8 %px_c, 46");
+        self.emit("  br i1 %px_dot, label %px_found, label %px_scan");
+        self.emit("px_found:");
+        self.emit("  %px_leading = icmp eq i64 %px_prev, %px_start");
+        self.emit("  br i1 %px_leading, label %px_none, label %px_ext");
+        self.emit("px_ext:");
+        self.emit("  %px_from = getelementptr i8, i8* %path, i64 %px_i");
+        self.emit("  %px_n = sub i64 %px_end, %px_i");
+        self.emit("  %px_out = call i8* @brn_substr_copy(i8* %px_from, i64 %px_n)");
+        self.emit("  ret i8* %px_out");
+        self.emit("px_none:");
+        self.emit("  %px_empty = call i8* @brn_substr_copy(i8* %path, i64 0)");
+        self.emit("  ret i8* %px_empty");
+        self.emit("}");
+        self.emit("");
+
+        // path_parent(p): everything before the last component, without
+        // the separators after it but keeping a root ("/a" gives "/");
+        // "" if the path is a single component
+        self.emit("define i8* @path_parent_impl(i8* %path) {");
+        self.emit("pp_entry:");
+        self.emit("  %pp_startp = alloca i64");
+        self.emit("  call i64 @brn_path_last(i8* %path, i64* %pp_startp)");
+        self.emit("  %pp_start = load i64, i64* %pp_startp");
+        self.emit("  br label %pp_trim");
+        self.emit("pp_trim:");
+        self.emit("  %pp_j = phi i64 [ %pp_start, %pp_entry ], [ %pp_prev, %pp_check ]");
+        self.emit("  %pp_root = icmp sle i64 %pp_j, 1");
+        self.emit("  br i1 %pp_root, label %pp_done, label %pp_check");
+        self.emit("pp_check:");
+        self.emit("  %pp_prev = sub i64 %pp_j, 1");
+        self.emit("  %pp_cp = getelementptr i8, i8* %path, i64 %pp_prev");
+        self.emit("  %pp_c = load i8, i8* %pp_cp");
+        self.emit("  %pp_sep = call i1 @brn_is_sep(i8 %pp_c)");
+        self.emit("  br i1 %pp_sep, label %pp_trim, label %pp_done");
+        self.emit("pp_done:");
+        self.emit("  %pp_out = call i8* @brn_substr_copy(i8* %path, i64 %pp_j)");
+        self.emit("  ret i8* %pp_out");
+        self.emit("}");
+        self.emit("");
+    }
+
+    /// `vec_get` / `vec_set` check their handle and index first: a null Vec
+    /// or an index outside `0..len` panics instead of touching memory the
+    /// Vec doesn't own.
+    fn emit_vec_check(&mut self) {
+        self.emit("define void @brn_vec_check(i8* %vec, i64 %idx) {");
+        self.emit("vk_entry:");
+        self.emit("  %vk_null = icmp eq i8* %vec, null");
+        self.emit("  br i1 %vk_null, label %vk_null_vec, label %vk_range");
+        self.emit("vk_null_vec:");
+        self.emit(
+            "  %vk_nmsg = getelementptr inbounds [18 x i8], [18 x i8]* @.str.null_vec, i64 0, i64 0",
+        );
+        self.emit("  call void @brn_panic(i8* %vk_nmsg)");
+        self.push(Inst::Unreachable);
+        self.emit("vk_range:");
+        self.emit("  %vk_lp = bitcast i8* %vec to i64*");
+        self.emit("  %vk_len = load i64, i64* %vk_lp");
+        // Unsigned, so a negative index is out of range too
+        self.emit("  %vk_ok = icmp ult i64 %idx, %vk_len");
+        self.emit("  br i1 %vk_ok, label %vk_done, label %vk_oob");
+        self.emit("vk_oob:");
+        self.emit(
+            "  %vk_omsg = getelementptr inbounds [24 x i8], [24 x i8]* @.str.vec_oob, i64 0, i64 0",
+        );
+        self.emit("  call void @brn_panic(i8* %vk_omsg)");
+        self.push(Inst::Unreachable);
+        self.emit("vk_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+    }
+
+    /// The C runtime's allocator, which AddressSanitizer replaces with its
+    /// checking one under `--sanitize`.
+    fn emit_libc_allocator(&mut self) {
+        self.emit("declare i8* @malloc(i64)");
+        self.emit("declare i8* @realloc(i8*, i64)");
+        self.emit("declare void @free(i8*)");
+        if !cfg!(target_os = "windows") {
+            self.emit("declare void @exit(i32)");
+        }
+        self.emit("");
+    }
+
+    /// The C runtime has its own `strlen`, `fopen` and friends, which the
+    /// sanitizer intercepts. The runtime's own functions of those names
+    /// (some with different contracts) are made internal so they neither
+    /// clash with nor replace the C ones.
+    fn internalize_libc_names(&mut self) {
+        const LIBC_NAMES: &[&str] = &[
+            "strlen", "strcmp", "strcpy", "puts", "fopen", "fclose", "fread", "fwrite", "fseek",
+            "ftell",
+        ];
+        self.internalize(|name| LIBC_NAMES.contains(&name));
+    }
+
+    /// A library's object file offers its exports and `#[no_mangle]`
+    /// functions, and nothing else: the runtime's functions and globals
+    /// are made internal, so its `malloc` or `brn_*` helpers don't clash
+    /// with the host program's symbols.
+    fn internalize_runtime(&mut self) {
+        let kept: std::collections::HashSet<String> = self
+            .exports
+            .iter()
+            .chain(&self.no_mangle)
+            .map(|name| self.mangle_fn(name))
+            .collect();
+        self.internalize(|name| !kept.contains(name));
+    }
+
+    /// Give the functions and globals defined under a name `pick` accepts
+    /// internal linkage, unless they already have a linkage of their own.
+    fn internalize(&mut self, pick: impl Fn(&str) -> bool) {
+        let mut output = String::with_capacity(self.output.len());
+        for line in self.output.lines() {
+            if let Some(rest) = line.strip_prefix("define ")
+                && rest.split_once('@').is_some_and(|(linkage, rest)| {
+                    !linkage.starts_with("internal ")
+                        && !linkage.starts_with("private ")
+                        && rest.split_once('(').is_some_and(|(name, _)| pick(name))
                 })
-                .collect::<Vec<_>>()
-                .join(", ")
-        };
+            {
+                output.push_str("define internal ");
+                output.push_str(rest);
+            } else if let Some((name, rest)) = line.split_once(" = global ")
+                && name.strip_prefix('@').is_some_and(&pick)
+            {
+                output.push_str(name);
+                output.push_str(" = internal global ");
+                output.push_str(rest);
+            } else {
+                output.push_str(line);
+            }
+            output.push('\n');
+        }
+        self.output = output;
+    }
 
-        let mangled = Self::mangle_fn(name);
+    /// Whether `malloc` / `realloc` / `free` are replaced by ones that
+    /// attribute each block to its allocation site.
+    fn tracks_allocs(&self) -> bool {
+        self.options.profile_alloc || self.options.detect_leaks
+    }
 
-        let fn_attrs = if name != "main" && self.pure_functions.contains(name) {
-            " nounwind readonly willreturn"
+    /// The name the platform allocator function `name` is defined under:
+    /// `--profile-alloc` and `--detect-leaks` put their own `malloc` /
+    /// `realloc` / `free` in front.
+    fn sys_alloc(&self, name: &str) -> String {
+        if self.tracks_allocs() {
+            format!("brn_sys_{}", name)
         } else {
-            " nounwind"
+            name.to_string()
+        }
+    }
+
+    /// `--profile-alloc` / `--detect-leaks`: every call from the function
+    /// emitted since `fn_start` to anything but a user function becomes an
+    /// allocation site. Its ID is stored in `@brn_alloc_site` before the
+    /// call, so the allocations made there (directly or inside a runtime
+    /// helper) are counted against it.
+    fn mark_alloc_sites(&mut self, fn_start: usize) {
+        let user_symbols: std::collections::HashSet<String> = self
+            .function_signatures
+            .keys()
+            .map(|f| self.mangle_fn(f))
+            .collect();
+        let mut marked = String::new();
+        let mut ordinal = 0;
+        for line in self.output[fn_start..].lines() {
+            let callee = line
+                .split_once("call ")
+                .and_then(|(_, rest)| rest.split_once('@'))
+                .and_then(|(_, rest)| rest.split_once('('))
+                .map(|(name, _)| name);
+            if let Some(callee) = callee
+                && !user_symbols.contains(callee)
+                && !callee.starts_with("brn_prof_")
+                && !callee.starts_with("llvm.")
+                && callee != "brn_alloc_report"
+                && callee != "brn_leak_report"
+            {
+                ordinal += 1;
+                let site = self.alloc_sites.len();
+                self.alloc_sites.push(format!(
+                    "{}#{} {}",
+                    self.current_function_name, ordinal, callee
+                ));
+                marked.push_str(&format!("  store i64 {}, i64* @brn_alloc_site\n", site));
+            }
+            marked.push_str(line);
+            marked.push('\n');
+        }
+        self.output.truncate(fn_start);
+        self.output.push_str(&marked);
+    }
+
+    /// The allocation sites shared by `--profile-alloc` and
+    /// `--detect-leaks`: `@brn_alloc_site` (the current one, set by
+    /// `mark_alloc_sites`), their names, and
+    /// `brn_alloc_write_sites(counts, bytes)`, which writes an
+    /// `n<TAB>bytes<TAB>site` row to stderr for each site with a nonzero
+    /// count. The last site, `(runtime)`, stands for allocations made
+    /// outside any. Returns the number of sites.
+    fn emit_alloc_sites_runtime(&mut self) -> usize {
+        let n = self.alloc_sites.len() + 1;
+        let mut names = self.alloc_sites.clone();
+        names.push("(runtime)".to_string());
+
+        self.emit("");
+        self.emit(&format!("@brn_alloc_site = global i64 {}", n - 1));
+        let mut entries = Vec::new();
+        for name in &names {
+            let id = self.new_string_literal(name);
+            let len = name.len() + 1;
+            entries.push(format!(
+                "i8* getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0)",
+                len, len, id
+            ));
+        }
+        self.emit(&format!(
+            "@brn_alloc_site_names = private constant [{} x i8*] [{}]",
+            n,
+            entries.join(", ")
+        ));
+        self.emit("");
+
+        let tab_id = self.new_string_literal("\t");
+        let nl_id = self.new_string_literal("\n");
+
+        self.emit("define void @brn_alloc_write_int(i64 %n) {");
+        self.emit("  %aw_buf = alloca [32 x i8]");
+        self.emit("  %aw_bufp = getelementptr [32 x i8], [32 x i8]* %aw_buf, i64 0, i64 0");
+        self.emit("  %aw_s = call i8* @int_to_string_stack(i64 %n, i8* %aw_bufp)");
+        self.emit("  %aw_len = call i64 @strlen(i8* %aw_s)");
+        self.emit("  call void @brn_write_stderr(i8* %aw_s, i64 %aw_len)");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @brn_alloc_write_sites(i64* %counts, i64* %bytes) {");
+        self.emit("as_entry:");
+        self.emit(&format!(
+            "  %as_tab = getelementptr inbounds [2 x i8], [2 x i8]* @{}, i64 0, i64 0",
+            tab_id
+        ));
+        self.emit(&format!(
+            "  %as_nl = getelementptr inbounds [2 x i8], [2 x i8]* @{}, i64 0, i64 0",
+            nl_id
+        ));
+        self.emit("  br label %as_loop");
+        self.emit("as_loop:");
+        self.emit("  %as_i = phi i64 [ 0, %as_entry ], [ %as_next, %as_step ]");
+        self.emit(&format!("  %as_done = icmp eq i64 %as_i, {}", n));
+        self.emit("  br i1 %as_done, label %as_exit, label %as_row");
+        self.emit("as_row:");
+        self.emit("  %as_cp = getelementptr i64, i64* %counts, i64 %as_i");
+        self.emit("  %as_count = load i64, i64* %as_cp");
+        self.emit("  %as_unused = icmp eq i64 %as_count, 0");
+        self.emit("  br i1 %as_unused, label %as_step, label %as_print");
+        self.emit("as_print:");
+        self.emit("  call void @brn_alloc_write_int(i64 %as_count)");
+        self.emit("  call void @brn_write_stderr(i8* %as_tab, i64 1)");
+        self.emit("  %as_bp = getelementptr i64, i64* %bytes, i64 %as_i");
+        self.emit("  %as_bytes = load i64, i64* %as_bp");
+        self.emit("  call void @brn_alloc_write_int(i64 %as_bytes)");
+        self.emit("  call void @brn_write_stderr(i8* %as_tab, i64 1)");
+        self.emit(&format!(
+            "  %as_np = getelementptr [{} x i8*], [{} x i8*]* @brn_alloc_site_names, i64 0, i64 %as_i",
+            n, n
+        ));
+        self.emit("  %as_name = load i8*, i8** %as_np");
+        self.emit("  %as_name_len = call i64 @strlen(i8* %as_name)");
+        self.emit("  call void @brn_write_stderr(i8* %as_name, i64 %as_name_len)");
+        self.emit("  call void @brn_write_stderr(i8* %as_nl, i64 1)");
+        self.emit("  br label %as_step");
+        self.emit("as_step:");
+        self.emit("  %as_next = add i64 %as_i, 1");
+        self.emit("  br label %as_loop");
+        self.emit("as_exit:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        n
+    }
+
+    /// Counting allocator and exit-time report for `--profile-alloc`. Each
+    /// block carries its size in a 16-byte header so `free` can track the
+    /// live bytes. The report goes to stderr: totals, then
+    /// `allocs<TAB>bytes<TAB>site` rows for the sites that allocated.
+    fn emit_alloc_profile_runtime(&mut self) {
+        let n = self.emit_alloc_sites_runtime();
+
+        self.emit("");
+        self.emit("@brn_alloc_count = global i64 0");
+        self.emit("@brn_alloc_frees = global i64 0");
+        self.emit("@brn_alloc_total = global i64 0");
+        self.emit("@brn_alloc_live = global i64 0");
+        self.emit("@brn_alloc_peak = global i64 0");
+        self.emit(&format!(
+            "@brn_alloc_site_count = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit(&format!(
+            "@brn_alloc_site_bytes = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit("");
+
+        self.emit("define i8* @malloc(i64 %size) {");
+        self.emit("  %am_full = add i64 %size, 16");
+        self.emit("  %am_raw = call i8* @brn_sys_malloc(i64 %am_full)");
+        self.emit("  %am_hdr = bitcast i8* %am_raw to i64*");
+        self.emit("  store i64 %size, i64* %am_hdr");
+        self.emit("  %am_c = load i64, i64* @brn_alloc_count");
+        self.emit("  %am_c1 = add i64 %am_c, 1");
+        self.emit("  store i64 %am_c1, i64* @brn_alloc_count");
+        self.emit("  %am_t = load i64, i64* @brn_alloc_total");
+        self.emit("  %am_t1 = add i64 %am_t, %size");
+        self.emit("  store i64 %am_t1, i64* @brn_alloc_total");
+        self.emit("  %am_l = load i64, i64* @brn_alloc_live");
+        self.emit("  %am_l1 = add i64 %am_l, %size");
+        self.emit("  store i64 %am_l1, i64* @brn_alloc_live");
+        self.emit("  %am_p = load i64, i64* @brn_alloc_peak");
+        self.emit("  %am_higher = icmp sgt i64 %am_l1, %am_p");
+        self.emit("  %am_p1 = select i1 %am_higher, i64 %am_l1, i64 %am_p");
+        self.emit("  store i64 %am_p1, i64* @brn_alloc_peak");
+        self.emit("  %am_site = load i64, i64* @brn_alloc_site");
+        self.emit(&format!(
+            "  %am_scp = getelementptr [{} x i64], [{} x i64]* @brn_alloc_site_count, i64 0, i64 %am_site",
+            n, n
+        ));
+        self.emit("  %am_sc = load i64, i64* %am_scp");
+        self.emit("  %am_sc1 = add i64 %am_sc, 1");
+        self.emit("  store i64 %am_sc1, i64* %am_scp");
+        self.emit(&format!(
+            "  %am_sbp = getelementptr [{} x i64], [{} x i64]* @brn_alloc_site_bytes, i64 0, i64 %am_site",
+            n, n
+        ));
+        self.emit("  %am_sb = load i64, i64* %am_sbp");
+        self.emit("  %am_sb1 = add i64 %am_sb, %size");
+        self.emit("  store i64 %am_sb1, i64* %am_sbp");
+        self.emit("  %am_ptr = getelementptr i8, i8* %am_raw, i64 16");
+        self.emit("  ret i8* %am_ptr");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @free(i8* %ptr) {");
+        self.emit("af_entry:");
+        self.emit("  %af_null = icmp eq i8* %ptr, null");
+        self.emit("  br i1 %af_null, label %af_done, label %af_free");
+        self.emit("af_free:");
+        self.emit("  %af_raw = getelementptr i8, i8* %ptr, i64 -16");
+        self.emit("  %af_hdr = bitcast i8* %af_raw to i64*");
+        self.emit("  %af_size = load i64, i64* %af_hdr");
+        self.emit("  %af_l = load i64, i64* @brn_alloc_live");
+        self.emit("  %af_l1 = sub i64 %af_l, %af_size");
+        self.emit("  store i64 %af_l1, i64* @brn_alloc_live");
+        self.emit("  %af_f = load i64, i64* @brn_alloc_frees");
+        self.emit("  %af_f1 = add i64 %af_f, 1");
+        self.emit("  store i64 %af_f1, i64* @brn_alloc_frees");
+        self.emit("  call void @brn_sys_free(i8* %af_raw)");
+        self.emit("  br label %af_done");
+        self.emit("af_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // A new counted block with the old contents, then the old one freed
+        self.emit("define i8* @realloc(i8* %ptr, i64 %size) {");
+        self.emit("ar_entry:");
+        self.emit("  %ar_new = call i8* @malloc(i64 %size)");
+        self.emit("  %ar_null = icmp eq i8* %ptr, null");
+        self.emit("  br i1 %ar_null, label %ar_done, label %ar_copy");
+        self.emit("ar_copy:");
+        self.emit("  %ar_raw = getelementptr i8, i8* %ptr, i64 -16");
+        self.emit("  %ar_hdr = bitcast i8* %ar_raw to i64*");
+        self.emit("  %ar_old = load i64, i64* %ar_hdr");
+        self.emit("  %ar_shrink = icmp slt i64 %size, %ar_old");
+        self.emit("  %ar_n = select i1 %ar_shrink, i64 %size, i64 %ar_old");
+        self.emit("  call void @brn_memcpy(i8* %ar_new, i8* %ptr, i64 %ar_n)");
+        self.emit("  call void @free(i8* %ptr)");
+        self.emit("  br label %ar_done");
+        self.emit("ar_done:");
+        self.emit("  ret i8* %ar_new");
+        self.emit("}");
+        self.emit("");
+
+        let header = "--- allocations ---\n";
+        let columns = "allocs\tbytes\tsite\n";
+        let labels = [
+            ("allocs", "allocs: "),
+            ("frees", "  frees: "),
+            ("total", "  bytes: "),
+            ("peak", "  peak bytes: "),
+            ("live", "  still live: "),
+        ];
+        let header_id = self.new_string_literal(header);
+        let columns_id = self.new_string_literal(columns);
+        let nl_id = self.new_string_literal("\n");
+        let label_ids: Vec<String> = labels
+            .iter()
+            .map(|(_, text)| self.new_string_literal(text))
+            .collect();
+
+        self.emit("define void @brn_alloc_report() {");
+        self.emit("ar_entry:");
+        let header_len = header.len() + 1;
+        self.emit(&format!(
+            "  %ar_hdr = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
+            header_len, header_len, header_id
+        ));
+        self.emit(&format!(
+            "  call void @brn_write_stderr(i8* %ar_hdr, i64 {})",
+            header.len()
+        ));
+        for ((global, text), id) in labels.iter().zip(&label_ids) {
+            let len = text.len() + 1;
+            self.emit(&format!(
+                "  %ar_l_{} = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
+                global, len, len, id
+            ));
+            self.emit(&format!(
+                "  call void @brn_write_stderr(i8* %ar_l_{}, i64 {})",
+                global,
+                text.len()
+            ));
+            let counter = if *global == "allocs" { "count" } else { global };
+            self.emit(&format!(
+                "  %ar_v_{} = load i64, i64* @brn_alloc_{}",
+                global, counter
+            ));
+            self.emit(&format!(
+                "  call void @brn_alloc_write_int(i64 %ar_v_{})",
+                global
+            ));
+        }
+        self.emit(&format!(
+            "  %ar_nl = getelementptr inbounds [2 x i8], [2 x i8]* @{}, i64 0, i64 0",
+            nl_id
+        ));
+        self.emit("  call void @brn_write_stderr(i8* %ar_nl, i64 1)");
+        let columns_len = columns.len() + 1;
+        self.emit(&format!(
+            "  %ar_cols = getelementptr inbounds [{} x i8], [{} x i8]* @{}, i64 0, i64 0",
+            columns_len, columns_len, columns_id
+        ));
+        self.emit(&format!(
+            "  call void @brn_write_stderr(i8* %ar_cols, i64 {})",
+            columns.len()
+        ));
+        self.emit(&format!(
+            "  call void @brn_alloc_write_sites(i64* getelementptr ([{} x i64], [{} x i64]* @brn_alloc_site_count, i64 0, i64 0), i64* getelementptr ([{} x i64], [{} x i64]* @brn_alloc_site_bytes, i64 0, i64 0))",
+            n, n, n, n
+        ));
+        self.push(Inst::Ret(None));
+        self.emit("}");
+    }
+
+    /// Tracking allocator and exit-time report for `--detect-leaks`. Each
+    /// block starts with a 32-byte header `{ prev, next, size, site }`
+    /// linking it into the list of live blocks at `@brn_leak_head`; `free`
+    /// unlinks it. Leaving `main` walks what is left and writes to stderr
+    /// the number of leaked blocks and bytes, then
+    /// `blocks<TAB>bytes<TAB>site` rows for the sites they came from.
+    fn emit_leak_detector_runtime(&mut self) {
+        let n = self.emit_alloc_sites_runtime();
+        let block = "{ i8*, i8*, i64, i64 }";
+
+        self.emit("");
+        self.emit("@brn_leak_head = global i8* null");
+        self.emit(&format!(
+            "@brn_leak_site_count = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit(&format!(
+            "@brn_leak_site_bytes = global [{} x i64] zeroinitializer",
+            n
+        ));
+        self.emit("");
+
+        // Pushed onto the front of the list
+        self.emit("define i8* @malloc(i64 %size) {");
+        self.emit("lm_entry:");
+        self.emit("  %lm_full = add i64 %size, 32");
+        self.emit("  %lm_raw = call i8* @brn_sys_malloc(i64 %lm_full)");
+        self.emit(&format!("  %lm_blk = bitcast i8* %lm_raw to {}*", block));
+        self.emit("  %lm_head = load i8*, i8** @brn_leak_head");
+        self.emit("  %lm_site = load i64, i64* @brn_alloc_site");
+        for (field, ty, value) in [
+            (0, "i8*", "null"),
+            (1, "i8*", "%lm_head"),
+            (2, "i64", "%size"),
+            (3, "i64", "%lm_site"),
+        ] {
+            self.emit(&format!(
+                "  %lm_f{} = getelementptr {}, {}* %lm_blk, i32 0, i32 {}",
+                field, block, block, field
+            ));
+            self.emit(&format!("  store {} {}, {}* %lm_f{}", ty, value, ty, field));
+        }
+        self.emit("  store i8* %lm_raw, i8** @brn_leak_head");
+        self.emit("  %lm_first = icmp eq i8* %lm_head, null");
+        self.emit("  br i1 %lm_first, label %lm_done, label %lm_link");
+        self.emit("lm_link:");
+        self.emit(&format!("  %lm_next = bitcast i8* %lm_head to {}*", block));
+        self.emit(&format!(
+            "  %lm_next_prev = getelementptr {}, {}* %lm_next, i32 0, i32 0",
+            block, block
+        ));
+        self.emit("  store i8* %lm_raw, i8** %lm_next_prev");
+        self.emit("  br label %lm_done");
+        self.emit("lm_done:");
+        self.emit("  %lm_ptr = getelementptr i8, i8* %lm_raw, i64 32");
+        self.emit("  ret i8* %lm_ptr");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("define void @free(i8* %ptr) {");
+        self.emit("lf_entry:");
+        self.emit("  %lf_null = icmp eq i8* %ptr, null");
+        self.emit("  br i1 %lf_null, label %lf_done, label %lf_unlink");
+        self.emit("lf_unlink:");
+        self.emit("  %lf_raw = getelementptr i8, i8* %ptr, i64 -32");
+        self.emit(&format!("  %lf_blk = bitcast i8* %lf_raw to {}*", block));
+        self.emit(&format!(
+            "  %lf_pp = getelementptr {}, {}* %lf_blk, i32 0, i32 0",
+            block, block
+        ));
+        self.emit("  %lf_prev = load i8*, i8** %lf_pp");
+        self.emit(&format!(
+            "  %lf_np = getelementptr {}, {}* %lf_blk, i32 0, i32 1",
+            block, block
+        ));
+        self.emit("  %lf_next = load i8*, i8** %lf_np");
+        self.emit("  %lf_is_head = icmp eq i8* %lf_prev, null");
+        self.emit("  br i1 %lf_is_head, label %lf_behead, label %lf_skip");
+        self.emit("lf_behead:");
+        self.emit("  store i8* %lf_next, i8** @brn_leak_head");
+        self.emit("  br label %lf_back");
+        self.emit("lf_skip:");
+        self.emit(&format!("  %lf_pb = bitcast i8* %lf_prev to {}*", block));
+        self.emit(&format!(
+            "  %lf_pb_next = getelementptr {}, {}* %lf_pb, i32 0, i32 1",
+            block, block
+        ));
+        self.emit("  store i8* %lf_next, i8** %lf_pb_next");
+        self.emit("  br label %lf_back");
+        self.emit("lf_back:");
+        self.emit("  %lf_is_tail = icmp eq i8* %lf_next, null");
+        self.emit("  br i1 %lf_is_tail, label %lf_release, label %lf_relink");
+        self.emit("lf_relink:");
+        self.emit(&format!("  %lf_nb = bitcast i8* %lf_next to {}*", block));
+        self.emit(&format!(
+            "  %lf_nb_prev = getelementptr {}, {}* %lf_nb, i32 0, i32 0",
+            block, block
+        ));
+        self.emit("  store i8* %lf_prev, i8** %lf_nb_prev");
+        self.emit("  br label %lf_release");
+        self.emit("lf_release:");
+        self.emit("  call void @brn_sys_free(i8* %lf_raw)");
+        self.emit("  br label %lf_done");
+        self.emit("lf_done:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+        self.emit("");
+
+        // A new tracked block with the old contents, then the old one freed
+        self.emit("define i8* @realloc(i8* %ptr, i64 %size) {");
+        self.emit("lr_entry:");
+        self.emit("  %lr_new = call i8* @malloc(i64 %size)");
+        self.emit("  %lr_null = icmp eq i8* %ptr, null");
+        self.emit("  br i1 %lr_null, label %lr_done, label %lr_copy");
+        self.emit("lr_copy:");
+        self.emit("  %lr_raw = getelementptr i8, i8* %ptr, i64 -32");
+        self.emit(&format!("  %lr_blk = bitcast i8* %lr_raw to {}*", block));
+        self.emit(&format!(
+            "  %lr_sp = getelementptr {}, {}* %lr_blk, i32 0, i32 2",
+            block, block
+        ));
+        self.emit("  %lr_old = load i64, i64* %lr_sp");
+        self.emit("  %lr_shrink = icmp slt i64 %size, %lr_old");
+        self.emit("  %lr_n = select i1 %lr_shrink, i64 %size, i64 %lr_old");
+        self.emit("  call void @brn_memcpy(i8* %lr_new, i8* %ptr, i64 %lr_n)");
+        self.emit("  call void @free(i8* %ptr)");
+        self.emit("  br label %lr_done");
+        self.emit("lr_done:");
+        self.emit("  ret i8* %lr_new");
+        self.emit("}");
+        self.emit("");
+
+        let header = "--- leaks ---\n";
+        let columns = "blocks\tbytes\tsite\n";
+        let blocks_label = "leaked blocks: ";
+        let bytes_label = "  bytes: ";
+        let header_id = self.new_string_literal(header);
+        let columns_id = self.new_string_literal(columns);
+        let blocks_id = self.new_string_literal(blocks_label);
+        let bytes_id = self.new_string_literal(bytes_label);
+        let nl_id = self.new_string_literal("\n");
+        let counts = format!(
+            "i64* getelementptr ([{} x i64], [{} x i64]* @brn_leak_site_count, i64 0, i64 0)",
+            n, n
+        );
+        let bytes = format!(
+            "i64* getelementptr ([{} x i64], [{} x i64]* @brn_leak_site_bytes, i64 0, i64 0)",
+            n, n
+        );
+        let text = |id: &str, value: &str| {
+            let len = value.len() + 1;
+            format!(
+                "  call void @brn_write_stderr(i8* getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0), i64 {})",
+                len,
+                len,
+                id,
+                value.len()
+            )
         };
 
+        self.emit("define void @brn_leak_report() {");
+        self.emit("lp_entry:");
+        self.emit("  %lp_first = load i8*, i8** @brn_leak_head");
+        self.emit("  br label %lp_walk");
+        self.emit("lp_walk:");
+        self.emit("  %lp_raw = phi i8* [ %lp_first, %lp_entry ], [ %lp_next, %lp_count ]");
+        self.emit("  %lp_blocks = phi i64 [ 0, %lp_entry ], [ %lp_blocks1, %lp_count ]");
+        self.emit("  %lp_bytes = phi i64 [ 0, %lp_entry ], [ %lp_bytes1, %lp_count ]");
+        self.emit("  %lp_end = icmp eq i8* %lp_raw, null");
+        self.emit("  br i1 %lp_end, label %lp_print, label %lp_count");
+        self.emit("lp_count:");
+        self.emit(&format!("  %lp_blk = bitcast i8* %lp_raw to {}*", block));
+        self.emit(&format!(
+            "  %lp_np = getelementptr {}, {}* %lp_blk, i32 0, i32 1",
+            block, block
+        ));
+        self.emit("  %lp_next = load i8*, i8** %lp_np");
+        self.emit(&format!(
+            "  %lp_sp = getelementptr {}, {}* %lp_blk, i32 0, i32 2",
+            block, block
+        ));
+        self.emit("  %lp_size = load i64, i64* %lp_sp");
         self.emit(&format!(
-            "\ndefine {} @{}({}){} {{",
-            ret_type, mangled, param_list, fn_attrs
+            "  %lp_ip = getelementptr {}, {}* %lp_blk, i32 0, i32 3",
+            block, block
         ));
-        self.emit("entry:");
+        self.emit("  %lp_site = load i64, i64* %lp_ip");
+        self.emit(&format!(
+            "  %lp_scp = getelementptr [{} x i64], [{} x i64]* @brn_leak_site_count, i64 0, i64 %lp_site",
+            n, n
+        ));
+        self.emit("  %lp_sc = load i64, i64* %lp_scp");
+        self.emit("  %lp_sc1 = add i64 %lp_sc, 1");
+        self.emit("  store i64 %lp_sc1, i64* %lp_scp");
+        self.emit(&format!(
+            "  %lp_sbp = getelementptr [{} x i64], [{} x i64]* @brn_leak_site_bytes, i64 0, i64 %lp_site",
+            n, n
+        ));
+        self.emit("  %lp_sb = load i64, i64* %lp_sbp");
+        self.emit("  %lp_sb1 = add i64 %lp_sb, %lp_size");
+        self.emit("  store i64 %lp_sb1, i64* %lp_sbp");
+        self.emit("  %lp_blocks1 = add i64 %lp_blocks, 1");
+        self.emit("  %lp_bytes1 = add i64 %lp_bytes, %lp_size");
+        self.emit("  br label %lp_walk");
+        self.emit("lp_print:");
+        self.emit(&text(&header_id, header));
+        self.emit(&text(&blocks_id, blocks_label));
+        self.emit("  call void @brn_alloc_write_int(i64 %lp_blocks)");
+        self.emit(&text(&bytes_id, bytes_label));
+        self.emit("  call void @brn_alloc_write_int(i64 %lp_bytes)");
+        self.emit(&text(&nl_id, "\n"));
+        self.emit("  %lp_none = icmp eq i64 %lp_blocks, 0");
+        self.emit("  br i1 %lp_none, label %lp_exit, label %lp_sites");
+        self.emit("lp_sites:");
+        self.emit(&text(&columns_id, columns));
+        self.emit(&format!(
+            "  call void @brn_alloc_write_sites({}, {})",
+            counts, bytes
+        ));
+        self.emit("  br label %lp_exit");
+        self.emit("lp_exit:");
+        self.push(Inst::Ret(None));
+        self.emit("}");
+    }
 
-        for param in params {
-            let (type_is_ref, _type_is_mut, inner_type) = Self::strip_ref_prefix(&param.param_type);
-            let type_is_ref = type_is_ref || param.is_reference;
+    /// `sdiv` / `srem`, which are undefined for a zero divisor: unless
+    /// `--no-checks` is given or the divisor is a nonzero literal, a zero
+    /// divisor panics with the operator's location instead.
+    fn gen_division(
+        &mut self,
+        instr: &'static str,
+        left_reg: &str,
+        right: &AstNode,
+        right_reg: &str,
+        location: &Location,
+    ) -> String {
+        if !self.options.no_checks && !matches!(right, AstNode::Number(n, _) if *n != 0) {
+            let is_zero = self.new_temp();
+            self.icmp(&is_zero, "eq", "i64", right_reg, "0");
+            let zero_label = self.new_label("div_zero");
+            let ok_label = self.new_label("div_ok");
+            self.cond_br(&is_zero, &zero_label, &ok_label);
+            self.label(&zero_label);
+            let message = format!(
+                "division by zero at line {}, column {}",
+                location.line, location.column
+            );
+            let msg_id = self.new_string_literal(&message);
+            let msg_len = message.len() + 1;
+            let msg_ptr = self.new_temp();
+            self.gep_inbounds(
+                &msg_ptr,
+                &format!("[{} x i8]", msg_len),
+                &format!("@{}", msg_id),
+                &[("i64", "0"), ("i64", "0")],
+            );
+            self.call(None, "void", "@brn_panic", &[("i8*", &msg_ptr)]);
+            self.push(Inst::Unreachable);
+            self.label(&ok_label);
+        }
+        let result = self.new_temp();
+        self.binary(&result, instr, "i64", left_reg, right_reg);
+        result
+    }
 
-            if type_is_ref {
-                let param_type_name = inner_type.to_string();
+    fn gen_string_concat(&mut self, left: &str, right: &str) -> String {
+        let use_stack = self.concat_on_stack();
+        self.gen_string_concat_inner(left, right, use_stack)
+    }
 
-                let array_size = if inner_type.starts_with('[') {
-                    if let Some(size_str) = inner_type.split(';').nth(1) {
-                        size_str
-                            .trim()
-                            .trim_end_matches(']')
-                            .trim()
-                            .parse::<usize>()
-                            .ok()
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                };
+    /// Concatenations bound to a non-escaping `let` are built on the stack.
+    fn concat_on_stack(&self) -> bool {
+        self.current_binding
+            .as_ref()
+            .is_some_and(|b| self.non_escaping.contains(b))
+    }
 
-                self.current_function_vars.insert(
-                    param.name.clone(),
-                    VarMetadata {
-                        llvm_name: format!("%arg_{}", param.name),
-                        var_type: param_type_name,
-                        is_heap: false,
-                        array_size,
-                        is_string_literal: false,
-                    },
+    /// Flatten `a + b + c` (left-associative) into `[a, b, c]`.
+    fn collect_concat_operands<'n>(&self, node: &'n AstNode, parts: &mut Vec<&'n AstNode>) {
+        if let AstNode::BinaryOp {
+            op: BinOp::Add,
+            left,
+            right,
+            ..
+        } = node
+            && self.infer_type(left) == "string"
+        {
+            self.collect_concat_operands(left, parts);
+            parts.push(right);
+        } else {
+            parts.push(node);
+        }
+    }
+
+    /// Three or more strings joined with `+` go through one StringBuilder
+    /// instead of allocating and copying every intermediate result.
+    fn gen_string_concat_chain(&mut self, parts: &[&AstNode]) -> String {
+        let sb = self.new_temp();
+        self.call(Some(&sb), "i8*", "@sb_new_impl", &[]);
+        let mut regs = Vec::new();
+        for part in parts {
+            let reg = self.gen_node(part).reg;
+            self.call(
+                None,
+                "void",
+                "@sb_append_impl",
+                &[("i8*", &sb), ("i8*", &reg)],
+            );
+            regs.push(reg);
+        }
+        let result = self.new_temp();
+        self.call(Some(&result), "i8*", "@sb_finish_impl", &[("i8*", &sb)]);
+        for (part, reg) in parts.iter().zip(&regs).rev() {
+            self.free_if_temporary(part, reg);
+        }
+        result
+    }
+
+    /// Free the heap values `vars` hold, each a variable's slot and type,
+    /// as their block ends.
+    fn free_vars(&mut self, vars: Vec<(String, String)>) {
+        for (llvm_name, var_type) in vars {
+            if self.struct_types.contains_key(&var_type) {
+                let struct_ptr = self.new_temp();
+                self.load_into(&struct_ptr, &format!("%{}*", var_type), &llvm_name);
+                let i8_ptr = self.new_temp();
+                self.cast(
+                    &i8_ptr,
+                    "bitcast",
+                    &format!("%{}*", var_type),
+                    &struct_ptr,
+                    "i8*",
                 );
+                self.call(None, "void", "@free", &[("i8*", &i8_ptr)]);
+            } else if Self::is_vec_type(&var_type)
+                || var_type == "StringBuilder"
+                || var_type == "Bytes"
+            {
+                let ptr_reg = self.new_temp();
+                self.load_into(&ptr_reg, "i8*", &llvm_name);
+                if self.vec_owns_elements(&var_type) {
+                    self.call(None, "void", "@vec_free_strings_impl", &[("i8*", &ptr_reg)]);
+                }
+                let dp_raw = self.new_temp();
+                self.gep(&dp_raw, "i8", &ptr_reg, &[("i64", "16")]);
+                let dp = self.new_temp();
+                self.cast(&dp, "bitcast", "i8*", &dp_raw, "i8**");
+                let data = self.new_temp();
+                self.load_into(&data, "i8*", &dp);
+                self.call(None, "void", "@free", &[("i8*", &data)]);
+                self.call(None, "void", "@free", &[("i8*", &ptr_reg)]);
+            } else if var_type == "LineReader" {
+                let ptr_reg = self.new_temp();
+                self.load_into(&ptr_reg, "i8*", &llvm_name);
+                self.call(None, "void", "@brn_lines_free", &[("i8*", &ptr_reg)]);
             } else {
-                let param_type_str = self.type_to_llvm(&param.param_type);
-                let param_type_name = param.param_type.clone();
+                let ptr_reg = self.new_temp();
+                self.load_into(&ptr_reg, "i8*", &llvm_name);
+                self.call(None, "void", "@free", &[("i8*", &ptr_reg)]);
+            }
+        }
+    }
 
-                let ptr = self.new_temp();
-                self.emit(&format!("  {} = alloca {}", ptr, param_type_str));
-                self.emit(&format!(
-                    "  store {} %arg_{}, {}* {}",
-                    param_type_str, param.name, param_type_str, ptr
-                ));
+    /// Before a `return`: free what every variable still in scope owns,
+    /// and unlock its guards, as if each block were ending. A variable
+    /// the returned value is made of is handed over instead.
+    fn free_on_return(&mut self, value: Option<&AstNode>) {
+        let mut kept = Vec::new();
+        if let Some(value) = value {
+            Self::returned_names(value, &mut kept);
+        }
+        let mut guards: Vec<String> = self
+            .current_function_vars
+            .values()
+            .filter(|meta| meta.var_type.starts_with("MutexGuard<") && !self.is_unsafe_fn)
+            .map(|meta| meta.llvm_name.clone())
+            .collect();
+        let mut vars: Vec<(String, String)> = self
+            .current_function_vars
+            .iter()
+            .filter(|(name, meta)| {
+                meta.is_heap
+                    && !meta.llvm_name.starts_with('@')
+                    && !self.moved_vars.contains(&meta.llvm_name)
+                    && !kept.contains(&name.as_str())
+            })
+            .map(|(_, meta)| (meta.llvm_name.clone(), meta.var_type.clone()))
+            .collect();
+        guards.sort();
+        vars.sort();
+        for guard_slot in guards {
+            let mutex_ptr = self.new_temp();
+            self.load_into(&mutex_ptr, "i8*", &guard_slot);
+            self.call(None, "void", "@brn_mutex_unlock", &[("i8*", &mutex_ptr)]);
+        }
+        self.free_vars(vars);
+    }
 
-                self.current_function_vars.insert(
-                    param.name.clone(),
-                    VarMetadata {
-                        llvm_name: ptr,
-                        var_type: param_type_name,
-                        is_heap: false,
-                        array_size: None,
-                        is_string_literal: false,
-                    },
-                );
+    /// The variables a returned value takes over: itself, or what a struct
+    /// literal, variant or array literal is built from.
+    fn returned_names<'n>(value: &'n AstNode, names: &mut Vec<&'n str>) {
+        match value {
+            AstNode::Identifier { name, .. } => names.push(name),
+            AstNode::StructInit { fields, base, .. } => {
+                for (_, field) in fields {
+                    Self::returned_names(field, names);
+                }
+                if let Some(base) = base {
+                    Self::returned_names(base, names);
+                }
+            }
+            AstNode::EnumValue { values, .. } | AstNode::ArrayLit(values, _) => {
+                for v in values {
+                    Self::returned_names(v, names);
+                }
             }
+            _ => {}
         }
+    }
 
-        self.block_terminated = false;
-        self.gen_node(body);
+    /// `+` only reads its operands; one that was built just for it is freed
+    /// once the result has been (see `StringOwnership`).
+    fn free_if_temporary(&mut self, node: &AstNode, reg: &str) {
+        // A concatenation built on the stack isn't the heap's to free
+        let on_stack = matches!(node, AstNode::BinaryOp { .. }) && self.concat_on_stack();
+        if self.string_ownership(node) == StringOwnership::Owned && !on_stack {
+            self.call(None, "void", "@free", &[("i8*", reg)]);
+        }
+    }
 
-        if name == "main" && !self.block_terminated {
-            self.emit("  ret i32 0");
-        } else if ret_type == "void" && !self.block_terminated {
-            self.emit("  ret void");
-        } else if !self.block_terminated {
-            self.emit("  unreachable");
+    /// How the string `node` evaluates to is owned (see `StringOwnership`).
+    fn string_ownership(&self, node: &AstNode) -> StringOwnership {
+        crate::parser::string_ownership(node, |call| match call {
+            AstNode::Call { name, .. } => {
+                self.externs.contains_key(name) || self.borrowed_returns.contains(name)
+            }
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => matches!(
+                self.method_as_call(object, method, args),
+                Some(AstNode::Call { name, .. }) if self.borrowed_returns.contains(&name)
+            ),
+            _ => false,
+        })
+    }
+
+    /// What a function returning `string` returns belongs to the caller; a
+    /// function returning `&string` hands back what it was given.
+    fn returned_string(&mut self, value: &AstNode, reg: String) -> String {
+        if self.infer_type(value) != "string"
+            || self.borrowed_returns.contains(&self.current_function_name)
+        {
+            return reg;
         }
+        self.take_string(value, reg)
+    }
 
-        self.emit("}");
-        String::new()
+    /// `value`, already generated into `reg`, as a string whoever stores it
+    /// owns: an `Owned` one as it is, anything else copied.
+    fn owned_string(&mut self, value: &AstNode, reg: String) -> String {
+        if self.string_ownership(value) == StringOwnership::Owned {
+            return reg;
+        }
+        let copy = self.new_temp();
+        self.call(Some(&copy), "i8*", "@brn_strdup", &[("i8*", &reg)]);
+        copy
     }
 
-    fn gen_string_concat(&mut self, left: &str, right: &str) -> String {
-        let use_stack = self
-            .current_binding
-            .as_ref()
-            .map(|b| self.non_escaping.contains(b))
-            .unwrap_or(false);
-        self.gen_string_concat_inner(left, right, use_stack)
+    /// Like `owned_string`, for a binding, assignment or `return` that moves
+    /// `value` when it is a variable: a variable that owns its string hands
+    /// it over, and its block no longer frees it.
+    fn take_string(&mut self, value: &AstNode, reg: String) -> String {
+        if let AstNode::Identifier { name, .. } = value
+            && let Some(meta) = self.current_function_vars.get(name)
+            && meta.is_heap
+        {
+            self.moved_vars.insert(meta.llvm_name.clone());
+            return reg;
+        }
+        self.owned_string(value, reg)
     }
 
     fn gen_string_concat_inner(&mut self, left: &str, right: &str, use_stack: bool) -> String {
         let len1 = self.new_temp();
         let len2 = self.new_temp();
-        self.emit(&format!("  {} = call i64 @strlen(i8* {})", len1, left));
-        self.emit(&format!("  {} = call i64 @strlen(i8* {})", len2, right));
+        self.call(Some(&len1), "i64", "@strlen", &[("i8*", left)]);
+        self.call(Some(&len2), "i64", "@strlen", &[("i8*", right)]);
 
         let total = self.new_temp();
         let total_plus_one = self.new_temp();
-        self.emit(&format!("  {} = add i64 {}, {}", total, len1, len2));
-        self.emit(&format!("  {} = add i64 {}, 1", total_plus_one, total));
+        self.binary(&total, "add", "i64", &len1, &len2);
+        self.binary(&total_plus_one, "add", "i64", &total, "1");
 
         let new_ptr = self.new_temp();
         if use_stack {
@@ -3008,29 +9963,32 @@ impl CodeGenerator {
                 new_ptr, total_plus_one
             ));
         } else {
-            self.emit(&format!(
-                "  {} = call i8* @malloc(i64 {})",
-                new_ptr, total_plus_one
-            ));
+            self.call(
+                Some(&new_ptr),
+                "i8*",
+                "@malloc",
+                &[("i64", &total_plus_one)],
+            );
         }
 
         let temp1 = self.new_temp();
-        self.emit(&format!(
-            "  {} = call i8* @strcpy(i8* {}, i8* {})",
-            temp1, new_ptr, left
-        ));
+        self.call(
+            Some(&temp1),
+            "i8*",
+            "@strcpy",
+            &[("i8*", &new_ptr), ("i8*", left)],
+        );
 
         let offset_ptr = self.new_temp();
-        self.emit(&format!(
-            "  {} = getelementptr i8, i8* {}, i64 {}",
-            offset_ptr, new_ptr, len1
-        ));
+        self.gep(&offset_ptr, "i8", &new_ptr, &[("i64", &len1)]);
 
         let temp2 = self.new_temp();
-        self.emit(&format!(
-            "  {} = call i8* @strcpy(i8* {}, i8* {})",
-            temp2, offset_ptr, right
-        ));
+        self.call(
+            Some(&temp2),
+            "i8*",
+            "@strcpy",
+            &[("i8*", &offset_ptr), ("i8*", right)],
+        );
 
         new_ptr
     }
@@ -3040,20 +9998,451 @@ impl CodeGenerator {
             AstNode::Identifier { name, .. } => self
                 .current_function_vars
                 .get(name)
-                .map(|m| m.var_type.clone())
+                .map(|m| Self::pointee(&m.var_type).to_string())
                 .unwrap_or_default(),
             AstNode::StructInit { name, .. } => name.clone(),
-            _ => String::new(),
+            _ => Self::pointee(&self.infer_type(node)).to_string(),
+        }
+    }
+
+    /// Prepare `value` (already generated into `value_reg`) to be stored in a
+    /// `*T`. A struct variable stored there now belongs to whatever holds the
+    /// pointer, so it must not be freed when its own block ends; a value
+    /// struct lives on the stack and is copied to the heap instead.
+    fn store_through_pointer(&mut self, value: &AstNode, value_reg: String) -> String {
+        let value_type = self.infer_type(value);
+        if self.is_value_struct(&value_type) {
+            return self.copy_struct(&value_type, &value_reg, true);
+        }
+        if let AstNode::Identifier { name, .. } = value
+            && let Some(meta) = self.current_function_vars.get(name)
+            && self.struct_types.contains_key(&meta.var_type)
+        {
+            self.moved_vars.insert(meta.llvm_name.clone());
+        }
+        value_reg
+    }
+
+    /// Small all-scalar structs (see `parser::is_value_struct`) are copied on
+    /// assignment and passed and returned as LLVM aggregates.
+    fn is_value_struct(&self, type_name: &str) -> bool {
+        self.struct_types
+            .get(type_name)
+            .is_some_and(|fields| is_value_struct(fields.iter().map(|(_, t)| t.as_str())))
+    }
+
+    /// LLVM type of a `type_name` held by value — in a struct field, a by-value
+    /// parameter or a return value. Only value structs differ from
+    /// `type_to_llvm`: they are the aggregate itself rather than a pointer.
+    fn value_type_to_llvm(&self, type_name: &str) -> String {
+        if self.is_value_struct(type_name) {
+            format!("%{}", type_name)
+        } else {
+            self.type_to_llvm(type_name)
+        }
+    }
+
+    /// The bytes `size_of(type_name)` reports: what a struct's or an enum's
+    /// own data takes up, rather than the pointer it is held by, and for
+    /// anything else the value itself.
+    fn size_of(&self, type_name: &str) -> u64 {
+        if self.enum_types.contains_key(type_name) {
+            return 16; // { i32, i64 }
+        }
+        match self.struct_types.get(type_name) {
+            Some(fields) => self.struct_layout(fields).0,
+            None => self.layout(type_name).0,
+        }
+    }
+
+    /// Size and alignment of the fields laid out one after another, each at
+    /// the next offset its alignment allows, padded at the end to a
+    /// multiple of the largest alignment — as LLVM lays out `%T` on the
+    /// 64-bit targets Brain compiles for.
+    fn struct_layout(&self, fields: &[(String, String)]) -> (u64, u64) {
+        let mut size: u64 = 0;
+        let mut align = 1;
+        for (_, field_type) in fields {
+            let (field_size, field_align) = self.layout(field_type);
+            size = size.next_multiple_of(field_align) + field_size;
+            align = align.max(field_align);
+        }
+        (size.next_multiple_of(align), align)
+    }
+
+    /// Size and alignment of a `type_name` where it is stored: in a
+    /// variable, a field or an argument.
+    fn layout(&self, type_name: &str) -> (u64, u64) {
+        if let Some(rest) = type_name.strip_prefix('[')
+            && let Some((_, len)) = rest.trim_end_matches(']').rsplit_once(';')
+        {
+            // Arrays keep every element in an `i64`
+            let len: u64 = len.trim().parse().unwrap_or(0);
+            return (len * 8, 8);
+        }
+        if self.is_value_struct(type_name) {
+            return self.struct_layout(&self.struct_types[type_name]);
+        }
+        match self.type_to_llvm(type_name).as_str() {
+            "i1" | "i8" => (1, 1),
+            "i32" => (4, 4),
+            "void" => (0, 1),
+            _ => (8, 8),
+        }
+    }
+
+    /// Store `value_reg`, a `field_type`, into the field at `field_ptr`. Value
+    /// struct fields are inline, so the struct is copied in.
+    fn gen_store_field(&mut self, field_type: &str, value_reg: &str, field_ptr: &str) {
+        if self.is_value_struct(field_type) {
+            let whole = self.new_temp();
+            self.load_into(&whole, &format!("%{}", field_type), value_reg);
+            self.store(&format!("%{}", field_type), &whole, field_ptr);
+        } else {
+            let llvm_type = self.type_to_llvm(field_type);
+            self.store(&llvm_type, value_reg, field_ptr);
+        }
+    }
+
+    /// Copy the struct at `src` into a fresh stack slot, or a fresh heap
+    /// allocation when `heap` is set, and return the new pointer.
+    fn copy_struct(&mut self, struct_name: &str, src: &str, heap: bool) -> String {
+        let dest = self.alloc_struct(struct_name, heap);
+        let whole = self.new_temp();
+        self.load_into(&whole, &format!("%{}", struct_name), src);
+        self.store(&format!("%{}", struct_name), &whole, &dest);
+        dest
+    }
+
+    /// Stack or heap memory for one `struct_name`.
+    fn alloc_struct(&mut self, struct_name: &str, heap: bool) -> String {
+        if !heap {
+            let slot = self.new_temp();
+            self.alloca_into(&slot, &format!("%{}", struct_name));
+            return slot;
+        }
+        let size = self.size_of(struct_name);
+        let raw = self.new_temp();
+        let ptr = self.new_temp();
+        self.call(Some(&raw), "i8*", "@malloc", &[("i64", &size.to_string())]);
+        self.cast(&ptr, "bitcast", "i8*", &raw, &format!("%{}*", struct_name));
+        ptr
+    }
+
+    /// A call of an `impl` method, `obj.m(args)`, as the plain call
+    /// `Type.m(obj, args)` — with `&obj` when the method borrows `self`.
+    fn method_as_call(&self, object: &AstNode, method: &str, args: &[AstNode]) -> Option<AstNode> {
+        let obj_type = self.infer_type(object);
+        let name = format!("{}.{}", Self::pointee(&obj_type), method);
+        let by_ref = *self.methods.get(&name)?;
+        let span = object.span();
+        let receiver = if by_ref {
+            AstNode::Reference(Box::new(object.clone()), span)
+        } else {
+            object.clone()
+        };
+        Some(AstNode::Call {
+            name,
+            args: std::iter::once(receiver)
+                .chain(args.iter().cloned())
+                .collect(),
+            span,
+        })
+    }
+
+    /// The iterator a `for` loop over `collection` steps through: the result of
+    /// `collection.iter()` if its type has an `iter` method, or the collection
+    /// itself if it has `next`. `None` means the loop counts over integers.
+    fn iterator_source(&self, collection: &AstNode) -> Option<AstNode> {
+        if matches!(
+            collection,
+            AstNode::BinaryOp {
+                op: BinOp::DotDot,
+                ..
+            }
+        ) {
+            return None;
+        }
+        let type_name = self.infer_type(collection);
+        let type_name = Self::pointee(&type_name);
+        if self.methods.contains_key(&format!("{}.iter", type_name)) {
+            Some(AstNode::MethodCall {
+                object: Box::new(collection.clone()),
+                method: "iter".to_string(),
+                args: Vec::new(),
+                span: collection.span(),
+            })
+        } else if self.methods.contains_key(&format!("{}.next", type_name)) {
+            Some(collection.clone())
+        } else {
+            None
+        }
+    }
+
+    /// `for x in source { body }` over an iterator: `next()` is called before
+    /// every iteration, `Option::Some(v)` binds `x` to `v` and `Option::None`
+    /// ends the loop.
+    fn gen_for_iterator(
+        &mut self,
+        variable: &str,
+        index: Option<&str>,
+        source: &AstNode,
+        body: &AstNode,
+    ) -> String {
+        let start_label = self.new_label("for_start");
+        let body_label = self.new_label("for_body");
+        let end_label = self.new_label("for_end");
+
+        // Not a valid identifier, so it can't clash with a user variable
+        let hidden = format!("iter@{}", start_label);
+        let span = source.span();
+        self.gen_node(&AstNode::LetBinding {
+            mutable: true,
+            name: hidden.clone(),
+            type_annotation: None,
+            value: Box::new(source.clone()),
+            span,
+            is_exported: false,
+            attributes: Vec::new(),
+        });
+        let next_call = AstNode::MethodCall {
+            object: Box::new(AstNode::Identifier { name: hidden, span }),
+            method: "next".to_string(),
+            args: Vec::new(),
+            span,
+        };
+        let some_tag = self
+            .enum_types
+            .get("Option")
+            .and_then(|variants| variants.iter().position(|v| v == "Some"))
+            .unwrap_or(0);
+
+        self.loop_stack.push(LoopLabels {
+            continue_label: start_label.clone(),
+            break_label: end_label.clone(),
+        });
+
+        let loop_var = self.new_temp();
+        self.alloca_into(&loop_var, "i64");
+        let counter = self.new_temp();
+        self.alloca_into(&counter, "i64");
+        self.store("i64", "0", &counter);
+        self.current_function_vars.insert(
+            variable.to_string(),
+            VarMetadata {
+                llvm_name: loop_var.clone(),
+                var_type: "int".to_string(),
+                is_heap: false,
+                array_size: None,
+            },
+        );
+
+        self.br(&start_label);
+        self.label(&start_label);
+        let option = self.gen_node(&next_call).reg;
+        let tag_ptr = self.new_temp();
+        self.gep(
+            &tag_ptr,
+            "{ i32, i64 }",
+            &option,
+            &[("i32", "0"), ("i32", "0")],
+        );
+        let tag = self.new_temp();
+        self.load_into(&tag, "i32", &tag_ptr);
+        let cond = self.new_temp();
+        self.icmp(&cond, "eq", "i32", &tag, &some_tag.to_string());
+        self.cond_br(&cond, &body_label, &end_label);
+
+        self.label(&body_label);
+        let val_ptr = self.new_temp();
+        self.gep(
+            &val_ptr,
+            "{ i32, i64 }",
+            &option,
+            &[("i32", "0"), ("i32", "1")],
+        );
+        let val = self.new_temp();
+        self.load_into(&val, "i64", &val_ptr);
+        self.store("i64", &val, &loop_var);
+        let count = self.new_temp();
+        let next_count = self.new_temp();
+        self.load_into(&count, "i64", &counter);
+        self.binary(&next_count, "add", "i64", &count, "1");
+        self.store("i64", &next_count, &counter);
+        if let Some(index) = index {
+            self.bind_loop_index(index, &count);
+        }
+        self.gen_node(body);
+        if !self.block_terminated() {
+            self.br(&start_label);
+        }
+
+        self.label(&end_label);
+        self.loop_stack.pop();
+        "0".to_string()
+    }
+
+    /// `for x in v` over a Vec: a counting loop over `0..v.len()` whose body
+    /// starts with `let x = v.get(i);`.
+    fn gen_for_vec(
+        &mut self,
+        variable: &str,
+        index: Option<&str>,
+        vec: &AstNode,
+        body: &AstNode,
+    ) -> String {
+        let label = self.new_label("for_vec");
+        let span = vec.span();
+        // A Vec that isn't a variable is evaluated once, into a hidden one
+        let vec = if let AstNode::Identifier { .. } = vec {
+            vec.clone()
+        } else {
+            let hidden = format!("vec@{}", label);
+            self.gen_node(&AstNode::LetBinding {
+                mutable: false,
+                name: hidden.clone(),
+                type_annotation: None,
+                value: Box::new(vec.clone()),
+                span,
+                is_exported: false,
+                attributes: Vec::new(),
+            });
+            AstNode::Identifier { name: hidden, span }
+        };
+        let index = index.map_or_else(|| format!("index@{}", label), str::to_string);
+
+        let mut statements = vec![AstNode::LetBinding {
+            mutable: false,
+            name: variable.to_string(),
+            type_annotation: None,
+            value: Box::new(AstNode::MethodCall {
+                object: Box::new(vec.clone()),
+                method: "get".to_string(),
+                args: vec![AstNode::Identifier {
+                    name: index.clone(),
+                    span,
+                }],
+                span,
+            }),
+            span,
+            is_exported: false,
+            attributes: Vec::new(),
+        }];
+        match body {
+            AstNode::Block(body, _) => statements.extend(body.iter().cloned()),
+            other => statements.push(other.clone()),
+        }
+        self.gen_node(&AstNode::For {
+            variable: index,
+            index: None,
+            iterator: Box::new(AstNode::BinaryOp {
+                op: BinOp::DotDot,
+                left: Box::new(AstNode::Number(0, span)),
+                right: Box::new(AstNode::MethodCall {
+                    object: Box::new(vec),
+                    method: "len".to_string(),
+                    args: Vec::new(),
+                    span,
+                }),
+                location: span.into(),
+                span,
+            }),
+            body: Box::new(AstNode::Block(statements, body.span())),
+            span,
+        })
+        .reg
+    }
+
+    /// Declares the `i` of `for (i, x) in enumerate(...)` holding `count`.
+    fn bind_loop_index(&mut self, index: &str, count: &str) {
+        let slot = self.new_temp();
+        self.alloca_into(&slot, "i64");
+        self.store("i64", count, &slot);
+        self.current_function_vars.insert(
+            index.to_string(),
+            VarMetadata {
+                llvm_name: slot,
+                var_type: "int".to_string(),
+                is_heap: false,
+                array_size: None,
+            },
+        );
+    }
+
+    /// `left <pred> right` for an ordering `pred` (`slt`, `sge`, ...).
+    /// Strings are ordered byte by byte through strcmp.
+    fn gen_ordering(
+        &mut self,
+        pred: &'static str,
+        left: &AstNode,
+        right: &AstNode,
+        left_reg: &str,
+        right_reg: &str,
+    ) -> String {
+        if self.infer_type(left) == "string" || self.infer_type(right) == "string" {
+            let cmp = self.new_temp();
+            self.call(
+                Some(&cmp),
+                "i32",
+                "@strcmp",
+                &[("i8*", left_reg), ("i8*", right_reg)],
+            );
+            let result = self.new_temp();
+            self.icmp(&result, pred, "i32", &cmp, "0");
+            result
+        } else {
+            let result = self.new_temp();
+            self.icmp(&result, pred, "i64", left_reg, right_reg);
+            result
+        }
+    }
+
+    /// Index of `field` in a struct's fields. A number is a position, as
+    /// produced by `let (a, b) = s;`.
+    fn field_index(fields: &[(String, String)], field: &str) -> Option<usize> {
+        fields.iter().position(|(n, _)| n == field).or_else(|| {
+            field
+                .parse::<usize>()
+                .ok()
+                .filter(|&idx| idx < fields.len())
+        })
+    }
+
+    /// The struct a `*T` points to; other types are returned unchanged.
+    fn pointee(type_name: &str) -> &str {
+        type_name.strip_prefix('*').unwrap_or(type_name)
+    }
+
+    /// LLVM type to compare `left` and `right` as pointers, if either side is
+    /// `null`, a `*T` or a struct.
+    /// Two structs of the same type, compared field by field; a `*T` or
+    /// `null` on either side is compared as a pointer.
+    fn is_struct_comparison(&self, left: &Value, right: &Value) -> bool {
+        left.brain_ty == right.brain_ty && self.struct_types.contains_key(&left.brain_ty)
+    }
+
+    fn pointer_cmp_type(&self, left: &AstNode, right: &AstNode) -> Option<String> {
+        let (lt, rt) = (self.infer_type(left), self.infer_type(right));
+        let is_ptr = |t: &str| t.starts_with('*') || self.struct_types.contains_key(t);
+        match (is_ptr(&lt), is_ptr(&rt)) {
+            (true, _) => Some(self.type_to_llvm(&lt)),
+            (_, true) => Some(self.type_to_llvm(&rt)),
+            _ if lt == "null" || rt == "null" => Some("i8*".to_string()),
+            _ => None,
         }
     }
 
     fn infer_type(&self, node: &AstNode) -> String {
         match node {
-            AstNode::Number(_) => "int".to_string(),
-            AstNode::Boolean(_) => "bool".to_string(),
-            AstNode::Character(_) => "char".to_string(),
-            AstNode::StringLit(_) => "string".to_string(),
+            AstNode::Number(_, _) => "int".to_string(),
+            AstNode::Boolean(_, _) => "bool".to_string(),
+            AstNode::Null(_) => "null".to_string(),
+            AstNode::Character(_, _) => "char".to_string(),
+            AstNode::SizeOf(_, _) => "int".to_string(),
+            AstNode::StringLit(_, _) => "string".to_string(),
+            AstNode::BytesLit(_, _) => "Bytes".to_string(),
             AstNode::StructInit { name, .. } => name.clone(),
+            AstNode::Slice { .. } => "string".to_string(),
             AstNode::BinaryOp { left, op, .. } => match op {
                 BinOp::Equal
                 | BinOp::NotEqual
@@ -3063,37 +10452,84 @@ impl CodeGenerator {
                 | BinOp::GreaterEqual
                 | BinOp::And
                 | BinOp::Or => "bool".to_string(),
-                _ => self.infer_type(left),
+                // Arithmetic on a `char` or `bool` is done on `int`s
+                _ => match self.infer_type(left).as_str() {
+                    "char" | "bool" => "int".to_string(),
+                    left_type => left_type.to_string(),
+                },
             },
+            AstNode::UnaryOp { op, .. } => match op {
+                crate::parser::UnOp::Not => "bool".to_string(),
+                crate::parser::UnOp::Negate => "int".to_string(),
+            },
+            AstNode::Cast { target, .. } => target.clone(),
             AstNode::Identifier { name, .. } => self
                 .current_function_vars
                 .get(name)
                 .map(|m| m.var_type.clone())
                 .unwrap_or_else(|| "int".to_string()),
-            AstNode::ArrayLit(_) => "array".to_string(),
+            AstNode::ArrayLit(_, _) => "array".to_string(),
             AstNode::EnumValue { enum_name, .. } => {
                 if enum_name == "Mutex" {
                     "Mutex<int>".to_string()
+                } else if self.enum_types.contains_key(enum_name) {
+                    enum_name.clone()
                 } else {
                     "enum".to_string()
                 }
             }
-            AstNode::Call { name, .. } => match name.as_str() {
-                "read_file" | "int_to_string" | "read_input" => "string".to_string(),
-                "write_file" => "int".to_string(),
-                "vec_new" => "Vec".to_string(),
-                "vec_get" | "vec_len" => "int".to_string(),
+            AstNode::Call { name, args, .. } => match name.as_str() {
+                _ if self.externs.contains_key(name) => {
+                    self.llvm_to_type(&self.externs[name].return_type)
+                }
+                "read_file" | "int_to_string" | "to_string" | "bool_to_string" | "enum_name"
+                | "read_input" | "run_capture" | "next_line" | "path_join" | "path_basename"
+                | "path_extension" | "path_parent" => "string".to_string(),
+                "open_lines" => "LineReader".to_string(),
+                "has_next_line" => "bool".to_string(),
+                "write_file" | "append_file" | "write_bytes" | "mkdir" | "remove_dir"
+                | "compare" | "char_to_int" | "char_count" | "now" => "int".to_string(),
+                "int_to_char" => "char".to_string(),
+                "vec_new" | "vec_with_capacity" | "read_bytes" => "Vec".to_string(),
+                "read_dir" => "Vec<string>".to_string(),
+                "vec_get" | "vec_pop" | "vec_remove" => match args.first() {
+                    Some(v) => self.vec_element(&self.infer_type(v)),
+                    None => "int".to_string(),
+                },
+                "vec_len" => "int".to_string(),
+                "sb_new" => "StringBuilder".to_string(),
+                "sb_to_string" => "string".to_string(),
+                "bytes_new" | "bytes_slice" | "bytes_from_string" => "Bytes".to_string(),
+                "bytes_len" | "bytes_get" => "int".to_string(),
+                "bytes_to_string" | "to_uppercase" | "to_lowercase" => "string".to_string(),
+                _ if self.vec_return_types.contains_key(name.as_str()) => {
+                    self.vec_return_types[name.as_str()].clone()
+                }
                 _ => self
                     .function_signatures
                     .get(name.as_str())
                     .map(|t| self.llvm_to_type(t))
                     .unwrap_or_else(|| "int".to_string()),
             },
-            AstNode::Reference(inner) => self.infer_type(inner),
-            AstNode::MethodCall { object, method, .. } => {
+            AstNode::Reference(inner, _) => self.infer_type(inner),
+            AstNode::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => {
+                if let Some(call) = self.method_as_call(object, method, args) {
+                    return self.infer_type(&call);
+                }
                 let obj_type = self.infer_type(object);
                 match method.as_str() {
-                    "len" | "char_at" | "get" => "int".to_string(),
+                    "len" | "char_count" | "char_at" => "int".to_string(),
+                    "to_string" if obj_type == "Bytes" => "string".to_string(),
+                    "next_line" if obj_type == "LineReader" => "string".to_string(),
+                    "has_next_line" if obj_type == "LineReader" => "bool".to_string(),
+                    "split" | "lines" => "Vec<string>".to_string(),
+                    "to_uppercase" | "to_lowercase" => "string".to_string(),
+                    "get" | "pop" | "remove" => self.vec_element(&obj_type),
                     "lock" => {
                         if obj_type.starts_with("Mutex<") {
                             let inner = &obj_type[6..obj_type.len() - 1];
@@ -3105,18 +10541,23 @@ impl CodeGenerator {
                     _ => obj_type,
                 }
             }
-            AstNode::MemberAccess { object, field } => {
+            AstNode::MemberAccess { object, field, .. } => {
                 let obj_type = self.infer_type(object);
                 self.struct_types
-                    .get(&obj_type)
+                    .get(Self::pointee(&obj_type))
                     .and_then(|fields| {
-                        fields
-                            .iter()
-                            .find(|(name, _)| name == field)
-                            .map(|(_, ty)| ty.clone())
+                        Self::field_index(fields, field).map(|idx| fields[idx].1.clone())
                     })
                     .unwrap_or_else(|| "int".to_string())
             }
+            AstNode::Index { array, .. } => {
+                let array_type = self.infer_type(array);
+                if Self::is_vec_type(&array_type) {
+                    self.vec_element(&array_type)
+                } else {
+                    "int".to_string()
+                }
+            }
             _ => "int".to_string(),
         }
     }
@@ -3130,6 +10571,7 @@ impl CodeGenerator {
             "void" => "void".to_string(),
             "{ i32, i64 }*" => "enum".to_string(),
             s if s.starts_with('%') && s.ends_with('*') => s[1..s.len() - 1].to_string(),
+            s if s.starts_with('%') => s[1..].to_string(),
             _ => "int".to_string(),
         }
     }
@@ -3141,11 +10583,16 @@ impl CodeGenerator {
             "char" => "i8".to_string(),
             "string" => "i8*".to_string(),
             "array" => "i64*".to_string(),
-            "Vec" => "i8*".to_string(),
+            "Vec" | "StringBuilder" | "Bytes" | "LineReader" => "i8*".to_string(),
+            t if t.starts_with("Vec<") => "i8*".to_string(),
             "void" => "void".to_string(),
             "enum" => "{ i32, i64 }*".to_string(),
             t if t.starts_with("Mutex<") => "i8*".to_string(),
             t if t.starts_with("MutexGuard<") => "i8*".to_string(),
+            // Structs are already held by pointer, so `*Node` is the same `%Node*`
+            t if t.starts_with('*') && self.struct_types.contains_key(&t[1..]) => {
+                format!("%{}*", &t[1..])
+            }
             t if t.starts_with('*') => {
                 let inner = self.type_to_llvm(&t[1..]);
                 format!("{}*", inner)
@@ -3181,14 +10628,242 @@ impl CodeGenerator {
         id
     }
 
+    /// A pointer to the first byte of the string constant `value`.
+    fn string_literal_ptr(&mut self, value: &str) -> String {
+        let id = self.new_string_literal(value);
+        let len = value.len() + 1;
+        let ptr = self.new_temp();
+        self.gep_inbounds(
+            &ptr,
+            &format!("[{} x i8]", len),
+            &format!("@{}", id),
+            &[("i64", "0"), ("i64", "0")],
+        );
+        ptr
+    }
+
+    /// The values of an array literal whose elements are all constants.
+    fn constant_array(elements: &[AstNode]) -> Option<Vec<i64>> {
+        elements
+            .iter()
+            .map(|elem| match elem {
+                AstNode::Number(n, _) => Some(*n),
+                AstNode::Boolean(b, _) => Some(*b as i64),
+                AstNode::Character(c, _) => Some(*c as i64),
+                AstNode::UnaryOp {
+                    op: crate::parser::UnOp::Negate,
+                    operand,
+                    ..
+                } => match operand.as_ref() {
+                    AstNode::Number(n, _) => n.checked_neg(),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn array_initializer(values: &[i64]) -> String {
+        values
+            .iter()
+            .map(|v| format!("i64 {}", v))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn new_byte_literal(&mut self, bytes: &[u8]) -> String {
+        if let Some((id, _)) = self.byte_literals.iter().find(|(_, b)| b == bytes) {
+            return id.clone();
+        }
+        let id = format!(".bytes.{}", self.byte_literals.len());
+        self.byte_literals.push((id.clone(), bytes.to_vec()));
+        id
+    }
+
+    fn new_array_literal(&mut self, values: Vec<i64>) -> String {
+        if let Some(id) = self.array_literal_map.get(&values) {
+            return id.clone();
+        }
+        let id = format!(".arr.{}", self.array_literals.len());
+        self.array_literal_map.insert(values.clone(), id.clone());
+        self.array_literals.push((id.clone(), values));
+        id
+    }
+
+    /// Add IR text: to the current function, one instruction per line, or
+    /// outside of one straight to the module.
     fn emit(&mut self, line: &str) {
-        self.output.push_str(line);
-        self.output.push('\n');
+        match &mut self.function {
+            Some(function) => {
+                for line in line.lines() {
+                    function.push(Inst::from_text(line));
+                }
+            }
+            None => {
+                self.output.push_str(line);
+                self.output.push('\n');
+            }
+        }
+    }
+
+    /// Add an instruction, the way `emit` adds text.
+    fn push(&mut self, inst: Inst) {
+        match &mut self.function {
+            Some(function) => function.push(inst),
+            None => {
+                self.output.push_str(&inst.to_string());
+                self.output.push('\n');
+            }
+        }
+    }
+
+    fn label(&mut self, label: &str) {
+        self.push(Inst::Label(label.to_string()));
+    }
+
+    fn br(&mut self, label: &str) {
+        self.push(Inst::Br(label.to_string()));
+    }
+
+    fn cond_br(&mut self, cond: &str, then_label: &str, else_label: &str) {
+        self.push(Inst::CondBr {
+            cond: cond.to_string(),
+            then_label: then_label.to_string(),
+            else_label: else_label.to_string(),
+        });
+    }
+
+    fn alloca_into(&mut self, dest: &str, ty: &str) {
+        self.push(Inst::Alloca {
+            dest: dest.to_string(),
+            ty: ty.to_string(),
+        });
+    }
+
+    fn load_into(&mut self, dest: &str, ty: &str, ptr: &str) {
+        self.push(Inst::Load {
+            dest: dest.to_string(),
+            ty: ty.to_string(),
+            ptr: ptr.to_string(),
+        });
+    }
+
+    fn store(&mut self, ty: &str, value: &str, ptr: &str) {
+        self.push(Inst::Store {
+            ty: ty.to_string(),
+            value: value.to_string(),
+            ptr: ptr.to_string(),
+        });
+    }
+
+    fn binary(&mut self, dest: &str, op: &'static str, ty: &str, lhs: &str, rhs: &str) {
+        self.push(Inst::Binary {
+            dest: dest.to_string(),
+            op,
+            ty: ty.to_string(),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        });
+    }
+
+    fn icmp(&mut self, dest: &str, pred: &'static str, ty: &str, lhs: &str, rhs: &str) {
+        self.push(Inst::Icmp {
+            dest: dest.to_string(),
+            pred,
+            ty: ty.to_string(),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        });
+    }
+
+    fn cast(&mut self, dest: &str, op: &'static str, from: &str, value: &str, to: &str) {
+        self.push(Inst::Cast {
+            dest: dest.to_string(),
+            op,
+            from: from.to_string(),
+            value: value.to_string(),
+            to: to.to_string(),
+        });
+    }
+
+    /// `call ret callee(args)`, each argument a type and a value; `dest`
+    /// names the result unless the call returns `void`.
+    fn call(&mut self, dest: Option<&str>, ret: &str, callee: &str, args: &[(&str, &str)]) {
+        self.push(Inst::Call {
+            dest: dest.map(str::to_string),
+            ret: ret.to_string(),
+            callee: callee.to_string(),
+            args: args
+                .iter()
+                .map(|(ty, value)| (ty.to_string(), value.to_string()))
+                .collect(),
+            variadic: None,
+        });
+    }
+
+    /// `getelementptr` into the `ty` at `ptr`, each index a type and a
+    /// value.
+    fn gep(&mut self, dest: &str, ty: &str, ptr: &str, indices: &[(&str, &str)]) {
+        self.push_gep(false, dest, ty, ptr, indices);
+    }
+
+    /// `getelementptr inbounds`, for an address known to be in the object.
+    fn gep_inbounds(&mut self, dest: &str, ty: &str, ptr: &str, indices: &[(&str, &str)]) {
+        self.push_gep(true, dest, ty, ptr, indices);
+    }
+
+    fn push_gep(
+        &mut self,
+        inbounds: bool,
+        dest: &str,
+        ty: &str,
+        ptr: &str,
+        indices: &[(&str, &str)],
+    ) {
+        self.push(Inst::Gep {
+            dest: dest.to_string(),
+            inbounds,
+            ty: ty.to_string(),
+            ptr: ptr.to_string(),
+            indices: indices
+                .iter()
+                .map(|(ty, index)| (ty.to_string(), index.to_string()))
+                .collect(),
+        });
+    }
+
+    fn ret(&mut self, ty: &str, value: &str) {
+        self.push(Inst::Ret(Some((ty.to_string(), value.to_string()))));
     }
 
-    fn escape_string(&self, s: &str) -> String {
+    fn builder(&mut self) -> &mut FunctionBuilder {
+        self.function
+            .as_mut()
+            .expect("instructions are only generated inside a function")
+    }
+
+    /// Whether the current block has ended, so that nothing after it runs.
+    fn block_terminated(&self) -> bool {
+        self.function
+            .as_ref()
+            .is_some_and(FunctionBuilder::is_terminated)
+    }
+
+    /// Start a function with the given `define ... {` line.
+    fn begin_function(&mut self, header: String) {
+        self.function = Some(FunctionBuilder::new(header));
+    }
+
+    /// Write out the function begun last.
+    fn end_function(&mut self) {
+        if let Some(function) = self.function.take() {
+            self.output.push_str(&function.finish());
+        }
+    }
+
+    fn escape_bytes(bytes: &[u8]) -> String {
         let mut escaped = String::new();
-        for c in s.bytes() {
+        for &c in bytes {
             match c {
                 b'\n' => escaped.push_str("\\0A"),
                 b'\r' => escaped.push_str("\\0D"),
@@ -3206,7 +10881,36 @@ impl CodeGenerator {
         format!(
             "target triple = \"{}\"\n\n{}",
             get_target_triple(),
-            self.output
+            quote_unicode_names(&self.output)
         )
     }
 }
+
+/// Names made from non-ASCII identifiers (`café`) aren't valid bare LLVM
+/// names, so quote them: `%"café"`, `@"brn_größe"`. String constants are
+/// escaped byte by byte, so raw non-ASCII text in the IR is always part of
+/// a name.
+fn quote_unicode_names(ir: &str) -> String {
+    if ir.is_ascii() {
+        return ir.to_string();
+    }
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || "-$._".contains(c) || !c.is_ascii();
+    let mut out = String::with_capacity(ir.len());
+    let mut rest = ir;
+    while let Some(sigil) = rest.find(['%', '@']) {
+        out.push_str(&rest[..=sigil]);
+        rest = &rest[sigil + 1..];
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let name = &rest[..end];
+        if name.is_ascii() {
+            out.push_str(name);
+        } else {
+            out.push('"');
+            out.push_str(name);
+            out.push('"');
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,59 @@
+//! `--emit=depgraph`: which files a program is built from, as JSON, for
+//! build systems, editors and CI caches.
+
+use crate::diagnostic::json_string;
+use crate::module::ModuleCache;
+use std::fs;
+
+/// The program made of the root files `roots` and every module in `cache`
+/// once its imports were resolved, on a single line:
+///
+/// ```json
+/// {"roots":["/src/main.brn"],"imports":["/src/util.brn"],
+///  "modules":[{"path":"/src/util.brn","exports":["helper"],"imports":[]}]}
+/// ```
+///
+/// Paths are canonical and modules sorted by path. Each `imports` lists
+/// the modules imported directly, in source order; the program's include
+/// `std/prelude.brn`, which it imports implicitly.
+pub fn to_json(roots: &[String], cache: &ModuleCache) -> String {
+    let roots: Vec<String> = roots
+        .iter()
+        .map(|root| {
+            fs::canonicalize(root)
+                .map_or_else(|_| root.clone(), |p| p.to_string_lossy().to_string())
+        })
+        .collect();
+
+    let mut paths: Vec<&String> = cache.module_paths().collect();
+    paths.sort();
+    let modules: Vec<String> = paths
+        .into_iter()
+        .filter_map(|path| {
+            let module = cache.module(path)?;
+            let mut exports: Vec<&String> = module.exported_names.iter().collect();
+            exports.sort();
+            Some(format!(
+                "{{\"path\":{},\"exports\":{},\"imports\":{}}}",
+                json_string(path),
+                json_array(exports),
+                json_array(&module.imports)
+            ))
+        })
+        .collect();
+
+    format!(
+        "{{\"roots\":{},\"imports\":{},\"modules\":[{}]}}\n",
+        json_array(&roots),
+        json_array(cache.program_imports()),
+        modules.join(",")
+    )
+}
+
+fn json_array<S: AsRef<str>>(items: impl IntoIterator<Item = S>) -> String {
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|item| json_string(item.as_ref()))
+        .collect();
+    format!("[{}]", items.join(","))
+}
@@ -0,0 +1,363 @@
+use crate::lexer::Span;
+use std::fs;
+use std::io::IsTerminal;
+use std::ops::{Deref, DerefMut};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    /// Reported without stopping the build, as lints are.
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A single compiler message with an optional source position.
+///
+/// `line == 0` means the diagnostic is not tied to a location (e.g. a file
+/// that could not be read), in which case no snippet is rendered.
+///
+/// Every compiler stage returns it as its error, so its fields live behind
+/// a `Box` to keep `Result<T, Diagnostic>` small.
+#[derive(Debug, Clone)]
+pub struct Diagnostic(Box<DiagnosticData>);
+
+/// What a `Diagnostic` says; its fields are read and set through it.
+#[derive(Debug, Clone)]
+pub struct DiagnosticData {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    /// Set by `at_span`: how many bytes of source to underline. Rendering
+    /// turns it into `length` characters, stopping at the end of the line.
+    pub span_bytes: Option<usize>,
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic(Box::new(DiagnosticData {
+            severity,
+            code: None,
+            message: message.into(),
+            file: String::new(),
+            line: 0,
+            column: 0,
+            length: 1,
+            span_bytes: None,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+        }))
+    }
+
+    pub fn at(mut self, file: &str, line: usize, column: usize) -> Self {
+        self.file = file.to_string();
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    /// Point at `span`, underlining all of it that is on its first line.
+    pub fn at_span(self, span: Span) -> Self {
+        let mut diagnostic = self.at(span.file, span.line, span.column);
+        diagnostic.span_bytes = Some(span.end - span.start);
+        diagnostic
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length.max(1);
+        self.span_bytes = None;
+        self
+    }
+
+    /// How many characters of `line_text` to underline.
+    fn underline_length(&self, line_text: Option<&str>) -> usize {
+        let (Some(bytes), Some(text)) = (self.span_bytes, line_text) else {
+            return self.length;
+        };
+        let rest = text
+            .char_indices()
+            .nth(self.column.saturating_sub(1))
+            .map_or("", |(i, _)| &text[i..]);
+        rest.char_indices()
+            .take_while(|(i, _)| *i < bytes)
+            .count()
+            .max(1)
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Render using the file on disk as the snippet source.
+    pub fn render(&self) -> String {
+        let source = if self.line > 0 {
+            fs::read_to_string(&self.file).ok()
+        } else {
+            None
+        };
+        self.render_with_source(source.as_deref())
+    }
+
+    pub fn render_with_source(&self, source: Option<&str>) -> String {
+        let style = Style::detect();
+        let mut out = String::new();
+
+        let sev_color = match self.severity {
+            Severity::Error => style.red,
+            Severity::Warning => style.yellow,
+        };
+        let code = self.code.map(|c| format!("[{}]", c)).unwrap_or_default();
+        out.push_str(&format!(
+            "{}{}{}{}{}: {}{}{}\n",
+            style.bold,
+            sev_color,
+            self.severity.as_str(),
+            code,
+            style.reset,
+            style.bold,
+            self.message,
+            style.reset
+        ));
+
+        if self.line == 0 {
+            if !self.file.is_empty() {
+                out.push_str(&format!(
+                    "  {}{}-->{} {}\n",
+                    style.bold, style.blue, style.reset, self.file
+                ));
+            }
+            self.render_footer(&mut out, &style, 2);
+            return out;
+        }
+
+        let gutter = self.line.to_string().len().max(2);
+        out.push_str(&format!(
+            "{:width$}{}{}-->{} {}:{}:{}\n",
+            "",
+            style.bold,
+            style.blue,
+            style.reset,
+            self.file,
+            self.line,
+            self.column,
+            width = gutter
+        ));
+
+        let line_text = source.and_then(|s| s.lines().nth(self.line - 1));
+        if let Some(text) = line_text {
+            let pipe = format!("{}{}|{}", style.bold, style.blue, style.reset);
+            out.push_str(&format!("{:width$} {}\n", "", pipe, width = gutter));
+            out.push_str(&format!(
+                "{}{}{:>width$}{} {} {}\n",
+                style.bold,
+                style.blue,
+                self.line,
+                style.reset,
+                pipe,
+                text,
+                width = gutter
+            ));
+
+            // Tabs in the source line are preserved in the padding so the
+            // caret stays aligned regardless of the terminal's tab width.
+            let padding: String = text
+                .chars()
+                .take(self.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let label = self
+                .label
+                .as_ref()
+                .map(|l| format!(" {}", l))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "{:width$} {} {}{}{}{}{}{}\n",
+                "",
+                pipe,
+                padding,
+                style.bold,
+                sev_color,
+                "^".repeat(self.underline_length(Some(text))),
+                label,
+                style.reset,
+                width = gutter
+            ));
+            if !self.notes.is_empty() || self.help.is_some() {
+                out.push_str(&format!("{:width$} {}\n", "", pipe, width = gutter));
+            }
+        }
+
+        self.render_footer(&mut out, &style, gutter);
+        out
+    }
+
+    /// Serialize as a single-line JSON object. Positions are 1-based; the
+    /// range ends on the same line, `length` columns after the start.
+    pub fn to_json(&self) -> String {
+        let code = match self.code {
+            Some(c) => json_string(c),
+            None => "null".to_string(),
+        };
+        let range = if self.line == 0 {
+            "null".to_string()
+        } else {
+            let source = self
+                .span_bytes
+                .and_then(|_| fs::read_to_string(&self.file).ok());
+            let line_text = source.as_deref().and_then(|s| s.lines().nth(self.line - 1));
+            format!(
+                "{{\"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}}}",
+                self.line,
+                self.column,
+                self.line,
+                self.column + self.underline_length(line_text)
+            )
+        };
+        let notes: Vec<String> = self.notes.iter().map(|n| json_string(n)).collect();
+        let opt = |v: &Option<String>| match v {
+            Some(s) => json_string(s),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"file\":{},\"range\":{},\"severity\":{},\"code\":{},\"message\":{},\"label\":{},\"notes\":[{}],\"help\":{}}}",
+            json_string(&self.file),
+            range,
+            json_string(self.severity.as_str()),
+            code,
+            json_string(&self.message),
+            opt(&self.label),
+            notes.join(","),
+            opt(&self.help)
+        )
+    }
+
+    fn render_footer(&self, out: &mut String, style: &Style, gutter: usize) {
+        for note in &self.notes {
+            out.push_str(&format!(
+                "{:width$} {}={} {}note{}: {}\n",
+                "",
+                style.blue,
+                style.reset,
+                style.bold,
+                style.reset,
+                note,
+                width = gutter
+            ));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!(
+                "{:width$} {}={} {}help{}: {}\n",
+                "",
+                style.blue,
+                style.reset,
+                style.bold,
+                style.reset,
+                help,
+                width = gutter
+            ));
+        }
+    }
+}
+
+impl Deref for Diagnostic {
+    type Target = DiagnosticData;
+
+    fn deref(&self) -> &DiagnosticData {
+        &self.0
+    }
+}
+
+impl DerefMut for Diagnostic {
+    fn deref_mut(&mut self) -> &mut DiagnosticData {
+        &mut self.0
+    }
+}
+
+struct Style {
+    bold: &'static str,
+    red: &'static str,
+    yellow: &'static str,
+    blue: &'static str,
+    reset: &'static str,
+}
+
+impl Style {
+    /// Colors are emitted only when stderr is a terminal and `NO_COLOR` is
+    /// unset, so redirected output and CI logs stay plain text.
+    fn detect() -> Self {
+        if std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal() {
+            Style {
+                bold: "\x1b[1m",
+                red: "\x1b[31m",
+                yellow: "\x1b[33m",
+                blue: "\x1b[34m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Style {
+                bold: "",
+                red: "",
+                yellow: "",
+                blue: "",
+                reset: "",
+            }
+        }
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
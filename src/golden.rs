@@ -0,0 +1,210 @@
+//! Golden-IR tests: compile a directory of `.brn` fixtures and compare the
+//! IR each one produces with the expected output checked in next to it
+//! (`loop.brn` → `loop.expected.ll`), so that a change to the code
+//! generator shows up as a diff to review.
+//!
+//! Every program carries the same runtime, which would drown the code the
+//! fixture is about, so the IR is normalized first: any top-level item —
+//! a function definition, a declaration, a global, a type — that appears
+//! unchanged in the IR of an empty program is left out. What remains is the
+//! code the fixture's source produced, plus the pieces of the runtime whose
+//! text depends on it, such as `@main`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{Compiler, Diagnostic};
+
+/// What a fixture's expected IR is saved as, in place of `.brn`.
+pub const EXPECTED_SUFFIX: &str = ".expected.ll";
+
+/// Lines of unchanged IR shown around each difference.
+const CONTEXT: usize = 2;
+
+const EMPTY_PROGRAM: &str = "fn main() {\n}\n";
+
+pub enum Outcome {
+    /// The IR matched the expected output.
+    Passed,
+    /// The expected output was written, or rewritten, from this IR.
+    Blessed,
+    /// There is no expected output to compare with.
+    Missing,
+    /// The IR differs from the expected output: a line diff, `-` for the
+    /// expected side and `+` for the IR produced now.
+    Mismatch(String),
+    /// The fixture did not compile.
+    Failed(Vec<Diagnostic>),
+}
+
+pub struct FixtureResult {
+    pub fixture: PathBuf,
+    pub outcome: Outcome,
+}
+
+/// The `.brn` files directly inside `dir`, sorted. Subdirectories are left
+/// alone, so fixtures can keep the modules they import there.
+pub fn fixtures(dir: &Path) -> Result<Vec<PathBuf>, Diagnostic> {
+    let entries = fs::read_dir(dir).map_err(|e| {
+        Diagnostic::error(format!(
+            "could not read directory '{}': {}",
+            dir.display(),
+            e
+        ))
+        .with_code("E0001")
+    })?;
+    let mut fixtures: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "brn"))
+        .collect();
+    fixtures.sort();
+    Ok(fixtures)
+}
+
+/// Where the expected IR of `fixture` is kept.
+pub fn expected_path(fixture: &Path) -> PathBuf {
+    let stem = fixture.file_stem().unwrap_or_default().to_string_lossy();
+    fixture.with_file_name(format!("{}{}", stem, EXPECTED_SUFFIX))
+}
+
+/// Compile every fixture in `dir` with `compiler` and compare its IR with
+/// the expected output. With `bless`, a fixture whose IR differs or that
+/// has no expected output yet gets it written instead.
+pub fn run(compiler: &Compiler, dir: &Path, bless: bool) -> Result<Vec<FixtureResult>, Diagnostic> {
+    let baseline_file = dir.join("<empty>.brn");
+    let baseline = compiler
+        .compile_source(EMPTY_PROGRAM, &baseline_file.to_string_lossy())
+        .map_err(|mut diagnostics| diagnostics.remove(0))?
+        .llvm_ir;
+
+    let mut results = Vec::new();
+    for fixture in fixtures(dir)? {
+        let outcome = match compiler.compile_file(&fixture.to_string_lossy()) {
+            Ok(artifacts) => {
+                let actual = normalize(&artifacts.llvm_ir, &baseline);
+                check(&expected_path(&fixture), &actual, bless)?
+            }
+            Err(diagnostics) => Outcome::Failed(diagnostics),
+        };
+        results.push(FixtureResult { fixture, outcome });
+    }
+    Ok(results)
+}
+
+fn check(expected_file: &Path, actual: &str, bless: bool) -> Result<Outcome, Diagnostic> {
+    let expected = fs::read_to_string(expected_file).ok();
+    if expected.as_deref() == Some(actual) {
+        return Ok(Outcome::Passed);
+    }
+    if bless {
+        fs::write(expected_file, actual).map_err(|e| {
+            Diagnostic::error(format!(
+                "could not write '{}': {}",
+                expected_file.display(),
+                e
+            ))
+            .with_code("E0001")
+        })?;
+        return Ok(Outcome::Blessed);
+    }
+    Ok(match expected {
+        Some(expected) => Outcome::Mismatch(diff(&expected, actual)),
+        None => Outcome::Missing,
+    })
+}
+
+/// `ir` without the top-level items it shares with `baseline`, one item
+/// per line and a blank line before each function definition.
+pub fn normalize(ir: &str, baseline: &str) -> String {
+    let common: HashSet<String> = items(baseline).into_iter().collect();
+    let mut normalized = String::new();
+    for item in items(ir) {
+        if common.contains(&item) {
+            continue;
+        }
+        if item.starts_with("define ") && !normalized.is_empty() {
+            normalized.push('\n');
+        }
+        normalized.push_str(&item);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// The top-level items of a module: each function definition from its
+/// `define` line to its closing brace, and every other non-blank line.
+fn items(ir: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut lines = ir.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let mut item = line.to_string();
+        if line.starts_with("define ") && line.ends_with('{') {
+            for line in lines.by_ref() {
+                let line = line.trim_end();
+                item.push('\n');
+                item.push_str(line);
+                if line == "}" {
+                    break;
+                }
+            }
+        }
+        items.push(item);
+    }
+    items
+}
+
+/// A line diff of `expected` against `actual`, with runs of unchanged lines
+/// far from any change cut short.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    // lcs[i][j]: the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&k| lines[k].0 != ' ').collect();
+    let near_change = |k: usize| changed.iter().any(|&c| c.abs_diff(k) <= CONTEXT);
+    let mut out = String::new();
+    let mut skipping = false;
+    for (k, (mark, line)) in lines.iter().enumerate() {
+        if near_change(k) {
+            out.push_str(&format!("{} {}\n", mark, line));
+            skipping = false;
+        } else if !skipping {
+            out.push_str("  ...\n");
+            skipping = true;
+        }
+    }
+    out
+}
@@ -0,0 +1,341 @@
+//! A function's LLVM IR as instructions, built up block by block.
+//!
+//! The code generator hands each instruction of the function it is
+//! generating to a [`FunctionBuilder`], which keeps track of the block it
+//! goes into: a label opens a block and a terminator (`br`, `ret`, ...)
+//! closes it, so whether the current block still needs one is a question
+//! for the builder rather than a flag to keep in step by hand. A label
+//! reached by falling off the end of a block gets the branch to it, and
+//! code that arrives after a terminator gets a fresh, unreachable block of
+//! its own, instead of either making the IR malformed. The text of the function is only
+//! produced at the end, by [`FunctionBuilder::finish`].
+//!
+//! Control flow, memory, arithmetic, comparisons, casts and calls have
+//! their own [`Inst`] variants, which also record the type of the value
+//! they define. What's left (`phi`, `select`, volatile and atomic memory
+//! access) is written as text ([`Inst::Text`]); it only needs to say
+//! whether it ends its block. The runtime's hand-written functions go
+//! straight into the module as text, outside any builder.
+
+use std::collections::HashMap;
+use std::fmt;
+
+pub enum Inst {
+    Label(String),
+    Alloca {
+        dest: String,
+        ty: String,
+    },
+    Load {
+        dest: String,
+        ty: String,
+        ptr: String,
+    },
+    Store {
+        ty: String,
+        value: String,
+        ptr: String,
+    },
+    /// `add`, `sub`, `mul`, `and`, ...
+    Binary {
+        dest: String,
+        op: &'static str,
+        ty: String,
+        lhs: String,
+        rhs: String,
+    },
+    Icmp {
+        dest: String,
+        pred: &'static str,
+        ty: String,
+        lhs: String,
+        rhs: String,
+    },
+    /// `zext`, `trunc`, `bitcast`, ...
+    Cast {
+        dest: String,
+        op: &'static str,
+        from: String,
+        value: String,
+        to: String,
+    },
+    Call {
+        dest: Option<String>,
+        ret: String,
+        callee: String,
+        args: Vec<(String, String)>,
+        /// A variadic callee's fixed parameter types: the call then names
+        /// its whole function type, `i32 (i8*, ...)`.
+        variadic: Option<Vec<String>>,
+    },
+    /// `getelementptr`, the address of an element of the `ty` at `ptr`;
+    /// each index is a type and a value.
+    Gep {
+        dest: String,
+        inbounds: bool,
+        ty: String,
+        ptr: String,
+        indices: Vec<(String, String)>,
+    },
+    Br(String),
+    CondBr {
+        cond: String,
+        then_label: String,
+        else_label: String,
+    },
+    /// A `switch` on `value`, going to `default` for anything no case
+    /// lists.
+    Switch {
+        ty: String,
+        value: String,
+        default: String,
+        cases: Vec<(i64, String)>,
+    },
+    /// `ret void`, or `ret <type> <value>`.
+    Ret(Option<(String, String)>),
+    Unreachable,
+    /// An instruction written out in full.
+    Text {
+        text: String,
+        terminator: bool,
+    },
+}
+
+impl Inst {
+    /// One line of IR as an instruction: a label, or text that ends its
+    /// block if it starts with a terminator.
+    pub fn from_text(line: &str) -> Inst {
+        if !line.starts_with(' ')
+            && let Some(label) = line.strip_suffix(':')
+        {
+            return Inst::Label(label.to_string());
+        }
+        let op = line.trim_start();
+        let terminator = [
+            "br ",
+            "ret ",
+            "unreachable",
+            "resume ",
+            "indirectbr ",
+            "switch ",
+        ]
+        .iter()
+        .any(|t| op.starts_with(t));
+        Inst::Text {
+            text: line.to_string(),
+            terminator,
+        }
+    }
+
+    pub fn is_terminator(&self) -> bool {
+        match self {
+            Inst::Br(_)
+            | Inst::CondBr { .. }
+            | Inst::Switch { .. }
+            | Inst::Ret(_)
+            | Inst::Unreachable => true,
+            Inst::Text { terminator, .. } => *terminator,
+            _ => false,
+        }
+    }
+
+    /// The value this instruction defines, and its type.
+    fn defines(&self) -> Option<(&str, String)> {
+        match self {
+            Inst::Alloca { dest, ty } => Some((dest, format!("{}*", ty))),
+            Inst::Load { dest, ty, .. } | Inst::Binary { dest, ty, .. } => Some((dest, ty.clone())),
+            Inst::Icmp { dest, .. } => Some((dest, "i1".to_string())),
+            Inst::Cast { dest, to, .. } => Some((dest, to.clone())),
+            Inst::Call {
+                dest: Some(dest),
+                ret,
+                ..
+            } => Some((dest, ret.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::Label(label) => write!(f, "{}:", label),
+            Inst::Alloca { dest, ty } => write!(f, "  {} = alloca {}", dest, ty),
+            Inst::Load { dest, ty, ptr } => write!(f, "  {} = load {}, {}* {}", dest, ty, ty, ptr),
+            Inst::Store { ty, value, ptr } => {
+                write!(f, "  store {} {}, {}* {}", ty, value, ty, ptr)
+            }
+            Inst::Binary {
+                dest,
+                op,
+                ty,
+                lhs,
+                rhs,
+            } => write!(f, "  {} = {} {} {}, {}", dest, op, ty, lhs, rhs),
+            Inst::Icmp {
+                dest,
+                pred,
+                ty,
+                lhs,
+                rhs,
+            } => write!(f, "  {} = icmp {} {} {}, {}", dest, pred, ty, lhs, rhs),
+            Inst::Cast {
+                dest,
+                op,
+                from,
+                value,
+                to,
+            } => write!(f, "  {} = {} {} {} to {}", dest, op, from, value, to),
+            Inst::Call {
+                dest,
+                ret,
+                callee,
+                args,
+                variadic,
+            } => {
+                let args: Vec<String> = args.iter().map(|(t, v)| format!("{} {}", t, v)).collect();
+                match dest {
+                    Some(dest) => write!(f, "  {} = ", dest)?,
+                    None => write!(f, "  ")?,
+                }
+                write!(f, "call {} ", ret)?;
+                if let Some(params) = variadic {
+                    let mut params = params.clone();
+                    params.push("...".to_string());
+                    write!(f, "({}) ", params.join(", "))?;
+                }
+                write!(f, "{}({})", callee, args.join(", "))
+            }
+            Inst::Gep {
+                dest,
+                inbounds,
+                ty,
+                ptr,
+                indices,
+            } => {
+                let inbounds = if *inbounds { "inbounds " } else { "" };
+                write!(
+                    f,
+                    "  {} = getelementptr {}{}, {}* {}",
+                    dest, inbounds, ty, ty, ptr
+                )?;
+                for (ty, index) in indices {
+                    write!(f, ", {} {}", ty, index)?;
+                }
+                Ok(())
+            }
+            Inst::Br(label) => write!(f, "  br label %{}", label),
+            Inst::CondBr {
+                cond,
+                then_label,
+                else_label,
+            } => write!(
+                f,
+                "  br i1 {}, label %{}, label %{}",
+                cond, then_label, else_label
+            ),
+            Inst::Switch {
+                ty,
+                value,
+                default,
+                cases,
+            } => {
+                write!(f, "  switch {} {}, label %{} [", ty, value, default)?;
+                for (case, label) in cases {
+                    write!(f, "\n    {} {}, label %{}", ty, case, label)?;
+                }
+                write!(f, "\n  ]")
+            }
+            Inst::Ret(None) => write!(f, "  ret void"),
+            Inst::Ret(Some((ty, value))) => write!(f, "  ret {} {}", ty, value),
+            Inst::Unreachable => write!(f, "  unreachable"),
+            Inst::Text { text, .. } => write!(f, "{}", text),
+        }
+    }
+}
+
+/// The function being generated.
+pub struct FunctionBuilder {
+    header: String,
+    insts: Vec<Inst>,
+    /// Where the entry block takes more `alloca`s: after the ones already
+    /// there.
+    entry_allocas: usize,
+    terminated: bool,
+    /// Blocks opened for code that follows a terminator.
+    dead_blocks: usize,
+    /// The types of the values defined by typed instructions.
+    types: HashMap<String, String>,
+}
+
+impl FunctionBuilder {
+    /// A function with the given `define ... {` line, positioned in its
+    /// `entry` block.
+    pub fn new(header: impl Into<String>) -> Self {
+        FunctionBuilder {
+            header: header.into(),
+            insts: vec![Inst::Label("entry".to_string())],
+            entry_allocas: 1,
+            terminated: false,
+            dead_blocks: 0,
+            types: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, inst: Inst) {
+        match &inst {
+            // LLVM has no falling through into the next block
+            Inst::Label(label) if !self.terminated => {
+                self.insts.push(Inst::Br(label.clone()));
+            }
+            Inst::Label(_) => {}
+            _ if self.terminated => {
+                let label = format!("dead.{}", self.dead_blocks);
+                self.dead_blocks += 1;
+                self.insts.push(Inst::Label(label));
+                self.terminated = false;
+            }
+            _ => {}
+        }
+        if let Some((dest, ty)) = inst.defines() {
+            self.types.insert(dest.to_string(), ty);
+        }
+        self.terminated = inst.is_terminator();
+        self.insts.push(inst);
+    }
+
+    /// Whether the current block has its terminator; anything pushed now
+    /// goes into a block nothing branches to.
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// An `alloca` at the top of the entry block, where it runs once per
+    /// call wherever the code needing it is.
+    pub fn alloca_in_entry(&mut self, dest: impl Into<String>, ty: impl Into<String>) {
+        let (dest, ty) = (dest.into(), ty.into());
+        self.types.insert(dest.clone(), format!("{}*", ty));
+        self.insts
+            .insert(self.entry_allocas, Inst::Alloca { dest, ty });
+        self.entry_allocas += 1;
+    }
+
+    /// The type of `value`, if a typed instruction defined it.
+    pub fn type_of(&self, value: &str) -> Option<&str> {
+        self.types.get(value).map(String::as_str)
+    }
+
+    /// The function as text, from its `define` line to its closing brace.
+    pub fn finish(self) -> String {
+        let mut text = String::new();
+        text.push('\n');
+        text.push_str(&self.header);
+        text.push('\n');
+        for inst in &self.insts {
+            text.push_str(&inst.to_string());
+            text.push('\n');
+        }
+        text.push_str("}\n");
+        text
+    }
+}
@@ -1,3 +1,7 @@
+use crate::diagnostic::Diagnostic;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Keywords
@@ -17,10 +21,13 @@ pub enum TokenType {
     Continue,
     True,
     False,
+    Null,
     Export,
     Import,
     From,
     Unsafe,
+    Extern,
+    Impl,
 
     // Types
     IntType,
@@ -30,9 +37,11 @@ pub enum TokenType {
 
     // Literals
     Number(i64),
+    /// The literal's value with escapes decoded, so unlike identifiers it
+    /// can't point into the source.
     StringLit(String),
     CharLit(char),
-    Identifier(String),
+    Identifier(Symbol),
 
     // Operators
     Plus,
@@ -66,40 +75,129 @@ pub enum TokenType {
     Arrow,
     FatArrow,
     DotDot,
+    /// `...`, ending a variadic `extern fn`'s parameters.
+    Ellipsis,
+    /// `@`, which starts a directive like `@when`.
+    At,
+    /// `#`, which starts an attribute like `#[inline]`.
+    Hash,
 
     // Special
     Eof,
 }
 
+/// A range of source text: bytes `start..end` of `file`, and the line and
+/// column `start` is at, for messages.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub file: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The spanned text of `source`.
+    pub fn text(self, source: &str) -> &str {
+        &source[self.start..self.end]
+    }
+
+    /// From the start of `self` to the end of `last`.
+    pub fn to(self, last: Span) -> Span {
+        Span {
+            end: last.end.max(self.start),
+            ..self
+        }
+    }
+}
+
+thread_local! {
+    static FILE_NAMES: RefCell<HashSet<&'static str>> = RefCell::new(HashSet::new());
+}
+
+/// `name` as a `'static` string spans can copy around freely. Each distinct
+/// file name is stored once, however often it is lexed.
+pub fn intern_file(name: &str) -> &'static str {
+    FILE_NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(interned) = names.get(name) {
+            return *interned;
+        }
+        let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+        names.insert(interned);
+        interned
+    })
+}
+
+/// An identifier, interned: the same name always gets the same symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// The identifier names of one source file, each stored once as a slice of
+/// the source rather than copied per token.
+#[derive(Default)]
+pub struct Interner<'a> {
+    symbols: HashMap<&'a str, Symbol>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> Interner<'a> {
+    pub fn intern(&mut self, name: &'a str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &'a str {
+        self.names[symbol.0 as usize]
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
+}
+
+/// What [`Lexer::tokenize`] produces: the tokens, the names their
+/// identifiers refer to, and the source their spans index into.
+pub struct Tokens<'a> {
+    pub tokens: Vec<Token>,
+    pub interner: Interner<'a>,
+    pub source: &'a str,
 }
 
 pub struct Lexer<'a> {
-    source: &'a str,
     filename: &'a str,
-    chars: Vec<char>,
-    current: usize,
+    /// `filename`, interned for spans.
+    file: &'static str,
+    source: &'a str,
+    /// Byte offset of the next character.
+    pos: usize,
     line: usize,
     column: usize,
+    interner: Interner<'a>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str, filename: &'a str) -> Self {
         Lexer {
-            source,
             filename,
-            chars: source.chars().collect(),
-            current: 0,
+            file: intern_file(filename),
+            source,
+            pos: 0,
             line: 1,
             column: 1,
+            interner: Interner::default(),
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokenize(&mut self) -> Result<Tokens<'a>, Diagnostic> {
         let mut tokens = Vec::new();
 
         while !self.is_at_end() {
@@ -115,16 +213,26 @@ impl<'a> Lexer<'a> {
 
         tokens.push(Token {
             token_type: TokenType::Eof,
-            line: self.line,
-            column: self.column,
+            span: Span {
+                file: self.file,
+                start: self.pos,
+                end: self.pos,
+                line: self.line,
+                column: self.column,
+            },
         });
 
-        Ok(tokens)
+        Ok(Tokens {
+            tokens,
+            interner: std::mem::take(&mut self.interner),
+            source: self.source,
+        })
     }
 
-    fn next_token(&mut self) -> Result<Token, String> {
+    fn next_token(&mut self) -> Result<Token, Diagnostic> {
         let line = self.line;
         let column = self.column;
+        let start = self.pos;
         let ch = self.peek();
 
         let token_type = match ch {
@@ -153,6 +261,14 @@ impl<'a> Lexer<'a> {
                 self.advance();
                 TokenType::Percent
             }
+            '@' => {
+                self.advance();
+                TokenType::At
+            }
+            '#' => {
+                self.advance();
+                TokenType::Hash
+            }
             '=' => {
                 self.advance();
                 if self.peek() == '=' {
@@ -252,14 +368,19 @@ impl<'a> Lexer<'a> {
                 self.advance();
                 if self.peek() == '.' {
                     self.advance();
-                    TokenType::DotDot
+                    if self.peek() == '.' {
+                        self.advance();
+                        TokenType::Ellipsis
+                    } else {
+                        TokenType::DotDot
+                    }
                 } else {
                     TokenType::Dot
                 }
             }
             '"' => self.read_string()?,
             '\'' => self.read_char()?,
-            _ if ch.is_ascii_digit() => self.read_number(),
+            _ if ch.is_ascii_digit() => self.read_number()?,
             _ if ch.is_alphabetic() || ch == '_' => self.read_identifier(),
             _ => {
                 return Err(self.error_with_context(&format!("Unexpected character '{}'", ch)));
@@ -268,12 +389,17 @@ impl<'a> Lexer<'a> {
 
         Ok(Token {
             token_type,
-            line,
-            column,
+            span: Span {
+                file: self.file,
+                start,
+                end: self.pos,
+                line,
+                column,
+            },
         })
     }
 
-    fn read_string(&mut self) -> Result<TokenType, String> {
+    fn read_string(&mut self) -> Result<TokenType, Diagnostic> {
         self.advance();
         let mut value = String::new();
 
@@ -310,7 +436,7 @@ impl<'a> Lexer<'a> {
         Ok(TokenType::StringLit(value))
     }
 
-    fn read_char(&mut self) -> Result<TokenType, String> {
+    fn read_char(&mut self) -> Result<TokenType, Diagnostic> {
         self.advance();
 
         if self.is_at_end() {
@@ -348,29 +474,41 @@ impl<'a> Lexer<'a> {
         Ok(TokenType::CharLit(ch))
     }
 
-    fn read_number(&mut self) -> TokenType {
-        let mut value = String::new();
+    fn read_number(&mut self) -> Result<TokenType, Diagnostic> {
+        let column = self.column;
+        let start = self.pos;
 
         while !self.is_at_end() && self.peek().is_ascii_digit() {
-            value.push(self.advance());
+            self.advance();
+        }
+        let value = &self.source[start..self.pos];
+
+        match value.parse() {
+            Ok(n) => Ok(TokenType::Number(n)),
+            Err(_) => Err(Diagnostic::error(format!(
+                "Integer literal '{}' does not fit in 64 bits",
+                value
+            ))
+            .at(self.filename, self.line, column)
+            .with_code("E0101")
+            .with_length(value.len())),
         }
-
-        TokenType::Number(value.parse().unwrap())
     }
 
     fn read_identifier(&mut self) -> TokenType {
-        let mut value = String::new();
+        let start = self.pos;
 
         while !self.is_at_end() {
             let ch = self.peek();
             if ch.is_alphanumeric() || ch == '_' {
-                value.push(self.advance());
+                self.advance();
             } else {
                 break;
             }
         }
+        let value = &self.source[start..self.pos];
 
-        match value.as_str() {
+        match value {
             "let" => TokenType::Let,
             "mut" => TokenType::Mut,
             "fn" => TokenType::Fn,
@@ -387,15 +525,18 @@ impl<'a> Lexer<'a> {
             "continue" => TokenType::Continue,
             "true" => TokenType::True,
             "false" => TokenType::False,
+            "null" => TokenType::Null,
             "export" => TokenType::Export,
             "import" => TokenType::Import,
             "from" => TokenType::From,
             "unsafe" => TokenType::Unsafe,
+            "extern" => TokenType::Extern,
+            "impl" => TokenType::Impl,
             "int" => TokenType::IntType,
             "bool" => TokenType::BoolType,
             "string" => TokenType::StringType,
             "char" => TokenType::CharType,
-            _ => TokenType::Identifier(value),
+            _ => TokenType::Identifier(self.interner.intern(value)),
         }
     }
 
@@ -420,95 +561,30 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn error_with_context(&self, message: &str) -> String {
-        let lines: Vec<&str> = self.source.lines().collect();
-        let current_line = lines.get(self.line - 1).unwrap_or(&"");
-
-        let line_num_width = self.line.to_string().len().max(3);
-
-        let mut error = String::new();
-
-        error.push_str(&format!("\x1b[1m\x1b[31merror\x1b[0m: {}\n", message));
-
-        error.push_str(&format!(
-            "  \x1b[1m\x1b[34m-->\x1b[0m {}:{}:{}\n",
-            self.filename, self.line, self.column
-        ));
-
-        error.push_str(&format!(
-            "{:width$} \x1b[1m\x1b[34m|\x1b[0m\n",
-            "",
-            width = line_num_width
-        ));
-
-        if self.line > 1
-            && let Some(prev_line) = lines.get(self.line - 2)
-        {
-            error.push_str(&format!(
-                "\x1b[1m\x1b[34m{:width$} |\x1b[0m {}\n",
-                self.line - 1,
-                prev_line,
-                width = line_num_width
-            ));
-        }
-
-        error.push_str(&format!(
-            "\x1b[1m\x1b[34m{:width$} |\x1b[0m {}\n",
-            self.line,
-            current_line,
-            width = line_num_width
-        ));
-
-        error.push_str(&format!(
-            "{:width$} \x1b[1m\x1b[34m|\x1b[0m {}\x1b[1m\x1b[31m^\x1b[0m\n",
-            "",
-            " ".repeat(self.column - 1),
-            width = line_num_width
-        ));
-
-        if let Some(next_line) = lines.get(self.line) {
-            error.push_str(&format!(
-                "\x1b[1m\x1b[34m{:width$} |\x1b[0m {}\n",
-                self.line + 1,
-                next_line,
-                width = line_num_width
-            ));
-        }
-
-        error.push_str(&format!(
-            "{:width$} \x1b[1m\x1b[34m|\x1b[0m\n",
-            "",
-            width = line_num_width
-        ));
-
-        error
+    fn error_with_context(&self, message: &str) -> Diagnostic {
+        Diagnostic::error(message)
+            .at(self.filename, self.line, self.column)
+            .with_code("E0101")
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.chars[self.current]
-        }
+        self.source[self.pos..].chars().next().unwrap_or('\0')
     }
 
     fn peek_ahead(&self, offset: usize) -> char {
-        let pos = self.current + offset;
-        if pos >= self.chars.len() {
-            '\0'
-        } else {
-            self.chars[pos]
-        }
+        self.source[self.pos..].chars().nth(offset).unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
-        let ch = self.chars[self.current];
-        self.current += 1;
-        self.column += 1;
+        let ch = self.peek();
+        if !self.is_at_end() {
+            self.pos += ch.len_utf8();
+            self.column += 1;
+        }
         ch
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.chars.len()
+        self.pos >= self.source.len()
     }
 }
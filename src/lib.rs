@@ -0,0 +1,381 @@
+//! The Brain compiler as a library.
+//!
+//! [`Compiler`] runs the same pipeline as the `brain` command — lex, parse,
+//! resolve imports, check ownership, generate LLVM IR — but hands failures
+//! back as [`Diagnostic`]s instead of printing them and exiting, so other
+//! Rust tools can embed it. Writing, linking and running the IR is left to
+//! the caller. Tools can also add passes of their own between semantic
+//! analysis and code generation ([`Pass`]).
+
+pub mod ast_dump;
+pub mod codegen;
+pub mod depgraph;
+pub mod diagnostic;
+pub mod golden;
+pub mod ir;
+pub mod lexer;
+pub mod lint;
+pub mod manifest;
+pub mod module;
+pub mod monomorphize;
+pub mod parser;
+pub mod pass;
+pub mod peephole;
+pub mod semantic;
+pub mod timing;
+pub mod verify;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+pub use codegen::{CodegenOptions, Roots, Runtime};
+pub use diagnostic::Diagnostic;
+pub use lint::{Level, LintLevels};
+pub use pass::Pass;
+
+use codegen::CodeGenerator;
+use lexer::Lexer;
+use manifest::Manifest;
+use module::{ModuleCache, ModuleLoader, resolve_imports};
+use parser::{AstNode, Parser, Span};
+use semantic::SemanticAnalyzer;
+use timing::PassTimings;
+
+/// What a successful compilation produces.
+pub struct Artifacts {
+    /// The whole program as textual LLVM IR.
+    pub llvm_ir: String,
+    /// Canonical paths of every module pulled in through `import`.
+    pub modules: Vec<PathBuf>,
+}
+
+type Progress = Box<dyn Fn(&str)>;
+
+/// Entry point for compiling Brain programs.
+///
+/// ```no_run
+/// let compiler = brain::Compiler::new();
+/// match compiler.compile_file("main.brn") {
+///     Ok(artifacts) => print!("{}", artifacts.llvm_ir),
+///     Err(diagnostics) => diagnostics.iter().for_each(|d| eprint!("{}", d.render())),
+/// }
+/// ```
+#[derive(Default)]
+pub struct Compiler {
+    codegen: CodegenOptions,
+    progress: Option<Progress>,
+    loader: Option<ModuleLoader>,
+    no_prelude: bool,
+    passes: Vec<Box<dyn Pass>>,
+    lint_levels: LintLevels,
+    warnings: RefCell<Vec<Diagnostic>>,
+    timings: RefCell<PassTimings>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_codegen_options(mut self, options: CodegenOptions) -> Self {
+        self.codegen = options;
+        self
+    }
+
+    /// Called with a short status line as each stage starts.
+    pub fn with_progress(mut self, progress: impl Fn(&str) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Serve imports from memory: `loader` gets the import path joined onto
+    /// the importing file's directory (`lib/util.brn`) and returns the
+    /// module's source, or `None` to read the file from disk as usual.
+    /// Together with [`Compiler::compile_source`] this compiles programs that
+    /// never touch the file system — for the REPL, editors and tests.
+    pub fn with_module_loader(mut self, loader: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        self.loader = Some(Rc::new(loader));
+        self
+    }
+
+    /// Whether every program implicitly imports `std/prelude.brn` (the
+    /// default) — `--no-prelude` turns it off.
+    pub fn with_prelude(mut self, enabled: bool) -> Self {
+        self.no_prelude = !enabled;
+        self
+    }
+
+    /// Run `pass` on every checked program, after the passes registered
+    /// before it.
+    pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Lint levels for the whole program; attributes in the source still
+    /// take precedence.
+    pub fn with_lint_levels(mut self, levels: LintLevels) -> Self {
+        self.lint_levels = levels;
+        self
+    }
+
+    /// The warnings reported since the last call, which a successful
+    /// compilation can have too.
+    pub fn take_warnings(&self) -> Vec<Diagnostic> {
+        self.warnings.take()
+    }
+
+    /// A module cache that consults this compiler's module loader.
+    pub fn module_cache(&self) -> ModuleCache {
+        match &self.loader {
+            Some(loader) => ModuleCache::with_loader(loader.clone()),
+            None => ModuleCache::new(),
+        }
+    }
+
+    /// Time spent in each pass so far, across every call on this compiler.
+    pub fn pass_timings(&self) -> PassTimings {
+        self.timings.borrow().clone()
+    }
+
+    /// Run `f`, adding its duration to [`Compiler::pass_timings`] — for steps
+    /// outside the compiler proper, such as linking.
+    pub fn time_pass<T>(&self, pass: &'static str, module: &str, f: impl FnOnce() -> T) -> T {
+        self.timings.borrow_mut().time(pass, module, f)
+    }
+
+    /// Read and compile `path`; imports resolve relative to it.
+    pub fn compile_file(&self, path: &str) -> Result<Artifacts, Vec<Diagnostic>> {
+        let source = read_source(path).map_err(|e| vec![e])?;
+        self.compile_source(&source, path)
+    }
+
+    /// Compile `source` as if it had been read from `file_name`. The file
+    /// does not need to exist; diagnostics in it can be shown with
+    /// [`Diagnostic::render_with_source`].
+    pub fn compile_source(
+        &self,
+        source: &str,
+        file_name: &str,
+    ) -> Result<Artifacts, Vec<Diagnostic>> {
+        let ast = self.parse(source, file_name).map_err(|e| vec![e])?;
+        self.compile_ast(ast, file_name, &mut self.module_cache())
+            .map_err(|e| vec![e])
+    }
+
+    /// Run everything except code generation.
+    pub fn check_file(&self, path: &str) -> Result<(), Vec<Diagnostic>> {
+        let source = read_source(path).map_err(|e| vec![e])?;
+        let ast = self.parse(&source, path).map_err(|e| vec![e])?;
+        self.check(ast, path, &mut self.module_cache())
+            .map(|_| ())
+            .map_err(|e| vec![e])
+    }
+
+    // The stages below stop at the first error. They are public so tools
+    // can stop early or reuse a `ModuleCache` to learn which files a
+    // program depends on.
+
+    /// Lex and parse a single source file (stages 1–2).
+    pub fn parse(&self, source: &str, file_name: &str) -> Result<AstNode, Diagnostic> {
+        self.progress("  [1/5] Lexical analysis...");
+        let tokens = self.time_pass("lex", file_name, || {
+            Lexer::new(source, file_name).tokenize()
+        })?;
+
+        self.progress("  [2/5] Parsing...");
+        self.time_pass("parse", file_name, || {
+            Parser::new(tokens, file_name).parse()
+        })
+    }
+
+    /// Parse several root files into one program that is then compiled as
+    /// if it were `paths[0]`. Imports in the other files are re-pointed so
+    /// they still resolve relative to their own file; a name defined at the
+    /// top level of two roots is an error.
+    pub fn parse_files(&self, paths: &[String]) -> Result<AstNode, Diagnostic> {
+        let mut merged: Vec<AstNode> = Vec::new();
+        let mut span = Span::default();
+        let mut defined_in: HashMap<String, &str> = HashMap::new();
+        for path in paths {
+            let source = read_source(path)?;
+            let AstNode::Program(nodes, file_span) = self.parse(&source, path)? else {
+                continue;
+            };
+            if path == &paths[0] {
+                span = file_span;
+            }
+            let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+            for node in module::select_for_target(nodes)? {
+                match node {
+                    AstNode::Import {
+                        names,
+                        path: import_path,
+                        span,
+                        ..
+                    } => {
+                        // Package imports (`std/...`) are left alone.
+                        let own = dir.join(&import_path).canonicalize();
+                        let import_path = match own {
+                            Ok(own) if path != &paths[0] => own.to_string_lossy().to_string(),
+                            _ => import_path,
+                        };
+                        merged.push(AstNode::Import {
+                            names,
+                            path: import_path,
+                            span,
+                        });
+                    }
+                    AstNode::FunctionDef { ref name, .. }
+                    | AstNode::LetBinding { ref name, .. }
+                    | AstNode::StructDef { ref name, .. }
+                    | AstNode::EnumDef { ref name, .. } => {
+                        if let Some(first) = defined_in.insert(name.clone(), path) {
+                            return Err(Diagnostic::error(format!(
+                                "'{}' is defined in both '{}' and '{}'",
+                                name, first, path
+                            ))
+                            .with_code("E0305")
+                            .with_help("rename one of them, or import it from one file instead"));
+                        }
+                        merged.push(node);
+                    }
+                    other => merged.push(other),
+                }
+            }
+        }
+        Ok(AstNode::Program(merged, span))
+    }
+
+    /// Load dependencies and inline imported definitions (stage 3).
+    pub fn resolve(
+        &self,
+        ast: AstNode,
+        file_name: &str,
+        cache: &mut ModuleCache,
+    ) -> Result<AstNode, Diagnostic> {
+        self.progress("  [3/5] Resolving imports...");
+        let started = Instant::now();
+        let already_timed = cache.timings.entries.len();
+        cache.set_prelude(!self.no_prelude);
+        let result = load_dependencies(file_name, cache)
+            .and_then(|()| resolve_imports(ast, cache, file_name));
+
+        // Imported modules are lexed and parsed while resolving; report them
+        // as their own passes and only the remainder as import resolution.
+        let mut timings = self.timings.borrow_mut();
+        let nested = &cache.timings.entries[already_timed..];
+        let module_time = nested.iter().map(|e| e.duration).sum();
+        timings.entries.extend(nested.iter().cloned());
+        timings.record(
+            "imports",
+            file_name,
+            started.elapsed().saturating_sub(module_time),
+        );
+        result
+    }
+
+    /// Resolve imports, run semantic analysis, the lints and then the
+    /// registered passes (stages 3–4).
+    pub fn check(
+        &self,
+        ast: AstNode,
+        file_name: &str,
+        cache: &mut ModuleCache,
+    ) -> Result<AstNode, Diagnostic> {
+        let ast = self.resolve(ast, file_name, cache)?;
+
+        self.progress("  [4/5] Semantic analysis (ownership checking)...");
+        self.time_pass("semantic", file_name, || {
+            SemanticAnalyzer::new(file_name).analyze(&ast)
+        })?;
+        let warnings =
+            self.time_pass("lint", file_name, || lint::check(&ast, &self.lint_levels))?;
+        self.warnings.borrow_mut().extend(warnings);
+        self.passes.iter().try_fold(ast, |ast, pass| {
+            self.time_pass(pass.name(), file_name, || pass.run(ast, file_name))
+        })
+    }
+
+    /// Resolve, check and generate LLVM IR for a parsed file (stages 3–5).
+    pub fn compile_ast(
+        &self,
+        ast: AstNode,
+        file_name: &str,
+        cache: &mut ModuleCache,
+    ) -> Result<Artifacts, Diagnostic> {
+        // Taken before imports are merged in, whose exports are not ours
+        let exports = exported_names(&ast);
+        let ast = self.check(ast, file_name, cache)?;
+
+        self.progress("  [5/5] Code generation...");
+        let llvm_ir = self.time_pass("codegen", file_name, || {
+            CodeGenerator::with_options(self.codegen.clone())
+                .with_exports(exports)
+                .generate(&ast)
+        });
+        let llvm_ir = self.time_pass("peephole", file_name, || peephole::optimize(&llvm_ir));
+        self.time_pass("verify", file_name, || verify::verify(&llvm_ir))?;
+
+        let mut modules: Vec<PathBuf> = cache.module_paths().map(PathBuf::from).collect();
+        modules.sort();
+        Ok(Artifacts { llvm_ir, modules })
+    }
+
+    fn progress(&self, message: &str) {
+        if let Some(progress) = &self.progress {
+            progress(message);
+        }
+    }
+}
+
+/// The functions and bindings a program declares `export`.
+fn exported_names(ast: &AstNode) -> Vec<String> {
+    let AstNode::Program(nodes, _) = ast else {
+        return Vec::new();
+    };
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            AstNode::FunctionDef {
+                name,
+                is_exported: true,
+                ..
+            }
+            | AstNode::LetBinding {
+                name,
+                is_exported: true,
+                ..
+            } => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn read_source(path: &str) -> Result<String, Diagnostic> {
+    fs::read_to_string(path).map_err(|e| {
+        Diagnostic::error(format!("could not read file '{}': {}", path, e)).with_code("E0001")
+    })
+}
+
+/// Fetch the dependencies listed in the nearest `brain.toml` (if any) and
+/// register each package root with the module cache, followed by the
+/// standard library as package `std` — a dependency of that name wins.
+fn load_dependencies(input_file: &str, cache: &mut ModuleCache) -> Result<(), Diagnostic> {
+    if let Some(manifest) = Manifest::find(input_file)? {
+        if let Some(name) = &manifest.name {
+            cache.add_package(name, manifest.root().to_path_buf());
+        }
+        for (name, root) in manifest.resolve_dependencies()? {
+            cache.add_package(&name, root);
+        }
+    }
+    if let Some(root) = module::std_root() {
+        cache.add_package("std", root);
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Lints: checks for code that compiles but goes against convention.
+//!
+//! Each lint has a name and a [`Level`]. A warning is reported without
+//! stopping the build, a denied lint is an error, and an allowed one is not
+//! checked at all. The level comes from, most specific first:
+//!
+//! - `#[allow(name)]`, `#[warn(name)]` or `#[deny(name)]` on the item
+//! - `#![allow(name)]` (or `warn` / `deny`) at the top of its file
+//! - `--allow=name`, `--warn=name` or `--deny=name` on the command line
+//! - the lint's default
+//!
+//! Lints run on the checked program, after semantic analysis.
+
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::parser::{AstNode, Attribute, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    /// The level an attribute (`allow`, `warn`, `deny`) or flag sets.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        }
+    }
+}
+
+pub struct Lint {
+    pub name: &'static str,
+    pub default: Level,
+    pub description: &'static str,
+}
+
+pub const NON_SNAKE_CASE: Lint = Lint {
+    name: "non_snake_case",
+    default: Level::Warn,
+    description: "function names should be snake_case",
+};
+
+pub const NON_CAMEL_CASE_TYPES: Lint = Lint {
+    name: "non_camel_case_types",
+    default: Level::Warn,
+    description: "struct, enum and variant names should be UpperCamelCase",
+};
+
+pub const LINTS: &[Lint] = &[NON_SNAKE_CASE, NON_CAMEL_CASE_TYPES];
+
+pub fn find(name: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.name == name)
+}
+
+/// Levels set for the whole program, overriding the lints' defaults.
+#[derive(Clone, Default)]
+pub struct LintLevels {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: &'static Lint, level: Level) {
+        self.levels.insert(lint.name, level);
+    }
+
+    fn level(&self, lint: &Lint, attributes: &[Attribute]) -> Level {
+        // File attributes come first on an item, so the item's own win
+        let from_attributes = attributes
+            .iter()
+            .filter(|a| a.args.iter().any(|arg| arg == lint.name))
+            .filter_map(|a| Level::from_name(&a.name))
+            .next_back();
+        from_attributes
+            .or_else(|| self.levels.get(lint.name).copied())
+            .unwrap_or(lint.default)
+    }
+}
+
+/// Run every lint over `program`: the warnings found, or the first lint
+/// that is denied.
+pub fn check(program: &AstNode, levels: &LintLevels) -> Result<Vec<Diagnostic>, Diagnostic> {
+    let AstNode::Program(nodes, _) = program else {
+        return Ok(Vec::new());
+    };
+    let mut found: Vec<(&Lint, Level, Diagnostic)> = Vec::new();
+    for node in nodes {
+        match node {
+            AstNode::FunctionDef {
+                name,
+                attributes,
+                span,
+                ..
+            } => {
+                // `Type.method` is checked by the method's name; a module's
+                // private functions carry a `$m...` suffix
+                let name = name.rsplit('.').next().unwrap_or(name);
+                let name = name.split('$').next().unwrap_or(name);
+                if !is_snake_case(name) {
+                    let level = levels.level(&NON_SNAKE_CASE, attributes);
+                    found.push((
+                        &NON_SNAKE_CASE,
+                        level,
+                        Diagnostic::warning(format!(
+                            "function '{}' should have a snake_case name",
+                            name
+                        ))
+                        .at_span(*span)
+                        .with_help(format!("rename it to '{}'", to_snake_case(name))),
+                    ));
+                }
+            }
+            AstNode::StructDef {
+                name,
+                attributes,
+                span,
+                ..
+            } if !is_camel_case(name) => {
+                let level = levels.level(&NON_CAMEL_CASE_TYPES, attributes);
+                found.push((
+                    &NON_CAMEL_CASE_TYPES,
+                    level,
+                    camel_case_warning("struct", name, *span),
+                ));
+            }
+            AstNode::EnumDef {
+                name,
+                variants,
+                attributes,
+                span,
+                ..
+            } => {
+                let level = levels.level(&NON_CAMEL_CASE_TYPES, attributes);
+                if !is_camel_case(name) {
+                    found.push((
+                        &NON_CAMEL_CASE_TYPES,
+                        level,
+                        camel_case_warning("enum", name, *span),
+                    ));
+                }
+                for variant in variants.iter().filter(|v| !is_camel_case(&v.name)) {
+                    found.push((
+                        &NON_CAMEL_CASE_TYPES,
+                        level,
+                        camel_case_warning("variant", &variant.name, *span),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for (lint, level, diagnostic) in found {
+        let diagnostic = diagnostic.with_code(lint.name);
+        match level {
+            Level::Allow => {}
+            Level::Warn => warnings.push(diagnostic),
+            Level::Deny => {
+                let mut diagnostic = diagnostic;
+                diagnostic.severity = Severity::Error;
+                return Err(diagnostic.with_note(format!("'{}' is denied", lint.name)));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+fn camel_case_warning(kind: &str, name: &str, span: Span) -> Diagnostic {
+    Diagnostic::warning(format!(
+        "{} '{}' should have an UpperCamelCase name",
+        kind, name
+    ))
+    .at_span(span)
+    .with_help(format!("rename it to '{}'", to_camel_case(name)))
+}
+
+/// No capital letters; underscores anywhere.
+fn is_snake_case(name: &str) -> bool {
+    !name.chars().any(char::is_uppercase)
+}
+
+/// Starts with a capital letter after any leading underscores, and has no
+/// underscores between words.
+fn is_camel_case(name: &str) -> bool {
+    let name = name.trim_start_matches('_');
+    name.chars().next().is_none_or(|c| !c.is_lowercase()) && !name.contains('_')
+}
+
+/// `fooBar` → `foo_bar`, `HTTPServer` → `http_server`.
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower = i > 0 && (chars[i - 1].is_lowercase() || chars[i - 1].is_numeric());
+            let before_lower = i > 0
+                && chars[i - 1].is_uppercase()
+                && chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if (after_lower || before_lower) && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// `foo_bar` → `FooBar`.
+fn to_camel_case(name: &str) -> String {
+    let leading = name.len() - name.trim_start_matches('_').len();
+    let mut camel = name[..leading].to_string();
+    for word in name[leading..].split('_').filter(|w| !w.is_empty()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
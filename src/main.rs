@@ -2,117 +2,688 @@ use std::env;
 use std::fs;
 use std::process;
 
-mod codegen;
-mod lexer;
-mod module;
-mod parser;
-mod semantic;
-
-use codegen::CodeGenerator;
-use lexer::Lexer;
-use module::{ModuleCache, resolve_imports};
-use parser::Parser;
-use semantic::SemanticAnalyzer;
+mod test_runner;
+mod watch;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+use brain::ast_dump::{self, DumpFormat};
+use brain::depgraph;
+use brain::lint::{self, Level, LintLevels};
+use brain::manifest::Manifest;
+use brain::module::ModuleCache;
+use brain::{CodegenOptions, Compiler, Diagnostic, Roots, Runtime};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    /// Compile and link a native executable (the default).
+    Build,
+    /// JIT the generated IR with `lli` — no clang or link step needed.
+    Run,
+    /// Discover and run `test_` functions under a file or directory.
+    Test,
+}
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <input.brn> [output]", args[0]);
-        eprintln!("Example: {} main.brn", args[0]);
-        process::exit(1);
+struct Options {
+    command: Command,
+    input_file: String,
+    /// Every root file, `input_file` first; further `.brn` files given on
+    /// the command line are compiled into the same program.
+    inputs: Vec<String>,
+    /// `-o` or a positional output name; by default the input without
+    /// `.brn`.
+    output_file: Option<String>,
+    /// `--out-dir=<dir>`: where build artifacts go (default: `build/` next
+    /// to `brain.toml` in a package, otherwise next to the input).
+    out_dir: Option<PathBuf>,
+    error_format: ErrorFormat,
+    /// Arguments after `--`, forwarded to the program by `brain run`.
+    program_args: Vec<String>,
+    /// `--dump-ast` / `--dump-ast-resolved`: print the tree and stop.
+    dump_ast: Option<AstDump>,
+    /// `--emit=depgraph`: print the module graph as JSON and stop.
+    depgraph: bool,
+    /// `--check`: run the front end only, producing no artifacts.
+    check: bool,
+    /// `--watch`: rebuild whenever the input or one of its imports changes.
+    watch: bool,
+    /// `--time-passes`: print how long each compiler pass took.
+    time_passes: bool,
+    /// `--keep-ir`: leave the `.ll` file next to the output after linking.
+    keep_ir: bool,
+    /// `--release`: optimize for size and strip the executable, instead of
+    /// the quick, unoptimized dev build.
+    release: bool,
+    /// `--no-prelude`: don't import `std/prelude.brn` implicitly.
+    no_prelude: bool,
+    /// `--temp-dir=<dir>`: where intermediate files go (default: the
+    /// system temp directory).
+    temp_dir: Option<PathBuf>,
+    /// `--test-ir`: compare the IR of the fixtures in the input directory
+    /// with their expected output.
+    test_ir: bool,
+    /// `--bless`: with `--test-ir`, write the expected output instead.
+    bless: bool,
+    /// `--allow=`, `--warn=` and `--deny=`, for every lint they name.
+    lint_levels: LintLevels,
+    codegen: CodegenOptions,
+}
+
+#[derive(Clone, Copy)]
+struct AstDump {
+    format: DumpFormat,
+    /// Dump after imports are resolved (flattened into one program).
+    resolved: bool,
+}
+
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} <input.brn> [more.brn...] [output] [options]",
+        program
+    );
+    eprintln!(
+        "       {} run <input.brn> [options] [-- <args>...]",
+        program
+    );
+    eprintln!("       {} test [file.brn|dir] [options]", program);
+    eprintln!("       {} --test-ir [dir] [--bless] [options]", program);
+    eprintln!("Example: {} main.brn", program);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -o <output>                 Output executable name");
+    eprintln!("  --out-dir=<dir>             Directory for the executable and kept IR");
+    eprintln!("  --error-format=human|json   How diagnostics are printed (default: human)");
+    eprintln!("  --check                     Check for errors without generating code");
+    eprintln!("  --watch                     Rebuild (or re-check) when a source file changes");
+    eprintln!(
+        "  --profile                   Instrument functions; print a call/time report at exit"
+    );
+    eprintln!("  --profile-alloc             Count heap allocations per call site; report at exit");
+    eprintln!(
+        "  --detect-leaks              Report heap blocks never freed, per call site, at exit"
+    );
+    eprintln!(
+        "  --no-checks                 Leave out runtime checks (division by zero, Vec indexing)"
+    );
+    eprintln!("  --runtime=<name>            Freestanding (default) or libc (default for --lib)");
+    eprintln!("  --sanitize                  Link with the C runtime and AddressSanitizer");
+    eprintln!("  --stack-size=<bytes>        Stack size of the main thread (suffixes K, M, G)");
+    eprintln!("  --lib                       Build an object file of the exports, without main");
+    eprintln!(
+        "  --release                   Optimize for size and strip symbols (default: dev build)"
+    );
+    eprintln!("  --keep-ir                   Keep the generated .ll file next to the output");
+    eprintln!("  --temp-dir=<dir>            Directory for intermediate files");
+    eprintln!("  --no-prelude                Don't import std/prelude.brn implicitly");
+    eprintln!("  --time-passes               Report the time spent in each compiler pass");
+    eprintln!("  --allow|warn|deny=<lint>    Set the level of a lint (default: warn)");
+    eprintln!("  --dump-ast[=pretty|json]    Print the parsed AST and stop");
+    eprintln!("  --dump-ast-resolved[=...]   Same, after imports are resolved");
+    eprintln!("  --emit=depgraph             Print the modules and their imports as JSON and stop");
+    eprintln!("  --test-ir                   Compare the IR of each fixture with its .expected.ll");
+    eprintln!("  --bless                     With --test-ir, (re)write the .expected.ll files");
+    eprintln!();
+    eprintln!("'run' executes the program with the LLVM JIT (lli, or $BRAIN_LLI).");
+    eprintln!(
+        "'test' runs every 'fn test_*()' or '#[test]' function, each in its own JIT process."
+    );
+    eprintln!("'--test-ir' compiles every .brn file in the directory (default: the current one).");
+    eprintln!();
+    eprintln!("Lints:");
+    for lint in lint::LINTS {
+        eprintln!("  {:<27} {}", lint.name, lint.description);
     }
+}
 
-    let input_file = &args[1];
-    let output_file = if args.len() > 2 {
-        args[2].clone()
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut positional: Vec<String> = Vec::new();
+    let mut output_flag: Option<String> = None;
+    let mut error_format = ErrorFormat::Human;
+    let mut program_args: Vec<String> = Vec::new();
+    let mut dump_ast: Option<AstDump> = None;
+    let mut depgraph = false;
+    let mut check = false;
+    let mut watch = false;
+    let mut time_passes = false;
+    let mut keep_ir = false;
+    let mut no_prelude = false;
+    let mut temp_dir: Option<PathBuf> = None;
+    let mut out_dir: Option<PathBuf> = None;
+    let mut test_ir = false;
+    let mut bless = false;
+    let mut release = false;
+    let mut runtime = None;
+    let mut lint_levels = LintLevels::default();
+    let mut codegen = CodegenOptions::default();
+
+    let mut rest = &args[1..];
+    let command = match rest.first().map(String::as_str) {
+        Some("run") => Command::Run,
+        Some("test") => Command::Test,
+        _ => Command::Build,
+    };
+    if command != Command::Build {
+        rest = &rest[1..];
+    }
+
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            if command != Command::Run {
+                return Err("'--' is only accepted by 'brain run'".to_string());
+            }
+            program_args.extend(iter.by_ref().cloned());
+        } else if arg == "--check" {
+            check = true;
+        } else if arg == "--watch" {
+            watch = true;
+        } else if arg == "--keep-ir" {
+            keep_ir = true;
+        } else if arg == "--release" {
+            release = true;
+        } else if arg == "--no-prelude" {
+            no_prelude = true;
+        } else if let Some(dir) = arg.strip_prefix("--temp-dir=") {
+            if dir.is_empty() {
+                return Err("'--temp-dir=' expects a directory".to_string());
+            }
+            temp_dir = Some(PathBuf::from(dir));
+        } else if let Some(dir) = arg.strip_prefix("--out-dir=") {
+            if dir.is_empty() {
+                return Err("'--out-dir=' expects a directory".to_string());
+            }
+            out_dir = Some(PathBuf::from(dir));
+        } else if let Some((level, name)) = arg
+            .strip_prefix("--")
+            .and_then(|flag| flag.split_once('='))
+            .and_then(|(level, name)| Some((Level::from_name(level)?, name)))
+        {
+            match lint::find(name) {
+                Some(lint) => lint_levels.set(lint, level),
+                None => return Err(format!("unknown lint '{}'", name)),
+            }
+        } else if arg == "--test-ir" {
+            test_ir = true;
+        } else if arg == "--bless" {
+            bless = true;
+        } else if arg == "--time-passes" {
+            time_passes = true;
+        } else if arg == "--profile" {
+            codegen.profile = true;
+        } else if arg == "--profile-alloc" {
+            codegen.profile_alloc = true;
+        } else if arg == "--detect-leaks" {
+            codegen.detect_leaks = true;
+        } else if arg == "--no-checks" {
+            codegen.no_checks = true;
+        } else if arg == "--sanitize" {
+            codegen.sanitize = true;
+        } else if let Some(name) = arg.strip_prefix("--runtime=") {
+            runtime = Some(match name {
+                "libc" => Runtime::Libc,
+                "freestanding" => Runtime::Freestanding,
+                other => {
+                    return Err(format!(
+                        "unknown runtime '{}' (expected 'libc' or 'freestanding')",
+                        other
+                    ));
+                }
+            });
+        } else if let Some(size) = arg.strip_prefix("--stack-size=") {
+            codegen.stack_size = Some(parse_size(size).ok_or_else(|| {
+                format!(
+                    "invalid stack size '{}' (expected bytes, optionally with K, M or G)",
+                    size
+                )
+            })?);
+        } else if arg == "--lib" {
+            codegen.roots = Roots::library();
+        } else if arg == "-o" {
+            match iter.next() {
+                Some(out) => output_flag = Some(out.clone()),
+                None => return Err("'-o' expects an output name".to_string()),
+            }
+        } else if let Some(fmt) = arg.strip_prefix("--error-format=") {
+            error_format = match fmt {
+                "human" => ErrorFormat::Human,
+                "json" => ErrorFormat::Json,
+                other => {
+                    return Err(format!(
+                        "unknown error format '{}' (expected 'human' or 'json')",
+                        other
+                    ));
+                }
+            };
+        } else if let Some(spec) = arg
+            .strip_prefix("--dump-ast-resolved")
+            .map(|rest| (rest, true))
+            .or_else(|| arg.strip_prefix("--dump-ast").map(|rest| (rest, false)))
+        {
+            let (fmt, resolved) = spec;
+            let format = match fmt {
+                "" | "=pretty" => DumpFormat::Pretty,
+                "=json" => DumpFormat::Json,
+                other => {
+                    return Err(format!(
+                        "unknown AST dump format '{}' (expected 'pretty' or 'json')",
+                        other.trim_start_matches('=')
+                    ));
+                }
+            };
+            dump_ast = Some(AstDump { format, resolved });
+        } else if let Some(what) = arg.strip_prefix("--emit=") {
+            match what {
+                "depgraph" => depgraph = true,
+                other => {
+                    return Err(format!(
+                        "unknown emit kind '{}' (expected 'depgraph')",
+                        other
+                    ));
+                }
+            }
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            return Err(format!("unknown option '{}'", arg));
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+
+    if codegen.sanitize {
+        if runtime == Some(Runtime::Freestanding) {
+            return Err(
+                "'--sanitize' needs the C runtime, not '--runtime=freestanding'".to_string(),
+            );
+        }
+        runtime = Some(Runtime::Libc);
+    }
+    // The JIT runs the program inside lli's own process, and a library
+    // inside its host's, whose malloc must not share the heap with Brain's
+    // allocator, so both get the C runtime unless Brain's allocator is what
+    // is being measured
+    let in_host = command != Command::Build || !codegen.roots.main;
+    let libc_runtime = in_host && !codegen.profile_alloc && !codegen.detect_leaks;
+    codegen.runtime = runtime.unwrap_or(if libc_runtime {
+        Runtime::Libc
     } else {
-        input_file.trim_end_matches(".brn").to_string()
+        Runtime::Freestanding
+    });
+
+    if codegen.runtime == Runtime::Libc && codegen.profile_alloc {
+        return Err(
+            "'--profile-alloc' counts Brain's own allocator, not the C runtime's".to_string(),
+        );
+    }
+    if codegen.runtime == Runtime::Libc && codegen.detect_leaks {
+        return Err(
+            "'--detect-leaks' tracks Brain's own allocator; the sanitizer reports leaks itself"
+                .to_string(),
+        );
+    }
+    if codegen.profile_alloc && codegen.detect_leaks {
+        return Err(
+            "'--profile-alloc' and '--detect-leaks' both replace the allocator".to_string(),
+        );
+    }
+
+    if !codegen.roots.main && (command != Command::Build || test_ir) {
+        return Err("'--lib' has no 'main' to run or test".to_string());
+    }
+
+    if release && command != Command::Build {
+        return Err("'--release' only applies to native builds, not the JIT".to_string());
+    }
+
+    if depgraph && (command != Command::Build || check || dump_ast.is_some()) {
+        return Err(
+            "'--emit=depgraph' prints the module graph and stops; it takes no other mode"
+                .to_string(),
+        );
+    }
+
+    if watch && (command != Command::Build || dump_ast.is_some() || depgraph) {
+        return Err("'--watch' can only be combined with a build or '--check'".to_string());
+    }
+
+    if bless && !test_ir {
+        return Err("'--bless' only makes sense with '--test-ir'".to_string());
+    }
+
+    if test_ir
+        && (command != Command::Build
+            || check
+            || watch
+            || dump_ast.is_some()
+            || depgraph
+            || positional.len() > 1
+            || output_flag.is_some())
+    {
+        return Err("'--test-ir' takes a single directory and no other mode".to_string());
+    }
+
+    if command != Command::Build && (positional.len() > 1 || output_flag.is_some()) {
+        return Err(
+            "'brain run' and 'brain test' take a single input and no output name".to_string(),
+        );
+    }
+
+    let input_file = match positional.first() {
+        Some(input) => input.clone(),
+        // `brain test` with no path searches the current directory
+        None if command == Command::Test || test_ir => ".".to_string(),
+        None => return Err("no input file given".to_string()),
     };
+    // After the first input, `.brn` files are more inputs and anything else
+    // is the output name.
+    let (more_inputs, outputs): (Vec<String>, Vec<String>) = positional
+        .iter()
+        .skip(1)
+        .cloned()
+        .partition(|p| p.ends_with(".brn"));
+    if outputs.len() > 1 || (!outputs.is_empty() && output_flag.is_some()) {
+        return Err("more than one output name given".to_string());
+    }
+    let mut inputs = vec![input_file.clone()];
+    inputs.extend(more_inputs);
+    let output_file = output_flag.or_else(|| outputs.first().cloned());
 
-    compile_file(input_file, &output_file);
+    Ok(Options {
+        command,
+        input_file,
+        inputs,
+        output_file,
+        out_dir,
+        error_format,
+        program_args,
+        dump_ast,
+        depgraph,
+        check,
+        watch,
+        time_passes,
+        keep_ir,
+        release,
+        no_prelude,
+        temp_dir,
+        test_ir,
+        bless,
+        lint_levels,
+        codegen,
+    })
 }
 
-fn get_output_filename(base: &str) -> String {
-    if cfg!(target_os = "windows") {
-        format!("{}.exe", base)
-    } else {
-        base.to_string()
+/// A byte count such as `8388608`, `8192K` or `8M`, rounded up to whole
+/// 64 KiB (a multiple of every platform's page size).
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, unit) = match text.char_indices().last()? {
+        (i, 'K' | 'k') => (&text[..i], 1 << 10),
+        (i, 'M' | 'm') => (&text[..i], 1 << 20),
+        (i, 'G' | 'g') => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+    let bytes = digits.parse::<u64>().ok()?.checked_mul(unit)?;
+    if bytes == 0 {
+        return None;
     }
+    bytes.checked_next_multiple_of(1 << 16)
 }
 
-fn compile_file(input_file: &str, output_file: &str) {
-    println!("Compiling {}...", input_file);
+fn main() {
+    let args: Vec<String> = env::args().collect();
 
-    let source = match fs::read_to_string(input_file) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("Error: Could not read file '{}': {}", input_file, e);
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            print_usage(&args[0]);
             process::exit(1);
         }
     };
 
-    println!("  [1/5] Lexical analysis...");
-    let mut lexer = Lexer::new(&source, input_file);
-    let tokens = match lexer.tokenize() {
-        Ok(tokens) => tokens,
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
-        }
-    };
+    if let Some(dump) = options.dump_ast {
+        dump_ast(&options, dump);
+        return;
+    }
 
-    println!("  [2/5] Parsing...");
-    let mut parser = Parser::new(tokens, input_file);
-    let ast = match parser.parse() {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
+    if options.depgraph {
+        emit_depgraph(&options);
+        return;
+    }
+
+    if options.watch {
+        watch::watch(&options);
+    }
+
+    if options.test_ir {
+        test_runner::run_ir_tests(&options);
+    }
+
+    if options.check {
+        check_file(&options);
+        return;
+    }
+
+    match options.command {
+        Command::Build => compile_file(&options),
+        Command::Run => run_file(&options),
+        Command::Test => test_runner::run_tests(&options),
+    }
+}
+
+/// Print a diagnostic to stderr in the format selected on the command line.
+fn report(diagnostic: &Diagnostic, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => eprint!("{}", diagnostic.render()),
+        ErrorFormat::Json => eprintln!("{}", diagnostic.to_json()),
+    }
+}
+
+fn fail(diagnostic: Diagnostic, format: ErrorFormat) -> ! {
+    fail_all(vec![diagnostic], format)
+}
+
+fn fail_all(diagnostics: Vec<Diagnostic>, format: ErrorFormat) -> ! {
+    for diagnostic in &diagnostics {
+        report(diagnostic, format);
+    }
+    process::exit(1);
+}
+
+/// Where the build goes, without the executable's extension. The output
+/// name is taken inside the output directory — `--out-dir`, or `build/`
+/// next to `brain.toml` — which is created if need be. Without one it is
+/// used as given, and by default is the input without `.brn`.
+fn output_base(options: &Options) -> Result<String, Diagnostic> {
+    let out_dir = match &options.out_dir {
+        Some(dir) => Some(dir.clone()),
+        None => {
+            Manifest::find(&options.input_file)?.map(|m| relative_to_cwd(&m.root().join("build")))
         }
     };
+    let Some(out_dir) = out_dir else {
+        return Ok(options
+            .output_file
+            .clone()
+            .unwrap_or_else(|| options.input_file.trim_end_matches(".brn").to_string()));
+    };
+    fs::create_dir_all(&out_dir).map_err(|e| {
+        Diagnostic::error(format!(
+            "could not create output directory '{}': {}",
+            out_dir.display(),
+            e
+        ))
+        .with_code("E0001")
+    })?;
+    let name = match &options.output_file {
+        Some(name) => PathBuf::from(name),
+        None => PathBuf::from(
+            Path::new(&options.input_file)
+                .file_stem()
+                .unwrap_or_default(),
+        ),
+    };
+    Ok(out_dir.join(name).to_string_lossy().to_string())
+}
 
-    println!("  [3/5] Resolving imports...");
-    let mut cache = ModuleCache::new();
-    let ast = match resolve_imports(ast, &mut cache, input_file) {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
+/// `path` relative to the current directory when it is inside it, which
+/// reads better in messages than the canonical path a manifest has.
+fn relative_to_cwd(path: &Path) -> PathBuf {
+    env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+fn get_output_filename(base: &str, library: bool) -> String {
+    if library {
+        // An object file for the user's own link
+        if cfg!(target_os = "windows") {
+            format!("{}.obj", base)
+        } else {
+            format!("{}.o", base)
         }
-    };
+    } else if cfg!(target_os = "windows") {
+        format!("{}.exe", base)
+    } else {
+        base.to_string()
+    }
+}
+
+/// A compiler configured from the command line; `verbose` prints each
+/// stage as it starts.
+fn compiler(options: &Options, verbose: bool) -> Compiler {
+    let compiler = Compiler::new()
+        .with_codegen_options(options.codegen.clone())
+        .with_prelude(!options.no_prelude)
+        .with_lint_levels(options.lint_levels.clone());
+    if verbose {
+        compiler.with_progress(|msg| println!("{}", msg))
+    } else {
+        compiler
+    }
+}
+
+fn report_timings(options: &Options, compiler: &Compiler) {
+    if options.time_passes {
+        eprint!("{}", compiler.pass_timings().report());
+    }
+}
 
-    println!("  [4/5] Semantic analysis (ownership checking)...");
-    let mut analyzer = SemanticAnalyzer::new(input_file);
-    if let Err(e) = analyzer.analyze(&ast) {
-        eprintln!("{}", e);
-        process::exit(1);
+fn report_warnings(options: &Options, compiler: &Compiler) {
+    for warning in compiler.take_warnings() {
+        report(&warning, options.error_format);
     }
+}
+
+fn check_file(options: &Options) {
+    let input_file = options.input_file.as_str();
+    let format = options.error_format;
+    let compiler = compiler(options, false);
+    let result = compiler
+        .parse_files(&options.inputs)
+        .and_then(|ast| compiler.check(ast, input_file, &mut compiler.module_cache()));
+    report_warnings(options, &compiler);
+    report_timings(options, &compiler);
+    if let Err(diagnostic) = result {
+        fail(diagnostic, format);
+    }
+    if format == ErrorFormat::Human {
+        println!("✓ No errors in {}", input_file);
+    }
+}
+
+fn dump_ast(options: &Options, dump: AstDump) {
+    let input_file = options.input_file.as_str();
+    let compiler = compiler(options, false);
+    let mut result = compiler.parse_files(&options.inputs);
+    if dump.resolved {
+        result = result.and_then(|ast| compiler.resolve(ast, input_file, &mut ModuleCache::new()));
+    }
+    match result {
+        Ok(ast) => print!("{}", ast_dump::dump(&ast, dump.format)),
+        Err(e) => fail(e, options.error_format),
+    }
+}
+
+fn emit_depgraph(options: &Options) {
+    let compiler = compiler(options, false);
+    let mut cache = ModuleCache::new();
+    let result = compiler
+        .parse_files(&options.inputs)
+        .and_then(|ast| compiler.resolve(ast, &options.input_file, &mut cache));
+    match result {
+        Ok(_) => print!("{}", depgraph::to_json(&options.inputs, &cache)),
+        Err(e) => fail(e, options.error_format),
+    }
+}
 
-    println!("  [5/5] Code generation...");
-    let mut codegen = CodeGenerator::new();
-    let llvm_ir = codegen.generate(&ast);
+/// Run the front end and code generator, returning the module's LLVM IR.
+fn generate_ir(
+    options: &Options,
+    compiler: &Compiler,
+    cache: &mut ModuleCache,
+) -> Result<String, Diagnostic> {
+    let input_file = options.input_file.as_str();
+    let ast = compiler.parse_files(&options.inputs)?;
+    let llvm_ir = compiler.compile_ast(ast, input_file, cache)?.llvm_ir;
 
     // Detect missing main() before invoking the linker — gives a clear error
     // instead of the cryptic "subsystem must be defined" from lld-link.
-    let has_main = llvm_ir.contains("define i32 @main()");
-    if !has_main {
-        eprintln!("Error: no 'main' function found in '{}'", input_file);
-        eprintln!("  Brain programs must define a 'fn main()' entry point.");
-        eprintln!("  If you're writing a library, compile with --lib (not yet supported).");
-        process::exit(1);
+    let has_main = llvm_ir.contains("define i32 @main(");
+    if !has_main && options.codegen.roots.main {
+        return Err(
+            Diagnostic::error(format!("no 'main' function found in '{}'", input_file))
+                .with_code("E0002")
+                .with_note("Brain programs must define a 'fn main()' entry point")
+                .with_help("if you're writing a library, compile with --lib"),
+        );
     }
 
-    let ll_file = format!("{}.ll", output_file);
-    let output_exe = get_output_filename(output_file);
+    Ok(llvm_ir)
+}
 
-    if let Err(e) = fs::write(&ll_file, llvm_ir) {
-        eprintln!("Error writing LLVM IR: {}", e);
-        process::exit(1);
-    }
+enum LinkOutcome {
+    Linked {
+        exe: String,
+    },
+    /// clang could not be started; the IR was still written.
+    IrOnly {
+        ll_file: String,
+        exe: String,
+    },
+}
+
+/// Write the IR and link it with clang. The `.ll` file goes to the temp
+/// directory and is removed once linking succeeds, unless `--keep-ir` asks
+/// for `<output>.ll`; it is also kept when linking fails.
+fn write_and_link(
+    options: &Options,
+    compiler: &Compiler,
+    llvm_ir: &str,
+    progress: &dyn Fn(&str),
+) -> Result<LinkOutcome, Diagnostic> {
+    let output_base = output_base(options)?;
+    let output_file = output_base.as_str();
+    let kept_ll_file = format!("{}.ll", output_file);
+    let library = !options.codegen.roots.main;
+    let output_exe = get_output_filename(output_file, library);
+
+    let ll_file = if options.keep_ir {
+        PathBuf::from(&kept_ll_file)
+    } else {
+        temp_ir_path(options, "build")?
+    };
+    write_ir(&ll_file, llvm_ir)?;
 
-    println!("  Generated LLVM IR: {}", ll_file);
-    println!("  Linking to executable: {}", output_exe);
+    progress(&format!("  Generated LLVM IR: {}", ll_file.display()));
+    if library {
+        progress(&format!("  Compiling to object file: {}", output_exe));
+    } else {
+        progress(&format!("  Linking to executable: {}", output_exe));
+    }
 
     let mut cmd = process::Command::new("clang");
     cmd.arg(&ll_file)
@@ -120,7 +691,49 @@ fn compile_file(input_file: &str, output_file: &str) {
         .arg(&output_exe)
         .arg("-Wno-override-module");
 
-    if cfg!(target_os = "windows") {
+    if let Some(size) = options.codegen.stack_size
+        && !library
+    {
+        // Linux has no link-time size for the main thread's stack;
+        // `brn_entry` raises the limit instead
+        if cfg!(target_os = "windows") {
+            cmd.arg(format!("-Wl,/stack:{}", size));
+        } else if cfg!(target_os = "macos") {
+            cmd.arg(format!("-Wl,-stack_size,{:#x}", size));
+        }
+    }
+
+    if options.release {
+        // Identical constants share one copy; each function and global gets
+        // its own section, so the linker can drop the ones nothing uses
+        cmd.arg("-Os")
+            .arg("-fmerge-all-constants")
+            .arg("-ffunction-sections")
+            .arg("-fdata-sections");
+        // An object file is stripped and trimmed by whoever links it
+        if !library {
+            if cfg!(target_os = "windows") {
+                cmd.arg("-Wl,/OPT:REF,/OPT:ICF,/DEBUG:NONE");
+            } else if cfg!(target_os = "macos") {
+                cmd.arg("-Wl,-dead_strip,-x");
+            } else {
+                cmd.arg("-s").arg("-Wl,--gc-sections");
+            }
+        }
+    }
+
+    if library {
+        cmd.arg("-c");
+    } else if options.codegen.runtime == Runtime::Libc {
+        // The C runtime's startup code calls main; the sanitizer runtime
+        // needs it around it too
+        if options.codegen.sanitize {
+            cmd.arg("-fsanitize=address");
+        }
+        if cfg!(target_os = "windows") {
+            cmd.arg("-Wl,/subsystem:console");
+        }
+    } else if cfg!(target_os = "windows") {
         cmd.arg("-fuse-ld=lld");
         cmd.arg("-lkernel32");
         // Tell lld-link this is a console application — required when
@@ -134,23 +747,147 @@ fn compile_file(input_file: &str, output_file: &str) {
         cmd.arg("-lSystem");
     }
 
-    match cmd.output() {
-        Ok(result) => {
-            if result.status.success() {
-                println!("✓ Successfully compiled to: {}", output_exe);
-            } else {
-                eprintln!("Error during linking:");
-                eprintln!("{}", String::from_utf8_lossy(&result.stderr));
-                process::exit(1);
+    match compiler.time_pass("link", output_file, || cmd.output()) {
+        Ok(result) if result.status.success() => {
+            if !options.keep_ir {
+                let _ = fs::remove_file(&ll_file);
             }
+            Ok(LinkOutcome::Linked { exe: output_exe })
         }
+        Ok(result) => Err(Diagnostic::error("linking failed")
+            .with_code("E0003")
+            .with_note(String::from_utf8_lossy(&result.stderr).trim_end())
+            .with_note(format!("the LLVM IR was kept at '{}'", ll_file.display()))),
         Err(e) => {
-            eprintln!("Error: clang not found. {}", e);
+            report(
+                &Diagnostic::warning(format!("could not start 'clang': {}", e))
+                    .with_note("only the LLVM IR was written")
+                    .with_help("install clang, or compile the IR with the command below"),
+                options.error_format,
+            );
+            // Without a linker the IR is the only result; put it where the
+            // user will look for it.
+            if !options.keep_ir {
+                let _ = fs::remove_file(&ll_file);
+                write_ir(Path::new(&kept_ll_file), llvm_ir)?;
+            }
+            Ok(LinkOutcome::IrOnly {
+                ll_file: kept_ll_file,
+                exe: output_exe,
+            })
+        }
+    }
+}
+
+fn compile_file(options: &Options) {
+    let format = options.error_format;
+    let progress = |msg: &str| println!("{}", msg);
+    progress(&format!("Compiling {}...", options.input_file));
+    let compiler = compiler(options, true);
+    let result = generate_ir(options, &compiler, &mut ModuleCache::new())
+        .and_then(|llvm_ir| write_and_link(options, &compiler, &llvm_ir, &progress));
+    report_warnings(options, &compiler);
+    report_timings(options, &compiler);
+
+    match result {
+        Ok(LinkOutcome::Linked { exe }) => println!("✓ Successfully compiled to: {}", exe),
+        Ok(LinkOutcome::IrOnly { ll_file, exe }) => {
             println!("LLVM IR saved to: {}", ll_file);
+            let compile_only = if options.codegen.roots.main {
+                ""
+            } else {
+                " -c"
+            };
             println!(
-                "You can compile manually with: clang {} -o {}",
-                ll_file, output_exe
+                "You can compile manually with: clang{} {} -o {}",
+                compile_only, ll_file, exe
             );
         }
+        Err(e) => fail(e, format),
+    }
+}
+
+/// A per-process `.ll` path in `--temp-dir` or the system temp directory.
+fn temp_ir_path(options: &Options, tag: &str) -> Result<PathBuf, Diagnostic> {
+    let dir = options.temp_dir.clone().unwrap_or_else(env::temp_dir);
+    fs::create_dir_all(&dir).map_err(|e| {
+        Diagnostic::error(format!(
+            "could not create temp directory '{}': {}",
+            dir.display(),
+            e
+        ))
+        .with_code("E0001")
+    })?;
+    Ok(dir.join(format!("brain-{}-{}.ll", tag, process::id())))
+}
+
+fn write_ir(ll_file: &Path, llvm_ir: &str) -> Result<(), Diagnostic> {
+    fs::write(ll_file, llvm_ir).map_err(|e| {
+        Diagnostic::error(format!(
+            "could not write LLVM IR to '{}': {}",
+            ll_file.display(),
+            e
+        ))
+        .with_code("E0001")
+    })
+}
+
+/// Write IR for the JIT to a temp file; the caller removes it afterwards
+/// via `remove_temp_ir`.
+fn write_temp_ir(options: &Options, llvm_ir: &str, tag: &str) -> PathBuf {
+    let format = options.error_format;
+    let ll_file = temp_ir_path(options, tag).unwrap_or_else(|e| fail(e, format));
+    write_ir(&ll_file, llvm_ir).unwrap_or_else(|e| fail(e, format));
+    ll_file
+}
+
+/// Delete a JIT input file, or with `--keep-ir` say where it is.
+fn remove_temp_ir(options: &Options, ll_file: &Path) {
+    if options.keep_ir {
+        eprintln!("note: LLVM IR kept at '{}'", ll_file.display());
+    } else {
+        let _ = fs::remove_file(ll_file);
+    }
+}
+
+fn lli_command() -> String {
+    env::var("BRAIN_LLI").unwrap_or_else(|_| "lli".to_string())
+}
+
+fn lli_not_found(lli: &str, e: std::io::Error, format: ErrorFormat) -> ! {
+    fail(
+        Diagnostic::error(format!("could not start the LLVM JIT '{}': {}", lli, e))
+            .with_code("E0004")
+            .with_help("install LLVM or point BRAIN_LLI at an 'lli' executable"),
+        format,
+    )
+}
+
+fn run_file(options: &Options) {
+    let format = options.error_format;
+    let compiler = compiler(options, false);
+    let result = generate_ir(options, &compiler, &mut ModuleCache::new());
+    report_warnings(options, &compiler);
+    report_timings(options, &compiler);
+    let llvm_ir = result.unwrap_or_else(|e| fail(e, format));
+    let ll_file = write_temp_ir(options, &llvm_ir, "run");
+
+    // `args.get(0)` is the program, named as a native build would be
+    let lli = lli_command();
+    let status = process::Command::new(&lli)
+        .arg("--jit-kind=mcjit")
+        .arg(format!(
+            "--fake-argv0={}",
+            options.input_file.trim_end_matches(".brn")
+        ))
+        .arg(&ll_file)
+        .args(&options.program_args)
+        .status();
+    remove_temp_ir(options, &ll_file);
+
+    match status {
+        // A program killed by a signal has no exit code; report failure.
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => lli_not_found(&lli, e, format),
     }
 }
@@ -0,0 +1,297 @@
+use crate::diagnostic::Diagnostic;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const MANIFEST_NAME: &str = "brain.toml";
+
+pub enum DependencySource {
+    Path(PathBuf),
+    Git { url: String, rev: Option<String> },
+}
+
+pub struct Dependency {
+    pub name: String,
+    pub source: DependencySource,
+    line: usize,
+}
+
+/// A `brain.toml` package manifest. Only the subset of TOML the manifest
+/// needs is understood:
+///
+/// ```toml
+/// [package]
+/// name = "game"
+///
+/// [dependencies]
+/// util = { path = "../util" }
+/// json = { git = "https://example.com/brain-json.git", rev = "v1.2" }
+/// ```
+pub struct Manifest {
+    pub path: PathBuf,
+    pub name: Option<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Manifest {
+    /// Look for `brain.toml` next to `input_file` and in each parent directory.
+    pub fn find(input_file: &str) -> Result<Option<Manifest>, Diagnostic> {
+        let start = Path::new(input_file)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(input_file));
+        let mut dir = start.parent();
+        while let Some(d) = dir {
+            let candidate = d.join(MANIFEST_NAME);
+            if candidate.is_file() {
+                return Self::load(&candidate).map(Some);
+            }
+            dir = d.parent();
+        }
+        Ok(None)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, Diagnostic> {
+        let file = path.to_string_lossy().to_string();
+        let source = fs::read_to_string(path).map_err(|e| {
+            Diagnostic::error(format!("cannot read manifest '{}': {}", file, e)).with_code("E0501")
+        })?;
+
+        let mut manifest = Manifest {
+            path: path.to_path_buf(),
+            name: None,
+            dependencies: Vec::new(),
+        };
+        let mut section = String::new();
+
+        for (i, raw) in source.lines().enumerate() {
+            let line_no = i + 1;
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| {
+                let column = raw.len() - raw.trim_start().len() + 1;
+                Diagnostic::error(message)
+                    .at(&file, line_no, column)
+                    .with_code("E0502")
+                    .with_length(line.chars().count())
+            };
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                if section != "package" && section != "dependencies" {
+                    return Err(error(format!("unknown manifest section '[{}]'", section))
+                        .with_help("expected '[package]' or '[dependencies]'"));
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected 'key = value'".to_string()));
+            };
+            let key = key.trim();
+            let value = value.trim();
+
+            match section.as_str() {
+                "package" => {
+                    if key == "name" {
+                        manifest.name = Some(
+                            parse_string(value)
+                                .ok_or_else(|| error("package name must be a string".into()))?,
+                        );
+                    }
+                }
+                "dependencies" => {
+                    let table = parse_inline_table(value).ok_or_else(|| {
+                        error(format!("invalid dependency specification for '{}'", key))
+                            .with_help("use '{ path = \"...\" }' or '{ git = \"...\" }'")
+                    })?;
+                    let get = |k: &str| {
+                        table
+                            .iter()
+                            .find(|(name, _)| name == k)
+                            .map(|(_, v)| v.clone())
+                    };
+                    let source = match (get("path"), get("git")) {
+                        (Some(p), None) => DependencySource::Path(PathBuf::from(p)),
+                        (None, Some(url)) => DependencySource::Git {
+                            url,
+                            rev: get("rev").or_else(|| get("tag")).or_else(|| get("branch")),
+                        },
+                        _ => {
+                            return Err(error(format!(
+                                "dependency '{}' must specify exactly one of 'path' or 'git'",
+                                key
+                            )));
+                        }
+                    };
+                    manifest.dependencies.push(Dependency {
+                        name: key.to_string(),
+                        source,
+                        line: line_no,
+                    });
+                }
+                _ => {
+                    return Err(error(format!("'{}' is outside of any section", key))
+                        .with_help("add a '[package]' or '[dependencies]' header above it"));
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn root(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Make every dependency available on disk — cloning git dependencies
+    /// into `.brain/deps/` — and return `(name, package root)` pairs,
+    /// including the dependencies of dependencies.
+    pub fn resolve_dependencies(&self) -> Result<Vec<(String, PathBuf)>, Diagnostic> {
+        let mut resolved: Vec<(String, PathBuf)> = Vec::new();
+        self.resolve_into(&mut resolved)?;
+        Ok(resolved)
+    }
+
+    fn resolve_into(&self, resolved: &mut Vec<(String, PathBuf)>) -> Result<(), Diagnostic> {
+        let file = self.path.to_string_lossy().to_string();
+        for dep in &self.dependencies {
+            if resolved.iter().any(|(name, _)| *name == dep.name) {
+                continue;
+            }
+            let at = |d: Diagnostic| d.at(&file, dep.line, 1).with_length(dep.name.len());
+
+            let root = match &dep.source {
+                DependencySource::Path(path) => {
+                    let root = self.root().join(path);
+                    if !root.is_dir() {
+                        return Err(at(Diagnostic::error(format!(
+                            "dependency '{}' not found at '{}'",
+                            dep.name,
+                            root.display()
+                        ))
+                        .with_code("E0503")
+                        .with_help("path dependencies are relative to the manifest")));
+                    }
+                    root
+                }
+                DependencySource::Git { url, rev } => {
+                    let root = self.root().join(".brain").join("deps").join(&dep.name);
+                    fetch_git(url, rev.as_deref(), &root)
+                        .map_err(|msg| at(msg.with_code("E0504")))?;
+                    root
+                }
+            };
+
+            let root = root.canonicalize().unwrap_or(root);
+            resolved.push((dep.name.clone(), root.clone()));
+
+            let nested = root.join(MANIFEST_NAME);
+            if nested.is_file() {
+                Manifest::load(&nested)?.resolve_into(resolved)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fetch_git(url: &str, rev: Option<&str>, dest: &Path) -> Result<(), Diagnostic> {
+    let run = |cmd: &mut Command, what: &str| -> Result<(), Diagnostic> {
+        let output = cmd
+            .output()
+            .map_err(|e| Diagnostic::error(format!("could not run git: {}", e)))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Diagnostic::error(format!("{} '{}' failed", what, url))
+                .with_note(String::from_utf8_lossy(&output.stderr).trim_end()))
+        }
+    };
+
+    let git = || {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(dest);
+        cmd
+    };
+
+    if !dest.join(".git").is_dir() {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                Diagnostic::error(format!("could not create '{}': {}", parent.display(), e))
+            })?;
+        }
+        run(
+            Command::new("git")
+                .arg("clone")
+                .arg("--quiet")
+                .arg(url)
+                .arg(dest),
+            "git clone",
+        )?;
+    }
+    let Some(rev) = rev else {
+        return Ok(());
+    };
+    if !is_commit_id(rev) {
+        // A branch or tag can move, so it is fetched on every build
+        run(git().args(["fetch", "--quiet", "origin", rev]), "git fetch")?;
+        return run(
+            git().args(["checkout", "--quiet", "--detach", "FETCH_HEAD"]),
+            "git checkout of",
+        );
+    }
+    // A commit can't: once it is here, skip the network round-trip
+    let present = git()
+        .args(["cat-file", "-e", &format!("{}^{{commit}}", rev)])
+        .output()
+        .is_ok_and(|o| o.status.success());
+    if !present {
+        run(git().args(["fetch", "--quiet", "origin"]), "git fetch")?;
+    }
+    run(git().args(["checkout", "--quiet", rev]), "git checkout of")
+}
+
+/// Whether `rev` names a commit by its (possibly abbreviated) hash rather
+/// than a branch or tag.
+fn is_commit_id(rev: &str) -> bool {
+    (7..=40).contains(&rev.len()) && rev.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    if inner.contains('"') {
+        return None;
+    }
+    Some(inner.replace("\\\\", "\\"))
+}
+
+/// Parse `{ key = "value", ... }`. Values are plain strings.
+fn parse_inline_table(value: &str) -> Option<Vec<(String, String)>> {
+    let inner = value.strip_prefix('{')?.strip_suffix('}')?.trim();
+    let mut entries = Vec::new();
+    if inner.is_empty() {
+        return Some(entries);
+    }
+    for part in inner.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let (k, v) = part.split_once('=')?;
+        entries.push((k.trim().to_string(), parse_string(v.trim())?));
+    }
+    Some(entries)
+}
@@ -1,26 +1,168 @@
 // I think this implementation is not good, I don't know hahaha
 
+use crate::diagnostic::Diagnostic;
 use crate::lexer::Lexer;
-use crate::parser::{AstNode, Parser};
+use crate::parser::{AstNode, Parser, Span};
+use crate::timing::PassTimings;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 
+/// Supplies module source by path — for modules that exist only in memory.
+/// Returning `None` falls back to the file system.
+pub type ModuleLoader = Rc<dyn Fn(&str) -> Option<String>>;
+
+/// What a module makes available. Definitions are shared with every module
+/// that imports them and with the programs they end up in, so importing a
+/// module copies pointers rather than syntax trees.
 pub struct ModuleExports {
     pub exported_names: HashSet<String>,
-    pub all_definitions: Vec<AstNode>,
+    pub all_definitions: Vec<Rc<AstNode>>,
+    /// The module's own top-level names → the names its definitions have
+    /// once flattened into a program. Exported names are kept; private ones
+    /// get a suffix naming the module, so helpers with the same name in two
+    /// modules don't collide.
+    pub symbols: HashMap<String, String>,
+    /// Canonical paths of the modules it imports directly, in source order.
+    pub imports: Vec<String>,
+}
+
+/// A module after the first phase of loading: parsed, with its own
+/// definitions renamed and its imports resolved to paths, but not yet
+/// linked to the definitions it imports.
+struct DeclaredModule {
+    exported_names: HashSet<String>,
+    /// The module's own definitions, already under their flattened names.
+    definitions: Vec<Rc<AstNode>>,
+    symbols: HashMap<String, String>,
+    /// Canonical path, requested names and span of each `import`.
+    imports: Vec<(String, Vec<String>, Span)>,
 }
 
 pub struct ModuleCache {
     cache: HashMap<String, ModuleExports>,
-    currently_loading: HashSet<String>,
+    /// Modules whose declarations are being collected: reaching one again
+    /// is an import cycle, which the second phase links like any import.
+    declaring: HashSet<String>,
+    /// Modules declared but not yet linked into `cache`.
+    declared: HashMap<String, DeclaredModule>,
+    /// Package name → root directory, from the manifest's `[dependencies]`.
+    packages: Vec<(String, PathBuf)>,
+    loader: Option<ModuleLoader>,
+    /// Sources handed out by `loader`, keyed by the path they were asked for.
+    virtual_sources: HashMap<String, String>,
+    /// Whether `resolve_imports` adds the exports of `std/prelude.brn`.
+    prelude: bool,
+    /// Canonical paths of the modules the program imports, the prelude
+    /// included, in the order they were first imported.
+    program_imports: Vec<String>,
+    /// Lex and parse time of each module loaded so far.
+    pub timings: PassTimings,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ModuleCache {
     pub fn new() -> Self {
         ModuleCache {
             cache: HashMap::new(),
-            currently_loading: HashSet::new(),
+            declaring: HashSet::new(),
+            declared: HashMap::new(),
+            packages: Vec::new(),
+            loader: None,
+            virtual_sources: HashMap::new(),
+            prelude: true,
+            program_imports: Vec::new(),
+            timings: PassTimings::default(),
+        }
+    }
+
+    /// Ask `loader` for every imported module before looking on disk. It is
+    /// called with the import path joined onto the importing file's
+    /// directory, e.g. `lib/util.brn` for `"util.brn"` imported from
+    /// `lib/main.brn`.
+    pub fn with_loader(loader: ModuleLoader) -> Self {
+        ModuleCache {
+            loader: Some(loader),
+            ..Self::new()
+        }
+    }
+
+    /// Make `import ... from "name/file.brn"` resolve inside `root` when the
+    /// path does not exist relative to the importing file.
+    pub fn add_package(&mut self, name: &str, root: PathBuf) {
+        if !self.packages.iter().any(|(n, _)| n == name) {
+            self.packages.push((name.to_string(), root));
+        }
+    }
+
+    /// Turn the automatic prelude import on or off (it is on by default).
+    pub fn set_prelude(&mut self, enabled: bool) {
+        self.prelude = enabled;
+    }
+
+    /// Everything `std/prelude.brn` exports, plus the private helpers those
+    /// definitions call. Empty when prelude is off or the standard library
+    /// can't be found.
+    fn prelude_definitions(&mut self) -> Result<Vec<Rc<AstNode>>, Diagnostic> {
+        if !self.prelude {
+            return Ok(Vec::new());
+        }
+        let Some(canonical) = self
+            .packages
+            .iter()
+            .find(|(name, _)| name == "std")
+            .and_then(|(_, root)| root.join("prelude.brn").canonicalize().ok())
+            .map(|p| p.to_string_lossy().to_string())
+        else {
+            return Ok(Vec::new());
+        };
+        if !self.cache.contains_key(&canonical) {
+            self.load_module(&canonical)?;
+        }
+        self.record_program_import(&canonical);
+        let exports = &self.cache[&canonical];
+        let mut roots: Vec<String> = exports.exported_names.iter().cloned().collect();
+        roots.extend(Self::used_by_statements(&exports.all_definitions));
+        let needed = Self::transitive_needed(&roots, &exports.all_definitions);
+        Ok(exports
+            .all_definitions
+            .iter()
+            .filter(|node| match node.as_ref() {
+                AstNode::FunctionDef { name, .. }
+                | AstNode::LetBinding { name, .. }
+                | AstNode::StructDef { name, .. }
+                | AstNode::EnumDef { name, .. } => needed.contains(name.as_str()),
+                _ => true,
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Canonical paths of every module loaded so far.
+    pub fn module_paths(&self) -> impl Iterator<Item = &String> {
+        self.cache.keys()
+    }
+
+    /// A module loaded so far, by canonical path.
+    pub fn module(&self, canonical_path: &str) -> Option<&ModuleExports> {
+        self.cache.get(canonical_path)
+    }
+
+    /// The modules the program itself imports, as opposed to those only
+    /// its imports do.
+    pub fn program_imports(&self) -> &[String] {
+        &self.program_imports
+    }
+
+    fn record_program_import(&mut self, canonical_path: &str) {
+        if !self.program_imports.iter().any(|p| p == canonical_path) {
+            self.program_imports.push(canonical_path.to_string());
         }
     }
 
@@ -29,24 +171,34 @@ impl ModuleCache {
         requesting_file: &str,
         import_path: &str,
         requested_names: &[String],
-    ) -> Result<Vec<AstNode>, String> {
-        let canonical = Self::resolve_path(requesting_file, import_path)?;
+        span: Span,
+    ) -> Result<Vec<Rc<AstNode>>, Diagnostic> {
+        let canonical = self.resolve_path(requesting_file, import_path, span)?;
 
         if !self.cache.contains_key(&canonical) {
             self.load_module(&canonical)?;
         }
+        self.record_program_import(&canonical);
 
         let exports = self.cache.get(&canonical).unwrap();
 
         for name in requested_names {
             if !exports.exported_names.contains(name) {
-                return Err(format!(
-                    "Error: '{}' is not exported from '{}'.\n  Exported symbols: {}\n  Hint: add 'export' before the declaration in '{}'",
-                    name,
-                    import_path,
-                    Self::format_names(&exports.exported_names),
-                    import_path,
-                ));
+                return Err(Diagnostic::error(format!(
+                    "'{}' is not exported from '{}'",
+                    name, import_path
+                ))
+                .at_span(span)
+                .with_code("E0302")
+                .with_length(6)
+                .with_note(format!(
+                    "exported symbols: {}",
+                    Self::format_names(&exports.exported_names)
+                ))
+                .with_help(format!(
+                    "add 'export' before the declaration in '{}'",
+                    import_path
+                )));
             }
         }
 
@@ -55,12 +207,15 @@ impl ModuleCache {
         // function like `enemy_take_damage` that calls a private helper
         // `_clamp` would produce an LLVM call to `@brn__clamp` with no
         // definition, causing a linker error.
-        let needed = Self::transitive_needed(requested_names, &exports.all_definitions);
+        // Top-level statements always run, so what they use comes too.
+        let mut roots = requested_names.to_vec();
+        roots.extend(Self::used_by_statements(&exports.all_definitions));
+        let needed = Self::transitive_needed(&roots, &exports.all_definitions);
 
         Ok(exports
             .all_definitions
             .iter()
-            .filter(|node| match node {
+            .filter(|node| match node.as_ref() {
                 AstNode::FunctionDef { name, .. }
                 | AstNode::LetBinding { name, .. }
                 | AstNode::StructDef { name, .. }
@@ -71,177 +226,333 @@ impl ModuleCache {
             .collect())
     }
 
-    pub fn resolve_path(requesting_file: &str, import_path: &str) -> Result<String, String> {
+    pub fn resolve_path(
+        &mut self,
+        requesting_file: &str,
+        import_path: &str,
+        span: Span,
+    ) -> Result<String, Diagnostic> {
         let base = Path::new(requesting_file)
             .parent()
             .unwrap_or(Path::new("."));
         let full = base.join(import_path);
-        full.canonicalize()
-            .map(|p| p.to_string_lossy().to_string())
-            .map_err(|_| {
-                format!(
-                    "Error: cannot find module '{}' (resolved from '{}')",
-                    import_path, requesting_file
-                )
-            })
+        if let Some(loader) = &self.loader {
+            for candidate in module_files(&full) {
+                let key = normalize(&candidate);
+                if self.virtual_sources.contains_key(&key) {
+                    return Ok(key);
+                }
+                if let Some(source) = loader(&key) {
+                    self.virtual_sources.insert(key.clone(), source);
+                    return Ok(key);
+                }
+            }
+        }
+        let mut searched = module_files(&full);
+
+        if let Some(in_package) = import_path.split_once('/').and_then(|(first, rest)| {
+            self.packages
+                .iter()
+                .find(|(name, _)| name == first)
+                .map(|(_, root)| root.join(rest))
+        }) {
+            searched.extend(module_files(&in_package));
+        }
+        if let Some(found) = searched.iter().find(|p| p.is_file())
+            && let Ok(p) = found.canonicalize()
+        {
+            return Ok(p.to_string_lossy().to_string());
+        }
+
+        let mut diagnostic = Diagnostic::error(format!("cannot find module '{}'", import_path))
+            .at_span(span)
+            .with_code("E0301")
+            .with_length(6);
+        for candidate in &searched {
+            diagnostic = diagnostic.with_note(format!("looked for '{}'", candidate.display()));
+        }
+        Err(diagnostic.with_help(
+            "import paths are resolved relative to the importing file, \
+             with '.brn' or '/mod.brn' added if needed, \
+             as 'package/path' for a dependency in brain.toml, \
+             or as 'std/file.brn' for the standard library",
+        ))
     }
 
-    fn load_module(&mut self, canonical_path: &str) -> Result<(), String> {
-        if self.currently_loading.contains(canonical_path) {
-            return Err(format!(
-                "Error: circular import detected — '{}' is already being loaded",
-                canonical_path
-            ));
+    /// Load a module and everything it imports in two phases, so that
+    /// modules may import each other: first every module in the import
+    /// graph is parsed and its declarations collected, then each is linked
+    /// to the definitions of the modules it imports.
+    fn load_module(&mut self, canonical_path: &str) -> Result<(), Diagnostic> {
+        let result = self.declare_module(canonical_path).and_then(|()| {
+            let mut paths: Vec<String> = self.declared.keys().cloned().collect();
+            paths.sort();
+            paths.iter().try_for_each(|path| self.check_imports(path))?;
+            for path in paths {
+                self.link_module(&path);
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            self.declaring.clear();
+            self.declared.clear();
+        }
+        result
+    }
+
+    /// Phase one: parse the module, resolve its imports and declare those
+    /// first, then rename its own top-level names. A module already being
+    /// declared is skipped; the cycle is closed when linking.
+    fn declare_module(&mut self, canonical_path: &str) -> Result<(), Diagnostic> {
+        if self.cache.contains_key(canonical_path)
+            || self.declared.contains_key(canonical_path)
+            || !self.declaring.insert(canonical_path.to_string())
+        {
+            return Ok(());
         }
-        self.currently_loading.insert(canonical_path.to_string());
 
-        let source = fs::read_to_string(canonical_path)
-            .map_err(|e| format!("Error: cannot read module '{}': {}", canonical_path, e))?;
+        let source = match self.virtual_sources.get(canonical_path) {
+            Some(source) => source.clone(),
+            None => fs::read_to_string(canonical_path).map_err(|e| {
+                Diagnostic::error(format!("cannot read module '{}': {}", canonical_path, e))
+                    .with_code("E0304")
+            })?,
+        };
 
         let path_owned = canonical_path.to_string();
-        let mut lexer = Lexer::new(&source, &path_owned);
-        let tokens = lexer
-            .tokenize()
-            .map_err(|e| format!("Lex error in '{}': {}", canonical_path, e))?;
-
-        let mut parser = Parser::new(tokens, &path_owned);
-        let ast = parser
-            .parse()
-            .map_err(|e| format!("Parse error in '{}': {}", canonical_path, e))?;
-
-        let mut transitive_imports: Vec<(String, Vec<String>)> = Vec::new();
-        if let AstNode::Program(ref nodes) = ast {
+        let tokens = self.timings.time("lex", canonical_path, || {
+            Lexer::new(&source, &path_owned).tokenize()
+        })?;
+        let ast = match self.timings.time("parse", canonical_path, || {
+            Parser::new(tokens, &path_owned).parse()
+        })? {
+            AstNode::Program(nodes, span) => AstNode::Program(select_for_target(nodes)?, span),
+            other => other,
+        };
+
+        let mut imports: Vec<(String, Vec<String>, Span)> = Vec::new();
+        if let AstNode::Program(ref nodes, _) = ast {
             for node in nodes {
-                if let AstNode::Import { names, path } = node {
-                    let dep = Self::resolve_path(canonical_path, path)?;
-                    transitive_imports.push((dep, names.clone()));
+                if let AstNode::Import {
+                    names, path, span, ..
+                } = node
+                {
+                    let dep = self.resolve_path(canonical_path, path, *span)?;
+                    imports.push((dep, names.clone(), *span));
                 }
             }
         }
 
-        for (dep_canonical, _) in &transitive_imports {
-            if !self.cache.contains_key(dep_canonical) {
-                self.load_module(dep_canonical)?;
-            }
+        for (dep_canonical, _, _) in &imports {
+            self.declare_module(dep_canonical)?;
         }
 
+        // Numbered once its imports are declared, like the modules already linked
+        let suffix = format!(
+            "$m{}{}",
+            self.cache.len() + self.declared.len(),
+            Path::new(canonical_path)
+                .file_stem()
+                .map_or(String::new(), |stem| stem
+                    .to_string_lossy()
+                    .chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect())
+        );
         let mut exported_names = HashSet::new();
-        let mut all_definitions: Vec<AstNode> = Vec::new();
+        let mut definitions: Vec<Rc<AstNode>> = Vec::new();
         let mut seen_names: HashSet<String> = HashSet::new();
-
-        for (dep_canonical, dep_names) in &transitive_imports {
-            if let Some(dep_exports) = self.cache.get(dep_canonical) {
-                for name in dep_names {
-                    if !dep_exports.exported_names.contains(name) {
-                        return Err(format!(
-                            "Error: '{}' is not exported from '{}' (imported by '{}')",
-                            name, dep_canonical, canonical_path
-                        ));
-                    }
+        let mut symbols = HashMap::new();
+        if let AstNode::Program(mut nodes, _) = ast {
+            for node in &nodes {
+                if let AstNode::FunctionDef {
+                    name, is_exported, ..
                 }
-                for node in &dep_exports.all_definitions {
-                    match node {
-                        AstNode::FunctionDef { name, .. }
-                        | AstNode::LetBinding { name, .. }
-                        | AstNode::StructDef { name, .. }
-                        | AstNode::EnumDef { name, .. } => {
-                            if seen_names.insert(name.clone()) {
-                                all_definitions.push(node.clone());
-                            }
-                        }
-                        other => all_definitions.push(other.clone()),
-                    }
+                | AstNode::LetBinding {
+                    name, is_exported, ..
+                } = node
+                {
+                    // A `#[no_mangle]` function is defined under the name it was given
+                    let flat =
+                        if *is_exported || name.contains('.') || node.has_attribute("no_mangle") {
+                            name.clone()
+                        } else {
+                            format!("{}{}", name, suffix)
+                        };
+                    symbols.insert(name.clone(), flat);
                 }
             }
-        }
-
-        if let AstNode::Program(nodes) = ast {
+            for node in &mut nodes {
+                rename_top_level(node, &symbols);
+            }
             for node in nodes {
                 match &node {
                     AstNode::Import { .. } => {}
 
+                    // An `extern fn` is never renamed: the name is the
+                    // outside symbol's
                     AstNode::FunctionDef {
                         name, is_exported, ..
-                    } => {
-                        if *is_exported {
-                            exported_names.insert(name.clone());
-                        }
-                        if seen_names.insert(name.clone()) {
-                            all_definitions.push(node);
-                        }
                     }
-
-                    AstNode::LetBinding {
+                    | AstNode::LetBinding {
                         name, is_exported, ..
-                    } => {
-                        if *is_exported {
-                            exported_names.insert(name.clone());
-                        }
-                        if seen_names.insert(name.clone()) {
-                            all_definitions.push(node);
-                        }
                     }
-
-                    AstNode::StructDef {
+                    | AstNode::StructDef {
                         name, is_exported, ..
-                    } => {
-                        if *is_exported {
-                            exported_names.insert(name.clone());
-                        }
-                        if seen_names.insert(name.clone()) {
-                            all_definitions.push(node);
-                        }
                     }
-
-                    AstNode::EnumDef {
+                    | AstNode::EnumDef {
+                        name, is_exported, ..
+                    }
+                    | AstNode::ExternFn {
                         name, is_exported, ..
                     } => {
                         if *is_exported {
                             exported_names.insert(name.clone());
                         }
                         if seen_names.insert(name.clone()) {
-                            all_definitions.push(node);
+                            definitions.push(Rc::new(node));
                         }
                     }
 
-                    other => {
-                        all_definitions.push(other.clone());
-                    }
+                    _ => definitions.push(Rc::new(node)),
                 }
             }
         }
 
-        self.currently_loading.remove(canonical_path);
+        self.declaring.remove(canonical_path);
+        self.declared.insert(
+            canonical_path.to_string(),
+            DeclaredModule {
+                exported_names,
+                definitions,
+                symbols,
+                imports,
+            },
+        );
+        Ok(())
+    }
+
+    /// Every name a declared module imports must be exported by the module
+    /// it comes from.
+    fn check_imports(&self, canonical_path: &str) -> Result<(), Diagnostic> {
+        for (dep_canonical, dep_names, span) in &self.declared[canonical_path].imports {
+            let exported = match self.cache.get(dep_canonical) {
+                Some(dep) => &dep.exported_names,
+                None => &self.declared[dep_canonical].exported_names,
+            };
+            if let Some(name) = dep_names.iter().find(|name| !exported.contains(*name)) {
+                return Err(Diagnostic::error(format!(
+                    "'{}' is not exported from '{}'",
+                    name, dep_canonical
+                ))
+                .at_span(*span)
+                .with_code("E0302")
+                .with_length(6)
+                .with_note(format!(
+                    "exported symbols: {}",
+                    Self::format_names(exported)
+                )));
+            }
+        }
+        Ok(())
+    }
 
+    /// Phase two: a module's definitions are those of everything it
+    /// imports, directly or not, followed by its own. Each module is visited
+    /// once, so a cycle contributes every member's definitions.
+    fn link_module(&mut self, canonical_path: &str) {
+        let mut all_definitions = Vec::new();
+        let mut seen_names = HashSet::new();
+        let mut visited = HashSet::new();
+        self.collect_definitions(
+            canonical_path,
+            &mut visited,
+            &mut seen_names,
+            &mut all_definitions,
+        );
+        let declared = self.declared.remove(canonical_path).unwrap();
+        let mut imports: Vec<String> = Vec::new();
+        for (dep_canonical, _, _) in declared.imports {
+            if !imports.contains(&dep_canonical) {
+                imports.push(dep_canonical);
+            }
+        }
         self.cache.insert(
             canonical_path.to_string(),
             ModuleExports {
-                exported_names,
+                exported_names: declared.exported_names,
                 all_definitions,
+                symbols: declared.symbols,
+                imports,
             },
         );
+    }
 
-        Ok(())
+    fn collect_definitions(
+        &self,
+        canonical_path: &str,
+        visited: &mut HashSet<String>,
+        seen_names: &mut HashSet<String>,
+        out: &mut Vec<Rc<AstNode>>,
+    ) {
+        if !visited.insert(canonical_path.to_string()) {
+            return;
+        }
+        // A module linked earlier already lists everything it imports
+        let definitions = match self.cache.get(canonical_path) {
+            Some(linked) => &linked.all_definitions,
+            None => {
+                let declared = &self.declared[canonical_path];
+                for (dep_canonical, _, _) in &declared.imports {
+                    self.collect_definitions(dep_canonical, visited, seen_names, out);
+                }
+                &declared.definitions
+            }
+        };
+        for node in definitions {
+            match node.as_ref() {
+                AstNode::FunctionDef { name, .. }
+                | AstNode::LetBinding { name, .. }
+                | AstNode::StructDef { name, .. }
+                | AstNode::EnumDef { name, .. }
+                | AstNode::ExternFn { name, .. } => {
+                    if seen_names.insert(name.clone()) {
+                        out.push(Rc::clone(node));
+                    }
+                }
+                _ => out.push(Rc::clone(node)),
+            }
+        }
     }
 
     /// Starting from `roots`, walk call-graph edges within `definitions` to
     /// find every function (exported or not) that must be included so that
-    /// all call sites have a definition available.
-    fn transitive_needed<'a>(roots: &'a [String], definitions: &'a [AstNode]) -> HashSet<&'a str> {
-        // Build a quick name → body map for every FunctionDef in the module.
+    /// all call sites have a definition available. Top-level bindings the
+    /// functions read come along too, with whatever their initializers use.
+    fn transitive_needed<'a>(
+        roots: &'a [String],
+        definitions: &'a [Rc<AstNode>],
+    ) -> HashSet<&'a str> {
+        // Build a quick name → body map for every FunctionDef and top-level
+        // binding in the module.
         let body_map: HashMap<&str, &AstNode> = definitions
             .iter()
-            .filter_map(|n| {
-                if let AstNode::FunctionDef { name, body, .. } = n {
-                    Some((name.as_str(), body.as_ref()))
-                } else {
-                    None
-                }
+            .map(Rc::as_ref)
+            .filter_map(|n| match n {
+                AstNode::FunctionDef { name, body, .. } => Some((name.as_str(), body.as_ref())),
+                AstNode::LetBinding { name, value, .. } => Some((name.as_str(), value.as_ref())),
+                AstNode::StructDef { name, .. } => Some((name.as_str(), n)),
+                _ => None,
             })
             .collect();
 
         let mut needed: HashSet<&str> = HashSet::new();
         let mut queue: Vec<&str> = roots.iter().map(|s| s.as_str()).collect();
+        // A type's `impl` methods (`Type.method`) come with the type.
+        queue.extend(body_map.keys().copied().filter(|name| {
+            name.split_once('.')
+                .is_some_and(|(type_name, _)| roots.iter().any(|r| r == type_name))
+        }));
 
         while let Some(current) = queue.pop() {
             if !needed.insert(current) {
@@ -255,28 +566,42 @@ impl ModuleCache {
         needed
     }
 
-    /// Recursively collect all direct Call targets from an AST node.
+    /// The names the top-level statements among `definitions` use.
+    fn used_by_statements(definitions: &[Rc<AstNode>]) -> Vec<String> {
+        let mut used = Vec::new();
+        for statement in definitions.iter().filter(|n| !n.is_declaration()) {
+            Self::collect_calls_from_body(statement, &mut used);
+        }
+        used.into_iter().map(str::to_string).collect()
+    }
+
+    /// Recursively collect all direct Call targets, and the names read
+    /// (which may be top-level bindings), from an AST node.
     fn collect_calls_from_body<'a>(node: &'a AstNode, out: &mut Vec<&'a str>) {
         match node {
-            AstNode::Call { name, args } => {
+            AstNode::Identifier { name, .. } => out.push(name.as_str()),
+            AstNode::Call { name, args, .. } => {
                 out.push(name.as_str());
                 for a in args {
                     Self::collect_calls_from_body(a, out);
                 }
             }
-            AstNode::Block(stmts) | AstNode::Program(stmts) => {
+            AstNode::Block(stmts, _) | AstNode::Program(stmts, _) => {
                 for s in stmts {
                     Self::collect_calls_from_body(s, out);
                 }
             }
             AstNode::FunctionDef { body, .. } => Self::collect_calls_from_body(body, out),
-            AstNode::LetBinding { value, .. } | AstNode::Assignment { value, .. } => {
-                Self::collect_calls_from_body(value, out)
+            AstNode::LetBinding { value, .. } => Self::collect_calls_from_body(value, out),
+            AstNode::Assignment { name, value, .. } => {
+                out.push(name.as_str());
+                Self::collect_calls_from_body(value, out);
             }
             AstNode::If {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
                 Self::collect_calls_from_body(condition, out);
                 Self::collect_calls_from_body(then_block, out);
@@ -284,7 +609,9 @@ impl ModuleCache {
                     Self::collect_calls_from_body(e, out);
                 }
             }
-            AstNode::While { condition, body } => {
+            AstNode::While {
+                condition, body, ..
+            } => {
                 Self::collect_calls_from_body(condition, out);
                 Self::collect_calls_from_body(body, out);
             }
@@ -292,35 +619,59 @@ impl ModuleCache {
                 Self::collect_calls_from_body(iterator, out);
                 Self::collect_calls_from_body(body, out);
             }
-            AstNode::Return(Some(v)) => Self::collect_calls_from_body(v, out),
+            AstNode::Return(Some(v), _) => Self::collect_calls_from_body(v, out),
             AstNode::BinaryOp { left, right, .. } => {
                 Self::collect_calls_from_body(left, out);
                 Self::collect_calls_from_body(right, out);
             }
-            AstNode::UnaryOp { operand, .. } => Self::collect_calls_from_body(operand, out),
-            AstNode::ExpressionStatement(e) => Self::collect_calls_from_body(e, out),
-            AstNode::Match { value, arms } => {
+            AstNode::UnaryOp { operand, .. } | AstNode::Cast { value: operand, .. } => {
+                Self::collect_calls_from_body(operand, out)
+            }
+            AstNode::ExpressionStatement(e, _) => Self::collect_calls_from_body(e, out),
+            AstNode::Match { value, arms, .. } => {
                 Self::collect_calls_from_body(value, out);
                 for arm in arms {
                     Self::collect_calls_from_body(&arm.body, out);
                 }
             }
-            AstNode::ArrayLit(elems) => {
+            AstNode::ArrayLit(elems, _) => {
                 for e in elems {
                     Self::collect_calls_from_body(e, out);
                 }
             }
-            AstNode::StructInit { fields, .. } => {
+            AstNode::StructInit {
+                name, fields, base, ..
+            } => {
+                out.push(name.as_str());
                 for (_, v) in fields {
                     Self::collect_calls_from_body(v, out);
                 }
+                if let Some(b) = base {
+                    Self::collect_calls_from_body(b, out);
+                }
+            }
+            AstNode::StructDef { fields, .. } => {
+                for default in fields.iter().filter_map(|f| f.default.as_ref()) {
+                    Self::collect_calls_from_body(default, out);
+                }
             }
-            AstNode::Index { array, index } => {
+            AstNode::Index { array, index, .. } => {
                 Self::collect_calls_from_body(array, out);
                 Self::collect_calls_from_body(index, out);
             }
-            AstNode::Reference(e) | AstNode::EnumValue { value: Some(e), .. } => {
-                Self::collect_calls_from_body(e, out)
+            AstNode::Slice {
+                value, start, end, ..
+            } => {
+                Self::collect_calls_from_body(value, out);
+                for bound in [start, end].into_iter().flatten() {
+                    Self::collect_calls_from_body(bound, out);
+                }
+            }
+            AstNode::Reference(e, _) => Self::collect_calls_from_body(e, out),
+            AstNode::EnumValue { values, .. } => {
+                for v in values {
+                    Self::collect_calls_from_body(v, out);
+                }
             }
             AstNode::MethodCall { object, args, .. } => {
                 Self::collect_calls_from_body(object, out);
@@ -329,11 +680,20 @@ impl ModuleCache {
                 }
             }
             AstNode::MemberAccess { object, .. } => Self::collect_calls_from_body(object, out),
-            AstNode::ArrayAssignment { index, value, .. } => {
+            AstNode::ArrayAssignment {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                out.push(array.as_str());
                 Self::collect_calls_from_body(index, out);
                 Self::collect_calls_from_body(value, out);
             }
-            AstNode::MemberAssignment { value, .. } => Self::collect_calls_from_body(value, out),
+            AstNode::MemberAssignment { object, value, .. } => {
+                Self::collect_calls_from_body(object, out);
+                Self::collect_calls_from_body(value, out);
+            }
             _ => {}
         }
     }
@@ -352,21 +712,320 @@ impl ModuleCache {
     }
 }
 
+/// The files `import ... from "path"` may name, in the order they are tried:
+/// `path` itself, then `path.brn`, then `path/mod.brn` for a module that is
+/// a directory.
+fn module_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    if path.extension().is_none_or(|ext| ext != "brn") {
+        let mut with_ext = path.as_os_str().to_owned();
+        with_ext.push(".brn");
+        files.push(PathBuf::from(with_ext));
+        files.push(path.join("mod.brn"));
+    }
+    files
+}
+
+/// The target's value for a `@when` condition, or `None` for a name that
+/// isn't one. Programs are compiled for the machine the compiler runs on.
+fn target_value(key: &str) -> Option<&'static str> {
+    match key {
+        "os" => Some(std::env::consts::OS),
+        "arch" => Some(std::env::consts::ARCH),
+        "family" => Some(std::env::consts::FAMILY),
+        _ => None,
+    }
+}
+
+/// `nodes` with every `@when` replaced by the items it selects for the
+/// target. Conditions are checked in both branches, taken or not.
+pub fn select_for_target(nodes: Vec<AstNode>) -> Result<Vec<AstNode>, Diagnostic> {
+    let mut selected = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let AstNode::When {
+            conditions,
+            body,
+            else_body,
+            ..
+        } = node
+        else {
+            selected.push(node);
+            continue;
+        };
+        let mut holds = true;
+        for (key, value, span) in &conditions {
+            let Some(actual) = target_value(key) else {
+                return Err(
+                    Diagnostic::error(format!("unknown '@when' condition '{}'", key))
+                        .at_span(*span)
+                        .with_code("E0303")
+                        .with_help("conditions are 'os', 'arch' and 'family'"),
+                );
+            };
+            holds &= actual == value;
+        }
+        let body = select_for_target(body)?;
+        let else_body = select_for_target(else_body)?;
+        selected.extend(if holds { body } else { else_body });
+    }
+    Ok(selected)
+}
+
+/// Give a module's top-level definition its flattened name from `symbols`,
+/// along with every reference to one of the module's names inside it.
+fn rename_top_level(node: &mut AstNode, symbols: &HashMap<String, String>) {
+    let mut locals = Vec::new();
+    match node {
+        AstNode::FunctionDef {
+            name, params, body, ..
+        } => {
+            if let Some(flat) = symbols.get(name.as_str()) {
+                *name = flat.clone();
+            }
+            locals.extend(params.iter().map(|p| p.name.clone()));
+            rename_node(body, symbols, &mut locals);
+        }
+        AstNode::LetBinding { name, value, .. } => {
+            if let Some(flat) = symbols.get(name.as_str()) {
+                *name = flat.clone();
+            }
+            rename_node(value, symbols, &mut locals);
+        }
+        other => rename_node(other, symbols, &mut locals),
+    }
+}
+
+/// Rename references to the module's top-level names inside `node`, except
+/// where `locals` (the variables in scope) shadow them.
+fn rename_node(node: &mut AstNode, symbols: &HashMap<String, String>, locals: &mut Vec<String>) {
+    let rename = |name: &mut String, locals: &[String]| {
+        if !locals.contains(name)
+            && let Some(flat) = symbols.get(name.as_str())
+        {
+            *name = flat.clone();
+        }
+    };
+    match node {
+        AstNode::Identifier { name, .. } => rename(name, locals),
+        // Variables can't be called, so a local never shadows a function
+        AstNode::Call { name, args, .. } => {
+            rename(name, &[]);
+            for a in args {
+                rename_node(a, symbols, locals);
+            }
+        }
+        AstNode::LetBinding { name, value, .. } => {
+            rename_node(value, symbols, locals);
+            locals.push(name.clone());
+        }
+        AstNode::Assignment { name, value, .. } => {
+            rename(name, locals);
+            rename_node(value, symbols, locals);
+        }
+        AstNode::ArrayAssignment {
+            array,
+            index,
+            value,
+            ..
+        } => {
+            rename(array, locals);
+            rename_node(index, symbols, locals);
+            rename_node(value, symbols, locals);
+        }
+        AstNode::Block(statements, _) => {
+            let scope = locals.len();
+            for s in statements {
+                rename_node(s, symbols, locals);
+            }
+            locals.truncate(scope);
+        }
+        AstNode::For {
+            variable,
+            index,
+            iterator,
+            body,
+            ..
+        } => {
+            rename_node(iterator, symbols, locals);
+            let scope = locals.len();
+            locals.push(variable.clone());
+            locals.extend(index.iter().cloned());
+            rename_node(body, symbols, locals);
+            locals.truncate(scope);
+        }
+        AstNode::Match { value, arms, .. } => {
+            rename_node(value, symbols, locals);
+            for arm in arms {
+                let scope = locals.len();
+                locals.extend(arm.pattern.bindings().into_iter().map(String::from));
+                rename_node(&mut arm.body, symbols, locals);
+                locals.truncate(scope);
+            }
+        }
+        AstNode::FunctionDef { params, body, .. } => {
+            let scope = locals.len();
+            locals.extend(params.iter().map(|p| p.name.clone()));
+            rename_node(body, symbols, locals);
+            locals.truncate(scope);
+        }
+        AstNode::StructDef { fields, .. } => {
+            for default in fields.iter_mut().filter_map(|f| f.default.as_mut()) {
+                rename_node(default, symbols, locals);
+            }
+        }
+        AstNode::StructInit { fields, base, .. } => {
+            for (_, v) in fields {
+                rename_node(v, symbols, locals);
+            }
+            if let Some(b) = base {
+                rename_node(b, symbols, locals);
+            }
+        }
+        AstNode::If {
+            condition,
+            then_block,
+            else_block,
+            ..
+        } => {
+            rename_node(condition, symbols, locals);
+            rename_node(then_block, symbols, locals);
+            if let Some(e) = else_block {
+                rename_node(e, symbols, locals);
+            }
+        }
+        AstNode::While {
+            condition, body, ..
+        } => {
+            rename_node(condition, symbols, locals);
+            rename_node(body, symbols, locals);
+        }
+        AstNode::BinaryOp { left, right, .. } => {
+            rename_node(left, symbols, locals);
+            rename_node(right, symbols, locals);
+        }
+        AstNode::Index { array, index, .. } => {
+            rename_node(array, symbols, locals);
+            rename_node(index, symbols, locals);
+        }
+        AstNode::Slice {
+            value, start, end, ..
+        } => {
+            rename_node(value, symbols, locals);
+            for bound in [start, end].into_iter().flatten() {
+                rename_node(bound, symbols, locals);
+            }
+        }
+        AstNode::MethodCall { object, args, .. } => {
+            rename_node(object, symbols, locals);
+            for a in args {
+                rename_node(a, symbols, locals);
+            }
+        }
+        AstNode::MemberAssignment { object, value, .. } => {
+            rename_node(object, symbols, locals);
+            rename_node(value, symbols, locals);
+        }
+        AstNode::Program(nodes, _) | AstNode::ArrayLit(nodes, _) => {
+            for n in nodes {
+                rename_node(n, symbols, locals);
+            }
+        }
+        AstNode::UnaryOp { operand: n, .. }
+        | AstNode::Cast { value: n, .. }
+        | AstNode::Reference(n, _)
+        | AstNode::MemberAccess { object: n, .. }
+        | AstNode::ExpressionStatement(n, _)
+        | AstNode::Return(Some(n), _) => rename_node(n, symbols, locals),
+        AstNode::EnumValue { values, .. } => {
+            for v in values {
+                rename_node(v, symbols, locals);
+            }
+        }
+        AstNode::Import { .. }
+        | AstNode::When { .. }
+        | AstNode::ExternFn { .. }
+        | AstNode::EnumDef { .. }
+        | AstNode::ArrayType { .. }
+        | AstNode::Number(..)
+        | AstNode::Boolean(..)
+        | AstNode::Null(_)
+        | AstNode::Character(..)
+        | AstNode::StringLit(..)
+        | AstNode::BytesLit(..)
+        | AstNode::SizeOf(..)
+        | AstNode::Return(None, _)
+        | AstNode::Break(_)
+        | AstNode::Continue(_) => {}
+    }
+}
+
+/// Where the standard library lives: `$BRAIN_STD`, a `std` directory next to
+/// the compiler executable, or the `std` directory of the source tree the
+/// compiler was built from. Its modules are imported as `"std/<file>.brn"`.
+pub fn std_root() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("BRAIN_STD") {
+        return Some(PathBuf::from(dir));
+    }
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("std")));
+    let in_source = Path::new(env!("CARGO_MANIFEST_DIR")).join("std");
+    beside_exe
+        .into_iter()
+        .chain([in_source])
+        .find(|dir| dir.is_dir())
+}
+
+/// Resolve `.` and `..` without touching the file system, using `/` as the
+/// separator so virtual paths look the same on every platform.
+fn normalize(path: &Path) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::RootDir => parts.push(String::new()),
+            Component::ParentDir => {
+                if parts.last().is_some_and(|p| p != ".." && !p.is_empty()) {
+                    parts.pop();
+                } else {
+                    parts.push("..".to_string());
+                }
+            }
+            other => parts.push(other.as_os_str().to_string_lossy().into_owned()),
+        }
+    }
+    parts.join("/")
+}
+
 pub fn resolve_imports(
     ast: AstNode,
     cache: &mut ModuleCache,
     file: &str,
-) -> Result<AstNode, String> {
-    if let AstNode::Program(nodes) = ast {
-        let mut resolved: Vec<AstNode> = Vec::new();
-        // Global dedup across all import statements in this file.
-        let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
+) -> Result<AstNode, Diagnostic> {
+    if let AstNode::Program(nodes, span) = ast {
+        let nodes = select_for_target(nodes)?;
+        let mut resolved: Vec<Rc<AstNode>> = Vec::new();
+        // Global dedup across all import statements in this file. The
+        // program's own definitions come first: a module that imports the
+        // program back hands them out again.
+        let mut seen: std::collections::HashSet<String> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                AstNode::FunctionDef { name, .. }
+                | AstNode::LetBinding { name, .. }
+                | AstNode::StructDef { name, .. }
+                | AstNode::EnumDef { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
         for node in nodes {
             match node {
-                AstNode::Import { names, path } => {
-                    let defs = cache.import(file, &path, &names)?;
+                AstNode::Import {
+                    names, path, span, ..
+                } => {
+                    let defs = cache.import(file, &path, &names, span)?;
                     for def in defs {
-                        match &def {
+                        match def.as_ref() {
                             AstNode::FunctionDef { name, .. }
                             | AstNode::LetBinding { name, .. }
                             | AstNode::StructDef { name, .. }
@@ -375,14 +1034,46 @@ pub fn resolve_imports(
                                     resolved.push(def);
                                 }
                             }
-                            other => resolved.push(other.clone()),
+                            // A module imported twice runs its statements once
+                            _ => {
+                                if !resolved.iter().any(|seen| Rc::ptr_eq(seen, &def)) {
+                                    resolved.push(def);
+                                }
+                            }
                         }
                     }
                 }
-                other => resolved.push(other),
+                other => resolved.push(Rc::new(other)),
+            }
+        }
+
+        // The prelude goes first; anything the program defines or imports
+        // under the same name replaces it.
+        for node in &resolved {
+            if let AstNode::FunctionDef { name, .. }
+            | AstNode::LetBinding { name, .. }
+            | AstNode::StructDef { name, .. }
+            | AstNode::EnumDef { name, .. } = node.as_ref()
+            {
+                seen.insert(name.clone());
             }
         }
-        Ok(AstNode::Program(resolved))
+        let mut program: Vec<Rc<AstNode>> = cache
+            .prelude_definitions()?
+            .into_iter()
+            .filter(|def| match def.as_ref() {
+                AstNode::FunctionDef { name, .. }
+                | AstNode::LetBinding { name, .. }
+                | AstNode::StructDef { name, .. }
+                | AstNode::EnumDef { name, .. } => !seen.contains(name),
+                _ => !resolved.iter().any(|node| Rc::ptr_eq(node, def)),
+            })
+            .collect();
+        program.extend(resolved);
+        // Only definitions the program actually uses are copied out of the
+        // cache, each once; its own nodes are moved.
+        let program = program.into_iter().map(Rc::unwrap_or_clone).collect();
+        Ok(AstNode::Program(program, span))
     } else {
         Ok(ast)
     }
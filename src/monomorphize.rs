@@ -0,0 +1,568 @@
+//! Monomorphization: a generic function is compiled once per concrete type
+//! signature.
+//!
+//! A template is an ordinary `FunctionDef` whose types mention type
+//! parameters (`T`, `Vec<T>`, `&T`, ...). Requesting `max` at `[int]` queues
+//! a copy with every `T` replaced by `int`, named `max$3int`; code
+//! generation drains the queue and compiles each copy like any other
+//! function. Requesting the same signature again gives the same name
+//! without queueing a second copy, and copies may request further
+//! instances while they are generated.
+//!
+//! A type parameter can be bounded, `fn sort<T: Ord>(v: Vec<T>)`: every
+//! request is checked against the bounds before anything is queued, so a
+//! type that can't do what the template needs is reported at the request
+//! by the capability it lacks, rather than as broken code in the copy.
+//!
+//! ```
+//! use brain::monomorphize::{Monomorphizer, TypeParam};
+//! use brain::parser::AstNode;
+//!
+//! # let def = |name: &str| AstNode::FunctionDef {
+//! #     name: name.to_string(),
+//! #     params: Vec::new(),
+//! #     return_type: None,
+//! #     body: Box::new(AstNode::Block(Vec::new(), Default::default())),
+//! #     is_exported: false,
+//! #     is_unsafe: false,
+//! #     attributes: Vec::new(),
+//! #     span: Default::default(),
+//! # };
+//! let mut instances = Monomorphizer::new();
+//! instances.add_template(vec![TypeParam::parse("T: Ord").unwrap()], def("sort"));
+//! assert_eq!(instances.request("sort", &["int".to_string()]).unwrap(), "sort$3int");
+//! assert!(instances.request("sort", &["string".to_string()]).is_ok());
+//!
+//! let error = instances.request("sort", &["bool".to_string()]).unwrap_err();
+//! assert!(error.message.contains("does not implement 'Ord'"));
+//! ```
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{AstNode, Parameter, Pattern};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// What a bound asks of a type argument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    /// Compared with `==` and `!=`.
+    Eq,
+    /// Ordered with `<`, `>`, `<=` and `>=`.
+    Ord,
+    /// Copied rather than moved when assigned or passed.
+    Copy,
+    /// Turned into text with `to_string`.
+    Display,
+}
+
+impl Bound {
+    pub fn from_name(name: &str) -> Option<Bound> {
+        match name {
+            "Eq" => Some(Bound::Eq),
+            "Ord" => Some(Bound::Ord),
+            "Copy" => Some(Bound::Copy),
+            "Display" => Some(Bound::Display),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Bound::Eq => "Eq",
+            Bound::Ord => "Ord",
+            Bound::Copy => "Copy",
+            Bound::Display => "Display",
+        }
+    }
+
+    fn capability(self) -> &'static str {
+        match self {
+            Bound::Eq => "compared with '==' and '!='",
+            Bound::Ord => "ordered with '<', '>', '<=' and '>='",
+            Bound::Copy => "copied instead of moved",
+            Bound::Display => "turned into text with 'to_string'",
+        }
+    }
+
+    /// The types that have it, for the help of an unmet bound.
+    fn satisfied_by(self) -> &'static str {
+        match self {
+            Bound::Eq => "int, bool, char, string, pointers and enums",
+            Bound::Ord => "int, char and string",
+            Bound::Copy => "int, bool, char, pointers and small structs of those",
+            Bound::Display => "int, bool, char and string",
+        }
+    }
+}
+
+/// A type parameter and the bounds its arguments must meet: `T: Ord + Copy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Vec<Bound>,
+}
+
+impl TypeParam {
+    /// Parse `T` or `T: Bound + Bound`.
+    pub fn parse(text: &str) -> Result<TypeParam, Diagnostic> {
+        let (name, bounds) = text.split_once(':').unwrap_or((text, ""));
+        let bounds = bounds
+            .split('+')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                Bound::from_name(b).ok_or_else(|| {
+                    Diagnostic::error(format!("unknown bound '{}' on '{}'", b, name.trim()))
+                        .with_code("E0414")
+                        .with_help("the bounds are 'Eq', 'Ord', 'Copy' and 'Display'")
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TypeParam {
+            name: name.trim().to_string(),
+            bounds,
+        })
+    }
+}
+
+impl std::fmt::Display for TypeParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (i, bound) in self.bounds.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { " + " };
+            write!(f, "{}{}", sep, bound.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+struct Template {
+    type_params: Vec<TypeParam>,
+    def: AstNode,
+}
+
+#[derive(Default)]
+pub struct Monomorphizer {
+    templates: HashMap<String, Template>,
+    /// Mangled names of every instance requested so far.
+    requested: HashSet<String>,
+    /// Instances requested but not yet handed out: template and type
+    /// arguments.
+    queue: VecDeque<(String, Vec<String>)>,
+    /// Structs small enough to be copied, for `Copy` bounds.
+    value_structs: HashSet<String>,
+    /// Every enum's name, for `Eq` bounds.
+    enums: HashSet<String>,
+}
+
+impl Monomorphizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The program's value structs and enums, which some bounds accept.
+    pub fn set_types(&mut self, value_structs: HashSet<String>, enums: HashSet<String>) {
+        self.value_structs = value_structs;
+        self.enums = enums;
+    }
+
+    /// Make the `FunctionDef` `def` generic over `type_params`.
+    pub fn add_template(&mut self, type_params: Vec<TypeParam>, def: AstNode) {
+        if let AstNode::FunctionDef { name, .. } = &def {
+            self.templates
+                .insert(name.clone(), Template { type_params, def });
+        }
+    }
+
+    pub fn is_template(&self, name: &str) -> bool {
+        self.templates.contains_key(name)
+    }
+
+    /// The function name of `template` at `type_args`, queueing the
+    /// instance the first time it is asked for.
+    pub fn request(&mut self, template: &str, type_args: &[String]) -> Result<String, Diagnostic> {
+        let Some(found) = self.templates.get(template) else {
+            return Err(
+                Diagnostic::error(format!("'{}' is not a generic function", template))
+                    .with_code("E0414"),
+            );
+        };
+        if found.type_params.len() != type_args.len() {
+            return Err(Diagnostic::error(format!(
+                "'{}' takes {} type argument(s) but {} were given",
+                template,
+                found.type_params.len(),
+                type_args.len()
+            ))
+            .with_code("E0414")
+            .with_note(format!("declared as {}", declared(template, found))));
+        }
+        for (param, arg) in found.type_params.iter().zip(type_args) {
+            if let Some(&bound) = param.bounds.iter().find(|b| !self.satisfies(arg, **b)) {
+                return Err(Diagnostic::error(format!(
+                    "the type '{}' does not implement '{}', which '{}' requires of '{}'",
+                    arg.trim(),
+                    bound.as_str(),
+                    template,
+                    param.name
+                ))
+                .with_code("E0414")
+                .with_note(format!(
+                    "'{}' is for types that can be {}; '{}' can't",
+                    bound.as_str(),
+                    bound.capability(),
+                    arg.trim()
+                ))
+                .with_note(format!("declared as {}", declared(template, found)))
+                .with_help(format!(
+                    "'{}' is implemented by {}",
+                    bound.as_str(),
+                    bound.satisfied_by()
+                )));
+            }
+        }
+        let name = mangle(template, type_args);
+        if self.requested.insert(name.clone()) {
+            self.queue
+                .push_back((template.to_string(), type_args.to_vec()));
+        }
+        Ok(name)
+    }
+
+    /// The parameters and return type of `template` at `type_args`, for
+    /// call sites generated before the instance itself.
+    pub fn signature(
+        &self,
+        template: &str,
+        type_args: &[String],
+    ) -> Option<(Vec<Parameter>, Option<String>)> {
+        let found = self.templates.get(template)?;
+        let AstNode::FunctionDef {
+            params,
+            return_type,
+            ..
+        } = &found.def
+        else {
+            return None;
+        };
+        let map = bindings(&found.type_params, type_args);
+        let params = params
+            .iter()
+            .map(|p| Parameter {
+                param_type: substitute(&p.param_type, &map),
+                ..p.clone()
+            })
+            .collect();
+        Some((params, return_type.as_ref().map(|t| substitute(t, &map))))
+    }
+
+    /// The next queued instance: the template's `FunctionDef` renamed to
+    /// its mangled name with the type arguments substituted throughout.
+    pub fn next_instance(&mut self) -> Option<AstNode> {
+        let (template, type_args) = self.queue.pop_front()?;
+        let found = &self.templates[&template];
+        let map = bindings(&found.type_params, &type_args);
+        let mut def = found.def.clone();
+        if let AstNode::FunctionDef { name, .. } = &mut def {
+            *name = mangle(&template, &type_args);
+        }
+        substitute_node(&mut def, &map);
+        Some(def)
+    }
+
+    /// Whether `ty` meets `bound`. Type parameters are substituted before
+    /// a request, so `ty` is always concrete.
+    fn satisfies(&self, ty: &str, bound: Bound) -> bool {
+        let ty = ty.trim();
+        let is_pointer = ty.starts_with('*');
+        match bound {
+            Bound::Eq => {
+                matches!(ty, "int" | "bool" | "char" | "string")
+                    || is_pointer
+                    || self.enums.contains(ty)
+            }
+            Bound::Ord => matches!(ty, "int" | "char" | "string"),
+            Bound::Copy => {
+                matches!(ty, "int" | "bool" | "char")
+                    || is_pointer
+                    || self.value_structs.contains(ty)
+            }
+            Bound::Display => matches!(ty, "int" | "bool" | "char" | "string"),
+        }
+    }
+}
+
+/// `max<T: Ord>`, for notes about a template.
+fn declared(template: &str, found: &Template) -> String {
+    let params: Vec<String> = found.type_params.iter().map(TypeParam::to_string).collect();
+    format!("'{}<{}>'", template, params.join(", "))
+}
+
+fn bindings<'a>(params: &'a [TypeParam], args: &'a [String]) -> HashMap<&'a str, &'a str> {
+    params
+        .iter()
+        .map(|p| p.name.as_str())
+        .zip(args.iter().map(String::as_str))
+        .collect()
+}
+
+/// `template` at `type_args` as a function name: `max` at `[int]` is
+/// `max$3int`, `first` at `[Vec<string>]` is `first$3VecI6stringE`. Each
+/// argument is encoded prefix-free, so different signatures never collide.
+pub fn mangle(template: &str, type_args: &[String]) -> String {
+    let mut name = format!("{}$", template);
+    for ty in type_args {
+        mangle_type(ty.trim(), &mut name);
+    }
+    name
+}
+
+/// Append the encoding of `ty`: a name is its length then the name, with
+/// `I...E` around generic arguments; `P` is `*`, `R` is `&`, `M` is `&mut`
+/// and `A<n>_` a `[T; n]` array.
+fn mangle_type(ty: &str, out: &mut String) {
+    if let Some(inner) = ty.strip_prefix("&mut ") {
+        out.push('M');
+        mangle_type(inner.trim(), out);
+    } else if let Some(inner) = ty.strip_prefix('&') {
+        out.push('R');
+        mangle_type(inner.trim(), out);
+    } else if let Some(inner) = ty.strip_prefix('*') {
+        out.push('P');
+        mangle_type(inner.trim(), out);
+    } else if let Some((elem, size)) = split_array(ty) {
+        out.push_str(&format!("A{}_", size));
+        mangle_type(elem, out);
+    } else if let Some((base, args)) = split_generic(ty) {
+        out.push_str(&format!("{}{}I", base.len(), base));
+        for arg in args {
+            mangle_type(arg, out);
+        }
+        out.push('E');
+    } else {
+        out.push_str(&format!("{}{}", ty.len(), ty));
+    }
+}
+
+/// `ty` with each type parameter in `map` replaced, however deeply it is
+/// nested: `Vec<T>` with `T = int` is `Vec<int>`.
+pub fn substitute(ty: &str, map: &HashMap<&str, &str>) -> String {
+    let ty = ty.trim();
+    if let Some(inner) = ty.strip_prefix("&mut ") {
+        format!("&mut {}", substitute(inner, map))
+    } else if let Some(inner) = ty.strip_prefix('&') {
+        format!("&{}", substitute(inner, map))
+    } else if let Some(inner) = ty.strip_prefix('*') {
+        format!("*{}", substitute(inner, map))
+    } else if let Some((elem, size)) = split_array(ty) {
+        format!("[{}; {}]", substitute(elem, map), size)
+    } else if let Some((base, args)) = split_generic(ty) {
+        let args: Vec<String> = args.iter().map(|a| substitute(a, map)).collect();
+        format!("{}<{}>", base, args.join(", "))
+    } else {
+        map.get(ty)
+            .map_or_else(|| ty.to_string(), |t| t.to_string())
+    }
+}
+
+/// `[T; n]` → `("T", "n")`.
+fn split_array(ty: &str) -> Option<(&str, &str)> {
+    let inner = ty.strip_prefix('[')?.strip_suffix(']')?;
+    let (elem, size) = inner.rsplit_once(';')?;
+    Some((elem.trim(), size.trim()))
+}
+
+/// `Name<A, B>` → `("Name", ["A", "B"])`, splitting only at top-level
+/// commas.
+fn split_generic(ty: &str) -> Option<(&str, Vec<&str>)> {
+    let open = ty.find('<')?;
+    let inner = ty[open + 1..].strip_suffix('>')?;
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '<' | '[' => depth += 1,
+            '>' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(inner[start..].trim());
+    Some((&ty[..open], args))
+}
+
+/// Substitute type parameters in every type written in `node`: parameter
+/// and return types, `let` annotations, and the type names of struct
+/// literals, enum values and patterns.
+fn substitute_node(node: &mut AstNode, map: &HashMap<&str, &str>) {
+    let sub = |ty: &mut String| *ty = substitute(ty, map);
+    match node {
+        AstNode::FunctionDef {
+            params,
+            return_type,
+            body,
+            ..
+        } => {
+            for p in params {
+                sub(&mut p.param_type);
+            }
+            if let Some(rt) = return_type {
+                sub(rt);
+            }
+            substitute_node(body, map);
+        }
+        AstNode::LetBinding {
+            type_annotation,
+            value,
+            ..
+        } => {
+            if let Some(ty) = type_annotation {
+                sub(ty);
+            }
+            substitute_node(value, map);
+        }
+        AstNode::StructInit {
+            name, fields, base, ..
+        } => {
+            sub(name);
+            for (_, v) in fields {
+                substitute_node(v, map);
+            }
+            if let Some(b) = base {
+                substitute_node(b, map);
+            }
+        }
+        AstNode::EnumValue {
+            enum_name, values, ..
+        } => {
+            sub(enum_name);
+            for v in values {
+                substitute_node(v, map);
+            }
+        }
+        AstNode::Match { value, arms, .. } => {
+            substitute_node(value, map);
+            for arm in arms {
+                substitute_pattern(&mut arm.pattern, map);
+                substitute_node(&mut arm.body, map);
+            }
+        }
+        AstNode::Program(nodes, _) | AstNode::Block(nodes, _) | AstNode::ArrayLit(nodes, _) => {
+            for n in nodes {
+                substitute_node(n, map);
+            }
+        }
+        AstNode::Call { args, .. } => {
+            for a in args {
+                substitute_node(a, map);
+            }
+        }
+        AstNode::MethodCall { object, args, .. } => {
+            substitute_node(object, map);
+            for a in args {
+                substitute_node(a, map);
+            }
+        }
+        AstNode::Assignment { value, .. } => substitute_node(value, map),
+        AstNode::ArrayAssignment { index, value, .. } => {
+            substitute_node(index, map);
+            substitute_node(value, map);
+        }
+        AstNode::MemberAssignment { object, value, .. } => {
+            substitute_node(object, map);
+            substitute_node(value, map);
+        }
+        AstNode::BinaryOp { left, right, .. } => {
+            substitute_node(left, map);
+            substitute_node(right, map);
+        }
+        AstNode::Index { array, index, .. } => {
+            substitute_node(array, map);
+            substitute_node(index, map);
+        }
+        AstNode::Slice {
+            value, start, end, ..
+        } => {
+            substitute_node(value, map);
+            for bound in [start, end].into_iter().flatten() {
+                substitute_node(bound, map);
+            }
+        }
+        AstNode::If {
+            condition,
+            then_block,
+            else_block,
+            ..
+        } => {
+            substitute_node(condition, map);
+            substitute_node(then_block, map);
+            if let Some(e) = else_block {
+                substitute_node(e, map);
+            }
+        }
+        AstNode::While {
+            condition, body, ..
+        } => {
+            substitute_node(condition, map);
+            substitute_node(body, map);
+        }
+        AstNode::For { iterator, body, .. } => {
+            substitute_node(iterator, map);
+            substitute_node(body, map);
+        }
+        AstNode::SizeOf(ty, _) => sub(ty),
+        AstNode::Cast { value, target, .. } => {
+            sub(target);
+            substitute_node(value, map);
+        }
+        AstNode::UnaryOp { operand: n, .. }
+        | AstNode::Reference(n, _)
+        | AstNode::MemberAccess { object: n, .. }
+        | AstNode::ExpressionStatement(n, _)
+        | AstNode::Return(Some(n), _) => substitute_node(n, map),
+        AstNode::Import { .. }
+        | AstNode::When { .. }
+        | AstNode::ExternFn { .. }
+        | AstNode::StructDef { .. }
+        | AstNode::EnumDef { .. }
+        | AstNode::ArrayType { .. }
+        | AstNode::Number(_, _)
+        | AstNode::Boolean(_, _)
+        | AstNode::Null(_)
+        | AstNode::Character(_, _)
+        | AstNode::StringLit(_, _)
+        | AstNode::BytesLit(_, _)
+        | AstNode::Identifier { .. }
+        | AstNode::Return(None, _)
+        | AstNode::Break(_)
+        | AstNode::Continue(_) => {}
+    }
+}
+
+/// Rename the enums and structs a pattern names, nested ones included.
+fn substitute_pattern(pattern: &mut Pattern, map: &HashMap<&str, &str>) {
+    match pattern {
+        Pattern::EnumPattern {
+            enum_name, payload, ..
+        } => {
+            *enum_name = substitute(enum_name, map);
+            for p in payload {
+                substitute_pattern(p, map);
+            }
+        }
+        Pattern::StructPattern {
+            struct_name,
+            fields,
+            ..
+        } => {
+            *struct_name = substitute(struct_name, map);
+            for (_, p) in fields {
+                substitute_pattern(p, map);
+            }
+        }
+        _ => {}
+    }
+}
@@ -1,4 +1,10 @@
-use crate::lexer::{Token, TokenType};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Interner, Token, TokenType, Tokens};
+use crate::lint;
+use std::fs;
+use std::path::Path;
+
+pub use crate::lexer::Span;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Location {
@@ -6,13 +12,26 @@ pub struct Location {
     pub column: usize,
 }
 
+impl From<Span> for Location {
+    fn from(span: Span) -> Self {
+        Location {
+            line: span.line,
+            column: span.column,
+        }
+    }
+}
+
+/// A node of the syntax tree. Every node carries the span of source it was
+/// parsed from; nodes the compiler makes up itself have the span of the
+/// code they stand for, or `Span::default()`.
 #[derive(Debug, Clone)]
 pub enum AstNode {
-    Program(Vec<AstNode>),
+    Program(Vec<AstNode>, Span),
 
     Import {
         names: Vec<String>,
         path: String,
+        span: Span,
     },
 
     LetBinding {
@@ -20,13 +39,15 @@ pub enum AstNode {
         name: String,
         type_annotation: Option<String>,
         value: Box<AstNode>,
-        location: Location,
+        span: Span,
         is_exported: bool,
+        /// `#[...]` before a top-level binding.
+        attributes: Vec<Attribute>,
     },
     Assignment {
         name: String,
         value: Box<AstNode>,
-        location: Location,
+        span: Span,
     },
 
     FunctionDef {
@@ -36,109 +57,353 @@ pub enum AstNode {
         body: Box<AstNode>,
         is_exported: bool,
         is_unsafe: bool,
+        attributes: Vec<Attribute>,
+        span: Span,
+    },
+
+    /// `extern fn name(params, ...) -> T;`: a function defined outside the
+    /// program, called by its own symbol name. `variadic` when the
+    /// parameters end in `...`.
+    ExternFn {
+        name: String,
+        params: Vec<Parameter>,
+        variadic: bool,
+        return_type: Option<String>,
+        is_exported: bool,
+        span: Span,
     },
 
     StructDef {
         name: String,
         fields: Vec<Field>,
         is_exported: bool,
+        attributes: Vec<Attribute>,
+        span: Span,
     },
+    /// `Name { field: value, ..base }`: fields left out come from `base`
+    /// when there is one, and otherwise from the struct's defaults.
     StructInit {
         name: String,
         fields: Vec<(String, AstNode)>,
+        base: Option<Box<AstNode>>,
+        span: Span,
     },
 
     EnumDef {
         name: String,
         variants: Vec<EnumVariant>,
         is_exported: bool,
+        attributes: Vec<Attribute>,
+        span: Span,
     },
+    /// `Enum::Variant`, or `Enum::Variant(a, ...)` with a value for each
+    /// of the variant's fields.
     EnumValue {
         enum_name: String,
         variant: String,
-        value: Option<Box<AstNode>>,
+        values: Vec<AstNode>,
+        span: Span,
     },
 
-    ArrayLit(Vec<AstNode>),
+    ArrayLit(Vec<AstNode>, Span),
+    #[allow(dead_code)]
     ArrayType {
         element_type: String,
         size: usize,
+        span: Span,
     },
     Index {
         array: Box<AstNode>,
         index: Box<AstNode>,
+        span: Span,
     },
 
     ArrayAssignment {
         array: String,
         index: Box<AstNode>,
         value: Box<AstNode>,
-        location: Location,
+        span: Span,
     },
 
+    /// `object.field = value`, where `object` is a variable or a chain of
+    /// member accesses (`a.b.c = value`).
     MemberAssignment {
-        object: String,
+        object: Box<AstNode>,
         field: String,
         value: Box<AstNode>,
-        location: Location,
+        span: Span,
     },
 
     BinaryOp {
         op: BinOp,
         left: Box<AstNode>,
         right: Box<AstNode>,
+        /// Where the operator is, for runtime errors like division by zero.
+        location: Location,
+        span: Span,
     },
     UnaryOp {
         op: UnOp,
         operand: Box<AstNode>,
+        span: Span,
     },
-    Number(i64),
-    Boolean(bool),
-    Character(char),
-    StringLit(String),
+    /// `value as T`: an explicit conversion between `int`, `char` and
+    /// `bool`, where an implicit one could lose information.
+    Cast {
+        value: Box<AstNode>,
+        target: String,
+        span: Span,
+    },
+    Number(i64, Span),
+    Boolean(bool, Span),
+    /// `null`: the empty value of a `*T` pointer.
+    Null(Span),
+    Character(char, Span),
+    StringLit(String, Span),
+    /// `include_bytes("path")`: a file's contents, read at compile time.
+    BytesLit(Vec<u8>, Span),
+    /// `size_of(T)`: the bytes a `T` takes up, known at compile time.
+    SizeOf(String, Span),
     Identifier {
         name: String,
-        location: Location,
+        span: Span,
     },
-    Reference(Box<AstNode>),
+    Reference(Box<AstNode>, Span),
     Call {
         name: String,
         args: Vec<AstNode>,
+        span: Span,
     },
     MethodCall {
         object: Box<AstNode>,
         method: String,
         args: Vec<AstNode>,
+        span: Span,
     },
     MemberAccess {
         object: Box<AstNode>,
         field: String,
+        span: Span,
+    },
+    /// `value[start..end]`: a new string copied from part of `value`. A
+    /// missing bound is the start or the end of the string.
+    Slice {
+        value: Box<AstNode>,
+        start: Option<Box<AstNode>>,
+        end: Option<Box<AstNode>>,
+        span: Span,
     },
 
     If {
         condition: Box<AstNode>,
         then_block: Box<AstNode>,
         else_block: Option<Box<AstNode>>,
+        span: Span,
     },
     While {
         condition: Box<AstNode>,
         body: Box<AstNode>,
+        span: Span,
     },
+    /// `for variable in iterator`, or `for (index, variable) in
+    /// enumerate(iterator)` when `index` is set.
     For {
         variable: String,
+        index: Option<String>,
         iterator: Box<AstNode>,
         body: Box<AstNode>,
+        span: Span,
     },
     Match {
         value: Box<AstNode>,
         arms: Vec<MatchArm>,
+        span: Span,
+    },
+    Return(Option<Box<AstNode>>, Span),
+    Break(Span),
+    Continue(Span),
+
+    Block(Vec<AstNode>, Span),
+    ExpressionStatement(Box<AstNode>, Span),
+
+    /// `@when(key = "value", ...) { ... } else { ... }`: top-level items
+    /// compiled only when every condition holds for the target, or else the
+    /// `else` items. Import resolution replaces it with the items chosen.
+    When {
+        conditions: Vec<(String, String, Span)>,
+        body: Vec<AstNode>,
+        else_body: Vec<AstNode>,
+        span: Span,
     },
-    Return(Option<Box<AstNode>>),
-    Break,
-    Continue,
+}
+
+impl AstNode {
+    /// The nodes directly inside this one, in source order. `@when` items
+    /// are left out: they are only part of the program once chosen.
+    pub fn children(&self) -> Vec<&AstNode> {
+        match self {
+            AstNode::Program(nodes, _) | AstNode::ArrayLit(nodes, _) | AstNode::Block(nodes, _) => {
+                nodes.iter().collect()
+            }
+            AstNode::Call { args, .. } | AstNode::EnumValue { values: args, .. } => {
+                args.iter().collect()
+            }
+            AstNode::MethodCall { object, args, .. } => {
+                std::iter::once(object.as_ref()).chain(args).collect()
+            }
+            AstNode::StructInit { fields, base, .. } => fields
+                .iter()
+                .map(|(_, value)| value)
+                .chain(base.as_deref())
+                .collect(),
+            AstNode::StructDef { fields, .. } => {
+                fields.iter().filter_map(|f| f.default.as_deref()).collect()
+            }
+            AstNode::Match { value, arms, .. } => std::iter::once(value.as_ref())
+                .chain(arms.iter().map(|arm| &arm.body))
+                .collect(),
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => [
+                Some(condition.as_ref()),
+                Some(then_block.as_ref()),
+                else_block.as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            AstNode::Slice {
+                value, start, end, ..
+            } => [Some(value.as_ref()), start.as_deref(), end.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::BinaryOp { left, right, .. } => vec![left, right],
+            AstNode::Index { array, index, .. } => vec![array, index],
+            AstNode::ArrayAssignment { index, value, .. } => vec![index, value],
+            AstNode::MemberAssignment { object, value, .. } => vec![object, value],
+            AstNode::While {
+                condition, body, ..
+            } => vec![condition, body],
+            AstNode::For { iterator, body, .. } => vec![iterator, body],
+            AstNode::LetBinding { value, .. }
+            | AstNode::Assignment { value, .. }
+            | AstNode::UnaryOp { operand: value, .. }
+            | AstNode::Cast { value, .. }
+            | AstNode::MemberAccess { object: value, .. }
+            | AstNode::FunctionDef { body: value, .. }
+            | AstNode::Reference(value, _)
+            | AstNode::ExpressionStatement(value, _)
+            | AstNode::Return(Some(value), _) => vec![value],
+            AstNode::Import { .. }
+            | AstNode::When { .. }
+            | AstNode::ExternFn { .. }
+            | AstNode::EnumDef { .. }
+            | AstNode::ArrayType { .. }
+            | AstNode::Number(..)
+            | AstNode::Boolean(..)
+            | AstNode::Null(_)
+            | AstNode::Character(..)
+            | AstNode::StringLit(..)
+            | AstNode::BytesLit(..)
+            | AstNode::SizeOf(..)
+            | AstNode::Identifier { .. }
+            | AstNode::Return(None, _)
+            | AstNode::Break(_)
+            | AstNode::Continue(_) => Vec::new(),
+        }
+    }
+
+    /// Whether this top-level node declares something, rather than being a
+    /// statement for its module to run before `main`.
+    pub fn is_declaration(&self) -> bool {
+        matches!(
+            self,
+            AstNode::FunctionDef { .. }
+                | AstNode::LetBinding { .. }
+                | AstNode::StructDef { .. }
+                | AstNode::EnumDef { .. }
+                | AstNode::ExternFn { .. }
+                | AstNode::Import { .. }
+                | AstNode::When { .. }
+        )
+    }
+
+    /// Whether this is an item marked `#[name]`.
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes().iter().any(|a| a.name == name)
+    }
+
+    /// The `#[...]` attributes of a function, binding, struct or enum.
+    pub fn attributes(&self) -> &[Attribute] {
+        match self {
+            AstNode::FunctionDef { attributes, .. }
+            | AstNode::LetBinding { attributes, .. }
+            | AstNode::StructDef { attributes, .. }
+            | AstNode::EnumDef { attributes, .. } => attributes,
+            _ => &[],
+        }
+    }
+
+    fn attributes_mut(&mut self) -> Option<&mut Vec<Attribute>> {
+        match self {
+            AstNode::FunctionDef { attributes, .. }
+            | AstNode::LetBinding { attributes, .. }
+            | AstNode::StructDef { attributes, .. }
+            | AstNode::EnumDef { attributes, .. } => Some(attributes),
+            _ => None,
+        }
+    }
 
-    Block(Vec<AstNode>),
-    ExpressionStatement(Box<AstNode>),
+    /// The source this node was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            AstNode::Program(_, span)
+            | AstNode::ArrayLit(_, span)
+            | AstNode::Number(_, span)
+            | AstNode::Boolean(_, span)
+            | AstNode::Null(span)
+            | AstNode::Character(_, span)
+            | AstNode::StringLit(_, span)
+            | AstNode::BytesLit(_, span)
+            | AstNode::SizeOf(_, span)
+            | AstNode::Reference(_, span)
+            | AstNode::Return(_, span)
+            | AstNode::Break(span)
+            | AstNode::Continue(span)
+            | AstNode::Block(_, span)
+            | AstNode::ExpressionStatement(_, span)
+            | AstNode::Import { span, .. }
+            | AstNode::LetBinding { span, .. }
+            | AstNode::Assignment { span, .. }
+            | AstNode::FunctionDef { span, .. }
+            | AstNode::ExternFn { span, .. }
+            | AstNode::StructDef { span, .. }
+            | AstNode::StructInit { span, .. }
+            | AstNode::EnumDef { span, .. }
+            | AstNode::EnumValue { span, .. }
+            | AstNode::ArrayType { span, .. }
+            | AstNode::Index { span, .. }
+            | AstNode::ArrayAssignment { span, .. }
+            | AstNode::MemberAssignment { span, .. }
+            | AstNode::BinaryOp { span, .. }
+            | AstNode::UnaryOp { span, .. }
+            | AstNode::Cast { span, .. }
+            | AstNode::Identifier { span, .. }
+            | AstNode::Call { span, .. }
+            | AstNode::MethodCall { span, .. }
+            | AstNode::MemberAccess { span, .. }
+            | AstNode::Slice { span, .. }
+            | AstNode::If { span, .. }
+            | AstNode::While { span, .. }
+            | AstNode::For { span, .. }
+            | AstNode::Match { span, .. }
+            | AstNode::When { span, .. } => *span,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -165,6 +430,42 @@ pub enum UnOp {
     Negate,
 }
 
+/// `#[name]` on a top-level function, binding, struct or enum:
+///
+/// - `inline` / `noinline`: always or never inline calls to the function
+/// - `no_mangle`: define the function under its own name, so code outside
+///   the program can call it
+/// - `startup` / `shutdown`: run the function when the program starts,
+///   before `main`, or when `main` returns
+/// - `test`: a test for `brain test`, whatever the function is called
+/// - `used`: keep the function or binding even if nothing reaches it
+/// - `allow(lint, ...)`, `warn(...)`, `deny(...)`: the level of the lints
+///   named (see [`crate::lint`]); `#![allow(...)]` at the top of a file
+///   sets it for every item in the file
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    /// The lints named in `allow(...)`, `warn(...)` or `deny(...)`.
+    pub args: Vec<String>,
+    pub span: Span,
+}
+
+/// The attributes each kind of item can have.
+const LINT_ATTRIBUTES: &[&str] = &["allow", "warn", "deny"];
+const FUNCTION_ATTRIBUTES: &[&str] = &[
+    "inline",
+    "noinline",
+    "no_mangle",
+    "startup",
+    "shutdown",
+    "test",
+    "used",
+    "allow",
+    "warn",
+    "deny",
+];
+const BINDING_ATTRIBUTES: &[&str] = &["used", "allow", "warn", "deny"];
+
 #[derive(Debug, Clone)]
 pub struct Parameter {
     pub is_reference: bool,
@@ -177,12 +478,71 @@ pub struct Parameter {
 pub struct Field {
     pub name: String,
     pub field_type: String,
+    /// `name: type = value`: used when a struct literal leaves the field out.
+    pub default: Option<Box<AstNode>>,
+}
+
+/// Structs with at most this many fields, all of them `int`, `bool` or
+/// `char`, are values: copied on assignment and passed and returned whole
+/// instead of through a heap pointer.
+pub const MAX_VALUE_STRUCT_FIELDS: usize = 4;
+
+/// Whether a struct with these field types is a value struct.
+pub fn is_value_struct<'a>(field_types: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut count = 0;
+    for ty in field_types {
+        if !matches!(ty, "int" | "bool" | "char") {
+            return false;
+        }
+        count += 1;
+    }
+    (1..=MAX_VALUE_STRUCT_FIELDS).contains(&count)
+}
+
+/// Who frees the string an expression evaluates to. Semantic analysis and
+/// code generation share these rules:
+/// - a variable, a `Vec<string>` slot or a by-value `string` parameter owns
+///   the string it holds and frees it; it takes an `Owned` string as it is,
+///   moves one out of a variable that owns it when the variable is moved,
+///   and copies anything else (an immutable `let` of a literal just points
+///   at the literal)
+/// - `+` only reads its operands, and frees those that were `Owned` once
+///   the result is built
+/// - a function returning `string` hands the caller an `Owned` one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringOwnership {
+    /// A literal, part of the program: never freed.
+    Static,
+    /// Somebody else's: a variable, a field, an element, or what a function
+    /// returning a reference hands back.
+    Borrowed,
+    /// Built for this use alone — by a call, `+`, or a slice — and freed by
+    /// whoever takes it.
+    Owned,
+}
+
+/// How the string `expr` evaluates to is owned. `returns_borrowed` says
+/// whether a call hands back a string it doesn't give away.
+pub fn string_ownership(
+    expr: &AstNode,
+    returns_borrowed: impl Fn(&AstNode) -> bool,
+) -> StringOwnership {
+    match expr {
+        AstNode::StringLit(_, _) => StringOwnership::Static,
+        AstNode::BinaryOp { .. } | AstNode::Slice { .. } => StringOwnership::Owned,
+        AstNode::Call { name, .. } if name == "enum_name" => StringOwnership::Borrowed,
+        AstNode::Call { .. } | AstNode::MethodCall { .. } if !returns_borrowed(expr) => {
+            StringOwnership::Owned
+        }
+        _ => StringOwnership::Borrowed,
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumVariant {
     pub name: String,
-    pub value_type: Option<String>,
+    /// The types of the values it holds: `Rect(int, int)` holds two.
+    pub fields: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -192,61 +552,377 @@ pub struct MatchArm {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Pattern {
     Identifier(String),
+    /// `Enum::Variant`, or `Enum::Variant(p, ...)` with a pattern for each
+    /// of the variant's values. Written `Variant(p, ...)`, the enum is left
+    /// empty and taken from the value matched.
     EnumPattern {
         enum_name: String,
         variant: String,
-        binding: Option<String>,
+        payload: Vec<Pattern>,
+    },
+    /// `Point { x, y: 0 }`: a pattern for each field named, which is bound
+    /// to its own name when it has none. With `..` the fields left out are
+    /// ignored; without it every field must be named.
+    StructPattern {
+        struct_name: String,
+        fields: Vec<(String, Pattern)>,
+        rest: bool,
     },
     NumberPattern(i64),
     StringPattern(String),
+    /// `"GET " ++ rest`: a string starting with `prefix`, binding the rest
+    /// of it to `rest` (unless that is `_`).
+    PrefixPattern {
+        prefix: String,
+        rest: String,
+    },
+    /// `name ++ ".brn"`: a string ending with `suffix`, binding what comes
+    /// before it.
+    SuffixPattern {
+        rest: String,
+        suffix: String,
+    },
     Wildcard,
 }
 
+impl Pattern {
+    /// The names the pattern binds, nested ones included.
+    pub fn bindings(&self) -> Vec<&str> {
+        match self {
+            Pattern::Identifier(name) => vec![name.as_str()],
+            Pattern::EnumPattern { payload, .. } => {
+                payload.iter().flat_map(Pattern::bindings).collect()
+            }
+            Pattern::StructPattern { fields, .. } => fields
+                .iter()
+                .flat_map(|(_, pattern)| pattern.bindings())
+                .collect(),
+            Pattern::PrefixPattern { rest, .. } | Pattern::SuffixPattern { rest, .. }
+                if rest != "_" =>
+            {
+                vec![rest.as_str()]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn attribute_error(attribute: &Attribute, message: String) -> Diagnostic {
+    Diagnostic::error(message)
+        .at_span(attribute.span)
+        .with_code("E0201")
+}
+
+fn misplaced_attributes(first: &Attribute) -> Diagnostic {
+    attribute_error(
+        first,
+        "attributes can only be put on 'fn', 'let', 'struct' and 'enum'".to_string(),
+    )
+}
+
+/// Whether `attribute` is one `allowed` on `kind` of item, and takes lint
+/// names if and only if it is a lint level.
+fn check_attribute(attribute: &Attribute, allowed: &[&str], kind: &str) -> Result<(), Diagnostic> {
+    let name = attribute.name.as_str();
+    if !FUNCTION_ATTRIBUTES.contains(&name) {
+        return Err(
+            attribute_error(attribute, format!("unknown attribute '{}'", name)).with_help(format!(
+                "known attributes are {}",
+                FUNCTION_ATTRIBUTES.join(", ")
+            )),
+        );
+    }
+    if !allowed.contains(&name) {
+        return Err(attribute_error(
+            attribute,
+            format!("'#[{}]' does not apply to {}", name, kind),
+        ));
+    }
+    if !LINT_ATTRIBUTES.contains(&name) {
+        if !attribute.args.is_empty() {
+            return Err(attribute_error(
+                attribute,
+                format!("'#[{}]' takes no arguments", name),
+            ));
+        }
+        return Ok(());
+    }
+    if attribute.args.is_empty() {
+        return Err(attribute_error(
+            attribute,
+            format!("'#[{}]' needs the lints it applies to", name),
+        ));
+    }
+    for arg in &attribute.args {
+        if lint::find(arg).is_none() {
+            let known: Vec<&str> = lint::LINTS.iter().map(|l| l.name).collect();
+            return Err(
+                attribute_error(attribute, format!("unknown lint '{}'", arg))
+                    .with_help(format!("known lints are {}", known.join(", "))),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Put a file's `#![...]` attributes on each of its items, ahead of the
+/// item's own so that those take precedence.
+fn add_file_attributes(nodes: &mut [AstNode], file_attributes: &[Attribute]) {
+    if file_attributes.is_empty() {
+        return;
+    }
+    for node in nodes {
+        if let AstNode::When {
+            body, else_body, ..
+        } = node
+        {
+            add_file_attributes(body, file_attributes);
+            add_file_attributes(else_body, file_attributes);
+        } else if let Some(attributes) = node.attributes_mut() {
+            attributes.splice(0..0, file_attributes.iter().cloned());
+        }
+    }
+}
+
+type FieldInits = (Vec<(String, AstNode)>, Option<Box<AstNode>>);
+
 pub struct Parser<'a> {
     tokens: Vec<Token>,
+    interner: Interner<'a>,
+    source: &'a str,
     current: usize,
     filename: &'a str,
     no_struct_init: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: Vec<Token>, filename: &'a str) -> Self {
+    pub fn new(tokens: Tokens<'a>, filename: &'a str) -> Self {
         Parser {
-            tokens,
+            tokens: tokens.tokens,
+            interner: tokens.interner,
+            source: tokens.source,
             current: 0,
             filename,
             no_struct_init: false,
         }
     }
 
-    pub fn parse(&mut self) -> Result<AstNode, String> {
+    pub fn parse(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         let mut nodes = Vec::new();
 
+        let mut file_attributes = Vec::new();
+        while self.check(&TokenType::Hash) && self.peek_ahead(1).token_type == TokenType::Not {
+            self.advance();
+            self.advance();
+            for attribute in self.parse_attribute_list()? {
+                check_attribute(&attribute, LINT_ATTRIBUTES, "files")?;
+                file_attributes.push(attribute);
+            }
+        }
+
         while !self.is_at_end() {
-            if self.check(&TokenType::Import) {
-                nodes.push(self.parse_import()?);
-            } else if self.check(&TokenType::Export) {
-                nodes.push(self.parse_export()?);
-            } else if self.check(&TokenType::Unsafe) {
+            self.parse_item_into(&mut nodes)?;
+        }
+        add_file_attributes(&mut nodes, &file_attributes);
+
+        Ok(AstNode::Program(nodes, start.to(self.peek_span())))
+    }
+
+    /// Parse one top-level item into `out`; an `impl` block adds one
+    /// function per method.
+    fn parse_item_into(&mut self, out: &mut Vec<AstNode>) -> Result<(), Diagnostic> {
+        if self.check(&TokenType::Import) {
+            out.push(self.parse_import()?);
+        } else if self.check(&TokenType::Export) {
+            out.push(self.parse_export()?);
+        } else if self.check(&TokenType::Unsafe) {
+            self.advance();
+            out.push(self.parse_function(false, true)?);
+        } else if self.check(&TokenType::Fn) {
+            out.push(self.parse_function(false, false)?);
+        } else if self.check(&TokenType::Extern) {
+            out.push(self.parse_extern_fn(false)?);
+        } else if self.check(&TokenType::Struct) {
+            out.push(self.parse_struct_def()?);
+        } else if self.check(&TokenType::Enum) {
+            out.push(self.parse_enum_def()?);
+        } else if self.check(&TokenType::Impl) {
+            out.extend(self.parse_impl()?);
+        } else if self.check(&TokenType::At) {
+            out.push(self.parse_when()?);
+        } else if self.check(&TokenType::Hash) {
+            let attributes = self.parse_attributes()?;
+            let mut item = Vec::new();
+            self.parse_item_into(&mut item)?;
+            match item.as_mut_slice() {
+                [node] => self.attach_attributes(node, attributes)?,
+                _ => return Err(misplaced_attributes(&attributes[0])),
+            }
+            out.append(&mut item);
+        } else {
+            self.parse_statement_into(out)?;
+        }
+        Ok(())
+    }
+
+    /// `#[name, ...]`, any number of times before an item.
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, Diagnostic> {
+        let mut attributes = Vec::new();
+        while self.check(&TokenType::Hash) {
+            self.advance();
+            if self.check(&TokenType::Not) {
+                return Err(self.error("'#![...]' only goes at the top of a file"));
+            }
+            attributes.extend(self.parse_attribute_list()?);
+        }
+        Ok(attributes)
+    }
+
+    /// `[name, ...]` after a `#` or `#!`. A lint level names its lints:
+    /// `allow(non_snake_case)`.
+    fn parse_attribute_list(&mut self) -> Result<Vec<Attribute>, Diagnostic> {
+        self.consume(&TokenType::LBracket, "Expected '[' after '#'")?;
+        let mut attributes = Vec::new();
+        loop {
+            let start = self.peek_span();
+            let name = self.consume_identifier("Expected an attribute name")?;
+            let mut args = Vec::new();
+            if self.check(&TokenType::LParen) {
                 self.advance();
-                nodes.push(self.parse_function(false, true)?);
-            } else if self.check(&TokenType::Fn) {
-                nodes.push(self.parse_function(false, false)?);
-            } else if self.check(&TokenType::Struct) {
-                nodes.push(self.parse_struct_def()?);
-            } else if self.check(&TokenType::Enum) {
-                nodes.push(self.parse_enum_def()?);
-            } else {
-                nodes.push(self.parse_statement()?);
+                loop {
+                    args.push(self.consume_identifier("Expected a lint name")?);
+                    if !self.check(&TokenType::Comma) {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.consume(&TokenType::RParen, "Expected ')'")?;
+            }
+            attributes.push(Attribute {
+                name,
+                args,
+                span: self.span_from(start),
+            });
+            if !self.check(&TokenType::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.consume(&TokenType::RBracket, "Expected ']' after the attributes")?;
+        Ok(attributes)
+    }
+
+    /// Give `node` the attributes written before it, if it can have them.
+    fn attach_attributes(
+        &self,
+        node: &mut AstNode,
+        attributes: Vec<Attribute>,
+    ) -> Result<(), Diagnostic> {
+        let (allowed, kind) = match node {
+            AstNode::FunctionDef { .. } => (FUNCTION_ATTRIBUTES, "functions"),
+            AstNode::LetBinding { .. } => (BINDING_ATTRIBUTES, "bindings"),
+            AstNode::StructDef { .. } => (LINT_ATTRIBUTES, "structs"),
+            AstNode::EnumDef { .. } => (LINT_ATTRIBUTES, "enums"),
+            _ => return Err(misplaced_attributes(&attributes[0])),
+        };
+        for attribute in &attributes {
+            check_attribute(attribute, allowed, kind)?;
+        }
+        let has = |name: &str| attributes.iter().find(|a| a.name == name);
+        if let (Some(_), Some(noinline)) = (has("inline"), has("noinline")) {
+            return Err(attribute_error(
+                noinline,
+                "a function cannot be both '#[inline]' and '#[noinline]'".to_string(),
+            ));
+        }
+        if let AstNode::FunctionDef { params, .. } = node
+            && let Some(test) = has("test")
+            && !params.is_empty()
+        {
+            return Err(attribute_error(
+                test,
+                "a '#[test]' function takes no parameters".to_string(),
+            ));
+        }
+        if let Some(own) = node.attributes_mut() {
+            *own = attributes;
+        }
+        Ok(())
+    }
+
+    /// `@when(os = "linux", arch = "x86_64") { items } else { items }`, where
+    /// `else @when(...)` chains another condition.
+    fn parse_when(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
+        self.consume(&TokenType::At, "Expected '@'")?;
+        if !matches!(&self.peek().token_type,
+            TokenType::Identifier(name) if self.interner.resolve(*name) == "when")
+        {
+            return Err(self.error("Expected 'when' after '@'"));
+        }
+        self.advance();
+        self.consume(&TokenType::LParen, "Expected '(' after '@when'")?;
+        let mut conditions = Vec::new();
+        while !self.check(&TokenType::RParen) && !self.is_at_end() {
+            let key_span = self.peek_span();
+            let key = self.consume_identifier("Expected a condition like 'os'")?;
+            self.consume(&TokenType::Assign, "Expected '=' after the condition name")?;
+            let value = match &self.peek().token_type {
+                TokenType::StringLit(s) => {
+                    let s = s.clone();
+                    self.advance();
+                    s
+                }
+                _ => return Err(self.error("Expected a string value, like \"linux\"")),
+            };
+            conditions.push((key, value, key_span));
+            if !self.check(&TokenType::Comma) {
+                break;
             }
+            self.advance();
         }
+        self.consume(&TokenType::RParen, "Expected ')'")?;
+        if conditions.is_empty() {
+            return Err(self.error("'@when' needs at least one condition"));
+        }
+
+        let body = self.parse_items_block()?;
+        let else_body = if self.check(&TokenType::Else) {
+            self.advance();
+            if self.check(&TokenType::At) {
+                vec![self.parse_when()?]
+            } else {
+                self.parse_items_block()?
+            }
+        } else {
+            Vec::new()
+        };
+
+        Ok(AstNode::When {
+            conditions,
+            body,
+            else_body,
+            span: self.span_from(start),
+        })
+    }
 
-        Ok(AstNode::Program(nodes))
+    /// `{ items }`: top-level items inside braces.
+    fn parse_items_block(&mut self) -> Result<Vec<AstNode>, Diagnostic> {
+        self.consume(&TokenType::LBrace, "Expected '{'")?;
+        let mut items = Vec::new();
+        while !self.check(&TokenType::RBrace) && !self.is_at_end() {
+            self.parse_item_into(&mut items)?;
+        }
+        self.consume(&TokenType::RBrace, "Expected '}'")?;
+        Ok(items)
     }
 
-    fn parse_import(&mut self) -> Result<AstNode, String> {
+    fn parse_import(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Import, "Expected 'import'")?;
         self.consume(&TokenType::LBrace, "Expected '{' after 'import'")?;
 
@@ -283,17 +959,37 @@ impl<'a> Parser<'a> {
 
         self.consume(&TokenType::Semicolon, "Expected ';'")?;
 
-        Ok(AstNode::Import { names, path })
+        Ok(AstNode::Import {
+            names,
+            path,
+            span: self.span_from(start),
+        })
     }
 
-    fn parse_export(&mut self) -> Result<AstNode, String> {
+    fn parse_export(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Export, "Expected 'export'")?;
+        let mut node = self.parse_exported()?;
+        match &mut node {
+            AstNode::FunctionDef { span, .. }
+            | AstNode::ExternFn { span, .. }
+            | AstNode::LetBinding { span, .. }
+            | AstNode::StructDef { span, .. }
+            | AstNode::EnumDef { span, .. } => *span = start.to(*span),
+            _ => {}
+        }
+        Ok(node)
+    }
 
+    /// The declaration after `export`.
+    fn parse_exported(&mut self) -> Result<AstNode, Diagnostic> {
         if self.check(&TokenType::Unsafe) {
             self.advance();
             self.parse_function(true, true)
         } else if self.check(&TokenType::Fn) {
             self.parse_function(true, false)
+        } else if self.check(&TokenType::Extern) {
+            self.parse_extern_fn(true)
         } else if self.check(&TokenType::Let) {
             self.parse_let_binding_exported(true)
         } else if self.check(&TokenType::Struct) {
@@ -318,11 +1014,16 @@ impl<'a> Parser<'a> {
             Ok(node)
         } else {
             Err(self
-                .error("'export' can only be applied to 'fn', 'unsafe fn', 'let', 'struct', or 'enum' declarations"))
+                .error("'export' can only be applied to 'fn', 'unsafe fn', 'extern fn', 'let', 'struct', or 'enum' declarations"))
         }
     }
 
-    fn parse_function(&mut self, is_exported: bool, is_unsafe: bool) -> Result<AstNode, String> {
+    fn parse_function(
+        &mut self,
+        is_exported: bool,
+        is_unsafe: bool,
+    ) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Fn, "Expected 'fn'")?;
 
         let name = self.consume_identifier("Expected function name")?;
@@ -347,10 +1048,122 @@ impl<'a> Parser<'a> {
             body,
             is_exported,
             is_unsafe,
+            attributes: Vec::new(),
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_extern_fn(&mut self, is_exported: bool) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
+        self.consume(&TokenType::Extern, "Expected 'extern'")?;
+        self.consume(&TokenType::Fn, "Expected 'fn' after 'extern'")?;
+
+        let name = self.consume_identifier("Expected function name")?;
+
+        self.consume(&TokenType::LParen, "Expected '('")?;
+        let mut params = Vec::new();
+        let mut variadic = false;
+        while !self.check(&TokenType::RParen) {
+            if self.check(&TokenType::Ellipsis) {
+                self.advance();
+                variadic = true;
+                break;
+            }
+            let name = self.consume_identifier("Expected parameter name or '...'")?;
+            self.consume(&TokenType::Colon, "Expected ':'")?;
+            let param_type = self.parse_type()?;
+            params.push(Parameter {
+                is_reference: false,
+                is_mutable: false,
+                name,
+                param_type,
+            });
+            if !self.check(&TokenType::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.consume(
+            &TokenType::RParen,
+            "Expected ')' ('...' must be the last parameter)",
+        )?;
+
+        let return_type = if self.check(&TokenType::Arrow) {
+            self.advance();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.consume(
+            &TokenType::Semicolon,
+            "Expected ';' after an extern declaration, which has no body",
+        )?;
+
+        Ok(AstNode::ExternFn {
+            name,
+            params,
+            variadic,
+            return_type,
+            is_exported,
+            span: self.span_from(start),
         })
     }
 
-    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, String> {
+    /// `impl Type { fn ... }` is flattened into ordinary functions named
+    /// `Type.method`, each taking `self`, `&self` or `&mut self` first.
+    fn parse_impl(&mut self) -> Result<Vec<AstNode>, Diagnostic> {
+        self.consume(&TokenType::Impl, "Expected 'impl'")?;
+        let type_name = self.consume_identifier("Expected type name after 'impl'")?;
+        self.consume(&TokenType::LBrace, "Expected '{' after impl type")?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RBrace) && !self.is_at_end() {
+            let is_unsafe = if self.check(&TokenType::Unsafe) {
+                self.advance();
+                true
+            } else {
+                false
+            };
+            if !self.check(&TokenType::Fn) {
+                return Err(self.error("Expected 'fn' inside impl block"));
+            }
+            let Span { line, column, .. } = self.peek_span();
+            let mut method = self.parse_function(false, is_unsafe)?;
+            if let AstNode::FunctionDef { name, params, .. } = &mut method {
+                match params.first_mut() {
+                    Some(receiver) if receiver.param_type == "Self" => {
+                        receiver.param_type = if !receiver.is_reference {
+                            type_name.clone()
+                        } else if receiver.is_mutable {
+                            format!("&mut {}", type_name)
+                        } else {
+                            format!("&{}", type_name)
+                        };
+                        if receiver.is_reference {
+                            receiver.is_reference = false;
+                            receiver.is_mutable = false;
+                        }
+                    }
+                    _ => {
+                        return Err(Diagnostic::error(format!(
+                            "method '{}' of '{}' must take 'self', '&self' or '&mut self' first",
+                            name, type_name
+                        ))
+                        .at(self.filename, line, column)
+                        .with_code("E0201")
+                        .with_help("free functions go outside the impl block"));
+                    }
+                }
+                *name = format!("{}.{}", type_name, name);
+            }
+            methods.push(method);
+        }
+
+        self.consume(&TokenType::RBrace, "Expected '}' to close impl block")?;
+        Ok(methods)
+    }
+
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, Diagnostic> {
         let mut params = Vec::new();
 
         if self.check(&TokenType::RParen) {
@@ -373,8 +1186,13 @@ impl<'a> Parser<'a> {
             };
 
             let name = self.consume_identifier("Expected parameter name")?;
-            self.consume(&TokenType::Colon, "Expected ':'")?;
-            let param_type = self.parse_type()?;
+            // A bare `self` receiver; `parse_impl` fills in the type
+            let param_type = if name == "self" && !self.check(&TokenType::Colon) {
+                "Self".to_string()
+            } else {
+                self.consume(&TokenType::Colon, "Expected ':'")?;
+                self.parse_type()?
+            };
 
             params.push(Parameter {
                 is_reference,
@@ -392,7 +1210,8 @@ impl<'a> Parser<'a> {
         Ok(params)
     }
 
-    fn parse_struct_def(&mut self) -> Result<AstNode, String> {
+    fn parse_struct_def(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Struct, "Expected 'struct'")?;
         let name = self.consume_identifier("Expected struct name")?;
 
@@ -403,6 +1222,12 @@ impl<'a> Parser<'a> {
             let field_name = self.consume_identifier("Expected field name")?;
             self.consume(&TokenType::Colon, "Expected ':'")?;
             let field_type = self.parse_type()?;
+            let default = if self.check(&TokenType::Assign) {
+                self.advance();
+                Some(Box::new(self.parse_expression()?))
+            } else {
+                None
+            };
             if self.check(&TokenType::Comma) {
                 self.advance();
             }
@@ -410,6 +1235,7 @@ impl<'a> Parser<'a> {
             fields.push(Field {
                 name: field_name,
                 field_type,
+                default,
             });
         }
 
@@ -419,10 +1245,13 @@ impl<'a> Parser<'a> {
             name,
             fields,
             is_exported: false,
+            attributes: Vec::new(),
+            span: self.span_from(start),
         })
     }
 
-    fn parse_enum_def(&mut self) -> Result<AstNode, String> {
+    fn parse_enum_def(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Enum, "Expected 'enum'")?;
         let name = self.consume_identifier("Expected enum name")?;
 
@@ -432,18 +1261,22 @@ impl<'a> Parser<'a> {
         while !self.check(&TokenType::RBrace) && !self.is_at_end() {
             let variant_name = self.consume_identifier("Expected variant name")?;
 
-            let value_type = if self.check(&TokenType::LParen) {
+            let mut fields = Vec::new();
+            if self.check(&TokenType::LParen) {
                 self.advance();
-                let ty = self.parse_type()?;
+                loop {
+                    fields.push(self.parse_type()?);
+                    if !self.check(&TokenType::Comma) {
+                        break;
+                    }
+                    self.advance();
+                }
                 self.consume(&TokenType::RParen, "Expected ')'")?;
-                Some(ty)
-            } else {
-                None
-            };
+            }
 
             variants.push(EnumVariant {
                 name: variant_name,
-                value_type,
+                fields,
             });
 
             if self.check(&TokenType::Comma) {
@@ -457,10 +1290,12 @@ impl<'a> Parser<'a> {
             name,
             variants,
             is_exported: false,
+            attributes: Vec::new(),
+            span: self.span_from(start),
         })
     }
 
-    fn parse_type(&mut self) -> Result<String, String> {
+    fn parse_type(&mut self) -> Result<String, Diagnostic> {
         match &self.peek().token_type {
             TokenType::IntType => {
                 self.advance();
@@ -489,6 +1324,11 @@ impl<'a> Parser<'a> {
                     Ok(format!("&{}", inner))
                 }
             }
+            TokenType::Star => {
+                self.advance();
+                let inner = self.parse_type()?;
+                Ok(format!("*{}", inner))
+            }
             TokenType::LBracket => {
                 self.advance();
                 let elem_type = self.parse_type()?;
@@ -506,10 +1346,24 @@ impl<'a> Parser<'a> {
                 Ok(format!("[{}; {}]", elem_type, size))
             }
             TokenType::Identifier(name) => {
-                let name = name.clone();
+                let name = self.interner.resolve(*name).to_string();
                 self.advance();
                 if name == "Vec" {
-                    Ok("Vec".to_string())
+                    if !self.check(&TokenType::LessThan) {
+                        return Ok("Vec".to_string());
+                    }
+                    self.advance();
+                    let inner = self.parse_type()?;
+                    self.consume(
+                        &TokenType::GreaterThan,
+                        "Expected '>' after Vec element type",
+                    )?;
+                    // `Vec` on its own has always meant a Vec of ints
+                    if inner == "int" {
+                        Ok("Vec".to_string())
+                    } else {
+                        Ok(format!("Vec<{}>", inner))
+                    }
                 } else if name == "Mutex" {
                     self.consume(&TokenType::LessThan, "Expected '<' after 'Mutex'")?;
                     let inner = self.parse_type()?;
@@ -518,6 +1372,12 @@ impl<'a> Parser<'a> {
                         "Expected '>' after Mutex inner type",
                     )?;
                     Ok(format!("Mutex<{}>", inner))
+                } else if name == "Box" {
+                    // `Box<T>` is spelled differently but is the same nullable pointer as `*T`
+                    self.consume(&TokenType::LessThan, "Expected '<' after 'Box'")?;
+                    let inner = self.parse_type()?;
+                    self.consume(&TokenType::GreaterThan, "Expected '>' after Box inner type")?;
+                    Ok(format!("*{}", inner))
                 } else {
                     Ok(name)
                 }
@@ -526,43 +1386,43 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_array_assignment_or_expression(&mut self) -> Result<AstNode, String> {
-        let location = Location {
-            line: self.peek().line,
-            column: self.peek().column,
-        };
-
-        let name = self.consume_identifier("Expected identifier")?;
-
-        if self.check(&TokenType::LBracket) {
-            self.advance();
-            let index = self.parse_expression()?;
-            self.consume(&TokenType::RBracket, "Expected ']'")?;
-
-            if self.check(&TokenType::Assign) {
-                self.advance();
-                let value = Box::new(self.parse_expression()?);
-                self.consume(&TokenType::Semicolon, "Expected ';'")?;
+    /// `a[i] = v;`, `a[i].field = v;`, or an expression statement starting
+    /// with `a[i]`.
+    fn parse_array_assignment_or_expression(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
+        let expr = self.parse_expression()?;
 
-                return Ok(AstNode::ArrayAssignment {
+        if !self.check(&TokenType::Assign) {
+            self.consume(&TokenType::Semicolon, "Expected ';'")?;
+            return Ok(AstNode::ExpressionStatement(
+                Box::new(expr),
+                self.span_from(start),
+            ));
+        }
+        self.advance();
+        let value = Box::new(self.parse_expression()?);
+        self.consume(&TokenType::Semicolon, "Expected ';'")?;
+        match expr {
+            AstNode::Index { array, index, .. } => match *array {
+                AstNode::Identifier { name, .. } => Ok(AstNode::ArrayAssignment {
                     array: name,
-                    index: Box::new(index),
+                    index,
                     value,
-                    location,
-                });
-            } else {
-                self.consume(&TokenType::Semicolon, "Expected ';'")?;
-                return Ok(AstNode::ExpressionStatement(Box::new(AstNode::Index {
-                    array: Box::new(AstNode::Identifier { name, location }),
-                    index: Box::new(index),
-                })));
-            }
+                    span: self.span_from(start),
+                }),
+                _ => Err(self.error("Invalid assignment target")),
+            },
+            AstNode::MemberAccess { object, field, .. } => Ok(AstNode::MemberAssignment {
+                object,
+                field,
+                value,
+                span: self.span_from(start),
+            }),
+            _ => Err(self.error("Invalid assignment target")),
         }
-
-        Err(self.error("Expected array index"))
     }
 
-    fn parse_statement(&mut self) -> Result<AstNode, String> {
+    fn parse_statement(&mut self) -> Result<AstNode, Diagnostic> {
         if self.check(&TokenType::Let) {
             self.parse_let_binding_exported(false)
         } else if self.check(&TokenType::If) {
@@ -576,13 +1436,15 @@ impl<'a> Parser<'a> {
         } else if self.check(&TokenType::Return) {
             self.parse_return()
         } else if self.check(&TokenType::Break) {
+            let start = self.peek_span();
             self.advance();
             self.consume(&TokenType::Semicolon, "Expected ';'")?;
-            Ok(AstNode::Break)
+            Ok(AstNode::Break(self.span_from(start)))
         } else if self.check(&TokenType::Continue) {
+            let start = self.peek_span();
             self.advance();
             self.consume(&TokenType::Semicolon, "Expected ';'")?;
-            Ok(AstNode::Continue)
+            Ok(AstNode::Continue(self.span_from(start)))
         } else if self.check(&TokenType::LBrace) {
             self.parse_block()
         } else if self.check_identifier() {
@@ -592,48 +1454,49 @@ impl<'a> Parser<'a> {
             } else if *next_token == TokenType::LBracket {
                 self.parse_array_assignment_or_expression()
             } else if *next_token == TokenType::Dot {
-                // Check for member assignment: obj.field = val;
-                let ahead2 = &self.peek_ahead(2).token_type;
-                let ahead3 = &self.peek_ahead(3).token_type;
-                if matches!(ahead2, TokenType::Identifier(_)) && *ahead3 == TokenType::Assign {
-                    let location = Location {
-                        line: self.peek().line,
-                        column: self.peek().column,
+                let start = self.peek_span();
+                let expr = self.parse_expression()?;
+                // Member assignment: obj.field = val; or obj.a.b = val;
+                if self.check(&TokenType::Assign) {
+                    let AstNode::MemberAccess { object, field, .. } = expr else {
+                        return Err(self.error("Invalid assignment target"));
                     };
-                    let obj_name = self.consume_identifier("Expected object name")?;
-                    self.advance(); // consume '.'
-                    let field_name = self.consume_identifier("Expected field name")?;
                     self.advance(); // consume '='
                     let value = Box::new(self.parse_expression()?);
                     self.consume(&TokenType::Semicolon, "Expected ';'")?;
-                    Ok(AstNode::MemberAssignment {
-                        object: obj_name,
-                        field: field_name,
+                    return Ok(AstNode::MemberAssignment {
+                        object,
+                        field,
                         value,
-                        location,
-                    })
-                } else {
-                    let expr = self.parse_expression()?;
-                    self.consume(&TokenType::Semicolon, "Expected ';'")?;
-                    Ok(AstNode::ExpressionStatement(Box::new(expr)))
+                        span: self.span_from(start),
+                    });
                 }
-            } else {
-                let expr = self.parse_expression()?;
                 self.consume(&TokenType::Semicolon, "Expected ';'")?;
-                Ok(AstNode::ExpressionStatement(Box::new(expr)))
+                Ok(AstNode::ExpressionStatement(
+                    Box::new(expr),
+                    self.span_from(start),
+                ))
+            } else {
+                self.parse_expression_statement()
             }
         } else {
-            let expr = self.parse_expression()?;
-            self.consume(&TokenType::Semicolon, "Expected ';'")?;
-            Ok(AstNode::ExpressionStatement(Box::new(expr)))
+            self.parse_expression_statement()
         }
     }
 
-    fn parse_let_binding_exported(&mut self, is_exported: bool) -> Result<AstNode, String> {
-        let location = Location {
-            line: self.peek().line,
-            column: self.peek().column,
-        };
+    /// `expr;`
+    fn parse_expression_statement(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
+        let expr = self.parse_expression()?;
+        self.consume(&TokenType::Semicolon, "Expected ';'")?;
+        Ok(AstNode::ExpressionStatement(
+            Box::new(expr),
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_let_binding_exported(&mut self, is_exported: bool) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
 
         self.consume(&TokenType::Let, "Expected 'let'")?;
 
@@ -662,16 +1525,14 @@ impl<'a> Parser<'a> {
             name,
             type_annotation,
             value,
-            location,
+            span: self.span_from(start),
             is_exported,
+            attributes: Vec::new(),
         })
     }
 
-    fn parse_assignment(&mut self) -> Result<AstNode, String> {
-        let location = Location {
-            line: self.peek().line,
-            column: self.peek().column,
-        };
+    fn parse_assignment(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
 
         let name = self.consume_identifier("Expected variable name")?;
         self.consume(&TokenType::Assign, "Expected '='")?;
@@ -681,23 +1542,142 @@ impl<'a> Parser<'a> {
         Ok(AstNode::Assignment {
             name,
             value,
-            location,
+            span: self.span_from(start),
         })
     }
 
-    fn parse_block(&mut self) -> Result<AstNode, String> {
+    fn parse_block(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::LBrace, "Expected '{'")?;
         let mut statements = Vec::new();
 
         while !self.check(&TokenType::RBrace) && !self.is_at_end() {
-            statements.push(self.parse_statement()?);
+            self.parse_statement_into(&mut statements)?;
         }
 
         self.consume(&TokenType::RBrace, "Expected '}'")?;
-        Ok(AstNode::Block(statements))
+        Ok(AstNode::Block(statements, self.span_from(start)))
+    }
+
+    /// Parse one statement into `out`. A destructuring `let` expands to
+    /// several bindings, so it can't go through `parse_statement`.
+    fn parse_statement_into(&mut self, out: &mut Vec<AstNode>) -> Result<(), Diagnostic> {
+        let pattern_at =
+            if self.check(&TokenType::Let) && self.peek_ahead(1).token_type == TokenType::Mut {
+                2
+            } else {
+                1
+            };
+        let is_destructuring = self.check(&TokenType::Let)
+            && (self.peek_ahead(pattern_at).token_type == TokenType::LParen
+                || (matches!(
+                    self.peek_ahead(pattern_at).token_type,
+                    TokenType::Identifier(_)
+                ) && self.peek_ahead(pattern_at + 1).token_type == TokenType::LBrace));
+        if is_destructuring {
+            out.extend(self.parse_destructuring_let()?);
+        } else {
+            out.push(self.parse_statement()?);
+        }
+        Ok(())
+    }
+
+    /// `let Point { x, y: py } = p;` or `let (a, b) = p;`, which binds a
+    /// struct's fields in declaration order. The value is bound once to a
+    /// hidden variable and each name is then read from one of its fields.
+    fn parse_destructuring_let(&mut self) -> Result<Vec<AstNode>, Diagnostic> {
+        let start = self.peek_span();
+        self.consume(&TokenType::Let, "Expected 'let'")?;
+        let mutable = if self.check(&TokenType::Mut) {
+            self.advance();
+            true
+        } else {
+            false
+        };
+
+        // (field, binding) pairs
+        let mut bindings: Vec<(String, String)> = Vec::new();
+        let struct_name = if self.check(&TokenType::LParen) {
+            self.advance();
+            while !self.check(&TokenType::RParen) && !self.is_at_end() {
+                let name = self.consume_identifier("Expected a name in the tuple pattern")?;
+                bindings.push((bindings.len().to_string(), name));
+                if !self.check(&TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.consume(
+                &TokenType::RParen,
+                "Expected ')' to close the tuple pattern",
+            )?;
+            None
+        } else {
+            let struct_name = self.consume_identifier("Expected struct name")?;
+            self.consume(&TokenType::LBrace, "Expected '{'")?;
+            while !self.check(&TokenType::RBrace) && !self.is_at_end() {
+                let field = self.consume_identifier("Expected field name in the pattern")?;
+                let name = if self.check(&TokenType::Colon) {
+                    self.advance();
+                    self.consume_identifier("Expected a name to bind the field to")?
+                } else {
+                    field.clone()
+                };
+                bindings.push((field, name));
+                if !self.check(&TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.consume(
+                &TokenType::RBrace,
+                "Expected '}' to close the struct pattern",
+            )?;
+            Some(struct_name)
+        };
+        if bindings.is_empty() {
+            return Err(self.error("A destructuring pattern must bind at least one name"));
+        }
+
+        self.consume(&TokenType::Assign, "Expected '='")?;
+        let value = Box::new(self.parse_expression()?);
+        self.consume(&TokenType::Semicolon, "Expected ';'")?;
+
+        let span = self.span_from(start);
+        // Not a valid identifier, so it can't clash with a user variable
+        let hidden = format!("destructure@{}:{}", span.line, span.column);
+        let mut nodes = vec![AstNode::LetBinding {
+            mutable: false,
+            name: hidden.clone(),
+            type_annotation: struct_name,
+            value,
+            span,
+            is_exported: false,
+            attributes: Vec::new(),
+        }];
+        for (field, name) in bindings {
+            nodes.push(AstNode::LetBinding {
+                mutable,
+                name,
+                type_annotation: None,
+                value: Box::new(AstNode::MemberAccess {
+                    object: Box::new(AstNode::Identifier {
+                        name: hidden.clone(),
+                        span,
+                    }),
+                    field,
+                    span,
+                }),
+                span,
+                is_exported: false,
+                attributes: Vec::new(),
+            });
+        }
+        Ok(nodes)
     }
 
-    fn parse_if(&mut self) -> Result<AstNode, String> {
+    fn parse_if(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::If, "Expected 'if'")?;
         self.no_struct_init = true;
         let condition = Box::new(self.parse_expression()?);
@@ -719,35 +1699,85 @@ impl<'a> Parser<'a> {
             condition,
             then_block,
             else_block,
+            span: self.span_from(start),
         })
     }
 
-    fn parse_while(&mut self) -> Result<AstNode, String> {
+    fn parse_while(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::While, "Expected 'while'")?;
         self.no_struct_init = true;
         let condition = Box::new(self.parse_expression()?);
         self.no_struct_init = false;
         let body = Box::new(self.parse_block()?);
 
-        Ok(AstNode::While { condition, body })
+        Ok(AstNode::While {
+            condition,
+            body,
+            span: self.span_from(start),
+        })
     }
 
-    fn parse_for(&mut self) -> Result<AstNode, String> {
+    fn parse_for(&mut self) -> Result<AstNode, Diagnostic> {
+        let span_start = self.peek_span();
         self.consume(&TokenType::For, "Expected 'for'")?;
-        let variable = self.consume_identifier("Expected loop variable")?;
+        let (index, variable) = if self.check(&TokenType::LParen) {
+            self.advance();
+            let index = self.consume_identifier("Expected index variable")?;
+            self.consume(&TokenType::Comma, "Expected ',' after index variable")?;
+            let variable = self.consume_identifier("Expected loop variable")?;
+            self.consume(&TokenType::RParen, "Expected ')'")?;
+            (Some(index), variable)
+        } else {
+            (None, self.consume_identifier("Expected loop variable")?)
+        };
         self.consume(&TokenType::In, "Expected 'in'")?;
 
         self.no_struct_init = true;
         let start = self.parse_expression()?;
         self.no_struct_init = false;
 
+        if index.is_some() {
+            // `enumerate(c)` and `c.enumerate()` count while iterating `c`
+            let iterator = match start {
+                AstNode::Call { name, mut args, .. } if name == "enumerate" && args.len() == 1 => {
+                    args.remove(0)
+                }
+                AstNode::MethodCall {
+                    object,
+                    method,
+                    args,
+                    ..
+                } if method == "enumerate" && args.is_empty() => *object,
+                _ => {
+                    return Err(self
+                        .error("Expected 'enumerate(...)' after '(index, value)' in a for loop")
+                        .with_help(
+                            "write 'for (i, x) in enumerate(v)' or 'for (i, x) in v.enumerate()'",
+                        ));
+                }
+            };
+            let body = Box::new(self.parse_block()?);
+            return Ok(AstNode::For {
+                variable,
+                index,
+                iterator: Box::new(iterator),
+                body,
+                span: self.span_from(span_start),
+            });
+        }
+
         let iterator = if self.check(&TokenType::DotDot) {
+            let location = self.peek_location();
             self.advance();
             let end = self.parse_expression()?;
+            let span = start.span().to(end.span());
             AstNode::BinaryOp {
                 op: BinOp::DotDot,
                 left: Box::new(start),
                 right: Box::new(end),
+                location,
+                span,
             }
         } else {
             start
@@ -756,12 +1786,15 @@ impl<'a> Parser<'a> {
         let body = Box::new(self.parse_block()?);
         Ok(AstNode::For {
             variable,
+            index,
             iterator: Box::new(iterator),
             body,
+            span: self.span_from(span_start),
         })
     }
 
-    fn parse_match(&mut self) -> Result<AstNode, String> {
+    fn parse_match(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Match, "Expected 'match'")?;
         self.no_struct_init = true;
         let value = Box::new(self.parse_expression()?);
@@ -780,7 +1813,8 @@ impl<'a> Parser<'a> {
                 self.parse_return()?
             } else {
                 let expr = self.parse_expression()?;
-                AstNode::ExpressionStatement(Box::new(expr))
+                let span = expr.span();
+                AstNode::ExpressionStatement(Box::new(expr), span)
             };
 
             arms.push(MatchArm { pattern, body });
@@ -792,10 +1826,14 @@ impl<'a> Parser<'a> {
 
         self.consume(&TokenType::RBrace, "Expected '}'")?;
 
-        Ok(AstNode::Match { value, arms })
+        Ok(AstNode::Match {
+            value,
+            arms,
+            span: self.span_from(start),
+        })
     }
 
-    fn parse_pattern(&mut self) -> Result<Pattern, String> {
+    fn parse_pattern(&mut self) -> Result<Pattern, Diagnostic> {
         match self.peek().token_type.clone() {
             TokenType::Number(n) => {
                 self.advance();
@@ -812,11 +1850,26 @@ impl<'a> Parser<'a> {
             }
             TokenType::StringLit(s) => {
                 self.advance();
+                if self.match_concat() {
+                    let rest = self.consume_identifier("Expected a name after '++'")?;
+                    return Ok(Pattern::PrefixPattern { prefix: s, rest });
+                }
                 Ok(Pattern::StringPattern(s))
             }
             TokenType::Identifier(_) => {
                 let first = self.consume_identifier("Expected identifier")?;
 
+                if self.match_concat() {
+                    let TokenType::StringLit(suffix) = self.peek().token_type.clone() else {
+                        return Err(self.error("Expected a string after '++'"));
+                    };
+                    self.advance();
+                    return Ok(Pattern::SuffixPattern {
+                        rest: first,
+                        suffix,
+                    });
+                }
+
                 if first == "_" {
                     return Ok(Pattern::Wildcard);
                 }
@@ -827,21 +1880,21 @@ impl<'a> Parser<'a> {
                     self.advance();
                     self.advance();
                     let variant = self.consume_identifier("Expected variant name")?;
-
-                    let binding = if self.check(&TokenType::LParen) {
-                        self.advance();
-                        let b = self.consume_identifier("Expected binding")?;
-                        self.consume(&TokenType::RParen, "Expected ')'")?;
-                        Some(b)
-                    } else {
-                        None
-                    };
+                    let payload = self.parse_payload_patterns()?;
 
                     Ok(Pattern::EnumPattern {
                         enum_name: first,
                         variant,
-                        binding,
+                        payload,
+                    })
+                } else if self.check(&TokenType::LParen) {
+                    Ok(Pattern::EnumPattern {
+                        enum_name: String::new(),
+                        variant: first,
+                        payload: self.parse_payload_patterns()?,
                     })
+                } else if self.check(&TokenType::LBrace) {
+                    self.parse_struct_pattern(first)
                 } else {
                     Ok(Pattern::Identifier(first))
                 }
@@ -850,7 +1903,70 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_return(&mut self) -> Result<AstNode, String> {
+    /// `(p, ...)` after a variant's name, if there is one.
+    fn parse_payload_patterns(&mut self) -> Result<Vec<Pattern>, Diagnostic> {
+        let mut payload = Vec::new();
+        if self.check(&TokenType::LParen) {
+            self.advance();
+            loop {
+                payload.push(self.parse_pattern()?);
+                if !self.check(&TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.consume(&TokenType::RParen, "Expected ')'")?;
+        }
+        Ok(payload)
+    }
+
+    /// The `{ x, y: p, .. }` of a struct pattern, after its name.
+    fn parse_struct_pattern(&mut self, struct_name: String) -> Result<Pattern, Diagnostic> {
+        self.consume(&TokenType::LBrace, "Expected '{'")?;
+        let mut fields = Vec::new();
+        let mut rest = false;
+        while !self.check(&TokenType::RBrace) && !self.is_at_end() {
+            if self.check(&TokenType::DotDot) {
+                self.advance();
+                rest = true;
+                break;
+            }
+            let field = self.consume_identifier("Expected field name in the pattern")?;
+            let pattern = if self.check(&TokenType::Colon) {
+                self.advance();
+                self.parse_pattern()?
+            } else {
+                Pattern::Identifier(field.clone())
+            };
+            fields.push((field, pattern));
+            if !self.check(&TokenType::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.consume(
+            &TokenType::RBrace,
+            "Expected '}' to close the struct pattern",
+        )?;
+        Ok(Pattern::StructPattern {
+            struct_name,
+            fields,
+            rest,
+        })
+    }
+
+    /// `++` joining the parts of a string pattern.
+    fn match_concat(&mut self) -> bool {
+        if self.check(&TokenType::Plus) && self.peek_ahead(1).token_type == TokenType::Plus {
+            self.advance();
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn parse_return(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         self.consume(&TokenType::Return, "Expected 'return'")?;
 
         let value = if self.check(&TokenType::Semicolon) {
@@ -860,52 +1976,59 @@ impl<'a> Parser<'a> {
         };
 
         self.consume(&TokenType::Semicolon, "Expected ';'")?;
-        Ok(AstNode::Return(value))
+        Ok(AstNode::Return(value, self.span_from(start)))
     }
 
-    fn parse_expression(&mut self) -> Result<AstNode, String> {
+    fn parse_expression(&mut self) -> Result<AstNode, Diagnostic> {
         if self.check(&TokenType::Ampersand) {
+            let start = self.peek_span();
             self.advance();
             let expr = self.parse_or()?;
-            return Ok(AstNode::Reference(Box::new(expr)));
+            return Ok(AstNode::Reference(Box::new(expr), self.span_from(start)));
         }
 
         self.parse_or()
     }
 
-    fn parse_or(&mut self) -> Result<AstNode, String> {
+    fn parse_or(&mut self) -> Result<AstNode, Diagnostic> {
         let mut left = self.parse_and()?;
 
         while self.check(&TokenType::Or) {
+            let location = self.peek_location();
             self.advance();
             let right = self.parse_and()?;
             left = AstNode::BinaryOp {
                 op: BinOp::Or,
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 right: Box::new(right),
+                location,
             };
         }
 
         Ok(left)
     }
 
-    fn parse_and(&mut self) -> Result<AstNode, String> {
+    fn parse_and(&mut self) -> Result<AstNode, Diagnostic> {
         let mut left = self.parse_comparison()?;
 
         while self.check(&TokenType::And) {
+            let location = self.peek_location();
             self.advance();
             let right = self.parse_comparison()?;
             left = AstNode::BinaryOp {
                 op: BinOp::And,
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 right: Box::new(right),
+                location,
             };
         }
 
         Ok(left)
     }
 
-    fn parse_comparison(&mut self) -> Result<AstNode, String> {
+    fn parse_comparison(&mut self) -> Result<AstNode, Diagnostic> {
         let mut left = self.parse_additive()?;
 
         while matches!(
@@ -917,6 +2040,7 @@ impl<'a> Parser<'a> {
                 | TokenType::GreaterThan
                 | TokenType::GreaterEqual
         ) {
+            let location = self.peek_location();
             let op = match &self.peek().token_type {
                 TokenType::EqualEqual => {
                     self.advance();
@@ -948,18 +2072,21 @@ impl<'a> Parser<'a> {
             let right = self.parse_additive()?;
             left = AstNode::BinaryOp {
                 op,
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 right: Box::new(right),
+                location,
             };
         }
 
         Ok(left)
     }
 
-    fn parse_additive(&mut self) -> Result<AstNode, String> {
+    fn parse_additive(&mut self) -> Result<AstNode, Diagnostic> {
         let mut left = self.parse_term()?;
 
         while self.check(&TokenType::Plus) || self.check(&TokenType::Minus) {
+            let location = self.peek_location();
             let op = if self.check(&TokenType::Plus) {
                 self.advance();
                 BinOp::Add
@@ -969,23 +2096,63 @@ impl<'a> Parser<'a> {
             };
 
             let right = self.parse_term()?;
-            left = AstNode::BinaryOp {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
+            left = match op {
+                BinOp::Add => Self::concat_literals(left, right, location),
+                _ => AstNode::BinaryOp {
+                    op,
+                    span: left.span().to(right.span()),
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    location,
+                },
             };
         }
 
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<AstNode, String> {
-        let mut left = self.parse_unary()?;
+    /// `left + right`, with string literals joined at compile time:
+    /// `"a" + "b"` becomes `"ab"`, and `s + "a" + "b"` becomes `s + "ab"`.
+    fn concat_literals(left: AstNode, right: AstNode, location: Location) -> AstNode {
+        let span = left.span().to(right.span());
+        match (left, right) {
+            (AstNode::StringLit(a, _), AstNode::StringLit(b, _)) => {
+                AstNode::StringLit(a + &b, span)
+            }
+            (
+                AstNode::BinaryOp {
+                    op: BinOp::Add,
+                    left: inner,
+                    right: middle,
+                    location: inner_location,
+                    ..
+                },
+                right @ AstNode::StringLit(..),
+            ) if matches!(middle.as_ref(), AstNode::StringLit(..)) => AstNode::BinaryOp {
+                op: BinOp::Add,
+                left: inner,
+                right: Box::new(Self::concat_literals(*middle, right, location)),
+                location: inner_location,
+                span,
+            },
+            (left, right) => AstNode::BinaryOp {
+                op: BinOp::Add,
+                left: Box::new(left),
+                right: Box::new(right),
+                location,
+                span,
+            },
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<AstNode, Diagnostic> {
+        let mut left = self.parse_cast()?;
 
         while self.check(&TokenType::Star)
             || self.check(&TokenType::Slash)
             || self.check(&TokenType::Percent)
         {
+            let location = self.peek_location();
             let op = if self.check(&TokenType::Star) {
                 self.advance();
                 BinOp::Mul
@@ -997,24 +2164,46 @@ impl<'a> Parser<'a> {
                 BinOp::Mod
             };
 
-            let right = self.parse_unary()?;
+            let right = self.parse_cast()?;
             left = AstNode::BinaryOp {
                 op,
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 right: Box::new(right),
+                location,
             };
         }
 
         Ok(left)
     }
 
-    fn parse_unary(&mut self) -> Result<AstNode, String> {
+    /// `value as T`, binding tighter than `*` and looser than `-`:
+    /// `-n as char * 2` is `((-n) as char) * 2`.
+    fn parse_cast(&mut self) -> Result<AstNode, Diagnostic> {
+        let mut value = self.parse_unary()?;
+        while matches!(&self.peek().token_type,
+            TokenType::Identifier(name) if self.interner.resolve(*name) == "as")
+        {
+            self.advance();
+            let target = self.parse_type()?;
+            value = AstNode::Cast {
+                span: self.span_from(value.span()),
+                value: Box::new(value),
+                target,
+            };
+        }
+        Ok(value)
+    }
+
+    fn parse_unary(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         if self.check(&TokenType::Minus) {
             self.advance();
             let operand = self.parse_unary()?;
             return Ok(AstNode::UnaryOp {
                 op: UnOp::Negate,
                 operand: Box::new(operand),
+                span: self.span_from(start),
             });
         }
 
@@ -1024,42 +2213,48 @@ impl<'a> Parser<'a> {
             return Ok(AstNode::UnaryOp {
                 op: UnOp::Not,
                 operand: Box::new(operand),
+                span: self.span_from(start),
             });
         }
 
         if self.check(&TokenType::Ampersand) {
             self.advance();
             let operand = self.parse_unary()?;
-            return Ok(AstNode::Reference(Box::new(operand)));
+            return Ok(AstNode::Reference(Box::new(operand), self.span_from(start)));
         }
 
         self.parse_factor()
     }
 
-    fn parse_factor(&mut self) -> Result<AstNode, String> {
+    fn parse_factor(&mut self) -> Result<AstNode, Diagnostic> {
+        let start = self.peek_span();
         match &self.peek().token_type {
             TokenType::Number(n) => {
                 let n = *n;
                 self.advance();
-                Ok(AstNode::Number(n))
+                Ok(AstNode::Number(n, start))
             }
             TokenType::True => {
                 self.advance();
-                Ok(AstNode::Boolean(true))
+                Ok(AstNode::Boolean(true, start))
             }
             TokenType::False => {
                 self.advance();
-                Ok(AstNode::Boolean(false))
+                Ok(AstNode::Boolean(false, start))
+            }
+            TokenType::Null => {
+                self.advance();
+                Ok(AstNode::Null(start))
             }
             TokenType::CharLit(c) => {
                 let c = *c;
                 self.advance();
-                Ok(AstNode::Character(c))
+                Ok(AstNode::Character(c, start))
             }
             TokenType::StringLit(s) => {
                 let s = s.clone();
                 self.advance();
-                Ok(AstNode::StringLit(s))
+                Ok(AstNode::StringLit(s, start))
             }
             TokenType::LBracket => {
                 self.advance();
@@ -1075,17 +2270,38 @@ impl<'a> Parser<'a> {
                 }
 
                 self.consume(&TokenType::RBracket, "Expected ']'")?;
-                Ok(AstNode::ArrayLit(elements))
+                Ok(AstNode::ArrayLit(elements, self.span_from(start)))
             }
             TokenType::Identifier(name) => {
-                let name = name.clone();
-                let location = Location {
-                    line: self.peek().line,
-                    column: self.peek().column,
-                };
+                let name = self.interner.resolve(*name).to_string();
                 self.advance();
 
-                self.parse_postfix(AstNode::Identifier { name, location })
+                // The argument of `size_of` is a type, not an expression
+                if name == "size_of" && self.check(&TokenType::LParen) {
+                    self.advance();
+                    let ty = self.parse_type()?;
+                    self.consume(&TokenType::RParen, "Expected ')' after the type")?;
+                    return Ok(AstNode::SizeOf(ty, self.span_from(start)));
+                }
+
+                // Replaced by the file's contents, so the argument must be a
+                // literal path
+                if (name == "include_str" || name == "include_bytes")
+                    && self.check(&TokenType::LParen)
+                {
+                    self.advance();
+                    let TokenType::StringLit(path) = self.peek().token_type.clone() else {
+                        return Err(
+                            self.error(&format!("Expected a file path string in '{}'", name))
+                        );
+                    };
+                    self.advance();
+                    self.consume(&TokenType::RParen, "Expected ')' after the path")?;
+                    let contents = self.include(&name, &path, self.span_from(start))?;
+                    return self.parse_postfix(contents);
+                }
+
+                self.parse_postfix(AstNode::Identifier { name, span: start })
             }
             TokenType::LParen => {
                 self.advance();
@@ -1097,7 +2313,8 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_postfix(&mut self, mut left: AstNode) -> Result<AstNode, String> {
+    fn parse_postfix(&mut self, mut left: AstNode) -> Result<AstNode, Diagnostic> {
+        let start = left.span();
         loop {
             if self.check(&TokenType::LParen) {
                 self.advance();
@@ -1105,7 +2322,11 @@ impl<'a> Parser<'a> {
                 self.consume(&TokenType::RParen, "Expected ')'")?;
 
                 if let AstNode::Identifier { name, .. } = left {
-                    left = AstNode::Call { name, args };
+                    left = AstNode::Call {
+                        name,
+                        args,
+                        span: self.span_from(start),
+                    };
                 } else {
                     return Err(self.error("Invalid function call"));
                 }
@@ -1123,27 +2344,56 @@ impl<'a> Parser<'a> {
                         object: Box::new(left),
                         method: field,
                         args,
+                        span: self.span_from(start),
                     };
                 } else {
                     left = AstNode::MemberAccess {
                         object: Box::new(left),
                         field,
+                        span: self.span_from(start),
                     };
                 }
             } else if self.check(&TokenType::LBracket) {
+                let span_start = start;
                 self.advance();
-                let index = self.parse_expression()?;
-                self.consume(&TokenType::RBracket, "Expected ']'")?;
-                left = AstNode::Index {
-                    array: Box::new(left),
-                    index: Box::new(index),
+                let start = if self.check(&TokenType::DotDot) {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expression()?))
                 };
+                if self.check(&TokenType::DotDot) {
+                    self.advance();
+                    let end = if self.check(&TokenType::RBracket) {
+                        None
+                    } else {
+                        Some(Box::new(self.parse_expression()?))
+                    };
+                    self.consume(&TokenType::RBracket, "Expected ']'")?;
+                    left = AstNode::Slice {
+                        value: Box::new(left),
+                        start,
+                        end,
+                        span: self.span_from(span_start),
+                    };
+                } else if let Some(index) = start {
+                    self.consume(&TokenType::RBracket, "Expected ']'")?;
+                    left = AstNode::Index {
+                        array: Box::new(left),
+                        index,
+                        span: self.span_from(span_start),
+                    };
+                }
             } else if self.check(&TokenType::LBrace) && !self.no_struct_init {
                 if let AstNode::Identifier { name, .. } = left {
                     self.advance();
-                    let fields = self.parse_field_inits()?;
+                    let (fields, base) = self.parse_field_inits()?;
                     self.consume(&TokenType::RBrace, "Expected '}'")?;
-                    left = AstNode::StructInit { name, fields };
+                    left = AstNode::StructInit {
+                        name,
+                        fields,
+                        base,
+                        span: self.span_from(start),
+                    };
                 } else {
                     break;
                 }
@@ -1158,24 +2408,20 @@ impl<'a> Parser<'a> {
                     self.advance();
                     let variant = self.consume_identifier("Expected variant name")?;
 
-                    let value = if self.check(&TokenType::LParen) {
+                    let values = if self.check(&TokenType::LParen) {
                         self.advance();
-                        if self.check(&TokenType::RParen) {
-                            self.advance();
-                            None
-                        } else {
-                            let v = self.parse_expression()?;
-                            self.consume(&TokenType::RParen, "Expected ')'")?;
-                            Some(Box::new(v))
-                        }
+                        let values = self.parse_arguments()?;
+                        self.consume(&TokenType::RParen, "Expected ')'")?;
+                        values
                     } else {
-                        None
+                        Vec::new()
                     };
 
                     left = AstNode::EnumValue {
                         enum_name,
                         variant,
-                        value,
+                        values,
+                        span: self.span_from(start),
                     };
                 } else {
                     break;
@@ -1188,7 +2434,7 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
-    fn parse_arguments(&mut self) -> Result<Vec<AstNode>, String> {
+    fn parse_arguments(&mut self) -> Result<Vec<AstNode>, Diagnostic> {
         let mut args = Vec::new();
 
         if self.check(&TokenType::RParen) {
@@ -1197,12 +2443,13 @@ impl<'a> Parser<'a> {
 
         loop {
             if self.check(&TokenType::Ampersand) {
+                let start = self.peek_span();
                 self.advance();
                 if self.check(&TokenType::Mut) {
                     self.advance();
                 }
                 let expr = self.parse_expression()?;
-                args.push(AstNode::Reference(Box::new(expr)));
+                args.push(AstNode::Reference(Box::new(expr), self.span_from(start)));
             } else {
                 args.push(self.parse_expression()?);
             }
@@ -1216,14 +2463,26 @@ impl<'a> Parser<'a> {
         Ok(args)
     }
 
-    fn parse_field_inits(&mut self) -> Result<Vec<(String, AstNode)>, String> {
+    /// The fields of a struct literal, and the `..base` ending it, if any.
+    fn parse_field_inits(&mut self) -> Result<FieldInits, Diagnostic> {
         let mut fields = Vec::new();
 
         if self.check(&TokenType::RBrace) {
-            return Ok(fields);
+            return Ok((fields, None));
         }
 
         loop {
+            if self.check(&TokenType::DotDot) {
+                self.advance();
+                let base = self.parse_expression()?;
+                if self.check(&TokenType::Comma) {
+                    self.advance();
+                }
+                if !self.check(&TokenType::RBrace) {
+                    return Err(self.error("'..base' must come last in a struct literal"));
+                }
+                return Ok((fields, Some(Box::new(base))));
+            }
             let name = self.consume_identifier("Expected field name")?;
             self.consume(&TokenType::Colon, "Expected ':'")?;
             let value = self.parse_expression()?;
@@ -1239,7 +2498,7 @@ impl<'a> Parser<'a> {
             }
         }
 
-        Ok(fields)
+        Ok((fields, None))
     }
 
     fn check(&self, token_type: &TokenType) -> bool {
@@ -1260,6 +2519,19 @@ impl<'a> Parser<'a> {
         &self.tokens[self.current]
     }
 
+    fn peek_location(&self) -> Location {
+        self.peek_span().into()
+    }
+
+    fn peek_span(&self) -> Span {
+        self.peek().span
+    }
+
+    /// From `start` to the end of the last token consumed.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.tokens[self.current.saturating_sub(1)].span)
+    }
+
     fn peek_ahead(&self, offset: usize) -> &Token {
         let pos = self.current + offset;
         if pos >= self.tokens.len() {
@@ -1280,7 +2552,7 @@ impl<'a> Parser<'a> {
         matches!(self.peek().token_type, TokenType::Eof)
     }
 
-    fn consume(&mut self, token_type: &TokenType, message: &str) -> Result<(), String> {
+    fn consume(&mut self, token_type: &TokenType, message: &str) -> Result<(), Diagnostic> {
         if self.check(token_type) {
             self.advance();
             Ok(())
@@ -1289,10 +2561,10 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn consume_identifier(&mut self, message: &str) -> Result<String, String> {
+    fn consume_identifier(&mut self, message: &str) -> Result<String, Diagnostic> {
         match &self.peek().token_type {
             TokenType::Identifier(name) => {
-                let name = name.clone();
+                let name = self.interner.resolve(*name).to_string();
                 self.advance();
                 Ok(name)
             }
@@ -1300,11 +2572,48 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn error(&self, message: &str) -> String {
+    /// An error underlining the whole current token.
+    /// `include_str` / `include_bytes`: the file at `path`, which is
+    /// resolved relative to the file being parsed like an import, as a
+    /// string or `Bytes` literal.
+    fn include(&self, builtin: &str, path: &str, span: Span) -> Result<AstNode, Diagnostic> {
+        let full = Path::new(self.filename)
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(path);
+        let contents = fs::read(&full).map_err(|e| {
+            Diagnostic::error(format!("cannot read '{}': {}", full.display(), e))
+                .at_span(span)
+                .with_code("E0306")
+                .with_help("included files are resolved relative to the including file")
+        })?;
+        if builtin == "include_bytes" {
+            return Ok(AstNode::BytesLit(contents, span));
+        }
+        // Strings are UTF-8 and end at their first zero byte
+        match String::from_utf8(contents) {
+            Ok(text) if !text.contains('\0') => Ok(AstNode::StringLit(text, span)),
+            Ok(_) => Err(Diagnostic::error(format!(
+                "'{}' contains a zero byte, which would end the string",
+                full.display()
+            ))
+            .at_span(span)
+            .with_code("E0306")
+            .with_help("use 'include_bytes' for binary files")),
+            Err(_) => Err(
+                Diagnostic::error(format!("'{}' is not valid UTF-8", full.display()))
+                    .at_span(span)
+                    .with_code("E0306")
+                    .with_help("use 'include_bytes' for binary files"),
+            ),
+        }
+    }
+
+    fn error(&self, message: &str) -> Diagnostic {
         let token = self.peek();
-        format!(
-            "{}:{}:{}: {}",
-            self.filename, token.line, token.column, message
-        )
+        Diagnostic::error(message)
+            .at(self.filename, token.span.line, token.span.column)
+            .with_code("E0201")
+            .with_length(token.span.text(self.source).chars().count().max(1))
     }
 }
@@ -0,0 +1,63 @@
+//! Custom passes over the checked program.
+//!
+//! A [`Pass`] registered with [`Compiler::with_pass`](crate::Compiler::with_pass)
+//! runs after semantic analysis and before code generation, on the whole
+//! program with its imports already inlined. It can look for things a
+//! project wants to forbid and stop the build with a diagnostic, or hand
+//! back a rewritten tree for the code generator. Passes run in the order
+//! they were registered, each on the output of the one before; with
+//! `--check` they run too, so a project's own rules are reported alongside
+//! the compiler's.
+//!
+//! ```no_run
+//! use brain::parser::AstNode;
+//! use brain::{Compiler, Diagnostic, Pass};
+//!
+//! /// Rejects calls to `exit`.
+//! struct NoExit;
+//!
+//! impl Pass for NoExit {
+//!     fn name(&self) -> &'static str {
+//!         "no-exit"
+//!     }
+//!
+//!     fn run(&self, program: AstNode, _file_name: &str) -> Result<AstNode, Diagnostic> {
+//!         for node in brain::pass::descendants(&program) {
+//!             if let AstNode::Call { name, span, .. } = node
+//!                 && name == "exit"
+//!             {
+//!                 return Err(Diagnostic::error("'exit' is not allowed here").at_span(*span));
+//!             }
+//!         }
+//!         Ok(program)
+//!     }
+//! }
+//!
+//! let compiler = Compiler::new().with_pass(NoExit);
+//! # let _ = compiler.compile_file("main.brn");
+//! ```
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::AstNode;
+
+pub trait Pass {
+    /// What the pass is called in `--time-passes` reports.
+    fn name(&self) -> &'static str;
+
+    /// Inspect or rewrite `program`, the whole program as a
+    /// [`AstNode::Program`]; `file_name` is the root file it was compiled
+    /// from. An error stops the compilation.
+    fn run(&self, program: AstNode, file_name: &str) -> Result<AstNode, Diagnostic>;
+}
+
+/// `node` and every node inside it, parents before their children and
+/// otherwise in source order.
+pub fn descendants(node: &AstNode) -> Vec<&AstNode> {
+    let mut found = Vec::new();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        found.push(node);
+        stack.extend(node.children().into_iter().rev());
+    }
+    found
+}
@@ -0,0 +1,535 @@
+//! Peephole clean-up of the emitted IR, run before it is written out.
+//!
+//! The code generator keeps every variable in an `alloca` and reloads it at
+//! each use, and closes every block with a branch even when the next block
+//! is the only place it can go. Working one function at a time, this pass
+//! - folds a block into the one before it when that block branches straight
+//!   to it and nothing else does,
+//! - replaces a load with the value just stored to, or loaded from, the same
+//!   pointer in the same block, and drops a store of a value just loaded
+//!   from where it is going,
+//! - folds `i64` arithmetic on two constants, which forwarding stores of
+//!   constants exposes,
+//! - reuses the result of an identical side-effect-free instruction earlier
+//!   in the same block,
+//! - deletes side-effect-free instructions whose result is never used, and
+//!   `alloca`s that are only ever stored to,
+//!
+//! then renumbers the unnamed values so they count up without gaps again,
+//! as LLVM requires. A function it can't follow is left as it is.
+
+use std::collections::{HashMap, HashSet};
+
+/// Instructions with no effect besides their result.
+const PURE: &[&str] = &[
+    "add",
+    "sub",
+    "mul",
+    "and",
+    "or",
+    "xor",
+    "shl",
+    "lshr",
+    "ashr",
+    "icmp",
+    "fcmp",
+    "fadd",
+    "fsub",
+    "fmul",
+    "fneg",
+    "zext",
+    "sext",
+    "trunc",
+    "bitcast",
+    "ptrtoint",
+    "inttoptr",
+    "sitofp",
+    "uitofp",
+    "fptosi",
+    "fptoui",
+    "fpext",
+    "fptrunc",
+    "getelementptr",
+    "select",
+    "extractvalue",
+    "insertvalue",
+    "phi",
+    "alloca",
+    "load",
+];
+
+/// Pure instructions that still can't stand in for an identical one: each
+/// gives a new value, or depends on where it is reached from or on memory.
+const STATEFUL: &[&str] = &["alloca", "phi", "load"];
+
+pub fn optimize(ir: &str) -> String {
+    let mut out = String::with_capacity(ir.len());
+    let mut body: Option<Vec<&str>> = None;
+    let mut header = "";
+    for line in ir.lines() {
+        match &mut body {
+            Some(lines) if line == "}" => {
+                let lines = if has_numbered_params(header) {
+                    lines.iter().map(|l| l.to_string()).collect()
+                } else {
+                    optimize_function(lines)
+                };
+                for line in lines {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                out.push_str("}\n");
+                body = None;
+            }
+            Some(lines) => lines.push(line),
+            None => {
+                out.push_str(line);
+                out.push('\n');
+                if line.starts_with("define ") && line.ends_with('{') {
+                    header = line;
+                    body = Some(Vec::new());
+                }
+            }
+        }
+    }
+    // An unterminated function is passed through untouched
+    for line in body.into_iter().flatten() {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Whether a parameter takes one of the value numbers, which renumbering
+/// would have to start after.
+fn has_numbered_params(header: &str) -> bool {
+    local_names(header).any(|name| name.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn optimize_function(lines: &[&str]) -> Vec<String> {
+    let original = || lines.iter().map(|l| l.to_string()).collect();
+    if !is_well_formed(lines) {
+        return original();
+    }
+    let mut lines: Vec<String> = original();
+    loop {
+        let before = lines.len();
+        merge_blocks(&mut lines);
+        forward_memory(&mut lines);
+        remove_unread_allocas(&mut lines);
+        remove_dead(&mut lines);
+        if lines.len() == before {
+            break;
+        }
+    }
+    renumber(&mut lines);
+    lines
+}
+
+/// Every block after the first starts with a label, so no value number is
+/// taken by an unnamed block.
+fn is_well_formed(lines: &[&str]) -> bool {
+    let mut terminated = false;
+    for line in lines {
+        if is_label(line) {
+            terminated = false;
+        } else if line.trim().is_empty() || line.trim_start().starts_with(';') {
+            continue;
+        } else if terminated {
+            return false;
+        } else {
+            terminated = is_terminator(line);
+        }
+    }
+    true
+}
+
+fn is_label(line: &str) -> bool {
+    !line.starts_with(' ') && line.ends_with(':')
+}
+
+/// Whether the block ends with `line`; a `switch` over several lines ends
+/// with its closing `]`.
+fn is_terminator(line: &str) -> bool {
+    let op = line.trim_start();
+    ["br ", "ret ", "unreachable", "resume ", "indirectbr "]
+        .iter()
+        .any(|t| op.starts_with(t))
+        || (op.starts_with("switch ") && !op.ends_with('['))
+        || op == "]"
+}
+
+/// `  br label %next` straight before `next:`, the only mention of `next`:
+/// the two blocks are one.
+fn merge_blocks(lines: &mut Vec<String>) {
+    let mut mentions: HashMap<&str, usize> = HashMap::new();
+    for line in lines.iter() {
+        for name in local_names(line) {
+            *mentions.entry(name).or_default() += 1;
+        }
+    }
+    let mut merged = HashSet::new();
+    for pair in lines.windows(2) {
+        if let Some(target) = pair[0].trim_start().strip_prefix("br label %")
+            && pair[0].starts_with("  ")
+            && pair[1].strip_suffix(':') == Some(target)
+            && mentions.get(target) == Some(&1)
+        {
+            merged.insert(target.to_string());
+        }
+    }
+    if merged.is_empty() {
+        return;
+    }
+    let mut i = 0;
+    while i + 1 < lines.len() {
+        if let Some(target) = lines[i].trim_start().strip_prefix("br label %")
+            && merged.contains(target)
+            && lines[i + 1].strip_suffix(':') == Some(target)
+            // A block that starts with a phi needs its own label
+            && !lines
+                .get(i + 2)
+                .is_some_and(|l| l.contains(" = phi "))
+        {
+            lines.drain(i..i + 2);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// `%r = load T, T* %p`, as (r, T, p), for a plain load.
+fn parse_load(line: &str) -> Option<(&str, &str, &str)> {
+    let (result, rest) = line.trim_start().split_once(" = load ")?;
+    let (ty, pointer) = rest.split_once(", ")?;
+    let pointer = pointer.split(", align").next()?;
+    let pointer = pointer.strip_prefix(ty)?.strip_prefix("* ")?;
+    (result.starts_with('%') && !ty.contains(' ')).then_some((result, ty, pointer))
+}
+
+/// `store T v, T* %p`, as (T, v, p), for a plain store.
+fn parse_store(line: &str) -> Option<(&str, &str, &str)> {
+    let rest = line.trim_start().strip_prefix("store ")?;
+    let (ty, rest) = rest.split_once(' ')?;
+    let (value, pointer) = rest.rsplit_once(", ")?;
+    let (value, pointer) = match pointer.strip_prefix("align ") {
+        Some(_) => rest[..rest.len() - pointer.len() - 2].rsplit_once(", ")?,
+        None => (value, pointer),
+    };
+    let pointer = pointer.strip_prefix(ty)?.strip_prefix("* ")?;
+    (!ty.contains(' ') && !value.contains(',')).then_some((ty, value, pointer))
+}
+
+/// Within each block, what every pointer is known to hold. A store makes
+/// the stored value known, a load of a known value is replaced by it, a load
+/// makes its result known, and storing back the value a pointer is known to
+/// hold does nothing. A store forgets what it may overwrite: a store to an
+/// `alloca` can't reach another `alloca`, and other pointers and calls
+/// can't reach one whose address is only ever loaded from and stored to.
+/// Anything else that may write memory forgets everything else.
+fn forward_memory(lines: &mut Vec<String>) {
+    let allocas = allocas(lines);
+    let private = private_allocas(lines, &allocas);
+    let mut known: HashMap<String, (String, String)> = HashMap::new();
+    let mut computed: HashMap<String, String> = HashMap::new();
+    let mut replace: HashMap<String, String> = HashMap::new();
+    let mut keep = vec![true; lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        let line = &substitute(line, &replace);
+        if is_label(line) {
+            known.clear();
+            computed.clear();
+        } else if let Some((result, ty, pointer)) = parse_load(line) {
+            let pointer = resolve(&replace, pointer);
+            match known.get(&pointer) {
+                Some((known_ty, value)) if known_ty == ty => {
+                    replace.insert(result.to_string(), value.clone());
+                    keep[i] = false;
+                }
+                _ => {
+                    known.insert(pointer, (ty.to_string(), result.to_string()));
+                }
+            }
+        } else if let Some((ty, value, pointer)) = parse_store(line) {
+            let value = resolve(&replace, value);
+            let pointer = resolve(&replace, pointer);
+            if known
+                .get(&pointer)
+                .is_some_and(|(known_ty, known)| known_ty == ty && *known == value)
+            {
+                keep[i] = false;
+            } else {
+                if allocas.contains(&pointer) {
+                    known.retain(|other, _| allocas.contains(other));
+                } else {
+                    known.retain(|other, _| private.contains(other));
+                }
+                known.insert(pointer, (ty.to_string(), value));
+            }
+        } else if let Some((result, value)) = fold_constant(line, &replace) {
+            replace.insert(result.to_string(), value.to_string());
+            keep[i] = false;
+        } else if let Some((result, expression)) = line.trim_start().split_once(" = ")
+            && opcode(line).is_some_and(|op| PURE.contains(&op) && !STATEFUL.contains(&op))
+        {
+            // The same computation again, as in a field address taken twice
+            match computed.get(expression) {
+                Some(earlier) => {
+                    replace.insert(result.to_string(), earlier.clone());
+                    keep[i] = false;
+                }
+                None => {
+                    computed.insert(expression.to_string(), result.to_string());
+                }
+            }
+        } else if !opcode(line).is_some_and(|op| PURE.contains(&op)) {
+            known.retain(|other, _| private.contains(other));
+        }
+    }
+    if replace.is_empty() && keep.iter().all(|k| *k) {
+        return;
+    }
+    let mut i = 0;
+    lines.retain(|_| {
+        i += 1;
+        keep[i - 1]
+    });
+    if !replace.is_empty() {
+        for line in lines.iter_mut() {
+            *line = substitute(line, &replace);
+        }
+    }
+}
+
+fn allocas(lines: &[String]) -> HashSet<String> {
+    lines
+        .iter()
+        .filter(|line| opcode(line) == Some("alloca"))
+        .filter_map(|line| Some(line.trim_start().split_once(" = ")?.0.to_string()))
+        .collect()
+}
+
+/// The `alloca`s used only as the address of plain loads and stores, which
+/// nothing else can read or write.
+fn private_allocas(lines: &[String], allocas: &HashSet<String>) -> HashSet<String> {
+    let mut private = allocas.clone();
+    for line in lines {
+        if opcode(line) == Some("alloca") {
+            continue;
+        }
+        let address = parse_load(line)
+            .map(|(_, _, pointer)| pointer)
+            .or_else(|| parse_store(line).map(|(_, _, pointer)| pointer));
+        let mut mentions = local_names(line).collect::<Vec<_>>();
+        if let Some(address) = address
+            && let Some(at) = mentions.iter().rposition(|name| address[1..] == **name)
+        {
+            mentions.remove(at);
+        }
+        for name in mentions {
+            private.remove(&format!("%{}", name));
+        }
+    }
+    private
+}
+
+/// Private `alloca`s that are never loaded from, and the stores to them.
+fn remove_unread_allocas(lines: &mut Vec<String>) {
+    let private = private_allocas(lines, &allocas(lines));
+    let mut unread = private.clone();
+    for line in lines.iter() {
+        if let Some((_, _, pointer)) = parse_load(line) {
+            unread.remove(pointer);
+        }
+    }
+    if unread.is_empty() {
+        return;
+    }
+    lines.retain(|line| {
+        let target = match parse_store(line) {
+            Some((_, _, pointer)) => Some(pointer),
+            None if opcode(line) == Some("alloca") => line
+                .trim_start()
+                .split_once(" = ")
+                .map(|(result, _)| result),
+            None => None,
+        };
+        !target.is_some_and(|target| unread.contains(target))
+    });
+}
+
+/// `%r = add i64 2, 3` as (`%r`, 5), for `add`, `sub` and `mul`.
+fn fold_constant<'l>(line: &'l str, replace: &HashMap<String, String>) -> Option<(&'l str, i64)> {
+    let (result, rest) = line.trim_start().split_once(" = ")?;
+    let (op, rest) = rest.split_once(" i64 ")?;
+    let (left, right) = rest.split_once(", ")?;
+    let left: i64 = resolve(replace, left).parse().ok()?;
+    let right: i64 = resolve(replace, right).parse().ok()?;
+    let value = match op {
+        "add" => left.wrapping_add(right),
+        "sub" => left.wrapping_sub(right),
+        "mul" => left.wrapping_mul(right),
+        _ => return None,
+    };
+    result.starts_with('%').then_some((result, value))
+}
+
+/// `line` with every replacement made.
+fn substitute(line: &str, replace: &HashMap<String, String>) -> String {
+    if replace.is_empty() {
+        return line.to_string();
+    }
+    rename(line, |name| {
+        let name = format!("%{}", name);
+        replace.contains_key(&name).then(|| resolve(replace, &name))
+    })
+}
+
+/// What `value` stands for once every replacement is made.
+fn resolve(replace: &HashMap<String, String>, value: &str) -> String {
+    let mut value = value;
+    while let Some(next) = replace.get(value) {
+        value = next;
+    }
+    value.to_string()
+}
+
+/// Pure instructions whose result nothing uses.
+fn remove_dead(lines: &mut Vec<String>) {
+    loop {
+        let mut uses: HashMap<&str, usize> = HashMap::new();
+        for line in lines.iter() {
+            let operands = match line.split_once(" = ") {
+                Some((result, rest)) if result.trim_start().starts_with('%') => rest,
+                _ => line,
+            };
+            for name in local_names(operands) {
+                *uses.entry(name).or_default() += 1;
+            }
+        }
+        let dead: Vec<bool> = lines
+            .iter()
+            .map(|line| {
+                let Some((result, _)) = line.split_once(" = ") else {
+                    return false;
+                };
+                let result = result.trim_start();
+                result.starts_with('%')
+                    && !line.contains(" volatile ")
+                    && opcode(line).is_some_and(|op| PURE.contains(&op))
+                    && !uses.contains_key(&result[1..])
+            })
+            .collect();
+        if !dead.contains(&true) {
+            return;
+        }
+        let mut i = 0;
+        lines.retain(|_| {
+            i += 1;
+            !dead[i - 1]
+        });
+    }
+}
+
+/// The instruction's opcode: `add` in `%3 = add i64 %1, %2`, `store` in
+/// `store i64 0, i64* %x`.
+fn opcode(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let op = match line.split_once(" = ") {
+        Some((result, rest)) if result.starts_with('%') => rest,
+        _ => line,
+    };
+    op.split_whitespace().next()
+}
+
+/// Number the unnamed values `%0`, `%1`, ... in the order they are defined
+/// (from `%1` when the entry block has no label, as it then takes `%0`).
+fn renumber(lines: &mut [String]) {
+    let mut numbers = HashMap::new();
+    let first = lines.iter().find(|l| !l.trim().is_empty());
+    let offset = usize::from(first.is_some_and(|l| !is_label(l)));
+    for line in lines.iter() {
+        if let Some((result, _)) = line.trim_start().split_once(" = ")
+            && let Some(n) = result.strip_prefix('%')
+            && !n.is_empty()
+            && n.bytes().all(|b| b.is_ascii_digit())
+        {
+            let next = (numbers.len() + offset).to_string();
+            numbers.insert(n.to_string(), next);
+        }
+    }
+    if numbers.iter().all(|(old, new)| old == new) {
+        return;
+    }
+    for line in lines.iter_mut() {
+        *line = rename(line, |name| numbers.get(name).map(|n| format!("%{}", n)));
+    }
+}
+
+/// The names of the local values and labels `line` mentions, without `%`.
+fn local_names(line: &str) -> impl Iterator<Item = &str> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && is_name_byte(bytes[end]) {
+                    end += 1;
+                }
+                i = end.max(start);
+                if end > start {
+                    return Some(&line[start..end]);
+                }
+            } else if bytes[i] == b'"' {
+                // Skip quoted names and strings
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += 1;
+                }
+                i += 1;
+            } else {
+                i += 1;
+            }
+        }
+        None
+    })
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'$'
+}
+
+/// `line` with each `%name` that `to` maps replaced by what it gives.
+fn rename(line: &str, to: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let bytes = line.as_bytes();
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += 1;
+            }
+            i += 1;
+        } else if bytes[i] == b'%' {
+            let start = i + 1;
+            let mut end = start;
+            while end < bytes.len() && is_name_byte(bytes[end]) {
+                end += 1;
+            }
+            if let Some(new) = to(&line[start..end]) {
+                out.push_str(&line[copied..i]);
+                out.push_str(&new);
+                copied = end;
+            }
+            i = end.max(start);
+        } else {
+            i += 1;
+        }
+    }
+    out.push_str(&line[copied..]);
+    out
+}
@@ -1,5 +1,8 @@
-use crate::parser::{AstNode, BinOp, Pattern};
-use std::collections::HashMap;
+use crate::diagnostic::Diagnostic;
+use crate::parser::{
+    AstNode, BinOp, EnumVariant, Field, Parameter, Pattern, Span, UnOp, is_value_struct,
+};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 struct VarInfo {
@@ -8,15 +11,77 @@ struct VarInfo {
     is_mutable: bool,
     declared_line: usize,
     var_type: String,
+    storage: Storage,
+    /// The Vec this variable borrows an element of (`let r = &v[i];`),
+    /// which can't change while the variable is in scope.
+    element_of: Option<String>,
+}
+
+/// Builtins and `Vec` methods that add, remove or replace elements, which
+/// would pull an element out from under a reference to it.
+const VEC_MUTATORS: &[&str] = &[
+    "vec_push",
+    "vec_pop",
+    "vec_insert",
+    "vec_remove",
+    "vec_set",
+    "vec_clear",
+    "vec_reserve",
+];
+const VEC_MUTATING_METHODS: &[&str] =
+    &["push", "pop", "insert", "remove", "set", "clear", "reserve"];
+
+/// Where the data a variable refers to lives, for checking the references
+/// a function returns.
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Storage {
+    /// In the variable itself: a local's is gone once its function returns.
+    #[default]
+    Owned,
+    /// Outside the function: a reference it was passed, or a top-level
+    /// binding.
+    Outside,
+    /// Inside the named local, which this variable borrows.
+    Local(String),
+}
+
+/// The variables that are moved at some point, each as its scope's depth
+/// in the symbol table and its name.
+type Moves = HashSet<(usize, String)>;
+
+#[derive(Default)]
+struct LoopExits {
+    at_break: Moves,
+    at_continue: Moves,
 }
 
 pub struct SemanticAnalyzer<'a> {
     filename: &'a str,
     symbol_table: Vec<HashMap<String, VarInfo>>,
-    current_line: usize,
-    current_column: usize,
+    /// The node errors point at.
+    current_span: Span,
     in_loop: bool,
+    /// Moves out of the innermost loop's variables that leave its body by
+    /// `break` or `continue` rather than by reaching the end.
+    loop_exits: LoopExits,
+    /// Set while a loop body is checked a second time, with the moves of
+    /// its first iteration in place: the loop's span.
+    repeated_loop: Option<Span>,
     in_unsafe_fn: bool,
+    /// Structs small enough to be copied rather than moved.
+    value_structs: HashSet<String>,
+    /// Every struct's fields, for checking `..base` in struct literals.
+    struct_fields: HashMap<String, Vec<Field>>,
+    /// Declared return type of every function, for typing `let x = f();`.
+    return_types: HashMap<String, String>,
+    /// Every function's parameters, for checking what its calls pass.
+    params: HashMap<String, Vec<Parameter>>,
+    /// The declared return type of the function being checked.
+    current_return_type: Option<String>,
+    /// `impl` methods (`Type.method`) → the type of their `self` parameter.
+    methods: HashMap<String, String>,
+    /// Every enum's variants, for checking `size_of` and patterns.
+    enums: HashMap<String, Vec<EnumVariant>>,
 }
 
 impl<'a> SemanticAnalyzer<'a> {
@@ -24,57 +89,232 @@ impl<'a> SemanticAnalyzer<'a> {
         SemanticAnalyzer {
             filename,
             symbol_table: vec![HashMap::new()],
-            current_line: 1,
-            current_column: 1,
+            current_span: Span {
+                line: 1,
+                column: 1,
+                ..Span::default()
+            },
             in_loop: false,
+            loop_exits: LoopExits::default(),
+            repeated_loop: None,
             in_unsafe_fn: false,
+            value_structs: HashSet::new(),
+            struct_fields: HashMap::new(),
+            return_types: HashMap::new(),
+            params: HashMap::new(),
+            current_return_type: None,
+            methods: HashMap::new(),
+            enums: HashMap::new(),
         }
     }
 
     fn is_copy_type(&self, name: &str) -> bool {
         if let Some(info) = self.lookup_variable(name) {
             matches!(info.var_type.as_str(), "int" | "bool" | "char")
+                || info.var_type.starts_with('*')
+                || self.value_structs.contains(&info.var_type)
         } else {
             false
         }
     }
 
-    pub fn analyze(&mut self, ast: &AstNode) -> Result<(), String> {
+    pub fn analyze(&mut self, ast: &AstNode) -> Result<(), Diagnostic> {
+        if let AstNode::Program(nodes, _) = ast {
+            self.check_struct_cycles(nodes)?;
+            self.check_global_cycles(nodes)?;
+            for node in nodes {
+                match node {
+                    AstNode::StructDef { name, fields, .. } => {
+                        if is_value_struct(fields.iter().map(|f| f.field_type.as_str())) {
+                            self.value_structs.insert(name.clone());
+                        }
+                        self.struct_fields.insert(name.clone(), fields.clone());
+                    }
+                    AstNode::EnumDef { name, variants, .. } => {
+                        self.enums.insert(name.clone(), variants.clone());
+                    }
+                    AstNode::ExternFn { name, params, .. } => {
+                        self.params.insert(name.clone(), params.clone());
+                    }
+                    AstNode::FunctionDef {
+                        name,
+                        params,
+                        return_type,
+                        ..
+                    } => {
+                        if let Some(rt) = return_type {
+                            self.return_types.insert(name.clone(), rt.clone());
+                        }
+                        self.params.insert(name.clone(), params.clone());
+                        if name.contains('.')
+                            && let Some(receiver) = params.first()
+                        {
+                            self.methods
+                                .insert(name.clone(), receiver.param_type.clone());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
         self.visit(ast)
     }
 
-    fn visit(&mut self, node: &AstNode) -> Result<(), String> {
+    /// A struct that contains itself by value, directly or through other
+    /// structs, has no finite layout; the cycle has to go through a `*T`.
+    fn check_struct_cycles(&self, nodes: &[AstNode]) -> Result<(), Diagnostic> {
+        let structs: HashMap<&str, &[Field]> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                AstNode::StructDef { name, fields, .. } => Some((name.as_str(), fields.as_slice())),
+                _ => None,
+            })
+            .collect();
+
+        fn visit<'n>(
+            name: &'n str,
+            structs: &HashMap<&'n str, &'n [Field]>,
+            path: &mut Vec<&'n str>,
+            done: &mut HashSet<&'n str>,
+        ) -> Option<(&'n str, &'n Field)> {
+            if done.contains(name) {
+                return None;
+            }
+            path.push(name);
+            for field in structs[name].iter() {
+                let field_type = field.field_type.as_str();
+                if !structs.contains_key(field_type) {
+                    continue;
+                }
+                if path.contains(&field_type) {
+                    return Some((name, field));
+                }
+                if let Some(found) = visit(field_type, structs, path, done) {
+                    return Some(found);
+                }
+            }
+            path.pop();
+            done.insert(name);
+            None
+        }
+
+        let mut done = HashSet::new();
+        let in_order = nodes.iter().filter_map(|node| match node {
+            AstNode::StructDef { name, .. } => Some(name.as_str()),
+            _ => None,
+        });
+        for name in in_order {
+            if let Some((owner, field)) = visit(name, &structs, &mut Vec::new(), &mut done) {
+                return Err(Diagnostic::error(format!(
+                    "struct '{}' in '{}' contains itself through field '{}' and has no finite size",
+                    owner, self.filename, field.name
+                ))
+                .with_code("E0409")
+                .with_help(format!(
+                    "store it behind a pointer: '{}: *{}' or '{}: Box<{}>'",
+                    field.name, field.field_type, field.name, field.field_type
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// A top-level binding whose initializer needs its own value, directly
+    /// or through other bindings and the functions they call, can't be
+    /// initialized.
+    fn check_global_cycles(&mut self, nodes: &[AstNode]) -> Result<(), Diagnostic> {
+        let Err(cycle) = global_init_order(nodes) else {
+            return Ok(());
+        };
+        if let Some(binding) = nodes
+            .iter()
+            .find(|n| matches!(n, AstNode::LetBinding { name, .. } if *name == cycle[0]))
+        {
+            self.current_span = binding.span();
+        }
+        Err(self
+            .error(&format!(
+                "top-level binding '{}' depends on its own value",
+                cycle[0]
+            ))
+            .with_code("E0412")
+            .with_length(cycle[0].chars().count())
+            .with_note(format!("initialization cycle: {}", cycle.join(" -> ")))
+            .with_help("compute one of these values inside 'main' instead"))
+    }
+
+    fn visit(&mut self, node: &AstNode) -> Result<(), Diagnostic> {
         match node {
-            AstNode::Program(nodes) => {
+            AstNode::Program(nodes, _) => {
+                // Top-level bindings first, in the order they are
+                // initialized, so every function can see all of them
+                let globals = global_init_order(nodes).unwrap_or_default();
+                for global in &globals {
+                    self.visit(global)?;
+                }
                 for node in nodes {
-                    self.visit(node)?;
+                    if !matches!(node, AstNode::LetBinding { .. }) {
+                        self.visit(node)?;
+                    }
                 }
                 Ok(())
             }
 
-            AstNode::Import { .. } => Ok(()),
+            AstNode::Import { .. } | AstNode::When { .. } | AstNode::ExternFn { .. } => Ok(()),
 
             AstNode::FunctionDef {
+                name,
                 params,
                 body,
+                return_type,
                 is_unsafe,
+                span,
                 ..
             } => {
+                self.current_span = *span;
+                if name == "main"
+                    && !(params.is_empty()
+                        || (params.len() == 1 && params[0].param_type == "Vec<string>"))
+                {
+                    return Err(self
+                        .error("'main' must take no parameters or a single 'Vec<string>'")
+                        .with_code("E0408")
+                        .with_help("declare it as 'fn main()' or 'fn main(args: Vec<string>)'"));
+                }
+                if node.has_attribute("no_mangle") {
+                    self.check_c_signature(name, params, return_type)?;
+                }
+                if let Some(hook) = ["startup", "shutdown"]
+                    .into_iter()
+                    .find(|hook| node.has_attribute(hook))
+                    && (!params.is_empty() || return_type.is_some())
+                {
+                    return Err(self
+                        .error(&format!(
+                            "'#[{}]' function '{}' must take no parameters and return nothing",
+                            hook, name
+                        ))
+                        .with_code("E0422")
+                        .with_help(format!("the entry point calls it as '{}()'", name)));
+                }
                 let prev_unsafe = self.in_unsafe_fn;
                 self.in_unsafe_fn = *is_unsafe;
+                let prev_return_type =
+                    std::mem::replace(&mut self.current_return_type, return_type.clone());
                 self.push_scope();
                 for param in params {
                     // Mutex params are always by-reference — enforce this
                     if param.param_type.starts_with("Mutex<") && !param.is_reference {
-                        return Err(format!(
-                            "{}:{}:{}: Error: Mutex '{}' must be passed by reference '&Mutex<...>', not by value\n    Help: Change to '&{}' or '&mut {}'",
-                            self.filename,
-                            self.current_line,
-                            self.current_column,
-                            param.name,
-                            param.name,
-                            param.name
-                        ));
+                        return Err(self
+                            .error(&format!(
+                                "Mutex '{}' must be passed by reference '&Mutex<...>', not by value",
+                                param.name
+                            ))
+                            .with_code("E0406")
+                            .with_help(format!(
+                                "change to '&{}' or '&mut {}'",
+                                param.name, param.name
+                            )));
                     }
                     let effective_mutable =
                         param.is_mutable || param.param_type.starts_with("&mut ");
@@ -84,10 +324,14 @@ impl<'a> SemanticAnalyzer<'a> {
                         param.param_type.clone()
                     };
                     self.declare_variable(&param.name, effective_mutable, clean_type, 0);
+                    if param.is_reference || param.param_type.starts_with('&') {
+                        self.set_storage(&param.name, Storage::Outside);
+                    }
                 }
                 self.visit(body)?;
                 self.pop_scope();
                 self.in_unsafe_fn = prev_unsafe;
+                self.current_return_type = prev_return_type;
                 Ok(())
             }
 
@@ -96,14 +340,30 @@ impl<'a> SemanticAnalyzer<'a> {
                 name,
                 value,
                 type_annotation,
-                location,
+                span,
                 ..
             } => {
-                self.current_line = location.line;
-                self.current_column = location.column;
+                self.current_span = *span;
+                if matches!(value.as_ref(), AstNode::Null(_))
+                    && !type_annotation
+                        .as_deref()
+                        .is_some_and(|t| t.starts_with('*'))
+                {
+                    return Err(self
+                        .error(&format!(
+                            "'{}' is initialized with 'null' but is not a pointer",
+                            name
+                        ))
+                        .with_code("E0410")
+                        .with_help(format!("give it a pointer type: 'let {}: *T = null'", name)));
+                }
                 self.visit(value)?;
+                if let Some(annotation) = type_annotation {
+                    self.check_conversion(value, annotation)?;
+                }
                 if let AstNode::Identifier { name: var_name, .. } = value.as_ref() {
                     self.check_not_consumed(var_name)?;
+                    self.check_elements_not_borrowed(var_name)?;
                     self.consume_variable(var_name)?;
                 }
                 let guard_type = if let AstNode::MethodCall { object, method, .. } = value.as_ref()
@@ -111,7 +371,8 @@ impl<'a> SemanticAnalyzer<'a> {
                     if method == "lock" {
                         if let AstNode::Identifier { name: obj_name, .. } = object.as_ref() {
                             if let Some(info) = self.lookup_variable(obj_name) {
-                                let obj_type = info.var_type.clone();
+                                // `m: &Mutex<T>` params keep their '&'
+                                let obj_type = info.var_type.trim_start_matches('&');
                                 if obj_type.starts_with("Mutex<") {
                                     let inner = &obj_type[6..obj_type.len() - 1];
                                     Some(format!("MutexGuard<{}>", inner))
@@ -134,26 +395,39 @@ impl<'a> SemanticAnalyzer<'a> {
                 let var_type = guard_type
                     .or_else(|| type_annotation.clone())
                     .unwrap_or_else(|| self.infer_type(value));
-                self.declare_variable(name, *mutable, var_type, location.line);
+                let storage = self.storage_of(value);
+                self.declare_variable(name, *mutable, var_type, span.line);
+                self.set_storage(name, storage);
+                if let AstNode::Reference(inner, _) = value.as_ref()
+                    && let AstNode::Index { array, .. } = inner.as_ref()
+                    && let AstNode::Identifier { name: vec, .. } = array.as_ref()
+                    && self.vec_element_type(array).is_some()
+                    && let Some(info) = self.symbol_table.last_mut().unwrap().get_mut(name)
+                {
+                    info.element_of = Some(vec.clone());
+                }
                 Ok(())
             }
 
             AstNode::Assignment {
-                name,
-                value,
-                location,
+                name, value, span, ..
             } => {
-                self.current_line = location.line;
-                self.current_column = location.column;
+                self.current_span = *span;
                 self.check_variable_exists(name)?;
                 self.check_not_consumed(name)?;
                 self.check_is_mutable(name)?;
                 self.check_not_borrowed(name)?;
+                self.check_elements_not_borrowed(name)?;
                 self.visit(value)?;
+                if let Some(var_type) = self.get_type(name).map(str::to_string) {
+                    self.check_conversion(value, &var_type)?;
+                }
                 if let AstNode::Identifier { name: var_name, .. } = value.as_ref() {
                     self.check_not_consumed(var_name)?;
                     self.consume_variable(var_name)?;
                 }
+                let storage = self.storage_of(value);
+                self.set_storage(name, storage);
                 Ok(())
             }
 
@@ -161,13 +435,14 @@ impl<'a> SemanticAnalyzer<'a> {
                 array,
                 index,
                 value,
-                location,
+                span,
+                ..
             } => {
-                self.current_line = location.line;
-                self.current_column = location.column;
+                self.current_span = *span;
                 self.check_variable_exists(array)?;
                 self.check_not_consumed(array)?;
                 self.check_is_mutable(array)?;
+                self.check_elements_not_borrowed(array)?;
                 self.visit(index)?;
                 self.visit(value)?;
                 Ok(())
@@ -175,23 +450,57 @@ impl<'a> SemanticAnalyzer<'a> {
 
             AstNode::MemberAssignment {
                 object,
+                field,
                 value,
-                location,
+                span,
                 ..
             } => {
-                self.current_line = location.line;
-                self.current_column = location.column;
-                self.check_variable_exists(object)?;
+                self.current_span = *span;
+                self.visit_place(object)?;
+                // `a.b.c = v` (or `a[i].c = v`) changes `a`, so `a` is what
+                // must be mutable
+                let mut root = object.as_ref();
+                while let AstNode::MemberAccess { object, .. }
+                | AstNode::Index { array: object, .. } = root
+                {
+                    root = object;
+                }
+                if let AstNode::Identifier { name, .. } = root {
+                    self.check_can_mutate(name, &format!("assign to field '{}'", field))?;
+                    // `v[i].x = ...` writes to an element a reference may hold
+                    self.check_elements_not_borrowed(name)?;
+                }
                 self.visit(value)?;
+                // The field takes what it is given, as in a struct literal
+                if let AstNode::Identifier { name: var_name, .. } = value.as_ref() {
+                    self.consume_variable(var_name)?;
+                }
                 Ok(())
             }
 
-            AstNode::Block(statements) => {
+            AstNode::Block(statements, _) => {
+                // Moves made in a block that ends in `return`, `break` or
+                // `continue` don't reach the code after it
+                let entry = self.moves();
+                let mut diverges = false;
                 self.push_scope();
                 for stmt in statements {
                     self.visit(stmt)?;
+                    if diverges {
+                        continue;
+                    }
+                    match stmt {
+                        AstNode::Return(..) => {}
+                        AstNode::Break(_) => self.loop_exits.at_break.extend(self.moves()),
+                        AstNode::Continue(_) => self.loop_exits.at_continue.extend(self.moves()),
+                        _ => continue,
+                    }
+                    diverges = true;
                 }
                 self.pop_scope();
+                if diverges {
+                    self.set_moves(&entry);
+                }
                 Ok(())
             }
 
@@ -199,6 +508,7 @@ impl<'a> SemanticAnalyzer<'a> {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
                 self.visit(condition)?;
                 self.visit(then_block)?;
@@ -208,47 +518,74 @@ impl<'a> SemanticAnalyzer<'a> {
                 Ok(())
             }
 
-            AstNode::While { condition, body } => {
+            AstNode::While {
+                condition,
+                body,
+                span,
+            } => {
                 self.visit(condition)?;
-                let was_in_loop = self.in_loop;
-                self.in_loop = true;
-                self.visit(body)?;
-                self.in_loop = was_in_loop;
-                Ok(())
+                self.visit_loop(Some(condition), body, *span)
             }
 
             AstNode::For {
                 variable,
+                index,
                 iterator,
                 body,
+                span,
             } => {
                 self.visit(iterator)?;
+                self.check_iterated(iterator)?;
+                // Looping over a Vec binds copies of its elements
+                let iterated = self.infer_type(iterator);
+                let element_type = match iterated
+                    .trim_start_matches("&mut ")
+                    .trim_start_matches('&')
+                {
+                    vec if vec.starts_with("Vec<") => vec["Vec<".len()..vec.len() - 1].to_string(),
+                    _ => "int".to_string(),
+                };
                 self.push_scope();
-                self.declare_variable(variable, false, "int".to_string(), self.current_line);
-                let was_in_loop = self.in_loop;
-                self.in_loop = true;
-                self.visit(body)?;
-                self.in_loop = was_in_loop;
+                if let Some(index) = index {
+                    self.declare_variable(index, false, "int".to_string(), self.current_span.line);
+                }
+                self.declare_variable(variable, false, element_type, self.current_span.line);
+                let result = self.visit_loop(None, body, *span);
                 self.pop_scope();
-                Ok(())
+                result
             }
 
-            AstNode::Match { value, arms } => {
+            AstNode::Match { value, arms, span } => {
                 self.visit(value)?;
+                let matched = self.infer_type(value);
+                let matched = matched.trim_start_matches("&mut ").trim_start_matches('&');
                 for arm in arms {
                     self.push_scope();
                     match &arm.pattern {
-                        Pattern::EnumPattern {
-                            binding: Some(b), ..
-                        } => {
-                            self.declare_variable(b, false, "int".to_string(), self.current_line);
+                        pattern @ (Pattern::EnumPattern { .. } | Pattern::StructPattern { .. }) => {
+                            self.current_span = *span;
+                            if let Err(e) = self.check_pattern(pattern, matched) {
+                                self.pop_scope();
+                                return Err(e);
+                            }
                         }
                         Pattern::Identifier(name) if name != "_" => {
                             self.declare_variable(
                                 name,
                                 false,
                                 "unknown".to_string(),
-                                self.current_line,
+                                self.current_span.line,
+                            );
+                        }
+                        Pattern::PrefixPattern { rest, .. }
+                        | Pattern::SuffixPattern { rest, .. }
+                            if rest != "_" =>
+                        {
+                            self.declare_variable(
+                                rest,
+                                false,
+                                "string".to_string(),
+                                self.current_span.line,
                             );
                         }
                         _ => {}
@@ -260,60 +597,45 @@ impl<'a> SemanticAnalyzer<'a> {
                 Ok(())
             }
 
-            AstNode::Return(value) => {
+            AstNode::Return(value, span) => {
                 if let Some(value) = value {
                     self.visit(value)?;
+                    self.check_not_dangling(value, *span)?;
+                    if let Some(return_type) = self.current_return_type.clone() {
+                        self.check_conversion(value, &return_type)?;
+                    }
                 }
                 Ok(())
             }
 
-            AstNode::Break => {
+            AstNode::Break(span) => {
+                self.current_span = *span;
                 if !self.in_loop {
-                    return Err(format!(
-                        "{}:{}:{}: Error: 'break' outside of loop",
-                        self.filename, self.current_line, self.current_column
-                    ));
+                    return Err(self
+                        .error("'break' outside of loop")
+                        .with_code("E0405")
+                        .with_length(5));
                 }
                 Ok(())
             }
 
-            AstNode::Continue => {
+            AstNode::Continue(span) => {
+                self.current_span = *span;
                 if !self.in_loop {
-                    return Err(format!(
-                        "{}:{}:{}: Error: 'continue' outside of loop",
-                        self.filename, self.current_line, self.current_column
-                    ));
+                    return Err(self
+                        .error("'continue' outside of loop")
+                        .with_code("E0405")
+                        .with_length(8));
                 }
                 Ok(())
             }
 
-            AstNode::ExpressionStatement(expr) => self.visit(expr),
+            AstNode::ExpressionStatement(expr, _) => self.visit(expr),
 
-            AstNode::BinaryOp { left, right, op } => {
+            // `+` on strings only reads its operands (see `StringOwnership`)
+            AstNode::BinaryOp { left, right, .. } => {
                 self.visit(left)?;
                 self.visit(right)?;
-                if matches!(op, BinOp::Add) {
-                    let left_var = match left.as_ref() {
-                        AstNode::Identifier { name, .. } => Some(name.as_str()),
-                        _ => None,
-                    };
-                    let right_var = match right.as_ref() {
-                        AstNode::Identifier { name, .. } => Some(name.as_str()),
-                        _ => None,
-                    };
-                    if let Some(var) = right_var {
-                        if self.get_type(var) == Some("string") {
-                            self.check_not_consumed(var)?;
-                            self.consume_variable(var)?;
-                        }
-                    }
-                    if let Some(var) = left_var {
-                        if self.get_type(var) == Some("string") {
-                            self.check_not_consumed(var)?;
-                            self.consume_variable(var)?;
-                        }
-                    }
-                }
                 Ok(())
             }
 
@@ -322,35 +644,60 @@ impl<'a> SemanticAnalyzer<'a> {
                 Ok(())
             }
 
-            AstNode::Identifier { name, location } => {
-                self.current_line = location.line;
-                self.current_column = location.column;
+            AstNode::Cast {
+                value,
+                target,
+                span,
+            } => {
+                self.visit(value)?;
+                self.current_span = *span;
+                self.check_cast(value, target)
+            }
+
+            AstNode::Identifier { name, span } => {
+                self.current_span = *span;
                 self.check_variable_exists(name)?;
                 self.check_not_consumed(name)?;
                 Ok(())
             }
 
-            AstNode::Reference(expr) => {
+            AstNode::Reference(expr, _) => {
                 if let AstNode::Identifier { name: var_name, .. } = expr.as_ref() {
                     self.check_not_consumed(var_name)?;
                     self.borrow_variable(var_name)?;
                 }
-                self.visit(expr)?;
+                self.visit_place(expr)?;
                 if let AstNode::Identifier { name: var_name, .. } = expr.as_ref() {
                     self.release_borrow(var_name);
                 }
                 Ok(())
             }
 
-            AstNode::Call { name: _, args } => {
+            AstNode::Call { name, args, .. } => {
+                if VEC_MUTATORS.contains(&name.as_str())
+                    && let Some(first) = args.first()
+                    && let AstNode::Identifier {
+                        name: vec,
+                        span: vec_span,
+                    } = match first {
+                        AstNode::Reference(inner, _) => inner.as_ref(),
+                        other => other,
+                    }
+                {
+                    self.current_span = *vec_span;
+                    self.check_elements_not_borrowed(vec)?;
+                }
                 let mut borrowed_vars: Vec<String> = Vec::new();
                 for arg in args.iter() {
-                    if let AstNode::Reference(ref_expr) = arg {
+                    if let AstNode::Reference(ref_expr, _) = arg {
                         if let AstNode::Identifier { name: var_name, .. } = ref_expr.as_ref() {
                             self.check_not_consumed(var_name)?;
                             self.borrow_variable(var_name)?;
                             borrowed_vars.push(var_name.clone());
                         }
+                    } else if matches!(name.as_str(), "print" | "println" | "debug" | "hash") {
+                        // These only read their argument, wherever it lives
+                        self.visit_place(arg)?;
                     } else {
                         self.visit(arg)?;
                         // Note: we intentionally do not consume value-passed identifiers here.
@@ -358,6 +705,14 @@ impl<'a> SemanticAnalyzer<'a> {
                         // false positives when the same variable name appears across functions.
                     }
                 }
+                let param_types: Vec<String> = self
+                    .params
+                    .get(name)
+                    .map(|params| params.iter().map(|p| p.param_type.clone()).collect())
+                    .unwrap_or_default();
+                for (arg, param_type) in args.iter().zip(&param_types) {
+                    self.check_conversion(arg, param_type)?;
+                }
                 for var_name in &borrowed_vars {
                     self.release_borrow(var_name);
                 }
@@ -376,23 +731,43 @@ impl<'a> SemanticAnalyzer<'a> {
                 }
                 if let AstNode::Identifier {
                     name: obj_name,
-                    location,
+                    span,
                 } = object.as_ref()
                 {
-                    self.current_line = location.line;
-                    self.current_column = location.column;
+                    self.current_span = *span;
+                    if VEC_MUTATING_METHODS.contains(&method.as_str())
+                        && self.vec_element_type(object).is_some()
+                    {
+                        self.check_elements_not_borrowed(obj_name)?;
+                    }
                     if let Some(info) = self.lookup_variable(obj_name) {
                         let obj_type = info.var_type.clone();
-                        if obj_type.starts_with("Mutex<") {
-                            if method != "lock" {
-                                return Err(format!(
-                                    "{}:{}:{}: Error: '{}' is not a valid method on Mutex — only '.lock()' is allowed\n    Help: Use '{}.lock()' to acquire the guard",
-                                    self.filename,
-                                    self.current_line,
-                                    self.current_column,
-                                    method,
-                                    obj_name
-                                ));
+                        if obj_type.starts_with("Mutex<") && method != "lock" {
+                            return Err(self
+                                    .error(&format!(
+                                        "'{}' is not a valid method on Mutex — only '.lock()' is allowed",
+                                        method
+                                    ))
+                                    .with_code("E0407")
+                                    .with_length(obj_name.chars().count())
+                                    .with_help(format!("use '{}.lock()' to acquire the guard", obj_name)));
+                        }
+                        let struct_name = obj_type
+                            .trim_start_matches("&mut ")
+                            .trim_start_matches(['&', '*']);
+                        if let Some(receiver) = self
+                            .methods
+                            .get(&format!("{}.{}", struct_name, method))
+                            .cloned()
+                        {
+                            if receiver.starts_with("&mut ") {
+                                self.check_can_mutate(
+                                    obj_name,
+                                    &format!("call '{}', which takes '&mut self',", method),
+                                )?;
+                            } else if !receiver.starts_with('&') {
+                                self.check_not_consumed(obj_name)?;
+                                self.consume_variable(obj_name)?;
                             }
                         }
                     }
@@ -400,45 +775,387 @@ impl<'a> SemanticAnalyzer<'a> {
                 Ok(())
             }
 
-            AstNode::MemberAccess { object, .. } => self.visit(object),
+            AstNode::MemberAccess { object, .. } => self.visit_place(object),
 
-            AstNode::Index { array, index } => {
+            AstNode::Index { array, index, span } => {
                 self.visit(array)?;
                 self.visit(index)?;
+                // Only a copy can leave; places that use the element where
+                // it is go through `visit_place`
+                if let Some(element) = self.vec_element_type(array)
+                    && self.struct_fields.contains_key(&element)
+                    && !self.value_structs.contains(&element)
+                {
+                    self.current_span = *span;
+                    let vec = match array.as_ref() {
+                        AstNode::Identifier { name, .. } => name.as_str(),
+                        _ => "the Vec",
+                    };
+                    return Err(self
+                        .error(&format!("cannot move a '{}' out of '{}'", element, vec))
+                        .with_code("E0419")
+                        .with_label("moves the element out of the Vec")
+                        .with_note("the Vec still owns the element, and frees it with itself")
+                        .with_help(format!(
+                            "borrow it with '&{}[i]', or copy it with 'vec_get(&{}, i)'",
+                            vec, vec
+                        )));
+                }
                 Ok(())
             }
 
-            AstNode::ArrayLit(elements) => {
+            AstNode::Slice {
+                value, start, end, ..
+            } => {
+                self.visit(value)?;
+                for bound in [start, end].into_iter().flatten() {
+                    self.visit(bound)?;
+                }
+                let sliced = self.infer_type(value);
+                if !matches!(
+                    sliced.as_str(),
+                    "string" | "&string" | "&mut string" | "unknown"
+                ) {
+                    self.current_span = value.span();
+                    return Err(self
+                        .error(&format!("cannot slice a value of type '{}'", sliced))
+                        .with_code("E0411")
+                        .with_help("only strings can be sliced with 's[start..end]'"));
+                }
+                Ok(())
+            }
+
+            AstNode::ArrayLit(elements, _) => {
                 for elem in elements {
                     self.visit(elem)?;
                 }
                 Ok(())
             }
 
-            AstNode::StructInit { fields, .. } => {
+            AstNode::StructInit {
+                name,
+                fields,
+                base,
+                span,
+            } => {
                 for (_, value) in fields {
                     self.visit(value)?;
+                    // The struct takes what it is given
+                    if let AstNode::Identifier { name: var_name, .. } = value {
+                        self.consume_variable(var_name)?;
+                    }
+                }
+                let Some(base) = base else {
+                    return Ok(());
+                };
+                self.visit(base)?;
+                // The base is only read: owned fields can't be shared with it
+                let declared = self.struct_fields.get(name).cloned().unwrap_or_default();
+                for field in declared {
+                    let ty = field.field_type.as_str();
+                    let copyable = matches!(ty, "int" | "bool" | "char" | "string")
+                        || ty.starts_with('*')
+                        || self.value_structs.contains(ty);
+                    if !copyable && !fields.iter().any(|(n, _)| *n == field.name) {
+                        self.current_span = *span;
+                        return Err(self
+                            .error(&format!(
+                                "field '{}' of type '{}' can't be taken from '..base'",
+                                field.name, ty
+                            ))
+                            .with_code("E0415")
+                            .with_note(format!(
+                                "'{}' is owned by the base struct, which is only borrowed",
+                                field.name
+                            ))
+                            .with_help(format!(
+                                "give '{}' a value in the struct literal",
+                                field.name
+                            )));
+                    }
                 }
                 Ok(())
             }
 
-            AstNode::EnumValue { value, .. } => {
-                if let Some(value) = value {
+            AstNode::EnumValue {
+                enum_name,
+                variant,
+                values,
+                span,
+            } => {
+                for value in values {
                     self.visit(value)?;
                 }
+                let holds = self
+                    .enums
+                    .get(enum_name)
+                    .and_then(|variants| variants.iter().find(|v| v.name == *variant))
+                    .map_or(values.len(), |v| v.fields.len());
+                if !values.is_empty() && values.len() != holds {
+                    self.current_span = *span;
+                    return Err(self
+                        .error(&format!(
+                            "variant '{}::{}' holds {}, but {} given",
+                            enum_name,
+                            variant,
+                            count(holds, "value"),
+                            if values.len() == 1 {
+                                "1 was".to_string()
+                            } else {
+                                format!("{} were", values.len())
+                            }
+                        ))
+                        .with_code("E0418"));
+                }
                 Ok(())
             }
 
-            AstNode::StructDef { .. } => Ok(()),
+            AstNode::StructDef { fields, .. } => {
+                for default in fields.iter().filter_map(|f| f.default.as_ref()) {
+                    self.visit(default)?;
+                }
+                Ok(())
+            }
             AstNode::EnumDef { .. } => Ok(()),
             AstNode::ArrayType { .. } => Ok(()),
-            AstNode::Number(_) => Ok(()),
-            AstNode::Boolean(_) => Ok(()),
-            AstNode::Character(_) => Ok(()),
-            AstNode::StringLit(_) => Ok(()),
+            AstNode::Number(_, _) => Ok(()),
+            AstNode::Boolean(_, _) => Ok(()),
+            AstNode::Null(_) => Ok(()),
+            AstNode::Character(_, _) => Ok(()),
+            AstNode::StringLit(_, _) | AstNode::BytesLit(_, _) => Ok(()),
+            AstNode::SizeOf(ty, span) => {
+                if self.is_known_type(ty) {
+                    return Ok(());
+                }
+                self.current_span = *span;
+                Err(self
+                    .error(&format!("unknown type '{}' in 'size_of'", ty))
+                    .with_code("E0416")
+                    .with_help("'size_of' takes a built-in type or a declared struct or enum"))
+            }
+        }
+    }
+
+    /// Check a loop's body, and its condition for a `while`, as run again
+    /// after an iteration: a value moved in one iteration can't be used in
+    /// the next. The loop's own variables (`for x in ...`) are in the
+    /// innermost scope and start over each time.
+    fn visit_loop(
+        &mut self,
+        condition: Option<&AstNode>,
+        body: &AstNode,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let outer_depth = if condition.is_some() {
+            self.symbol_table.len()
+        } else {
+            self.symbol_table.len() - 1
+        };
+        let before = self.moves();
+        let was_in_loop = std::mem::replace(&mut self.in_loop, true);
+        let outer_exits = std::mem::take(&mut self.loop_exits);
+
+        let result = self.visit(body).and_then(|()| {
+            let mut next_iteration = self.moves();
+            next_iteration.extend(self.loop_exits.at_continue.iter().cloned());
+            next_iteration.retain(|(depth, _)| *depth < outer_depth);
+            if next_iteration.is_subset(&before) {
+                return Ok(());
+            }
+            self.set_moves(&next_iteration);
+            let outer_repeated = self.repeated_loop.replace(span);
+            let repeated = condition
+                .map_or(Ok(()), |condition| self.visit(condition))
+                .and_then(|()| self.visit(body));
+            self.repeated_loop = outer_repeated;
+            self.set_moves(&next_iteration);
+            repeated
+        });
+
+        // After the loop, anything moved on the way out of it is gone
+        let at_break = std::mem::replace(&mut self.loop_exits, outer_exits).at_break;
+        let mut after = self.moves();
+        after.extend(at_break);
+        self.set_moves(&after);
+        self.in_loop = was_in_loop;
+        result
+    }
+
+    fn moves(&self) -> Moves {
+        self.symbol_table
+            .iter()
+            .enumerate()
+            .flat_map(|(depth, scope)| {
+                scope
+                    .iter()
+                    .filter(|(_, info)| info.is_consumed)
+                    .map(move |(name, _)| (depth, name.clone()))
+            })
+            .collect()
+    }
+
+    fn set_moves(&mut self, moves: &Moves) {
+        for (depth, scope) in self.symbol_table.iter_mut().enumerate() {
+            for (name, info) in scope.iter_mut() {
+                info.is_consumed = moves.contains(&(depth, name.clone()));
+            }
         }
     }
 
+    /// A pattern against the type of the value it matches, which for a
+    /// variant's payload or a struct's field is the declared one. Names are
+    /// declared in the arm's scope; a variant's pattern may leave out its
+    /// values entirely, and a struct's may leave out fields after `..`.
+    fn check_pattern(&mut self, pattern: &Pattern, ty: &str) -> Result<(), Diagnostic> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Identifier(name) => {
+                self.declare_variable(name, false, ty.to_string(), self.current_span.line);
+                Ok(())
+            }
+            Pattern::NumberPattern(_) if matches!(ty, "int" | "char" | "unknown") => Ok(()),
+            Pattern::StringPattern(_) if matches!(ty, "string" | "unknown") => Ok(()),
+            Pattern::EnumPattern {
+                enum_name,
+                variant,
+                payload,
+            } => {
+                let enum_name = if enum_name.is_empty() { ty } else { enum_name };
+                let Some(variants) = self.enums.get(enum_name) else {
+                    return Err(self
+                        .error(&format!("no enum to take the variant '{}' from", variant))
+                        .with_code("E0418")
+                        .with_help(format!("name the enum: 'Enum::{}'", variant)));
+                };
+                if self.enums.contains_key(ty) && ty != enum_name {
+                    return Err(self.pattern_mismatch(pattern, ty));
+                }
+                let Some(fields) = variants
+                    .iter()
+                    .find(|v| v.name == *variant)
+                    .map(|v| v.fields.clone())
+                else {
+                    return Err(self
+                        .error(&format!(
+                            "enum '{}' has no variant '{}'",
+                            enum_name, variant
+                        ))
+                        .with_code("E0418"));
+                };
+                if !payload.is_empty() && payload.len() != fields.len() {
+                    let values = (0..fields.len())
+                        .map(|i| format!("v{}", i))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return Err(self
+                        .error(&format!(
+                            "variant '{}::{}' holds {}, but its pattern has {}",
+                            enum_name,
+                            variant,
+                            count(fields.len(), "value"),
+                            payload.len()
+                        ))
+                        .with_code("E0418")
+                        .with_help(if fields.is_empty() {
+                            format!("match it as '{}::{}'", enum_name, variant)
+                        } else {
+                            format!("match it as '{}::{}({})'", enum_name, variant, values)
+                        }));
+                }
+                payload
+                    .iter()
+                    .zip(&fields)
+                    .try_for_each(|(pattern, field_type)| self.check_pattern(pattern, field_type))
+            }
+            Pattern::StructPattern {
+                struct_name,
+                fields,
+                rest,
+            } => {
+                let Some(declared) = self.struct_fields.get(struct_name).cloned() else {
+                    return Err(self
+                        .error(&format!("unknown struct '{}' in pattern", struct_name))
+                        .with_code("E0418"));
+                };
+                if ty != "unknown" && ty != struct_name {
+                    return Err(self.pattern_mismatch(pattern, ty));
+                }
+                for (field, pattern) in fields {
+                    let Some(declared) = declared.iter().find(|f| f.name == *field) else {
+                        return Err(self
+                            .error(&format!(
+                                "struct '{}' has no field '{}'",
+                                struct_name, field
+                            ))
+                            .with_code("E0418"));
+                    };
+                    self.check_pattern(pattern, &declared.field_type)?;
+                }
+                let missing: Vec<&str> = declared
+                    .iter()
+                    .filter(|f| !fields.iter().any(|(name, _)| *name == f.name))
+                    .map(|f| f.name.as_str())
+                    .collect();
+                if !rest && !missing.is_empty() {
+                    return Err(self
+                        .error(&format!(
+                            "pattern for '{}' leaves out {}",
+                            struct_name,
+                            missing
+                                .iter()
+                                .map(|f| format!("'{}'", f))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ))
+                        .with_code("E0418")
+                        .with_help("name them, or end the pattern with '..' to ignore them"));
+                }
+                Ok(())
+            }
+            _ => Err(self.pattern_mismatch(pattern, ty)),
+        }
+    }
+
+    fn pattern_mismatch(&self, pattern: &Pattern, ty: &str) -> Diagnostic {
+        let what = match pattern {
+            Pattern::EnumPattern { .. } => "a variant's pattern",
+            Pattern::StructPattern { .. } => "a struct pattern",
+            Pattern::NumberPattern(_) => "a number",
+            _ => "a string pattern",
+        };
+        self.error(&format!("{} can't match a value of type '{}'", what, ty))
+            .with_code("E0418")
+            .with_help("bind it to a name or skip it with '_'")
+    }
+
+    /// Whether `ty` names a type: a built-in one or a declared struct or
+    /// enum, possibly behind a pointer or reference, or inside an array or
+    /// a built-in generic.
+    fn is_known_type(&self, ty: &str) -> bool {
+        let ty = ty.trim();
+        if let Some(inner) = ty
+            .strip_prefix("&mut ")
+            .or_else(|| ty.strip_prefix('&'))
+            .or_else(|| ty.strip_prefix('*'))
+        {
+            return self.is_known_type(inner);
+        }
+        if let Some(rest) = ty.strip_prefix('[')
+            && let Some((elem, _)) = rest.rsplit_once(';')
+        {
+            return self.is_known_type(elem);
+        }
+        if let Some((base, args)) = ty.strip_suffix('>').and_then(|t| t.split_once('<')) {
+            return matches!(base, "Vec" | "Mutex" | "MutexGuard")
+                && args.split(',').all(|arg| self.is_known_type(arg));
+        }
+        matches!(
+            ty,
+            "int" | "bool" | "char" | "string" | "Vec" | "StringBuilder" | "Bytes" | "LineReader"
+        ) || self.struct_fields.contains_key(ty)
+            || self.enums.contains_key(ty)
+    }
+
     fn declare_variable(&mut self, name: &str, mutable: bool, var_type: String, line: usize) {
         let scope = self.symbol_table.last_mut().unwrap();
         scope.insert(
@@ -449,75 +1166,203 @@ impl<'a> SemanticAnalyzer<'a> {
                 is_mutable: mutable,
                 declared_line: line,
                 var_type,
+                storage: Storage::Owned,
+                element_of: None,
             },
         );
     }
 
-    fn check_variable_exists(&self, name: &str) -> Result<(), String> {
+    fn check_variable_exists(&self, name: &str) -> Result<(), Diagnostic> {
         if self.lookup_variable(name).is_none() {
-            return Err(format!(
-                "{}:{}:{}: Error: cannot find value '{}' in this scope",
-                self.filename, self.current_line, self.current_column, name
-            ));
+            return Err(self
+                .error(&format!("cannot find value '{}' in this scope", name))
+                .with_code("E0401")
+                .with_length(name.chars().count())
+                .with_label("not found in this scope"));
         }
         Ok(())
     }
 
-    fn check_not_consumed(&self, name: &str) -> Result<(), String> {
+    /// `for x in v` calls `v.iter()` when `v`'s type has one, with the same
+    /// ownership rules as an explicit call; otherwise a `v` with only a `next`
+    /// method is moved into the loop.
+    fn check_iterated(&mut self, iterator: &AstNode) -> Result<(), Diagnostic> {
+        let AstNode::Identifier { name, .. } = iterator else {
+            return Ok(());
+        };
+        let Some(info) = self.lookup_variable(name) else {
+            return Ok(());
+        };
+        let struct_name = info
+            .var_type
+            .trim_start_matches("&mut ")
+            .trim_start_matches(['&', '*'])
+            .to_string();
+        if self.methods.contains_key(&format!("{}.iter", struct_name)) {
+            self.visit(&AstNode::MethodCall {
+                object: Box::new(iterator.clone()),
+                method: "iter".to_string(),
+                args: Vec::new(),
+                span: iterator.span(),
+            })
+        } else if self.methods.contains_key(&format!("{}.next", struct_name))
+            && !info.var_type.starts_with('&')
+        {
+            self.check_not_consumed(name)?;
+            self.consume_variable(name)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_not_consumed(&self, name: &str) -> Result<(), Diagnostic> {
         if self.is_copy_type(name) {
             return Ok(());
         }
-        if let Some(info) = self.lookup_variable(name) {
-            if info.is_consumed {
-                return Err(format!(
-                    "{}:{}:{}: Error: use of moved value '{}'\n    Note: value moved at line {}, cannot be used again\n    Help: Consider borrowing '&{}' to keep ownership",
-                    self.filename,
-                    self.current_line,
-                    self.current_column,
-                    name,
-                    info.declared_line,
-                    name
-                ));
+        if let Some(info) = self.lookup_variable(name)
+            && info.is_consumed
+        {
+            let error = self
+                .error(&format!("use of moved value '{}'", name))
+                .with_code("E0402")
+                .with_length(name.chars().count());
+            // On a loop's second pass, anything new was moved by the first
+            if let Some(loop_span) = self.repeated_loop {
+                return Err(error
+                    .with_label("value used here after being moved in the previous iteration")
+                    .with_note(format!(
+                        "'{}' was declared at line {}, before the loop at line {}, and is moved inside it",
+                        name, info.declared_line, loop_span.line
+                    ))
+                    .with_help(format!(
+                        "borrow '&{}' inside the loop, or declare it inside the loop body",
+                        name
+                    )));
             }
+            return Err(error
+                .with_label("value used here after move")
+                .with_note(format!(
+                    "'{}' was declared at line {} and has already been moved",
+                    name, info.declared_line
+                ))
+                .with_help(format!("consider borrowing '&{}' to keep ownership", name)));
         }
         Ok(())
     }
 
-    fn check_is_mutable(&self, name: &str) -> Result<(), String> {
-        if let Some(info) = self.lookup_variable(name) {
-            if !info.is_mutable {
-                return Err(format!(
-                    "{}:{}:{}: Error: cannot assign to immutable variable '{}'\n    Help: Consider declaring with 'let mut {}'",
-                    self.filename, self.current_line, self.current_column, name, name
-                ));
-            }
+    fn check_is_mutable(&self, name: &str) -> Result<(), Diagnostic> {
+        if let Some(info) = self.lookup_variable(name)
+            && !info.is_mutable
+        {
+            return Err(self
+                .error(&format!("cannot assign to immutable variable '{}'", name))
+                .with_code("E0403")
+                .with_length(name.chars().count())
+                .with_label("cannot assign twice to immutable variable")
+                .with_help(format!("consider declaring with 'let mut {}'", name)));
         }
         Ok(())
     }
 
-    fn check_not_borrowed(&self, name: &str) -> Result<(), String> {
-        if let Some(info) = self.lookup_variable(name) {
-            if info.borrow_count > 0 {
-                return Err(format!(
-                    "{}:{}:{}: Error: cannot move '{}' while borrowed\n    Note: {} active borrow(s) exist",
-                    self.filename, self.current_line, self.current_column, name, info.borrow_count
-                ));
-            }
+    /// Changing what `name` holds — a field, or through a `&mut self` method —
+    /// needs a mutable binding, a `&mut` parameter, a pointer or a mutex guard.
+    fn check_can_mutate(&self, name: &str, action: &str) -> Result<(), Diagnostic> {
+        if let Some(info) = self.lookup_variable(name)
+            && !info.is_mutable
+            && !info.var_type.starts_with('*')
+            && !info.var_type.starts_with("MutexGuard<")
+        {
+            let help = match info.var_type.strip_prefix('&') {
+                Some(_) if name == "self" => "take it as '&mut self'".to_string(),
+                Some(inner) => format!("take it as '&mut {}'", inner),
+                None => format!("consider declaring with 'let mut {}'", name),
+            };
+            return Err(self
+                .error(&format!("cannot {} through immutable '{}'", action, name))
+                .with_code("E0403")
+                .with_length(name.chars().count())
+                .with_help(help));
+        }
+        Ok(())
+    }
+
+    fn check_not_borrowed(&self, name: &str) -> Result<(), Diagnostic> {
+        if let Some(info) = self.lookup_variable(name)
+            && info.borrow_count > 0
+        {
+            return Err(self
+                .error(&format!("cannot move '{}' while borrowed", name))
+                .with_code("E0404")
+                .with_length(name.chars().count())
+                .with_note(format!("{} active borrow(s) exist", info.borrow_count)));
         }
         Ok(())
     }
 
-    fn consume_variable(&mut self, name: &str) -> Result<(), String> {
+    /// A `#[no_mangle]` function is called from C, so everything it takes
+    /// and returns must be something C can hand over: a scalar, a string
+    /// (as `char *`) or a raw pointer.
+    fn check_c_signature(
+        &self,
+        name: &str,
+        params: &[Parameter],
+        return_type: &Option<String>,
+    ) -> Result<(), Diagnostic> {
+        fn c_compatible(ty: &str) -> bool {
+            let ty = ty
+                .strip_prefix("&mut ")
+                .or_else(|| ty.strip_prefix('&'))
+                .unwrap_or(ty);
+            matches!(ty, "int" | "bool" | "char" | "string") || ty.starts_with('*')
+        }
+        if name.contains('.') {
+            return Err(self
+                .error(&format!("method '{}' cannot be '#[no_mangle]'", name))
+                .with_code("E0421")
+                .with_help("make it a free function taking the receiver as a parameter"));
+        }
+        let bad = params
+            .iter()
+            .map(|p| (format!("parameter '{}'", p.name), &p.param_type))
+            .chain(return_type.iter().map(|t| ("return type".to_string(), t)))
+            .find(|(_, ty)| !c_compatible(ty));
+        match bad {
+            Some((what, ty)) => Err(self
+                .error(&format!(
+                    "'#[no_mangle]' function '{}' has a {} of type '{}', which C cannot pass",
+                    name, what, ty
+                ))
+                .with_code("E0421")
+                .with_note("a '#[no_mangle]' function is called from outside the program")
+                .with_help("use 'int', 'bool', 'char', 'string' or a raw pointer such as '*Node'")),
+            None => Ok(()),
+        }
+    }
+
+    fn consume_variable(&mut self, name: &str) -> Result<(), Diagnostic> {
         if self.is_copy_type(name) {
             return Ok(());
         }
+        if self.symbol_table.len() > 1
+            && self.symbol_table[0].contains_key(name)
+            && !self.symbol_table[1..]
+                .iter()
+                .any(|scope| scope.contains_key(name))
+        {
+            return Err(self
+                .error(&format!("cannot move out of top-level binding '{}'", name))
+                .with_code("E0413")
+                .with_length(name.chars().count())
+                .with_note("top-level bindings live until the program exits")
+                .with_help(format!("borrow it with '&{}' instead", name)));
+        }
         for scope in self.symbol_table.iter_mut().rev() {
             if let Some(info) = scope.get_mut(name) {
                 if info.borrow_count > 0 {
-                    return Err(format!(
-                        "{}:{}:{}: Error: cannot move '{}' while borrowed",
-                        self.filename, self.current_line, self.current_column, name
-                    ));
+                    return Err(self
+                        .error(&format!("cannot move '{}' while borrowed", name))
+                        .with_code("E0404")
+                        .with_length(name.chars().count()));
                 }
                 info.is_consumed = true;
                 return Ok(());
@@ -526,7 +1371,186 @@ impl<'a> SemanticAnalyzer<'a> {
         Ok(())
     }
 
-    fn borrow_variable(&mut self, name: &str) -> Result<(), String> {
+    /// `v[i]` where the element is used where it is — borrowed, or a field
+    /// of it read or assigned — rather than moved out of the Vec.
+    fn visit_place(&mut self, node: &AstNode) -> Result<(), Diagnostic> {
+        match node {
+            AstNode::Index { array, index, .. } => {
+                self.visit(array)?;
+                self.visit(index)
+            }
+            _ => self.visit(node),
+        }
+    }
+
+    /// The element type of `array` if it is a Vec: `int` for a plain `Vec`.
+    fn vec_element_type(&self, array: &AstNode) -> Option<String> {
+        let array_type = self.infer_type(array);
+        let array_type = array_type
+            .trim_start_matches("&mut ")
+            .trim_start_matches('&');
+        if array_type == "Vec" {
+            return Some("int".to_string());
+        }
+        let element = array_type.strip_prefix("Vec<")?.strip_suffix('>')?;
+        Some(element.to_string())
+    }
+
+    /// Changing `vec` while a variable borrows one of its elements could
+    /// free or move the element out from under it.
+    fn check_elements_not_borrowed(&self, vec: &str) -> Result<(), Diagnostic> {
+        let borrower = self
+            .symbol_table
+            .iter()
+            .flat_map(|scope| scope.iter())
+            .filter(|(_, info)| info.element_of.as_deref() == Some(vec))
+            .min_by_key(|(_, info)| info.declared_line);
+        let Some((borrower, info)) = borrower else {
+            return Ok(());
+        };
+        Err(self
+            .error(&format!(
+                "cannot change '{}' while '{}' borrows one of its elements",
+                vec, borrower
+            ))
+            .with_code("E0420")
+            .with_length(vec.chars().count())
+            .with_label(format!("'{}' is changed here", vec))
+            .with_note(format!(
+                "'{}' was borrowed from '{}' at line {}, and stays borrowed until the end of its block",
+                borrower, vec, info.declared_line
+            ))
+            .with_help(format!(
+                "copy the element with 'vec_get(&{}, i)' instead of borrowing it",
+                vec
+            )))
+    }
+
+    /// Returning a reference to a local, or to data inside one, would leave
+    /// the caller pointing at a stack frame that no longer exists.
+    fn check_not_dangling(&mut self, value: &AstNode, span: Span) -> Result<(), Diagnostic> {
+        let Storage::Local(local) = self.storage_of(value) else {
+            return Ok(());
+        };
+        self.current_span = span;
+        let what = match value {
+            AstNode::Reference(..) => format!("a reference to local '{}'", local),
+            _ => format!("a reference into local '{}'", local),
+        };
+        let declared = self
+            .lookup_variable(&local)
+            .map_or(0, |info| info.declared_line);
+        let owner = if declared == 0 {
+            format!("'{}' is a parameter passed by value", local)
+        } else {
+            format!("'{}' was declared at line {}", local, declared)
+        };
+        Err(self
+            .error(&format!("cannot return {}", what))
+            .with_code("E0417")
+            .with_length("return".len())
+            .with_label("returns a reference to data owned by the current function")
+            .with_note(format!(
+                "{}, and is dropped when the function returns",
+                owner
+            ))
+            .with_help(format!(
+                "return '{}' by value to move it to the caller, or take it as a '&' parameter",
+                local
+            )))
+    }
+
+    /// Where the data `expr` evaluates to lives, when it's a reference.
+    fn storage_of(&self, expr: &AstNode) -> Storage {
+        match expr {
+            AstNode::Reference(inner, _) => {
+                // `&x`, `&x.field`, `&x[i]` and `&x[a..b]` all point into `x`
+                let mut root = inner.as_ref();
+                while let AstNode::MemberAccess { object: inner, .. }
+                | AstNode::Index { array: inner, .. }
+                | AstNode::Slice { value: inner, .. } = root
+                {
+                    root = inner;
+                }
+                match root {
+                    AstNode::Identifier { name, .. } => match self.lookup_storage(name) {
+                        Some(Storage::Owned) => Storage::Local(name.clone()),
+                        Some(storage) => storage,
+                        None => Storage::Outside,
+                    },
+                    _ => Storage::Outside,
+                }
+            }
+            AstNode::Identifier { name, .. } => {
+                self.lookup_storage(name).unwrap_or(Storage::Outside)
+            }
+            // A function returning a reference borrows from what it's given
+            AstNode::Call { name, args, .. }
+                if self
+                    .return_types
+                    .get(name)
+                    .is_some_and(|t| t.starts_with('&')) =>
+            {
+                self.first_local(args.iter())
+            }
+            AstNode::MethodCall { object, args, .. }
+                if self
+                    .method_return_type(object, expr)
+                    .is_some_and(|t| t.starts_with('&')) =>
+            {
+                let object = match object.as_ref() {
+                    AstNode::Identifier { .. } => AstNode::Reference(object.clone(), object.span()),
+                    other => other.clone(),
+                };
+                self.first_local(std::iter::once(&object).chain(args))
+            }
+            _ => Storage::Owned,
+        }
+    }
+
+    fn first_local<'n>(&self, args: impl Iterator<Item = &'n AstNode>) -> Storage {
+        args.map(|arg| self.storage_of(arg))
+            .find(|storage| matches!(storage, Storage::Local(_)))
+            .unwrap_or(Storage::Outside)
+    }
+
+    /// The declared return type of the method `call` calls on `object`.
+    fn method_return_type(&self, object: &AstNode, call: &AstNode) -> Option<&String> {
+        let AstNode::MethodCall { method, .. } = call else {
+            return None;
+        };
+        let object_type = self.infer_type(object);
+        let struct_name = object_type
+            .trim_start_matches("&mut ")
+            .trim_start_matches(['&', '*']);
+        self.return_types
+            .get(&format!("{}.{}", struct_name, method))
+    }
+
+    /// A variable's storage; top-level bindings outlive every function.
+    fn lookup_storage(&self, name: &str) -> Option<Storage> {
+        for (depth, scope) in self.symbol_table.iter().enumerate().rev() {
+            if let Some(info) = scope.get(name) {
+                return Some(if depth == 0 {
+                    Storage::Outside
+                } else {
+                    info.storage.clone()
+                });
+            }
+        }
+        None
+    }
+
+    fn set_storage(&mut self, name: &str, storage: Storage) {
+        for scope in self.symbol_table.iter_mut().rev() {
+            if let Some(info) = scope.get_mut(name) {
+                info.storage = storage;
+                return;
+            }
+        }
+    }
+
+    fn borrow_variable(&mut self, name: &str) -> Result<(), Diagnostic> {
         for scope in self.symbol_table.iter_mut().rev() {
             if let Some(info) = scope.get_mut(name) {
                 info.borrow_count += 1;
@@ -563,15 +1587,49 @@ impl<'a> SemanticAnalyzer<'a> {
 
     fn infer_type(&self, expr: &AstNode) -> String {
         match expr {
-            AstNode::Number(_) => "int".to_string(),
-            AstNode::Boolean(_) => "bool".to_string(),
-            AstNode::Character(_) => "char".to_string(),
-            AstNode::StringLit(_) => "string".to_string(),
+            AstNode::Number(_, _) => "int".to_string(),
+            AstNode::Boolean(_, _) => "bool".to_string(),
+            AstNode::Null(_) => "null".to_string(),
+            AstNode::Character(_, _) => "char".to_string(),
+            AstNode::StructInit { name, .. } => name.clone(),
+            AstNode::Call { name, .. } => self
+                .return_types
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| "unknown".to_string()),
+            AstNode::StringLit(_, _) => "string".to_string(),
+            AstNode::BytesLit(_, _) => "Bytes".to_string(),
             AstNode::Identifier { name, .. } => {
                 self.get_type(name).unwrap_or("unknown").to_string()
             }
-            AstNode::BinaryOp { left, .. } => self.infer_type(left),
-            AstNode::ArrayLit(elements) => {
+            AstNode::BinaryOp { op, left, .. } => match op {
+                BinOp::Equal
+                | BinOp::NotEqual
+                | BinOp::LessThan
+                | BinOp::LessEqual
+                | BinOp::GreaterThan
+                | BinOp::GreaterEqual
+                | BinOp::And
+                | BinOp::Or => "bool".to_string(),
+                // Arithmetic on a `char` or `bool` is done on `int`s
+                _ => match self.infer_type(left).as_str() {
+                    "char" | "bool" => "int".to_string(),
+                    left_type => left_type.to_string(),
+                },
+            },
+            AstNode::UnaryOp { op, .. } => match op {
+                UnOp::Not => "bool".to_string(),
+                UnOp::Negate => "int".to_string(),
+            },
+            AstNode::Cast { target, .. } => target.clone(),
+            AstNode::Index { array, .. } => self.vec_element_type(array).unwrap_or_else(|| {
+                let array_type = self.infer_type(array);
+                match array_type.strip_prefix('[').and_then(|t| t.split_once(';')) {
+                    Some((element, _)) => element.trim().to_string(),
+                    None => "unknown".to_string(),
+                }
+            }),
+            AstNode::ArrayLit(elements, _) => {
                 if elements.is_empty() {
                     "[int; 0]".to_string()
                 } else {
@@ -583,6 +1641,63 @@ impl<'a> SemanticAnalyzer<'a> {
         }
     }
 
+    /// `value` where a `to` is expected. Widening is implicit: a `bool` or
+    /// `char` becomes an `int`, a `bool` a `char`, and an integer literal
+    /// from 0 to 255 a `char`. Anything that could lose information has to
+    /// say so with `as`.
+    fn check_conversion(&mut self, value: &AstNode, to: &str) -> Result<(), Diagnostic> {
+        let from = self.infer_type(value);
+        let implicit = match (from.as_str(), to) {
+            (from, to) if from == to || !is_scalar(from) || !is_scalar(to) => true,
+            ("bool" | "char", "int") | ("bool", "char") => true,
+            ("int", "char") => matches!(value, AstNode::Number(n, _) if (0..=255).contains(n)),
+            _ => false,
+        };
+        if implicit {
+            return Ok(());
+        }
+        self.current_span = value.span();
+        let error = self
+            .error(&format!("cannot convert '{}' to '{}' implicitly", from, to))
+            .with_code("E0423");
+        Err(if to == "bool" {
+            error.with_help("compare it instead: '... != 0'")
+        } else {
+            error
+                .with_note("a 'char' holds only the low byte of an 'int'")
+                .with_help(format!("convert it explicitly: '... as {}'", to))
+        })
+    }
+
+    /// `value as target` converts between `int`, `char` and `bool`, and only
+    /// to a number: a `bool` comes from a comparison.
+    fn check_cast(&self, value: &AstNode, target: &str) -> Result<(), Diagnostic> {
+        let from = self.infer_type(value);
+        if target == "bool" {
+            return Err(self
+                .error(&format!("cannot cast '{}' to 'bool' with 'as'", from))
+                .with_code("E0423")
+                .with_help("compare it instead: '... != 0'"));
+        }
+        if !matches!(target, "int" | "char") || !(is_scalar(&from) || from == "unknown") {
+            return Err(self
+                .error(&format!("cannot cast '{}' to '{}' with 'as'", from, target))
+                .with_code("E0423")
+                .with_note("'as' converts between 'int', 'char' and 'bool'"));
+        }
+        Ok(())
+    }
+
+    /// An error at the current node, in the file it was parsed from.
+    fn error(&self, message: &str) -> Diagnostic {
+        let span = self.current_span;
+        if span.file.is_empty() {
+            Diagnostic::error(message).at(self.filename, span.line, span.column)
+        } else {
+            Diagnostic::error(message).at_span(span)
+        }
+    }
+
     fn push_scope(&mut self) {
         self.symbol_table.push(HashMap::new());
     }
@@ -591,3 +1706,256 @@ impl<'a> SemanticAnalyzer<'a> {
         self.symbol_table.pop();
     }
 }
+
+fn is_scalar(ty: &str) -> bool {
+    matches!(ty, "int" | "char" | "bool")
+}
+
+/// Top-level `let` bindings in the order they are initialized before `main`
+/// runs: each one after every binding its initializer reads, directly or
+/// through the functions it calls, and otherwise in declaration order. A
+/// cycle is returned as `Err`, starting and ending with the same name.
+pub(crate) fn global_init_order(nodes: &[AstNode]) -> Result<Vec<&AstNode>, Vec<String>> {
+    let globals: Vec<(&str, &AstNode)> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            AstNode::LetBinding { name, .. } => Some((name.as_str(), node)),
+            _ => None,
+        })
+        .collect();
+    let functions: HashMap<&str, &AstNode> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            AstNode::FunctionDef { name, .. } | AstNode::StructDef { name, .. } => {
+                Some((name.as_str(), node))
+            }
+            _ => None,
+        })
+        .collect();
+
+    // The bindings each initializer reads, following calls into function
+    // bodies (where the function's own locals and parameters shadow them)
+    // and struct literals into field defaults. A method call `.m` may
+    // reach any `Type.m`.
+    let mut deps: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, node) in &globals {
+        let mut refs = Vec::new();
+        if let AstNode::LetBinding { value, .. } = node {
+            collect_names(value, &mut refs, &mut HashSet::new());
+        }
+        let mut seen_fns = HashSet::new();
+        let mut reads: Vec<&str> = Vec::new();
+        while let Some(r) = refs.pop() {
+            if let Some((g, _)) = globals.iter().find(|(g, _)| *g == r) {
+                if !reads.contains(g) {
+                    reads.push(g);
+                }
+                continue;
+            }
+            let callees: Vec<&str> = if r.starts_with('.') {
+                functions
+                    .keys()
+                    .copied()
+                    .filter(|f| f.ends_with(r.as_str()))
+                    .collect()
+            } else {
+                functions
+                    .get_key_value(r.as_str())
+                    .map(|(f, _)| *f)
+                    .into_iter()
+                    .collect()
+            };
+            for callee in callees {
+                if seen_fns.insert(callee) {
+                    let mut locals = HashSet::new();
+                    let mut body_refs = Vec::new();
+                    collect_names(functions[callee], &mut body_refs, &mut locals);
+                    refs.extend(body_refs.into_iter().filter(|n| !locals.contains(n)));
+                }
+            }
+        }
+        deps.insert(name, reads);
+    }
+
+    fn visit<'n>(
+        name: &'n str,
+        deps: &HashMap<&'n str, Vec<&'n str>>,
+        path: &mut Vec<&'n str>,
+        done: &mut Vec<&'n str>,
+    ) -> Result<(), Vec<String>> {
+        if done.contains(&name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|p| *p == name) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|p| p.to_string()).collect();
+            cycle.push(name.to_string());
+            return Err(cycle);
+        }
+        path.push(name);
+        for dep in &deps[name] {
+            visit(dep, deps, path, done)?;
+        }
+        path.pop();
+        done.push(name);
+        Ok(())
+    }
+
+    let mut done = Vec::new();
+    for (name, _) in &globals {
+        visit(name, &deps, &mut Vec::new(), &mut done)?;
+    }
+    Ok(done
+        .iter()
+        .filter_map(|name| globals.iter().find(|(g, _)| g == name).map(|(_, n)| *n))
+        .collect())
+}
+
+/// Every name `node` refers to — variables, called functions, and `.m` for
+/// a method call — into `refs`, and every name it binds into `bound`.
+fn collect_names(node: &AstNode, refs: &mut Vec<String>, bound: &mut HashSet<String>) {
+    match node {
+        AstNode::Identifier { name, .. } => refs.push(name.clone()),
+        AstNode::Call { name, args, .. } => {
+            refs.push(name.clone());
+            for a in args {
+                collect_names(a, refs, bound);
+            }
+        }
+        AstNode::MethodCall {
+            object,
+            method,
+            args,
+            ..
+        } => {
+            refs.push(format!(".{}", method));
+            collect_names(object, refs, bound);
+            for a in args {
+                collect_names(a, refs, bound);
+            }
+        }
+        AstNode::FunctionDef { params, body, .. } => {
+            bound.extend(params.iter().map(|p| p.name.clone()));
+            collect_names(body, refs, bound);
+        }
+        AstNode::LetBinding { name, value, .. } => {
+            bound.insert(name.clone());
+            collect_names(value, refs, bound);
+        }
+        AstNode::Assignment { name, value, .. } => {
+            refs.push(name.clone());
+            collect_names(value, refs, bound);
+        }
+        AstNode::ArrayAssignment {
+            array,
+            index,
+            value,
+            ..
+        } => {
+            refs.push(array.clone());
+            collect_names(index, refs, bound);
+            collect_names(value, refs, bound);
+        }
+        AstNode::MemberAssignment { object, value, .. } => {
+            collect_names(object, refs, bound);
+            collect_names(value, refs, bound);
+        }
+        AstNode::For {
+            variable,
+            index,
+            iterator,
+            body,
+            ..
+        } => {
+            bound.insert(variable.clone());
+            bound.extend(index.iter().cloned());
+            refs.push(".iter".to_string());
+            refs.push(".next".to_string());
+            collect_names(iterator, refs, bound);
+            collect_names(body, refs, bound);
+        }
+        AstNode::Match { value, arms, .. } => {
+            collect_names(value, refs, bound);
+            for arm in arms {
+                bound.extend(arm.pattern.bindings().into_iter().map(String::from));
+                collect_names(&arm.body, refs, bound);
+            }
+        }
+        AstNode::Block(nodes, _) | AstNode::Program(nodes, _) | AstNode::ArrayLit(nodes, _) => {
+            for n in nodes {
+                collect_names(n, refs, bound);
+            }
+        }
+        AstNode::StructInit {
+            name, fields, base, ..
+        } => {
+            refs.push(name.clone());
+            for (_, v) in fields {
+                collect_names(v, refs, bound);
+            }
+            if let Some(b) = base {
+                collect_names(b, refs, bound);
+            }
+        }
+        AstNode::StructDef { fields, .. } => {
+            for default in fields.iter().filter_map(|f| f.default.as_ref()) {
+                collect_names(default, refs, bound);
+            }
+        }
+        AstNode::If {
+            condition,
+            then_block,
+            else_block,
+            ..
+        } => {
+            collect_names(condition, refs, bound);
+            collect_names(then_block, refs, bound);
+            if let Some(e) = else_block {
+                collect_names(e, refs, bound);
+            }
+        }
+        AstNode::While {
+            condition, body, ..
+        } => {
+            collect_names(condition, refs, bound);
+            collect_names(body, refs, bound);
+        }
+        AstNode::BinaryOp { left, right, .. }
+        | AstNode::Index {
+            array: left,
+            index: right,
+            ..
+        } => {
+            collect_names(left, refs, bound);
+            collect_names(right, refs, bound);
+        }
+        AstNode::Slice {
+            value, start, end, ..
+        } => {
+            collect_names(value, refs, bound);
+            for b in [start, end].into_iter().flatten() {
+                collect_names(b, refs, bound);
+            }
+        }
+        AstNode::UnaryOp { operand: e, .. }
+        | AstNode::Cast { value: e, .. }
+        | AstNode::Reference(e, _)
+        | AstNode::MemberAccess { object: e, .. }
+        | AstNode::ExpressionStatement(e, _)
+        | AstNode::Return(Some(e), _) => collect_names(e, refs, bound),
+        AstNode::EnumValue { values, .. } => {
+            for v in values {
+                collect_names(v, refs, bound);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `n` of `noun`: "no values", "1 value", "2 values".
+fn count(n: usize, noun: &str) -> String {
+    match n {
+        0 => format!("no {}s", noun),
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
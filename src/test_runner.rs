@@ -0,0 +1,372 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+
+use brain::Diagnostic;
+use brain::golden::{self, Outcome};
+use brain::module::ModuleCache;
+use brain::parser::{AstNode, MatchArm, Parameter, Pattern, Span};
+use brain::read_source;
+
+use crate::{Options, compiler, fail, lli_command, lli_not_found, remove_temp_ir, write_temp_ir};
+
+struct TestCase {
+    file: String,
+    name: String,
+}
+
+struct Failure {
+    test: String,
+    output: String,
+}
+
+/// `brain test`: find every `fn test_*()` or `#[test]` function with no
+/// parameters and build them all into one harness program, whose `main`
+/// runs the test its first argument names. The harness is compiled once and
+/// JITed once per test, so a failed `assert` only takes down its own test.
+pub fn run_tests(options: &Options) -> ! {
+    let format = options.error_format;
+    let compiler = compiler(options, false);
+
+    let mut files = Vec::new();
+    collect_sources(Path::new(&options.input_file), &mut files);
+    files.sort();
+
+    // Each file is checked as the program it is first, so its errors point
+    // into it rather than into the harness
+    let mut cache = ModuleCache::new();
+    let mut tests = Vec::new();
+    for file in &files {
+        let ast = read_source(file)
+            .and_then(|source| compiler.parse(&source, file))
+            .unwrap_or_else(|e| fail(e, format));
+        if let AstNode::Program(nodes, _) = &ast {
+            for node in nodes {
+                if let AstNode::FunctionDef { name, params, .. } = node
+                    && (name.starts_with("test_") || node.has_attribute("test"))
+                    && params.is_empty()
+                {
+                    tests.push(TestCase {
+                        file: file.clone(),
+                        name: name.clone(),
+                    });
+                }
+            }
+        }
+        compiler
+            .check(ast, file, &mut cache)
+            .unwrap_or_else(|e| fail(e, format));
+    }
+
+    if tests.is_empty() {
+        fail(
+            Diagnostic::error(format!("no tests found in '{}'", options.input_file))
+                .with_code("E0005")
+                .with_help("tests are functions named 'test_*', or marked '#[test]', that take no parameters"),
+            format,
+        );
+    }
+
+    let input = Path::new(&options.input_file);
+    let harness_file = if input.is_dir() {
+        input.join(HARNESS_FILE)
+    } else {
+        input.with_file_name(HARNESS_FILE)
+    }
+    .to_string_lossy()
+    .into_owned();
+    let harness = harness(&tests, &harness_file, &mut cache).unwrap_or_else(|e| fail(e, format));
+    let llvm_ir = compiler
+        .compile_ast(harness, &harness_file, &mut cache)
+        .unwrap_or_else(|e| fail(e, format))
+        .llvm_ir;
+    let ll_file = write_temp_ir(options, &llvm_ir, "test-harness");
+
+    println!(
+        "running {} test{}",
+        tests.len(),
+        if tests.len() == 1 { "" } else { "s" }
+    );
+
+    let lli = lli_command();
+    let mut passed = 0;
+    let mut failures: Vec<Failure> = Vec::new();
+
+    for (index, test) in tests.iter().enumerate() {
+        let output = match process::Command::new(&lli)
+            .arg(&ll_file)
+            .arg(index.to_string())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                remove_temp_ir(options, &ll_file);
+                lli_not_found(&lli, e, format)
+            }
+        };
+
+        let label = format!("{}::{}", test.file, test.name);
+        if output.status.success() {
+            println!("test {} ... ok", label);
+            passed += 1;
+        } else {
+            println!("test {} ... FAILED", label);
+            let mut captured = String::from_utf8_lossy(&output.stdout).into_owned();
+            captured.push_str(&String::from_utf8_lossy(&output.stderr));
+            if output.status.code().is_none() {
+                captured.push_str("(terminated by a signal)\n");
+            }
+            failures.push(Failure {
+                test: label,
+                output: captured,
+            });
+        }
+    }
+    remove_temp_ir(options, &ll_file);
+
+    finish(passed, &failures)
+}
+
+/// Print the output of each failure and the summary line, then exit with
+/// the status the run deserves.
+fn finish(passed: usize, failures: &[Failure]) -> ! {
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for failure in failures {
+            println!();
+            println!("---- {} ----", failure.test);
+            print!("{}", failure.output);
+        }
+        println!();
+        println!("failures:");
+        for failure in failures {
+            println!("    {}", failure.test);
+        }
+    }
+
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len()
+    );
+
+    process::exit(if failures.is_empty() { 0 } else { 1 });
+}
+
+/// `brain --test-ir`: compile each fixture in the input directory and
+/// compare its normalized IR with the `.expected.ll` beside it, or with
+/// `--bless` write that file from the IR instead.
+pub fn run_ir_tests(options: &Options) -> ! {
+    let format = options.error_format;
+    let compiler = compiler(options, false);
+    let dir = Path::new(&options.input_file);
+
+    let fixtures = golden::fixtures(dir).unwrap_or_else(|e| fail(e, format));
+    if fixtures.is_empty() {
+        fail(
+            Diagnostic::error(format!("no IR fixtures found in '{}'", options.input_file))
+                .with_code("E0005")
+                .with_help("fixtures are the '.brn' files directly inside the directory"),
+            format,
+        );
+    }
+    println!(
+        "running {} IR test{}",
+        fixtures.len(),
+        if fixtures.len() == 1 { "" } else { "s" }
+    );
+
+    let results = golden::run(&compiler, dir, options.bless).unwrap_or_else(|e| fail(e, format));
+    let mut passed = 0;
+    let mut failures: Vec<Failure> = Vec::new();
+    for result in results {
+        let label = result.fixture.display().to_string();
+        let output = match result.outcome {
+            Outcome::Passed => {
+                println!("test {} ... ok", label);
+                passed += 1;
+                continue;
+            }
+            Outcome::Blessed => {
+                println!("test {} ... blessed", label);
+                passed += 1;
+                continue;
+            }
+            Outcome::Missing => format!(
+                "no expected IR at '{}'; run with --bless to create it\n",
+                golden::expected_path(&result.fixture).display()
+            ),
+            Outcome::Mismatch(diff) => {
+                format!(
+                    "IR differs from the expected output (-expected +actual):\n{}",
+                    diff
+                )
+            }
+            Outcome::Failed(diagnostics) => diagnostics.iter().map(Diagnostic::render).collect(),
+        };
+        println!("test {} ... FAILED", label);
+        failures.push(Failure {
+            test: label,
+            output,
+        });
+    }
+
+    finish(passed, &failures)
+}
+
+/// Collect `.brn` files under `path`, skipping hidden directories and
+/// cargo's `target/`.
+fn collect_sources(path: &Path, out: &mut Vec<String>) {
+    if path.is_file() {
+        out.push(path.to_string_lossy().into_owned());
+        return;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let entry_path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry_path.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                collect_sources(&entry_path, out);
+            }
+        } else if name.ends_with(".brn") {
+            out.push(entry_path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// The name the harness is compiled under. It is never written: the
+/// directory only anchors the search for `brain.toml`.
+const HARNESS_FILE: &str = "brain-test-harness.brn";
+
+/// The harness program: every test file loaded as a module, so private
+/// names that two files share don't collide, without the files' own
+/// `main`s, and a `main` that calls the test whose index is its argument.
+fn harness(
+    tests: &[TestCase],
+    harness_file: &str,
+    cache: &mut ModuleCache,
+) -> Result<AstNode, Diagnostic> {
+    let span = Span::default();
+    let mut nodes: Vec<AstNode> = Vec::new();
+    let mut seen_names = HashSet::new();
+    let mut seen_nodes: Vec<Rc<AstNode>> = Vec::new();
+    let mut arms = Vec::new();
+    // Test files are found under the harness's directory, and imported
+    // relative to it
+    let harness_dir = Path::new(harness_file).parent().unwrap_or(Path::new(""));
+    for (index, test) in tests.iter().enumerate() {
+        let file = Path::new(&test.file);
+        let import = file
+            .strip_prefix(harness_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .into_owned();
+        let path = cache.resolve_path(harness_file, &import, span)?;
+        cache.import(harness_file, &import, &[], span)?;
+        let Some(module) = cache.module(&path) else {
+            continue;
+        };
+        let main = module.symbols.get("main");
+        for def in &module.all_definitions {
+            let fresh = match def.as_ref() {
+                AstNode::FunctionDef { name, .. } if Some(name) == main => false,
+                AstNode::FunctionDef { name, .. }
+                | AstNode::LetBinding { name, .. }
+                | AstNode::StructDef { name, .. }
+                | AstNode::EnumDef { name, .. } => seen_names.insert(name.clone()),
+                // Top-level statements of a module two files import run once
+                _ => !seen_nodes.iter().any(|seen| Rc::ptr_eq(seen, def)),
+            };
+            if fresh {
+                seen_nodes.push(def.clone());
+                nodes.push(def.as_ref().clone());
+            }
+        }
+        let name = module.symbols.get(&test.name).unwrap_or(&test.name);
+        arms.push(MatchArm {
+            pattern: Pattern::StringPattern(index.to_string()),
+            body: AstNode::Call {
+                name: name.clone(),
+                args: Vec::new(),
+                span: nodes
+                    .iter()
+                    .find(|n| matches!(n, AstNode::FunctionDef { name: n, .. } if n == name))
+                    .map_or(span, AstNode::span),
+            },
+        });
+    }
+    arms.push(MatchArm {
+        pattern: Pattern::Wildcard,
+        body: AstNode::Block(Vec::new(), span),
+    });
+
+    // The dispatch is its own function: in a `main`, which returns a
+    // value, each arm of a `match` would be returned
+    let which = |name: &str| AstNode::Identifier {
+        name: name.to_string(),
+        span,
+    };
+    nodes.push(function(
+        DISPATCH,
+        vec![Parameter {
+            is_reference: false,
+            is_mutable: false,
+            name: "which".to_string(),
+            param_type: "string".to_string(),
+        }],
+        AstNode::Match {
+            value: Box::new(which("which")),
+            arms,
+            span,
+        },
+    ));
+    nodes.push(function(
+        "main",
+        vec![Parameter {
+            is_reference: false,
+            is_mutable: false,
+            name: "args".to_string(),
+            param_type: "Vec<string>".to_string(),
+        }],
+        AstNode::Call {
+            name: DISPATCH.to_string(),
+            args: vec![AstNode::MethodCall {
+                object: Box::new(which("args")),
+                method: "get".to_string(),
+                args: vec![AstNode::Number(1, span)],
+                span,
+            }],
+            span,
+        },
+    ));
+    Ok(AstNode::Program(nodes, span))
+}
+
+/// The harness's dispatch function; `$` keeps it apart from user names.
+const DISPATCH: &str = "run_test$harness";
+
+/// A function of the harness whose body is the one statement `body`.
+fn function(name: &str, params: Vec<Parameter>, body: AstNode) -> AstNode {
+    let span = Span::default();
+    AstNode::FunctionDef {
+        name: name.to_string(),
+        params,
+        return_type: None,
+        body: Box::new(AstNode::Block(
+            vec![AstNode::ExpressionStatement(Box::new(body), span)],
+            span,
+        )),
+        is_exported: false,
+        is_unsafe: false,
+        attributes: Vec::new(),
+        span,
+    }
+}
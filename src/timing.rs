@@ -0,0 +1,85 @@
+use std::env;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// One measured compiler pass over one module.
+#[derive(Clone)]
+pub struct PassTime {
+    pub pass: &'static str,
+    pub module: String,
+    pub duration: Duration,
+}
+
+/// Wall-clock time spent in each pass, in the order the passes ran.
+#[derive(Clone, Default)]
+pub struct PassTimings {
+    pub entries: Vec<PassTime>,
+}
+
+impl PassTimings {
+    pub fn time<T>(&mut self, pass: &'static str, module: &str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(pass, module, started.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, pass: &'static str, module: &str, duration: Duration) {
+        self.entries.push(PassTime {
+            pass,
+            module: module.to_string(),
+            duration,
+        });
+    }
+
+    pub fn total(&self) -> Duration {
+        self.entries.iter().map(|e| e.duration).sum()
+    }
+
+    /// The `--time-passes` table: one row per pass and module, then the
+    /// time per pass summed over all modules.
+    pub fn report(&self) -> String {
+        let mut out = String::from("--- time passes ---\n");
+        out.push_str(&format!("{:>10}  {:<9} module\n", "ms", "pass"));
+        for entry in &self.entries {
+            out.push_str(&format!(
+                "{:>10.3}  {:<9} {}\n",
+                millis(entry.duration),
+                entry.pass,
+                display_module(&entry.module)
+            ));
+        }
+
+        let mut passes: Vec<(&'static str, Duration)> = Vec::new();
+        for entry in &self.entries {
+            match passes.iter_mut().find(|(pass, _)| *pass == entry.pass) {
+                Some((_, total)) => *total += entry.duration,
+                None => passes.push((entry.pass, entry.duration)),
+            }
+        }
+        out.push('\n');
+        for (pass, duration) in passes {
+            out.push_str(&format!("{:>10.3}  {}\n", millis(duration), pass));
+        }
+        out.push_str(&format!("{:>10.3}  total\n", millis(self.total())));
+        out
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Imported modules are keyed by canonical path; show them relative to the
+/// working directory when possible.
+fn display_module(module: &str) -> String {
+    env::current_dir()
+        .ok()
+        .and_then(|cwd| {
+            Path::new(module)
+                .strip_prefix(&cwd)
+                .ok()
+                .map(|p| p.display().to_string())
+        })
+        .unwrap_or_else(|| module.to_string())
+}
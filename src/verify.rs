@@ -0,0 +1,689 @@
+//! A structural check of the generated IR before it leaves the compiler.
+//!
+//! Malformed IR is always a compiler bug, but LLVM reports it in terms of
+//! a temporary file the user never asked for. This pass looks at each
+//! function's blocks and values and reports the first problem as an
+//! internal error naming the function:
+//! - a block that doesn't end in a terminator, or code after one,
+//! - a label defined twice or branched to but never defined,
+//! - a value defined twice, out of order, or used but never defined,
+//! - a value used as a different type than the one it was defined with,
+//!   where both are plain to see from the instruction text,
+//! - a call passing a different number or type of arguments than the
+//!   function's `define` or `declare` says it takes,
+//! - memory that escape analysis put on the stack instead of the heap (a
+//!   string built with `+`, or a struct) being returned, freed, or stored
+//!   somewhere other than the function's own frame.
+
+use crate::diagnostic::Diagnostic;
+use std::collections::{HashMap, HashSet};
+
+/// Instructions whose result has the type written straight after the
+/// opcode: `%r = add i64 %a, %b`.
+const SAME_TYPE: &[&str] = &[
+    "add", "sub", "mul", "sdiv", "udiv", "srem", "urem", "and", "or", "xor", "shl", "lshr", "ashr",
+    "fadd", "fsub", "fmul", "fdiv", "frem", "phi", "load",
+];
+
+const CASTS: &[&str] = &[
+    "zext", "sext", "trunc", "bitcast", "ptrtoint", "inttoptr", "sitofp", "uitofp", "fptosi",
+    "fptoui", "fpext", "fptrunc",
+];
+
+/// Check the IR of a whole module, reporting the first problem found.
+///
+/// ```
+/// let ir = "declare i64 @brn_puts(i8*)
+///
+/// define void @show(i64 %n) {
+///   %1 = call i64 @brn_puts(i64 %n)
+///   ret void
+/// }
+/// ";
+/// let error = brain::verify::verify(ir).unwrap_err();
+/// assert!(error.render().contains("passes i64 as argument 1 of @brn_puts, which takes i8*"));
+/// ```
+pub fn verify(ir: &str) -> Result<(), Diagnostic> {
+    // Named struct types are written like values: `%Player`
+    let types: HashSet<&str> = ir
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix('%')?
+                .split_once(" = type ")
+                .map(|(n, _)| n)
+        })
+        .collect();
+    let signatures: HashMap<&str, Signature> = ir
+        .lines()
+        .filter(|line| line.starts_with("define ") || line.starts_with("declare "))
+        .filter_map(Signature::parse)
+        .collect();
+    let mut function: Option<Function> = None;
+    for (index, line) in ir.lines().enumerate() {
+        match &mut function {
+            Some(f) if line == "}" => {
+                f.finish().map_err(|problem| f.report(problem))?;
+                function = None;
+            }
+            Some(f) => f
+                .line(index + 1, line)
+                .map_err(|problem| f.report(problem))?,
+            None if line.starts_with("define ") && line.ends_with('{') => {
+                function = Some(Function::new(line, &types, &signatures));
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// What is wrong, and the line of IR it is wrong at.
+struct Problem {
+    message: String,
+    line: Option<(usize, String)>,
+}
+
+impl Problem {
+    fn new(message: impl Into<String>) -> Self {
+        Problem {
+            message: message.into(),
+            line: None,
+        }
+    }
+}
+
+/// The parameters a `define` or `declare` gives a function.
+struct Signature {
+    /// Each parameter's type, where it is plain.
+    params: Vec<Option<String>>,
+    variadic: bool,
+}
+
+impl Signature {
+    /// The function a header names and its signature.
+    fn parse(header: &str) -> Option<(&str, Signature)> {
+        let (_, rest) = header.split_once(" @")?;
+        let (name, rest) = rest.split_once('(')?;
+        let (params, _) = rest.rsplit_once(')')?;
+        let mut signature = Signature {
+            params: Vec::new(),
+            variadic: false,
+        };
+        for param in split_top_level(params) {
+            if param == "..." {
+                signature.variadic = true;
+            } else {
+                signature
+                    .params
+                    .push(param.split(' ').next().and_then(plain));
+            }
+        }
+        Some((name, signature))
+    }
+}
+
+/// One function being checked, line by line.
+struct Function<'t> {
+    types: &'t HashSet<&'t str>,
+    signatures: &'t HashMap<&'t str, Signature>,
+    name: String,
+    return_type: Option<String>,
+    /// Values and their types, where known.
+    values: HashMap<String, Option<String>>,
+    /// Uses of values not defined yet, which a later definition must cover.
+    pending: Vec<(String, usize, String)>,
+    labels: HashSet<String>,
+    branched: Vec<(String, usize, String)>,
+    next_number: usize,
+    /// The block is open: it has a label, or it is the first.
+    open: bool,
+    in_switch: bool,
+    /// Values pointing into the function's stack frame: `alloca`s and
+    /// pointers derived from them.
+    frame: HashSet<String>,
+    /// The part of `frame` that holds a promoted string or struct.
+    promoted: HashSet<String>,
+    /// Slots in the frame a promoted pointer was stored into; loading from
+    /// one gives it back.
+    holding: HashSet<String>,
+}
+
+impl<'t> Function<'t> {
+    fn new(
+        header: &str,
+        types: &'t HashSet<&'t str>,
+        signatures: &'t HashMap<&'t str, Signature>,
+    ) -> Self {
+        let name = header
+            .split_once('@')
+            .and_then(|(_, rest)| rest.split_once('('))
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_default();
+        let return_type = header
+            .split_once(" @")
+            .and_then(|(prefix, _)| prefix.split(' ').next_back())
+            .and_then(plain);
+        let mut values = HashMap::new();
+        let params = header
+            .split_once('(')
+            .and_then(|(_, rest)| rest.rsplit_once(')'))
+            .map(|(params, _)| params)
+            .unwrap_or_default();
+        let mut next_number = 0;
+        for param in split_top_level(params) {
+            if let Some((ty, name)) = param.rsplit_once(" %") {
+                values.insert(name.to_string(), ty.split(' ').next().and_then(plain));
+                if name.parse::<usize>().is_ok() {
+                    next_number += 1;
+                }
+            }
+        }
+        Function {
+            types,
+            signatures,
+            name,
+            return_type,
+            next_number,
+            values,
+            pending: Vec::new(),
+            labels: HashSet::new(),
+            branched: Vec::new(),
+            open: true,
+            in_switch: false,
+            frame: HashSet::new(),
+            promoted: HashSet::new(),
+            holding: HashSet::new(),
+        }
+    }
+
+    fn report(&self, problem: Problem) -> Diagnostic {
+        let function = match self.name.strip_prefix("brn_") {
+            Some(brain) => format!("'{}' (@{})", brain, self.name),
+            None => format!("@{}", self.name),
+        };
+        let mut diagnostic = Diagnostic::error(format!(
+            "internal compiler error: malformed LLVM IR generated for function {}",
+            function
+        ))
+        .with_code("E0006")
+        .with_note(problem.message);
+        if let Some((line, text)) = problem.line {
+            diagnostic = diagnostic.with_note(format!("at IR line {}: {}", line, text.trim()));
+        }
+        diagnostic.with_help("this is a bug in the Brain compiler, not in your program")
+    }
+
+    fn line(&mut self, number: usize, text: &str) -> Result<(), Problem> {
+        let at = |message: String| Problem {
+            message,
+            line: Some((number, text.to_string())),
+        };
+        let code = text.trim();
+        if code.is_empty() || code.starts_with(';') {
+            return Ok(());
+        }
+        if !text.starts_with(' ') && code.ends_with(':') {
+            let label = &code[..code.len() - 1];
+            if self.open && !self.labels.is_empty() {
+                return Err(at(format!(
+                    "the block before '{}' does not end in a terminator",
+                    label
+                )));
+            }
+            if !self.labels.insert(label.to_string()) {
+                return Err(at(format!("label '{}' is defined twice", label)));
+            }
+            self.open = true;
+            return Ok(());
+        }
+        if self.in_switch {
+            if code == "]" {
+                self.in_switch = false;
+                self.open = false;
+            }
+            self.check_branch_targets(number, text);
+            return Ok(());
+        }
+        if !self.open {
+            return Err(at(
+                "code follows the end of its block without a label".to_string()
+            ));
+        }
+        // A first block without a label is numbered like a value
+        if self.labels.is_empty() {
+            self.labels.insert(String::new());
+            self.next_number += 1;
+        }
+
+        let (result, instruction) = match code.split_once(" = ") {
+            Some((result, rest)) if result.starts_with('%') => (Some(&result[1..]), rest),
+            _ => (None, code),
+        };
+        self.check_uses(number, text, instruction);
+        self.check_types(instruction).map_err(at)?;
+        self.check_stack(result, instruction).map_err(at)?;
+        self.check_branch_targets(number, text);
+
+        if let Some(result) = result {
+            if self.values.contains_key(result) {
+                return Err(at(format!("%{} is defined twice", result)));
+            }
+            if let Ok(n) = result.parse::<usize>() {
+                if n != self.next_number {
+                    return Err(at(format!(
+                        "unnamed values must be numbered in order: expected %{}, found %{}",
+                        self.next_number, n
+                    )));
+                }
+                self.next_number += 1;
+            }
+            self.values
+                .insert(result.to_string(), result_type(instruction));
+        }
+
+        let opcode = instruction.split_whitespace().next().unwrap_or_default();
+        match opcode {
+            "switch" if code.ends_with('[') => self.in_switch = true,
+            "br" | "ret" | "unreachable" | "switch" | "resume" | "indirectbr" => self.open = false,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Every local value an instruction reads must be defined somewhere in
+    /// the function; a `phi` may read one defined further down.
+    fn check_uses(&mut self, number: usize, text: &str, instruction: &str) {
+        let labels = label_operands(text);
+        for name in local_names(instruction) {
+            if labels.contains(&name) || self.values.contains_key(name) || self.types.contains(name)
+            {
+                continue;
+            }
+            self.pending
+                .push((name.to_string(), number, text.to_string()));
+        }
+    }
+
+    fn check_branch_targets(&mut self, number: usize, text: &str) {
+        for label in label_operands(text) {
+            self.branched
+                .push((label.to_string(), number, text.to_string()));
+        }
+    }
+
+    /// Operand types against the types their values were defined with,
+    /// and a call's arguments against the callee's parameters.
+    fn check_types(&self, instruction: &str) -> Result<(), String> {
+        let Some((opcode, rest)) = instruction.split_once(' ') else {
+            return Ok(());
+        };
+        let mut expect = Vec::new();
+        match opcode {
+            "add" | "sub" | "mul" | "sdiv" | "udiv" | "srem" | "urem" | "and" | "or" | "xor"
+            | "shl" | "lshr" | "ashr" => {
+                let rest = strip_flags(rest);
+                if let Some((ty, operands)) = rest.split_once(' ') {
+                    for operand in operands.split(", ") {
+                        expect.push((operand, ty));
+                    }
+                }
+            }
+            "icmp" | "fcmp" => {
+                if let Some((_, rest)) = rest.split_once(' ')
+                    && let Some((ty, operands)) = rest.split_once(' ')
+                {
+                    for operand in operands.split(", ") {
+                        expect.push((operand, ty));
+                    }
+                }
+            }
+            "store" => {
+                if let Some((ty, rest)) = rest.split_once(' ')
+                    && let Some((value, pointer)) = rest.split_once(", ")
+                {
+                    expect.push((value, ty));
+                    let pointer = pointer.split(", align").next().unwrap_or_default();
+                    if let Some((pointer_ty, pointer)) = pointer.rsplit_once(' ') {
+                        expect.push((pointer, pointer_ty));
+                    }
+                }
+            }
+            "load" => {
+                if let Some((_, pointer)) = rest.split_once(", ") {
+                    let pointer = pointer.split(", align").next().unwrap_or_default();
+                    if let Some((pointer_ty, pointer)) = pointer.rsplit_once(' ') {
+                        expect.push((pointer, pointer_ty));
+                    }
+                }
+            }
+            "br" => {
+                if let Some(condition) = rest.strip_prefix("i1 ")
+                    && let Some((condition, _)) = condition.split_once(", ")
+                {
+                    expect.push((condition, "i1"));
+                }
+            }
+            "ret" if rest != "void" => {
+                if let Some((ty, value)) = rest.rsplit_once(' ') {
+                    if let Some(return_type) = &self.return_type
+                        && plain(ty).is_some()
+                        && ty != return_type
+                    {
+                        return Err(format!(
+                            "returns {} from a function declared to return {}",
+                            ty, return_type
+                        ));
+                    }
+                    expect.push((value, ty));
+                }
+            }
+            _ if CASTS.contains(&opcode) => {
+                if let Some((operand, _)) = rest.split_once(" to ")
+                    && let Some((ty, value)) = operand.rsplit_once(' ')
+                {
+                    expect.push((value, ty));
+                }
+            }
+            "call" | "tail" => {
+                if let Some((_, args)) = rest.split_once(")(").or_else(|| {
+                    rest.split_once('@')
+                        .and_then(|(_, callee)| callee.split_once('('))
+                }) && let Some((args, _)) = args.rsplit_once(')')
+                {
+                    let args = split_top_level(args);
+                    if let Some(callee) = callee(rest)
+                        && let Some(signature) = self.signatures.get(callee)
+                    {
+                        let count = signature.params.len();
+                        if args.len() < count || args.len() > count && !signature.variadic {
+                            return Err(format!(
+                                "calls @{} with {} argument{}, but it takes {}",
+                                callee,
+                                args.len(),
+                                if args.len() == 1 { "" } else { "s" },
+                                count
+                            ));
+                        }
+                        for (i, (arg, param)) in args.iter().zip(&signature.params).enumerate() {
+                            let ty = arg.split(' ').next().unwrap_or_default();
+                            if let Some(param) = param
+                                && plain(ty).is_some()
+                                && ty != param
+                            {
+                                return Err(format!(
+                                    "passes {} as argument {} of @{}, which takes {}",
+                                    ty,
+                                    i + 1,
+                                    callee,
+                                    param
+                                ));
+                            }
+                        }
+                    }
+                    for arg in args {
+                        if let Some((ty, value)) = arg.rsplit_once(' ') {
+                            expect.push((value, ty.split(' ').next().unwrap_or_default()));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        for (value, ty) in expect {
+            let Some(name) = value.strip_prefix('%') else {
+                continue;
+            };
+            if let Some(Some(actual)) = self.values.get(name)
+                && plain(ty).is_some()
+                && actual != ty
+            {
+                return Err(format!(
+                    "%{} is defined with type {} but used as {}",
+                    name, actual, ty
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stack memory standing in for the heap must not outlive the call:
+    /// follow pointers to it through casts, `getelementptr`s and the slots
+    /// it is stored in, and reject it reaching a `ret`, a `free` or a store
+    /// into memory outside the frame.
+    fn check_stack(&mut self, result: Option<&str>, instruction: &str) -> Result<(), String> {
+        let Some((opcode, rest)) = instruction.split_once(' ') else {
+            return Ok(());
+        };
+        let value = |operand: &str| {
+            operand
+                .rsplit_once(' ')
+                .and_then(|(_, value)| value.strip_prefix('%'))
+                .map(str::to_string)
+        };
+        let operands = split_top_level(rest.strip_prefix("inbounds ").unwrap_or(rest));
+        let from = match opcode {
+            "alloca" => {
+                let ty = operands.first().copied().unwrap_or_default();
+                // `alloca i8, i64 %n` is a string's buffer; a variable's
+                // slot has no count
+                let is_buffer = operands.get(1).is_some_and(|o| !o.starts_with("align"));
+                let is_struct = ty
+                    .strip_prefix('%')
+                    .is_some_and(|name| self.types.contains(name));
+                if let Some(result) = result {
+                    self.frame.insert(result.to_string());
+                    if is_buffer || is_struct {
+                        self.promoted.insert(result.to_string());
+                    }
+                }
+                return Ok(());
+            }
+            "getelementptr" => operands.get(1).and_then(|o| value(o)),
+            "bitcast" => rest.split_once(" to ").and_then(|(o, _)| value(o)),
+            "load" => {
+                let slot = operands.get(1).and_then(|o| value(o));
+                if let (Some(result), Some(slot)) = (result, slot)
+                    && self.holding.contains(&slot)
+                {
+                    self.promoted.insert(result.to_string());
+                }
+                return Ok(());
+            }
+            "store" => {
+                let (Some(stored), Some(slot)) = (
+                    operands.first().and_then(|o| value(o)),
+                    operands.get(1).and_then(|o| value(o)),
+                ) else {
+                    return Ok(());
+                };
+                if !self.promoted.contains(&stored) {
+                    return Ok(());
+                }
+                if !self.frame.contains(&slot) {
+                    return Err(format!(
+                        "%{} points to stack memory and is stored through %{}, which can outlive the function",
+                        stored, slot
+                    ));
+                }
+                self.holding.insert(slot);
+                return Ok(());
+            }
+            "ret" => {
+                return match value(rest) {
+                    Some(returned) if self.promoted.contains(&returned) => Err(format!(
+                        "returns %{}, which points to stack memory",
+                        returned
+                    )),
+                    _ => Ok(()),
+                };
+            }
+            "call" | "tail" => {
+                let freed = rest
+                    .split_once("@free(")
+                    .and_then(|(_, args)| args.strip_suffix(')'))
+                    .and_then(value);
+                return match freed {
+                    Some(freed) if self.promoted.contains(&freed) => {
+                        Err(format!("frees %{}, which is stack memory", freed))
+                    }
+                    _ => Ok(()),
+                };
+            }
+            _ => None,
+        };
+        if let (Some(result), Some(from)) = (result, from) {
+            if self.promoted.contains(&from) {
+                self.promoted.insert(result.to_string());
+            }
+            if self.frame.contains(&from) {
+                self.frame.insert(result.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Problem> {
+        if self.open {
+            return Err(Problem::new("the last block does not end in a terminator"));
+        }
+        for (name, line, text) in &self.pending {
+            if !self.values.contains_key(name) {
+                return Err(Problem {
+                    message: format!("%{} is used but never defined", name),
+                    line: Some((*line, text.clone())),
+                });
+            }
+        }
+        for (label, line, text) in &self.branched {
+            if !self.labels.contains(label) {
+                return Err(Problem {
+                    message: format!("branch to undefined label '{}'", label),
+                    line: Some((*line, text.clone())),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The type of an instruction's result, where the text says it outright.
+fn result_type(instruction: &str) -> Option<String> {
+    let (opcode, rest) = instruction.split_once(' ')?;
+    let ty = match opcode {
+        _ if SAME_TYPE.contains(&opcode) => {
+            let rest = strip_flags(rest);
+            rest.split([' ', ',']).next()?.to_string()
+        }
+        "icmp" | "fcmp" => "i1".to_string(),
+        "alloca" => format!("{}*", rest.split(',').next()?),
+        _ if CASTS.contains(&opcode) => rest.rsplit_once(" to ")?.1.to_string(),
+        "call" => {
+            let ty = rest.split(' ').next()?;
+            // A type in parentheses is the callee's type, not its result
+            (!rest.starts_with('(')).then(|| ty.to_string())?
+        }
+        "select" => {
+            let (_, value) = rest.split_once(", ")?;
+            value.rsplit_once(' ')?.0.to_string()
+        }
+        _ => return None,
+    };
+    plain(&ty)
+}
+
+/// The function a direct call names: `@brn_puts` in
+/// `call i64 @brn_puts(i8* %s)`. A `@` after an unclosed parenthesis is
+/// an argument of an indirect call.
+fn callee(rest: &str) -> Option<&str> {
+    let (before, after) = rest.split_once('@')?;
+    if before.matches('(').count() != before.matches(')').count() {
+        return None;
+    }
+    after.split_once('(').map(|(name, _)| name)
+}
+
+/// A type written as one word, which comparing as text is fair for;
+/// aggregates and function types are left unchecked.
+fn plain(ty: &str) -> Option<String> {
+    (!ty.is_empty() && !ty.contains(['{', '}', '[', ']', '<', '>', '(', ')', ' ', ',']))
+        .then(|| ty.to_string())
+}
+
+/// `nsw`, `nuw`, `exact` and `volatile` before an instruction's type.
+fn strip_flags(mut rest: &str) -> &str {
+    for flag in ["nsw ", "nuw ", "exact ", "volatile "] {
+        rest = rest.strip_prefix(flag).unwrap_or(rest);
+    }
+    rest
+}
+
+/// The labels an instruction branches to: `label %name`, and the blocks a
+/// `phi` names as where its values come from.
+fn label_operands(text: &str) -> Vec<&str> {
+    let mut labels: Vec<&str> = text
+        .split("label %")
+        .skip(1)
+        .filter_map(|rest| rest.split([',', ' ', ']']).next())
+        .collect();
+    if text.contains(" = phi ") {
+        labels.extend(
+            text.split("[ ")
+                .skip(1)
+                .filter_map(|arm| arm.split_once(", %"))
+                .filter_map(|(_, rest)| rest.split([' ', ']']).next()),
+        );
+    }
+    labels
+}
+
+/// The names of the local values and labels `text` mentions, without `%`.
+fn local_names(text: &str) -> impl Iterator<Item = &str> {
+    let mut in_string = false;
+    let mut names = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => in_string = !in_string,
+            b'%' if !in_string => {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len()
+                    && (bytes[end].is_ascii_alphanumeric() || b"_.$".contains(&bytes[end]))
+                {
+                    end += 1;
+                }
+                if end > start {
+                    names.push(&text[start..end]);
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    names.into_iter()
+}
+
+/// Comma-separated items, not splitting inside brackets or parentheses.
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' | ']' | '}' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !list[start..].trim().is_empty() {
+        items.push(list[start..].trim());
+    }
+    items
+}
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use brain::manifest::Manifest;
+use brain::module::ModuleCache;
+use brain::{Compiler, Diagnostic};
+
+use crate::{
+    LinkOutcome, Options, compiler, generate_ir, report, report_timings, report_warnings,
+    write_and_link,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `--watch`: build once, then poll the input file, every module it
+/// imported and the manifest, rebuilding whenever one of them changes.
+/// Runs until interrupted.
+pub fn watch(options: &Options) -> ! {
+    let input = PathBuf::from(&options.input_file);
+    // Never shrinks: a file that fails to parse still imports what it did
+    // before, and must stay watched so fixing it triggers a rebuild.
+    let mut watched: BTreeSet<PathBuf> = BTreeSet::new();
+    watched.insert(input.clone());
+    watched.extend(options.inputs.iter().map(PathBuf::from));
+
+    loop {
+        let started = Instant::now();
+        let mut cache = ModuleCache::new();
+        let result = build_once(options, &mut cache);
+        let elapsed = started.elapsed().as_secs_f64();
+
+        watched.extend(cache.module_paths().map(PathBuf::from));
+        if let Ok(Some(manifest)) = Manifest::find(&options.input_file) {
+            watched.insert(manifest.path);
+        }
+
+        match result {
+            Ok(summary) => println!(
+                "[watch] ✓ {} in {:.2}s — watching {} file{}",
+                summary,
+                elapsed,
+                watched.len(),
+                if watched.len() == 1 { "" } else { "s" }
+            ),
+            Err(e) => {
+                report(&e, options.error_format);
+                println!("[watch] ✗ failed — waiting for changes");
+            }
+        }
+
+        let changed = wait_for_change(&watched);
+        println!("[watch] {} changed, rebuilding...", display_name(&changed));
+    }
+}
+
+fn build_once(options: &Options, cache: &mut ModuleCache) -> Result<String, Diagnostic> {
+    let compiler = compiler(options, false);
+    let result = build_with(options, &compiler, cache);
+    report_warnings(options, &compiler);
+    report_timings(options, &compiler);
+    result
+}
+
+fn build_with(
+    options: &Options,
+    compiler: &Compiler,
+    cache: &mut ModuleCache,
+) -> Result<String, Diagnostic> {
+    let input_file = options.input_file.as_str();
+    let quiet = |_: &str| {};
+
+    if options.check {
+        let ast = compiler.parse_files(&options.inputs)?;
+        compiler.check(ast, input_file, cache)?;
+        return Ok(format!("{} checked", input_file));
+    }
+
+    let llvm_ir = generate_ir(options, compiler, cache)?;
+    Ok(match write_and_link(options, compiler, &llvm_ir, &quiet)? {
+        LinkOutcome::Linked { exe } => format!("built {}", exe),
+        LinkOutcome::IrOnly { ll_file, .. } => format!("wrote {}", ll_file),
+    })
+}
+
+fn snapshot(files: &BTreeSet<PathBuf>) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| {
+            let mtime = fs::metadata(f).and_then(|m| m.modified()).ok();
+            (f.clone(), mtime)
+        })
+        .collect()
+}
+
+fn wait_for_change(files: &BTreeSet<PathBuf>) -> PathBuf {
+    let before = snapshot(files);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let now = snapshot(files);
+        if let Some((path, _)) = now
+            .iter()
+            .find(|(path, mtime)| before.get(*path) != Some(mtime))
+        {
+            // Editors often write a file in several steps; let them finish.
+            thread::sleep(POLL_INTERVAL);
+            return path.clone();
+        }
+    }
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
@@ -0,0 +1,92 @@
+//! Bounds checks have to survive `opt -O2`: a function that indexes a
+//! `Vec` may panic, so a call whose result is unused must still be made.
+//! Needs LLVM's `opt` and `lli` (or `BRAIN_LLI`); skipped without them.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+const PROGRAM: &str = r#"
+fn fifth(v: Vec<int>) -> int {
+    return v[5];
+}
+
+fn fifth_get(v: Vec<int>) -> int {
+    return v.get(5);
+}
+
+fn fifth_builtin(v: Vec<int>) -> int {
+    return vec_get(v, 5);
+}
+
+fn main(args: Vec<string>) {
+    let v = vec_new();
+    vec_push(v, 1);
+    let which = args.get(1);
+    if which == "index" {
+        fifth(v);
+    } else if which == "get" {
+        fifth_get(v);
+    } else {
+        fifth_builtin(v);
+    }
+    println("unchecked");
+}
+"#;
+
+/// Compile `PROGRAM`, optimize it with `opt -O2` and JIT it once per
+/// argument, or `None` if the LLVM tools aren't installed.
+fn run_optimized(args: &[&str]) -> Option<Vec<Output>> {
+    let artifacts = brain::Compiler::new()
+        .compile_source(PROGRAM, "bounds.brn")
+        .unwrap_or_else(|diagnostics| panic!("{}", diagnostics[0].render()));
+
+    let dir = env::temp_dir().join(format!("brain-bounds-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ll_file = dir.join("bounds.ll");
+    let optimized = dir.join("bounds.opt.ll");
+    fs::write(&ll_file, artifacts.llvm_ir).unwrap();
+
+    let Ok(opt) = Command::new("opt")
+        .arg("-O2")
+        .arg("-S")
+        .arg(&ll_file)
+        .arg("-o")
+        .arg(&optimized)
+        .output()
+    else {
+        eprintln!("skipped: 'opt' not found");
+        return None;
+    };
+    assert!(
+        opt.status.success(),
+        "opt failed: {}",
+        String::from_utf8_lossy(&opt.stderr)
+    );
+
+    let lli = env::var("BRAIN_LLI").unwrap_or_else(|_| "lli".to_string());
+    let mut outputs = Vec::new();
+    for arg in args {
+        match Command::new(&lli).arg(&optimized).arg(arg).output() {
+            Ok(output) => outputs.push(output),
+            Err(_) => {
+                eprintln!("skipped: '{}' not found", lli);
+                return None;
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+    Some(outputs)
+}
+
+#[test]
+fn unused_out_of_bounds_read_still_panics_at_o2() {
+    let Some(outputs) = run_optimized(&["index", "get", "builtin"]) else {
+        return;
+    };
+    for output in outputs {
+        assert_eq!(output.status.code(), Some(101));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Vec index out of bounds"));
+        assert!(!String::from_utf8_lossy(&output.stdout).contains("unchecked"));
+    }
+}
@@ -0,0 +1,29 @@
+//! The IR of each fixture in `tests/ir` must match its `.expected.ll`, as
+//! `brain --test-ir tests/ir` checks; rerun that with `--bless` after an
+//! intended change to the generated code.
+
+use brain::golden::{self, Outcome};
+use std::path::Path;
+
+#[test]
+fn fixtures_match_expected_ir() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let results = golden::run(&brain::Compiler::new(), &dir, false)
+        .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.render()));
+    assert!(!results.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for result in results {
+        let problem = match result.outcome {
+            Outcome::Passed | Outcome::Blessed => continue,
+            Outcome::Missing => "no expected IR".to_string(),
+            Outcome::Mismatch(diff) => diff,
+            Outcome::Failed(diagnostics) => diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.render())
+                .collect(),
+        };
+        failures.push(format!("{}:\n{}", result.fixture.display(), problem));
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
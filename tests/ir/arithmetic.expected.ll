@@ -0,0 +1,23 @@
+define i64 @brn_add(i64 %arg_a, i64 %arg_b) nounwind readonly willreturn {
+entry:
+  %0 = add i64 %arg_a, %arg_b
+  ret i64 %0
+}
+
+define i1 @brn_is_between(i64 %arg_val, i64 %arg_lo, i64 %arg_hi) nounwind readonly willreturn {
+entry:
+  %0 = icmp sge i64 %arg_val, %arg_lo
+  %1 = icmp sle i64 %arg_val, %arg_hi
+  %2 = and i1 %0, %1
+  ret i1 %2
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = call i64 @brn_add(i64 42, i64 1)
+  call void @brn_print_int(i64 %0)
+  %1 = call i1 @brn_is_between(i64 42, i64 0, i64 100)
+  %2 = zext i1 %1 to i64
+  call void @brn_print_int(i64 %2)
+  ret i32 0
+}
@@ -0,0 +1,47 @@
+define i64 @brn_square(i64 %arg_x) nounwind readonly willreturn alwaysinline {
+entry:
+  %0 = mul i64 %arg_x, %arg_x
+  ret i64 %0
+}
+
+define i64 @brn_cube(i64 %arg_x) nounwind readonly willreturn noinline {
+entry:
+  %0 = mul i64 %arg_x, %arg_x
+  %1 = mul i64 %0, %arg_x
+  ret i64 %1
+}
+
+define i64 @brain_version() nounwind readonly willreturn {
+entry:
+  ret i64 3
+}
+
+define i64 @brn_never_called() nounwind readonly willreturn {
+entry:
+  ret i64 0
+}
+
+define void @brn_hello() nounwind {
+entry:
+  call void @brn_print_int(i64 1)
+  ret void
+}
+
+define void @brn_goodbye() nounwind {
+entry:
+  call void @brn_print_int(i64 2)
+  ret void
+}
+
+define i32 @main() nounwind {
+entry:
+  call void @brn_hello()
+  %0 = call i64 @brn_square(i64 3)
+  %1 = call i64 @brn_cube(i64 2)
+  %2 = add i64 %0, %1
+  %3 = call i64 @brain_version()
+  %4 = add i64 %2, %3
+  call void @brn_print_int(i64 %4)
+  call void @brn_goodbye()
+  ret i32 0
+}
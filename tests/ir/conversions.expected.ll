@@ -0,0 +1,25 @@
+define i8 @brn_shift(i8 %arg_c, i64 %arg_by) nounwind readonly willreturn {
+entry:
+  %0 = zext i8 %arg_c to i64
+  %1 = add i64 %0, %arg_by
+  %2 = trunc i64 %1 to i8
+  ret i8 %2
+}
+
+define i64 @brn_code(i8 %arg_c) nounwind readonly willreturn {
+entry:
+  %0 = zext i8 %arg_c to i64
+  ret i64 %0
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = call i8 @brn_shift(i8 97, i64 2)
+  %1 = zext i8 %0 to i64
+  %2 = call i64 @brn_code(i8 %0)
+  %3 = add i64 %1, %2
+  call void @brn_print_int(i64 %3)
+  %4 = call i8 @brn_shift(i8 %0, i64 1)
+  call void @brn_print_char(i8 %4)
+  ret i32 0
+}
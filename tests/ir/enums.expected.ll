@@ -0,0 +1,44 @@
+define i64 @brn_area({ i32, i64 }* noalias readonly %arg_s) nounwind readonly willreturn {
+entry:
+  %0 = alloca { i32, i64 }*
+  store { i32, i64 }* %arg_s, { i32, i64 }** %0
+  %1 = load { i32, i64 }*, { i32, i64 }** %0
+  %2 = getelementptr { i32, i64 }, { i32, i64 }* %1, i32 0, i32 0
+  %3 = load i32, i32* %2
+  switch i32 %3, label %match_end0 [
+    i32 0, label %match_arm_01
+    i32 1, label %match_arm_12
+  ]
+match_arm_01:
+  %4 = getelementptr { i32, i64 }, { i32, i64 }* %1, i32 0, i32 1
+  %5 = load i64, i64* %4
+  %6 = mul i64 3, %5
+  %7 = mul i64 %6, %5
+  ret i64 %7
+match_arm_12:
+  %8 = getelementptr { i32, i64 }, { i32, i64 }* %1, i32 0, i32 1
+  %9 = load i64, i64* %8
+  %10 = mul i64 %9, %9
+  ret i64 %10
+match_end0:
+  unreachable
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = alloca { i32, i64 }
+  %1 = getelementptr { i32, i64 }, { i32, i64 }* %0, i32 0, i32 0
+  store i32 0, i32* %1
+  %2 = getelementptr { i32, i64 }, { i32, i64 }* %0, i32 0, i32 1
+  store i64 2, i64* %2
+  %3 = call i64 @brn_area({ i32, i64 }* %0)
+  call void @brn_print_int(i64 %3)
+  %4 = alloca { i32, i64 }
+  %5 = getelementptr { i32, i64 }, { i32, i64 }* %4, i32 0, i32 0
+  store i32 1, i32* %5
+  %6 = getelementptr { i32, i64 }, { i32, i64 }* %4, i32 0, i32 1
+  store i64 3, i64* %6
+  %7 = call i64 @brn_area({ i32, i64 }* %4)
+  call void @brn_print_int(i64 %7)
+  ret i32 0
+}
@@ -0,0 +1,41 @@
+%C = type { i64, i64 }
+
+define i64 @brn_show(%C* noalias readonly %arg_c) nounwind readonly willreturn {
+entry:
+  %0 = getelementptr %C, %C* %arg_c, i32 0, i32 0
+  %1 = load i64, i64* %0
+  %2 = getelementptr %C, %C* %arg_c, i32 0, i32 1
+  %3 = load i64, i64* %2
+  %4 = add i64 %1, %3
+  ret i64 %4
+}
+
+define i64 @brn_fwd(%C* noalias readonly %arg_c) nounwind readonly willreturn {
+entry:
+  %0 = call i64 @brn_show(%C* %arg_c)
+  ret i64 %0
+}
+
+define i64 @brn_bump(%C* noalias %arg_c) nounwind {
+entry:
+  %0 = getelementptr %C, %C* %arg_c, i32 0, i32 0
+  %1 = load i64, i64* %0
+  %2 = add i64 %1, 1
+  store i64 %2, i64* %0
+  %3 = call i64 @brn_show(%C* %arg_c)
+  ret i64 %3
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = alloca %C
+  %1 = getelementptr %C, %C* %0, i32 0, i32 0
+  store i64 1, i64* %1
+  %2 = getelementptr %C, %C* %0, i32 0, i32 1
+  store i64 2, i64* %2
+  %3 = call i64 @brn_fwd(%C* %0)
+  call void @brn_print_int(i64 %3)
+  %4 = call i64 @brn_bump(%C* %0)
+  call void @brn_print_int(i64 %4)
+  ret i32 0
+}
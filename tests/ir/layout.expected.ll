@@ -0,0 +1,39 @@
+%Named = type { i8*, %Flags }
+%Flags = type { i1, i64, i8 }
+@.str.0 = private unnamed_addr constant [2 x i8] c"x\00", align 1
+
+define %Named* @brn_make(i8* noalias readonly %arg_name) nounwind {
+entry:
+  %0 = call i8* @malloc(i64 32)
+  %1 = bitcast i8* %0 to %Named*
+  %2 = getelementptr %Named, %Named* %1, i32 0, i32 0
+  store i8* %arg_name, i8** %2
+  %3 = alloca %Flags
+  %4 = getelementptr %Flags, %Flags* %3, i32 0, i32 0
+  store i1 1, i1* %4
+  %5 = getelementptr %Flags, %Flags* %3, i32 0, i32 1
+  store i64 1, i64* %5
+  %6 = getelementptr %Flags, %Flags* %3, i32 0, i32 2
+  store i8 97, i8* %6
+  %7 = getelementptr %Named, %Named* %1, i32 0, i32 1
+  %8 = load %Flags, %Flags* %3
+  store %Flags %8, %Flags* %7
+  ret %Named* %1
+}
+
+define i32 @main() nounwind {
+entry:
+  call void @brn_print_int(i64 24)
+  call void @brn_print_int(i64 32)
+  call void @brn_print_int(i64 24)
+  %0 = getelementptr inbounds [2 x i8], [2 x i8]* @.str.0, i64 0, i64 0
+  %1 = call i8* @brn_strdup(i8* %0)
+  %2 = call %Named* @brn_make(i8* %1)
+  %3 = getelementptr %Named, %Named* %2, i32 0, i32 1
+  %4 = getelementptr %Flags, %Flags* %3, i32 0, i32 1
+  %5 = load i64, i64* %4
+  call void @brn_print_int(i64 %5)
+  %6 = bitcast %Named* %2 to i8*
+  call void @free(i8* %6)
+  ret i32 0
+}
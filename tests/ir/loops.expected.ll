@@ -0,0 +1,66 @@
+define i64 @brn_sum_scaled(i64 %arg_n, i64 %arg_k) nounwind {
+entry:
+  %sr4 = alloca i64
+  %0 = alloca i64
+  store i64 0, i64* %0
+  %1 = alloca i64
+  store i64 0, i64* %1
+  %2 = alloca i64
+  store i64 %arg_n, i64* %2
+  store i64 0, i64* %sr4
+  %3 = mul i64 %arg_k, 3
+  br label %for_start0
+for_start0:
+  %4 = load i64, i64* %1
+  %5 = load i64, i64* %2
+  %6 = icmp slt i64 %4, %5
+  br i1 %6, label %for_body1, label %for_end3
+for_body1:
+  %7 = load i64, i64* %0
+  %8 = load i64, i64* %sr4
+  %9 = add i64 %7, %8
+  %10 = add i64 %9, %3
+  store i64 %10, i64* %0
+  %11 = load i64, i64* %1
+  %12 = add i64 %11, 1
+  store i64 %12, i64* %1
+  %13 = add i64 %8, 4
+  store i64 %13, i64* %sr4
+  br label %for_start0
+for_end3:
+  %14 = load i64, i64* %0
+  ret i64 %14
+}
+
+define i64 @brn_count_down(i64 %arg_n) nounwind {
+entry:
+  %0 = alloca i64
+  store i64 %arg_n, i64* %0
+  %1 = alloca i64
+  store i64 0, i64* %1
+  br label %while_cond0
+while_cond0:
+  %2 = load i64, i64* %0
+  %3 = icmp sgt i64 %2, 0
+  br i1 %3, label %while_body1, label %while_end2
+while_body1:
+  %4 = load i64, i64* %0
+  %5 = sub i64 %4, 1
+  store i64 %5, i64* %0
+  %6 = load i64, i64* %1
+  %7 = add i64 %6, 1
+  store i64 %7, i64* %1
+  br label %while_cond0
+while_end2:
+  %8 = load i64, i64* %1
+  ret i64 %8
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = call i64 @brn_sum_scaled(i64 10, i64 2)
+  call void @brn_print_int(i64 %0)
+  %1 = call i64 @brn_count_down(i64 5)
+  call void @brn_print_int(i64 %1)
+  ret i32 0
+}
@@ -0,0 +1,134 @@
+%Point = type { i64, i64 }
+
+define i64 @brn_area({ i32, i64 }* noalias readonly %arg_s) nounwind readonly willreturn {
+entry:
+  %0 = alloca { i32, i64 }*
+  store { i32, i64 }* %arg_s, { i32, i64 }** %0
+  %1 = load { i32, i64 }*, { i32, i64 }** %0
+  %2 = ptrtoint { i32, i64 }* %1 to i64
+  %3 = inttoptr i64 %2 to { i32, i64 }*
+  %4 = getelementptr { i32, i64 }, { i32, i64 }* %3, i32 0, i32 0
+  %5 = load i32, i32* %4
+  %6 = icmp eq i32 %5, 0
+  br i1 %6, label %pattern_ok2, label %match_check_11
+pattern_ok2:
+  %7 = getelementptr { i32, i64 }, { i32, i64 }* %3, i32 0, i32 1
+  %8 = load i64, i64* %7
+  %9 = inttoptr i64 %8 to [2 x i64]*
+  %10 = getelementptr [2 x i64], [2 x i64]* %9, i64 0, i64 0
+  %11 = load i64, i64* %10
+  %12 = getelementptr [2 x i64], [2 x i64]* %9, i64 0, i64 1
+  %13 = load i64, i64* %12
+  %14 = mul i64 %11, %13
+  ret i64 %14
+match_check_11:
+  %15 = inttoptr i64 %2 to { i32, i64 }*
+  %16 = getelementptr { i32, i64 }, { i32, i64 }* %15, i32 0, i32 0
+  %17 = load i32, i32* %16
+  %18 = icmp eq i32 %17, 1
+  br i1 %18, label %pattern_ok4, label %match_check_23
+pattern_ok4:
+  %19 = getelementptr { i32, i64 }, { i32, i64 }* %15, i32 0, i32 1
+  %20 = load i64, i64* %19
+  %21 = inttoptr i64 %20 to %Point*
+  %22 = getelementptr %Point, %Point* %21, i32 0, i32 0
+  %23 = load i64, i64* %22
+  %24 = icmp eq i64 %23, 0
+  br i1 %24, label %pattern_ok5, label %match_check_23
+pattern_ok5:
+  ret i64 -1
+match_check_23:
+  %25 = inttoptr i64 %2 to { i32, i64 }*
+  %26 = getelementptr { i32, i64 }, { i32, i64 }* %25, i32 0, i32 0
+  %27 = load i32, i32* %26
+  %28 = icmp eq i32 %27, 1
+  br i1 %28, label %pattern_ok7, label %match_check_36
+pattern_ok7:
+  %29 = getelementptr { i32, i64 }, { i32, i64 }* %25, i32 0, i32 1
+  %30 = load i64, i64* %29
+  %31 = inttoptr i64 %30 to %Point*
+  %32 = getelementptr %Point, %Point* %31, i32 0, i32 0
+  %33 = load i64, i64* %32
+  %34 = getelementptr %Point, %Point* %31, i32 0, i32 1
+  %35 = load i64, i64* %34
+  %36 = add i64 %33, %35
+  ret i64 %36
+match_check_36:
+  %37 = inttoptr i64 %2 to { i32, i64 }*
+  %38 = getelementptr { i32, i64 }, { i32, i64 }* %37, i32 0, i32 0
+  %39 = load i32, i32* %38
+  %40 = icmp eq i32 %39, 2
+  br i1 %40, label %pattern_ok8, label %match_end0
+pattern_ok8:
+  ret i64 0
+match_end0:
+  unreachable
+}
+
+define i64 @brn_first_x({ i32, i64 }* noalias readonly %arg_m) nounwind readonly willreturn {
+entry:
+  %0 = alloca { i32, i64 }*
+  store { i32, i64 }* %arg_m, { i32, i64 }** %0
+  %1 = load { i32, i64 }*, { i32, i64 }** %0
+  %2 = ptrtoint { i32, i64 }* %1 to i64
+  %3 = inttoptr i64 %2 to { i32, i64 }*
+  %4 = getelementptr { i32, i64 }, { i32, i64 }* %3, i32 0, i32 0
+  %5 = load i32, i32* %4
+  %6 = icmp eq i32 %5, 0
+  br i1 %6, label %pattern_ok2, label %match_check_11
+pattern_ok2:
+  %7 = getelementptr { i32, i64 }, { i32, i64 }* %3, i32 0, i32 1
+  %8 = load i64, i64* %7
+  %9 = inttoptr i64 %8 to %Point*
+  %10 = getelementptr %Point, %Point* %9, i32 0, i32 0
+  %11 = load i64, i64* %10
+  ret i64 %11
+match_check_11:
+  ret i64 -1
+match_end0:
+  unreachable
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = alloca { i32, i64 }
+  %1 = getelementptr { i32, i64 }, { i32, i64 }* %0, i32 0, i32 0
+  store i32 0, i32* %1
+  %2 = alloca [2 x i64]
+  %3 = getelementptr [2 x i64], [2 x i64]* %2, i64 0, i64 0
+  store i64 3, i64* %3
+  %4 = getelementptr [2 x i64], [2 x i64]* %2, i64 0, i64 1
+  store i64 4, i64* %4
+  %5 = ptrtoint [2 x i64]* %2 to i64
+  %6 = getelementptr { i32, i64 }, { i32, i64 }* %0, i32 0, i32 1
+  store i64 %5, i64* %6
+  %7 = call i64 @brn_area({ i32, i64 }* %0)
+  call void @brn_print_int(i64 %7)
+  %8 = alloca { i32, i64 }
+  %9 = getelementptr { i32, i64 }, { i32, i64 }* %8, i32 0, i32 0
+  store i32 1, i32* %9
+  %10 = alloca %Point
+  %11 = getelementptr %Point, %Point* %10, i32 0, i32 0
+  store i64 2, i64* %11
+  %12 = getelementptr %Point, %Point* %10, i32 0, i32 1
+  store i64 9, i64* %12
+  %13 = ptrtoint %Point* %10 to i64
+  %14 = getelementptr { i32, i64 }, { i32, i64 }* %8, i32 0, i32 1
+  store i64 %13, i64* %14
+  %15 = call i64 @brn_area({ i32, i64 }* %8)
+  call void @brn_print_int(i64 %15)
+  %16 = alloca { i32, i64 }
+  %17 = getelementptr { i32, i64 }, { i32, i64 }* %16, i32 0, i32 0
+  store i32 0, i32* %17
+  %18 = alloca %Point
+  %19 = getelementptr %Point, %Point* %18, i32 0, i32 0
+  store i64 42, i64* %19
+  %20 = getelementptr %Point, %Point* %18, i32 0, i32 1
+  store i64 1, i64* %20
+  %21 = ptrtoint %Point* %18 to i64
+  %22 = getelementptr { i32, i64 }, { i32, i64 }* %16, i32 0, i32 1
+  store i64 %21, i64* %22
+  %23 = call i64 @brn_first_x({ i32, i64 }* %16)
+  call void @brn_print_int(i64 %23)
+  ret i32 0
+}
@@ -0,0 +1,56 @@
+%Point = type { i64, i64 }
+
+define internal void @brn.init_globals() nounwind {
+entry:
+  store i64 0, i64* @brn.g.ORIGIN
+  ret void
+}
+@brn.g.ORIGIN = internal global i64 zeroinitializer
+
+define i64 @brn_first(i64* noalias readonly %arg_a, i64* noalias readonly %arg_b) nounwind readonly willreturn {
+entry:
+  %0 = load i64, i64* %arg_a
+  ret i64 %0
+}
+
+define i64 @brn_origin() nounwind readonly willreturn {
+entry:
+  %0 = load i64, i64* @brn.g.ORIGIN
+  ret i64 %0
+}
+
+define %Point @brn_closer(%Point* noalias readonly %arg_p) nounwind readonly willreturn {
+entry:
+  %0 = load %Point, %Point* %arg_p
+  ret %Point %0
+}
+
+define i32 @main() nounwind {
+entry:
+  call void @brn.init_globals()
+  %0 = alloca i64
+  store i64 5, i64* %0
+  %1 = alloca i64
+  store i64 7, i64* %1
+  %2 = call i64 @brn_first(i64* %0, i64* %1)
+  %3 = load i64, i64* %0
+  %4 = alloca i64
+  store i64 %3, i64* %4
+  %5 = call i64 @brn_first(i64* %1, i64* %4)
+  %6 = add i64 %2, %5
+  call void @brn_print_int(i64 %6)
+  %7 = call i64 @brn_origin()
+  call void @brn_print_int(i64 %7)
+  %8 = alloca %Point
+  %9 = getelementptr %Point, %Point* %8, i32 0, i32 0
+  store i64 1, i64* %9
+  %10 = getelementptr %Point, %Point* %8, i32 0, i32 1
+  store i64 2, i64* %10
+  %11 = call %Point @brn_closer(%Point* %8)
+  %12 = alloca %Point
+  store %Point %11, %Point* %12
+  %13 = getelementptr %Point, %Point* %12, i32 0, i32 1
+  %14 = load i64, i64* %13
+  call void @brn_print_int(i64 %14)
+  ret i32 0
+}
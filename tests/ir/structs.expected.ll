@@ -0,0 +1,38 @@
+%Point = type { i64, i64 }
+%Person = type { i8*, i64 }
+@.str.0 = private unnamed_addr constant [6 x i8] c"Alice\00", align 1
+
+define %Point @brn_make_point(i64 %arg_x, i64 %arg_y) nounwind {
+entry:
+  %0 = alloca %Point
+  %1 = getelementptr %Point, %Point* %0, i32 0, i32 0
+  store i64 %arg_x, i64* %1
+  %2 = getelementptr %Point, %Point* %0, i32 0, i32 1
+  store i64 %arg_y, i64* %2
+  %3 = load %Point, %Point* %0
+  ret %Point %3
+}
+
+define i32 @main() nounwind {
+entry:
+  %0 = call %Point @brn_make_point(i64 10, i64 20)
+  %1 = alloca %Point
+  store %Point %0, %Point* %1
+  %2 = getelementptr %Point, %Point* %1, i32 0, i32 0
+  %3 = load i64, i64* %2
+  %4 = getelementptr %Point, %Point* %1, i32 0, i32 1
+  %5 = load i64, i64* %4
+  %6 = add i64 %3, %5
+  call void @brn_print_int(i64 %6)
+  %7 = alloca %Person
+  %8 = getelementptr inbounds [6 x i8], [6 x i8]* @.str.0, i64 0, i64 0
+  %9 = getelementptr %Person, %Person* %7, i32 0, i32 0
+  store i8* %8, i8** %9
+  %10 = getelementptr %Person, %Person* %7, i32 0, i32 1
+  store i64 30, i64* %10
+  %11 = load i8*, i8** %9
+  %12 = call i32 @puts(i8* %11)
+  %13 = load i64, i64* %10
+  call void @brn_print_int(i64 %13)
+  ret i32 0
+}
@@ -0,0 +1,64 @@
+//! `std/json.brn` has to give back what it parsed: a value written out,
+//! parsed again and written out once more reads the same, keys and all.
+//! Needs `lli` (or `BRAIN_LLI`); skipped without it.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+const PROGRAM: &str = r#"
+import { json_parse, json_stringify, json_get, json_keys, json_as_int, json_as_string, json_at, json_len } from "std/json.brn";
+
+fn main() {
+    let text = "{\"name\":\"brain\",\"tags\":[\"a\",\"b\\n\"],\"n\":-12,\"ok\":true,\"none\":null,\"nested\":{\"key\":\"value\"}}";
+    let v = json_parse(text);
+    println(json_stringify(v));
+    println(json_stringify(json_parse(json_stringify(v))));
+    for k in json_keys(v) {
+        println(k);
+    }
+    println(json_as_int(json_get(v, "n")));
+    println(json_as_string(json_get(json_get(v, "nested"), "key")));
+    println(json_len(json_get(v, "tags")));
+    println(json_as_string(json_at(json_get(v, "tags"), 0)));
+}
+"#;
+
+const EXPECTED: &str = r#"{"name":"brain","tags":["a","b\n"],"n":-12,"ok":true,"none":null,"nested":{"key":"value"}}
+{"name":"brain","tags":["a","b\n"],"n":-12,"ok":true,"none":null,"nested":{"key":"value"}}
+name
+tags
+n
+ok
+none
+nested
+-12
+value
+2
+a
+"#;
+
+#[test]
+fn json_round_trips() {
+    let artifacts = brain::Compiler::new()
+        .compile_source(PROGRAM, "json_round_trip.brn")
+        .unwrap_or_else(|diagnostics| panic!("{}", diagnostics[0].render()));
+
+    let dir = env::temp_dir().join(format!("brain-json-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ll_file = dir.join("json_round_trip.ll");
+    fs::write(&ll_file, artifacts.llvm_ir).unwrap();
+
+    let lli = env::var("BRAIN_LLI").unwrap_or_else(|_| "lli".to_string());
+    let Ok(output) = Command::new(&lli).arg(&ll_file).output() else {
+        eprintln!("skipped: '{}' not found", lli);
+        return;
+    };
+    let _ = fs::remove_dir_all(&dir);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), EXPECTED);
+}
@@ -0,0 +1,116 @@
+//! A `--lib` object offers its exports and `#[no_mangle]` functions to
+//! the program it is linked into, and nothing else. Linking one into a C
+//! program needs `llc` and `cc`; that test is skipped without them.
+
+use brain::{CodegenOptions, Compiler, Roots, Runtime};
+use std::env;
+use std::fs;
+use std::process::Command;
+
+const LIBRARY: &str = r#"
+export fn add(a: int, b: int) -> int {
+    return a + b;
+}
+
+#[no_mangle]
+fn brain_twice(n: int) -> int {
+    println(int_to_string(n));
+    return n * 2;
+}
+"#;
+
+const CALLER: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+
+long brain_twice(long n);
+long brn_add(long a, long b);
+
+int main(void) {
+    char *buffer = malloc(16);
+    printf("%ld\n", brain_twice(21));
+    printf("%ld\n", brn_add(2, 3));
+    free(buffer);
+    return 0;
+}
+"#;
+
+/// The IR of `LIBRARY` built as a library on `runtime`.
+fn library_ir(runtime: Runtime) -> String {
+    let options = CodegenOptions {
+        roots: Roots::library(),
+        runtime,
+        ..CodegenOptions::default()
+    };
+    Compiler::new()
+        .with_codegen_options(options)
+        .compile_source(LIBRARY, "library.brn")
+        .unwrap_or_else(|diagnostics| panic!("{}", diagnostics[0].render()))
+        .llvm_ir
+}
+
+#[test]
+fn only_exports_are_visible() {
+    for runtime in [Runtime::Freestanding, Runtime::Libc] {
+        let ir = library_ir(runtime);
+        let visible: Vec<&str> = ir
+            .lines()
+            .filter(|line| {
+                line.starts_with("define ") && !line.starts_with("define internal ")
+                    || line.contains(" = global ")
+            })
+            .collect();
+        assert_eq!(visible.len(), 2, "{:#?}", visible);
+        assert!(visible[0].contains(" @brn_add("));
+        assert!(visible[1].contains(" @brain_twice("));
+    }
+}
+
+#[test]
+fn c_program_calls_exports() {
+    let dir = env::temp_dir().join(format!("brain-library-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ll_file = dir.join("library.ll");
+    let object = dir.join("library.o");
+    let caller = dir.join("caller.c");
+    let program = dir.join("caller");
+    fs::write(&ll_file, library_ir(Runtime::Libc)).unwrap();
+    fs::write(&caller, CALLER).unwrap();
+
+    let Ok(llc) = Command::new("llc")
+        .arg("-relocation-model=pic")
+        .arg("-filetype=obj")
+        .arg(&ll_file)
+        .arg("-o")
+        .arg(&object)
+        .output()
+    else {
+        eprintln!("skipped: 'llc' not found");
+        return;
+    };
+    assert!(
+        llc.status.success(),
+        "{}",
+        String::from_utf8_lossy(&llc.stderr)
+    );
+    let Ok(cc) = Command::new("cc")
+        .arg(&caller)
+        .arg(&object)
+        .arg("-o")
+        .arg(&program)
+        .output()
+    else {
+        eprintln!("skipped: 'cc' not found");
+        return;
+    };
+    assert!(
+        cc.status.success(),
+        "{}",
+        String::from_utf8_lossy(&cc.stderr)
+    );
+
+    let output = Command::new(&program).output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "21\n42\n5\n");
+}
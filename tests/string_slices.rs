@@ -0,0 +1,62 @@
+//! `s[start..end]` counts a negative bound back from the end, clamps
+//! bounds past either end, and is empty when `start` comes after `end`.
+//! Needs `lli` (or `BRAIN_LLI`); skipped without it.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+const PROGRAM: &str = r#"
+fn show(s: string) {
+    println("[" + s + "]");
+}
+
+fn main() {
+    let s = "hello";
+    show(s[1..3]);
+    show(s[2..]);
+    show(s[..2]);
+    // Negative bounds
+    show(s[-3..-1]);
+    show(s[-2..]);
+    show(s[..-1]);
+    // Past either end
+    show(s[-10..2]);
+    show(s[3..99]);
+    show(s[-10..99]);
+    show(s[7..9]);
+    show(s[-9..-7]);
+    // Start after end
+    show(s[4..2]);
+    show(s[-1..-3]);
+    show(s[5..5]);
+}
+"#;
+
+const EXPECTED: &str =
+    "[el]\n[llo]\n[he]\n[ll]\n[lo]\n[hell]\n[he]\n[lo]\n[hello]\n[]\n[]\n[]\n[]\n[]\n";
+
+#[test]
+fn slice_bounds() {
+    let artifacts = brain::Compiler::new()
+        .compile_source(PROGRAM, "string_slices.brn")
+        .unwrap_or_else(|diagnostics| panic!("{}", diagnostics[0].render()));
+
+    let dir = env::temp_dir().join(format!("brain-slices-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ll_file = dir.join("string_slices.ll");
+    fs::write(&ll_file, artifacts.llvm_ir).unwrap();
+
+    let lli = env::var("BRAIN_LLI").unwrap_or_else(|_| "lli".to_string());
+    let Ok(output) = Command::new(&lli).arg(&ll_file).output() else {
+        eprintln!("skipped: '{}' not found", lli);
+        return;
+    };
+    let _ = fs::remove_dir_all(&dir);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), EXPECTED);
+}